@@ -8,13 +8,19 @@ export interface JsonApiVersion {
   version: string;
 }
 
-export type Resource = BookmarkResource | TagResource;
+export type Resource =
+  | BookmarkResource
+  | TagResource
+  | SavedSearchResource
+  | RuleResource;
 
 export interface BookmarkResource {
   type: 'bookmark';
   id: string;
   attributes: BookmarkAttributes;
   relationships?: BookmarkRelationships;
+  /** Custom fields from integrations; `activity` is filled in by the host on read */
+  meta?: Record<string, unknown>;
 }
 
 export interface BookmarkAttributes {
@@ -23,6 +29,30 @@ export interface BookmarkAttributes {
   created: string; // ISO 8601 datetime
   modified?: string; // ISO 8601 datetime
   notes?: string;
+  /** Saved to read later; cleared with the `markread` message */
+  unread?: boolean;
+  /** Shown among the favorites on the new-tab page */
+  pinned?: boolean;
+  /** Position among pinned bookmarks; set with the `reorderpinned` message */
+  pin_order?: number;
+  /** Wayback Machine snapshot, set once the link checker found the link dead */
+  archive_url?: string;
+  /** ISO 8601 datetime; once past, the host tags the bookmark `expired` or deletes it */
+  expires_at?: string;
+  /** Filled in by the `enrich` message */
+  enrichment?: Enrichment;
+  /** Accepted or dismissed with the `reviewsuggestedtags` message */
+  suggested_tags?: string[];
+}
+
+export interface Enrichment {
+  content_type: 'article' | 'video' | 'audio' | 'image' | 'pdf' | 'page' | 'other';
+  mime_type?: string;
+  word_count?: number;
+  reading_minutes?: number;
+  /** Primary language subtag, like `en` */
+  language?: string;
+  fetched: string; // ISO 8601 datetime
 }
 
 export interface BookmarkRelationships {
@@ -59,6 +89,35 @@ export interface ParentRelationship {
   data: ResourceIdentifier | null;
 }
 
+export interface SavedSearchResource {
+  type: 'savedsearch';
+  id: string;
+  attributes: SavedSearchAttributes;
+}
+
+export interface SavedSearchAttributes {
+  name: string;
+  /** Words, `tag:<name>`, and `is:unread` / `is:read` / `is:private`, all of which must match */
+  query: string;
+  sort?: 'newest' | 'oldest' | 'title';
+  created: string; // ISO 8601 datetime
+}
+
+export interface RuleResource {
+  type: 'rule';
+  id: string;
+  attributes: RuleAttributes;
+  relationships: { tags: RelationshipData };
+}
+
+export interface RuleAttributes {
+  name: string;
+  field: 'url' | 'title' | 'domain';
+  /** Case-insensitive; `equals` on a domain also matches its subdomains */
+  match?: 'contains' | 'equals' | 'regex';
+  pattern: string;
+}
+
 export interface BookmarksData {
   jsonapi: JsonApiVersion;
   data: Resource[];
@@ -68,6 +127,7 @@ export interface BookmarksData {
 // Native messaging protocol types
 
 export type NativeMessage =
+  | HelloMessage
   | InitMessage
   | WriteMessage
   | ReadMessage
@@ -78,6 +138,77 @@ export type NativeMessage =
   | DisableEncryptionMessage
   | EncryptionStatusMessage;
 
+/** Protocol handshake; send it first to negotiate a version and capabilities */
+export interface HelloMessage {
+  type: 'hello';
+  protocol_version: number;
+  /** Compression the extension can decode, most preferred first */
+  compression?: Compression[];
+}
+
+export type Compression = 'gzip' | 'zstd';
+
+/** Optional features; a message that needs one fails with ERR_CAPABILITY until it's negotiated */
+export type Capability =
+  | 'chunked_payloads'
+  | 'indexes'
+  | 'local_activity'
+  | 'key_transfer'
+  | 'recovery_codes'
+  | 'key_cache'
+  | 'field_encryption'
+  | 'xchacha20'
+  | 'diff'
+  | 'config'
+  | 'shallow_clone'
+  | 'maintenance'
+  | 'commit_batching'
+  | 'device_identity'
+  | 'multiple_remotes'
+  | 'ssh_key'
+  | 'doctor'
+  | 'logs'
+  | 'metrics'
+  | 'shutdown'
+  | 'repair'
+  | 'read_later'
+  | 'saved_searches'
+  | 'rules'
+  | 'tag_suggestions'
+  | 'pagination'
+  | 'import'
+  | 'progress'
+  | 'static_site'
+  | 'markdown_export'
+  | 'sync_backends'
+  | 'reauth'
+  | 'repo_picker'
+  | 'sign_out'
+  | 'auth_cancel'
+  | 'conflict_resolution'
+  | 'notes'
+  | 'tag_ops'
+  | 'reports'
+  | 'stats'
+  | 'bulk_ops'
+  | 'undo'
+  | 'backup'
+  | 'scheduled_backups'
+  | 'bundles'
+  | 'duplicates'
+  | 'write_delta'
+  | 'compression'
+  | 'visibility'
+  | 'history_reset'
+  | 'archives'
+  | 'link_monitoring'
+  | 'enrichment'
+  | 'content_suggestions'
+  | 'expiration'
+  | 'pinned'
+  | 'tag_tree'
+  | 'tag_counts';
+
 export interface InitMessage {
   type: 'init';
   repo_path?: string;
@@ -91,6 +222,16 @@ export interface WriteMessage {
 
 export interface ReadMessage {
   type: 'read';
+  // Any of these returns a page of bookmarks (requires the `pagination`
+  // capability); a page must never be sent back with `write`
+  sort?: 'created' | 'modified' | 'title' | 'url';
+  order?: 'asc' | 'desc';
+  limit?: number;
+  offset?: number;
+  tag_filter?: string; // Tag ID
+  filter?: Record<string, string>; // tag (tag ID), unread ('true'/'false'), q (query)
+  fields?: Record<string, string>; // Resource type -> comma-separated field names
+  include?: string; // 'tags' and/or 'tags.parent'; without it every tag is included
 }
 
 export interface SyncMessage {
@@ -119,7 +260,18 @@ export interface EncryptionStatusMessage {
   type: 'encryptionstatus';
 }
 
-export type NativeResponse = SuccessResponse | ErrorResponse | AuthFlowResponse;
+export type NativeResponse = HelloResponse | SuccessResponse | ErrorResponse | AuthFlowResponse;
+
+export interface HelloResponse {
+  type: 'hello';
+  /** Version both sides will speak from now on */
+  protocol_version: number;
+  supported_versions: number[];
+  /** Capabilities enabled for this connection */
+  capabilities: Capability[];
+  /** Applied to large responses from now on, if one was agreed */
+  compression?: Compression;
+}
 
 export interface SuccessResponse {
   type: 'success';
@@ -130,9 +282,79 @@ export interface SuccessResponse {
 export interface ErrorResponse {
   type: 'error';
   message: string;
-  code?: string;
+  code: HostErrorCode;
+  category: ErrorCategory;
+  /** Whether sending the same message again may succeed */
+  retryable: boolean;
+  /** Structured context for some codes, e.g. the problems behind ERR_VALIDATE */
+  details?: Record<string, unknown>;
 }
 
+/** Broad class of failure, so the UI can react without parsing messages */
+export type ErrorCategory =
+  | 'protocol'
+  | 'validation'
+  | 'state'
+  | 'storage'
+  | 'git'
+  | 'network'
+  | 'auth'
+  | 'encryption'
+  | 'platform'
+  | 'internal';
+
+/** Stable error codes from the host; new ones may be added but never renamed */
+export type HostErrorCode =
+  | 'ERR_READ_MESSAGE'
+  | 'ERR_PROTOCOL_VERSION'
+  | 'ERR_CAPABILITY'
+  | 'ERR_PAYLOAD'
+  | 'ERR_INVALID_PATH'
+  | 'ERR_PARSE'
+  | 'ERR_VALIDATE'
+  | 'ERR_INTEGRITY'
+  | 'ERR_NOT_INITIALIZED'
+  | 'ERR_NO_REMOTE'
+  | 'ERR_LOCKED'
+  | 'ERR_READ_FILE'
+  | 'ERR_WRITE_FILE'
+  | 'ERR_INDEX'
+  | 'ERR_ACTIVITY'
+  | 'ERR_INIT'
+  | 'ERR_OPEN_REPO'
+  | 'ERR_GIT_ADD'
+  | 'ERR_GIT_COMMIT'
+  | 'ERR_DIFF'
+  | 'ERR_MAINTENANCE'
+  | 'ERR_REMOTE'
+  | 'ERR_CLONE'
+  | 'ERR_GIT_PUSH'
+  | 'ERR_GIT_PULL'
+  | 'ERR_SYNC_CONFLICT'
+  | 'ERR_HISTORY_REWRITTEN'
+  | 'ERR_HOOK'
+  | 'ERR_GITHUB_API'
+  | 'ERR_OAUTH_START'
+  | 'ERR_AUTH_CANCELLED'
+  | 'ERR_NO_TOKEN'
+  | 'ERR_INVALID_TOKEN'
+  | 'ERR_VALIDATE_TOKEN'
+  | 'ERR_STORE_TOKEN'
+  | 'ERR_UNTRUSTED_CALLER'
+  | 'ERR_KEYGEN'
+  | 'ERR_CHECK_ENCRYPTION'
+  | 'ERR_READ_FOR_ENCRYPT'
+  | 'ERR_ENCRYPT'
+  | 'ERR_DECRYPT'
+  | 'ERR_WRITE_DECRYPT'
+  | 'ERR_KEY_EXPORT'
+  | 'ERR_KEY_IMPORT'
+  | 'ERR_RECOVERY'
+  | 'ERR_BACKUP'
+  | 'ERR_BUNDLE'
+  | 'ERR_PLATFORM_NOT_SUPPORTED'
+  | 'ERR_SERIALIZE';
+
 export interface AuthFlowResponse {
   type: 'authflow';
   user_code: string;
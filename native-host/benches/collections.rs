@@ -0,0 +1,91 @@
+//! Whole-collection operations on 1k, 10k, and 100k bookmarks.
+//!
+//! Run with `cargo bench --bench collections`; the budgets these are held to
+//! are in docs/DEVELOPMENT.md under "Performance Budget".
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use webtags_host::storage::{self, BookmarksData, Resource};
+use webtags_host::{diff, index};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// One tag per this many bookmarks
+const BOOKMARKS_PER_TAG: usize = 20;
+
+/// Tags nest this deep, so breadcrumbs have some length to them
+const TAG_DEPTH: usize = 4;
+
+fn collection(bookmarks: usize) -> BookmarksData {
+    let mut data = BookmarksData::new();
+
+    let mut tag_ids: Vec<String> = Vec::new();
+    for i in 0..(bookmarks / BOOKMARKS_PER_TAG).max(1) {
+        // Every TAG_DEPTH-th tag starts a new chain; the rest nest under the
+        // previous one
+        let parent = (i % TAG_DEPTH != 0).then(|| tag_ids[i - 1].clone());
+        let tag = storage::create_tag(format!("tag {i}"), None, parent);
+        tag_ids.push(tag.id().to_string());
+        data.add_tag(tag).expect("tag is a tag");
+    }
+
+    for i in 0..bookmarks {
+        let tags = vec![tag_ids[i % tag_ids.len()].clone()];
+        let bookmark = storage::create_bookmark(
+            format!("https://example.com/articles/{i}"),
+            format!("Article number {i} about topic {}", i % 97),
+            tags,
+        );
+        data.add_bookmark(bookmark).expect("bookmark is a bookmark");
+    }
+
+    data
+}
+
+/// A later version of `data` with every tenth bookmark retitled
+fn edited(data: &BookmarksData) -> BookmarksData {
+    let mut edited = data.clone();
+    for resource in edited.data_mut().iter_mut().step_by(10) {
+        if let Resource::Bookmark { attributes, .. } = resource {
+            attributes.title.push_str(" (edited)");
+        }
+    }
+    edited
+}
+
+fn collections(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collections");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let data = collection(size);
+        let edited = edited(&data);
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("validate", size), &data, |b, data| {
+            b.iter(|| data.validate().expect("collection is valid"));
+        });
+        group.bench_with_input(BenchmarkId::new("serialize", size), &data, |b, data| {
+            b.iter(|| serde_json::to_vec(black_box(data)).expect("collection serializes"));
+        });
+        group.bench_with_input(BenchmarkId::new("search_index", size), &data, |b, data| {
+            b.iter(|| index::build_search_index(black_box(data)));
+        });
+        group.bench_with_input(BenchmarkId::new("breadcrumbs", size), &data, |b, data| {
+            b.iter(|| black_box(data).get_tag_breadcrumbs());
+        });
+        // Comparing two versions is the work a merge does before anything is
+        // written
+        group.bench_with_input(
+            BenchmarkId::new("diff", size),
+            &(data, edited),
+            |b, (from, to)| {
+                b.iter(|| diff::diff(black_box(from), black_box(to)));
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, collections);
+criterion_main!(benches);
@@ -0,0 +1,112 @@
+//! Parse cost of a large `write` message.
+//!
+//! Run with `cargo bench --bench write_payload`. Compares `parse_message`
+//! against the old two-step parse (an untyped `serde_json::Value` first, then
+//! `BookmarksData`) on a 100k-bookmark payload, reporting time and peak
+//! memory growth. Peak memory is read from `/proc`, so it is only measured on
+//! Linux; elsewhere only timings are shown.
+
+use std::hint::black_box;
+use std::time::Instant;
+use webtags_host::messaging::{parse_message, Message};
+use webtags_host::storage::{self, BookmarksData};
+
+const BOOKMARKS: usize = 100_000;
+
+/// Peak memory of the streaming parse may not exceed this multiple of the
+/// payload size: the parsed bookmarks themselves, plus allocator slack
+const MAX_PEAK_RATIO: f64 = 3.0;
+
+fn payload() -> Vec<u8> {
+    let mut data = BookmarksData::new();
+    *data.data_mut() = (0..BOOKMARKS)
+        .map(|i| {
+            storage::create_bookmark(
+                format!("https://example.com/articles/{i}"),
+                format!("Bookmark number {i}"),
+                vec![format!("tag-{}", i % 50)],
+            )
+        })
+        .collect();
+    serde_json::to_vec(&Message::Write {
+        data,
+        strict: false,
+    })
+    .expect("payload serializes")
+}
+
+/// `VmRSS` or `VmHWM` from `/proc/self/status`, in bytes
+fn proc_status_bytes(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(field))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Run `parse` and return how far peak memory rose above the starting point
+fn measure(name: &str, json: &[u8], parse: impl FnOnce(&[u8]) -> Message) -> Option<u64> {
+    // Writing 5 resets the high-water mark to the current RSS
+    let reset = std::fs::write("/proc/self/clear_refs", "5").is_ok();
+    let before = proc_status_bytes("VmRSS");
+
+    let start = Instant::now();
+    let message = black_box(parse(json));
+    let elapsed = start.elapsed();
+
+    let peak = proc_status_bytes("VmHWM");
+    drop(message);
+
+    let growth = match (reset, before, peak) {
+        (true, Some(before), Some(peak)) => Some(peak.saturating_sub(before)),
+        _ => None,
+    };
+    let memory = growth.map_or_else(
+        || "peak memory unavailable".to_string(),
+        |bytes| format!("peak +{:.1} MiB", mib(bytes)),
+    );
+    println!(
+        "{name:<12} {:>8.1} ms   {memory}",
+        elapsed.as_secs_f64() * 1000.0
+    );
+    growth
+}
+
+#[allow(clippy::cast_precision_loss)] // sizes are far below 2^52
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn main() {
+    let json = payload();
+    println!(
+        "{BOOKMARKS} bookmarks, {:.1} MiB payload",
+        mib(json.len() as u64)
+    );
+
+    let streaming = measure("streaming", &json, |json| {
+        parse_message(json).expect("payload parses")
+    });
+    let two_step = measure("two-step", &json, |json| {
+        let mut value: serde_json::Value = serde_json::from_slice(json).expect("payload parses");
+        let data: BookmarksData =
+            serde_json::from_value(value["data"].take()).expect("bookmarks parse");
+        Message::Write {
+            data,
+            strict: false,
+        }
+    });
+
+    if let (Some(streaming), Some(two_step)) = (streaming, two_step) {
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = streaming as f64 / json.len() as f64;
+        println!("streaming peak is {ratio:.2}x the payload size");
+        assert!(
+            ratio <= MAX_PEAK_RATIO,
+            "streaming parse peaked at {ratio:.2}x the payload (limit {MAX_PEAK_RATIO}x)"
+        );
+        assert!(
+            streaming < two_step,
+            "streaming parse should use less memory than the two-step parse"
+        );
+    }
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+// Framing, limits, decompression, and parsing must turn away any input
+// with an error rather than a panic or runaway allocation.
+// Run with `cargo +nightly fuzz run read_message` from native-host.
+
+use libfuzzer_sys::fuzz_target;
+use webtags_host::messaging::{parse_message, read_message};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = read_message(data);
+    let _ = parse_message(data);
+});
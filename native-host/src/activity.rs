@@ -0,0 +1,185 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::storage;
+
+/// Sidecar file holding per-device browsing activity. It lives in the repo
+/// directory but is gitignored, so it is never committed or pushed.
+pub const ACTIVITY_FILE: &str = "activity.local.json";
+
+const GITIGNORE_FILE: &str = ".gitignore";
+
+/// Bookmark `meta` key activity is reported under on `Read`
+pub const ACTIVITY_META_KEY: &str = "activity";
+
+/// Local-only activity for a single bookmark
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct BookmarkActivity {
+    pub visit_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_visited: Option<DateTime<Utc>>,
+}
+
+/// Activity for all bookmarks, keyed by bookmark ID
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ActivityLog {
+    pub bookmarks: HashMap<String, BookmarkActivity>,
+}
+
+impl ActivityLog {
+    /// Load the activity log from a repository, or an empty log if none exists
+    pub fn load<P: AsRef<Path>>(repo_path: P) -> Result<Self> {
+        let path = repo_path.as_ref().join(ACTIVITY_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path).context("Failed to read activity file")?;
+        serde_json::from_str(&content).context("Failed to parse activity file")
+    }
+
+    /// Save the activity log, making sure it stays out of git
+    pub fn save<P: AsRef<Path>>(&self, repo_path: P) -> Result<()> {
+        let repo_path = repo_path.as_ref();
+        ensure_gitignored(repo_path)?;
+
+        let json = serde_json::to_string(self).context("Failed to serialize activity")?;
+        let path = repo_path.join(ACTIVITY_FILE);
+        storage::write_atomic(&path, json.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Record a visit to a bookmark
+    pub fn record_visit(&mut self, bookmark_id: &str, at: DateTime<Utc>) -> &BookmarkActivity {
+        let entry = self.bookmarks.entry(bookmark_id.to_string()).or_default();
+        entry.visit_count += 1;
+        entry.last_visited = Some(at);
+        entry
+    }
+
+    /// Merge activity into serialized bookmarks as JSON API resource `meta`.
+    ///
+    /// `Write` strips the key again (see
+    /// [`BookmarksData::remove_host_meta`](crate::storage::BookmarksData::remove_host_meta)),
+    /// so the merged data never makes it back into bookmarks.json.
+    pub fn merge_into(&self, data: &mut serde_json::Value) {
+        let Some(resources) = data.get_mut("data").and_then(|d| d.as_array_mut()) else {
+            return;
+        };
+
+        for resource in resources {
+            let Some(activity) = resource
+                .get("id")
+                .and_then(|id| id.as_str())
+                .and_then(|id| self.bookmarks.get(id))
+            else {
+                continue;
+            };
+
+            if let Some(obj) = resource.as_object_mut() {
+                let meta = obj.entry("meta").or_insert_with(|| serde_json::json!({}));
+                if let Some(meta) = meta.as_object_mut() {
+                    meta.insert(
+                        ACTIVITY_META_KEY.to_string(),
+                        serde_json::to_value(activity).unwrap_or_default(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Add the activity file to the repository's .gitignore if it isn't already
+pub fn ensure_gitignored<P: AsRef<Path>>(repo_path: P) -> Result<()> {
+    let gitignore = repo_path.as_ref().join(GITIGNORE_FILE);
+    let existing = if gitignore.exists() {
+        fs::read_to_string(&gitignore).context("Failed to read .gitignore")?
+    } else {
+        String::new()
+    };
+
+    if existing.lines().any(|line| line.trim() == ACTIVITY_FILE) {
+        return Ok(());
+    }
+
+    let mut updated = existing;
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push_str(ACTIVITY_FILE);
+    updated.push('\n');
+
+    fs::write(&gitignore, updated).context("Failed to update .gitignore")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_record_visit_and_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut log = ActivityLog::load(temp_dir.path()).unwrap();
+
+        log.record_visit("bookmark-1", Utc::now());
+        let activity = log.record_visit("bookmark-1", Utc::now());
+        assert_eq!(activity.visit_count, 2);
+
+        log.save(temp_dir.path()).unwrap();
+        let loaded = ActivityLog::load(temp_dir.path()).unwrap();
+        assert_eq!(loaded, log);
+    }
+
+    #[test]
+    fn test_save_gitignores_sidecar() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join(".gitignore"), "*.tmp").unwrap();
+
+        ActivityLog::default().save(temp_dir.path()).unwrap();
+        ActivityLog::default().save(temp_dir.path()).unwrap();
+
+        let gitignore = fs::read_to_string(temp_dir.path().join(".gitignore")).unwrap();
+        assert_eq!(gitignore, format!("*.tmp\n{ACTIVITY_FILE}\n"));
+    }
+
+    #[test]
+    fn test_sidecar_not_tracked_by_git() {
+        use crate::git::GitRepo;
+
+        let temp_dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(temp_dir.path()).unwrap();
+        ensure_gitignored(temp_dir.path()).unwrap();
+        repo.add_file(".gitignore").unwrap();
+        repo.commit("Ignore activity").unwrap();
+
+        let mut log = ActivityLog::default();
+        log.record_visit("bookmark-1", Utc::now());
+        log.save(temp_dir.path()).unwrap();
+
+        assert!(repo.is_clean().unwrap());
+    }
+
+    #[test]
+    fn test_merge_into_read_data() {
+        let mut log = ActivityLog::default();
+        log.record_visit("bookmark-1", Utc::now());
+
+        let mut data = serde_json::json!({
+            "data": [
+                {"type": "bookmark", "id": "bookmark-1"},
+                {"type": "bookmark", "id": "bookmark-2"}
+            ]
+        });
+        log.merge_into(&mut data);
+
+        assert_eq!(data["data"][0]["meta"]["activity"]["visit_count"], 1);
+        assert!(data["data"][1].get("meta").is_none());
+    }
+}
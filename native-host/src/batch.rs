@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+/// Coalesces rapid `Write`s into a single commit.
+///
+/// The first write after a commit opens a window; every write inside it is
+/// staged but not committed, and the whole batch is committed once the
+/// window closes (or on an explicit flush). A zero window disables batching,
+/// so each write commits immediately.
+#[derive(Debug, Default)]
+pub struct CommitBatcher {
+    window: Duration,
+    pending: Option<PendingBatch>,
+}
+
+/// Writes staged but not yet committed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingBatch {
+    opened_at: Instant,
+    /// Number of writes folded into this batch
+    pub writes: usize,
+}
+
+impl CommitBatcher {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: None,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Change the batching window. Takes effect for the next batch; an open
+    /// batch keeps its deadline.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    /// Fold a staged write into the open batch (opening one if needed)
+    pub fn record_write(&mut self, now: Instant) -> &PendingBatch {
+        let batch = self.pending.get_or_insert(PendingBatch {
+            opened_at: now,
+            writes: 0,
+        });
+        batch.writes += 1;
+        batch
+    }
+
+    pub fn pending(&self) -> Option<&PendingBatch> {
+        self.pending.as_ref()
+    }
+
+    /// When the open batch should be committed, if there is one
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending
+            .as_ref()
+            .map(|batch| batch.opened_at + self.window)
+    }
+
+    /// Close the open batch so it can be committed
+    pub fn take(&mut self) -> Option<PendingBatch> {
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_by_default() {
+        let batcher = CommitBatcher::default();
+        assert!(!batcher.is_enabled());
+        assert_eq!(batcher.deadline(), None);
+    }
+
+    #[test]
+    fn test_writes_coalesce_until_taken() {
+        let start = Instant::now();
+        let mut batcher = CommitBatcher::new(Duration::from_secs(30));
+
+        batcher.record_write(start);
+        batcher.record_write(start + Duration::from_secs(10));
+        let batch = batcher.record_write(start + Duration::from_secs(20));
+        assert_eq!(batch.writes, 3);
+
+        // The window runs from the first write, so a stream of writes can't
+        // postpone the commit forever
+        assert_eq!(batcher.deadline(), Some(start + Duration::from_secs(30)));
+
+        assert_eq!(batcher.take().unwrap().writes, 3);
+        assert_eq!(batcher.take(), None);
+        assert_eq!(batcher.deadline(), None);
+    }
+}
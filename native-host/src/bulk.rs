@@ -0,0 +1,361 @@
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::query::Query;
+use crate::rules::domain;
+use crate::storage::{
+    self, BookmarkRelationships, BookmarksData, ParentRelationship, RelationshipData, Resource,
+    ResourceIdentifier, TagRelationships,
+};
+use crate::unicode;
+
+/// Which bookmarks a [`BulkOp`] applies to. Every given field must match; a
+/// filter with no fields matches nothing, so a typo can't touch everything.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct BulkFilter {
+    /// Bookmarks on this host or any of its subdomains
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// Bookmarks matching a search query (see [`Query`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+}
+
+/// A change applied in one go by `BulkOp`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BulkOp {
+    /// Tag every matching bookmark with `tag_id`
+    AddTag { tag_id: String },
+    /// Untag every matching bookmark
+    RemoveTag { tag_id: String },
+    /// Delete every matching bookmark
+    Delete,
+    /// Put tag `tag_id` under `parent_id`, or at the top level without one.
+    /// Doesn't use the filter.
+    MoveTag {
+        tag_id: String,
+        #[serde(default)]
+        parent_id: Option<String>,
+    },
+}
+
+impl BulkFilter {
+    fn is_empty(&self) -> bool {
+        self.domain.is_none() && self.query.is_none()
+    }
+
+    /// IDs of the bookmarks in `data` this filter matches
+    pub fn matching(&self, data: &BookmarksData) -> Result<Vec<String>> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        let query = self.query.as_deref().map(Query::parse).transpose()?;
+        let wanted = self
+            .domain
+            .as_deref()
+            .map(|d| d.trim_end_matches('.').to_lowercase());
+
+        Ok(data
+            .get_bookmarks()
+            .into_iter()
+            .filter(|resource| {
+                let Resource::Bookmark { attributes, .. } = resource else {
+                    return false;
+                };
+                let on_domain = wanted.as_deref().is_none_or(|wanted| {
+                    domain(&attributes.url).is_some_and(|host| {
+                        host == wanted
+                            || host
+                                .strip_suffix(wanted)
+                                .is_some_and(|sub| sub.ends_with('.'))
+                    })
+                });
+                on_domain && query.as_ref().is_none_or(|q| q.matches(data, resource))
+            })
+            .map(|resource| resource.id().to_string())
+            .collect())
+    }
+}
+
+impl BulkOp {
+    /// Apply the operation to the bookmarks `filter` matches, returning the
+    /// IDs of the resources it changed
+    pub fn apply(&self, data: &mut BookmarksData, filter: &BulkFilter) -> Result<Vec<String>> {
+        if let BulkOp::MoveTag { tag_id, parent_id } = self {
+            if !filter.is_empty() {
+                anyhow::bail!("'movetag' doesn't take a filter");
+            }
+            return move_tag(data, tag_id, parent_id.as_deref());
+        }
+
+        let ids = filter.matching(data)?;
+        let matched: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        match self {
+            BulkOp::AddTag { tag_id } | BulkOp::RemoveTag { tag_id } => {
+                if !matches!(data.get(tag_id), Some(Resource::Tag { .. })) {
+                    anyhow::bail!("No tag with ID {tag_id}");
+                }
+                let add = matches!(self, BulkOp::AddTag { .. });
+                let mut changed = Vec::new();
+                for resource in data.data_mut() {
+                    let Resource::Bookmark {
+                        id,
+                        attributes,
+                        relationships,
+                        ..
+                    } = resource
+                    else {
+                        continue;
+                    };
+                    if !matched.contains(id.as_str()) || !set_tag(relationships, tag_id, add) {
+                        continue;
+                    }
+                    attributes.modified = Some(Utc::now());
+                    changed.push(id.clone());
+                }
+                Ok(changed)
+            }
+            BulkOp::Delete => {
+                data.data_mut().retain(|resource| {
+                    !matches!(resource, Resource::Bookmark { .. })
+                        || !matched.contains(resource.id())
+                });
+                Ok(ids)
+            }
+            BulkOp::MoveTag { .. } => unreachable!("handled above"),
+        }
+    }
+}
+
+/// ID of the top-level tag named `name` (case-insensitive)
+pub(crate) fn find_top_level_tag(data: &BookmarksData, name: &str) -> Option<String> {
+    let name = unicode::fold(name);
+    data.get_tags()
+        .into_iter()
+        .find(|tag| match tag {
+            Resource::Tag { attributes, .. } => {
+                unicode::fold(&attributes.name) == name && data.tag_parent_id(tag.id()).is_none()
+            }
+            _ => false,
+        })
+        .map(|tag| tag.id().to_string())
+}
+
+/// ID of the top-level tag named `name`, created if missing. For the host's
+/// own tag names, which are always valid.
+pub(crate) fn top_level_tag(data: &mut BookmarksData, name: &str) -> String {
+    if let Some(id) = find_top_level_tag(data, name) {
+        return id;
+    }
+    let tag = storage::create_tag(name.to_string(), None, None);
+    let id = tag.id().to_string();
+    data.add_tag(tag).expect("create_tag makes a tag");
+    id
+}
+
+/// Add or remove `tag_id` on a bookmark, returning whether that changed it
+pub(crate) fn set_tag(
+    relationships: &mut Option<BookmarkRelationships>,
+    tag_id: &str,
+    add: bool,
+) -> bool {
+    let tags = &mut relationships
+        .get_or_insert(BookmarkRelationships { tags: None })
+        .tags
+        .get_or_insert(RelationshipData { data: Vec::new() })
+        .data;
+    let has = tags.iter().any(|tag| tag.id == tag_id);
+    if add && !has {
+        tags.push(ResourceIdentifier {
+            resource_type: "tag".to_string(),
+            id: tag_id.to_string(),
+        });
+    } else if !add && has {
+        tags.retain(|tag| tag.id != tag_id);
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Give tag `tag_id` a new parent, or make it top-level, refusing to put a
+/// tag under its own descendant
+pub(crate) fn move_tag(
+    data: &mut BookmarksData,
+    tag_id: &str,
+    parent_id: Option<&str>,
+) -> Result<Vec<String>> {
+    if let Some(parent_id) = parent_id {
+        if !matches!(data.get(parent_id), Some(Resource::Tag { .. })) {
+            anyhow::bail!("No tag with ID {parent_id}");
+        }
+        let mut ancestor = Some(parent_id);
+        let mut seen = HashSet::new();
+        while let Some(id) = ancestor.filter(|id| seen.insert(*id)) {
+            if id == tag_id {
+                anyhow::bail!("Can't move tag {tag_id} under its own descendant {parent_id}");
+            }
+            ancestor = data.tag_parent_id(id);
+        }
+    }
+
+    let (data, included) = data.resources_mut();
+    let tag = data
+        .iter_mut()
+        .chain(included.iter_mut().flatten())
+        .find_map(|resource| match resource {
+            Resource::Tag {
+                id, relationships, ..
+            } if id == tag_id => Some(relationships),
+            _ => None,
+        });
+    let Some(relationships) = tag else {
+        anyhow::bail!("No tag with ID {tag_id}");
+    };
+    *relationships = parent_id.map(|parent_id| TagRelationships {
+        parent: Some(ParentRelationship {
+            data: Some(ResourceIdentifier {
+                resource_type: "tag".to_string(),
+                id: parent_id.to_string(),
+            }),
+        }),
+    });
+    Ok(vec![tag_id.to_string()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+
+    fn sample() -> (BookmarksData, String) {
+        let mut data = BookmarksData::new();
+        let tag = create_tag("hn".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+        for url in [
+            "https://news.ycombinator.com/item?id=1",
+            "https://news.ycombinator.com/item?id=2",
+            "https://ycombinator.com",
+            "https://example.com/news",
+        ] {
+            data.add_bookmark(create_bookmark(url.to_string(), url.to_string(), vec![]))
+                .unwrap();
+        }
+        (data, tag_id)
+    }
+
+    fn domain_filter(domain: &str) -> BulkFilter {
+        BulkFilter {
+            domain: Some(domain.to_string()),
+            query: None,
+        }
+    }
+
+    #[test]
+    fn test_filter_matching() {
+        let (data, _) = sample();
+        assert_eq!(
+            domain_filter("news.ycombinator.com")
+                .matching(&data)
+                .unwrap()
+                .len(),
+            2
+        );
+        // Subdomains count
+        assert_eq!(
+            domain_filter("YCombinator.com")
+                .matching(&data)
+                .unwrap()
+                .len(),
+            3
+        );
+        // Not a suffix match on the name itself
+        assert!(domain_filter("combinator.com")
+            .matching(&data)
+            .unwrap()
+            .is_empty());
+
+        let both = BulkFilter {
+            domain: Some("ycombinator.com".to_string()),
+            query: Some("item".to_string()),
+        };
+        assert_eq!(both.matching(&data).unwrap().len(), 2);
+        assert!(BulkFilter::default().matching(&data).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_add_and_remove_tag() {
+        let (mut data, tag_id) = sample();
+        let add = BulkOp::AddTag {
+            tag_id: tag_id.clone(),
+        };
+        let filter = domain_filter("news.ycombinator.com");
+        assert_eq!(add.apply(&mut data, &filter).unwrap().len(), 2);
+        assert_eq!(data.bookmarks_with_tag(&tag_id).len(), 2);
+        // Already tagged, so nothing changes
+        assert!(add.apply(&mut data, &filter).unwrap().is_empty());
+
+        let remove = BulkOp::RemoveTag {
+            tag_id: tag_id.clone(),
+        };
+        assert_eq!(
+            remove
+                .apply(&mut data, &domain_filter("ycombinator.com"))
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(data.bookmarks_with_tag(&tag_id).is_empty());
+
+        let missing = BulkOp::AddTag {
+            tag_id: "missing".to_string(),
+        };
+        assert!(missing.apply(&mut data, &filter).is_err());
+    }
+
+    #[test]
+    fn test_delete() {
+        let (mut data, tag_id) = sample();
+        let deleted = BulkOp::Delete
+            .apply(&mut data, &domain_filter("ycombinator.com"))
+            .unwrap();
+        assert_eq!(deleted.len(), 3);
+        assert_eq!(data.get_bookmarks().len(), 1);
+        assert!(data.get(&tag_id).is_some());
+    }
+
+    #[test]
+    fn test_move_tag() {
+        let mut data = BookmarksData::new();
+        let parent = create_tag("parent".to_string(), None, None);
+        let child = create_tag("child".to_string(), None, None);
+        let (parent_id, child_id) = (parent.id().to_string(), child.id().to_string());
+        data.add_tag(parent).unwrap();
+        data.add_tag(child).unwrap();
+
+        let op = BulkOp::MoveTag {
+            tag_id: child_id.clone(),
+            parent_id: Some(parent_id.clone()),
+        };
+        op.apply(&mut data, &BulkFilter::default()).unwrap();
+        assert_eq!(data.tag_parent_id(&child_id), Some(parent_id.as_str()));
+
+        let cycle = BulkOp::MoveTag {
+            tag_id: parent_id.clone(),
+            parent_id: Some(child_id.clone()),
+        };
+        assert!(cycle.apply(&mut data, &BulkFilter::default()).is_err());
+        assert!(op.apply(&mut data, &domain_filter("example.com")).is_err());
+
+        let to_top = BulkOp::MoveTag {
+            tag_id: child_id.clone(),
+            parent_id: None,
+        };
+        to_top.apply(&mut data, &BulkFilter::default()).unwrap();
+        assert_eq!(data.tag_parent_id(&child_id), None);
+    }
+}
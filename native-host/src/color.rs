@@ -0,0 +1,188 @@
+use anyhow::Result;
+
+/// Saturation and lightness of generated colors: vivid enough to tell apart,
+/// dark enough for white text
+const AUTO_SATURATION: f64 = 0.65;
+const AUTO_LIGHTNESS: f64 = 0.45;
+
+/// Hues closer than this to a color already in use are skipped if possible
+const MIN_HUE_GAP: f64 = 24.0;
+
+/// Step between candidate hues; the golden angle spreads any number of
+/// steps evenly around the wheel
+const GOLDEN_ANGLE: f64 = 137.508;
+
+/// Candidate hues tried before settling for the least crowded one
+const MAX_CANDIDATES: u32 = 32;
+
+/// CSS named colors, separated by spaces
+const NAMED_COLORS: &str = "\
+    aliceblue antiquewhite aqua aquamarine azure beige bisque black blanchedalmond blue \
+    blueviolet brown burlywood cadetblue chartreuse chocolate coral cornflowerblue cornsilk \
+    crimson cyan darkblue darkcyan darkgoldenrod darkgray darkgreen darkgrey darkkhaki \
+    darkmagenta darkolivegreen darkorange darkorchid darkred darksalmon darkseagreen \
+    darkslateblue darkslategray darkslategrey darkturquoise darkviolet deeppink deepskyblue \
+    dimgray dimgrey dodgerblue firebrick floralwhite forestgreen fuchsia gainsboro \
+    ghostwhite gold goldenrod gray green greenyellow grey honeydew hotpink indianred indigo \
+    ivory khaki lavender lavenderblush lawngreen lemonchiffon lightblue lightcoral lightcyan \
+    lightgoldenrodyellow lightgray lightgreen lightgrey lightpink lightsalmon lightseagreen \
+    lightskyblue lightslategray lightslategrey lightsteelblue lightyellow lime limegreen \
+    linen magenta maroon mediumaquamarine mediumblue mediumorchid mediumpurple \
+    mediumseagreen mediumslateblue mediumspringgreen mediumturquoise mediumvioletred \
+    midnightblue mintcream mistyrose moccasin navajowhite navy oldlace olive olivedrab \
+    orange orangered orchid palegoldenrod palegreen paleturquoise palevioletred papayawhip \
+    peachpuff peru pink plum powderblue purple rebeccapurple red rosybrown royalblue \
+    saddlebrown salmon sandybrown seagreen seashell sienna silver skyblue slateblue \
+    slategray slategrey snow springgreen steelblue tan teal thistle tomato turquoise violet \
+    wheat white whitesmoke yellow yellowgreen";
+
+/// Check a tag color is `#rgb`, `#rrggbb`, or a CSS color name
+pub fn validate_color(color: &str) -> Result<()> {
+    let named = NAMED_COLORS
+        .split_whitespace()
+        .any(|name| name.eq_ignore_ascii_case(color));
+    if named || parse_hex(color).is_some() {
+        return Ok(());
+    }
+    anyhow::bail!("Invalid color '{color}' (use #rgb, #rrggbb, or a CSS color name)")
+}
+
+/// A color for a tag named `name`, as `#rrggbb`. The hue comes from a hash
+/// of the name, moved along the wheel if that's too close to one of the
+/// `used` colors, so the same name and palette always give the same color.
+pub fn auto_color<'a>(name: &str, used: impl IntoIterator<Item = &'a str>) -> String {
+    let used: Vec<f64> = used.into_iter().filter_map(hue_of).collect();
+    let gap = |hue: f64| {
+        used.iter()
+            .map(|&other| {
+                let distance = (hue - other).abs();
+                distance.min(360.0 - distance)
+            })
+            .fold(f64::INFINITY, f64::min)
+    };
+
+    let start = f64::from(u32::try_from(fnv1a(&name.to_lowercase()) % 360).unwrap_or(0));
+    let mut best = (start, gap(start));
+    for step in 0..MAX_CANDIDATES {
+        let hue = (start + f64::from(step) * GOLDEN_ANGLE) % 360.0;
+        let gap = gap(hue);
+        if gap >= MIN_HUE_GAP {
+            best = (hue, gap);
+            break;
+        }
+        if gap > best.1 {
+            best = (hue, gap);
+        }
+    }
+    hsl_to_hex(best.0, AUTO_SATURATION, AUTO_LIGHTNESS)
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across releases
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn parse_hex(color: &str) -> Option<(u8, u8, u8)> {
+    let digits = color.strip_prefix('#')?;
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize, width: usize| {
+        let value = u8::from_str_radix(&digits[i * width..(i + 1) * width], 16).ok()?;
+        Some(if width == 1 { value * 17 } else { value })
+    };
+    let width = match digits.len() {
+        3 => 1,
+        6 => 2,
+        _ => return None,
+    };
+    Some((channel(0, width)?, channel(1, width)?, channel(2, width)?))
+}
+
+/// Hue in degrees of a hex color; `None` for greys and names
+fn hue_of(color: &str) -> Option<f64> {
+    let (red, green, blue) = parse_hex(color)?;
+    let max = red.max(green).max(blue);
+    let min = red.min(green).min(blue);
+    if max == min {
+        return None;
+    }
+    let delta = f64::from(max - min);
+    let (r, g, b) = (f64::from(red), f64::from(green), f64::from(blue));
+    let sector = if max == red {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == green {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    Some(sector * 60.0)
+}
+
+fn hsl_to_hex(hue: f64, saturation: f64, lightness: f64) -> String {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let second = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (red, green, blue) = match hue {
+        h if h < 60.0 => (chroma, second, 0.0),
+        h if h < 120.0 => (second, chroma, 0.0),
+        h if h < 180.0 => (0.0, chroma, second),
+        h if h < 240.0 => (0.0, second, chroma),
+        h if h < 300.0 => (second, 0.0, chroma),
+        _ => (chroma, 0.0, second),
+    };
+    let offset = lightness - chroma / 2.0;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // channels are within 0..=1
+    let byte = |channel: f64| ((channel + offset) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", byte(red), byte(green), byte(blue))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_color() {
+        for valid in ["#fff", "#A1b2C3", "red", "RebeccaPurple"] {
+            assert!(validate_color(valid).is_ok(), "{valid}");
+        }
+        for invalid in ["", "#ff", "#ggg", "#1234567", "fff", "bright red", "url(x)"] {
+            assert!(validate_color(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_auto_color_is_deterministic() {
+        let color = auto_color("Rust", []);
+        assert_eq!(color, auto_color("rust", []));
+        assert!(validate_color(&color).is_ok());
+        assert_eq!(color.len(), 7);
+    }
+
+    #[test]
+    fn test_auto_colors_are_distinct() {
+        let mut used: Vec<String> = Vec::new();
+        for name in [
+            "rust", "go", "python", "web", "news", "recipes", "travel", "music",
+        ] {
+            let color = auto_color(name, used.iter().map(String::as_str));
+            let hue = hue_of(&color).unwrap();
+            for other in &used {
+                let distance = (hue - hue_of(other).unwrap()).abs();
+                assert!(distance.min(360.0 - distance) >= MIN_HUE_GAP - 1.0);
+            }
+            used.push(color);
+        }
+    }
+
+    #[test]
+    fn test_hue_round_trip() {
+        for hue in [0.0, 45.0, 200.0, 330.0] {
+            let color = hsl_to_hex(hue, AUTO_SATURATION, AUTO_LIGHTNESS);
+            assert!((hue_of(&color).unwrap() - hue).abs() < 2.0, "{hue} {color}");
+        }
+        assert_eq!(hue_of("#808080"), None);
+        assert_eq!(hue_of("red"), None);
+    }
+}
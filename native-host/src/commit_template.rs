@@ -0,0 +1,179 @@
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::diff::BookmarksDiff;
+use crate::storage::BookmarksData;
+
+/// Used when no template has been configured with `SetConfig`
+pub const DEFAULT_COMMIT_TEMPLATE: &str =
+    "Update bookmarks: {added} added, {removed} removed, {modified} modified ({bookmarks} bookmarks, {tags} tags)";
+
+/// Placeholders a template may use, each written as `{name}`
+pub const PLACEHOLDERS: &[&str] = &[
+    "added",
+    "removed",
+    "modified",
+    "bookmarks",
+    "tags",
+    "device",
+    "timestamp",
+];
+
+/// Values substituted into a commit message template
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitSummary {
+    /// Bookmarks added, removed, and modified since the previous commit
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    /// Totals after the change
+    pub bookmarks: usize,
+    pub tags: usize,
+    pub device: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CommitSummary {
+    /// Summarize the change from the previously committed data to `current`
+    pub fn new(
+        previous: &BookmarksData,
+        current: &BookmarksData,
+        device: String,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        let BookmarksDiff { bookmarks, .. } = crate::diff::diff(previous, current);
+        Self {
+            added: bookmarks.added.len(),
+            removed: bookmarks.removed.len(),
+            modified: bookmarks.modified.len(),
+            bookmarks: current.get_bookmarks().len(),
+            tags: current.get_tags().len(),
+            device,
+            timestamp,
+        }
+    }
+
+    fn value(&self, placeholder: &str) -> Option<String> {
+        let value = match placeholder {
+            "added" => self.added.to_string(),
+            "removed" => self.removed.to_string(),
+            "modified" => self.modified.to_string(),
+            "bookmarks" => self.bookmarks.to_string(),
+            "tags" => self.tags.to_string(),
+            "device" => self.device.clone(),
+            "timestamp" => self.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Check that a template only uses known placeholders and isn't blank
+pub fn validate_template(template: &str) -> Result<()> {
+    if template.trim().is_empty() {
+        anyhow::bail!("Commit message template is empty");
+    }
+    for name in placeholders(template) {
+        if !PLACEHOLDERS.contains(&name) {
+            anyhow::bail!(
+                "Unknown placeholder {{{name}}} (expected one of: {})",
+                PLACEHOLDERS.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fill in a template. Unknown placeholders are left as written.
+pub fn render(template: &str, summary: &CommitSummary) -> String {
+    let mut message = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        message.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some((end, value)) = after
+            .find('}')
+            .and_then(|end| Some((end, summary.value(&after[..end])?)))
+        {
+            message.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            message.push('{');
+            rest = after;
+        }
+    }
+    message.push_str(rest);
+    message
+}
+
+/// Names of all `{placeholder}`s in a template
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|part| {
+        let end = part.find('}')?;
+        Some(&part[..end])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+
+    fn summary() -> CommitSummary {
+        CommitSummary {
+            added: 2,
+            removed: 1,
+            modified: 3,
+            bookmarks: 10,
+            tags: 4,
+            device: "work-laptop".to_string(),
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        assert_eq!(
+            render(DEFAULT_COMMIT_TEMPLATE, &summary()),
+            "Update bookmarks: 2 added, 1 removed, 3 modified (10 bookmarks, 4 tags)"
+        );
+    }
+
+    #[test]
+    fn test_render_device_and_timestamp() {
+        assert_eq!(
+            render("[{device}] +{added} at {timestamp}", &summary()),
+            "[work-laptop] +2 at 2023-11-14T22:13:20Z"
+        );
+    }
+
+    #[test]
+    fn test_unknown_placeholders_are_kept_and_rejected() {
+        assert_eq!(render("{nope} {added}", &summary()), "{nope} 2");
+        assert_eq!(render("unclosed {added", &summary()), "unclosed {added");
+        assert!(validate_template("{nope}").is_err());
+        assert!(validate_template("   ").is_err());
+        assert!(validate_template(DEFAULT_COMMIT_TEMPLATE).is_ok());
+    }
+
+    #[test]
+    fn test_summary_counts_changes() {
+        let kept = create_bookmark("https://a.example".to_string(), "A".to_string(), vec![]);
+        let removed = create_bookmark("https://b.example".to_string(), "B".to_string(), vec![]);
+        let added = create_bookmark("https://c.example".to_string(), "C".to_string(), vec![]);
+
+        let mut previous = BookmarksData::new();
+        previous.add_bookmark(kept.clone()).unwrap();
+        previous.add_bookmark(removed).unwrap();
+        let mut current = BookmarksData::new();
+        current.add_bookmark(kept).unwrap();
+        current.add_bookmark(added).unwrap();
+
+        let summary = CommitSummary::new(&previous, &current, "desk".to_string(), Utc::now());
+        assert_eq!(
+            (summary.added, summary.removed, summary.modified),
+            (1, 1, 0)
+        );
+        assert_eq!(summary.bookmarks, 2);
+    }
+}
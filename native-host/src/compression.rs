@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+use crate::messaging::{Response, MAX_PAYLOAD_SIZE};
+
+/// Response data smaller than this is sent as it is
+pub const MIN_COMPRESSED_SIZE: usize = 4096;
+
+/// zstd level for responses; higher levels cost more time than they save
+const ZSTD_LEVEL: i32 = 3;
+
+/// Payload compression agreed on in the `Hello` handshake
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The first of the client's `accepted` encodings (in its order of
+    /// preference) that the host supports
+    pub fn negotiate(accepted: &[String]) -> Option<Self> {
+        accepted.iter().find_map(|name| match name.as_str() {
+            "gzip" => Some(Self::Gzip),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        })
+    }
+
+    pub fn compress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Zstd => Ok(zstd::encode_all(bytes, ZSTD_LEVEL)?),
+        }
+    }
+
+    /// Decompress `bytes`, refusing output over `limit` bytes so a small
+    /// payload can't expand to fill memory
+    pub fn decompress(self, bytes: &[u8], limit: usize) -> Result<Vec<u8>> {
+        let reader: Box<dyn Read + '_> = match self {
+            Self::Gzip => Box::new(GzDecoder::new(bytes)),
+            Self::Zstd => Box::new(zstd::Decoder::new(bytes)?),
+        };
+        let mut output = Vec::new();
+        reader
+            .take(limit as u64 + 1)
+            .read_to_end(&mut output)
+            .context("Failed to decompress payload")?;
+        if output.len() > limit {
+            anyhow::bail!("Decompressed payload exceeds {limit} bytes");
+        }
+        Ok(output)
+    }
+}
+
+/// Decode a base64 payload compressed with `compression`
+pub fn decode(encoded: &str, compression: Compression) -> Result<Vec<u8>> {
+    let compressed = BASE64
+        .decode(encoded)
+        .context("Compressed payload isn't valid base64")?;
+    compression.decompress(&compressed, MAX_PAYLOAD_SIZE)
+}
+
+/// `response` for a client that accepts `compression`. A `Success` whose
+/// data is large enough to benefit has `data` replaced by its compressed
+/// JSON in base64, with `encoding` naming the compression.
+pub fn encode_response(response: &Response, compression: Compression) -> Result<serde_json::Value> {
+    let mut value = serde_json::to_value(response).context("Failed to serialize response")?;
+    if !matches!(response, Response::Success { data: Some(_), .. }) {
+        return Ok(value);
+    }
+
+    let json = serde_json::to_vec(&value["data"]).context("Failed to serialize response data")?;
+    if json.len() < MIN_COMPRESSED_SIZE {
+        return Ok(value);
+    }
+    let compressed = compression.compress(&json)?;
+    if compressed.len() < json.len() {
+        value["data"] = BASE64.encode(compressed).into();
+        value["encoding"] = serde_json::to_value(compression)?;
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn large_data() -> serde_json::Value {
+        let bookmarks: Vec<_> = (0..200)
+            .map(|i| serde_json::json!({ "id": i, "url": format!("https://example.com/{i}") }))
+            .collect();
+        serde_json::json!({ "data": bookmarks })
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let names = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            Compression::negotiate(&names(&["br", "zstd", "gzip"])),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::negotiate(&names(&["gzip", "zstd"])),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(Compression::negotiate(&names(&["br"])), None);
+        assert_eq!(Compression::negotiate(&[]), None);
+    }
+
+    #[test]
+    fn test_round_trip_and_limit() {
+        let json = serde_json::to_vec(&large_data()).unwrap();
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(&json).unwrap();
+            assert!(compressed.len() < json.len() / 4);
+            assert_eq!(
+                compression.decompress(&compressed, json.len()).unwrap(),
+                json
+            );
+            assert!(compression.decompress(&compressed, json.len() - 1).is_err());
+            assert!(compression.decompress(b"not compressed", 1024).is_err());
+        }
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = Response::Success {
+            message: "Read".to_string(),
+            data: Some(large_data()),
+        };
+        let value = encode_response(&response, Compression::Gzip).unwrap();
+        assert_eq!(value["type"], "success");
+        assert_eq!(value["encoding"], "gzip");
+        let data = decode(value["data"].as_str().unwrap(), Compression::Gzip).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&data).unwrap(),
+            large_data()
+        );
+
+        // Small data and other responses aren't worth it
+        let small = Response::Success {
+            message: "Saved".to_string(),
+            data: Some(serde_json::json!({ "commit": "abc123" })),
+        };
+        assert_eq!(
+            encode_response(&small, Compression::Zstd).unwrap(),
+            serde_json::to_value(&small).unwrap()
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use std::process::Command;
+
+/// Longest device name accepted for commit authors and trailers
+pub const MAX_DEVICE_NAME_LENGTH: usize = 64;
+
+/// Commit trailer key recording which device made a commit
+pub const DEVICE_TRAILER: &str = "Device";
+
+/// Best-effort name for this machine, used when no device name is configured.
+///
+/// Tries the usual environment variables, then `/etc/hostname`, then the
+/// `hostname` command, falling back to `"unknown"`.
+pub fn hostname() -> String {
+    let sources: [&dyn Fn() -> Option<String>; 4] = [
+        &|| std::env::var("HOSTNAME").ok(),
+        &|| std::env::var("COMPUTERNAME").ok(),
+        &|| std::fs::read_to_string("/etc/hostname").ok(),
+        &|| {
+            let output = Command::new("hostname").output().ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8(output.stdout).ok())?
+        },
+    ];
+
+    sources
+        .iter()
+        .filter_map(|source| source())
+        .map(|name| name.trim().to_string())
+        .find(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Check a user-supplied device name is usable in an author name and trailer
+pub fn validate_device_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        anyhow::bail!("Device name is empty");
+    }
+    if name.chars().count() > MAX_DEVICE_NAME_LENGTH {
+        anyhow::bail!("Device name is longer than {MAX_DEVICE_NAME_LENGTH} characters");
+    }
+    if name
+        .chars()
+        .any(|c| c.is_control() || matches!(c, '<' | '>'))
+    {
+        anyhow::bail!("Device name contains control characters or angle brackets");
+    }
+    Ok(())
+}
+
+/// `name` as a branch name component: lowercase ASCII letters and digits,
+/// with runs of anything else turned into single hyphens
+pub fn branch_name(name: &str) -> String {
+    let mut branch = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            branch.push(c.to_ascii_lowercase());
+        } else if !branch.is_empty() && !branch.ends_with('-') {
+            branch.push('-');
+        }
+    }
+    let branch = branch.trim_end_matches('-');
+    if branch.is_empty() {
+        "unnamed".to_string()
+    } else {
+        branch.to_string()
+    }
+}
+
+/// Append a `Device:` trailer to a commit message
+pub fn with_device_trailer(message: &str, device: &str) -> String {
+    format!("{}\n\n{DEVICE_TRAILER}: {device}", message.trim_end())
+}
+
+/// The device recorded in a commit message's trailer, if any
+pub fn device_from_message(message: &str) -> Option<String> {
+    let trailers = git2::message_trailers_strs(message).ok()?;
+    let device = trailers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(DEVICE_TRAILER))
+        .map(|(_, value)| value.to_string());
+    device
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trailer_roundtrip() {
+        let message = with_device_trailer("Update bookmarks\n", "work-laptop");
+        assert_eq!(message, "Update bookmarks\n\nDevice: work-laptop");
+        assert_eq!(
+            device_from_message(&message).as_deref(),
+            Some("work-laptop")
+        );
+        assert_eq!(device_from_message("Update bookmarks"), None);
+    }
+
+    #[test]
+    fn test_branch_name() {
+        assert_eq!(branch_name("Work Laptop"), "work-laptop");
+        assert_eq!(branch_name("  Sam's  MacBook (2)"), "sam-s-macbook-2");
+        assert_eq!(branch_name("Büro"), "b-ro");
+        assert_eq!(branch_name("ноутбук"), "unnamed");
+    }
+
+    #[test]
+    fn test_validate_device_name() {
+        assert!(validate_device_name("work-laptop").is_ok());
+        assert!(validate_device_name(" ").is_err());
+        assert!(validate_device_name("bad\nname").is_err());
+        assert!(validate_device_name("<evil>").is_err());
+        assert!(validate_device_name(&"x".repeat(MAX_DEVICE_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_hostname_is_never_empty() {
+        assert!(!hostname().is_empty());
+    }
+}
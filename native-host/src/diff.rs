@@ -0,0 +1,369 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::storage::{
+    BookmarkRelationships, BookmarksData, Resource, RuleRelationships, TagRelationships,
+};
+
+/// Semantic difference between two versions of bookmarks.json
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct BookmarksDiff {
+    pub bookmarks: ChangeSet,
+    pub tags: ChangeSet,
+    pub saved_searches: ChangeSet,
+    pub rules: ChangeSet,
+}
+
+/// Resources added, removed, or modified between two versions
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct ChangeSet {
+    pub added: Vec<ResourceRef>,
+    pub removed: Vec<ResourceRef>,
+    pub modified: Vec<Modification>,
+}
+
+/// A resource identified by ID, with a human-readable label
+/// (bookmark title, or tag, saved search, or rule name) for display
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ResourceRef {
+    pub id: String,
+    pub label: String,
+}
+
+/// A resource present on both sides whose contents changed
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Modification {
+    pub id: String,
+    /// Label as of the newer version
+    pub label: String,
+    /// Names of the attributes/relationships that differ
+    pub fields: Vec<&'static str>,
+}
+
+impl BookmarksDiff {
+    /// Whether the two versions are semantically identical
+    pub fn is_empty(&self) -> bool {
+        self.bookmarks.is_empty()
+            && self.tags.is_empty()
+            && self.saved_searches.is_empty()
+            && self.rules.is_empty()
+    }
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Compare two versions of the bookmarks data resource by resource.
+///
+/// Resources are matched by ID, so a bookmark whose URL changed shows up as
+/// modified rather than as a removal plus an addition. Ordering of resources
+/// and of tag relationships is ignored. `modified` timestamps are not
+/// compared since they change on every edit anyway.
+pub fn diff(from: &BookmarksData, to: &BookmarksData) -> BookmarksDiff {
+    BookmarksDiff {
+        bookmarks: diff_resources(&from.get_bookmarks(), &to.get_bookmarks()),
+        tags: diff_resources(&from.get_tags(), &to.get_tags()),
+        saved_searches: diff_resources(&from.get_saved_searches(), &to.get_saved_searches()),
+        rules: diff_resources(&from.get_rules(), &to.get_rules()),
+    }
+}
+
+fn diff_resources(from: &[&Resource], to: &[&Resource]) -> ChangeSet {
+    let from: BTreeMap<&str, &Resource> = from.iter().map(|r| (r.id(), *r)).collect();
+    let to: BTreeMap<&str, &Resource> = to.iter().map(|r| (r.id(), *r)).collect();
+
+    let mut changes = ChangeSet::default();
+    for (id, old) in &from {
+        match to.get(id) {
+            None => changes.removed.push(resource_ref(old)),
+            Some(new) => {
+                let fields = changed_fields(old, new);
+                if !fields.is_empty() {
+                    changes.modified.push(Modification {
+                        id: (*id).to_string(),
+                        label: resource_label(new).to_string(),
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+    for (id, new) in &to {
+        if !from.contains_key(id) {
+            changes.added.push(resource_ref(new));
+        }
+    }
+    changes
+}
+
+fn resource_label(resource: &Resource) -> &str {
+    match resource {
+        Resource::Bookmark { attributes, .. } => &attributes.title,
+        Resource::Tag { attributes, .. } => &attributes.name,
+        Resource::SavedSearch { attributes, .. } => &attributes.name,
+        Resource::Rule { attributes, .. } => &attributes.name,
+    }
+}
+
+fn resource_ref(resource: &Resource) -> ResourceRef {
+    ResourceRef {
+        id: resource.id().to_string(),
+        label: resource_label(resource).to_string(),
+    }
+}
+
+fn changed_fields(old: &Resource, new: &Resource) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    match (old, new) {
+        (
+            Resource::Bookmark {
+                attributes: a,
+                relationships: ra,
+                extra: ea,
+                ..
+            },
+            Resource::Bookmark {
+                attributes: b,
+                relationships: rb,
+                extra: eb,
+                ..
+            },
+        ) => {
+            if a.url != b.url {
+                fields.push("url");
+            }
+            if a.title != b.title {
+                fields.push("title");
+            }
+            if a.notes != b.notes {
+                fields.push("notes");
+            }
+            if a.private != b.private {
+                fields.push("private");
+            }
+            if a.unread != b.unread {
+                fields.push("unread");
+            }
+            if a.archive_url != b.archive_url {
+                fields.push("archive_url");
+            }
+            if a.enrichment != b.enrichment {
+                fields.push("enrichment");
+            }
+            if a.suggested_tags != b.suggested_tags {
+                fields.push("suggested_tags");
+            }
+            if a.expires_at != b.expires_at {
+                fields.push("expires_at");
+            }
+            if a.pinned != b.pinned || a.pin_order != b.pin_order {
+                fields.push("pinned");
+            }
+            if bookmark_tag_ids(ra.as_ref()) != bookmark_tag_ids(rb.as_ref()) {
+                fields.push("tags");
+            }
+            if ea != eb {
+                fields.push("meta");
+            }
+        }
+        (
+            Resource::Tag {
+                attributes: a,
+                relationships: ra,
+                ..
+            },
+            Resource::Tag {
+                attributes: b,
+                relationships: rb,
+                ..
+            },
+        ) => {
+            if a.name != b.name {
+                fields.push("name");
+            }
+            if a.color != b.color {
+                fields.push("color");
+            }
+            if a.description != b.description {
+                fields.push("description");
+            }
+            let parent = |r: Option<&TagRelationships>| {
+                r.and_then(|r| r.parent.as_ref())
+                    .and_then(|p| p.data.as_ref())
+                    .map(|d| d.id.clone())
+            };
+            if parent(ra.as_ref()) != parent(rb.as_ref()) {
+                fields.push("parent");
+            }
+        }
+        (
+            Resource::SavedSearch { attributes: a, .. },
+            Resource::SavedSearch { attributes: b, .. },
+        ) => {
+            if a.name != b.name {
+                fields.push("name");
+            }
+            if a.query != b.query {
+                fields.push("query");
+            }
+            if a.sort != b.sort {
+                fields.push("sort");
+            }
+        }
+        (
+            Resource::Rule {
+                attributes: a,
+                relationships: ra,
+                ..
+            },
+            Resource::Rule {
+                attributes: b,
+                relationships: rb,
+                ..
+            },
+        ) => {
+            if a.name != b.name {
+                fields.push("name");
+            }
+            if a.field != b.field || a.match_kind != b.match_kind || a.pattern != b.pattern {
+                fields.push("pattern");
+            }
+            let tag_ids = |r: &RuleRelationships| -> BTreeSet<String> {
+                r.tags.data.iter().map(|t| t.id.clone()).collect()
+            };
+            if tag_ids(ra) != tag_ids(rb) {
+                fields.push("tags");
+            }
+        }
+        // Same ID reused for a different resource type
+        _ => fields.push("type"),
+    }
+    fields
+}
+
+fn bookmark_tag_ids(relationships: Option<&BookmarkRelationships>) -> BTreeSet<&str> {
+    relationships
+        .and_then(|r| r.tags.as_ref())
+        .map(|tags| tags.data.iter().map(|t| t.id.as_str()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+
+    fn id_of(resource: &Resource) -> String {
+        resource.id().to_string()
+    }
+
+    #[test]
+    fn test_identical_data_has_no_changes() {
+        let mut data = BookmarksData::new();
+        data.add_bookmark(create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec![],
+        ))
+        .unwrap();
+
+        assert!(diff(&data, &data.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_added_and_removed() {
+        let kept = create_bookmark(
+            "https://kept.example".to_string(),
+            "Kept".to_string(),
+            vec![],
+        );
+        let gone = create_bookmark(
+            "https://gone.example".to_string(),
+            "Gone".to_string(),
+            vec![],
+        );
+        let new = create_bookmark("https://new.example".to_string(), "New".to_string(), vec![]);
+        let tag = create_tag("Rust".to_string(), None, None);
+
+        let mut from = BookmarksData::new();
+        from.add_bookmark(kept.clone()).unwrap();
+        from.add_bookmark(gone.clone()).unwrap();
+
+        let mut to = BookmarksData::new();
+        to.add_bookmark(new.clone()).unwrap();
+        to.add_bookmark(kept).unwrap();
+        to.add_tag(tag.clone()).unwrap();
+
+        let result = diff(&from, &to);
+        assert_eq!(
+            result.bookmarks.added,
+            vec![ResourceRef {
+                id: id_of(&new),
+                label: "New".to_string()
+            }]
+        );
+        assert_eq!(
+            result.bookmarks.removed,
+            vec![ResourceRef {
+                id: id_of(&gone),
+                label: "Gone".to_string()
+            }]
+        );
+        assert!(result.bookmarks.modified.is_empty());
+        assert_eq!(result.tags.added.len(), 1);
+        assert_eq!(result.tags.added[0].id, id_of(&tag));
+    }
+
+    #[test]
+    fn test_modified_fields() {
+        let tag = create_tag("Rust".to_string(), None, None);
+        let bookmark = create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec![],
+        );
+
+        let mut from = BookmarksData::new();
+        from.add_bookmark(bookmark.clone()).unwrap();
+        from.add_tag(tag.clone()).unwrap();
+
+        let mut edited = bookmark.clone();
+        if let Resource::Bookmark {
+            attributes,
+            relationships,
+            ..
+        } = &mut edited
+        {
+            attributes.title = "Renamed".to_string();
+            attributes.modified = Some(chrono::Utc::now());
+            *relationships = match create_bookmark(String::new(), String::new(), vec![id_of(&tag)])
+            {
+                Resource::Bookmark { relationships, .. } => relationships,
+                _ => unreachable!(),
+            };
+        }
+        let mut recolored = tag.clone();
+        if let Resource::Tag { attributes, .. } = &mut recolored {
+            attributes.color = Some("#ff0000".to_string());
+        }
+
+        let mut to = BookmarksData::new();
+        to.add_bookmark(edited).unwrap();
+        to.add_tag(recolored).unwrap();
+
+        let result = diff(&from, &to);
+        assert_eq!(
+            result.bookmarks.modified,
+            vec![Modification {
+                id: id_of(&bookmark),
+                label: "Renamed".to_string(),
+                fields: vec!["title", "tags"],
+            }]
+        );
+        assert_eq!(result.tags.modified.len(), 1);
+        assert_eq!(result.tags.modified[0].fields, vec!["color"]);
+    }
+}
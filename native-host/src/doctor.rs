@@ -0,0 +1,250 @@
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::git::GitRepo;
+use crate::storage;
+
+/// An `index.lock` older than this is assumed to be left over from a crashed
+/// git process rather than held by a running one
+pub const STALE_LOCK_AGE: Duration = Duration::from_mins(2);
+
+/// Outcome of one health check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    /// Was broken and has been repaired
+    Fixed,
+    /// Broken and needs the user's attention
+    Problem,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Ok,
+            detail: None,
+        }
+    }
+
+    fn fixed(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fixed,
+            detail: Some(detail),
+        }
+    }
+
+    fn problem(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Problem,
+            detail: Some(detail),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    /// Problems that couldn't be repaired automatically
+    pub fn problems(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Problem)
+            .count()
+    }
+
+    pub fn fixed(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Fixed)
+            .count()
+    }
+}
+
+/// Check the repository for the ways a sync can leave it broken, repairing
+/// what is safe to repair
+pub fn run(repo: &GitRepo, encryption_enabled: bool) -> DoctorReport {
+    run_at(repo, encryption_enabled, SystemTime::now())
+}
+
+fn run_at(repo: &GitRepo, encryption_enabled: bool, now: SystemTime) -> DoctorReport {
+    // The lock goes first: aborting a merge needs to write the index
+    let checks = vec![
+        check_index_lock(repo.path(), now),
+        check_operation(repo),
+        check_head(repo),
+        check_bookmarks(repo.path(), encryption_enabled),
+    ];
+    DoctorReport { checks }
+}
+
+fn check_index_lock(repo_path: &Path, now: SystemTime) -> Check {
+    const NAME: &str = "index_lock";
+
+    let lock = repo_path.join(".git").join("index.lock");
+    let Ok(metadata) = lock.metadata() else {
+        return Check::ok(NAME);
+    };
+
+    let age = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| now.duration_since(modified).ok())
+        .unwrap_or_default();
+    if age < STALE_LOCK_AGE {
+        return Check::problem(
+            NAME,
+            "index.lock is held, probably by a running git process; try again shortly".to_string(),
+        );
+    }
+
+    match std::fs::remove_file(&lock) {
+        Ok(()) => Check::fixed(
+            NAME,
+            format!("Removed stale index.lock ({}s old)", age.as_secs()),
+        ),
+        Err(e) => Check::problem(NAME, format!("Failed to remove stale index.lock: {e}")),
+    }
+}
+
+fn check_operation(repo: &GitRepo) -> Check {
+    const NAME: &str = "interrupted_operation";
+
+    let Some(operation) = repo.operation_in_progress() else {
+        return Check::ok(NAME);
+    };
+    match repo.abort_operation() {
+        Ok(()) => Check::fixed(NAME, format!("Aborted interrupted {operation}")),
+        Err(e) => Check::problem(NAME, format!("Failed to abort {operation}: {e:#}")),
+    }
+}
+
+fn check_head(repo: &GitRepo) -> Check {
+    const NAME: &str = "head";
+
+    match repo.head_error() {
+        None => Check::ok(NAME),
+        Some(error) => Check::problem(NAME, format!("HEAD is invalid: {error}")),
+    }
+}
+
+fn check_bookmarks(repo_path: &Path, encryption_enabled: bool) -> Check {
+    const NAME: &str = "bookmarks";
+
+    let file = repo_path.join("bookmarks.json");
+    if !file.exists() {
+        return Check::ok(NAME);
+    }
+    match storage::read_from_file_with_encryption(&file, encryption_enabled) {
+        Ok(_) => Check::ok(NAME),
+        Err(e) => match e.downcast_ref::<storage::ReferenceError>() {
+            Some(broken) => Check::problem(NAME, format!("{broken}; send Repair to fix them")),
+            None => Check::problem(NAME, format!("bookmarks.json is unreadable: {e:#}")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn committed_repo(dir: &TempDir) -> GitRepo {
+        let repo = GitRepo::init(dir.path()).unwrap();
+        storage::write_to_file(
+            dir.path().join("bookmarks.json"),
+            &storage::BookmarksData::new(),
+        )
+        .unwrap();
+        repo.add_file("bookmarks.json").unwrap();
+        repo.commit("Initial").unwrap();
+        repo
+    }
+
+    fn status(report: &DoctorReport, name: &str) -> CheckStatus {
+        report
+            .checks
+            .iter()
+            .find(|check| check.name == name)
+            .unwrap()
+            .status
+            .clone()
+    }
+
+    #[test]
+    fn test_healthy_repo() {
+        let dir = TempDir::new().unwrap();
+        let repo = committed_repo(&dir);
+
+        let report = run(&repo, false);
+        assert_eq!(report.problems(), 0);
+        assert_eq!(report.fixed(), 0);
+    }
+
+    #[test]
+    fn test_stale_lock_is_removed_but_fresh_lock_is_not() {
+        let dir = TempDir::new().unwrap();
+        let repo = committed_repo(&dir);
+        let lock = dir.path().join(".git").join("index.lock");
+        fs::write(&lock, "").unwrap();
+
+        let report = run_at(&repo, false, SystemTime::now());
+        assert_eq!(status(&report, "index_lock"), CheckStatus::Problem);
+        assert!(lock.exists());
+
+        let later = SystemTime::now() + STALE_LOCK_AGE + Duration::from_secs(1);
+        let report = run_at(&repo, false, later);
+        assert_eq!(status(&report, "index_lock"), CheckStatus::Fixed);
+        assert!(!lock.exists());
+    }
+
+    #[test]
+    fn test_interrupted_merge_is_aborted() {
+        let dir = TempDir::new().unwrap();
+        let repo = committed_repo(&dir);
+        let head = git2::Repository::open(dir.path())
+            .unwrap()
+            .refname_to_id("HEAD")
+            .unwrap();
+        fs::write(
+            dir.path().join(".git").join("MERGE_HEAD"),
+            format!("{head}\n"),
+        )
+        .unwrap();
+        fs::write(dir.path().join("bookmarks.json"), "<<<<<<< conflict").unwrap();
+        assert_eq!(repo.operation_in_progress().as_deref(), Some("merge"));
+
+        let report = run(&repo, false);
+        assert_eq!(status(&report, "interrupted_operation"), CheckStatus::Fixed);
+        // Resetting to HEAD also restored the conflicted file
+        assert_eq!(status(&report, "bookmarks"), CheckStatus::Ok);
+        assert_eq!(repo.operation_in_progress(), None);
+    }
+
+    #[test]
+    fn test_unreadable_bookmarks_are_reported() {
+        let dir = TempDir::new().unwrap();
+        let repo = committed_repo(&dir);
+        fs::write(dir.path().join("bookmarks.json"), "{ not json").unwrap();
+
+        let report = run(&repo, false);
+        assert_eq!(status(&report, "bookmarks"), CheckStatus::Problem);
+        assert_eq!(report.problems(), 1);
+    }
+}
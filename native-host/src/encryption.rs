@@ -1,25 +1,225 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, KeyInit, OsRng, Payload},
     Aes256Gcm, Nonce,
 };
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
 
+use crate::metrics;
+
+#[cfg(target_os = "macos")]
+use security_framework::passwords::{self, AccessControlOptions, PasswordOptions};
 #[cfg(target_os = "macos")]
-use security_framework::os::macos::keychain::SecKeychain;
+use security_framework_sys::base::errSecItemNotFound;
 
 #[cfg(target_os = "macos")]
 const KEYCHAIN_SERVICE: &str = "com.webtags.encryption";
 #[cfg(target_os = "macos")]
 const KEYCHAIN_ACCOUNT: &str = "master-key";
+/// `errSecMissingEntitlement`: the data protection keychain only works for a
+/// signed binary with a `keychain-access-groups` entitlement
+#[cfg(target_os = "macos")]
+const ERR_SEC_MISSING_ENTITLEMENT: i32 = -34018;
 const NONCE_SIZE: usize = 12; // 96 bits for AES-GCM
+const XNONCE_SIZE: usize = 24; // 192 bits for XChaCha20-Poly1305
+const AAD_SCHEME_REPO_PATH: &str = "repo-path";
+const AAD_VERSION: u32 = 1;
+const KEY_SIZE: usize = 32; // 256 bits for AES-256-GCM
+const SALT_SIZE: usize = 16;
+const MIN_PASSPHRASE_LENGTH: usize = 12;
+
+// Argon2id parameters for key export (OWASP recommended minimums)
+const ARGON2_MEMORY_KIB: u32 = 19_456;
+const ARGON2_ITERATIONS: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+
+// Upper bounds for parameters read back from a wrapped key. The blob may come from
+// another device or a committed file, so it must not be able to demand gigabytes of RAM.
+const MAX_ARGON2_MEMORY_KIB: u32 = ARGON2_MEMORY_KIB * 4;
+const MAX_ARGON2_ITERATIONS: u32 = ARGON2_ITERATIONS * 4;
+const MAX_ARGON2_PARALLELISM: u32 = ARGON2_PARALLELISM * 4;
+
+/// How long a key read from the Keychain is reused before Touch ID is required again
+pub const DEFAULT_KEY_CACHE_TTL: Duration = Duration::from_mins(5);
+
+/// Longest TTL `SetKeyCacheTtl` accepts; longer would keep the key in memory
+/// for as long as the browser is open
+pub const MAX_KEY_CACHE_TTL: Duration = Duration::from_hours(24);
+
+/// Decrypted master key shared by every `EncryptionManager` in the process.
+///
+/// Managers are created per operation, so the cache can't live in one of them.
+static KEY_CACHE: Mutex<KeyCache> = Mutex::new(KeyCache::new(DEFAULT_KEY_CACHE_TTL));
+
+/// In-memory copy of the master key that expires after a TTL.
+/// A zero TTL disables caching entirely.
+struct KeyCache {
+    entry: Option<(Zeroizing<Vec<u8>>, Instant)>,
+    ttl: Duration,
+}
 
-/// Encrypted file format
-#[derive(Debug, Serialize, Deserialize)]
+impl KeyCache {
+    const fn new(ttl: Duration) -> Self {
+        Self { entry: None, ttl }
+    }
+
+    /// Cached key, if one was stored less than `ttl` before `now`
+    fn get(&mut self, now: Instant) -> Option<Zeroizing<Vec<u8>>> {
+        match &self.entry {
+            Some((key, stored_at)) if now.duration_since(*stored_at) < self.ttl => {
+                Some(key.clone())
+            }
+            Some(_) => {
+                self.clear();
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, key: &[u8], now: Instant) {
+        if !self.ttl.is_zero() {
+            self.entry = Some((Zeroizing::new(key.to_vec()), now));
+        }
+    }
+
+    fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+        if ttl.is_zero() {
+            self.clear();
+        }
+    }
+
+    fn clear(&mut self) {
+        // Zeroizing wipes the key bytes on drop
+        self.entry = None;
+    }
+}
+
+fn key_cache() -> std::sync::MutexGuard<'static, KeyCache> {
+    KEY_CACHE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// How bookmarks.json is encrypted when encryption is enabled
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionMode {
+    /// The whole file is a single encrypted blob
+    #[default]
+    Full,
+    /// URLs and titles stay plaintext; notes and private bookmarks are encrypted per field
+    Fields,
+}
+
+/// AEAD cipher used for new encryptions. Decryption always follows the
+/// `algorithm` recorded in the data, so files written with either still open.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Cipher {
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    /// 192-bit random nonces, so nonce reuse is not a concern even across
+    /// many small encrypted fields
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Algorithm name recorded in `EncryptedData`
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => "AES-256-GCM",
+            Cipher::XChaCha20Poly1305 => "XChaCha20-Poly1305",
+        }
+    }
+
+    /// Look up a cipher by its recorded algorithm name
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "AES-256-GCM" => Ok(Cipher::Aes256Gcm),
+            "XChaCha20-Poly1305" => Ok(Cipher::XChaCha20Poly1305),
+            _ => anyhow::bail!("Unsupported encryption algorithm: {name}"),
+        }
+    }
+
+    fn nonce_size(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => NONCE_SIZE,
+            Cipher::XChaCha20Poly1305 => XNONCE_SIZE,
+        }
+    }
+
+    fn seal(self, key: &[u8], nonce: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let ciphertext = match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(key)
+                .map_err(|e| anyhow::anyhow!("Failed to create cipher: {e:?}"))?
+                .encrypt(Nonce::from_slice(nonce), payload),
+            Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new_from_slice(key)
+                .map_err(|e| anyhow::anyhow!("Failed to create cipher: {e:?}"))?
+                .encrypt(XNonce::from_slice(nonce), payload),
+        };
+        ciphertext.map_err(|e| anyhow::anyhow!("Encryption failed: {e}"))
+    }
+
+    fn open(self, key: &[u8], nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        let plaintext = match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(key)
+                .map_err(|e| anyhow::anyhow!("Failed to create cipher: {e:?}"))?
+                .decrypt(Nonce::from_slice(nonce), payload),
+            Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new_from_slice(key)
+                .map_err(|e| anyhow::anyhow!("Failed to create cipher: {e:?}"))?
+                .decrypt(XNonce::from_slice(nonce), payload),
+        };
+        plaintext.map_err(|e| anyhow::anyhow!("Decryption failed: {e}"))
+    }
+}
+
+/// Describes how the associated data of an `EncryptedData` was built, so the
+/// construction can evolve without breaking files already on disk
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AadDescriptor {
+    pub scheme: String,
+    pub version: u32,
+}
+
+impl AadDescriptor {
+    /// AAD is the repo-relative path (plus field location for envelopes)
+    fn repo_path() -> Self {
+        Self {
+            scheme: AAD_SCHEME_REPO_PATH.to_string(),
+            version: AAD_VERSION,
+        }
+    }
+
+    /// Associated data bytes binding ciphertext to `location`
+    fn build(&self, location: &str) -> Result<Vec<u8>> {
+        match (self.scheme.as_str(), self.version) {
+            (AAD_SCHEME_REPO_PATH, 1) => {
+                Ok(format!("webtags:{AAD_SCHEME_REPO_PATH}:v1:{location}").into_bytes())
+            }
+            (scheme, version) => anyhow::bail!("Unsupported AAD scheme: {scheme} v{version}"),
+        }
+    }
+}
+
+/// Encrypted file format, also used as the envelope for individually encrypted fields
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct EncryptedData {
     version: String,
     encrypted: bool,
@@ -28,9 +228,29 @@ pub struct EncryptedData {
     nonce: Vec<u8>,
     #[serde(with = "base64_serde")]
     ciphertext: Vec<u8>,
+    /// Absent on data written before location binding, which has no AAD
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aad: Option<AadDescriptor>,
 }
 
-mod base64_serde {
+impl EncryptedData {
+    /// Recorded algorithm name (e.g. `AES-256-GCM`)
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    /// Whether this is actually encrypted data (the `encrypted` header flag)
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Whether this data is bound to a location via associated data
+    pub fn is_bound(&self) -> bool {
+        self.aad.is_some()
+    }
+}
+
+pub(crate) mod base64_serde {
     use super::{Engine, Result, BASE64};
     use serde::{Deserialize, Deserializer, Serializer};
 
@@ -53,12 +273,41 @@ mod base64_serde {
 /// Encryption manager
 pub struct EncryptionManager {
     enabled: bool,
+    cipher: Cipher,
+    /// Explicit key, bypassing the Keychain (tests only)
+    key: Option<Zeroizing<Vec<u8>>>,
 }
 
 impl EncryptionManager {
     /// Create new encryption manager
     pub fn new(enabled: bool) -> Self {
-        Self { enabled }
+        Self {
+            enabled,
+            cipher: Cipher::default(),
+            key: None,
+        }
+    }
+
+    /// Use `cipher` for new encryptions
+    #[must_use]
+    pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Cipher used for new encryptions
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Create an enabled manager that uses `key` instead of the Keychain
+    #[cfg(test)]
+    pub(crate) fn with_key(key: &[u8]) -> Self {
+        Self {
+            enabled: true,
+            cipher: Cipher::default(),
+            key: Some(Zeroizing::new(key.to_vec())),
+        }
     }
 
     /// Check if encryption is enabled
@@ -66,11 +315,53 @@ impl EncryptionManager {
         self.enabled
     }
 
+    /// Set how long the key stays cached after a Keychain read.
+    /// `Duration::ZERO` disables caching and locks immediately.
+    pub fn set_key_cache_ttl(ttl: Duration) {
+        key_cache().set_ttl(ttl);
+    }
+
+    /// Current key cache TTL
+    pub fn key_cache_ttl() -> Duration {
+        key_cache().ttl
+    }
+
+    /// Whether a cached key is available without prompting for Touch ID
+    pub fn is_unlocked() -> bool {
+        key_cache().get(Instant::now()).is_some()
+    }
+
+    /// Forget the cached key, so the next operation prompts for Touch ID again
+    pub fn lock() {
+        key_cache().clear();
+        log::info!("Encryption key cache cleared");
+    }
+
+    /// Key for this manager: the explicit key if set, otherwise the cached Keychain key
+    fn key(&self) -> Result<Zeroizing<Vec<u8>>> {
+        match &self.key {
+            Some(key) => Ok(key.clone()),
+            None => Self::cached_key(),
+        }
+    }
+
+    /// Get the master key from the cache, falling back to the Keychain
+    fn cached_key() -> Result<Zeroizing<Vec<u8>>> {
+        if let Some(key) = key_cache().get(Instant::now()) {
+            return Ok(key);
+        }
+
+        // Don't hold the lock across the Touch ID prompt
+        let key = Zeroizing::new(Self::get_key_from_keychain()?);
+        key_cache().insert(&key, Instant::now());
+        Ok(key)
+    }
+
     /// Generate a new encryption key and store in Keychain with Touch ID
     #[cfg(target_os = "macos")]
     pub fn generate_and_store_key() -> Result<()> {
         // Generate random 256-bit key
-        let mut key = [0u8; 32];
+        let mut key = [0u8; KEY_SIZE];
         OsRng.fill_bytes(&mut key);
 
         // Store in macOS Keychain with biometric protection
@@ -84,102 +375,139 @@ impl EncryptionManager {
         anyhow::bail!("Encryption with biometric authentication is only supported on macOS");
     }
 
-    /// Store encryption key in macOS Keychain with Touch ID requirement
+    /// Keychain query for the master key in the data protection keychain
     #[cfg(target_os = "macos")]
-    fn store_key_in_keychain(key: &[u8]) -> Result<()> {
-        use std::io::Read;
-        use std::process::Command;
+    fn keychain_options() -> PasswordOptions {
+        let mut options = PasswordOptions::new_generic_password(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT);
+        options.use_protected_keychain();
+        options
+    }
 
+    /// Keychain query for the login keychain item older versions wrote, and
+    /// unsigned builds still use
+    #[cfg(target_os = "macos")]
+    fn legacy_keychain_options() -> PasswordOptions {
+        PasswordOptions::new_generic_password(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT)
+    }
+
+    /// Store encryption key in macOS Keychain with Touch ID requirement.
+    ///
+    /// The item is created through the Security framework with a
+    /// `SecAccessControl` of `kSecAccessControlBiometryCurrentSet`, so reading it
+    /// requires Touch ID from a currently enrolled finger, and the key never
+    /// appears in a process argument list.
+    ///
+    /// Unsigned builds can't use the data protection keychain, so they store
+    /// the key in the login keychain instead, without the Touch ID requirement.
+    #[cfg(target_os = "macos")]
+    pub(crate) fn store_key_in_keychain(key: &[u8]) -> Result<()> {
         // Convert key to base64 for storage
         let key_b64 = BASE64.encode(key);
 
-        // Delete existing key if present
-        let _ = Self::delete_key_from_keychain();
-
-        // Use the `security` command-line tool to add the item with Touch ID requirement
-        // The -T "" flag means require authentication for all apps (prompts Touch ID)
-        let mut child = Command::new("security")
-            .args([
-                "add-generic-password",
-                "-a",
-                KEYCHAIN_ACCOUNT,
-                "-s",
-                KEYCHAIN_SERVICE,
-                "-w",
-                &key_b64,
-                "-T",
-                "",   // Require authentication (Touch ID) for access
-                "-U", // Update if exists
-            ])
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
-            .context("Failed to spawn security command")?;
-
-        let status = child
-            .wait()
-            .context("Failed to wait for security command")?;
-
-        if status.success() {
-            log::info!("Encryption key stored in Keychain with Touch ID requirement");
-            Ok(())
-        } else {
-            let stderr = child
-                .stderr
-                .and_then(|mut s| {
-                    let mut buf = String::new();
-                    s.read_to_string(&mut buf).ok().map(|_| buf)
-                })
-                .unwrap_or_default();
-            anyhow::bail!("Failed to store key in Keychain: {stderr}")
+        // Access-controlled items can't be updated in place, so replace any existing key
+        Self::delete_keychain_item(Self::keychain_options())?;
+        Self::lock();
+
+        let mut options = Self::keychain_options();
+        options.set_access_control_options(AccessControlOptions::BIOMETRY_CURRENT_SET);
+        options.set_label("WebTags encryption key");
+
+        match passwords::set_generic_password_options(key_b64.as_bytes(), options) {
+            Ok(()) => {
+                log::info!("Encryption key stored in Keychain with Touch ID requirement");
+                Ok(())
+            }
+            Err(e) if e.code() == ERR_SEC_MISSING_ENTITLEMENT => {
+                log::warn!(
+                    "Unsigned build: storing encryption key in the login keychain without Touch ID"
+                );
+                Self::delete_keychain_item(Self::legacy_keychain_options())?;
+                passwords::set_generic_password_options(
+                    key_b64.as_bytes(),
+                    Self::legacy_keychain_options(),
+                )
+                .context("Failed to store key in Keychain")
+            }
+            Err(e) => Err(e).context("Failed to store key in Keychain"),
         }
     }
 
     #[cfg(not(target_os = "macos"))]
-    #[allow(dead_code)]
-    fn store_key_in_keychain(_key: &[u8]) -> Result<()> {
+    pub(crate) fn store_key_in_keychain(_key: &[u8]) -> Result<()> {
         anyhow::bail!("macOS Keychain not available on this platform");
     }
 
     /// Retrieve encryption key from Keychain (triggers Touch ID prompt)
     #[cfg(target_os = "macos")]
-    fn get_key_from_keychain() -> Result<Vec<u8>> {
-        use security_framework::os::macos::keychain::SecKeychain;
-
-        let keychain = SecKeychain::default()?;
-
-        let (password_bytes, _) = keychain
-            .find_generic_password(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT)
-            .context("Encryption key not found in Keychain. Please enable encryption first.")?;
+    pub(crate) fn get_key_from_keychain() -> Result<Vec<u8>> {
+        let password_bytes = match passwords::generic_password(Self::keychain_options()) {
+            Ok(bytes) => bytes,
+            Err(e) if e.code() == errSecItemNotFound => Self::migrate_legacy_key()?,
+            // Unsigned build: the key can only be in the login keychain
+            Err(e) if e.code() == ERR_SEC_MISSING_ENTITLEMENT => Self::read_legacy_key()?,
+            Err(e) => return Err(e).context("Failed to read encryption key from Keychain"),
+        };
 
         // Decode from base64
         let key = BASE64
             .decode(&password_bytes)
             .context("Failed to decode encryption key")?;
 
-        if key.len() != 32 {
+        if key.len() != KEY_SIZE {
             anyhow::bail!("Invalid encryption key size");
         }
 
         Ok(key)
     }
 
+    /// Move a key written by older versions (via the `security` CLI, without
+    /// biometric access control) into an access-controlled item.
+    #[cfg(target_os = "macos")]
+    fn migrate_legacy_key() -> Result<Vec<u8>> {
+        let password_bytes = Self::read_legacy_key()?;
+
+        let key = BASE64
+            .decode(&password_bytes)
+            .context("Failed to decode encryption key")?;
+        Self::store_key_in_keychain(&key)?;
+        Self::delete_keychain_item(Self::legacy_keychain_options())?;
+        log::info!("Migrated encryption key to biometry-protected Keychain item");
+
+        Ok(password_bytes)
+    }
+
+    /// Read the login keychain item, still base64 encoded
+    #[cfg(target_os = "macos")]
+    fn read_legacy_key() -> Result<Vec<u8>> {
+        passwords::generic_password(Self::legacy_keychain_options())
+            .context("Encryption key not found in Keychain. Please enable encryption first.")
+    }
+
     #[cfg(not(target_os = "macos"))]
-    fn get_key_from_keychain() -> Result<Vec<u8>> {
+    pub(crate) fn get_key_from_keychain() -> Result<Vec<u8>> {
         anyhow::bail!("macOS Keychain not available on this platform");
     }
 
-    /// Delete encryption key from Keychain
+    /// Delete a Keychain item, treating a missing item as success
     #[cfg(target_os = "macos")]
-    pub fn delete_key_from_keychain() -> Result<()> {
-        let keychain = SecKeychain::default()?;
-
-        // Find and delete the password
-        if let Ok((_, item)) = keychain.find_generic_password(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT) {
-            item.delete();
-            log::info!("Encryption key deleted from Keychain");
+    fn delete_keychain_item(options: PasswordOptions) -> Result<()> {
+        match passwords::delete_generic_password_options(options) {
+            Ok(()) => Ok(()),
+            // Without the entitlement there can't be a data protection item to delete
+            Err(e) if e.code() == errSecItemNotFound || e.code() == ERR_SEC_MISSING_ENTITLEMENT => {
+                Ok(())
+            }
+            Err(e) => Err(e).context("Failed to delete key from Keychain"),
         }
+    }
+
+    /// Delete encryption key from Keychain, including any legacy item
+    #[cfg(target_os = "macos")]
+    pub fn delete_key_from_keychain() -> Result<()> {
+        Self::lock();
+        Self::delete_keychain_item(Self::keychain_options())?;
+        Self::delete_keychain_item(Self::legacy_keychain_options())?;
+        log::info!("Encryption key deleted from Keychain");
 
         Ok(())
     }
@@ -189,67 +517,119 @@ impl EncryptionManager {
         Ok(()) // No-op on non-macOS
     }
 
-    /// Encrypt data with AES-256-GCM
+    /// Export the master key wrapped with a passphrase, for moving to another device.
+    /// Returns a base64 blob safe to copy/paste.
+    pub fn export_key(passphrase: &str) -> Result<String> {
+        let key = Self::cached_key()?;
+        let wrapped = wrap_key(&key, passphrase)?;
+        let json = serde_json::to_vec(&wrapped).context("Failed to serialize exported key")?;
+        Ok(BASE64.encode(json))
+    }
+
+    /// Import a master key exported with [`EncryptionManager::export_key`],
+    /// replacing any key currently stored in the Keychain
+    pub fn import_key(passphrase: &str, blob: &str) -> Result<()> {
+        let json = BASE64
+            .decode(blob.trim())
+            .context("Exported key is not valid base64")?;
+        let wrapped: WrappedKey =
+            serde_json::from_slice(&json).context("Failed to parse exported key")?;
+        let key = unwrap_key(&wrapped, passphrase)?;
+        Self::store_key_in_keychain(&key)
+    }
+
+    /// Encrypt data with the configured cipher, without binding it to a location
     pub fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedData> {
+        self.encrypt_with(plaintext, None)
+    }
+
+    /// Encrypt data bound to `location` (a repo-relative path) via AEAD
+    /// associated data, so it fails to decrypt anywhere else
+    pub fn encrypt_bound(&self, plaintext: &[u8], location: &str) -> Result<EncryptedData> {
+        self.encrypt_with(plaintext, Some(location))
+    }
+
+    fn encrypt_with(&self, plaintext: &[u8], location: Option<&str>) -> Result<EncryptedData> {
         if !self.enabled {
             anyhow::bail!("Encryption is not enabled");
         }
 
-        // Get encryption key from Keychain (triggers Touch ID)
-        let key_bytes = Self::get_key_from_keychain()?;
+        let aad = location.map(|_| AadDescriptor::repo_path());
+        let aad_bytes = match (&aad, location) {
+            (Some(descriptor), Some(location)) => descriptor.build(location)?,
+            _ => Vec::new(),
+        };
 
-        // Create cipher
-        let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-            .map_err(|e| anyhow::anyhow!("Failed to create cipher: {e:?}"))?;
+        // Get encryption key (triggers Touch ID unless cached)
+        let key_bytes = self.key()?;
 
         // Generate random nonce
-        let mut nonce_bytes = [0u8; NONCE_SIZE];
+        let mut nonce_bytes = vec![0u8; self.cipher.nonce_size()];
         OsRng.fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
 
         // Encrypt
-        let ciphertext = cipher
-            .encrypt(nonce, plaintext)
-            .map_err(|e| anyhow::anyhow!("Encryption failed: {e}"))?;
+        let ciphertext = metrics::time(metrics::ENCRYPT, || {
+            self.cipher
+                .seal(&key_bytes, &nonce_bytes, plaintext, &aad_bytes)
+        })?;
 
         Ok(EncryptedData {
             version: "1".to_string(),
             encrypted: true,
-            algorithm: "AES-256-GCM".to_string(),
-            nonce: nonce_bytes.to_vec(),
+            algorithm: self.cipher.name().to_string(),
+            nonce: nonce_bytes,
             ciphertext,
+            aad,
         })
     }
 
-    /// Decrypt data with AES-256-GCM
+    /// Decrypt data that isn't bound to a location
     pub fn decrypt(&self, encrypted: &EncryptedData) -> Result<Vec<u8>> {
+        self.decrypt_with(encrypted, None)
+    }
+
+    /// Decrypt data, checking it was encrypted for `location`.
+    /// Data written before location binding (no AAD header) is still accepted.
+    pub fn decrypt_bound(&self, encrypted: &EncryptedData, location: &str) -> Result<Vec<u8>> {
+        self.decrypt_with(encrypted, Some(location))
+    }
+
+    fn decrypt_with(&self, encrypted: &EncryptedData, location: Option<&str>) -> Result<Vec<u8>> {
         if !encrypted.encrypted {
             anyhow::bail!("Data is not encrypted");
         }
 
-        if encrypted.algorithm != "AES-256-GCM" {
-            anyhow::bail!("Unsupported encryption algorithm: {}", encrypted.algorithm);
-        }
-
-        // Get encryption key from Keychain (triggers Touch ID)
-        let key_bytes = Self::get_key_from_keychain()?;
-
-        // Create cipher
-        let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-            .map_err(|e| anyhow::anyhow!("Failed to create cipher: {e:?}"))?;
+        let cipher = Cipher::from_name(&encrypted.algorithm)?;
 
         // Get nonce
-        if encrypted.nonce.len() != NONCE_SIZE {
+        if encrypted.nonce.len() != cipher.nonce_size() {
             anyhow::bail!("Invalid nonce size");
         }
-        let nonce = Nonce::from_slice(&encrypted.nonce);
 
-        // Decrypt
-        let plaintext = cipher
-            .decrypt(nonce, encrypted.ciphertext.as_ref())
-            .map_err(|e| anyhow::anyhow!("Decryption failed: {e}"))?;
+        let aad_bytes = match (&encrypted.aad, location) {
+            (Some(descriptor), Some(location)) => descriptor.build(location)?,
+            (Some(_), None) => anyhow::bail!("Encrypted data is bound to a location"),
+            (None, _) => Vec::new(),
+        };
+
+        // Get encryption key (triggers Touch ID unless cached)
+        let key_bytes = self.key()?;
 
-        Ok(plaintext)
+        // Decrypt
+        metrics::time(metrics::DECRYPT, || {
+            cipher.open(
+                &key_bytes,
+                &encrypted.nonce,
+                &encrypted.ciphertext,
+                &aad_bytes,
+            )
+        })
+        .map_err(|e| match location.filter(|_| encrypted.aad.is_some()) {
+            Some(location) => e.context(format!(
+                "Data was not encrypted for {location}; it may have been moved or tampered with"
+            )),
+            None => e,
+        })
     }
 
     /// Read encrypted file
@@ -259,23 +639,168 @@ impl EncryptionManager {
         let encrypted: EncryptedData =
             serde_json::from_str(&content).context("Failed to parse encrypted file")?;
 
-        self.decrypt(&encrypted)
+        self.decrypt_bound(&encrypted, &repo_relative_path(path.as_ref()))
     }
 
     /// Write encrypted file
     pub fn write_encrypted_file<P: AsRef<Path>>(&self, path: P, data: &[u8]) -> Result<()> {
-        let encrypted = self.encrypt(data)?;
+        let encrypted = self.encrypt_bound(data, &repo_relative_path(path.as_ref()))?;
 
         let json = serde_json::to_string_pretty(&encrypted)
             .context("Failed to serialize encrypted data")?;
 
-        // Atomic write
-        let temp_path = path.as_ref().with_extension("tmp");
-        fs::write(&temp_path, json).context("Failed to write temp file")?;
-        fs::rename(&temp_path, path.as_ref()).context("Failed to rename temp file to target")?;
+        crate::storage::write_atomic(path.as_ref(), json.as_bytes())
+    }
+}
 
-        Ok(())
+/// Path of `path` relative to the root of the git repository containing it,
+/// with `/` separators. Falls back to the file name outside a repository.
+pub fn repo_relative_path(path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let Some(parent) = path.parent().and_then(|p| p.canonicalize().ok()) else {
+        return file_name;
+    };
+    let absolute = parent.join(&file_name);
+
+    parent
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .and_then(|root| absolute.strip_prefix(root).ok())
+        .map_or(file_name, |relative| {
+            relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+}
+
+/// Master key encrypted with a key-encryption key derived from a passphrase
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WrappedKey {
+    version: String,
+    kdf: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    #[serde(with = "base64_serde")]
+    salt: Vec<u8>,
+    #[serde(with = "base64_serde")]
+    nonce: Vec<u8>,
+    #[serde(with = "base64_serde")]
+    ciphertext: Vec<u8>,
+}
+
+/// Derive a key-encryption key from a passphrase with Argon2id
+fn derive_kek(
+    passphrase: &str,
+    salt: &[u8],
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> Result<[u8; KEY_SIZE]> {
+    let params = argon2::Params::new(memory_kib, iterations, parallelism, Some(KEY_SIZE))
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {e}"))?;
+    let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+
+    let mut kek = [0u8; KEY_SIZE];
+    argon2
+        .hash_password_into(passphrase.as_bytes(), salt, &mut kek)
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {e}"))?;
+    Ok(kek)
+}
+
+/// Wrap a master key with a passphrase
+pub fn wrap_key(key: &[u8], passphrase: &str) -> Result<WrappedKey> {
+    seal_with_passphrase(key, passphrase)
+}
+
+/// Encrypt any data with a passphrase, the same way [`wrap_key`] wraps a key
+pub fn seal_with_passphrase(plaintext: &[u8], passphrase: &str) -> Result<WrappedKey> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LENGTH {
+        anyhow::bail!("Passphrase must be at least {MIN_PASSPHRASE_LENGTH} characters");
     }
+
+    let mut salt = [0u8; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    let kek = derive_kek(
+        passphrase,
+        &salt,
+        ARGON2_MEMORY_KIB,
+        ARGON2_ITERATIONS,
+        ARGON2_PARALLELISM,
+    )?;
+
+    let cipher = Aes256Gcm::new_from_slice(&kek)
+        .map_err(|e| anyhow::anyhow!("Failed to create cipher: {e:?}"))?;
+    let mut nonce_bytes = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), plaintext)
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {e}"))?;
+
+    Ok(WrappedKey {
+        version: "1".to_string(),
+        kdf: "argon2id".to_string(),
+        memory_kib: ARGON2_MEMORY_KIB,
+        iterations: ARGON2_ITERATIONS,
+        parallelism: ARGON2_PARALLELISM,
+        salt: salt.to_vec(),
+        nonce: nonce_bytes.to_vec(),
+        ciphertext,
+    })
+}
+
+/// Recover a master key wrapped with [`wrap_key`]
+pub fn unwrap_key(wrapped: &WrappedKey, passphrase: &str) -> Result<Vec<u8>> {
+    let key = open_with_passphrase(wrapped, passphrase)
+        .map_err(|e| anyhow::anyhow!("{e} (key export)"))?;
+    if key.len() != KEY_SIZE {
+        anyhow::bail!("Invalid encryption key size");
+    }
+    Ok(key)
+}
+
+/// Decrypt data sealed with [`seal_with_passphrase`]
+pub fn open_with_passphrase(wrapped: &WrappedKey, passphrase: &str) -> Result<Vec<u8>> {
+    if wrapped.kdf != "argon2id" {
+        anyhow::bail!("Unsupported key derivation function: {}", wrapped.kdf);
+    }
+    if wrapped.nonce.len() != NONCE_SIZE {
+        anyhow::bail!("Invalid nonce size");
+    }
+    if wrapped.memory_kib > MAX_ARGON2_MEMORY_KIB
+        || wrapped.iterations > MAX_ARGON2_ITERATIONS
+        || wrapped.parallelism > MAX_ARGON2_PARALLELISM
+    {
+        anyhow::bail!(
+            "Argon2 parameters exceed limits (memory {} KiB, {} iterations, parallelism {})",
+            wrapped.memory_kib,
+            wrapped.iterations,
+            wrapped.parallelism
+        );
+    }
+
+    let kek = derive_kek(
+        passphrase,
+        &wrapped.salt,
+        wrapped.memory_kib,
+        wrapped.iterations,
+        wrapped.parallelism,
+    )?;
+    let cipher = Aes256Gcm::new_from_slice(&kek)
+        .map_err(|e| anyhow::anyhow!("Failed to create cipher: {e:?}"))?;
+
+    // AES-GCM authentication fails on a wrong passphrase, so this doubles as the check
+    cipher
+        .decrypt(
+            Nonce::from_slice(&wrapped.nonce),
+            wrapped.ciphertext.as_ref(),
+        )
+        .map_err(|_| anyhow::anyhow!("Incorrect passphrase or corrupted data"))
 }
 
 /// Check if a file is encrypted
@@ -305,6 +830,7 @@ mod tests {
             algorithm: "AES-256-GCM".to_string(),
             nonce: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
             ciphertext: vec![1, 2, 3, 4, 5],
+            aad: None,
         };
 
         let json = serde_json::to_string(&data).unwrap();
@@ -352,6 +878,7 @@ mod tests {
             algorithm: "AES-256-GCM".to_string(),
             nonce: vec![1, 2, 3], // Invalid: only 3 bytes instead of 12
             ciphertext: vec![1, 2, 3, 4, 5],
+            aad: None,
         };
 
         let result = manager.decrypt(&encrypted);
@@ -369,6 +896,7 @@ mod tests {
             algorithm: "AES-128-CBC".to_string(),
             nonce: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
             ciphertext: vec![1, 2, 3, 4, 5],
+            aad: None,
         };
 
         let result = manager.decrypt(&encrypted);
@@ -390,6 +918,7 @@ mod tests {
             algorithm: "AES-256-GCM".to_string(),
             nonce: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
             ciphertext: vec![1, 2, 3, 4, 5],
+            aad: None,
         };
 
         let result = manager.decrypt(&encrypted);
@@ -424,6 +953,7 @@ mod tests {
             algorithm: "AES-256-GCM".to_string(),
             nonce: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
             ciphertext: vec![1, 2, 3, 4, 5],
+            aad: None,
         };
 
         let json = serde_json::to_string(&encrypted_data).unwrap();
@@ -459,6 +989,7 @@ mod tests {
             algorithm: "AES-256-GCM".to_string(),
             nonce: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
             ciphertext: vec![255, 254, 253, 252, 251],
+            aad: None,
         };
 
         // Serialize to JSON
@@ -476,6 +1007,160 @@ mod tests {
         assert_eq!(parsed.ciphertext, data.ciphertext);
     }
 
+    #[test]
+    fn test_wrap_unwrap_key_roundtrip() {
+        let key = [7u8; KEY_SIZE];
+        let wrapped = wrap_key(&key, "correct horse battery staple").unwrap();
+
+        let json = serde_json::to_string(&wrapped).unwrap();
+        let parsed: WrappedKey = serde_json::from_str(&json).unwrap();
+
+        let unwrapped = unwrap_key(&parsed, "correct horse battery staple").unwrap();
+        assert_eq!(unwrapped, key);
+    }
+
+    #[test]
+    fn test_unwrap_key_wrong_passphrase() {
+        let wrapped = wrap_key(&[7u8; KEY_SIZE], "correct horse battery staple").unwrap();
+
+        let result = unwrap_key(&wrapped, "incorrect horse battery staple");
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Incorrect passphrase"));
+    }
+
+    #[test]
+    fn test_unwrap_key_rejects_excessive_argon2_params() {
+        let mut wrapped = wrap_key(&[7u8; KEY_SIZE], "correct horse battery staple").unwrap();
+        wrapped.memory_kib = 4 * 1024 * 1024;
+
+        let result = unwrap_key(&wrapped, "correct horse battery staple");
+        assert!(result.unwrap_err().to_string().contains("exceed limits"));
+
+        let mut wrapped = wrap_key(&[7u8; KEY_SIZE], "correct horse battery staple").unwrap();
+        wrapped.iterations = u32::MAX;
+        assert!(unwrap_key(&wrapped, "correct horse battery staple").is_err());
+    }
+
+    #[test]
+    fn test_wrap_key_rejects_short_passphrase() {
+        let result = wrap_key(&[7u8; KEY_SIZE], "short");
+        assert!(result.unwrap_err().to_string().contains("at least"));
+    }
+
+    #[test]
+    fn test_import_key_invalid_blob() {
+        let result = EncryptionManager::import_key("correct horse battery staple", "%%%");
+        assert!(result.unwrap_err().to_string().contains("base64"));
+    }
+
+    #[test]
+    fn test_key_cache_expires_after_ttl() {
+        let mut cache = KeyCache::new(Duration::from_mins(1));
+        let start = Instant::now();
+        cache.insert(&[7u8; KEY_SIZE], start);
+
+        let key = cache.get(start + Duration::from_secs(59)).unwrap();
+        assert_eq!(key.as_slice(), &[7u8; KEY_SIZE]);
+
+        assert!(cache.get(start + Duration::from_mins(1)).is_none());
+        assert!(cache.entry.is_none());
+    }
+
+    #[test]
+    fn test_key_cache_zero_ttl_disables_caching() {
+        let mut cache = KeyCache::new(Duration::from_mins(1));
+        let now = Instant::now();
+        cache.insert(&[7u8; KEY_SIZE], now);
+
+        cache.set_ttl(Duration::ZERO);
+        assert!(cache.get(now).is_none());
+
+        cache.insert(&[7u8; KEY_SIZE], now);
+        assert!(cache.get(now).is_none());
+    }
+
+    #[test]
+    fn test_key_cache_clear() {
+        let mut cache = KeyCache::new(DEFAULT_KEY_CACHE_TTL);
+        let now = Instant::now();
+        cache.insert(&[7u8; KEY_SIZE], now);
+        cache.clear();
+        assert!(cache.get(now).is_none());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_with_each_cipher() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            let manager = EncryptionManager::with_key(&[9u8; KEY_SIZE]).with_cipher(cipher);
+            let encrypted = manager.encrypt(b"bookmarks").unwrap();
+            assert_eq!(encrypted.algorithm(), cipher.name());
+            assert_eq!(encrypted.nonce.len(), cipher.nonce_size());
+
+            // Decryption follows the recorded algorithm, not the manager's cipher
+            let other = EncryptionManager::with_key(&[9u8; KEY_SIZE]);
+            assert_eq!(other.decrypt(&encrypted).unwrap(), b"bookmarks");
+        }
+    }
+
+    #[test]
+    fn test_cipher_names_roundtrip() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            assert_eq!(Cipher::from_name(cipher.name()).unwrap(), cipher);
+        }
+        assert!(Cipher::from_name("AES-128-CBC").is_err());
+        assert_eq!(
+            serde_json::to_value(Cipher::XChaCha20Poly1305).unwrap(),
+            "xchacha20-poly1305"
+        );
+    }
+
+    #[test]
+    fn test_bound_data_only_decrypts_at_its_location() {
+        let manager = EncryptionManager::with_key(&[9u8; KEY_SIZE]);
+        let encrypted = manager
+            .encrypt_bound(b"bookmarks", "bookmarks.json")
+            .unwrap();
+        assert!(encrypted.is_bound());
+
+        assert_eq!(
+            manager.decrypt_bound(&encrypted, "bookmarks.json").unwrap(),
+            b"bookmarks"
+        );
+        let err = manager.decrypt_bound(&encrypted, "other.json").unwrap_err();
+        assert!(err.to_string().contains("moved or tampered"));
+        assert!(manager.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_unbound_data_still_decrypts() {
+        let manager = EncryptionManager::with_key(&[9u8; KEY_SIZE]);
+        let encrypted = manager.encrypt(b"legacy").unwrap();
+        assert!(!encrypted.is_bound());
+        assert_eq!(
+            manager.decrypt_bound(&encrypted, "bookmarks.json").unwrap(),
+            b"legacy"
+        );
+    }
+
+    #[test]
+    fn test_encrypted_file_bound_to_repo_path() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join(".git")).unwrap();
+        fs::create_dir(temp_dir.path().join("nested")).unwrap();
+        let original = temp_dir.path().join("nested").join("bookmarks.json");
+        assert_eq!(repo_relative_path(&original), "nested/bookmarks.json");
+
+        let manager = EncryptionManager::with_key(&[9u8; KEY_SIZE]);
+        manager.write_encrypted_file(&original, b"data").unwrap();
+        assert_eq!(manager.read_encrypted_file(&original).unwrap(), b"data");
+
+        let moved = temp_dir.path().join("bookmarks.json");
+        fs::rename(&original, &moved).unwrap();
+        assert!(manager.read_encrypted_file(&moved).is_err());
+    }
+
     // Note: Full encryption tests require macOS Keychain access
     // and would trigger Touch ID prompts, so they're excluded from
     // automated tests. Manual testing required on macOS.
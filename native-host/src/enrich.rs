@@ -0,0 +1,454 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use reqwest::header::{CONTENT_LANGUAGE, CONTENT_TYPE};
+use reqwest::Client;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::storage::{BookmarksData, ContentKind, Enrichment, Resource};
+use crate::suggest;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_CONCURRENT_FETCHES: usize = 8;
+/// Most of a page read; the rest is left unread and the word count is
+/// scaled up from the share that was
+const MAX_BODY_BYTES: usize = 512 * 1024;
+/// Pages with at least this many words, and no other kind, are articles
+const ARTICLE_MIN_WORDS: u32 = 300;
+const WORDS_PER_MINUTE: u32 = 230;
+
+/// What one fetch found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub enrichment: Enrichment,
+    /// From [`suggest::keywords`], for suggesting tags
+    pub keywords: Vec<String>,
+}
+
+/// What [`apply`] changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Applied {
+    pub enriched: usize,
+    /// Bookmarks whose suggested tags changed
+    pub suggested: usize,
+}
+
+/// Bookmarks worth enriching: http(s) ones not enriched yet, or all of them
+/// with `refresh`
+pub fn targets(data: &BookmarksData, refresh: bool) -> Vec<(String, String)> {
+    data.get_bookmarks()
+        .into_iter()
+        .filter_map(|resource| match resource {
+            Resource::Bookmark { id, attributes, .. }
+                if refresh || attributes.enrichment.is_none() =>
+            {
+                Some((id.clone(), attributes.url.clone()))
+            }
+            _ => None,
+        })
+        .filter(|(_, url)| {
+            url::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        })
+        .collect()
+}
+
+/// Fetch the start of a page and describe it
+pub async fn fetch(client: &Client, url: &str) -> Result<Page> {
+    let mut response = client
+        .get(url)
+        .header("User-Agent", "WebTags")
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await
+        .context("Failed to fetch page")?
+        .error_for_status()
+        .context("Page answered with an error")?;
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let mime_type = header(CONTENT_TYPE).map(|value| {
+        value
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    });
+    let content_language = header(CONTENT_LANGUAGE);
+    let total_length = response.content_length();
+
+    let mut body = Vec::new();
+    if mime_type.as_deref().is_none_or(is_text) {
+        while body.len() < MAX_BODY_BYTES {
+            match response.chunk().await.context("Failed to read page")? {
+                Some(chunk) => body.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+    }
+    let truncated = body.len() >= MAX_BODY_BYTES;
+    body.truncate(MAX_BODY_BYTES);
+
+    let body = String::from_utf8_lossy(&body);
+    let mut enrichment = analyze(
+        mime_type.as_deref(),
+        content_language.as_deref(),
+        &body,
+        Utc::now(),
+    );
+    let keywords = match mime_type.as_deref() {
+        _ if enrichment.word_count.is_none() => Vec::new(),
+        Some("text/html" | "application/xhtml+xml") | None => {
+            suggest::keywords(&html_title(&body).unwrap_or_default(), &visible_text(&body))
+        }
+        Some(_) => suggest::keywords("", &body),
+    };
+    if truncated {
+        // Estimate the rest from the share that was read
+        if let (Some(words), Some(total)) = (enrichment.word_count, total_length) {
+            let scaled = u64::from(words) * total / MAX_BODY_BYTES as u64;
+            enrichment.word_count = Some(u32::try_from(scaled).unwrap_or(u32::MAX));
+            enrichment.reading_minutes = enrichment.word_count.map(reading_minutes);
+        }
+    }
+    Ok(Page {
+        enrichment,
+        keywords,
+    })
+}
+
+fn is_text(mime_type: &str) -> bool {
+    mime_type.starts_with("text/") || mime_type == "application/xhtml+xml"
+}
+
+/// Describe a page from its MIME type, `Content-Language` header, and body
+pub fn analyze(
+    mime_type: Option<&str>,
+    content_language: Option<&str>,
+    body: &str,
+    fetched: DateTime<Utc>,
+) -> Enrichment {
+    let html = mime_type.is_none_or(|mime| mime == "text/html" || mime == "application/xhtml+xml");
+    let text = mime_type.is_none_or(is_text);
+    let word_count = text.then(|| {
+        let words = if html {
+            count_words(&visible_text(body))
+        } else {
+            count_words(body)
+        };
+        u32::try_from(words).unwrap_or(u32::MAX)
+    });
+    let og_type = if html { og_type(body) } else { None };
+
+    let content_type = match (mime_type, og_type.as_deref()) {
+        (Some(mime), _) if mime.starts_with("video/") => ContentKind::Video,
+        (Some(mime), _) if mime.starts_with("audio/") => ContentKind::Audio,
+        (Some(mime), _) if mime.starts_with("image/") => ContentKind::Image,
+        (Some("application/pdf"), _) => ContentKind::Pdf,
+        (_, Some(og)) if og.starts_with("video") => ContentKind::Video,
+        (_, Some(og)) if og.starts_with("music") => ContentKind::Audio,
+        _ if !text => ContentKind::Other,
+        (_, Some("article")) => ContentKind::Article,
+        _ if word_count.unwrap_or(0) >= ARTICLE_MIN_WORDS => ContentKind::Article,
+        _ => ContentKind::Page,
+    };
+
+    let language = html
+        .then(|| html_lang(body))
+        .flatten()
+        .or_else(|| content_language.and_then(|value| value.split(',').find_map(primary_subtag)));
+
+    Enrichment {
+        content_type,
+        mime_type: mime_type
+            .filter(|mime| !mime.is_empty())
+            .map(str::to_string),
+        reading_minutes: word_count.map(reading_minutes),
+        word_count,
+        language,
+        fetched,
+    }
+}
+
+fn reading_minutes(words: u32) -> u32 {
+    words.div_ceil(WORDS_PER_MINUTE)
+}
+
+fn count_words(text: &str) -> usize {
+    text.split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count()
+}
+
+/// Text a reader would see: scripts, styles, and tags removed
+fn visible_text(html: &str) -> String {
+    static HIDDEN: OnceLock<Regex> = OnceLock::new();
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    let hidden = HIDDEN.get_or_init(|| {
+        Regex::new(r"(?is)<(script|style|noscript|template|svg|head)\b.*?</(script|style|noscript|template|svg|head)\s*>|<!--.*?-->")
+            .expect("valid regex")
+    });
+    let tag = TAG.get_or_init(|| Regex::new(r"(?s)<[^>]*>").expect("valid regex"));
+    let without_hidden = hidden.replace_all(html, " ");
+    tag.replace_all(&without_hidden, " ").into_owned()
+}
+
+/// `content` of `<meta property="og:type">`, lowercased
+fn og_type(html: &str) -> Option<String> {
+    static META: OnceLock<Regex> = OnceLock::new();
+    static CONTENT: OnceLock<Regex> = OnceLock::new();
+    let meta = META.get_or_init(|| {
+        Regex::new(r#"(?i)<meta\b[^>]*\bproperty\s*=\s*["']og:type["'][^>]*>"#)
+            .expect("valid regex")
+    });
+    let content = CONTENT.get_or_init(|| {
+        Regex::new(r#"(?i)\bcontent\s*=\s*["']([^"']*)["']"#).expect("valid regex")
+    });
+    let tag = meta.find(html)?.as_str();
+    Some(content.captures(tag)?[1].trim().to_ascii_lowercase())
+}
+
+/// Text of `<title>`
+fn html_title(html: &str) -> Option<String> {
+    static TITLE: OnceLock<Regex> = OnceLock::new();
+    let title = TITLE
+        .get_or_init(|| Regex::new(r"(?is)<title\b[^>]*>(.*?)</title\s*>").expect("valid regex"));
+    Some(title.captures(html)?[1].to_string())
+}
+
+/// Primary subtag of `<html lang>`
+fn html_lang(html: &str) -> Option<String> {
+    static LANG: OnceLock<Regex> = OnceLock::new();
+    let lang = LANG.get_or_init(|| {
+        Regex::new(r#"(?i)<html\b[^>]*\blang\s*=\s*["']?([A-Za-z-]+)"#).expect("valid regex")
+    });
+    primary_subtag(&lang.captures(html)?[1])
+}
+
+/// `en` from `en-US`; none for tags that aren't a language, like `*`
+fn primary_subtag(tag: &str) -> Option<String> {
+    let primary = tag
+        .trim()
+        .split(['-', '_', ';'])
+        .next()?
+        .to_ascii_lowercase();
+    (2..=3)
+        .contains(&primary.len())
+        .then_some(primary)
+        .filter(|p| p.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+/// Fetch every target, calling `on_progress(done, total)` as fetches finish.
+/// Returns `(bookmark id, url, page)` for the pages that could be fetched;
+/// the rest are tried again next time.
+pub async fn enrich_all(
+    client: &Client,
+    targets: Vec<(String, String)>,
+    mut on_progress: impl FnMut(usize, usize),
+) -> Vec<(String, String, Page)> {
+    let total = targets.len();
+    let mut pending = targets.into_iter();
+    let mut fetches = JoinSet::new();
+    let mut done = 0;
+    let mut results = Vec::new();
+    loop {
+        while fetches.len() < MAX_CONCURRENT_FETCHES {
+            let Some((id, url)) = pending.next() else {
+                break;
+            };
+            let client = client.clone();
+            fetches.spawn(async move {
+                let page = fetch(&client, &url).await;
+                (id, url, page)
+            });
+        }
+        let Some(finished) = fetches.join_next().await else {
+            break;
+        };
+        done += 1;
+        match finished {
+            Ok((id, url, Ok(page))) => results.push((id, url, page)),
+            Ok((id, _, Err(e))) => log::debug!("Could not enrich {id}: {e:#}"),
+            Err(e) => log::warn!("Enrichment task failed: {e}"),
+        }
+        on_progress(done, total);
+    }
+    results
+}
+
+/// Store `results` on their bookmarks, skipping any deleted or moved to
+/// another URL since the fetch. With `suggest_tags`, each fetched bookmark's
+/// suggested tags are replaced with ones picked from its page.
+pub fn apply(
+    data: &mut BookmarksData,
+    results: Vec<(String, String, Page)>,
+    suggest_tags: bool,
+) -> Applied {
+    let mut applied = Applied::default();
+    for (bookmark_id, url, page) in results {
+        let suggestions = suggest_tags.then(|| {
+            suggest::content_suggestions(
+                data,
+                &bookmark_id,
+                &page.keywords,
+                suggest::CONTENT_SUGGESTIONS,
+            )
+        });
+        let found = data
+            .data_mut()
+            .iter_mut()
+            .find_map(|resource| match resource {
+                Resource::Bookmark { id, attributes, .. }
+                    if *id == bookmark_id && attributes.url == url =>
+                {
+                    Some(attributes)
+                }
+                _ => None,
+            });
+        let Some(attributes) = found else {
+            continue;
+        };
+        // Only `fetched` differs when nothing about the page did
+        let enrichment = page.enrichment;
+        let unchanged = attributes.enrichment.as_ref().is_some_and(|old| {
+            Enrichment {
+                fetched: enrichment.fetched,
+                ..old.clone()
+            } == enrichment
+        });
+        if !unchanged {
+            attributes.enrichment = Some(enrichment);
+            applied.enriched += 1;
+        }
+        if let Some(suggestions) = suggestions {
+            if attributes.suggested_tags != suggestions {
+                attributes.suggested_tags = suggestions;
+                applied.suggested += 1;
+            }
+        }
+    }
+    applied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn article(words: usize) -> String {
+        format!(
+            r#"<!DOCTYPE html><html lang="en-GB"><head><title>Hi</title>
+            <script>var ignored = "not words at all";</script></head>
+            <body><p>{}</p></body></html>"#,
+            "word ".repeat(words)
+        )
+    }
+
+    #[test]
+    fn test_analyze_article() {
+        let now = Utc::now();
+        let long = analyze(Some("text/html"), None, &article(1000), now);
+        assert_eq!(long.content_type, ContentKind::Article);
+        assert_eq!(long.word_count, Some(1000));
+        assert_eq!(long.reading_minutes, Some(5));
+        assert_eq!(long.language.as_deref(), Some("en"));
+
+        let short = analyze(Some("text/html"), Some("fr-CA"), "<p>Bonjour</p>", now);
+        assert_eq!(short.content_type, ContentKind::Page);
+        assert_eq!(short.word_count, Some(1));
+        assert_eq!(short.reading_minutes, Some(1));
+        assert_eq!(short.language.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn test_analyze_kinds() {
+        let now = Utc::now();
+        let video_page = r#"<html><head><meta property="og:type" content="video.other"></head><body>Watch</body></html>"#;
+        assert_eq!(
+            analyze(Some("text/html"), None, video_page, now).content_type,
+            ContentKind::Video
+        );
+        let pdf = analyze(Some("application/pdf"), None, "", now);
+        assert_eq!(pdf.content_type, ContentKind::Pdf);
+        assert_eq!(pdf.word_count, None);
+        assert_eq!(pdf.reading_minutes, None);
+        assert_eq!(
+            analyze(Some("image/png"), None, "", now).content_type,
+            ContentKind::Image
+        );
+        assert_eq!(
+            analyze(Some("application/zip"), None, "", now).content_type,
+            ContentKind::Other
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enrich_all_and_apply() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/long"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(article(2300), "text/html; charset=utf-8"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/gone"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let mut data = BookmarksData::new();
+        let mut ids = Vec::new();
+        for url in [
+            format!("{}/long", server.uri()),
+            format!("{}/gone", server.uri()),
+            "magnet:?xt=urn:btih:abc".to_string(),
+        ] {
+            let bookmark = create_bookmark(url, "x".to_string(), vec![]);
+            ids.push(bookmark.id().to_string());
+            data.add_bookmark(bookmark).unwrap();
+        }
+
+        let targets = targets(&data, false);
+        assert_eq!(targets.len(), 2);
+        let mut progress = Vec::new();
+        let results = enrich_all(&Client::new(), targets, |done, total| {
+            progress.push((done, total));
+        })
+        .await;
+        assert_eq!(progress.last(), Some(&(2, 2)));
+        assert_eq!(results.len(), 1);
+
+        assert_eq!(
+            results[0].2.keywords.first().map(String::as_str),
+            Some("word")
+        );
+        assert_eq!(apply(&mut data, results.clone(), false).enriched, 1);
+        // The same facts again change nothing
+        assert_eq!(apply(&mut data, results.clone(), false), Applied::default());
+        let applied = apply(&mut data, results, true);
+        assert_eq!(applied.suggested, 1);
+        match data.get(&ids[0]) {
+            Some(Resource::Bookmark { attributes, .. }) => {
+                let enrichment = attributes.enrichment.as_ref().unwrap();
+                assert_eq!(enrichment.content_type, ContentKind::Article);
+                assert_eq!(enrichment.mime_type.as_deref(), Some("text/html"));
+                assert_eq!(enrichment.reading_minutes, Some(10));
+                assert_eq!(attributes.suggested_tags, vec!["word"]);
+            }
+            _ => panic!("bookmark missing"),
+        }
+        assert_eq!(super::targets(&data, false).len(), 1);
+        assert_eq!(super::targets(&data, true).len(), 2);
+    }
+}
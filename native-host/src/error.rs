@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Broad class of failure, so the extension can pick a UX without parsing messages
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// Malformed or unsupported native messaging traffic
+    Protocol,
+    /// Request data failed validation
+    Validation,
+    /// Host is not in the right state for the request (e.g. not initialized)
+    State,
+    /// Local file I/O failed
+    Storage,
+    /// Local git operation failed
+    Git,
+    /// Remote operation failed; usually worth retrying
+    Network,
+    /// Missing, invalid, or unstorable credentials
+    Auth,
+    /// Encryption or key management failed
+    Encryption,
+    /// Feature unavailable on this platform
+    Platform,
+    /// Unexpected host-side failure
+    Internal,
+}
+
+/// Stable, machine-readable error codes returned in `Response::Error`.
+///
+/// The serialized codes are part of the protocol: never rename one, only add.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+pub enum HostError {
+    #[serde(rename = "ERR_READ_MESSAGE")]
+    ReadMessage,
+    #[serde(rename = "ERR_PROTOCOL_VERSION")]
+    ProtocolVersion,
+    #[serde(rename = "ERR_CAPABILITY")]
+    Capability,
+    #[serde(rename = "ERR_PAYLOAD")]
+    Payload,
+    #[serde(rename = "ERR_INVALID_PATH")]
+    InvalidPath,
+    #[serde(rename = "ERR_PARSE")]
+    Parse,
+    #[serde(rename = "ERR_VALIDATE")]
+    Validate,
+    #[serde(rename = "ERR_INTEGRITY")]
+    Integrity,
+    #[serde(rename = "ERR_NOT_INITIALIZED")]
+    NotInitialized,
+    #[serde(rename = "ERR_NO_REMOTE")]
+    NoRemote,
+    #[serde(rename = "ERR_LOCKED")]
+    Locked,
+    #[serde(rename = "ERR_READ_FILE")]
+    ReadFile,
+    #[serde(rename = "ERR_WRITE_FILE")]
+    WriteFile,
+    #[serde(rename = "ERR_INDEX")]
+    Index,
+    #[serde(rename = "ERR_ACTIVITY")]
+    Activity,
+    #[serde(rename = "ERR_INIT")]
+    Init,
+    #[serde(rename = "ERR_OPEN_REPO")]
+    OpenRepo,
+    #[serde(rename = "ERR_GIT_ADD")]
+    GitAdd,
+    #[serde(rename = "ERR_GIT_COMMIT")]
+    GitCommit,
+    #[serde(rename = "ERR_DIFF")]
+    Diff,
+    #[serde(rename = "ERR_MAINTENANCE")]
+    Maintenance,
+    #[serde(rename = "ERR_REMOTE")]
+    Remote,
+    #[serde(rename = "ERR_CLONE")]
+    Clone,
+    #[serde(rename = "ERR_GIT_PUSH")]
+    GitPush,
+    #[serde(rename = "ERR_GIT_PULL")]
+    GitPull,
+    #[serde(rename = "ERR_SYNC_CONFLICT")]
+    SyncConflict,
+    #[serde(rename = "ERR_HISTORY_REWRITTEN")]
+    HistoryRewritten,
+    #[serde(rename = "ERR_HOOK")]
+    Hook,
+    #[serde(rename = "ERR_GITHUB_API")]
+    GitHubApi,
+    #[serde(rename = "ERR_OAUTH_START")]
+    OAuthStart,
+    #[serde(rename = "ERR_AUTH_CANCELLED")]
+    AuthCancelled,
+    #[serde(rename = "ERR_NO_TOKEN")]
+    NoToken,
+    #[serde(rename = "ERR_INVALID_TOKEN")]
+    InvalidToken,
+    #[serde(rename = "ERR_VALIDATE_TOKEN")]
+    ValidateToken,
+    #[serde(rename = "ERR_STORE_TOKEN")]
+    StoreToken,
+    #[serde(rename = "ERR_UNTRUSTED_CALLER")]
+    UntrustedCaller,
+    #[serde(rename = "ERR_KEYGEN")]
+    Keygen,
+    #[serde(rename = "ERR_CHECK_ENCRYPTION")]
+    CheckEncryption,
+    #[serde(rename = "ERR_READ_FOR_ENCRYPT")]
+    ReadForEncrypt,
+    #[serde(rename = "ERR_ENCRYPT")]
+    Encrypt,
+    #[serde(rename = "ERR_DECRYPT")]
+    Decrypt,
+    #[serde(rename = "ERR_WRITE_DECRYPT")]
+    WriteDecrypt,
+    #[serde(rename = "ERR_KEY_EXPORT")]
+    KeyExport,
+    #[serde(rename = "ERR_KEY_IMPORT")]
+    KeyImport,
+    #[serde(rename = "ERR_RECOVERY")]
+    Recovery,
+    #[serde(rename = "ERR_BACKUP")]
+    Backup,
+    #[serde(rename = "ERR_BUNDLE")]
+    Bundle,
+    #[serde(rename = "ERR_PLATFORM_NOT_SUPPORTED")]
+    PlatformNotSupported,
+    #[serde(rename = "ERR_SERIALIZE")]
+    Serialize,
+}
+
+impl HostError {
+    /// The stable wire code (e.g. `ERR_GIT_PUSH`)
+    pub fn code(self) -> &'static str {
+        match self {
+            HostError::ReadMessage => "ERR_READ_MESSAGE",
+            HostError::ProtocolVersion => "ERR_PROTOCOL_VERSION",
+            HostError::Capability => "ERR_CAPABILITY",
+            HostError::Payload => "ERR_PAYLOAD",
+            HostError::InvalidPath => "ERR_INVALID_PATH",
+            HostError::Parse => "ERR_PARSE",
+            HostError::Validate => "ERR_VALIDATE",
+            HostError::Integrity => "ERR_INTEGRITY",
+            HostError::NotInitialized => "ERR_NOT_INITIALIZED",
+            HostError::NoRemote => "ERR_NO_REMOTE",
+            HostError::Locked => "ERR_LOCKED",
+            HostError::ReadFile => "ERR_READ_FILE",
+            HostError::WriteFile => "ERR_WRITE_FILE",
+            HostError::Index => "ERR_INDEX",
+            HostError::Activity => "ERR_ACTIVITY",
+            HostError::Init => "ERR_INIT",
+            HostError::OpenRepo => "ERR_OPEN_REPO",
+            HostError::GitAdd => "ERR_GIT_ADD",
+            HostError::GitCommit => "ERR_GIT_COMMIT",
+            HostError::Diff => "ERR_DIFF",
+            HostError::Maintenance => "ERR_MAINTENANCE",
+            HostError::Remote => "ERR_REMOTE",
+            HostError::Clone => "ERR_CLONE",
+            HostError::GitPush => "ERR_GIT_PUSH",
+            HostError::GitPull => "ERR_GIT_PULL",
+            HostError::SyncConflict => "ERR_SYNC_CONFLICT",
+            HostError::HistoryRewritten => "ERR_HISTORY_REWRITTEN",
+            HostError::Hook => "ERR_HOOK",
+            HostError::GitHubApi => "ERR_GITHUB_API",
+            HostError::OAuthStart => "ERR_OAUTH_START",
+            HostError::AuthCancelled => "ERR_AUTH_CANCELLED",
+            HostError::NoToken => "ERR_NO_TOKEN",
+            HostError::InvalidToken => "ERR_INVALID_TOKEN",
+            HostError::ValidateToken => "ERR_VALIDATE_TOKEN",
+            HostError::StoreToken => "ERR_STORE_TOKEN",
+            HostError::UntrustedCaller => "ERR_UNTRUSTED_CALLER",
+            HostError::Keygen => "ERR_KEYGEN",
+            HostError::CheckEncryption => "ERR_CHECK_ENCRYPTION",
+            HostError::ReadForEncrypt => "ERR_READ_FOR_ENCRYPT",
+            HostError::Encrypt => "ERR_ENCRYPT",
+            HostError::Decrypt => "ERR_DECRYPT",
+            HostError::WriteDecrypt => "ERR_WRITE_DECRYPT",
+            HostError::KeyExport => "ERR_KEY_EXPORT",
+            HostError::KeyImport => "ERR_KEY_IMPORT",
+            HostError::Recovery => "ERR_RECOVERY",
+            HostError::Backup => "ERR_BACKUP",
+            HostError::Bundle => "ERR_BUNDLE",
+            HostError::PlatformNotSupported => "ERR_PLATFORM_NOT_SUPPORTED",
+            HostError::Serialize => "ERR_SERIALIZE",
+        }
+    }
+
+    /// Which broad class of failure this is
+    pub fn category(self) -> ErrorCategory {
+        match self {
+            HostError::ReadMessage
+            | HostError::ProtocolVersion
+            | HostError::Capability
+            | HostError::Payload => ErrorCategory::Protocol,
+            HostError::InvalidPath
+            | HostError::Parse
+            | HostError::Validate
+            | HostError::Integrity => ErrorCategory::Validation,
+            HostError::NotInitialized | HostError::NoRemote | HostError::Locked => {
+                ErrorCategory::State
+            }
+            HostError::ReadFile
+            | HostError::WriteFile
+            | HostError::Index
+            | HostError::Activity
+            | HostError::Backup
+            | HostError::ReadForEncrypt
+            | HostError::WriteDecrypt => ErrorCategory::Storage,
+            HostError::Init
+            | HostError::OpenRepo
+            | HostError::GitAdd
+            | HostError::GitCommit
+            | HostError::Diff
+            | HostError::Maintenance
+            | HostError::Bundle
+            | HostError::HistoryRewritten
+            | HostError::Hook
+            | HostError::Remote => ErrorCategory::Git,
+            HostError::Clone
+            | HostError::GitPush
+            | HostError::GitPull
+            | HostError::SyncConflict
+            | HostError::GitHubApi => ErrorCategory::Network,
+            HostError::OAuthStart
+            | HostError::AuthCancelled
+            | HostError::NoToken
+            | HostError::InvalidToken
+            | HostError::ValidateToken
+            | HostError::StoreToken
+            | HostError::UntrustedCaller => ErrorCategory::Auth,
+            HostError::Keygen
+            | HostError::CheckEncryption
+            | HostError::Encrypt
+            | HostError::Decrypt
+            | HostError::KeyExport
+            | HostError::KeyImport
+            | HostError::Recovery => ErrorCategory::Encryption,
+            HostError::PlatformNotSupported => ErrorCategory::Platform,
+            HostError::Serialize => ErrorCategory::Internal,
+        }
+    }
+
+    /// Whether repeating the same request may succeed without user changes
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            HostError::Locked
+                | HostError::Clone
+                | HostError::GitPush
+                | HostError::GitPull
+                // Syncing again pulls the changes that caused it
+                | HostError::SyncConflict
+                | HostError::GitHubApi
+                | HostError::OAuthStart
+                | HostError::ValidateToken
+                // Touch ID prompts can be dismissed and retried
+                | HostError::Encrypt
+                | HostError::Decrypt
+        )
+    }
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_matches_serialization() {
+        for error in [
+            HostError::ReadMessage,
+            HostError::NotInitialized,
+            HostError::GitPush,
+            HostError::OAuthStart,
+            HostError::PlatformNotSupported,
+        ] {
+            let value = serde_json::to_value(error).unwrap();
+            assert_eq!(value, error.code());
+            let parsed: HostError = serde_json::from_value(value).unwrap();
+            assert_eq!(parsed, error);
+        }
+    }
+
+    #[test]
+    fn test_category_and_retryable() {
+        assert_eq!(HostError::GitPush.category(), ErrorCategory::Network);
+        assert!(HostError::GitPush.retryable());
+        assert_eq!(HostError::Validate.category(), ErrorCategory::Validation);
+        assert!(!HostError::Validate.retryable());
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::bulk;
+use crate::git::GitRepo;
+use crate::storage::{BookmarksData, Resource};
+
+/// Tag given to expired bookmarks with [`Action::Tag`]
+pub const EXPIRED_TAG: &str = "expired";
+
+/// What happens to expired bookmarks, kept in the repository's local git config
+const ACTION_KEY: &str = "webtags.expiredAction";
+
+/// How long the host waits between looking for expired bookmarks on its own
+pub const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_hours(1);
+
+/// What becomes of a bookmark once its `expires_at` has passed
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Tag it `expired` and leave it where it is
+    #[default]
+    Tag,
+    /// Delete it, in a commit of its own that `Undo` reverts
+    Delete,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Tag => "tag",
+            Action::Delete => "delete",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tag" => Some(Action::Tag),
+            "delete" => Some(Action::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Expiry settings for one repository
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub action: Action,
+}
+
+/// Settings to change with [`Settings::update`]; `None` leaves one as it is
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Update {
+    #[serde(default)]
+    pub action: Option<Action>,
+}
+
+impl Settings {
+    pub fn load(repo: &GitRepo) -> Self {
+        Self {
+            action: repo
+                .local_config(ACTION_KEY)
+                .and_then(|name| Action::from_name(&name))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Change the settings given, leaving the others as they are
+    pub fn update(repo: &GitRepo, update: Update) -> Result<Self> {
+        if let Some(action) = update.action {
+            let name = (action != Action::default()).then_some(action.name());
+            repo.set_local_config(ACTION_KEY, name)?;
+        }
+        Ok(Self::load(repo))
+    }
+}
+
+/// A bookmark past, or coming up to, its expiry
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Expired {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Bookmarks whose `expires_at` is before `until`, soonest first
+pub fn expiring_before(data: &BookmarksData, until: DateTime<Utc>) -> Vec<Expired> {
+    let mut expiring: Vec<Expired> = data
+        .get_bookmarks()
+        .into_iter()
+        .filter_map(|resource| match resource {
+            Resource::Bookmark { id, attributes, .. } => {
+                let expires_at = attributes.expires_at.filter(|at| *at <= until)?;
+                Some(Expired {
+                    id: id.clone(),
+                    title: attributes.title.clone(),
+                    url: attributes.url.clone(),
+                    expires_at,
+                })
+            }
+            _ => None,
+        })
+        .collect();
+    expiring.sort_by_key(|bookmark| bookmark.expires_at);
+    expiring
+}
+
+/// Apply `action` to every bookmark that expired by `now` and hasn't been
+/// dealt with yet, returning those. `data` is only modified if some were.
+pub fn expire(data: &mut BookmarksData, action: Action, now: DateTime<Utc>) -> Vec<Expired> {
+    let tag_id = bulk::find_top_level_tag(data, EXPIRED_TAG);
+    let mut expired = expiring_before(data, now);
+    if action == Action::Tag {
+        // Already tagged, by an earlier pass or by hand
+        expired.retain(|bookmark| {
+            tag_id
+                .as_deref()
+                .is_none_or(|tag_id| !has_tag(data, &bookmark.id, tag_id))
+        });
+    }
+    if expired.is_empty() {
+        return expired;
+    }
+
+    let ids: HashSet<&str> = expired
+        .iter()
+        .map(|bookmark| bookmark.id.as_str())
+        .collect();
+    match action {
+        Action::Tag => {
+            let tag_id = bulk::top_level_tag(data, EXPIRED_TAG);
+            for resource in data.data_mut() {
+                if let Resource::Bookmark {
+                    id,
+                    attributes,
+                    relationships,
+                    ..
+                } = resource
+                {
+                    if ids.contains(id.as_str()) && bulk::set_tag(relationships, &tag_id, true) {
+                        attributes.modified = Some(now);
+                    }
+                }
+            }
+        }
+        Action::Delete => data.data_mut().retain(|resource| {
+            !matches!(resource, Resource::Bookmark { .. }) || !ids.contains(resource.id())
+        }),
+    }
+    expired
+}
+
+fn has_tag(data: &BookmarksData, bookmark_id: &str, tag_id: &str) -> bool {
+    matches!(
+        data.get(bookmark_id),
+        Some(Resource::Bookmark {
+            relationships: Some(relationships),
+            ..
+        }) if relationships
+            .tags
+            .iter()
+            .flat_map(|tags| &tags.data)
+            .any(|tag| tag.id == tag_id)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+    use chrono::Duration;
+
+    fn sample(now: DateTime<Utc>) -> (BookmarksData, Vec<String>) {
+        let mut data = BookmarksData::new();
+        let mut ids = Vec::new();
+        for (url, expires_at) in [
+            ("https://example.com/sale", Some(now - Duration::days(1))),
+            ("https://example.com/event", Some(now + Duration::days(3))),
+            ("https://example.com/docs", None),
+        ] {
+            let mut bookmark = create_bookmark(url.to_string(), url.to_string(), vec![]);
+            if let Resource::Bookmark { attributes, .. } = &mut bookmark {
+                attributes.expires_at = expires_at;
+            }
+            ids.push(bookmark.id().to_string());
+            data.add_bookmark(bookmark).unwrap();
+        }
+        (data, ids)
+    }
+
+    #[test]
+    fn test_expire_tags_once() {
+        let now = Utc::now();
+        let (mut data, ids) = sample(now);
+
+        let expired = expire(&mut data, Action::Tag, now);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, ids[0]);
+        let tag_id = bulk::find_top_level_tag(&data, EXPIRED_TAG).unwrap();
+        assert!(has_tag(&data, &ids[0], &tag_id));
+        // Already tagged, so a second pass does nothing
+        assert!(expire(&mut data, Action::Tag, now).is_empty());
+
+        let later = now + Duration::days(7);
+        assert_eq!(expire(&mut data, Action::Tag, later).len(), 1);
+        assert_eq!(data.get_bookmarks().len(), 3);
+    }
+
+    #[test]
+    fn test_expire_deletes() {
+        let now = Utc::now();
+        let (mut data, ids) = sample(now);
+        assert_eq!(expire(&mut data, Action::Delete, now).len(), 1);
+        assert!(data.get(&ids[0]).is_none());
+        assert_eq!(data.get_bookmarks().len(), 2);
+        assert!(bulk::find_top_level_tag(&data, EXPIRED_TAG).is_none());
+    }
+
+    #[test]
+    fn test_expiring_before() {
+        let now = Utc::now();
+        let (data, ids) = sample(now);
+        let upcoming = expiring_before(&data, now + Duration::days(7));
+        let upcoming: Vec<&str> = upcoming.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(upcoming, vec![ids[0].as_str(), ids[1].as_str()]);
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = GitRepo::init(temp_dir.path()).unwrap();
+        assert_eq!(Settings::load(&repo).action, Action::Tag);
+        let settings = Settings::update(
+            &repo,
+            Update {
+                action: Some(Action::Delete),
+            },
+        )
+        .unwrap();
+        assert_eq!(settings.action, Action::Delete);
+        assert_eq!(Settings::load(&repo).action, Action::Delete);
+    }
+}
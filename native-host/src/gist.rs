@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::github::{self, is_transient_error};
+use crate::proxy;
+use crate::retry::RetryPolicy;
+use crate::sync::{Conflict, Fetched, RemoteFile, Stored};
+
+const API_BASE: &str = "https://api.github.com";
+
+/// Name of the bookmarks file inside the gist
+pub const GIST_FILE: &str = "bookmarks.json";
+
+#[derive(Debug, Deserialize)]
+struct Gist {
+    id: String,
+    /// A file removed by an edit shows up as `null`
+    #[serde(default)]
+    files: HashMap<String, Option<GistFile>>,
+    /// Newest revision first
+    #[serde(default)]
+    history: Vec<GistRevision>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GistFile {
+    content: Option<String>,
+    /// Large files are cut short in API responses and must be fetched raw
+    #[serde(default)]
+    truncated: bool,
+    raw_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GistRevision {
+    version: String,
+}
+
+impl Gist {
+    fn revision(&self) -> Option<String> {
+        self.history
+            .first()
+            .map(|revision| revision.version.clone())
+    }
+
+    fn file(&self) -> Option<&GistFile> {
+        self.files.get(GIST_FILE).and_then(Option::as_ref)
+    }
+}
+
+/// Keeps bookmarks.json in a secret GitHub gist, using the token from `Auth`.
+///
+/// The gist API has no conditional writes, so the gist's revision stands in
+/// for an `ETag`: a write first checks the revision is still the one last
+/// synced. Two devices writing within the same moment can still race.
+pub struct GistClient {
+    client: Client,
+    api_base: String,
+    token: String,
+    gist_id: String,
+    retry: RetryPolicy,
+}
+
+impl GistClient {
+    pub fn new(token: String, gist_id: String) -> Self {
+        let client = proxy::http_client().unwrap_or_else(|e| {
+            log::warn!("Ignoring proxy configuration: {e:#}");
+            Client::new()
+        });
+        Self {
+            client,
+            api_base: API_BASE.to_string(),
+            token,
+            gist_id,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Talk to a different API server (GitHub Enterprise, or tests)
+    #[must_use]
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Use a different retry policy for requests
+    #[must_use]
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    pub fn gist_id(&self) -> &str {
+        &self.gist_id
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, url)
+            .header("Accept", "application/vnd.github+json")
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("User-Agent", "WebTags")
+    }
+
+    /// Create a secret gist holding `content` and switch to it
+    pub async fn create(&mut self, content: &str) -> Result<String> {
+        let body = serde_json::json!({
+            "description": "WebTags bookmarks",
+            "public": false,
+            "files": { GIST_FILE: { "content": content } },
+        });
+        // Not retried: a timed-out request may still have created the gist
+        let response = github::send(
+            self.request(reqwest::Method::POST, &format!("{}/gists", self.api_base))
+                .json(&body),
+        )
+        .await
+        .context("Failed to create gist")?;
+        let gist: Gist = parse(response).await.context("Failed to create gist")?;
+        self.gist_id.clone_from(&gist.id);
+        Ok(gist.id)
+    }
+
+    /// The gist, or `None` if it was deleted
+    async fn fetch(&self) -> Result<Option<Gist>> {
+        let url = format!("{}/gists/{}", self.api_base, self.gist_id);
+        let response = self
+            .retry
+            .run_async(
+                || github::send(self.request(reqwest::Method::GET, &url)),
+                is_transient_error,
+            )
+            .await
+            .context("Failed to reach GitHub")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        parse(response).await.map(Some)
+    }
+
+    async fn content(&self, file: &GistFile) -> Result<String> {
+        match (&file.content, &file.raw_url) {
+            (Some(content), _) if !file.truncated => Ok(content.clone()),
+            (_, Some(raw_url)) => {
+                let response = self
+                    .retry
+                    .run_async(
+                        || github::send(self.request(reqwest::Method::GET, raw_url)),
+                        is_transient_error,
+                    )
+                    .await
+                    .context("Failed to download bookmarks from gist")?;
+                if !response.status().is_success() {
+                    anyhow::bail!(
+                        "Failed to download bookmarks from gist: {}",
+                        response.status()
+                    );
+                }
+                response
+                    .text()
+                    .await
+                    .context("Failed to download bookmarks from gist")
+            }
+            _ => anyhow::bail!("Gist file has no content"),
+        }
+    }
+}
+
+impl RemoteFile for GistClient {
+    fn name(&self) -> &'static str {
+        "Gist"
+    }
+
+    async fn get(&self, etag: Option<&str>) -> Result<Fetched> {
+        let Some(gist) = self.fetch().await? else {
+            anyhow::bail!("Gist {} not found; it may have been deleted", self.gist_id);
+        };
+        let Some(file) = gist.file() else {
+            return Ok(Fetched::Missing);
+        };
+        let revision = gist.revision();
+        if revision.is_some() && revision.as_deref() == etag {
+            return Ok(Fetched::Unchanged);
+        }
+        Ok(Fetched::Changed {
+            content: self.content(file).await?,
+            etag: revision,
+        })
+    }
+
+    async fn put(&self, content: &str, etag: Option<&str>) -> Result<Stored> {
+        let Some(current) = self.fetch().await? else {
+            anyhow::bail!("Gist {} not found; it may have been deleted", self.gist_id);
+        };
+        let unseen = match etag {
+            Some(etag) => current.revision().as_deref() != Some(etag),
+            None => current.file().is_some(),
+        };
+        if unseen {
+            return Err(Conflict.into());
+        }
+
+        let body = serde_json::json!({ "files": { GIST_FILE: { "content": content } } });
+        let url = format!("{}/gists/{}", self.api_base, self.gist_id);
+        // Not retried: the edit is already checked against a revision that a
+        // timed-out attempt may have moved on
+        let response = github::send(self.request(reqwest::Method::PATCH, &url).json(&body))
+            .await
+            .context("Failed to update gist")?;
+        let gist: Gist = parse(response).await.context("Failed to update gist")?;
+        let revision = gist.revision();
+        Ok(Stored {
+            etag: revision.clone(),
+            version: revision,
+        })
+    }
+}
+
+async fn parse(response: reqwest::Response) -> Result<Gist> {
+    let status = response.status();
+    match status {
+        StatusCode::UNAUTHORIZED => Err(github::TokenRejected.into()),
+        // GitHub answers 404 rather than 403 when the token lacks the gist scope
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => {
+            anyhow::bail!(
+                "GitHub refused the request ({status}); sign in again to grant gist access"
+            )
+        }
+        // Don't include response body in error (may contain sensitive data)
+        status if !status.is_success() => anyhow::bail!("GitHub API error: {status}"),
+        _ => response
+            .json()
+            .await
+            .context("Failed to parse gist response"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn gist(revision: &str, content: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": "abc123",
+            "files": { GIST_FILE: { "content": content, "truncated": false } },
+            "history": [{ "version": revision }, { "version": "older" }],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_create_read_and_update() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/gists"))
+            .and(header("Authorization", "Bearer token"))
+            .and(body_partial_json(serde_json::json!({ "public": false })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(gist("r1", "{}")))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/gists/abc123"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(gist("r1", "{}")))
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/gists/abc123"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(gist("r2", "{ }")))
+            .mount(&server)
+            .await;
+
+        let mut client = GistClient::new("token".to_string(), String::new())
+            .with_api_base(&server.uri())
+            .with_retry_policy(RetryPolicy::none());
+        assert_eq!(client.create("{}").await.unwrap(), "abc123");
+        assert_eq!(client.gist_id(), "abc123");
+
+        assert_eq!(
+            client.get(None).await.unwrap(),
+            Fetched::Changed {
+                content: "{}".to_string(),
+                etag: Some("r1".to_string()),
+            }
+        );
+        assert_eq!(client.get(Some("r1")).await.unwrap(), Fetched::Unchanged);
+
+        let stored = client.put("{ }", Some("r1")).await.unwrap();
+        assert_eq!(stored.etag.as_deref(), Some("r2"));
+        // Someone else's revision, or a file we never pulled, isn't overwritten
+        assert!(client
+            .put("{ }", Some("r0"))
+            .await
+            .unwrap_err()
+            .is::<Conflict>());
+        assert!(client.put("{ }", None).await.unwrap_err().is::<Conflict>());
+    }
+
+    #[tokio::test]
+    async fn test_missing_scope_is_explained() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        let mut client =
+            GistClient::new("token".to_string(), String::new()).with_api_base(&server.uri());
+        let err = client.create("{}").await.unwrap_err();
+        assert!(format!("{err:#}").contains("gist access"));
+    }
+}
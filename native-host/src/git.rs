@@ -1,12 +1,138 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use git2::{FetchOptions, PushOptions, RemoteCallbacks, Repository, Signature};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
+use crate::device;
 use crate::github;
+use crate::metrics;
+use crate::proxy;
+use crate::retry::RetryPolicy;
+use crate::ssh;
 
 pub struct GitRepo {
     repo: Repository,
     path: PathBuf,
+    /// Applied to network operations (clone, fetch, push)
+    retry: RetryPolicy,
+}
+
+/// How far the local branch has diverged from its remote-tracking branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AheadBehind {
+    /// Local commits not yet pushed
+    pub ahead: usize,
+    /// Remote commits not yet pulled
+    pub behind: usize,
+}
+
+/// Local (never committed) git config key holding this clone's device name
+const DEVICE_CONFIG_KEY: &str = "webtags.device";
+
+/// Local git config key turning on per-device branches
+const DEVICE_BRANCHES_KEY: &str = "webtags.deviceBranches";
+
+/// The remote `Sync` pulls from; any others are push-only mirrors
+pub const PRIMARY_REMOTE: &str = "origin";
+
+/// Prefix of the branches devices push to with per-device branches on
+pub const DEVICE_BRANCH_PREFIX: &str = "device/";
+
+/// Where a bundle's branch is fetched to while it's merged
+const BUNDLE_REF: &str = "refs/webtags/bundle";
+
+/// Bits of an index entry's flags holding its merge stage
+const INDEX_STAGE_MASK: u16 = 0x3000;
+
+/// The remote branch no longer contains what was last fetched from it, e.g.
+/// after its history was squashed on GitHub
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "the history of {remote}/{branch} was rewritten on the remote; send resettoremote to start \
+     again from it"
+)]
+pub struct HistoryRewritten {
+    pub remote: String,
+    pub branch: String,
+}
+
+/// Result of pushing to one remote
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RemotePush {
+    pub remote: String,
+    #[serde(flatten)]
+    pub outcome: PushOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum PushOutcome {
+    Pushed,
+    /// Remote unreachable; the push waits in the pending-ops queue
+    Queued {
+        error: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// The remote refused to update a branch: it has commits this clone doesn't,
+/// or a server-side rule (such as branch protection) turned the push down
+#[derive(Debug, thiserror::Error)]
+#[error("Remote rejected {reference}: {reason}")]
+pub struct PushRejected {
+    pub reference: String,
+    pub reason: String,
+}
+
+/// Commits returned by `History` when the client doesn't ask for a number
+pub const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+/// One entry of the commit log
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommitInfo {
+    pub id: String,
+    /// First line of the message
+    pub summary: String,
+    pub author: String,
+    /// From the `Device:` trailer, for commits made by a named device
+    pub device: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+/// History that [`GitRepo::squash_history`] would collapse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SquashPlan {
+    /// Newest commit to fold into the new root commit
+    pub baseline: git2::Oid,
+    /// Commits reachable from the baseline, all replaced by one
+    pub squashed: usize,
+    /// Newer first-parent commits replayed on top
+    pub kept: usize,
+}
+
+/// What a pull brought in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PullSummary {
+    /// Remote commits that were not yet in the local branch
+    pub pulled: usize,
+    /// Conflicting files resolved in favour of the remote version
+    pub conflicts_resolved: usize,
+    /// The commits a merge commit joined, when the histories had diverged
+    pub merge: Option<MergeParents>,
+}
+
+/// The three sides of a merge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeParents {
+    /// Common ancestor, if the histories share one
+    pub base: Option<git2::Oid>,
+    pub ours: git2::Oid,
+    pub theirs: git2::Oid,
 }
 
 impl GitRepo {
@@ -20,11 +146,28 @@ impl GitRepo {
             Repository::init(&path).context("Failed to initialize repository")?
         };
 
-        Ok(Self { repo, path })
+        let mut repo = Self {
+            repo,
+            path,
+            retry: RetryPolicy::default(),
+        };
+        repo.retry = RetryPolicy::load(&repo);
+        Ok(repo)
     }
 
-    /// Clone a repository from a URL
-    pub fn clone<P: AsRef<Path>>(url: &str, path: P) -> Result<Self> {
+    /// Retry policy used for network operations
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Replace the retry policy used for network operations
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Clone a repository from a URL. With a `depth`, only that many commits
+    /// of history are fetched; see [`GitRepo::deepen`] to fetch the rest later.
+    pub fn clone<P: AsRef<Path>>(url: &str, path: P, depth: Option<u32>) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
 
         if let Some(parent) = path.parent() {
@@ -35,17 +178,29 @@ impl GitRepo {
         let mut callbacks = RemoteCallbacks::new();
         callbacks.credentials(Self::create_smart_credentials());
 
+        let proxy_url = proxy::explicit_proxy();
         let mut fetch_options = FetchOptions::new();
         fetch_options.remote_callbacks(callbacks);
+        fetch_options.proxy_options(proxy::git_proxy_options(proxy_url.as_deref()));
+        if let Some(depth) = depth {
+            fetch_options.depth(fetch_depth(depth));
+        }
 
         let mut builder = git2::build::RepoBuilder::new();
         builder.fetch_options(fetch_options);
 
+        let retry = RetryPolicy::default();
+
         // Try original URL
-        match builder.clone(url, &path) {
+        match metrics::time(metrics::GIT_CLONE, || {
+            retry.run(
+                || builder.clone(url, &path).map_err(Into::into),
+                is_transient_error,
+            )
+        }) {
             Ok(repo) => {
                 log::info!("Successfully cloned from {url}");
-                Ok(Self { repo, path })
+                Ok(Self { repo, path, retry })
             }
             Err(e) => {
                 // If SSH URL failed and we have a token, try HTTPS
@@ -54,12 +209,15 @@ impl GitRepo {
                 {
                     if let Ok(https_url) = crate::git_url::convert_ssh_to_https(url) {
                         log::info!("SSH clone failed, trying HTTPS with stored token");
-                        if let Ok(repo) = builder.clone(&https_url, &path) {
-                            return Ok(Self { repo, path });
+                        if let Ok(repo) = retry.run(
+                            || builder.clone(&https_url, &path).map_err(Into::into),
+                            is_transient_error,
+                        ) {
+                            return Ok(Self { repo, path, retry });
                         }
                     }
                 }
-                Err(e.into())
+                Err(e)
             }
         }
     }
@@ -69,11 +227,30 @@ impl GitRepo {
         &self.path
     }
 
+    /// The `.git` directory
+    pub fn git_dir(&self) -> &Path {
+        self.repo.path()
+    }
+
     /// Check if the repository has a remote configured
     pub fn has_remote(&self, remote_name: &str) -> bool {
         self.repo.find_remote(remote_name).is_ok()
     }
 
+    /// URL of a remote, if it exists
+    pub fn remote_url(&self, remote_name: &str) -> Option<String> {
+        let remote = self.repo.find_remote(remote_name).ok()?;
+        remote.url().map(str::to_string)
+    }
+
+    /// Names of all configured remotes, primary first, then alphabetically
+    pub fn remotes(&self) -> Result<Vec<String>> {
+        let names = self.repo.remotes().context("Failed to list remotes")?;
+        let mut names: Vec<String> = names.iter().flatten().map(str::to_string).collect();
+        names.sort_by_key(|name| (name != PRIMARY_REMOTE, name.clone()));
+        Ok(names)
+    }
+
     /// Add a remote to the repository
     pub fn add_remote(&mut self, name: &str, url: &str) -> Result<()> {
         self.repo
@@ -82,6 +259,14 @@ impl GitRepo {
         Ok(())
     }
 
+    /// Remove a remote and its remote-tracking branches
+    pub fn remove_remote(&mut self, name: &str) -> Result<()> {
+        self.repo
+            .remote_delete(name)
+            .context("Failed to remove remote")?;
+        Ok(())
+    }
+
     /// Stage a file for commit
     pub fn add_file<P: AsRef<Path>>(&self, file_path: P) -> Result<()> {
         let mut index = self
@@ -107,6 +292,40 @@ impl GitRepo {
         Ok(())
     }
 
+    /// Stage the removal of a file deleted from the working tree; a no-op if untracked
+    pub fn remove_file<P: AsRef<Path>>(&self, file_path: P) -> Result<()> {
+        let mut index = self
+            .repo
+            .index()
+            .context("Failed to get repository index")?;
+
+        index
+            .remove_path(file_path.as_ref())
+            .context("Failed to remove file from index")?;
+        index.write().context("Failed to write index")?;
+
+        Ok(())
+    }
+
+    /// Stage everything under a directory, including files removed from it
+    pub fn add_dir<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let mut index = self
+            .repo
+            .index()
+            .context("Failed to get repository index")?;
+
+        let pathspec = dir.as_ref().to_string_lossy().into_owned();
+        index
+            .add_all([&pathspec], git2::IndexAddOption::DEFAULT, None)
+            .context("Failed to add directory to index")?;
+        index
+            .update_all([&pathspec], None)
+            .context("Failed to update index")?;
+        index.write().context("Failed to write index")?;
+
+        Ok(())
+    }
+
     /// Commit staged changes
     pub fn commit(&self, message: &str) -> Result<git2::Oid> {
         let mut index = self.repo.index().context("Failed to get index")?;
@@ -119,6 +338,13 @@ impl GitRepo {
         // Get signature (use git config or default)
         let signature = self.get_signature()?;
 
+        // Record which machine made the change
+        let message = match self.device_name() {
+            Some(device) => device::with_device_trailer(message, &device),
+            None => message.to_string(),
+        };
+        let message = message.as_str();
+
         // Get parent commit (if any)
         let parent_commit = match self.repo.head() {
             Ok(head) => {
@@ -153,9 +379,13 @@ impl GitRepo {
     ) -> impl FnMut(&str, Option<&str>, git2::CredentialType) -> Result<git2::Cred, git2::Error>
     {
         move |_url, username, allowed_types| {
-            // 1. Try SSH agent (developers with SSH keys)
+            // 1. Try an explicitly configured key file, then the SSH agent
             if allowed_types.contains(git2::CredentialType::SSH_KEY) {
                 if let Some(username) = username {
+                    if let Some(cred) = ssh::credentials(username) {
+                        log::info!("Using configured SSH key");
+                        return cred;
+                    }
                     if let Ok(cred) = git2::Cred::ssh_key_from_agent(username) {
                         log::info!("Using SSH key from agent");
                         return Ok(cred);
@@ -179,6 +409,25 @@ impl GitRepo {
 
     /// Push to remote
     pub fn push(&self, remote_name: &str, branch: &str) -> Result<()> {
+        self.push_refspec(
+            remote_name,
+            &format!("refs/heads/{branch}:refs/heads/{branch}"),
+        )
+    }
+
+    /// Push to remote, overwriting the remote branch even if it has
+    /// commits we don't (e.g. after [`GitRepo::squash_history`])
+    pub fn force_push(&self, remote_name: &str, branch: &str) -> Result<()> {
+        self.push_refspec(
+            remote_name,
+            &format!("+refs/heads/{branch}:refs/heads/{branch}"),
+        )
+    }
+
+    fn push_refspec(&self, remote_name: &str, refspec: &str) -> Result<()> {
+        // Servers report refused refs through a callback rather than failing
+        // the push
+        let rejected = RefCell::new(None);
         let mut remote = self
             .repo
             .find_remote(remote_name)
@@ -187,21 +436,295 @@ impl GitRepo {
         // Set up callbacks for authentication
         let mut callbacks = RemoteCallbacks::new();
         callbacks.credentials(Self::create_smart_credentials());
+        callbacks.push_update_reference(|reference, status| {
+            if let Some(reason) = status {
+                *rejected.borrow_mut() = Some(PushRejected {
+                    reference: reference.to_string(),
+                    reason: reason.to_string(),
+                });
+            }
+            Ok(())
+        });
 
+        let proxy_url = proxy::explicit_proxy();
         let mut push_options = PushOptions::new();
         push_options.remote_callbacks(callbacks);
+        push_options.proxy_options(proxy::git_proxy_options(proxy_url.as_deref()));
+
+        metrics::time(metrics::GIT_PUSH, || {
+            self.retry.run(
+                || Ok(remote.push(&[refspec], Some(&mut push_options))?),
+                is_transient_error,
+            )
+        })
+        .context("Failed to push to remote")?;
+
+        let rejection = rejected.borrow_mut().take();
+        match rejection {
+            Some(rejection) => {
+                Err(anyhow::Error::new(rejection).context("Failed to push to remote"))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Work out which history [`GitRepo::squash_history`] would collapse:
+    /// everything up to the newest commit (following first parents from HEAD)
+    /// made before `cutoff`. `None` if there is nothing to squash.
+    pub fn plan_squash(&self, cutoff: DateTime<Utc>) -> Result<Option<SquashPlan>> {
+        let head = self
+            .repo
+            .head()
+            .context("Failed to get HEAD")?
+            .peel_to_commit()
+            .context("Failed to peel to commit")?;
+
+        let mut kept = 0;
+        let mut commit = head;
+        loop {
+            if commit.time().seconds() < cutoff.timestamp() {
+                break;
+            }
+            match commit.parents().next() {
+                Some(parent) => {
+                    commit = parent;
+                    kept += 1;
+                }
+                None => return Ok(None),
+            }
+        }
+
+        // A root commit on its own is already as small as it gets
+        if commit.parent_count() == 0 {
+            return Ok(None);
+        }
+
+        let mut walk = self.repo.revwalk().context("Failed to walk history")?;
+        walk.push(commit.id())?;
+        let squashed = walk.count();
+
+        Ok(Some(SquashPlan {
+            baseline: commit.id(),
+            squashed,
+            kept,
+        }))
+    }
+
+    /// Replace the history up to `plan.baseline` with a single root commit
+    /// holding the same tree, then replay the newer first-parent commits on
+    /// top (same trees, authors, and messages). The working tree is
+    /// unchanged; the branch now needs a force push.
+    pub fn squash_history(&self, plan: &SquashPlan) -> Result<git2::Oid> {
+        let head = self.repo.head().context("Failed to get HEAD")?;
+        if !head.is_branch() {
+            anyhow::bail!("Cannot squash history with a detached HEAD");
+        }
+        let branch_ref = head.name().context("Branch name is not UTF-8")?.to_string();
+
+        let mut replay = Vec::with_capacity(plan.kept);
+        let mut commit = head.peel_to_commit().context("Failed to peel to commit")?;
+        while commit.id() != plan.baseline {
+            let parent = commit
+                .parents()
+                .next()
+                .context("Baseline commit is not in the current history")?;
+            replay.push(commit);
+            commit = parent;
+        }
+
+        let signature = self.get_signature()?;
+        let baseline_date = DateTime::from_timestamp(commit.time().seconds(), 0)
+            .map_or_else(String::new, |date| date.format("%Y-%m-%d").to_string());
+        let mut new_head = self
+            .repo
+            .commit(
+                None,
+                &commit.author(),
+                &signature,
+                &format!(
+                    "Baseline: {} commits up to {baseline_date} squashed",
+                    plan.squashed
+                ),
+                &commit.tree()?,
+                &[],
+            )
+            .context("Failed to create baseline commit")?;
+
+        for original in replay.iter().rev() {
+            let parent = self.repo.find_commit(new_head)?;
+            new_head = self
+                .repo
+                .commit(
+                    None,
+                    &original.author(),
+                    &original.committer(),
+                    original.message().unwrap_or_default(),
+                    &original.tree()?,
+                    &[&parent],
+                )
+                .context("Failed to replay commit")?;
+        }
+
+        self.repo
+            .reference(&branch_ref, new_head, true, "Squash old history")
+            .context("Failed to update branch")?;
+        Ok(new_head)
+    }
+
+    /// Expire reflogs and run `git gc --prune=now` so objects made
+    /// unreachable (e.g. by squashing) are actually deleted.
+    ///
+    /// libgit2 has no garbage collector, so this needs the git CLI; returns
+    /// `false` without doing anything if it isn't installed.
+    pub fn gc(&self) -> Result<bool> {
+        let commands: [&[&str]; 2] = [
+            &["reflog", "expire", "--expire=now", "--all"],
+            &["gc", "--prune=now", "--quiet"],
+        ];
+        for args in commands {
+            let output = match std::process::Command::new("git")
+                .arg("-C")
+                .arg(&self.path)
+                .args(args)
+                .output()
+            {
+                Ok(output) => output,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e).context("Failed to run git"),
+            };
+            if !output.status.success() {
+                anyhow::bail!(
+                    "git {} failed: {}",
+                    args[0],
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+        Ok(true)
+    }
+
+    /// Write `branch` to a bundle file at `path`: its whole history, or only
+    /// the commits after `since` (a revision the receiving machine already
+    /// has). Returns how many commits went in.
+    ///
+    /// libgit2 can't read or write bundles, so this needs the git CLI.
+    pub fn create_bundle(&self, path: &Path, branch: &str, since: Option<&str>) -> Result<usize> {
+        let tip = self
+            .repo
+            .revparse_single(&format!("refs/heads/{branch}"))
+            .and_then(|object| object.peel_to_commit())
+            .context("Nothing has been committed yet")?;
+        let mut walk = self.repo.revwalk()?;
+        walk.push(tip.id())?;
+        let mut range = branch.to_string();
+        if let Some(since) = since {
+            let base = self
+                .repo
+                .revparse_single(since)
+                .and_then(|object| object.peel_to_commit())
+                .with_context(|| format!("Unknown revision {since}"))?;
+            walk.hide(base.id())?;
+            range = format!("{}..{branch}", base.id());
+        }
+        let commits = walk.count();
+        if commits == 0 {
+            anyhow::bail!("No commits to export since {}", since.unwrap_or(branch));
+        }
+        self.run_git([
+            OsStr::new("bundle"),
+            OsStr::new("create"),
+            OsStr::new("--quiet"),
+            path.as_os_str(),
+            OsStr::new(&range),
+        ])?;
+        Ok(commits)
+    }
 
-        let refspec = format!("refs/heads/{branch}:refs/heads/{branch}");
-        remote
-            .push(&[&refspec], Some(&mut push_options))
-            .context("Failed to push to remote")?;
+    /// Fetch `branch` from a bundle written by [`GitRepo::create_bundle`] and
+    /// merge it in, as [`GitRepo::pull`] does from a remote. Fails if the
+    /// bundle builds on commits this repository doesn't have.
+    pub fn pull_bundle(&self, path: &Path, branch: &str) -> Result<PullSummary> {
+        self.run_git([
+            OsStr::new("bundle"),
+            OsStr::new("verify"),
+            OsStr::new("--quiet"),
+            path.as_os_str(),
+        ])?;
+        self.run_git([
+            OsStr::new("fetch"),
+            OsStr::new("--quiet"),
+            OsStr::new("--no-write-fetch-head"),
+            path.as_os_str(),
+            OsStr::new(&format!("+refs/heads/{branch}:{BUNDLE_REF}")),
+        ])?;
+
+        let mut reference = self.repo.find_reference(BUNDLE_REF)?;
+        let fetch_commit = self.repo.reference_to_annotated_commit(&reference)?;
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+        let summary =
+            self.merge_commit(branch, &fetch_commit, &format!("Merge from bundle {name}"));
+        if let Err(e) = reference.delete() {
+            log::warn!("Failed to remove {BUNDLE_REF}: {e}");
+        }
+        summary
+    }
 
+    /// Run the git CLI in this repository, failing with its error output
+    fn run_git<I, S>(&self, args: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&self.path)
+            .args(args)
+            .output();
+        let output = match output {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                anyhow::bail!("The git command line tool is required but isn't installed")
+            }
+            Err(e) => return Err(e).context("Failed to run git"),
+        };
+        if !output.status.success() {
+            anyhow::bail!(
+                "git failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
         Ok(())
     }
 
-    /// Pull from remote (with rebase)
-    pub fn pull(&self, remote_name: &str, branch: &str) -> Result<()> {
-        // Fetch from remote
+    /// Total size in bytes of the `.git` directory
+    pub fn git_dir_size(&self) -> Result<u64> {
+        fn dir_size(path: &Path) -> std::io::Result<u64> {
+            let mut total = 0;
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                total += if metadata.is_dir() {
+                    dir_size(&entry.path())?
+                } else {
+                    metadata.len()
+                };
+            }
+            Ok(total)
+        }
+        dir_size(self.repo.path()).context("Failed to measure repository size")
+    }
+
+    /// Whether this is a shallow clone with truncated history
+    pub fn is_shallow(&self) -> bool {
+        self.repo.is_shallow()
+    }
+
+    /// Fetch more history for a shallow clone: `depth` commits back from the
+    /// remote branch tip, or the full history when `None`
+    pub fn deepen(&self, remote_name: &str, branch: &str, depth: Option<u32>) -> Result<()> {
         let mut remote = self
             .repo
             .find_remote(remote_name)
@@ -210,23 +733,245 @@ impl GitRepo {
         let mut callbacks = RemoteCallbacks::new();
         callbacks.credentials(Self::create_smart_credentials());
 
+        let proxy_url = proxy::explicit_proxy();
         let mut fetch_options = FetchOptions::new();
         fetch_options.remote_callbacks(callbacks);
+        fetch_options.proxy_options(proxy::git_proxy_options(proxy_url.as_deref()));
+        fetch_options.depth(depth.map_or(UNSHALLOW_DEPTH, fetch_depth));
 
-        remote
-            .fetch(&[branch], Some(&mut fetch_options), None)
-            .context("Failed to fetch from remote")?;
+        metrics::time(metrics::GIT_FETCH, || {
+            self.retry.run(
+                || Ok(remote.fetch(&[branch], Some(&mut fetch_options), None)?),
+                is_transient_error,
+            )
+        })
+        .context("Failed to fetch history from remote")?;
+        Ok(())
+    }
+
+    /// Check that the remote is reachable (and accepts our credentials)
+    /// without transferring any objects
+    pub fn check_connectivity(&self, remote_name: &str) -> Result<()> {
+        let mut remote = self
+            .repo
+            .find_remote(remote_name)
+            .context("Failed to find remote")?;
+
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(Self::create_smart_credentials());
+
+        let proxy_url = proxy::explicit_proxy();
+        let connection = remote
+            .connect_auth(
+                git2::Direction::Fetch,
+                Some(callbacks),
+                Some(proxy::git_proxy_options(proxy_url.as_deref())),
+            )
+            .context("Failed to connect to remote")?;
+        drop(connection);
+        Ok(())
+    }
+
+    /// Fetch every branch under `prefix` (e.g. `device/`) from the remote
+    /// into remote-tracking branches, dropping those deleted on the remote.
+    /// Returns the branch names, sorted.
+    pub fn fetch_branches(&self, remote_name: &str, prefix: &str) -> Result<Vec<String>> {
+        let mut remote = self
+            .repo
+            .find_remote(remote_name)
+            .context("Failed to find remote")?;
+
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(Self::create_smart_credentials());
+
+        let proxy_url = proxy::explicit_proxy();
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        fetch_options.proxy_options(proxy::git_proxy_options(proxy_url.as_deref()));
+        fetch_options.prune(git2::FetchPrune::On);
+
+        let refspec = format!("+refs/heads/{prefix}*:refs/remotes/{remote_name}/{prefix}*");
+        metrics::time(metrics::GIT_FETCH, || {
+            self.retry.run(
+                || Ok(remote.fetch(&[&refspec], Some(&mut fetch_options), None)?),
+                is_transient_error,
+            )
+        })
+        .with_context(|| format!("Failed to fetch {prefix}* branches from remote"))?;
+
+        let tracking = format!("refs/remotes/{remote_name}/");
+        let mut branches = Vec::new();
+        for reference in self
+            .repo
+            .references_glob(&format!("{tracking}{prefix}*"))
+            .context("Failed to list fetched branches")?
+        {
+            let reference = reference.context("Failed to list fetched branches")?;
+            if let Some(name) = reference
+                .name()
+                .and_then(|name| name.strip_prefix(&tracking))
+            {
+                branches.push(name.to_string());
+            }
+        }
+        branches.sort();
+        Ok(branches)
+    }
+
+    /// Merge the remote-tracking branch `remote_name/branch` into the local
+    /// `into` branch, which must be checked out, as a pull would
+    pub fn merge_remote_branch(
+        &self,
+        remote_name: &str,
+        branch: &str,
+        into: &str,
+    ) -> Result<PullSummary> {
+        let reference = self
+            .repo
+            .find_reference(&format!("refs/remotes/{remote_name}/{branch}"))
+            .with_context(|| format!("No remote-tracking branch {remote_name}/{branch}"))?;
+        let commit = self.repo.reference_to_annotated_commit(&reference)?;
+        self.merge_commit(into, &commit, &format!("Merge from {remote_name}/{branch}"))
+    }
+
+    /// Point the local branch `name` at HEAD, creating it if needed. HEAD
+    /// stays where it is.
+    pub fn create_branch(&self, name: &str) -> Result<()> {
+        let head = self
+            .repo
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .context("Nothing committed to branch from")?;
+        self.repo
+            .branch(name, &head, true)
+            .with_context(|| format!("Failed to create branch {name}"))?;
+        Ok(())
+    }
+
+    /// Pull from remote (with rebase). Fails with [`HistoryRewritten`] if
+    /// the remote branch no longer contains what was last fetched from it.
+    pub fn pull(&self, remote_name: &str, branch: &str) -> Result<PullSummary> {
+        // Read first: the fetch updates the remote-tracking branch itself
+        let tracking = format!("refs/remotes/{remote_name}/{branch}");
+        let previous = self.repo.refname_to_id(&tracking).ok();
+        let fetch_commit = self.fetch(remote_name, branch)?;
+
+        // A squash or force push on the remote leaves nothing to merge with
+        if let Some(previous) = previous {
+            if previous != fetch_commit.id()
+                && !self
+                    .repo
+                    .graph_descendant_of(fetch_commit.id(), previous)
+                    .context("Failed to compare with the remote history")?
+            {
+                // Put back what the fetch moved, so this is reported until
+                // the user resets
+                self.repo
+                    .reference(&tracking, previous, true, "History rewritten on the remote")
+                    .context("Failed to restore remote-tracking branch")?;
+                return Err(HistoryRewritten {
+                    remote: remote_name.to_string(),
+                    branch: branch.to_string(),
+                }
+                .into());
+            }
+        }
+
+        // Keep the remote-tracking branch current so ahead/behind counts are accurate
+        self.repo
+            .reference(&tracking, fetch_commit.id(), true, "fetch")
+            .context("Failed to update remote-tracking branch")?;
+
+        self.merge_commit(
+            branch,
+            &fetch_commit,
+            &format!("Merge from {remote_name}/{branch}"),
+        )
+    }
+
+    /// Start `branch` again from the remote's copy after its history was
+    /// rewritten, dropping local commits. Returns the commit last fetched
+    /// before, if any, as a base for bringing local changes back.
+    pub fn reset_to_remote(&self, remote_name: &str, branch: &str) -> Result<Option<git2::Oid>> {
+        let tracking = format!("refs/remotes/{remote_name}/{branch}");
+        let previous = self.repo.refname_to_id(&tracking).ok();
+        let fetch_commit = self.fetch(remote_name, branch)?;
+
+        let refname = format!("refs/heads/{branch}");
+        for name in [&tracking, &refname] {
+            self.repo
+                .reference(name, fetch_commit.id(), true, "Reset to remote")
+                .with_context(|| format!("Failed to update {name}"))?;
+        }
+        self.repo.set_head(&refname)?;
+        self.repo
+            .checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+            .context("Failed to check out the remote history")?;
+        self.repo.cleanup_state()?;
+        Ok(previous)
+    }
+
+    /// Fetch `branch` from the remote, returning what it points at
+    fn fetch(&self, remote_name: &str, branch: &str) -> Result<git2::AnnotatedCommit<'_>> {
+        let mut remote = self
+            .repo
+            .find_remote(remote_name)
+            .context("Failed to find remote")?;
+
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(Self::create_smart_credentials());
+
+        let proxy_url = proxy::explicit_proxy();
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        fetch_options.proxy_options(proxy::git_proxy_options(proxy_url.as_deref()));
+
+        metrics::time(metrics::GIT_FETCH, || {
+            self.retry.run(
+                || Ok(remote.fetch(&[branch], Some(&mut fetch_options), None)?),
+                is_transient_error,
+            )
+        })
+        .context("Failed to fetch from remote")?;
 
-        // Get fetch head
         let fetch_head = self.repo.find_reference("FETCH_HEAD")?;
-        let fetch_commit = self.repo.reference_to_annotated_commit(&fetch_head)?;
+        Ok(self.repo.reference_to_annotated_commit(&fetch_head)?)
+    }
+
+    /// Bring `branch` up to `fetch_commit`, fast-forwarding when possible and
+    /// otherwise committing a merge (conflicts go to the incoming side)
+    fn merge_commit(
+        &self,
+        branch: &str,
+        fetch_commit: &git2::AnnotatedCommit,
+        message: &str,
+    ) -> Result<PullSummary> {
+        let mut summary = PullSummary::default();
+        if let Ok(head) = self.repo.head().and_then(|h| h.peel_to_commit()) {
+            summary.pulled = self
+                .repo
+                .graph_ahead_behind(head.id(), fetch_commit.id())
+                .context("Failed to count incoming commits")?
+                .1;
+        }
 
         // Perform merge analysis
-        let analysis = self.repo.merge_analysis(&[&fetch_commit])?;
+        let analysis = self.repo.merge_analysis(&[fetch_commit])?;
 
         if analysis.0.is_up_to_date() {
             // Already up to date
-            return Ok(());
+            return Ok(summary);
+        } else if analysis.0.is_unborn() {
+            // Nothing committed here yet: take the incoming history as is
+            let mut walk = self.repo.revwalk()?;
+            walk.push(fetch_commit.id())?;
+            summary.pulled = walk.count();
+            let refname = format!("refs/heads/{branch}");
+            self.repo
+                .reference(&refname, fetch_commit.id(), true, message)?;
+            self.repo.set_head(&refname)?;
+            self.repo
+                .checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
         } else if analysis.0.is_fast_forward() {
             // Fast-forward merge
             let refname = format!("refs/heads/{branch}");
@@ -236,10 +981,12 @@ impl GitRepo {
             self.repo
                 .checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
         } else {
-            // Need to merge - for now, prefer remote (simple strategy)
-            // In a real implementation, we'd want conflict resolution UI
+            // Diverged: let git take the remote side of any conflicting hunk. This
+            // commit is only a placeholder; callers re-merge bookmarks.json field by
+            // field from `summary.merge` (see `sync::GitBackend`) and save what can't
+            // be settled for the user to resolve with `ResolveConflicts`.
             self.repo.merge(
-                &[&fetch_commit],
+                &[fetch_commit],
                 None,
                 Some(
                     git2::build::CheckoutBuilder::default()
@@ -251,11 +998,16 @@ impl GitRepo {
             // Check if merge resulted in conflicts
             let mut index = self.repo.index()?;
             if index.has_conflicts() {
-                // For now, just use "theirs" strategy
-                // TODO: Implement conflict resolution UI
+                // Resolve textual conflicts as "theirs"; the field-level re-merge
+                // by the caller is what decides the outcome
                 let conflicts: Vec<_> = index.conflicts()?.flatten().collect();
+                summary.conflicts_resolved = conflicts.len();
                 for conflict in conflicts {
-                    if let Some(their) = conflict.their {
+                    if let Some(mut their) = conflict.their {
+                        let path = String::from_utf8_lossy(&their.path).into_owned();
+                        index.remove_path(Path::new(&path))?;
+                        // Back to an ordinary entry, not a conflict stage
+                        their.flags &= !INDEX_STAGE_MASK;
                         index.add(&their)?;
                     }
                 }
@@ -268,12 +1020,20 @@ impl GitRepo {
             let tree = self.repo.find_tree(tree_id)?;
             let head_commit = self.repo.head()?.peel_to_commit()?;
             let fetch_commit_obj = self.repo.find_commit(fetch_commit.id())?;
+            summary.merge = Some(MergeParents {
+                base: self
+                    .repo
+                    .merge_base(head_commit.id(), fetch_commit.id())
+                    .ok(),
+                ours: head_commit.id(),
+                theirs: fetch_commit.id(),
+            });
 
             self.repo.commit(
                 Some("HEAD"),
                 &signature,
                 &signature,
-                &format!("Merge from {remote_name}/{branch}"),
+                message,
                 &tree,
                 &[&head_commit, &fetch_commit_obj],
             )?;
@@ -282,7 +1042,7 @@ impl GitRepo {
             self.repo.cleanup_state()?;
         }
 
-        Ok(())
+        Ok(summary)
     }
 
     /// Get the current commit message
@@ -292,11 +1052,315 @@ impl GitRepo {
         Ok(commit.message().unwrap_or("(no message)").to_string())
     }
 
+    /// This clone's device name, if one has been set
+    pub fn device_name(&self) -> Option<String> {
+        self.local_config(DEVICE_CONFIG_KEY)
+    }
+
+    /// Whether `Sync` pushes this device's commits to a branch of its own
+    pub fn device_branches_enabled(&self) -> bool {
+        self.local_config(DEVICE_BRANCHES_KEY).as_deref() == Some("true")
+    }
+
+    pub fn set_device_branches(&self, enabled: bool) -> Result<()> {
+        self.set_local_config(DEVICE_BRANCHES_KEY, enabled.then_some("true"))
+    }
+
+    /// This device's branch, `device/<name>`, when per-device branches are
+    /// on and the device has a name
+    pub fn device_branch(&self) -> Option<String> {
+        if !self.device_branches_enabled() {
+            return None;
+        }
+        let name = self.device_name()?;
+        Some(format!(
+            "{DEVICE_BRANCH_PREFIX}{}",
+            device::branch_name(&name)
+        ))
+    }
+
+    /// Who new bookmarks are attributed to: git's `user.name`, or this
+    /// clone's device name without one
+    pub fn author_name(&self) -> Option<String> {
+        self.repo
+            .config()
+            .ok()
+            .and_then(|config| config.get_string("user.name").ok())
+            .filter(|name| !name.trim().is_empty())
+            .or_else(|| self.device_name())
+    }
+
+    /// Name this clone's device. Stored in the repository's local git config,
+    /// so it never syncs to other machines.
+    pub fn set_device_name(&self, name: &str) -> Result<()> {
+        device::validate_device_name(name)?;
+        self.set_local_config(DEVICE_CONFIG_KEY, Some(name))
+            .context("Failed to save device name")
+    }
+
+    /// A setting from git config, if set and not empty
+    pub fn local_config(&self, key: &str) -> Option<String> {
+        let config = self.repo.config().ok()?;
+        config
+            .get_string(key)
+            .ok()
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Every setting in the repository's local git config whose name matches
+    /// the regex `pattern`, in file order
+    pub fn local_config_entries(&self, pattern: &str) -> Result<Vec<(String, String)>> {
+        let config = self
+            .repo
+            .config()
+            .and_then(|config| config.open_level(git2::ConfigLevel::Local))
+            .context("Failed to open repository config")?;
+        let mut entries = Vec::new();
+        let mut iter = config
+            .entries(Some(pattern))
+            .context("Failed to read repository config")?;
+        while let Some(entry) = iter.next() {
+            let entry = entry.context("Failed to read repository config")?;
+            if let (Some(name), Some(value)) = (entry.name(), entry.value()) {
+                entries.push((name.to_string(), value.to_string()));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Set (or with `None`, remove) a setting in the repository's local git
+    /// config, which is never pushed
+    pub fn set_local_config(&self, key: &str, value: Option<&str>) -> Result<()> {
+        let mut config = self
+            .repo
+            .config()
+            .and_then(|config| config.open_level(git2::ConfigLevel::Local))
+            .context("Failed to open repository config")?;
+        match value {
+            Some(value) => config
+                .set_str(key, value)
+                .with_context(|| format!("Failed to set {key}")),
+            None => match config.remove(key) {
+                Err(e) if e.code() != git2::ErrorCode::NotFound => {
+                    Err(e).with_context(|| format!("Failed to remove {key}"))
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// ID of the commit HEAD points at, or `None` before the first commit
+    pub fn head_id(&self) -> Option<git2::Oid> {
+        self.repo
+            .head()
+            .ok()
+            .and_then(|head| head.peel_to_commit().ok())
+            .map(|commit| commit.id())
+    }
+
+    /// Most recent commits reachable from HEAD, newest first
+    pub fn history(&self, limit: usize) -> Result<Vec<CommitInfo>> {
+        if !self.has_commits() {
+            return Ok(Vec::new());
+        }
+
+        let mut walk = self.repo.revwalk().context("Failed to walk history")?;
+        walk.push_head()?;
+        // Timestamps only have second precision; never list a parent first
+        walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+        walk.take(limit)
+            .map(|oid| {
+                let commit = self.repo.find_commit(oid?)?;
+                let message = commit.message().unwrap_or_default();
+                let author = commit.author();
+                Ok(CommitInfo {
+                    id: commit.id().to_string(),
+                    summary: commit.summary().unwrap_or_default().to_string(),
+                    author: author.name().unwrap_or_default().to_string(),
+                    device: device::device_from_message(message),
+                    time: DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    /// The interrupted operation (merge, rebase, cherry-pick, ...) the
+    /// repository is stuck in, if any
+    pub fn operation_in_progress(&self) -> Option<String> {
+        match self.repo.state() {
+            git2::RepositoryState::Clean => None,
+            state => Some(format!("{state:?}").to_lowercase()),
+        }
+    }
+
+    /// Abandon an interrupted operation, resetting the index and working tree
+    /// to HEAD (like `git merge --abort`)
+    pub fn abort_operation(&self) -> Result<()> {
+        if self.has_commits() {
+            let head = self.repo.head()?.peel_to_commit()?;
+            self.repo
+                .reset(head.as_object(), git2::ResetType::Hard, None)
+                .context("Failed to reset to HEAD")?;
+        }
+        self.repo
+            .cleanup_state()
+            .context("Failed to clear interrupted operation")
+    }
+
+    /// Why HEAD can't be resolved to a commit, if it can't. An unborn branch
+    /// (no commits yet) is not a problem.
+    pub fn head_error(&self) -> Option<String> {
+        match self.repo.head() {
+            Ok(head) => head.peel_to_commit().err().map(|e| e.message().to_string()),
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(e) => Some(e.message().to_string()),
+        }
+    }
+
+    /// Whether the index differs from HEAD, i.e. committing would record
+    /// something
+    pub fn has_staged_changes(&self) -> Result<bool> {
+        let index = self.repo.index().context("Failed to get index")?;
+        let head_tree = match self.repo.head() {
+            Ok(head) => Some(head.peel_to_tree().context("Failed to read HEAD tree")?),
+            Err(_) => None,
+        };
+        let diff = self
+            .repo
+            .diff_tree_to_index(head_tree.as_ref(), Some(&index), None)
+            .context("Failed to compare index with HEAD")?;
+        Ok(diff.deltas().len() > 0)
+    }
+
+    /// Throw away staged changes: the index and every staged file go back
+    /// to HEAD, and files HEAD doesn't have are removed. Before the first
+    /// commit there is nothing to go back to, so files are only unstaged.
+    pub fn discard_staged(&self) -> Result<()> {
+        let mut index = self.repo.index().context("Failed to get index")?;
+        let Ok(head) = self.repo.head().and_then(|head| head.peel_to_commit()) else {
+            index.clear().context("Failed to clear index")?;
+            return index.write().context("Failed to write index");
+        };
+        let head_tree = head.tree().context("Failed to read HEAD tree")?;
+        let diff = self
+            .repo
+            .diff_tree_to_index(Some(&head_tree), Some(&index), None)
+            .context("Failed to compare index with HEAD")?;
+        let paths: Vec<PathBuf> = diff
+            .deltas()
+            .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+            .map(Path::to_path_buf)
+            .collect();
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        self.repo
+            .reset(head.as_object(), git2::ResetType::Mixed, None)
+            .context("Failed to reset index")?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.force().remove_untracked(true);
+        for path in &paths {
+            checkout.path(path);
+        }
+        self.repo
+            .checkout_head(Some(&mut checkout))
+            .context("Failed to restore staged files")
+    }
+
+    /// Whether HEAD points at a commit (false for a freshly initialized repo)
+    pub fn has_commits(&self) -> bool {
+        self.repo
+            .head()
+            .is_ok_and(|head| head.peel_to_commit().is_ok())
+    }
+
+    /// Name of the checked-out branch, or `None` if HEAD is detached or unborn
+    pub fn current_branch(&self) -> Option<String> {
+        let head = self.repo.head().ok()?;
+        if !head.is_branch() {
+            return None;
+        }
+        head.shorthand().map(str::to_string)
+    }
+
+    /// Compare the current branch to `<remote_name>/<branch>`.
+    ///
+    /// Returns `None` when there is no current branch or the remote-tracking
+    /// branch doesn't exist yet (never fetched or pushed). Counts are as of the
+    /// last fetch; this does not contact the remote.
+    pub fn ahead_behind(&self, remote_name: &str) -> Result<Option<AheadBehind>> {
+        let Some(branch) = self.current_branch() else {
+            return Ok(None);
+        };
+        self.branch_ahead_behind(remote_name, &branch)
+    }
+
+    /// Compare local `branch` to `<remote_name>/<branch>`, as [`Self::ahead_behind`]
+    /// does for the current branch. `None` if either side doesn't exist.
+    pub fn branch_ahead_behind(
+        &self,
+        remote_name: &str,
+        branch: &str,
+    ) -> Result<Option<AheadBehind>> {
+        let Ok(local) = self.repo.find_reference(&format!("refs/heads/{branch}")) else {
+            return Ok(None);
+        };
+        let local = local
+            .peel_to_commit()
+            .context("Failed to resolve local branch")?
+            .id();
+        let Ok(upstream) = self
+            .repo
+            .find_reference(&format!("refs/remotes/{remote_name}/{branch}"))
+        else {
+            return Ok(None);
+        };
+        let upstream = upstream
+            .peel_to_commit()
+            .context("Failed to resolve remote-tracking branch")?
+            .id();
+
+        let (ahead, behind) = self
+            .repo
+            .graph_ahead_behind(local, upstream)
+            .context("Failed to compare with remote-tracking branch")?;
+        Ok(Some(AheadBehind { ahead, behind }))
+    }
+
+    /// Read a file's contents as of revision `rev` (a commit id, branch, or
+    /// expression like `HEAD~1`). Returns `None` if the file didn't exist there.
+    pub fn read_file_at(&self, rev: &str, file_path: &str) -> Result<Option<Vec<u8>>> {
+        let commit = self
+            .repo
+            .revparse_single(rev)
+            .with_context(|| format!("Unknown revision: {rev}"))?
+            .peel_to_commit()
+            .with_context(|| format!("Revision is not a commit: {rev}"))?;
+        let tree = commit.tree().context("Failed to get commit tree")?;
+
+        let entry = match tree.get_path(Path::new(file_path)) {
+            Ok(entry) => entry,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Failed to look up file in commit tree"),
+        };
+        let blob = entry
+            .to_object(&self.repo)
+            .and_then(|object| object.peel_to_blob())
+            .with_context(|| format!("{file_path} is not a file at {rev}"))?;
+        Ok(Some(blob.content().to_vec()))
+    }
+
     /// Check if working directory is clean
     pub fn is_clean(&self) -> Result<bool> {
+        // Ignored files (e.g. local-only sidecars) don't make the tree dirty
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(true).include_ignored(false);
+
         let statuses = self
             .repo
-            .statuses(None)
+            .statuses(Some(&mut options))
             .context("Failed to get repository status")?;
         Ok(statuses.is_empty())
     }
@@ -312,10 +1376,64 @@ impl GitRepo {
             .get_string("user.email")
             .unwrap_or_else(|_| "webtags@localhost".to_string());
 
+        // Show the device in the author field too, e.g. "Jane (work-laptop)"
+        let name = match self.device_name() {
+            Some(device) => format!("{name} ({device})"),
+            None => name,
+        };
+
         Signature::now(&name, &email).context("Failed to create signature")
     }
 }
 
+/// libgit2's `GIT_FETCH_DEPTH_UNSHALLOW`: fetch all missing history
+const UNSHALLOW_DEPTH: i32 = i32::MAX;
+
+/// Clamp a requested depth into libgit2's range. 0 would mean "full history",
+/// which isn't what a caller asking for a depth wants, so it becomes 1.
+fn fetch_depth(depth: u32) -> i32 {
+    i32::try_from(depth.max(1)).unwrap_or(UNSHALLOW_DEPTH - 1)
+}
+
+/// Whether a failed network operation is worth retrying: connection and
+/// transport failures are, authentication, certificate, and rejected-push
+/// errors are not.
+pub fn is_transient_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<git2::Error>())
+        .any(|e| {
+            matches!(
+                e.class(),
+                git2::ErrorClass::Net | git2::ErrorClass::Http | git2::ErrorClass::Ssh
+            ) && !matches!(
+                e.code(),
+                git2::ErrorCode::Auth
+                    | git2::ErrorCode::Certificate
+                    | git2::ErrorCode::NotFastForward
+                    | git2::ErrorCode::User
+            )
+        })
+}
+
+/// Whether a push failed because the remote refused the update (not a
+/// fast-forward, or rejected by the server) rather than for lack of a
+/// connection or credentials
+pub fn is_push_rejected(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<PushRejected>()
+            || cause
+                .downcast_ref::<git2::Error>()
+                .is_some_and(|e| e.code() == git2::ErrorCode::NotFastForward)
+    })
+}
+
+/// Whether a failed network operation was refused for bad credentials
+pub fn is_auth_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<git2::Error>())
+        .any(|e| e.code() == git2::ErrorCode::Auth)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -454,6 +1572,45 @@ mod tests {
         assert!(repo.is_clean().unwrap());
     }
 
+    #[test]
+    fn test_add_dir_stages_removals() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo_path = temp_dir.path();
+        let repo = GitRepo::init(repo_path).unwrap();
+
+        std::fs::create_dir_all(repo_path.join("site/tags")).unwrap();
+        create_test_file(repo_path, "site/index.html", "index");
+        create_test_file(repo_path, "site/tags/a.html", "a");
+        repo.add_dir("site").unwrap();
+        repo.commit("Add site").unwrap();
+        assert!(repo.is_clean().unwrap());
+
+        std::fs::remove_file(repo_path.join("site/tags/a.html")).unwrap();
+        repo.add_dir("site").unwrap();
+        assert!(repo.has_staged_changes().unwrap());
+        repo.commit("Remove page").unwrap();
+        assert!(repo.is_clean().unwrap());
+    }
+
+    #[test]
+    fn test_remove_file_stages_deletion() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo_path = temp_dir.path();
+        let repo = GitRepo::init(repo_path).unwrap();
+
+        create_test_file(repo_path, "test.txt", "content");
+        repo.add_file("test.txt").unwrap();
+        repo.commit("Initial commit").unwrap();
+
+        std::fs::remove_file(repo_path.join("test.txt")).unwrap();
+        repo.remove_file("test.txt").unwrap();
+        repo.commit("Remove file").unwrap();
+        assert!(repo.is_clean().unwrap());
+
+        // Removing a path that was never tracked is fine
+        repo.remove_file("missing.txt").unwrap();
+    }
+
     #[test]
     fn test_commit_without_staged_changes() {
         let temp_dir = TempDir::new().unwrap();
@@ -472,6 +1629,394 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_pull_reports_incoming_and_unpushed_commits() {
+        let temp_dir = TempDir::new().unwrap();
+        let remote_path = temp_dir.path().join("remote.git");
+        Repository::init_bare(&remote_path).unwrap();
+        let remote_url = remote_path.to_str().unwrap();
+
+        let local_path = temp_dir.path().join("local");
+        let mut local = GitRepo::init(&local_path).unwrap();
+        local.add_remote("origin", remote_url).unwrap();
+        create_test_file(&local_path, "base.txt", "base");
+        local.add_file("base.txt").unwrap();
+        local.commit("Base").unwrap();
+        let branch = local.current_branch().unwrap();
+        local.push("origin", &branch).unwrap();
+
+        // Another device pushes a commit
+        let other_path = temp_dir.path().join("other");
+        let other = GitRepo::clone(remote_url, &other_path, None).unwrap();
+        create_test_file(&other_path, "other.txt", "other");
+        other.add_file("other.txt").unwrap();
+        other.commit("From other device").unwrap();
+        other.push("origin", &branch).unwrap();
+
+        // Meanwhile a local commit
+        create_test_file(&local_path, "local.txt", "local");
+        local.add_file("local.txt").unwrap();
+        local.commit("Local change").unwrap();
+
+        let summary = local.pull("origin", &branch).unwrap();
+        assert_eq!(summary.pulled, 1);
+        assert_eq!(summary.conflicts_resolved, 0);
+        let parents = summary.merge.unwrap();
+        assert_ne!(parents.ours, parents.theirs);
+        assert!(parents.base.is_some());
+
+        // Local commit plus the merge commit are waiting to be pushed
+        let status = local.branch_ahead_behind("origin", &branch).unwrap();
+        assert_eq!(
+            status,
+            Some(AheadBehind {
+                ahead: 2,
+                behind: 0
+            })
+        );
+
+        local.push("origin", &branch).unwrap();
+        assert_eq!(
+            local.branch_ahead_behind("origin", &branch).unwrap(),
+            Some(AheadBehind {
+                ahead: 0,
+                behind: 0
+            })
+        );
+        assert_eq!(
+            local.pull("origin", &branch).unwrap(),
+            PullSummary::default()
+        );
+    }
+
+    #[test]
+    fn test_rewritten_remote_history_is_detected_and_reset() {
+        let temp_dir = TempDir::new().unwrap();
+        let remote_path = temp_dir.path().join("remote.git");
+        Repository::init_bare(&remote_path).unwrap();
+        let remote_url = remote_path.to_str().unwrap();
+
+        let local_path = temp_dir.path().join("local");
+        let mut local = GitRepo::init(&local_path).unwrap();
+        local.add_remote("origin", remote_url).unwrap();
+        create_test_file(&local_path, "base.txt", "base");
+        local.add_file("base.txt").unwrap();
+        local.commit("Base").unwrap();
+        let branch = local.current_branch().unwrap();
+        local.push("origin", &branch).unwrap();
+        local.pull("origin", &branch).unwrap();
+        let synced = local.head_id().unwrap();
+
+        // Another device rewrites the history and force-pushes it
+        let other_path = temp_dir.path().join("other");
+        let other = GitRepo::clone(remote_url, &other_path, None).unwrap();
+        Repository::open(&other_path)
+            .unwrap()
+            .head()
+            .unwrap()
+            .peel_to_commit()
+            .unwrap()
+            .amend(Some("HEAD"), None, None, None, Some("Squashed"), None)
+            .unwrap();
+        other.force_push("origin", &branch).unwrap();
+        let rewritten = other.head_id().unwrap();
+        // The device that rewrote it isn't told about its own rewrite
+        assert_eq!(
+            other.pull("origin", &branch).unwrap(),
+            PullSummary::default()
+        );
+
+        create_test_file(&local_path, "local.txt", "local");
+        local.add_file("local.txt").unwrap();
+        local.commit("Local change").unwrap();
+        let err = local.pull("origin", &branch).unwrap_err();
+        assert!(err.is::<HistoryRewritten>());
+        // Still detected until the user decides what to do
+        assert!(local
+            .pull("origin", &branch)
+            .unwrap_err()
+            .is::<HistoryRewritten>());
+
+        assert_eq!(
+            local.reset_to_remote("origin", &branch).unwrap(),
+            Some(synced)
+        );
+        assert_eq!(local.head_id(), Some(rewritten));
+        assert_eq!(local.get_last_commit_message().unwrap(), "Squashed");
+        assert_eq!(
+            local.pull("origin", &branch).unwrap(),
+            PullSummary::default()
+        );
+    }
+
+    #[test]
+    fn test_transient_error_classification() {
+        let network = git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Net,
+            "connection reset",
+        );
+        assert!(is_transient_error(
+            &anyhow::Error::from(network).context("Failed to fetch")
+        ));
+
+        let auth = git2::Error::new(
+            git2::ErrorCode::Auth,
+            git2::ErrorClass::Http,
+            "authentication required",
+        );
+        let auth = anyhow::Error::from(auth).context("Failed to push");
+        assert!(!is_transient_error(&auth));
+        assert!(is_auth_error(&auth));
+
+        let rejected = git2::Error::new(
+            git2::ErrorCode::NotFastForward,
+            git2::ErrorClass::Reference,
+            "not fast-forward",
+        );
+        let rejected = anyhow::Error::from(rejected).context("Failed to push to remote");
+        assert!(!is_transient_error(&rejected));
+        assert!(is_push_rejected(&rejected));
+        assert!(!is_push_rejected(&auth));
+        let refused = anyhow::Error::new(PushRejected {
+            reference: "refs/heads/main".to_string(),
+            reason: "protected branch hook declined".to_string(),
+        })
+        .context("Failed to push to remote");
+        assert!(is_push_rejected(&refused));
+        assert!(!is_transient_error(&anyhow::anyhow!("not a git error")));
+        assert!(!is_push_rejected(&anyhow::anyhow!("not a git error")));
+    }
+
+    #[test]
+    fn test_fetch_depth() {
+        assert_eq!(fetch_depth(0), 1);
+        assert_eq!(fetch_depth(50), 50);
+        assert!(fetch_depth(u32::MAX) < UNSHALLOW_DEPTH);
+    }
+
+    #[test]
+    fn test_full_clone_is_not_shallow() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(temp_dir.path()).unwrap();
+        assert!(!repo.is_shallow());
+    }
+
+    /// Commit the current index with an explicit timestamp
+    fn commit_at(repo: &GitRepo, message: &str, seconds: i64) -> git2::Oid {
+        let signature =
+            Signature::new("Test", "test@example.com", &git2::Time::new(seconds, 0)).unwrap();
+        let tree_id = repo.repo.index().unwrap().write_tree().unwrap();
+        let tree = repo.repo.find_tree(tree_id).unwrap();
+        let parent = repo.repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_squash_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo_path = temp_dir.path();
+        let repo = GitRepo::init(repo_path).unwrap();
+
+        let day = 86_400;
+        let now = Utc::now().timestamp();
+        for (i, age_days) in [30, 20, 10, 1, 0].into_iter().enumerate() {
+            create_test_file(repo_path, "file.txt", &format!("version {i}"));
+            repo.add_file("file.txt").unwrap();
+            commit_at(&repo, &format!("Commit {i}"), now - age_days * day);
+        }
+        let tree_before = repo.repo.head().unwrap().peel_to_tree().unwrap().id();
+
+        // Nothing older than 60 days
+        let cutoff = Utc::now() - chrono::Duration::days(60);
+        assert_eq!(repo.plan_squash(cutoff).unwrap(), None);
+
+        let cutoff = Utc::now() - chrono::Duration::days(5);
+        let plan = repo.plan_squash(cutoff).unwrap().unwrap();
+        assert_eq!(plan.squashed, 3);
+        assert_eq!(plan.kept, 2);
+
+        repo.squash_history(&plan).unwrap();
+
+        let mut walk = repo.repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        let messages: Vec<String> = walk
+            .map(|oid| {
+                let commit = repo.repo.find_commit(oid.unwrap()).unwrap();
+                commit.message().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0], "Commit 4");
+        assert_eq!(messages[1], "Commit 3");
+        assert!(messages[2].starts_with("Baseline: 3 commits"));
+        assert_eq!(
+            repo.repo.head().unwrap().peel_to_tree().unwrap().id(),
+            tree_before
+        );
+        assert!(repo.is_clean().unwrap());
+
+        // Squashing again finds only the new root commit
+        assert_eq!(repo.plan_squash(cutoff).unwrap(), None);
+
+        // Succeeds (or is skipped without git installed) and keeps the new history
+        repo.gc().unwrap();
+        assert_eq!(
+            repo.repo.head().unwrap().peel_to_tree().unwrap().id(),
+            tree_before
+        );
+    }
+
+    #[test]
+    fn test_remotes_lists_primary_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut repo = GitRepo::init(temp_dir.path()).unwrap();
+        assert!(repo.remotes().unwrap().is_empty());
+
+        repo.add_remote("backup", "https://git.example.com/me/bookmarks.git")
+            .unwrap();
+        repo.add_remote("origin", "https://github.com/me/bookmarks.git")
+            .unwrap();
+        repo.add_remote("archive", "https://archive.example.com/bookmarks.git")
+            .unwrap();
+        assert_eq!(repo.remotes().unwrap(), vec!["origin", "archive", "backup"]);
+    }
+
+    #[test]
+    fn test_push_outcome_serialization() {
+        let push = RemotePush {
+            remote: "backup".to_string(),
+            outcome: PushOutcome::Queued {
+                error: "offline".to_string(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&push).unwrap(),
+            serde_json::json!({ "remote": "backup", "status": "queued", "error": "offline" })
+        );
+    }
+
+    #[test]
+    fn test_device_identity_in_commits() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo_path = temp_dir.path();
+        let repo = GitRepo::init(repo_path).unwrap();
+        assert_eq!(repo.device_name(), None);
+
+        create_test_file(repo_path, "file.txt", "one");
+        repo.add_file("file.txt").unwrap();
+        repo.commit("Before naming").unwrap();
+
+        assert!(repo.set_device_name("bad\nname").is_err());
+        repo.set_device_name("work-laptop").unwrap();
+        assert_eq!(repo.device_name().as_deref(), Some("work-laptop"));
+        repo.set_local_config("user.name", Some("Jane")).unwrap();
+        assert_eq!(repo.author_name().as_deref(), Some("Jane"));
+
+        create_test_file(repo_path, "file.txt", "two");
+        repo.add_file("file.txt").unwrap();
+        repo.commit("After naming").unwrap();
+
+        let history = repo.history(10).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].summary, "After naming");
+        assert_eq!(history[0].device.as_deref(), Some("work-laptop"));
+        assert!(history[0].author.ends_with("(work-laptop)"));
+        assert_eq!(history[1].device, None);
+
+        assert_eq!(repo.history(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_read_file_at() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo_path = temp_dir.path();
+        let repo = GitRepo::init(repo_path).unwrap();
+
+        create_test_file(repo_path, "file1.txt", "first");
+        repo.add_file("file1.txt").unwrap();
+        let first = repo.commit("First commit").unwrap();
+
+        create_test_file(repo_path, "file1.txt", "second");
+        repo.add_file("file1.txt").unwrap();
+        repo.commit("Second commit").unwrap();
+
+        assert_eq!(
+            repo.read_file_at(&first.to_string(), "file1.txt").unwrap(),
+            Some(b"first".to_vec())
+        );
+        assert_eq!(
+            repo.read_file_at("HEAD", "file1.txt").unwrap(),
+            Some(b"second".to_vec())
+        );
+        assert_eq!(repo.read_file_at("HEAD", "missing.txt").unwrap(), None);
+        assert!(repo.read_file_at("no-such-rev", "file1.txt").is_err());
+    }
+
+    #[test]
+    fn test_ahead_behind() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo_path = temp_dir.path();
+        let repo = GitRepo::init(repo_path).unwrap();
+
+        // No commits, so no branch to compare
+        assert_eq!(repo.ahead_behind("origin").unwrap(), None);
+
+        create_test_file(repo_path, "file1.txt", "content1");
+        repo.add_file("file1.txt").unwrap();
+        let base = repo.commit("First commit").unwrap();
+        let branch = repo.current_branch().unwrap();
+
+        // No remote-tracking branch yet
+        assert_eq!(repo.ahead_behind("origin").unwrap(), None);
+
+        let tracking = format!("refs/remotes/origin/{branch}");
+        repo.repo.reference(&tracking, base, true, "test").unwrap();
+        assert_eq!(
+            repo.ahead_behind("origin").unwrap(),
+            Some(AheadBehind {
+                ahead: 0,
+                behind: 0
+            })
+        );
+
+        // Two local commits
+        repo.commit("Second commit").unwrap();
+        repo.commit("Third commit").unwrap();
+
+        // One remote commit on top of the base
+        let signature = repo.get_signature().unwrap();
+        let base_commit = repo.repo.find_commit(base).unwrap();
+        repo.repo
+            .commit(
+                Some(&tracking),
+                &signature,
+                &signature,
+                "Remote commit",
+                &base_commit.tree().unwrap(),
+                &[&base_commit],
+            )
+            .unwrap();
+
+        assert_eq!(
+            repo.ahead_behind("origin").unwrap(),
+            Some(AheadBehind {
+                ahead: 2,
+                behind: 1
+            })
+        );
+    }
+
     // Note: Testing clone, push, pull requires a real git server or complex mocking
     // These would be covered in integration tests with a local git server
 }
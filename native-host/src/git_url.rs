@@ -79,6 +79,33 @@ pub fn convert_https_to_ssh(url: &str) -> Result<String> {
     Ok(format!("git@{host}:{path}.git"))
 }
 
+/// Owner and name of a repository hosted on github.com, for either URL form
+/// Example: `git@github.com:user/repo.git` → `("user", "repo")`
+pub fn github_repo(url: &str) -> Option<(String, String)> {
+    let https = if parse_git_url(url).ok()? == GitUrlType::Ssh {
+        convert_ssh_to_https(url).ok()?
+    } else {
+        url.to_string()
+    };
+    let captures = HTTPS_URL_PATTERN.captures(&https)?;
+    if !captures.get(1)?.as_str().eq_ignore_ascii_case("github.com") {
+        return None;
+    }
+    let (owner, repo) = captures.get(2)?.as_str().split_once('/')?;
+    (!repo.contains('/')).then(|| (owner.to_string(), repo.to_string()))
+}
+
+/// Git LFS server of a repository, by the convention every forge follows
+/// Example: `git@github.com:user/repo.git` → `https://github.com/user/repo.git/info/lfs`
+pub fn lfs_endpoint(url: &str) -> Result<String> {
+    let https = match parse_git_url(url)? {
+        GitUrlType::Ssh => convert_ssh_to_https(url)?,
+        GitUrlType::Https => url.to_string(),
+    };
+    let base = https.trim_end_matches('/').trim_end_matches(".git");
+    Ok(format!("{base}.git/info/lfs"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -189,6 +216,34 @@ mod tests {
         assert!(convert_https_to_ssh("git@github.com:user/repo").is_err());
     }
 
+    #[test]
+    fn test_github_repo() {
+        let expected = Some(("user".to_string(), "repo".to_string()));
+        assert_eq!(github_repo("https://github.com/user/repo.git"), expected);
+        assert_eq!(github_repo("git@github.com:user/repo.git"), expected);
+        assert_eq!(github_repo("ssh://git@github.com/user/repo"), expected);
+        assert_eq!(github_repo("https://gitlab.com/user/repo.git"), None);
+        assert_eq!(github_repo("https://github.com/user"), None);
+    }
+
+    #[test]
+    fn test_lfs_endpoint() {
+        let expected = "https://github.com/user/repo.git/info/lfs";
+        assert_eq!(
+            lfs_endpoint("git@github.com:user/repo.git").unwrap(),
+            expected
+        );
+        assert_eq!(
+            lfs_endpoint("https://github.com/user/repo").unwrap(),
+            expected
+        );
+        assert_eq!(
+            lfs_endpoint("https://github.com/user/repo.git/").unwrap(),
+            expected
+        );
+        assert!(lfs_endpoint("not-a-url").is_err());
+    }
+
     #[test]
     fn test_roundtrip_conversion() {
         let original_ssh = "git@github.com:user/repo.git";
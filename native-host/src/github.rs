@@ -1,13 +1,36 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use keyring::Entry;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 
+use crate::proxy;
+use crate::retry::RetryPolicy;
+
+const API_BASE: &str = "https://api.github.com";
+/// Host of the OAuth endpoints, which aren't part of the API
+const LOGIN_BASE: &str = "https://github.com";
 const GITHUB_CLIENT_ID: &str = "Ov23liYifB4i3sUooRaE"; // WebTags OAuth app
 const KEYRING_SERVICE: &str = "com.webtags.github";
 const KEYRING_USERNAME: &str = "github_token";
+const KEYRING_METADATA_USERNAME: &str = "github_token_metadata";
+
+/// How long a successful validation is trusted before asking GitHub again
+const VALIDATION_TTL: chrono::Duration = chrono::Duration::hours(1);
+/// A token this close to expiring is reported as `expiring`
+const EXPIRY_WARNING: chrono::Duration = chrono::Duration::days(7);
+/// Response header carrying the expiry of fine-grained and expiring OAuth tokens
+const EXPIRATION_HEADER: &str = "github-authentication-token-expiration";
+/// Response header listing a classic token's scopes; fine-grained tokens omit it
+const SCOPES_HEADER: &str = "x-oauth-scopes";
+
+/// Scopes WebTags has a use for: pushing to the bookmarks repository, and
+/// the Gist backend
+const USEFUL_SCOPES: &[&str] = &["repo", "gist"];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeviceCodeResponse {
@@ -23,6 +46,11 @@ pub struct AccessTokenResponse {
     pub access_token: String,
     pub token_type: String,
     pub scope: String,
+    /// Only set for apps with expiring user tokens
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -30,9 +58,192 @@ pub struct TokenPollResponse {
     pub access_token: Option<String>,
     pub token_type: Option<String>,
     pub scope: Option<String>,
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
     pub error: Option<String>,
 }
 
+/// GitHub answered 401 to a request made with the stored token
+#[derive(Debug, thiserror::Error)]
+#[error("GitHub rejected the stored token; sign in again")]
+pub struct TokenRejected;
+
+/// What is known about the stored token, kept in the keychain beside it
+#[derive(Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    /// `None` for tokens that never expire
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Last time GitHub accepted or rejected the token
+    #[serde(default)]
+    pub validated_at: Option<DateTime<Utc>>,
+    /// GitHub turned the token down; only signing in again clears this
+    #[serde(default)]
+    pub rejected: bool,
+    /// A classic token's scopes; `None` for fine-grained tokens, whose
+    /// permissions are per repository
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    /// Exchanged for a new token once this one expires
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+// Hand-written so the refresh token never reaches a log
+impl std::fmt::Debug for TokenMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenMetadata")
+            .field("expires_at", &self.expires_at)
+            .field("validated_at", &self.validated_at)
+            .field("rejected", &self.rejected)
+            .field("scopes", &self.scopes)
+            .field(
+                "refresh_token",
+                &self.refresh_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+/// The stored token's standing, as reported in `Status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenState {
+    /// No token stored
+    Missing,
+    Valid,
+    /// Valid, but expires within a week
+    Expiring,
+    Expired,
+    /// GitHub refused the token; send `Reauth`
+    Rejected,
+    /// Never validated, and GitHub couldn't be reached to check
+    Unknown,
+}
+
+impl TokenMetadata {
+    pub fn state(&self, now: DateTime<Utc>) -> TokenState {
+        if self.rejected {
+            return TokenState::Rejected;
+        }
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now => TokenState::Expired,
+            Some(expires_at) if expires_at - now <= EXPIRY_WARNING => TokenState::Expiring,
+            _ if self.validated_at.is_some() => TokenState::Valid,
+            _ => TokenState::Unknown,
+        }
+    }
+
+    /// Whether the cached validation is too old to trust
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        self.validated_at
+            .is_none_or(|validated_at| now - validated_at > VALIDATION_TTL)
+    }
+
+    fn issued(response: &AccessTokenResponse, now: DateTime<Utc>) -> Self {
+        Self {
+            expires_at: response
+                .expires_in
+                .and_then(|secs| i64::try_from(secs).ok())
+                .map(|secs| now + chrono::Duration::seconds(secs)),
+            validated_at: Some(now),
+            rejected: false,
+            scopes: Some(parse_scopes(&response.scope)),
+            refresh_token: response.refresh_token.clone(),
+        }
+    }
+}
+
+/// How a token's scopes compare with what WebTags needs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScopeReport {
+    /// `None` for fine-grained tokens
+    pub scopes: Option<Vec<String>>,
+    /// Granted but never used; a leaked token could do more than it needs to
+    pub excess_scopes: Vec<String>,
+    /// Needed but not granted
+    pub missing_scopes: Vec<String>,
+    /// Whether the token may push to the bookmarks repository, when it is on
+    /// GitHub and GitHub could be asked
+    pub can_push: Option<bool>,
+}
+
+impl ScopeReport {
+    /// Compare granted `scopes` with the `required` ones. `repo:*` and
+    /// `public_repo` are narrower than `repo`, so they aren't excess.
+    pub fn new(scopes: Option<Vec<String>>, required: &[&str]) -> Self {
+        let granted = scopes.as_deref().unwrap_or_default();
+        let excess_scopes = granted
+            .iter()
+            .filter(|scope| {
+                !USEFUL_SCOPES.contains(&scope.as_str())
+                    && !scope.starts_with("repo:")
+                    && *scope != "public_repo"
+            })
+            .cloned()
+            .collect();
+        let missing_scopes = if scopes.is_some() {
+            required
+                .iter()
+                .filter(|scope| !granted.iter().any(|granted| granted == *scope))
+                .map(|scope| (*scope).to_string())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self {
+            scopes,
+            excess_scopes,
+            missing_scopes,
+            can_push: None,
+        }
+    }
+
+    /// Whether the token can't do everything WebTags needs
+    pub fn is_insufficient(&self) -> bool {
+        !self.missing_scopes.is_empty() || self.can_push == Some(false)
+    }
+}
+
+fn parse_scopes(header: &str) -> Vec<String> {
+    header
+        .split([',', ' '])
+        .filter(|scope| !scope.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The `rel="next"` URL from a `Link` pagination header
+fn next_page(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    let link = headers.get(reqwest::header::LINK)?.to_str().ok()?;
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+/// Parse the expiry header, e.g. `2024-05-01 12:00:00 UTC`
+fn parse_expiration(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S %z")
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S UTC")
+                .ok()
+                .map(|time| time.and_utc())
+        })
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateRepoRequest {
     pub name: String,
@@ -49,30 +260,195 @@ pub struct Repository {
     pub clone_url: String,
     pub ssh_url: String,
     pub private: bool,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
+/// A repository offered in the setup picker
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RepoChoice {
+    pub name: String,
+    pub full_name: String,
+    pub clone_url: String,
+    pub ssh_url: String,
+    pub private: bool,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Holds WebTags bookmarks already; `None` if GitHub couldn't say
+    pub has_bookmarks: Option<bool>,
+}
+
+/// Repositories fetched per page, GitHub's maximum
+const REPOS_PER_PAGE: usize = 100;
+/// Stop paging after this many repositories
+const MAX_LISTED_REPOS: usize = 1000;
+
 pub struct GitHubClient {
     client: Client,
+    api_base: String,
+    login_base: String,
+    retry: RetryPolicy,
+}
+
+/// Poll interval when a flow's own isn't known, e.g. after a host restart
+pub const DEFAULT_POLL_INTERVAL: u64 = 5;
+/// GitHub's device code lifetime, for flows this host didn't start
+pub const DEFAULT_DEVICE_CODE_LIFETIME: Duration = Duration::from_mins(15);
+const SLOW_DOWN_SECS: u64 = 5;
+/// How quickly a waiting poll notices it was cancelled
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A device flow started by this host
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceFlow {
+    pub interval: u64,
+    pub expires_at: Instant,
+    pub cancelled: bool,
+}
+
+/// Device flows in progress, by device code. A cancel has to reach a poll
+/// while its request is still being handled, so the stdin reader flags it
+/// here as soon as it arrives.
+static DEVICE_FLOWS: Mutex<BTreeMap<String, DeviceFlow>> = Mutex::new(BTreeMap::new());
+
+fn device_flows() -> std::sync::MutexGuard<'static, BTreeMap<String, DeviceFlow>> {
+    DEVICE_FLOWS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Remember a started flow's polling interval and lifetime
+pub fn track_device_flow(started: &DeviceCodeResponse) {
+    device_flows().insert(
+        started.device_code.clone(),
+        DeviceFlow {
+            interval: started.interval.max(1),
+            expires_at: Instant::now() + Duration::from_secs(started.expires_in),
+            cancelled: false,
+        },
+    );
+}
+
+pub fn device_flow(device_code: &str) -> Option<DeviceFlow> {
+    device_flows().get(device_code).copied()
+}
+
+/// Ask a poll for `device_code` to stop. Returns whether the flow was known.
+pub fn cancel_device_flow(device_code: &str) -> bool {
+    device_flows()
+        .get_mut(device_code)
+        .map(|flow| flow.cancelled = true)
+        .is_some()
+}
+
+pub fn forget_device_flow(device_code: &str) {
+    device_flows().remove(device_code);
+}
+
+/// Sleep for `duration`, cutting it short if the flow is cancelled
+async fn wait_unless_cancelled(device_code: &str, duration: Duration) -> Result<()> {
+    let deadline = Instant::now() + duration;
+    loop {
+        if device_flow(device_code).is_some_and(|flow| flow.cancelled) {
+            return Err(AuthCancelled.into());
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(());
+        }
+        sleep(remaining.min(CANCEL_CHECK_INTERVAL)).await;
+    }
+}
+
+/// The user cancelled a device flow with `AuthCancel`
+#[derive(Debug, thiserror::Error)]
+#[error("Sign-in was cancelled")]
+pub struct AuthCancelled;
+
+/// A response status worth retrying: server errors and rate limiting
+#[derive(Debug, thiserror::Error)]
+#[error("GitHub API error: {0}")]
+pub struct RetryableStatus(pub reqwest::StatusCode);
+
+/// Whether a failed API call is worth retrying: timeouts, connection
+/// failures, 5xx responses, and 429s are; other statuses are not.
+pub fn is_transient_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<RetryableStatus>()
+            || cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(|e| e.is_timeout() || e.is_connect())
+    })
+}
+
+/// Send a request, turning retryable statuses into [`RetryableStatus`] errors
+pub(crate) async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(RetryableStatus(status).into());
+    }
+    Ok(response)
 }
 
 impl GitHubClient {
     pub fn new() -> Self {
+        let client = proxy::http_client().unwrap_or_else(|e| {
+            log::warn!("Ignoring proxy configuration: {e:#}");
+            Client::new()
+        });
         Self {
-            client: Client::new(),
+            client,
+            api_base: API_BASE.to_string(),
+            login_base: LOGIN_BASE.to_string(),
+            retry: RetryPolicy::default(),
         }
     }
 
+    /// Talk to a different API server (GitHub Enterprise, or tests)
+    #[must_use]
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Run the OAuth device flow against a different server
+    #[must_use]
+    pub fn with_login_base(mut self, login_base: &str) -> Self {
+        self.login_base = login_base.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Send requests with `client` instead of one built from the proxy settings
+    #[must_use]
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Use a different retry policy for API calls
+    #[must_use]
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
     /// Start OAuth device flow
     pub async fn start_device_flow(&self) -> Result<DeviceCodeResponse> {
         let response = self
-            .client
-            .post("https://github.com/login/device/code")
-            .header("Accept", "application/json")
-            .form(&[
-                ("client_id", GITHUB_CLIENT_ID),
-                ("scope", "repo"), // Full access to private repositories
-            ])
-            .send()
+            .retry
+            .run_async(
+                || {
+                    send(
+                        self.client
+                            .post(format!("{}/login/device/code", self.login_base))
+                            .header("Accept", "application/json")
+                            .form(&[
+                                ("client_id", GITHUB_CLIENT_ID),
+                                // Full access to private repositories, and gists for the Gist backend
+                                ("scope", "repo gist"),
+                            ]),
+                    )
+                },
+                is_transient_error,
+            )
             .await
             .context("Failed to start device flow")?;
 
@@ -96,26 +472,50 @@ impl GitHubClient {
         device_code: &str,
         interval: u64,
     ) -> Result<AccessTokenResponse> {
-        let mut attempts = 0;
-        let max_attempts = 100; // 100 * interval seconds timeout
+        // 100 * interval seconds timeout
+        let timeout = Duration::from_secs(interval.saturating_mul(100));
+        self.poll_for_token_until(device_code, interval, timeout, |_| {})
+            .await
+    }
+
+    /// Poll for an OAuth access token for up to `timeout`, calling `on_wait`
+    /// with the time left before each wait. Gives up with [`AuthCancelled`]
+    /// once [`cancel_device_flow`] is called for `device_code`.
+    pub async fn poll_for_token_until(
+        &self,
+        device_code: &str,
+        mut interval: u64,
+        timeout: Duration,
+        mut on_wait: impl FnMut(Duration),
+    ) -> Result<AccessTokenResponse> {
+        let deadline = Instant::now() + timeout;
 
         loop {
-            if attempts >= max_attempts {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
                 anyhow::bail!("Timeout waiting for user authorization");
             }
-
-            sleep(Duration::from_secs(interval)).await;
+            on_wait(remaining);
+            wait_unless_cancelled(device_code, Duration::from_secs(interval).min(remaining))
+                .await?;
 
             let response = self
-                .client
-                .post("https://github.com/login/oauth/access_token")
-                .header("Accept", "application/json")
-                .form(&[
-                    ("client_id", GITHUB_CLIENT_ID),
-                    ("device_code", device_code),
-                    ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
-                ])
-                .send()
+                .retry
+                .run_async(
+                    || {
+                        send(
+                            self.client
+                                .post(format!("{}/login/oauth/access_token", self.login_base))
+                                .header("Accept", "application/json")
+                                .form(&[
+                                    ("client_id", GITHUB_CLIENT_ID),
+                                    ("device_code", device_code),
+                                    ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                                ]),
+                        )
+                    },
+                    is_transient_error,
+                )
                 .await
                 .context("Failed to poll for token")?;
 
@@ -129,18 +529,18 @@ impl GitHubClient {
                     access_token,
                     token_type: poll_response.token_type.unwrap_or_default(),
                     scope: poll_response.scope.unwrap_or_default(),
+                    expires_in: poll_response.expires_in,
+                    refresh_token: poll_response.refresh_token,
                 });
             }
 
             match poll_response.error.as_deref() {
                 Some("authorization_pending") => {
                     // Continue polling
-                    attempts += 1;
                 }
                 Some("slow_down") => {
-                    // Increase interval
-                    sleep(Duration::from_secs(interval)).await;
-                    attempts += 1;
+                    // The device flow spec asks for 5 more seconds per slow_down
+                    interval += SLOW_DOWN_SECS;
                 }
                 Some("expired_token") => {
                     anyhow::bail!("Device code expired");
@@ -172,16 +572,17 @@ impl GitHubClient {
             auto_init: true, // Initialize with README
         };
 
-        let response = self
-            .client
-            .post("https://api.github.com/user/repos")
-            .header("Accept", "application/vnd.github+json")
-            .header("Authorization", format!("Bearer {token}"))
-            .header("User-Agent", "WebTags")
-            .json(&request)
-            .send()
-            .await
-            .context("Failed to create repository")?;
+        // Not retried: a timed-out request may still have created the repository
+        let response = send(
+            self.client
+                .post(format!("{}/user/repos", self.api_base))
+                .header("Accept", "application/vnd.github+json")
+                .header("Authorization", format!("Bearer {token}"))
+                .header("User-Agent", "WebTags")
+                .json(&request),
+        )
+        .await
+        .context("Failed to create repository")?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -199,17 +600,278 @@ impl GitHubClient {
 
     /// Validate a token by making a test API call
     pub async fn validate_token(&self, token: &str) -> Result<bool> {
+        Ok(!self.check_token(token).await?.rejected)
+    }
+
+    /// Ask GitHub about a token: whether it is accepted, and when it expires
+    pub async fn check_token(&self, token: &str) -> Result<TokenMetadata> {
         let response = self
-            .client
-            .get("https://api.github.com/user")
-            .header("Accept", "application/vnd.github+json")
-            .header("Authorization", format!("Bearer {token}"))
-            .header("User-Agent", "WebTags")
-            .send()
+            .retry
+            .run_async(
+                || {
+                    send(
+                        self.client
+                            .get(format!("{}/user", self.api_base))
+                            .header("Accept", "application/vnd.github+json")
+                            .header("Authorization", format!("Bearer {token}"))
+                            .header("User-Agent", "WebTags"),
+                    )
+                },
+                is_transient_error,
+            )
             .await
             .context("Failed to validate token")?;
 
-        Ok(response.status().is_success())
+        let status = response.status();
+        if !status.is_success() && status != reqwest::StatusCode::UNAUTHORIZED {
+            anyhow::bail!("GitHub API error: {status}");
+        }
+        Ok(TokenMetadata {
+            expires_at: response
+                .headers()
+                .get(EXPIRATION_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_expiration),
+            validated_at: Some(Utc::now()),
+            rejected: !status.is_success(),
+            scopes: response
+                .headers()
+                .get(SCOPES_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(parse_scopes),
+            refresh_token: None,
+        })
+    }
+
+    /// Whether the token may push to `owner/repo`. A repository the token
+    /// can't see at all answers 404, which counts as no.
+    pub async fn can_push(&self, token: &str, owner: &str, repo: &str) -> Result<bool> {
+        #[derive(Deserialize)]
+        struct Permissions {
+            #[serde(default)]
+            push: bool,
+        }
+        #[derive(Deserialize)]
+        struct RepoPermissions {
+            permissions: Option<Permissions>,
+        }
+
+        let url = format!("{}/repos/{owner}/{repo}", self.api_base);
+        let response = self
+            .retry
+            .run_async(
+                || {
+                    send(
+                        self.client
+                            .get(&url)
+                            .header("Accept", "application/vnd.github+json")
+                            .header("Authorization", format!("Bearer {token}"))
+                            .header("User-Agent", "WebTags"),
+                    )
+                },
+                is_transient_error,
+            )
+            .await
+            .context("Failed to check repository permissions")?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        if !status.is_success() {
+            anyhow::bail!("GitHub API error: {status}");
+        }
+        let repo: RepoPermissions = response
+            .json()
+            .await
+            .context("Failed to parse repository response")?;
+        Ok(repo.permissions.is_some_and(|permissions| permissions.push))
+    }
+
+    /// The user's repositories, most recently updated first
+    pub async fn list_repositories(&self, token: &str) -> Result<Vec<Repository>> {
+        let mut repos = Vec::new();
+        let mut url = Some(format!(
+            "{}/user/repos?per_page={REPOS_PER_PAGE}&sort=updated&affiliation=owner,collaborator",
+            self.api_base
+        ));
+        while let Some(page) = url.take() {
+            let response = self
+                .retry
+                .run_async(
+                    || {
+                        send(
+                            self.client
+                                .get(&page)
+                                .header("Accept", "application/vnd.github+json")
+                                .header("Authorization", format!("Bearer {token}"))
+                                .header("User-Agent", "WebTags"),
+                        )
+                    },
+                    is_transient_error,
+                )
+                .await
+                .context("Failed to list repositories")?;
+
+            let status = response.status();
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                return Err(TokenRejected.into());
+            }
+            if !status.is_success() {
+                // Don't include response body in error (may contain sensitive data)
+                anyhow::bail!("Failed to list repositories: {status}");
+            }
+            url = next_page(response.headers());
+            let batch: Vec<Repository> = response
+                .json()
+                .await
+                .context("Failed to parse repository list")?;
+            repos.extend(batch);
+            if repos.len() >= MAX_LISTED_REPOS {
+                break;
+            }
+        }
+        Ok(repos)
+    }
+
+    /// Whether `full_name` has `path` on its default branch
+    pub async fn has_file(&self, token: &str, full_name: &str, path: &str) -> Result<bool> {
+        let url = format!("{}/repos/{full_name}/contents/{path}", self.api_base);
+        let response = self
+            .retry
+            .run_async(
+                || {
+                    send(
+                        self.client
+                            .head(&url)
+                            .header("Accept", "application/vnd.github+json")
+                            .header("Authorization", format!("Bearer {token}"))
+                            .header("User-Agent", "WebTags"),
+                    )
+                },
+                is_transient_error,
+            )
+            .await
+            .context("Failed to check repository contents")?;
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            // An empty repository answers 409
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::CONFLICT => Ok(false),
+            status => anyhow::bail!("GitHub API error: {status}"),
+        }
+    }
+
+    /// Revoke a token through GitHub's credential revocation API, which
+    /// takes the token itself rather than an app secret, so it works for
+    /// device flow tokens and personal access tokens alike
+    pub async fn revoke_token(&self, token: &str) -> Result<()> {
+        let body = serde_json::json!({ "credentials": [token] });
+        let response = self
+            .retry
+            .run_async(
+                || {
+                    send(
+                        self.client
+                            .post(format!("{}/credentials/revoke", self.api_base))
+                            .header("Accept", "application/vnd.github+json")
+                            .header("User-Agent", "WebTags")
+                            .json(&body),
+                    )
+                },
+                is_transient_error,
+            )
+            .await
+            .context("Failed to revoke token")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("Failed to revoke token: {status}");
+        }
+        Ok(())
+    }
+
+    /// Exchange a refresh token for a new token
+    pub async fn refresh_token(&self, refresh_token: &str) -> Result<AccessTokenResponse> {
+        let response = self
+            .retry
+            .run_async(
+                || {
+                    send(
+                        self.client
+                            .post(format!("{}/login/oauth/access_token", self.login_base))
+                            .header("Accept", "application/json")
+                            .form(&[
+                                ("client_id", GITHUB_CLIENT_ID),
+                                ("refresh_token", refresh_token),
+                                ("grant_type", "refresh_token"),
+                            ]),
+                    )
+                },
+                is_transient_error,
+            )
+            .await
+            .context("Failed to refresh token")?;
+
+        let refreshed: TokenPollResponse = response
+            .json()
+            .await
+            .context("Failed to parse refresh response")?;
+        match (refreshed.access_token, refreshed.error) {
+            (Some(access_token), _) => Ok(AccessTokenResponse {
+                access_token,
+                token_type: refreshed.token_type.unwrap_or_default(),
+                scope: refreshed.scope.unwrap_or_default(),
+                expires_in: refreshed.expires_in,
+                refresh_token: refreshed.refresh_token,
+            }),
+            (None, Some(error)) => anyhow::bail!("OAuth error: {error}"),
+            (None, None) => anyhow::bail!("Unexpected response from GitHub"),
+        }
+    }
+
+    /// The stored token's state, asking GitHub at most once per
+    /// [`VALIDATION_TTL`]. An expired token with a refresh token is renewed.
+    pub async fn token_state(&self) -> TokenState {
+        if get_token().is_err() {
+            return TokenState::Missing;
+        }
+        let now = Utc::now();
+        let mut metadata = get_token_metadata();
+
+        if metadata.state(now) == TokenState::Expired {
+            if let Some(refresh) = metadata.refresh_token.clone() {
+                match self.refresh_token(&refresh).await {
+                    Ok(issued) => match store_issued_token(&issued) {
+                        Ok(()) => return TokenState::Valid,
+                        Err(e) => log::warn!("Failed to store refreshed token: {e:#}"),
+                    },
+                    Err(e) => log::warn!("Failed to refresh GitHub token: {e:#}"),
+                }
+            }
+            return TokenState::Expired;
+        }
+        if metadata.rejected || !metadata.is_stale(now) {
+            return metadata.state(now);
+        }
+
+        let Ok(token) = get_token() else {
+            return TokenState::Missing;
+        };
+        match self.check_token(&token).await {
+            Ok(checked) => {
+                // GitHub only reports expiry for some tokens; keep what the
+                // device flow told us otherwise
+                metadata.expires_at = checked.expires_at.or(metadata.expires_at);
+                metadata.validated_at = checked.validated_at;
+                metadata.rejected = checked.rejected;
+                if let Err(e) = store_token_metadata(&metadata) {
+                    log::warn!("Failed to store token metadata: {e:#}");
+                }
+            }
+            // Offline: go with what we knew
+            Err(e) => log::info!("Could not validate GitHub token: {e:#}"),
+        }
+        metadata.state(Utc::now())
     }
 }
 
@@ -245,9 +907,51 @@ pub fn delete_token() -> Result<()> {
     entry
         .delete_password()
         .context("Failed to delete token from keychain")?;
+    if let Ok(metadata) = Entry::new(KEYRING_SERVICE, KEYRING_METADATA_USERNAME) {
+        let _ = metadata.delete_password();
+    }
     Ok(())
 }
 
+/// Store a token fresh from the device flow or a refresh, with its expiry
+pub fn store_issued_token(issued: &AccessTokenResponse) -> Result<()> {
+    store_token(&issued.access_token)?;
+    store_token_metadata(&TokenMetadata::issued(issued, Utc::now()))
+}
+
+/// Store what is known about the token alongside it
+pub fn store_token_metadata(metadata: &TokenMetadata) -> Result<()> {
+    let entry = Entry::new(KEYRING_SERVICE, KEYRING_METADATA_USERNAME)
+        .context("Failed to create keyring entry")?;
+    entry
+        .set_password(&serde_json::to_string(metadata)?)
+        .context("Failed to store token metadata in keychain")
+}
+
+/// What is known about the token; empty for tokens stored before metadata
+/// was kept, or if the keychain entry can't be read
+pub fn get_token_metadata() -> TokenMetadata {
+    Entry::new(KEYRING_SERVICE, KEYRING_METADATA_USERNAME)
+        .and_then(|entry| entry.get_password())
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Remember that GitHub refused the stored token, so `Status` reports it
+/// without another request. Does nothing when no token is stored.
+pub fn mark_token_rejected() {
+    if get_token().is_err() {
+        return;
+    }
+    let mut metadata = get_token_metadata();
+    metadata.rejected = true;
+    metadata.validated_at = Some(Utc::now());
+    if let Err(e) = store_token_metadata(&metadata) {
+        log::warn!("Failed to store token metadata: {e:#}");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -258,8 +962,8 @@ mod tests {
         assert!(std::mem::size_of_val(&client) > 0);
     }
 
-    // Note: Most tests require mocking the GitHub API
-    // These would use wiremock in integration tests
+    // The OAuth and repository endpoints are exercised against wiremock in
+    // tests/github_tests.rs
 
     #[tokio::test]
     async fn test_device_code_response_deserialization() {
@@ -305,6 +1009,230 @@ mod tests {
         assert!(repo.private);
     }
 
+    #[test]
+    fn test_transient_error_classification() {
+        let server_error =
+            anyhow::Error::from(RetryableStatus(reqwest::StatusCode::SERVICE_UNAVAILABLE))
+                .context("Failed to validate token");
+        assert!(is_transient_error(&server_error));
+        assert!(!is_transient_error(&anyhow::anyhow!(
+            "GitHub API error: 401"
+        )));
+    }
+
+    #[test]
+    fn test_token_state() {
+        let now = Utc::now();
+        let mut metadata = TokenMetadata::default();
+        assert_eq!(metadata.state(now), TokenState::Unknown);
+        assert!(metadata.is_stale(now));
+
+        metadata.validated_at = Some(now - chrono::Duration::minutes(5));
+        assert_eq!(metadata.state(now), TokenState::Valid);
+        assert!(!metadata.is_stale(now));
+        assert!(metadata.is_stale(now + chrono::Duration::hours(2)));
+
+        metadata.expires_at = Some(now + chrono::Duration::days(3));
+        assert_eq!(metadata.state(now), TokenState::Expiring);
+        assert_eq!(
+            metadata.state(now + chrono::Duration::days(4)),
+            TokenState::Expired
+        );
+
+        metadata.rejected = true;
+        assert_eq!(metadata.state(now), TokenState::Rejected);
+    }
+
+    #[test]
+    fn test_issued_token_metadata() {
+        let now = Utc::now();
+        let issued: AccessTokenResponse = serde_json::from_str(
+            r#"{"access_token": "ghu_x", "token_type": "bearer", "scope": "",
+                "expires_in": 28800, "refresh_token": "ghr_y"}"#,
+        )
+        .unwrap();
+        let metadata = TokenMetadata::issued(&issued, now);
+        assert_eq!(metadata.expires_at, Some(now + chrono::Duration::hours(8)));
+        assert_eq!(metadata.refresh_token.as_deref(), Some("ghr_y"));
+        assert!(!format!("{metadata:?}").contains("ghr_y"));
+    }
+
+    #[test]
+    fn test_scope_report() {
+        let scopes = parse_scopes("repo, gist, admin:org, delete_repo");
+        let report = ScopeReport::new(Some(scopes), &["repo"]);
+        assert_eq!(report.excess_scopes, ["admin:org", "delete_repo"]);
+        assert!(report.missing_scopes.is_empty());
+        assert!(!report.is_insufficient());
+
+        let report = ScopeReport::new(Some(parse_scopes("public_repo")), &["repo"]);
+        assert!(report.excess_scopes.is_empty());
+        assert_eq!(report.missing_scopes, ["repo"]);
+        assert!(report.is_insufficient());
+
+        // Fine-grained tokens have no scopes to judge
+        let mut report = ScopeReport::new(None, &["repo"]);
+        assert!(!report.is_insufficient());
+        report.can_push = Some(false);
+        assert!(report.is_insufficient());
+    }
+
+    #[test]
+    fn test_parse_expiration_header() {
+        let expected = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(parse_expiration("2024-05-01 12:00:00 UTC"), Some(expected));
+        assert_eq!(
+            parse_expiration("2024-05-01 12:00:00 +0000"),
+            Some(expected)
+        );
+        assert_eq!(parse_expiration("soon"), None);
+    }
+
+    fn repo_json(name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": 1,
+            "name": name,
+            "full_name": format!("me/{name}"),
+            "clone_url": format!("https://github.com/me/{name}.git"),
+            "ssh_url": format!("git@github.com:me/{name}.git"),
+            "private": true,
+            "updated_at": "2024-05-01T12:00:00Z",
+        })
+    }
+
+    #[tokio::test]
+    async fn test_list_repositories_follows_pages() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user/repos"))
+            .and(query_param("page", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json([repo_json("older")]))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user/repos"))
+            .and(query_param("sort", "updated"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(
+                        "Link",
+                        format!(
+                            r#"<{}/user/repos?page=2>; rel="next", <{0}/user/repos?page=2>; rel="last""#,
+                            server.uri()
+                        )
+                        .as_str(),
+                    )
+                    .set_body_json([repo_json("bookmarks")]),
+            )
+            .mount(&server)
+            .await;
+
+        let client = GitHubClient::new()
+            .with_api_base(&server.uri())
+            .with_retry_policy(RetryPolicy::none());
+        let repos = client.list_repositories("token").await.unwrap();
+        let names: Vec<_> = repos.iter().map(|repo| repo.name.as_str()).collect();
+        assert_eq!(names, ["bookmarks", "older"]);
+        assert!(repos[0].updated_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_has_file() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/repos/me/bookmarks/contents/bookmarks.json"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let client = GitHubClient::new()
+            .with_api_base(&server.uri())
+            .with_retry_policy(RetryPolicy::none());
+        assert!(client
+            .has_file("token", "me/bookmarks", "bookmarks.json")
+            .await
+            .unwrap());
+        assert!(!client
+            .has_file("token", "me/other", "bookmarks.json")
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_token() {
+        use wiremock::matchers::{body_json, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/credentials/revoke"))
+            .and(body_json(serde_json::json!({ "credentials": ["ghp_x"] })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = GitHubClient::new()
+            .with_api_base(&server.uri())
+            .with_retry_policy(RetryPolicy::none());
+        client.revoke_token("ghp_x").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_device_flow_stops_polling() {
+        let started = DeviceCodeResponse {
+            device_code: "cancel-me".to_string(),
+            user_code: "ABCD-1234".to_string(),
+            verification_uri: "https://github.com/login/device".to_string(),
+            expires_in: 900,
+            interval: 5,
+        };
+        track_device_flow(&started);
+        assert!(cancel_device_flow("cancel-me"));
+        assert!(!cancel_device_flow("unknown"));
+
+        let mut waits = Vec::new();
+        let err = GitHubClient::new()
+            .poll_for_token_until("cancel-me", 5, Duration::from_mins(15), |left| {
+                waits.push(left);
+            })
+            .await
+            .unwrap_err();
+        assert!(err.is::<AuthCancelled>());
+        assert_eq!(waits.len(), 1);
+
+        forget_device_flow("cancel-me");
+        assert!(device_flow("cancel-me").is_none());
+    }
+
+    #[test]
+    fn test_next_page() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(next_page(&headers), None);
+        headers.insert(
+            reqwest::header::LINK,
+            r#"<https://api.github.com/user/repos?page=1>; rel="prev", <https://api.github.com/user/repos?page=3>; rel="next""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            next_page(&headers).as_deref(),
+            Some("https://api.github.com/user/repos?page=3")
+        );
+    }
+
     // Keyring tests are platform-specific and may require mocking
     // Skip them in CI environments
 }
@@ -0,0 +1,400 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::git::GitRepo;
+
+/// Hook commands live in the repository's local git config, never in synced
+/// files, so a pulled commit can't make this device run anything
+const PRE_COMMIT_KEY: &str = "webtags.hooks.preCommit";
+const POST_SYNC_KEY: &str = "webtags.hooks.postSync";
+const TIMEOUT_KEY: &str = "webtags.hooks.timeout";
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 10;
+pub const MAX_TIMEOUT_SECS: u64 = 300;
+
+/// Output kept from each of stdout and stderr; the rest is read and dropped
+const OUTPUT_LIMIT: usize = 16 * 1024;
+/// The only variables a hook inherits from the host, which may hold tokens
+/// in its environment
+const INHERITED_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "TMPDIR",
+    "SYSTEMROOT",
+    "TEMP",
+];
+/// How long to wait for output still buffered after a hook exits, in case it
+/// left a background process holding the pipe
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Hook {
+    /// Before WebTags commits; a failure stops the commit
+    PreCommit,
+    /// After a successful sync; a failure is reported but changes nothing
+    PostSync,
+}
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::PreCommit => "pre-commit",
+            Hook::PostSync => "post-sync",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Hook::PreCommit => PRE_COMMIT_KEY,
+            Hook::PostSync => POST_SYNC_KEY,
+        }
+    }
+}
+
+/// Hook commands for one repository
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub pre_commit: Option<PathBuf>,
+    pub post_sync: Option<PathBuf>,
+    pub timeout_secs: u64,
+}
+
+/// Settings to change with [`Settings::update`]; `None` leaves one as it
+/// is and an empty command removes the hook
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Update {
+    #[serde(default)]
+    pub pre_commit: Option<String>,
+    #[serde(default)]
+    pub post_sync: Option<String>,
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+impl Settings {
+    pub fn load(repo: &GitRepo) -> Self {
+        Self {
+            pre_commit: repo.local_config(PRE_COMMIT_KEY).map(PathBuf::from),
+            post_sync: repo.local_config(POST_SYNC_KEY).map(PathBuf::from),
+            timeout_secs: repo
+                .local_config(TIMEOUT_KEY)
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(DEFAULT_TIMEOUT_SECS),
+        }
+    }
+
+    /// Change the settings given, leaving the others as they are
+    pub fn update(repo: &GitRepo, update: Update) -> Result<Self> {
+        if let Some(secs) = update.timeout_secs {
+            if !(1..=MAX_TIMEOUT_SECS).contains(&secs) {
+                anyhow::bail!("Hook timeout must be between 1 and {MAX_TIMEOUT_SECS} seconds");
+            }
+        }
+        let commands = [
+            (Hook::PreCommit, update.pre_commit),
+            (Hook::PostSync, update.post_sync),
+        ];
+        // Checked up front so a bad command leaves every setting alone
+        for (hook, command) in &commands {
+            if let Some(command) = command.as_deref().filter(|c| !c.is_empty()) {
+                validate_command(Path::new(command))
+                    .with_context(|| format!("Invalid {} hook", hook.name()))?;
+            }
+        }
+        for (hook, command) in commands {
+            if let Some(command) = command {
+                repo.set_local_config(
+                    hook.key(),
+                    Some(command.as_str()).filter(|c| !c.is_empty()),
+                )?;
+            }
+        }
+        if let Some(secs) = update.timeout_secs {
+            let secs = (secs != DEFAULT_TIMEOUT_SECS).then(|| secs.to_string());
+            repo.set_local_config(TIMEOUT_KEY, secs.as_deref())?;
+        }
+        Ok(Self::load(repo))
+    }
+
+    fn command(&self, hook: Hook) -> Option<&Path> {
+        match hook {
+            Hook::PreCommit => self.pre_commit.as_deref(),
+            Hook::PostSync => self.post_sync.as_deref(),
+        }
+    }
+}
+
+/// Hooks are executables run directly, without a shell, so there's no
+/// quoting to get wrong; an absolute path keeps `PATH` out of it
+fn validate_command(command: &Path) -> Result<()> {
+    if !command.is_absolute() {
+        anyhow::bail!("{} is not an absolute path", command.display());
+    }
+    if !command.is_file() {
+        anyhow::bail!("{} is not a file", command.display());
+    }
+    Ok(())
+}
+
+/// How a hook run went
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub hook: Hook,
+    /// `None` if it timed out or was killed by a signal
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Outcome {
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// One line for logs and error messages
+    pub fn describe(&self) -> String {
+        match self.exit_code {
+            _ if self.timed_out => format!("The {} hook timed out", self.hook.name()),
+            Some(code) => format!("The {} hook exited with code {code}", self.hook.name()),
+            None => format!("The {} hook was killed", self.hook.name()),
+        }
+    }
+}
+
+/// Run `hook` if the repository has one, passing the repository path as its
+/// argument and `summary` as JSON on stdin
+pub fn run(repo: &GitRepo, hook: Hook, summary: &serde_json::Value) -> Result<Option<Outcome>> {
+    let settings = Settings::load(repo);
+    let Some(command) = settings.command(hook) else {
+        return Ok(None);
+    };
+    let timeout = Duration::from_secs(settings.timeout_secs);
+    execute(command, hook, repo.path(), summary, timeout).map(Some)
+}
+
+fn execute(
+    command: &Path,
+    hook: Hook,
+    repo_path: &Path,
+    summary: &serde_json::Value,
+    timeout: Duration,
+) -> Result<Outcome> {
+    let inherited = INHERITED_ENV
+        .iter()
+        .filter_map(|name| std::env::var_os(name).map(|value| (name, value)));
+    let mut child = Command::new(command)
+        .arg(repo_path)
+        .current_dir(repo_path)
+        .env_clear()
+        .envs(inherited)
+        .env("WEBTAGS_HOOK", hook.name())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| {
+            format!(
+                "Failed to run the {} hook {}",
+                hook.name(),
+                command.display()
+            )
+        })?;
+
+    // A hook that ignores stdin may exit before reading it all, so the write
+    // happens off this thread and its broken pipe doesn't matter
+    let input = serde_json::to_vec(summary).context("Failed to serialize hook input")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    let stdout = capture(child.stdout.take());
+    let stderr = capture(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let (status, timed_out) = loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for hook")? {
+            break (Some(status), false);
+        }
+        if Instant::now() >= deadline {
+            log::warn!("Killing the {} hook after {timeout:?}", hook.name());
+            let _ = child.kill();
+            let _ = child.wait();
+            break (None, true);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+
+    Ok(Outcome {
+        hook,
+        exit_code: status.and_then(|status| status.code()),
+        timed_out,
+        stdout: collect(&stdout),
+        stderr: collect(&stderr),
+    })
+}
+
+/// Read a stream to its end on another thread, keeping the first
+/// [`OUTPUT_LIMIT`] bytes
+fn capture<R: Read + Send + 'static>(stream: Option<R>) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    if let Some(mut stream) = stream {
+        std::thread::spawn(move || {
+            let mut kept = Vec::new();
+            let mut buffer = [0; 4096];
+            while let Ok(read @ 1..) = stream.read(&mut buffer) {
+                let room = OUTPUT_LIMIT.saturating_sub(kept.len());
+                kept.extend_from_slice(&buffer[..read.min(room)]);
+            }
+            let _ = sender.send(kept);
+        });
+    }
+    receiver
+}
+
+fn collect(output: &mpsc::Receiver<Vec<u8>>) -> String {
+    output
+        .recv_timeout(OUTPUT_GRACE)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_settings_update() {
+        let dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        assert_eq!(Settings::load(&repo).timeout_secs, DEFAULT_TIMEOUT_SECS);
+
+        let hook = dir.path().join("hook");
+        std::fs::write(&hook, "").unwrap();
+        let settings = Settings::update(
+            &repo,
+            Update {
+                post_sync: Some(hook.display().to_string()),
+                timeout_secs: Some(30),
+                ..Update::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(settings.post_sync.as_deref(), Some(hook.as_path()));
+        assert_eq!(settings.timeout_secs, 30);
+
+        for invalid in [
+            Update {
+                pre_commit: Some("hook.sh".to_string()),
+                ..Update::default()
+            },
+            Update {
+                pre_commit: Some("/no/such/hook".to_string()),
+                ..Update::default()
+            },
+            Update {
+                timeout_secs: Some(MAX_TIMEOUT_SECS + 1),
+                ..Update::default()
+            },
+        ] {
+            assert!(Settings::update(&repo, invalid).is_err());
+        }
+
+        let settings = Settings::update(
+            &repo,
+            Update {
+                post_sync: Some(String::new()),
+                ..Update::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(settings.post_sync, None);
+        assert_eq!(settings.timeout_secs, 30);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hook_gets_repo_and_summary() {
+        let dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        let hook = script(
+            dir.path(),
+            "hook",
+            "echo \"$WEBTAGS_HOOK $1 ${CARGO_MANIFEST_DIR:-cleared}\"; cat; echo oops >&2; exit 3",
+        );
+        Settings::update(
+            &repo,
+            Update {
+                pre_commit: Some(hook.display().to_string()),
+                ..Update::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            run(&repo, Hook::PostSync, &serde_json::json!({})).unwrap(),
+            None
+        );
+
+        let outcome = run(
+            &repo,
+            Hook::PreCommit,
+            &serde_json::json!({ "message": "Add" }),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(outcome.exit_code, Some(3));
+        assert!(!outcome.succeeded());
+        assert_eq!(
+            outcome.stdout,
+            format!(
+                "pre-commit {} cleared\n{{\"message\":\"Add\"}}",
+                dir.path().display()
+            )
+        );
+        assert_eq!(outcome.stderr, "oops\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hook_timeout_and_output_limit() {
+        let dir = TempDir::new().unwrap();
+        let hook = script(
+            dir.path(),
+            "slow",
+            "head -c 100000 /dev/zero; exec sleep 10",
+        );
+        let started = Instant::now();
+        let outcome = execute(
+            &hook,
+            Hook::PostSync,
+            dir.path(),
+            &serde_json::json!({}),
+            Duration::from_millis(300),
+        )
+        .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(outcome.timed_out);
+        assert!(!outcome.succeeded());
+        assert_eq!(outcome.stdout.len(), OUTPUT_LIMIT);
+        assert_eq!(outcome.describe(), "The post-sync hook timed out");
+    }
+}
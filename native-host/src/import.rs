@@ -0,0 +1,1022 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+
+use crate::storage::{
+    self, BookmarkRelationships, BookmarksData, RelationshipData, Resource, ResourceIdentifier,
+};
+use crate::unicode;
+use crate::urlnorm;
+use crate::validation::ValidationConfig;
+
+/// Tag Pocket favorites get, since bookmarks have no favorite flag of their own
+pub const FAVORITE_TAG: &str = "favorite";
+
+/// Items between progress reports
+pub const PROGRESS_INTERVAL: usize = 500;
+
+/// Raindrop's catch-all collection, which says nothing about the bookmark
+const RAINDROP_UNSORTED: &str = "Unsorted";
+
+// Pocket's HTML export: list headings, links, and the attributes on them
+static HEADING_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<h1[^>]*>(.*?)</h1>").unwrap());
+static LINK_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<a\s([^>]*)>(.*?)</a>").unwrap());
+static ATTRIBUTE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([\w-]+)\s*=\s*"([^"]*)""#).unwrap());
+static TAG_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+// `#tag` in a browser bookmark's title, as the extension writes them
+static TITLE_TAG_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"#(\w+)").unwrap());
+static ENTITY_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap());
+
+/// Formats `Import` understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// `ril_export.html` from Pocket's export page
+    PocketHtml,
+    /// `part_000000.csv` from Pocket's newer export archive
+    PocketCsv,
+    /// JSON from Pinboard's backup page
+    PinboardJson,
+    /// CSV from Raindrop.io's export
+    RaindropCsv,
+}
+
+/// What to do with the folders (collections) items were filed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderMapping {
+    /// A tag per folder, nested like the folders
+    #[default]
+    Nested,
+    /// One top-level tag named after the innermost folder
+    Flat,
+    Ignore,
+}
+
+/// How an import maps the source's organization onto tags
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub folders: FolderMapping,
+    /// Source tag name (ignoring case) → tag name to use instead; an empty
+    /// name drops the tag
+    #[serde(default)]
+    pub tag_mapping: BTreeMap<String, String>,
+    /// Length limits to import within, set by the host from the repository
+    #[serde(skip)]
+    pub limits: ValidationConfig,
+}
+
+/// One item read from an export file, before it becomes a bookmark
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedItem {
+    pub url: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    /// Folder path, outermost first
+    pub folder: Vec<String>,
+    pub notes: Option<String>,
+    pub created: Option<DateTime<Utc>>,
+    /// Saved to read later and not read yet
+    pub unread: bool,
+    pub favorite: bool,
+    pub private: bool,
+}
+
+/// What an import did, or with `dry_run` would do
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImportReport {
+    /// Items read from the file
+    pub items: usize,
+    /// New bookmarks
+    pub created: usize,
+    /// Existing bookmarks (or earlier items with the same URL) that gained tags
+    pub merged: usize,
+    /// Items already stored with all their tags
+    pub unchanged: usize,
+    /// Items that could not be imported; see `warnings`
+    pub skipped: usize,
+    /// Names of tags that did not exist yet
+    pub tags_created: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Read the items in an export file
+pub fn parse(format: ImportFormat, content: &str) -> Result<Vec<ImportedItem>> {
+    match format {
+        ImportFormat::PocketHtml => parse_pocket_html(content),
+        ImportFormat::PocketCsv => parse_pocket_csv(content),
+        ImportFormat::PinboardJson => parse_pinboard_json(content),
+        ImportFormat::RaindropCsv => parse_raindrop_csv(content),
+    }
+}
+
+/// Pocket's HTML export: an `<h1>` per list ("Unread", "Read Archive"), each
+/// followed by links carrying `time_added` and comma-separated `tags`
+pub fn parse_pocket_html(content: &str) -> Result<Vec<ImportedItem>> {
+    // Where each list starts, and whether it is the archive
+    let sections: Vec<(usize, bool)> = HEADING_PATTERN
+        .captures_iter(content)
+        .map(|caps| {
+            let archive = caps[1].to_lowercase().contains("archive");
+            (caps.get(0).map_or(0, |m| m.start()), archive)
+        })
+        .collect();
+    if sections.is_empty() && !content.to_lowercase().contains("<a ") {
+        anyhow::bail!("Not a Pocket HTML export (no lists or links found)");
+    }
+
+    let mut items = Vec::new();
+    for caps in LINK_PATTERN.captures_iter(content) {
+        let start = caps.get(0).map_or(0, |m| m.start());
+        let archived = sections
+            .iter()
+            .rev()
+            .find(|(position, _)| *position <= start)
+            .is_some_and(|(_, archive)| *archive);
+        let attributes: HashMap<String, String> = ATTRIBUTE_PATTERN
+            .captures_iter(&caps[1])
+            .map(|a| (a[1].to_lowercase(), decode_entities(&a[2])))
+            .collect();
+        let Some(url) = attributes.get("href") else {
+            continue;
+        };
+        items.push(ImportedItem {
+            url: url.trim().to_string(),
+            title: decode_entities(strip_tags(&caps[2]).trim()),
+            tags: split_tags(attributes.get("tags").map_or("", String::as_str), ','),
+            folder: Vec::new(),
+            notes: None,
+            created: attributes
+                .get("time_added")
+                .and_then(|time| unix_time(time)),
+            unread: !archived,
+            favorite: attributes.get("favorite").is_some_and(|f| is_true(f)),
+            private: false,
+        });
+    }
+    Ok(items)
+}
+
+/// Pocket's CSV export: a header row naming `title`, `url`, `time_added`,
+/// `tags` (separated by `|`), and `status` (`unread` or `archive`), with an
+/// optional `favorite` column
+pub fn parse_pocket_csv(content: &str) -> Result<Vec<ImportedItem>> {
+    let table = CsvTable::parse(content)?;
+    let url = table.require("url")?;
+    let (title, time_added, tags, status, favorite) = (
+        table.column("title"),
+        table.column("time_added"),
+        table.column("tags"),
+        table.column("status"),
+        table.column("favorite"),
+    );
+
+    Ok(table
+        .rows()
+        .map(|row| ImportedItem {
+            url: row.get(Some(url)).to_string(),
+            title: row.get(title).to_string(),
+            tags: split_tags(row.get(tags), '|'),
+            folder: Vec::new(),
+            notes: None,
+            created: unix_time(row.get(time_added)),
+            unread: !row.get(status).eq_ignore_ascii_case("archive"),
+            favorite: is_true(row.get(favorite)),
+            private: false,
+        })
+        .collect())
+}
+
+/// A post in Pinboard's JSON backup
+#[derive(Debug, Deserialize)]
+struct PinboardPost {
+    href: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    extended: String,
+    #[serde(default)]
+    time: Option<String>,
+    /// `yes` or `no`
+    #[serde(default)]
+    shared: String,
+    /// `yes` or `no`
+    #[serde(default)]
+    toread: String,
+    /// Separated by spaces
+    #[serde(default)]
+    tags: String,
+}
+
+/// Pinboard's JSON backup: an array of posts. Unshared posts become private
+/// bookmarks and `toread` ones unread.
+pub fn parse_pinboard_json(content: &str) -> Result<Vec<ImportedItem>> {
+    let posts: Vec<PinboardPost> =
+        serde_json::from_str(content).context("Not a Pinboard JSON backup")?;
+    Ok(posts
+        .into_iter()
+        .map(|post| ImportedItem {
+            url: post.href.trim().to_string(),
+            title: post.description.trim().to_string(),
+            tags: split_tags(&post.tags, ' '),
+            folder: Vec::new(),
+            notes: Some(post.extended.trim().to_string()).filter(|notes| !notes.is_empty()),
+            created: post.time.as_deref().and_then(rfc3339_time),
+            unread: post.toread == "yes",
+            favorite: false,
+            private: post.shared == "no",
+        })
+        .collect())
+}
+
+/// Raindrop's CSV export: a header row naming `url`, `title`, `note`,
+/// `folder` (collections separated by `/`), `tags` (separated by `,`),
+/// `created`, and `favorite`
+pub fn parse_raindrop_csv(content: &str) -> Result<Vec<ImportedItem>> {
+    let table = CsvTable::parse(content)?;
+    let url = table.require("url")?;
+    let (title, note, folder, tags, created, favorite) = (
+        table.column("title"),
+        table.column("note"),
+        table.column("folder"),
+        table.column("tags"),
+        table.column("created"),
+        table.column("favorite"),
+    );
+
+    Ok(table
+        .rows()
+        .map(|row| ImportedItem {
+            url: row.get(Some(url)).to_string(),
+            title: row.get(title).to_string(),
+            tags: split_tags(row.get(tags), ','),
+            folder: split_tags(row.get(folder), '/')
+                .into_iter()
+                .filter(|name| name != RAINDROP_UNSORTED)
+                .collect(),
+            notes: Some(row.get(note).to_string()).filter(|notes| !notes.is_empty()),
+            created: rfc3339_time(row.get(created)),
+            unread: false,
+            favorite: is_true(row.get(favorite)),
+            private: false,
+        })
+        .collect())
+}
+
+/// A node in the browser's bookmarks tree: a bookmark if it has a URL,
+/// otherwise a folder
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowserNode {
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub title: String,
+    /// Milliseconds since the epoch
+    #[serde(default)]
+    pub date_added: Option<i64>,
+    #[serde(default)]
+    pub children: Vec<BrowserNode>,
+}
+
+/// The bookmarks in a browser's tree, each filed under the folders above it.
+///
+/// The browser's own top-level folders ("Bookmarks Bar", "Other Bookmarks")
+/// are not folders the user made, so they don't become tags; a subtree
+/// passed on its own (with a `parentId`) keeps its folder. `#tags` in titles
+/// become tags, as they do when the extension syncs.
+pub fn browser_tree_items(tree: &[BrowserNode]) -> Vec<ImportedItem> {
+    fn walk(node: &BrowserNode, path: &mut Vec<String>, items: &mut Vec<ImportedItem>) {
+        if let Some(url) = &node.url {
+            let tags = TITLE_TAG_PATTERN
+                .captures_iter(&node.title)
+                .map(|caps| caps[1].to_string())
+                .collect();
+            items.push(ImportedItem {
+                url: url.trim().to_string(),
+                title: TITLE_TAG_PATTERN
+                    .replace_all(&node.title, "")
+                    .trim()
+                    .to_string(),
+                tags,
+                folder: path.clone(),
+                notes: None,
+                created: node.date_added.and_then(DateTime::from_timestamp_millis),
+                unread: false,
+                favorite: false,
+                private: false,
+            });
+            return;
+        }
+        let named = !node.title.trim().is_empty();
+        if named {
+            path.push(node.title.trim().to_string());
+        }
+        for child in &node.children {
+            walk(child, path, items);
+        }
+        if named {
+            path.pop();
+        }
+    }
+
+    let mut items = Vec::new();
+    for node in tree {
+        if node.parent_id.is_some() || node.url.is_some() {
+            walk(node, &mut Vec::new(), &mut items);
+            continue;
+        }
+        // The root and the browser's folders under it
+        for top in &node.children {
+            if top.url.is_some() {
+                walk(top, &mut Vec::new(), &mut items);
+            } else {
+                for child in &top.children {
+                    walk(child, &mut Vec::new(), &mut items);
+                }
+            }
+        }
+    }
+    items
+}
+
+/// Add `items` to `data`: new URLs become bookmarks, and URLs already stored
+/// (or seen earlier in the file) gain any tags they lack. Tags are matched by
+/// name, ignoring case, or created at the top level; folders become tags as
+/// `options` says. `progress` is called with the items done and the total
+/// every [`PROGRESS_INTERVAL`] items and at the end.
+pub fn import(
+    data: &mut BookmarksData,
+    items: Vec<ImportedItem>,
+    options: &ImportOptions,
+    mut progress: impl FnMut(usize, usize),
+) -> ImportReport {
+    let total = items.len();
+    let mut report = ImportReport {
+        items: total,
+        ..ImportReport::default()
+    };
+    let tag_mapping: HashMap<String, &str> = options
+        .tag_mapping
+        .iter()
+        .map(|(from, to)| (unicode::fold(from), to.trim()))
+        .collect();
+    let mut tags = TagResolver::new(data, options.limits.clone());
+    // Keyed by canonical URL, so the same page saved two ways merges
+    let url_rules = urlnorm::rules();
+    let mut bookmarks_by_url: HashMap<String, usize> = data
+        .data()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, resource)| match resource {
+            Resource::Bookmark { attributes, .. } => {
+                Some((urlnorm::key_with(&attributes.url, &url_rules), index))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for (done, mut item) in items.into_iter().enumerate() {
+        if done > 0 && done % PROGRESS_INTERVAL == 0 {
+            progress(done, total);
+        }
+        item.url = urlnorm::normalize_with(&item.url, &url_rules);
+        if let Err(e) = storage::validate_bookmark_url(&item.url, &options.limits) {
+            report.skipped += 1;
+            report.warnings.push(format!("Skipped '{}': {e}", item.url));
+            continue;
+        }
+
+        let mut names: Vec<String> = item
+            .tags
+            .into_iter()
+            .filter_map(|name| match tag_mapping.get(&unicode::fold(&name)) {
+                Some(&"") => None,
+                Some(mapped) => Some((*mapped).to_string()),
+                None => Some(name),
+            })
+            .collect();
+        if item.favorite {
+            names.push(FAVORITE_TAG.to_string());
+        }
+        let mut tag_ids: Vec<String> = Vec::new();
+        let folder_tag = match options.folders {
+            FolderMapping::Nested => tags.folder(data, &mut report, &item.folder),
+            FolderMapping::Flat => item
+                .folder
+                .last()
+                .and_then(|name| tags.named(data, &mut report, name)),
+            FolderMapping::Ignore => None,
+        };
+        for id in folder_tag.into_iter().chain(
+            names
+                .iter()
+                .filter_map(|name| tags.named(data, &mut report, name)),
+        ) {
+            if !tag_ids.contains(&id) {
+                tag_ids.push(id);
+            }
+        }
+
+        let url_key = urlnorm::key_with(&item.url, &url_rules);
+        if let Some(&index) = bookmarks_by_url.get(&url_key) {
+            if add_tags(&mut data.data_mut()[index], &tag_ids) {
+                report.merged += 1;
+            } else {
+                report.unchanged += 1;
+            }
+            continue;
+        }
+
+        let mut title = if item.title.is_empty() {
+            item.url.clone()
+        } else {
+            item.title
+        };
+        truncate(&mut title, options.limits.max_title_length);
+        let mut bookmark = storage::create_bookmark(item.url.clone(), title, tag_ids);
+        if let Resource::Bookmark { attributes, .. } = &mut bookmark {
+            if let Some(created) = item.created {
+                attributes.created = created;
+            }
+            attributes.notes = item.notes.map(|notes| {
+                let mut notes = storage::sanitize_notes(&notes).into_owned();
+                truncate(&mut notes, storage::MAX_NOTES_LENGTH);
+                notes
+            });
+            attributes.unread = item.unread;
+            attributes.private = item.private;
+        }
+        data.add_bookmark(bookmark)
+            .expect("create_bookmark makes a bookmark");
+        bookmarks_by_url.insert(url_key, data.data().len() - 1);
+        report.created += 1;
+    }
+
+    progress(total, total);
+    report
+}
+
+/// Finds the tags an import needs, creating them as it goes
+struct TagResolver {
+    /// Lowercase name → ID of the first tag with that name
+    by_name: HashMap<String, String>,
+    /// (parent ID, lowercase name) → ID
+    by_parent: HashMap<(Option<String>, String), String>,
+    limits: ValidationConfig,
+}
+
+impl TagResolver {
+    fn new(data: &BookmarksData, limits: ValidationConfig) -> Self {
+        let mut resolver = Self {
+            by_name: HashMap::new(),
+            by_parent: HashMap::new(),
+            limits,
+        };
+        for tag in data.get_tags() {
+            if let Resource::Tag { id, attributes, .. } = tag {
+                let name = unicode::fold(&attributes.name);
+                let parent = data.tag_parent_id(id).map(str::to_string);
+                resolver.by_name.entry(name.clone()).or_insert(id.clone());
+                resolver
+                    .by_parent
+                    .entry((parent, name))
+                    .or_insert(id.clone());
+            }
+        }
+        resolver
+    }
+
+    /// Any tag called `name`, or a new top-level one
+    fn named(
+        &mut self,
+        data: &mut BookmarksData,
+        report: &mut ImportReport,
+        name: &str,
+    ) -> Option<String> {
+        if let Some(id) = self.by_name.get(&unicode::fold(name)) {
+            return Some(id.clone());
+        }
+        self.create(data, report, name, None)
+    }
+
+    /// The tag at the end of `path`, creating each level that's missing
+    fn folder(
+        &mut self,
+        data: &mut BookmarksData,
+        report: &mut ImportReport,
+        path: &[String],
+    ) -> Option<String> {
+        let mut parent: Option<String> = None;
+        for name in path {
+            let key = (parent.clone(), unicode::fold(name));
+            parent = match self.by_parent.get(&key) {
+                Some(id) => Some(id.clone()),
+                None => Some(self.create(data, report, name, parent)?),
+            };
+        }
+        parent
+    }
+
+    fn create(
+        &mut self,
+        data: &mut BookmarksData,
+        report: &mut ImportReport,
+        name: &str,
+        parent: Option<String>,
+    ) -> Option<String> {
+        if let Err(e) = storage::validate_tag_name(name, &self.limits) {
+            report.warnings.push(format!("Dropped tag '{name}': {e}"));
+            return None;
+        }
+        let tag = storage::create_tag(name.to_string(), None, parent.clone());
+        let id = tag.id().to_string();
+        let key = unicode::fold(name);
+        self.by_name.entry(key.clone()).or_insert(id.clone());
+        self.by_parent.insert((parent, key), id.clone());
+        data.add_tag(tag).expect("create_tag makes a tag");
+        report.tags_created.push(name.to_string());
+        Some(id)
+    }
+}
+
+/// Give a bookmark the tags in `tag_ids` it lacks, returning whether any were
+/// added
+fn add_tags(bookmark: &mut Resource, tag_ids: &[String]) -> bool {
+    let Resource::Bookmark { relationships, .. } = bookmark else {
+        return false;
+    };
+    let mut added = false;
+    for tag_id in tag_ids {
+        let tags = &mut relationships
+            .get_or_insert_with(|| BookmarkRelationships { tags: None })
+            .tags
+            .get_or_insert_with(|| RelationshipData { data: Vec::new() })
+            .data;
+        if !tags.iter().any(|tag| tag.id == *tag_id) {
+            tags.push(ResourceIdentifier {
+                resource_type: "tag".to_string(),
+                id: tag_id.clone(),
+            });
+            added = true;
+        }
+    }
+    added
+}
+
+/// Cut `text` to at most `max` bytes, on a character boundary
+fn truncate(text: &mut String, max: usize) {
+    if text.len() > max {
+        let mut end = max;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+}
+
+fn split_tags(tags: &str, separator: char) -> Vec<String> {
+    tags.split(separator)
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn unix_time(seconds: &str) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds.trim().parse().ok()?, 0)
+}
+
+fn rfc3339_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time.trim())
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn is_true(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes")
+}
+
+fn strip_tags(html: &str) -> String {
+    TAG_PATTERN.replace_all(html, "").into_owned()
+}
+
+/// Decode the character references Pocket's exports use
+fn decode_entities(text: &str) -> String {
+    ENTITY_PATTERN
+        .replace_all(text, |caps: &regex::Captures| {
+            let entity = &caps[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            decoded.map_or_else(|| caps[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+/// A CSV file with a header row
+struct CsvTable {
+    /// Lowercase column names
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl CsvTable {
+    fn parse(content: &str) -> Result<Self> {
+        let mut rows = parse_csv(content)?.into_iter();
+        let header = rows
+            .next()
+            .context("CSV file is empty")?
+            .into_iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect();
+        Ok(Self {
+            header,
+            rows: rows.collect(),
+        })
+    }
+
+    fn column(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|h| h == name)
+    }
+
+    fn require(&self, name: &str) -> Result<usize> {
+        self.column(name)
+            .with_context(|| format!("CSV file has no '{name}' column"))
+    }
+
+    /// Rows that aren't blank
+    fn rows(&self) -> impl Iterator<Item = CsvRow<'_>> {
+        self.rows
+            .iter()
+            .filter(|row| row.iter().any(|field| !field.is_empty()))
+            .map(|row| CsvRow(row))
+    }
+}
+
+struct CsvRow<'a>(&'a [String]);
+
+impl CsvRow<'_> {
+    /// Trimmed value in `column`, or empty if there's no such column
+    fn get(&self, column: Option<usize>) -> &str {
+        column
+            .and_then(|i| self.0.get(i))
+            .map_or("", |value| value.trim())
+    }
+}
+
+/// Split RFC 4180 CSV into rows of fields
+fn parse_csv(content: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n' | '\r', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if in_quotes {
+        anyhow::bail!("CSV file ends inside a quoted field");
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTML: &str = r#"<!DOCTYPE html>
+<html><head><title>Pocket Export</title></head><body>
+<h1>Unread</h1>
+<ul>
+<li><a href="https://example.com/a" time_added="1600000000" tags="rust,async">Async &amp; Rust</a></li>
+<li><a href="https://example.com/b" time_added="1600000100" tags="">B</a></li>
+</ul>
+<h1>Read Archive</h1>
+<ul>
+<li><a href="https://example.com/c" time_added="1500000000" tags="Rust" favorite="1">C</a></li>
+<li><a href="javascript:alert(1)" time_added="1500000000" tags="">Bad</a></li>
+</ul>
+</body></html>"#;
+
+    const CSV: &str = "title,url,time_added,cursor,tags,status\r\n\
+        \"Hello, world\",https://example.com/a,1600000000,,rust|web,unread\r\n\
+        \"Say \"\"hi\"\"\",https://example.com/d,1600000200,,,archive\r\n";
+
+    #[test]
+    fn test_parse_pocket_html() {
+        let items = parse_pocket_html(HTML).unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].title, "Async & Rust");
+        assert_eq!(items[0].tags, vec!["rust", "async"]);
+        assert_eq!(items[0].created.unwrap().timestamp(), 1_600_000_000);
+        assert!(items[0].unread && !items[0].favorite);
+        assert!(items[1].tags.is_empty());
+        assert!(!items[2].unread && items[2].favorite);
+
+        assert!(parse_pocket_html("just some text").is_err());
+    }
+
+    #[test]
+    fn test_parse_pocket_csv() {
+        let items = parse_pocket_csv(CSV).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].title, "Hello, world");
+        assert_eq!(items[0].tags, vec!["rust", "web"]);
+        assert!(items[0].unread);
+        assert_eq!(items[1].title, "Say \"hi\"");
+        assert!(!items[1].unread);
+
+        assert!(parse_pocket_csv("title,link\nx,y\n").is_err());
+        assert!(parse_pocket_csv("url\n\"unterminated\n").is_err());
+    }
+
+    #[test]
+    fn test_import_creates_and_merges() {
+        let mut data = BookmarksData::new();
+        let rust = storage::create_tag("Rust".to_string(), None, None);
+        let rust_id = rust.id().to_string();
+        data.add_tag(rust).unwrap();
+        data.add_bookmark(storage::create_bookmark(
+            "https://example.com/a".to_string(),
+            "Already here".to_string(),
+            vec![],
+        ))
+        .unwrap();
+
+        let report = import(
+            &mut data,
+            parse_pocket_html(HTML).unwrap(),
+            &ImportOptions::default(),
+            |_, _| {},
+        );
+        assert_eq!(report.items, 4);
+        assert_eq!(report.created, 2);
+        assert_eq!(report.merged, 1);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.tags_created, vec!["async", FAVORITE_TAG]);
+        assert_eq!(data.get_bookmarks().len(), 3);
+        // "rust" matched the existing "Rust" tag
+        assert_eq!(data.bookmarks_with_tag(&rust_id).len(), 2);
+        assert!(data.validate().is_ok());
+
+        let archived = data
+            .get_bookmarks()
+            .into_iter()
+            .find_map(|b| match b {
+                Resource::Bookmark { attributes, .. } if attributes.url.ends_with("/c") => {
+                    Some(attributes)
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert!(!archived.unread);
+        assert_eq!(archived.created.timestamp(), 1_500_000_000);
+
+        // Importing the same file again changes nothing
+        let again = import(
+            &mut data,
+            parse_pocket_html(HTML).unwrap(),
+            &ImportOptions::default(),
+            |_, _| {},
+        );
+        assert_eq!((again.created, again.merged, again.unchanged), (0, 0, 3));
+        assert!(again.tags_created.is_empty());
+    }
+
+    const PINBOARD: &str = r#"[
+        {"href": "https://example.com/p", "description": "Pinned", "extended": "Some notes",
+         "time": "2020-09-13T12:26:40Z", "shared": "no", "toread": "yes", "tags": "rust  web"},
+        {"href": "https://example.com/q", "description": "", "extended": "",
+         "time": "not a time", "shared": "yes", "toread": "no", "tags": ""}
+    ]"#;
+
+    const RAINDROP: &str =
+        "id,title,note,excerpt,url,folder,tags,created,cover,highlights,favorite\n\
+        1,Docs,,,https://example.com/r,Dev/Rust,\"async, tokio\",2021-01-01T00:00:00.000Z,,,true\n\
+        2,Misc,A note,,https://example.com/s,Unsorted,,2021-01-02T00:00:00.000Z,,,false\n";
+
+    #[test]
+    fn test_parse_pinboard_json() {
+        let items = parse_pinboard_json(PINBOARD).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].tags, vec!["rust", "web"]);
+        assert_eq!(items[0].notes.as_deref(), Some("Some notes"));
+        assert_eq!(items[0].created.unwrap().timestamp(), 1_600_000_000);
+        assert!(items[0].private && items[0].unread);
+        assert!(!items[1].private && !items[1].unread);
+        assert!(items[1].notes.is_none() && items[1].created.is_none());
+
+        assert!(parse_pinboard_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_parse_raindrop_csv() {
+        let items = parse_raindrop_csv(RAINDROP).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].folder, vec!["Dev", "Rust"]);
+        assert_eq!(items[0].tags, vec!["async", "tokio"]);
+        assert!(items[0].favorite);
+        // Raindrop's catch-all collection isn't a folder worth keeping
+        assert!(items[1].folder.is_empty());
+        assert_eq!(items[1].notes.as_deref(), Some("A note"));
+    }
+
+    #[test]
+    fn test_folder_and_tag_mapping() {
+        let breadcrumbs = |options: &ImportOptions| {
+            let mut data = BookmarksData::new();
+            let report = import(
+                &mut data,
+                parse_raindrop_csv(RAINDROP).unwrap(),
+                options,
+                |_, _| {},
+            );
+            assert!(data.validate().is_ok());
+            let mut paths: Vec<String> = data
+                .get_tag_breadcrumbs()
+                .into_values()
+                .map(|path| path.join("/"))
+                .collect();
+            paths.sort();
+            (paths, report)
+        };
+
+        let (paths, _) = breadcrumbs(&ImportOptions::default());
+        assert_eq!(
+            paths,
+            vec!["Dev", "Dev/Rust", "async", FAVORITE_TAG, "tokio"]
+        );
+
+        let (paths, _) = breadcrumbs(&ImportOptions {
+            folders: FolderMapping::Flat,
+            tag_mapping: [
+                ("ASYNC".to_string(), "concurrency".to_string()),
+                ("tokio".to_string(), String::new()),
+            ]
+            .into(),
+            ..ImportOptions::default()
+        });
+        assert_eq!(paths, vec!["Rust", "concurrency", FAVORITE_TAG]);
+
+        let (paths, report) = breadcrumbs(&ImportOptions {
+            folders: FolderMapping::Ignore,
+            ..ImportOptions::default()
+        });
+        assert_eq!(paths, vec!["async", FAVORITE_TAG, "tokio"]);
+        assert_eq!(report.created, 2);
+    }
+
+    #[test]
+    fn test_same_page_merges_under_its_canonical_url() {
+        let item = |url: &str, tag: &str| ImportedItem {
+            url: url.to_string(),
+            title: String::new(),
+            tags: vec![tag.to_string()],
+            folder: Vec::new(),
+            notes: None,
+            created: None,
+            unread: false,
+            favorite: false,
+            private: false,
+        };
+        let mut data = BookmarksData::new();
+        let report = import(
+            &mut data,
+            vec![
+                item("https://Example.com/post/?utm_source=feed", "a"),
+                item("https://www.example.com/post", "b"),
+            ],
+            &ImportOptions::default(),
+            |_, _| {},
+        );
+        assert_eq!(report.created, 1);
+        assert_eq!(report.merged, 1);
+        match data.get_bookmarks()[0] {
+            Resource::Bookmark { attributes, .. } => {
+                assert_eq!(attributes.url, "https://example.com/post/");
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_progress_is_reported() {
+        let items: Vec<ImportedItem> = (0..=PROGRESS_INTERVAL * 2)
+            .map(|i| ImportedItem {
+                url: format!("https://example.com/{i}"),
+                title: format!("Item {i}"),
+                tags: Vec::new(),
+                folder: Vec::new(),
+                notes: None,
+                created: None,
+                unread: false,
+                favorite: false,
+                private: false,
+            })
+            .collect();
+        let total = items.len();
+
+        let mut reports = Vec::new();
+        import(
+            &mut BookmarksData::new(),
+            items,
+            &ImportOptions::default(),
+            |done, total| reports.push((done, total)),
+        );
+        assert_eq!(
+            reports,
+            vec![
+                (PROGRESS_INTERVAL, total),
+                (PROGRESS_INTERVAL * 2, total),
+                (total, total)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_browser_tree_items() {
+        let tree: Vec<BrowserNode> = serde_json::from_value(serde_json::json!([{
+            "id": "0",
+            "title": "",
+            "children": [
+                {
+                    "id": "1",
+                    "parentId": "0",
+                    "title": "Bookmarks Bar",
+                    "children": [
+                        {"id": "3", "parentId": "1", "title": "Top #news",
+                         "url": "https://example.com/top", "dateAdded": 1_600_000_000_000_i64},
+                        {"id": "4", "parentId": "1", "title": "Dev", "children": [
+                            {"id": "5", "parentId": "4", "title": "Rust", "children": [
+                                {"id": "6", "parentId": "5", "title": "Book",
+                                 "url": "https://doc.rust-lang.org/book"}
+                            ]}
+                        ]}
+                    ]
+                },
+                {"id": "2", "parentId": "0", "title": "Other Bookmarks", "children": []}
+            ]
+        }]))
+        .unwrap();
+
+        let items = browser_tree_items(&tree);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].title, "Top");
+        assert_eq!(items[0].tags, vec!["news"]);
+        assert!(items[0].folder.is_empty());
+        assert_eq!(items[0].created.unwrap().timestamp(), 1_600_000_000);
+        assert_eq!(items[1].folder, vec!["Dev", "Rust"]);
+
+        // A subtree keeps its own folder
+        let dev = &tree[0].children[0].children[1];
+        let items = browser_tree_items(std::slice::from_ref(dev));
+        assert_eq!(items[0].folder, vec!["Dev", "Rust"]);
+
+        let mut data = BookmarksData::new();
+        import(&mut data, items, &ImportOptions::default(), |_, _| {});
+        assert_eq!(data.get_tag_breadcrumbs().len(), 2);
+        assert!(data.validate().is_ok());
+    }
+}
@@ -0,0 +1,321 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::storage::{BookmarksData, Resource};
+
+/// Bump whenever the layout or contents of the index directory change.
+/// A mismatched marker causes the whole directory to be discarded and rebuilt.
+pub const INDEX_FORMAT_VERSION: u32 = 2;
+
+const VERSION_FILE: &str = "VERSION";
+const SEARCH_INDEX_FILE: &str = "search.json";
+const STATS_FILE: &str = "stats.json";
+const FAVICON_DIR: &str = "favicons";
+
+/// Inverted search index: lowercase token → bookmark IDs
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct SearchIndex {
+    pub tokens: BTreeMap<String, BTreeSet<String>>,
+    /// IDs of unread bookmarks, for the "unread" search filter
+    pub unread: BTreeSet<String>,
+}
+
+/// Collection statistics derived from bookmarks.json
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct IndexStats {
+    pub bookmark_count: usize,
+    pub tag_count: usize,
+    pub unread_count: usize,
+    pub built_at: DateTime<Utc>,
+}
+
+/// Directory holding all derived, disposable artifacts for a repository.
+///
+/// Nothing in here is ever committed; it can be deleted at any time and
+/// regenerated from bookmarks.json with [`IndexDir::rebuild`].
+pub struct IndexDir {
+    root: PathBuf,
+}
+
+impl IndexDir {
+    /// Use an explicit directory as the index root
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Default index location for a repository:
+    /// `~/.local/share/webtags/index/<repo name>-<path hash>`
+    pub fn for_repo<P: AsRef<Path>>(repo_path: P) -> Result<Self> {
+        let home = dirs::home_dir().context("No home directory found")?;
+        Self::under(
+            &home
+                .join(".local")
+                .join("share")
+                .join("webtags")
+                .join("index"),
+            repo_path.as_ref(),
+        )
+    }
+
+    /// Index for `repo_path` inside `base`. The name carries a hash of the
+    /// canonical path, so two clones with the same name don't share an index.
+    fn under(base: &Path, repo_path: &Path) -> Result<Self> {
+        let repo_name = repo_path
+            .file_name()
+            .context("Repository path has no name")?;
+        let canonical = repo_path
+            .canonicalize()
+            .unwrap_or_else(|_| repo_path.to_path_buf());
+        let hash = hex::encode(Sha256::digest(canonical.to_string_lossy().as_bytes()));
+
+        let mut dir_name = repo_name.to_os_string();
+        dir_name.push(format!("-{}", &hash[..16]));
+        Ok(Self::new(base.join(dir_name)))
+    }
+
+    /// Get the index root directory
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Favicon cache directory (filled lazily; emptied on rebuild)
+    pub fn favicon_dir(&self) -> PathBuf {
+        self.root.join(FAVICON_DIR)
+    }
+
+    /// Read the format version marker, if present
+    pub fn format_version(&self) -> Option<u32> {
+        fs::read_to_string(self.root.join(VERSION_FILE))
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+    }
+
+    /// Ensure the directory exists with the current format version.
+    ///
+    /// Returns `true` if existing contents were discarded because they were
+    /// written by a different format version.
+    pub fn open(&self) -> Result<bool> {
+        let stale = match self.format_version() {
+            Some(version) => version != INDEX_FORMAT_VERSION,
+            None => self.root.exists(),
+        };
+
+        if stale {
+            log::info!("Index format changed, discarding {}", self.root.display());
+            fs::remove_dir_all(&self.root).context("Failed to remove stale index directory")?;
+        }
+
+        fs::create_dir_all(&self.root).context("Failed to create index directory")?;
+        fs::write(
+            self.root.join(VERSION_FILE),
+            INDEX_FORMAT_VERSION.to_string(),
+        )
+        .context("Failed to write index version marker")?;
+
+        Ok(stale)
+    }
+
+    /// Regenerate every derived artifact from the given bookmarks
+    pub fn rebuild(&self, data: &BookmarksData) -> Result<IndexStats> {
+        if self.root.exists() {
+            fs::remove_dir_all(&self.root).context("Failed to clear index directory")?;
+        }
+        self.open()?;
+        fs::create_dir_all(self.favicon_dir()).context("Failed to create favicon cache")?;
+
+        let search = build_search_index(data);
+        write_json(&self.root.join(SEARCH_INDEX_FILE), &search)?;
+
+        let stats = IndexStats {
+            bookmark_count: data.get_bookmarks().len(),
+            tag_count: data.get_tags().len(),
+            unread_count: search.unread.len(),
+            built_at: Utc::now(),
+        };
+        write_json(&self.root.join(STATS_FILE), &stats)?;
+
+        log::info!("Rebuilt indexes at {}", self.root.display());
+        Ok(stats)
+    }
+
+    /// Load the search index, if it has been built
+    pub fn search_index(&self) -> Result<Option<SearchIndex>> {
+        read_json(&self.root.join(SEARCH_INDEX_FILE))
+    }
+
+    /// Load the collection statistics, if they have been built
+    pub fn stats(&self) -> Result<Option<IndexStats>> {
+        read_json(&self.root.join(STATS_FILE))
+    }
+}
+
+/// Split text into lowercase alphanumeric search tokens
+pub(crate) fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Build an inverted index over bookmark titles, URLs, and tag names, plus
+/// notes of bookmarks that aren't private (the index isn't encrypted)
+pub fn build_search_index(data: &BookmarksData) -> SearchIndex {
+    let mut index = SearchIndex::default();
+
+    for bookmark in data.get_bookmarks() {
+        let Resource::Bookmark {
+            id,
+            attributes,
+            relationships,
+            ..
+        } = bookmark
+        else {
+            continue;
+        };
+
+        let mut text = format!("{} {}", attributes.title, attributes.url);
+        if let Some(notes) = attributes.notes.as_deref().filter(|_| !attributes.private) {
+            text.push(' ');
+            text.push_str(notes);
+        }
+        if let Some(tags) = relationships.as_ref().and_then(|r| r.tags.as_ref()) {
+            for tag in &tags.data {
+                if let Some(Resource::Tag { attributes, .. }) = data.get(&tag.id) {
+                    text.push(' ');
+                    text.push_str(&attributes.name);
+                }
+            }
+        }
+
+        for token in tokenize(&text) {
+            index.tokens.entry(token).or_default().insert(id.clone());
+        }
+        if attributes.unread {
+            index.unread.insert(id.clone());
+        }
+    }
+
+    index
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let json = serde_json::to_vec(value).context("Failed to serialize index artifact")?;
+    fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let value = serde_json::from_slice(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+    use tempfile::TempDir;
+
+    fn sample_data() -> BookmarksData {
+        let mut data = BookmarksData::new();
+        let tag = create_tag("Rust".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+        data.add_bookmark(create_bookmark(
+            "https://doc.rust-lang.org/book".to_string(),
+            "The Book".to_string(),
+            vec![tag_id],
+        ))
+        .unwrap();
+        let mut later = create_bookmark(
+            "https://blog.rust-lang.org".to_string(),
+            "Rust Blog".to_string(),
+            vec![],
+        );
+        if let Resource::Bookmark { attributes, .. } = &mut later {
+            attributes.unread = true;
+        }
+        data.add_bookmark(later).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_rebuild_writes_artifacts() {
+        let temp_dir = TempDir::new().unwrap();
+        let index = IndexDir::new(temp_dir.path().join("index"));
+
+        let stats = index.rebuild(&sample_data()).unwrap();
+        assert_eq!(stats.bookmark_count, 2);
+        assert_eq!(stats.tag_count, 1);
+        assert_eq!(stats.unread_count, 1);
+
+        assert_eq!(index.format_version(), Some(INDEX_FORMAT_VERSION));
+        assert!(index.favicon_dir().exists());
+        assert_eq!(index.stats().unwrap().unwrap(), stats);
+
+        let search = index.search_index().unwrap().unwrap();
+        assert!(search.tokens.contains_key("book"));
+        assert!(search.tokens.contains_key("rust"));
+        assert_eq!(search.unread.len(), 1);
+        assert!(search.tokens["blog"].is_subset(&search.unread));
+    }
+
+    #[test]
+    fn test_open_discards_stale_format() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("index");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join(VERSION_FILE), "0").unwrap();
+        fs::write(root.join(STATS_FILE), "garbage").unwrap();
+
+        let index = IndexDir::new(&root);
+        assert!(index.open().unwrap());
+        assert!(!root.join(STATS_FILE).exists());
+        assert_eq!(index.format_version(), Some(INDEX_FORMAT_VERSION));
+
+        // Reopening with the current version keeps contents
+        assert!(!index.open().unwrap());
+    }
+
+    #[test]
+    fn test_same_named_repos_get_separate_indexes() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path().join("index");
+        let first = temp_dir.path().join("a").join("bookmarks");
+        let second = temp_dir.path().join("b").join("bookmarks");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+
+        let first_index = IndexDir::under(&base, &first).unwrap();
+        let second_index = IndexDir::under(&base, &second).unwrap();
+        assert_ne!(first_index.path(), second_index.path());
+        assert!(first_index
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("bookmarks-"));
+
+        // Another spelling of the same path finds the same index
+        let respelled = temp_dir.path().join("a").join(".").join("bookmarks");
+        let respelled_index = IndexDir::under(&base, &respelled).unwrap();
+        assert_eq!(respelled_index.path(), first_index.path());
+    }
+
+    #[test]
+    fn test_missing_artifacts_are_none() {
+        let temp_dir = TempDir::new().unwrap();
+        let index = IndexDir::new(temp_dir.path());
+        assert!(index.search_index().unwrap().is_none());
+        assert!(index.stats().unwrap().is_none());
+    }
+}
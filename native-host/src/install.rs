@@ -0,0 +1,324 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Native messaging host name; the manifest file is `<HOST_NAME>.json`
+pub const HOST_NAME: &str = "com.webtags.host";
+
+/// Extension ID the Firefox build is signed with
+pub const DEFAULT_FIREFOX_EXTENSION_ID: &str = "webtags@example.com";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    Chrome,
+    Chromium,
+    Edge,
+    Brave,
+    Firefox,
+}
+
+impl Browser {
+    pub const ALL: &'static [Browser] = &[
+        Browser::Chrome,
+        Browser::Chromium,
+        Browser::Edge,
+        Browser::Brave,
+        Browser::Firefox,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Browser::Chrome => "Google Chrome",
+            Browser::Chromium => "Chromium",
+            Browser::Edge => "Microsoft Edge",
+            Browser::Brave => "Brave",
+            Browser::Firefox => "Firefox",
+        }
+    }
+
+    fn is_firefox(self) -> bool {
+        self == Browser::Firefox
+    }
+
+    /// Browser profile directory relative to the home directory; the browser
+    /// counts as installed when it exists
+    #[cfg(target_os = "macos")]
+    fn config_dir(self) -> &'static str {
+        match self {
+            Browser::Chrome => "Library/Application Support/Google/Chrome",
+            Browser::Chromium => "Library/Application Support/Chromium",
+            Browser::Edge => "Library/Application Support/Microsoft Edge",
+            Browser::Brave => "Library/Application Support/BraveSoftware/Brave-Browser",
+            Browser::Firefox => "Library/Application Support/Mozilla",
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn config_dir(self) -> &'static str {
+        match self {
+            Browser::Chrome => "AppData/Local/Google/Chrome",
+            Browser::Chromium => "AppData/Local/Chromium",
+            Browser::Edge => "AppData/Local/Microsoft/Edge",
+            Browser::Brave => "AppData/Local/BraveSoftware/Brave-Browser",
+            Browser::Firefox => "AppData/Roaming/Mozilla",
+        }
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    fn config_dir(self) -> &'static str {
+        match self {
+            Browser::Chrome => ".config/google-chrome",
+            Browser::Chromium => ".config/chromium",
+            Browser::Edge => ".config/microsoft-edge",
+            Browser::Brave => ".config/BraveSoftware/Brave-Browser",
+            Browser::Firefox => ".mozilla",
+        }
+    }
+
+    /// Where the browser looks for host manifests. Windows browsers find
+    /// manifests through the registry instead, so any directory works there.
+    fn manifest_dir(self, home: &Path) -> PathBuf {
+        let config_dir = home.join(self.config_dir());
+        if cfg!(target_os = "windows") {
+            config_dir.join("WebTags")
+        } else if self.is_firefox() && !cfg!(target_os = "macos") {
+            config_dir.join("native-messaging-hosts")
+        } else {
+            config_dir.join("NativeMessagingHosts")
+        }
+    }
+
+    /// `HKEY_CURRENT_USER` key the browser reads manifest paths from on Windows
+    #[cfg(target_os = "windows")]
+    fn registry_key(self) -> String {
+        let vendor = match self {
+            Browser::Chrome => r"Google\Chrome",
+            Browser::Chromium => "Chromium",
+            Browser::Edge => r"Microsoft\Edge",
+            Browser::Brave => r"BraveSoftware\Brave-Browser",
+            Browser::Firefox => "Mozilla",
+        };
+        format!(r"HKCU\Software\{vendor}\NativeMessagingHosts\{HOST_NAME}")
+    }
+}
+
+/// Which extensions may launch the host
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtensionIds {
+    /// Chromium extension IDs (32 characters, `a`-`p`)
+    pub chromium: Vec<String>,
+    /// Firefox add-on IDs
+    pub firefox: Vec<String>,
+}
+
+/// Check a Chromium extension ID is well formed
+pub fn validate_chromium_id(id: &str) -> Result<()> {
+    if id.len() != 32 || !id.bytes().all(|b| (b'a'..=b'p').contains(&b)) {
+        anyhow::bail!("Invalid Chromium extension ID '{id}' (expected 32 letters a-p)");
+    }
+    Ok(())
+}
+
+/// Manifest JSON for one browser
+pub fn manifest(browser: Browser, binary: &Path, ids: &ExtensionIds) -> serde_json::Value {
+    let mut manifest = serde_json::json!({
+        "name": HOST_NAME,
+        "description": "WebTags Native Messaging Host",
+        "path": binary,
+        "type": "stdio",
+    });
+    if browser.is_firefox() {
+        manifest["allowed_extensions"] = serde_json::json!(ids.firefox);
+    } else {
+        let origins: Vec<String> = ids
+            .chromium
+            .iter()
+            .map(|id| format!("chrome-extension://{id}/"))
+            .collect();
+        manifest["allowed_origins"] = serde_json::json!(origins);
+    }
+    manifest
+}
+
+/// What happened for one browser
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Manifest written to (or removed from) this path
+    Done(PathBuf),
+    /// Skipped, with the reason
+    Skipped(String),
+}
+
+/// Write manifests for every installed browser under `home`
+pub fn install(home: &Path, binary: &Path, ids: &ExtensionIds) -> Vec<(Browser, Result<Outcome>)> {
+    Browser::ALL
+        .iter()
+        .map(|&browser| (browser, install_one(browser, home, binary, ids)))
+        .collect()
+}
+
+fn install_one(
+    browser: Browser,
+    home: &Path,
+    binary: &Path,
+    ids: &ExtensionIds,
+) -> Result<Outcome> {
+    if !home.join(browser.config_dir()).is_dir() {
+        return Ok(Outcome::Skipped("not installed".to_string()));
+    }
+    let no_ids = if browser.is_firefox() {
+        ids.firefox.is_empty()
+    } else {
+        ids.chromium.is_empty()
+    };
+    if no_ids {
+        return Ok(Outcome::Skipped("no extension ID given".to_string()));
+    }
+
+    let dir = browser.manifest_dir(home);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(format!("{HOST_NAME}.json"));
+    let json = serde_json::to_string_pretty(&manifest(browser, binary, ids))?;
+    fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    #[cfg(target_os = "windows")]
+    registry(&[
+        "add",
+        &browser.registry_key(),
+        "/ve",
+        "/t",
+        "REG_SZ",
+        "/d",
+        &path.to_string_lossy(),
+        "/f",
+    ])?;
+
+    Ok(Outcome::Done(path))
+}
+
+/// Remove manifests written by [`install`]
+pub fn uninstall(home: &Path) -> Vec<(Browser, Result<Outcome>)> {
+    Browser::ALL
+        .iter()
+        .map(|&browser| (browser, uninstall_one(browser, home)))
+        .collect()
+}
+
+fn uninstall_one(browser: Browser, home: &Path) -> Result<Outcome> {
+    let path = browser.manifest_dir(home).join(format!("{HOST_NAME}.json"));
+    if !path.exists() {
+        return Ok(Outcome::Skipped("not installed".to_string()));
+    }
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+
+    #[cfg(target_os = "windows")]
+    registry(&["delete", &browser.registry_key(), "/f"])?;
+
+    Ok(Outcome::Done(path))
+}
+
+#[cfg(target_os = "windows")]
+fn registry(args: &[&str]) -> Result<()> {
+    let status = std::process::Command::new("reg")
+        .args(args)
+        .status()
+        .context("Failed to run reg")?;
+    if !status.success() {
+        anyhow::bail!("reg {} failed", args.join(" "));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, not(target_os = "windows")))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const CHROME_ID: &str = "abcdefghijklmnopabcdefghijklmnop";
+
+    fn ids() -> ExtensionIds {
+        ExtensionIds {
+            chromium: vec![CHROME_ID.to_string()],
+            firefox: vec![DEFAULT_FIREFOX_EXTENSION_ID.to_string()],
+        }
+    }
+
+    #[test]
+    fn test_manifest_contents() {
+        let binary = Path::new("/usr/local/bin/webtags-host");
+
+        let chrome = manifest(Browser::Chrome, binary, &ids());
+        assert_eq!(chrome["name"], HOST_NAME);
+        assert_eq!(chrome["path"], "/usr/local/bin/webtags-host");
+        assert_eq!(
+            chrome["allowed_origins"][0],
+            format!("chrome-extension://{CHROME_ID}/")
+        );
+        assert!(chrome.get("allowed_extensions").is_none());
+
+        let firefox = manifest(Browser::Firefox, binary, &ids());
+        assert_eq!(
+            firefox["allowed_extensions"][0],
+            DEFAULT_FIREFOX_EXTENSION_ID
+        );
+        assert!(firefox.get("allowed_origins").is_none());
+    }
+
+    #[test]
+    fn test_validate_chromium_id() {
+        assert!(validate_chromium_id(CHROME_ID).is_ok());
+        assert!(validate_chromium_id("EXTENSION_ID_PLACEHOLDER").is_err());
+        assert!(validate_chromium_id("zbcdefghijklmnopabcdefghijklmnop").is_err());
+    }
+
+    #[test]
+    fn test_install_only_detected_browsers_then_uninstall() {
+        let home = TempDir::new().unwrap();
+        fs::create_dir_all(home.path().join(Browser::Chrome.config_dir())).unwrap();
+        fs::create_dir_all(home.path().join(Browser::Firefox.config_dir())).unwrap();
+        let binary = Path::new("/opt/webtags/webtags-host");
+
+        let results = install(home.path(), binary, &ids());
+        let installed: Vec<Browser> = results
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Ok(Outcome::Done(_))))
+            .map(|(browser, _)| *browser)
+            .collect();
+        assert_eq!(installed, vec![Browser::Chrome, Browser::Firefox]);
+
+        let chrome_manifest = Browser::Chrome
+            .manifest_dir(home.path())
+            .join(format!("{HOST_NAME}.json"));
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&chrome_manifest).unwrap()).unwrap();
+        assert_eq!(written["path"], "/opt/webtags/webtags-host");
+
+        let removed = uninstall(home.path())
+            .into_iter()
+            .filter(|(_, outcome)| matches!(outcome, Ok(Outcome::Done(_))))
+            .count();
+        assert_eq!(removed, 2);
+        assert!(!chrome_manifest.exists());
+    }
+
+    #[test]
+    fn test_chromium_browsers_skipped_without_id() {
+        let home = TempDir::new().unwrap();
+        fs::create_dir_all(home.path().join(Browser::Brave.config_dir())).unwrap();
+        let ids = ExtensionIds {
+            chromium: Vec::new(),
+            firefox: vec![DEFAULT_FIREFOX_EXTENSION_ID.to_string()],
+        };
+
+        let results = install(home.path(), Path::new("/bin/webtags-host"), &ids);
+        let brave = results
+            .iter()
+            .find(|(browser, _)| *browser == Browser::Brave)
+            .unwrap();
+        assert!(
+            matches!(&brave.1, Ok(Outcome::Skipped(reason)) if reason.contains("extension ID"))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::storage::{self, BookmarksData, Resource};
+
+/// Journal of undoable operations. Kept inside `.git` so it is per-clone and
+/// never committed.
+pub const JOURNAL_FILE: &str = "journal.json";
+
+/// Operations kept; older ones can no longer be undone
+pub const MAX_ENTRIES: usize = 20;
+
+/// One resource as it was before and after an operation; `None` where it
+/// didn't exist
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Change {
+    pub id: String,
+    pub before: Option<Resource>,
+    pub after: Option<Resource>,
+}
+
+/// A mutating operation and everything it changed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Entry {
+    /// What was done, e.g. `delete`, `bulkop`, or `merge`
+    pub operation: String,
+    pub at: DateTime<Utc>,
+    pub changes: Vec<Change>,
+}
+
+/// Persisted journal, oldest first
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Journal {
+    pub entries: Vec<Entry>,
+}
+
+/// What [`Journal::undo`] did
+#[derive(Debug, Serialize, Clone, PartialEq, Default)]
+pub struct UndoSummary {
+    /// Operations undone, most recent first
+    pub undone: Vec<String>,
+    /// Resources left alone because they changed again after the operation
+    pub skipped: Vec<String>,
+}
+
+fn journal_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(".git").join(JOURNAL_FILE)
+}
+
+fn by_id(data: &BookmarksData) -> HashMap<&str, &Resource> {
+    data.data()
+        .iter()
+        .chain(data.included().into_iter().flatten())
+        .map(|resource| (resource.id(), resource))
+        .collect()
+}
+
+impl Journal {
+    /// Load the journal for a repository, or an empty one if none exists
+    pub fn load<P: AsRef<Path>>(repo_path: P) -> Result<Self> {
+        let path = journal_path(repo_path.as_ref());
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path).context("Failed to read operation journal")?;
+        serde_json::from_str(&content).context("Failed to parse operation journal")
+    }
+
+    /// Save the journal, removing the file once it is empty
+    pub fn save<P: AsRef<Path>>(&self, repo_path: P) -> Result<()> {
+        let path = journal_path(repo_path.as_ref());
+        if self.entries.is_empty() {
+            if path.exists() {
+                fs::remove_file(&path).context("Failed to remove operation journal")?;
+            }
+            return Ok(());
+        }
+
+        let json = serde_json::to_string(self).context("Failed to serialize operation journal")?;
+        storage::write_atomic(&path, json.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Record the resources that differ between `before` and `after`.
+    /// Operations that changed nothing aren't recorded.
+    pub fn record(&mut self, operation: &str, before: &BookmarksData, after: &BookmarksData) {
+        let old = by_id(before);
+        let new = by_id(after);
+        let mut changes: Vec<Change> = old
+            .keys()
+            .chain(new.keys().filter(|id| !old.contains_key(*id)))
+            .filter(|id| old.get(*id) != new.get(*id))
+            .map(|id| Change {
+                id: (*id).to_string(),
+                before: old.get(id).map(|r| (*r).clone()),
+                after: new.get(id).map(|r| (*r).clone()),
+            })
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        changes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        self.entries.push(Entry {
+            operation: operation.to_string(),
+            at: Utc::now(),
+            changes,
+        });
+        let excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+        self.entries.drain(..excess);
+    }
+
+    /// Revert the last `count` operations in `data`, most recent first, and
+    /// drop them from the journal. A resource that changed again since an
+    /// operation keeps its current state.
+    pub fn undo(&mut self, data: &mut BookmarksData, count: usize) -> UndoSummary {
+        let mut summary = UndoSummary::default();
+        let keep = self.entries.len().saturating_sub(count);
+        for entry in self.entries.drain(keep..).rev() {
+            for change in entry.changes {
+                if data.get(&change.id) != change.after.as_ref() {
+                    summary.skipped.push(change.id);
+                    continue;
+                }
+                data.remove_resource(&change.id);
+                if let Some(before) = change.before {
+                    data.add_resource(before);
+                }
+            }
+            summary.undone.push(entry.operation);
+        }
+        summary
+    }
+}
+
+/// Record an operation in the journal stored in `repo_path`
+pub fn record(
+    repo_path: &Path,
+    operation: &str,
+    before: &BookmarksData,
+    after: &BookmarksData,
+) -> Result<()> {
+    let mut journal = Journal::load(repo_path)?;
+    journal.record(operation, before, after);
+    journal.save(repo_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+    use tempfile::TempDir;
+
+    fn sample() -> (BookmarksData, Vec<String>) {
+        let mut data = BookmarksData::new();
+        let tag = create_tag("rust".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+        let mut ids = vec![tag_id.clone()];
+        for url in ["https://a.example", "https://b.example"] {
+            let bookmark = create_bookmark(url.to_string(), url.to_string(), vec![tag_id.clone()]);
+            ids.push(bookmark.id().to_string());
+            data.add_bookmark(bookmark).unwrap();
+        }
+        (data, ids)
+    }
+
+    #[test]
+    fn test_undo_delete() {
+        let (before, ids) = sample();
+        let mut after = before.clone();
+        after.remove_resource(&ids[1]);
+
+        let mut journal = Journal::default();
+        journal.record("delete", &before, &after);
+        journal.record("nothing", &after, &after);
+        assert_eq!(journal.entries.len(), 1);
+
+        let summary = journal.undo(&mut after, 1);
+        assert_eq!(summary.undone, ["delete"]);
+        assert!(summary.skipped.is_empty());
+        assert_eq!(after.get(&ids[1]), before.get(&ids[1]));
+        assert!(after.validate().is_ok());
+        assert!(journal.entries.is_empty());
+    }
+
+    #[test]
+    fn test_undo_several_and_skip_changed() {
+        let (first, ids) = sample();
+        let mut second = first.clone();
+        second
+            .update_bookmark(&ids[1], Some("Renamed".to_string()), None)
+            .unwrap();
+        let mut third = second.clone();
+        third.remove_resource(&ids[2]);
+
+        let mut journal = Journal::default();
+        journal.record("rename", &first, &second);
+        journal.record("delete", &second, &third);
+
+        // Edited again after the rename, so that edit stays
+        third
+            .update_bookmark(&ids[1], Some("Edited".to_string()), None)
+            .unwrap();
+        let summary = journal.undo(&mut third, 5);
+        assert_eq!(summary.undone, ["delete", "rename"]);
+        assert_eq!(summary.skipped, [ids[1].clone()]);
+        assert!(third.get(&ids[2]).is_some());
+        match third.get(&ids[1]) {
+            Some(Resource::Bookmark { attributes, .. }) => assert_eq!(attributes.title, "Edited"),
+            other => panic!("expected bookmark, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_journal_is_capped_and_persisted() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        let (mut data, _) = sample();
+        for i in 0..=MAX_ENTRIES {
+            let before = data.clone();
+            data.add_bookmark(create_bookmark(
+                format!("https://{i}.example"),
+                String::new(),
+                vec![],
+            ))
+            .unwrap();
+            record(dir.path(), &format!("add {i}"), &before, &data).unwrap();
+        }
+        let journal = Journal::load(dir.path()).unwrap();
+        assert_eq!(journal.entries.len(), MAX_ENTRIES);
+        assert_eq!(journal.entries[0].operation, "add 1");
+
+        Journal::default().save(dir.path()).unwrap();
+        assert!(!dir.path().join(".git").join(JOURNAL_FILE).exists());
+    }
+}
@@ -0,0 +1,526 @@
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::proxy;
+use crate::storage;
+
+/// Directory in the repo whose files are stored through LFS: one page
+/// archive per bookmark, named by its ID
+pub const ARCHIVE_DIR: &str = "archive";
+
+/// Tells the git-lfs command line tool that archive files are LFS pointers
+const ATTRIBUTES_LINE: &str = "archive/** filter=lfs diff=lfs merge=lfs -text";
+
+const POINTER_VERSION: &str = "https://git-lfs.github.com/spec/v1";
+const MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// Objects per batch request; servers commonly refuse more
+const BATCH_SIZE: usize = 100;
+
+/// What git stores in place of a large file: the SHA-256 and size of the
+/// content, which lives in the LFS store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pointer {
+    pub oid: String,
+    pub size: u64,
+}
+
+impl Pointer {
+    pub fn for_content(content: &[u8]) -> Self {
+        Self {
+            oid: hex::encode(Sha256::digest(content)),
+            size: content.len() as u64,
+        }
+    }
+
+    /// Parse a pointer file; `None` if `text` isn't one
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next()?.strip_prefix("version ")? != POINTER_VERSION {
+            return None;
+        }
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            match line.split_once(' ') {
+                Some(("oid", value)) => oid = value.strip_prefix("sha256:"),
+                Some(("size", value)) => size = value.parse().ok(),
+                _ => {}
+            }
+        }
+        // The oid names a file in the store, so nothing but hex gets through
+        let oid = oid.filter(|oid| {
+            oid.len() == 64
+                && oid
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        })?;
+        Some(Self {
+            oid: oid.to_string(),
+            size: size?,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "version {POINTER_VERSION}\noid sha256:{}\nsize {}\n",
+            self.oid, self.size
+        )
+    }
+}
+
+/// LFS objects on this device, laid out as git-lfs does
+/// (`.git/lfs/objects/ab/cd/abcd…`) so the two can share a clone
+pub struct ObjectStore {
+    root: PathBuf,
+}
+
+impl ObjectStore {
+    pub fn for_git_dir(git_dir: &Path) -> Self {
+        Self {
+            root: git_dir.join("lfs").join("objects"),
+        }
+    }
+
+    fn path_of(&self, pointer: &Pointer) -> PathBuf {
+        self.root
+            .join(&pointer.oid[0..2])
+            .join(&pointer.oid[2..4])
+            .join(&pointer.oid)
+    }
+
+    pub fn contains(&self, pointer: &Pointer) -> bool {
+        self.path_of(pointer).exists()
+    }
+
+    pub fn put(&self, content: &[u8]) -> Result<Pointer> {
+        let pointer = Pointer::for_content(content);
+        let path = self.path_of(&pointer);
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).context("Failed to create LFS object directory")?;
+            }
+            storage::write_atomic(&path, content)?;
+        }
+        Ok(pointer)
+    }
+
+    /// The object's content, or `None` if it isn't on this device
+    pub fn get(&self, pointer: &Pointer) -> Result<Option<Vec<u8>>> {
+        let path = self.path_of(pointer);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read(&path).context("Failed to read LFS object")?;
+        if Pointer::for_content(&content) != *pointer {
+            anyhow::bail!("LFS object {} is corrupt", pointer.oid);
+        }
+        Ok(Some(content))
+    }
+}
+
+/// Path in the repo of a bookmark's archive
+pub fn archive_path(bookmark_id: &str) -> Result<String> {
+    let valid = !bookmark_id.is_empty()
+        && bookmark_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+    if !valid {
+        anyhow::bail!("Bookmark ID {bookmark_id:?} can't name an archive file");
+    }
+    Ok(format!("{ARCHIVE_DIR}/{bookmark_id}"))
+}
+
+/// Store `content` as the archive of `bookmark_id`: the content goes in
+/// `store` and a pointer to it in `archive/<id>`, with `.gitattributes`
+/// marking archives as LFS files. Returns the repo paths to stage.
+pub fn store_archive(
+    repo_path: &Path,
+    store: &ObjectStore,
+    bookmark_id: &str,
+    content: &[u8],
+) -> Result<Vec<String>> {
+    let path = archive_path(bookmark_id)?;
+    let pointer = store.put(content)?;
+    fs::create_dir_all(repo_path.join(ARCHIVE_DIR))
+        .context("Failed to create archive directory")?;
+    storage::write_atomic(&repo_path.join(&path), pointer.to_text().as_bytes())?;
+
+    let attributes_path = repo_path.join(".gitattributes");
+    let attributes = fs::read_to_string(&attributes_path).unwrap_or_default();
+    if !attributes
+        .lines()
+        .any(|line| line.trim() == ATTRIBUTES_LINE)
+    {
+        let mut updated = attributes;
+        if !updated.is_empty() && !updated.ends_with('\n') {
+            updated.push('\n');
+        }
+        updated.push_str(ATTRIBUTES_LINE);
+        updated.push('\n');
+        fs::write(&attributes_path, updated).context("Failed to update .gitattributes")?;
+    }
+    Ok(vec![path, ".gitattributes".to_string()])
+}
+
+/// The pointer for `bookmark_id`'s archive, if it has one
+pub fn read_pointer(repo_path: &Path, bookmark_id: &str) -> Result<Option<Pointer>> {
+    let path = repo_path.join(archive_path(bookmark_id)?);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(&path).context("Failed to read archive pointer")?;
+    Pointer::parse(&text)
+        .map(Some)
+        .with_context(|| format!("{} isn't an LFS pointer", path.display()))
+}
+
+/// Pointers of every archive in the working tree
+pub fn pointers(repo_path: &Path) -> Result<Vec<Pointer>> {
+    let dir = repo_path.join(ARCHIVE_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut pointers = Vec::new();
+    for entry in fs::read_dir(&dir).context("Failed to read archive directory")? {
+        let entry = entry.context("Failed to read archive directory")?;
+        let text = fs::read_to_string(entry.path()).unwrap_or_default();
+        match Pointer::parse(&text) {
+            Some(pointer) => pointers.push(pointer),
+            None => log::warn!("Skipping {}: not an LFS pointer", entry.path().display()),
+        }
+    }
+    Ok(pointers)
+}
+
+#[derive(Debug, Serialize)]
+struct BatchRequest<'a> {
+    operation: &'a str,
+    transfers: [&'a str; 1],
+    objects: &'a [Pointer],
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchResponse {
+    #[serde(default)]
+    objects: Vec<BatchObject>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchObject {
+    oid: String,
+    /// None when the server already has an object being uploaded
+    #[serde(default)]
+    actions: Option<HashMap<String, Action>>,
+    #[serde(default)]
+    error: Option<ObjectError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Action {
+    href: String,
+    #[serde(default)]
+    header: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ObjectError {
+    code: u16,
+    message: String,
+}
+
+/// Talks to a Git LFS server's batch API with the basic transfer adapter
+pub struct LfsClient {
+    client: Client,
+    endpoint: String,
+    /// GitHub token, sent as the password of basic auth
+    token: Option<String>,
+}
+
+impl LfsClient {
+    /// `endpoint` as derived by [`crate::git_url::lfs_endpoint`], or the
+    /// repository's `lfs.url`
+    pub fn new(endpoint: &str, token: Option<String>) -> Self {
+        let client = proxy::http_client().unwrap_or_else(|e| {
+            log::warn!("Ignoring proxy configuration: {e:#}");
+            Client::new()
+        });
+        Self {
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, url)
+            .header("User-Agent", "WebTags");
+        match &self.token {
+            Some(token) => request.basic_auth("x-access-token", Some(token)),
+            None => request,
+        }
+    }
+
+    async fn batch(&self, operation: &str, pointers: &[Pointer]) -> Result<Vec<BatchObject>> {
+        let mut objects = Vec::new();
+        for chunk in pointers.chunks(BATCH_SIZE) {
+            let response = self
+                .request(
+                    reqwest::Method::POST,
+                    &format!("{}/objects/batch", self.endpoint),
+                )
+                .header("Accept", MEDIA_TYPE)
+                .header("Content-Type", MEDIA_TYPE)
+                .json(&BatchRequest {
+                    operation,
+                    transfers: ["basic"],
+                    objects: chunk,
+                })
+                .send()
+                .await
+                .context("LFS batch request failed")?;
+            let status = response.status();
+            if !status.is_success() {
+                anyhow::bail!("LFS server refused the {operation} batch ({status})");
+            }
+            let batch: BatchResponse = response
+                .json()
+                .await
+                .context("Failed to parse LFS batch response")?;
+            objects.extend(batch.objects);
+        }
+        for object in &objects {
+            if let Some(error) = &object.error {
+                anyhow::bail!(
+                    "LFS object {}: {} ({})",
+                    object.oid,
+                    error.message,
+                    error.code
+                );
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Upload the objects the server doesn't have yet. Returns how many
+    /// were sent.
+    pub async fn upload(&self, store: &ObjectStore, pointers: &[Pointer]) -> Result<usize> {
+        let local: Vec<Pointer> = pointers
+            .iter()
+            .filter(|pointer| store.contains(pointer))
+            .cloned()
+            .collect();
+        if local.is_empty() {
+            return Ok(0);
+        }
+        let by_oid: HashMap<&str, &Pointer> = local.iter().map(|p| (p.oid.as_str(), p)).collect();
+
+        let mut uploaded = 0;
+        for object in self.batch("upload", &local).await? {
+            let Some(mut actions) = object.actions else {
+                continue;
+            };
+            let (Some(upload), Some(pointer)) =
+                (actions.remove("upload"), by_oid.get(object.oid.as_str()))
+            else {
+                continue;
+            };
+            let content = store
+                .get(pointer)?
+                .with_context(|| format!("LFS object {} went missing", pointer.oid))?;
+            let mut request = self
+                .client
+                .put(&upload.href)
+                .header("Content-Type", "application/octet-stream");
+            for (name, value) in &upload.header {
+                request = request.header(name, value);
+            }
+            let status = request
+                .body(content)
+                .send()
+                .await
+                .context("LFS upload failed")?
+                .status();
+            if !status.is_success() {
+                anyhow::bail!("LFS server refused object {} ({status})", pointer.oid);
+            }
+            if let Some(verify) = actions.remove("verify") {
+                let mut request = self
+                    .client
+                    .post(&verify.href)
+                    .header("Content-Type", MEDIA_TYPE)
+                    .json(pointer);
+                for (name, value) in &verify.header {
+                    request = request.header(name, value);
+                }
+                let status = request.send().await.context("LFS verify failed")?.status();
+                if !status.is_success() {
+                    anyhow::bail!("LFS server didn't verify object {} ({status})", pointer.oid);
+                }
+            }
+            uploaded += 1;
+        }
+        Ok(uploaded)
+    }
+
+    /// Download an object into `store` and return its content
+    pub async fn download(&self, store: &ObjectStore, pointer: &Pointer) -> Result<Vec<u8>> {
+        let object = self
+            .batch("download", std::slice::from_ref(pointer))
+            .await?
+            .into_iter()
+            .find(|object| object.oid == pointer.oid)
+            .with_context(|| format!("LFS server doesn't have object {}", pointer.oid))?;
+        let download = object
+            .actions
+            .and_then(|mut actions| actions.remove("download"))
+            .with_context(|| format!("LFS server can't send object {}", pointer.oid))?;
+
+        let mut request = self.client.get(&download.href);
+        for (name, value) in &download.header {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.context("LFS download failed")?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("LFS download of {} failed ({status})", pointer.oid);
+        }
+        let content = response.bytes().await.context("LFS download failed")?;
+        if Pointer::for_content(&content) != *pointer {
+            anyhow::bail!(
+                "Downloaded LFS object {} doesn't match its pointer",
+                pointer.oid
+            );
+        }
+        store.put(&content)?;
+        Ok(content.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_pointer_round_trip() {
+        let pointer = Pointer::for_content(b"<html>archived</html>");
+        assert_eq!(pointer.size, 21);
+        assert_eq!(Pointer::parse(&pointer.to_text()), Some(pointer.clone()));
+        assert!(pointer
+            .to_text()
+            .starts_with("version https://git-lfs.github.com/spec/v1\n"));
+
+        assert_eq!(Pointer::parse("<html>not a pointer</html>"), None);
+        let traversal = pointer.to_text().replace(&pointer.oid, "../../etc/passwd");
+        assert_eq!(Pointer::parse(&traversal), None);
+    }
+
+    #[test]
+    fn test_store_archive() {
+        let dir = TempDir::new().unwrap();
+        let store = ObjectStore::for_git_dir(&dir.path().join(".git"));
+        let paths = store_archive(dir.path(), &store, "bookmark-1", b"page").unwrap();
+        assert_eq!(paths, vec!["archive/bookmark-1", ".gitattributes"]);
+        store_archive(dir.path(), &store, "bookmark-2", b"other page").unwrap();
+
+        let pointer = read_pointer(dir.path(), "bookmark-1").unwrap().unwrap();
+        assert_eq!(store.get(&pointer).unwrap().as_deref(), Some(&b"page"[..]));
+        assert_eq!(pointers(dir.path()).unwrap().len(), 2);
+        assert_eq!(read_pointer(dir.path(), "bookmark-3").unwrap(), None);
+        assert!(archive_path("../bookmarks.json").is_err());
+
+        let attributes = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+        assert_eq!(attributes, format!("{ATTRIBUTES_LINE}\n"));
+    }
+
+    #[tokio::test]
+    async fn test_upload_and_download() {
+        let server = MockServer::start().await;
+        let dir = TempDir::new().unwrap();
+        let store = ObjectStore::for_git_dir(dir.path());
+        let pointer = store.put(b"page").unwrap();
+        let stored = Pointer::for_content(b"already there");
+
+        Mock::given(method("POST"))
+            .and(path("/repo.git/info/lfs/objects/batch"))
+            .and(header("Accept", MEDIA_TYPE))
+            .and(body_json(serde_json::json!({
+                "operation": "upload",
+                "transfers": ["basic"],
+                "objects": [pointer],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "objects": [{
+                    "oid": pointer.oid,
+                    "size": pointer.size,
+                    "actions": {
+                        "upload": {
+                            "href": format!("{}/upload/{}", server.uri(), pointer.oid),
+                            "header": { "X-Upload": "yes" },
+                        },
+                    },
+                }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path(format!("/upload/{}", pointer.oid)))
+            .and(header("X-Upload", "yes"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = LfsClient::new(&format!("{}/repo.git/info/lfs/", server.uri()), None);
+        // Objects only another device has aren't part of the upload
+        assert_eq!(
+            client
+                .upload(&store, &[pointer.clone(), stored])
+                .await
+                .unwrap(),
+            1
+        );
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/objects/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "objects": [{
+                    "oid": pointer.oid,
+                    "size": pointer.size,
+                    "actions": {
+                        "download": { "href": format!("{}/download", server.uri()) },
+                    },
+                }],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/download"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"page".to_vec()))
+            .mount(&server)
+            .await;
+
+        let other = TempDir::new().unwrap();
+        let other_store = ObjectStore::for_git_dir(other.path());
+        let client = LfsClient::new(&server.uri(), None);
+        assert_eq!(
+            client.download(&other_store, &pointer).await.unwrap(),
+            b"page"
+        );
+        assert!(other_store.contains(&pointer));
+        // Content that doesn't match the pointer is refused
+        let wrong = Pointer::for_content(b"something else");
+        assert!(client.download(&other_store, &wrong).await.is_err());
+    }
+}
@@ -1,9 +1,60 @@
 // Library exports for WebTags native messaging host
 // This allows integration tests to import and test the modules
 
+pub mod activity;
+pub mod backup;
+pub mod batch;
+pub mod bulk;
+pub mod color;
+pub mod commit_template;
+pub mod compression;
+pub mod device;
+pub mod diff;
+pub mod doctor;
 pub mod encryption;
+pub mod enrich;
+pub mod error;
+pub mod expiry;
+pub mod gist;
 pub mod git;
 pub mod git_url;
 pub mod github;
+pub mod hooks;
+pub mod import;
+pub mod index;
+pub mod install;
+pub mod journal;
+pub mod lfs;
+pub mod linkcheck;
+pub mod lock;
+pub mod logging;
+pub mod markdown;
+pub mod mcp;
+pub mod merge;
 pub mod messaging;
+pub mod metrics;
+pub mod migrations;
+pub mod origin;
+pub mod pending;
+pub mod proxy;
+pub mod query;
+pub mod recovery;
+pub mod report;
+pub mod retry;
+pub mod rules;
+pub mod s3;
+pub mod similar;
+pub mod site;
+pub mod snapshots;
+pub mod ssh;
+pub mod stats;
 pub mod storage;
+pub mod suggest;
+pub mod sync;
+pub mod tag_ops;
+pub mod unicode;
+pub mod urlnorm;
+pub mod validation;
+pub mod wal;
+pub mod wayback;
+pub mod webhooks;
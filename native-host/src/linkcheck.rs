@@ -0,0 +1,599 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::bulk;
+use crate::git::GitRepo;
+use crate::snapshots::Schedule;
+use crate::storage::{self, BookmarksData, Resource};
+
+/// Check results over time, local to each clone since they depend on this
+/// device's network
+pub const STATUS_FILE: &str = "linkstatus.json";
+
+/// Tag given to bookmarks whose link keeps failing
+pub const DEAD_LINK_TAG: &str = "dead-link";
+
+/// How often links are checked, kept in the repository's local git config
+const SCHEDULE_KEY: &str = "webtags.linkCheck";
+/// Failed checks in a row before a bookmark is tagged
+const THRESHOLD_KEY: &str = "webtags.linkCheckFailures";
+
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// Checks kept per link
+const HISTORY_LEN: usize = 10;
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CONCURRENT_CHECKS: usize = 8;
+/// Wait after a run that recorded nothing (this device seemed offline)
+/// before a scheduled run tries again
+const RETRY_AFTER_OFFLINE: chrono::Duration = chrono::Duration::hours(1);
+
+/// Link check settings for one repository
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub schedule: Schedule,
+    pub failure_threshold: u32,
+}
+
+/// Settings to change with [`Settings::update`]; `None` leaves one as it is
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Update {
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    #[serde(default)]
+    pub failure_threshold: Option<u32>,
+}
+
+impl Settings {
+    pub fn load(repo: &GitRepo) -> Self {
+        Self {
+            schedule: repo
+                .local_config(SCHEDULE_KEY)
+                .and_then(|name| Schedule::from_name(&name))
+                .unwrap_or_default(),
+            failure_threshold: repo
+                .local_config(THRESHOLD_KEY)
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(DEFAULT_FAILURE_THRESHOLD),
+        }
+    }
+
+    /// Change the settings given, leaving the others as they are
+    pub fn update(repo: &GitRepo, update: Update) -> Result<Self> {
+        if update.failure_threshold == Some(0) {
+            anyhow::bail!("A link must fail at least once before it's tagged dead");
+        }
+        if let Some(schedule) = update.schedule {
+            let name = (schedule != Schedule::Off).then_some(schedule.name());
+            repo.set_local_config(SCHEDULE_KEY, name)?;
+        }
+        if let Some(threshold) = update.failure_threshold {
+            let threshold = (threshold != DEFAULT_FAILURE_THRESHOLD).then(|| threshold.to_string());
+            repo.set_local_config(THRESHOLD_KEY, threshold.as_deref())?;
+        }
+        Ok(Self::load(repo))
+    }
+}
+
+/// One check of one link
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Check {
+    pub at: DateTime<Utc>,
+    pub ok: bool,
+    /// HTTP status, if the server answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Why there was no answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// History of one bookmark's link
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LinkStatus {
+    pub url: String,
+    pub consecutive_failures: u32,
+    /// Start of the current run of failures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failing_since: Option<DateTime<Utc>>,
+    /// Whether the dead-link tag was added by the checker, so it's only
+    /// taken off again if the user didn't put it there
+    #[serde(default)]
+    pub tagged: bool,
+    /// Newest last
+    pub history: Vec<Check>,
+}
+
+/// Contents of [`STATUS_FILE`], keyed by bookmark ID
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StatusFile {
+    /// Last run that recorded results
+    #[serde(default)]
+    pub last_run: Option<DateTime<Utc>>,
+    /// Last run, even one that found this device offline
+    #[serde(default)]
+    pub last_attempt: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub links: BTreeMap<String, LinkStatus>,
+}
+
+fn status_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(".git").join(STATUS_FILE)
+}
+
+impl StatusFile {
+    pub fn load<P: AsRef<Path>>(repo_path: P) -> Result<Self> {
+        let path = status_path(repo_path.as_ref());
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path).context("Failed to read link status")?;
+        serde_json::from_str(&content).context("Failed to parse link status")
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, repo_path: P) -> Result<()> {
+        let json = serde_json::to_vec(self).context("Failed to serialize link status")?;
+        storage::write_atomic(&status_path(repo_path.as_ref()), &json)
+    }
+
+    /// Whether a scheduled check should run at `now`
+    pub fn is_due(&self, schedule: Schedule, now: DateTime<Utc>) -> bool {
+        let Some(interval) = schedule.interval() else {
+            return false;
+        };
+        self.last_run.is_none_or(|last| now - last >= interval)
+            && self
+                .last_attempt
+                .is_none_or(|last| now - last >= RETRY_AFTER_OFFLINE)
+    }
+
+    /// Add a check to a link's history. A changed URL starts a new history.
+    fn record(&mut self, id: &str, url: &str, check: Check) -> &mut LinkStatus {
+        let status = self
+            .links
+            .entry(id.to_string())
+            .or_insert_with(|| LinkStatus {
+                url: url.to_string(),
+                consecutive_failures: 0,
+                failing_since: None,
+                tagged: false,
+                history: Vec::new(),
+            });
+        if status.url != url {
+            status.url = url.to_string();
+            status.consecutive_failures = 0;
+            status.failing_since = None;
+            status.history.clear();
+        }
+        if check.ok {
+            status.consecutive_failures = 0;
+            status.failing_since = None;
+        } else {
+            status.consecutive_failures += 1;
+            status.failing_since.get_or_insert(check.at);
+        }
+        status.history.push(check);
+        let excess = status.history.len().saturating_sub(HISTORY_LEN);
+        status.history.drain(..excess);
+        status
+    }
+}
+
+/// A bookmark whose link changed state in a run
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct LinkChange {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failing_since: Option<DateTime<Utc>>,
+    /// Archived copy of the page, from [`crate::wayback`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive_url: Option<String>,
+}
+
+/// What a run found
+#[derive(Debug, Serialize, Clone, PartialEq, Default)]
+pub struct RunSummary {
+    pub checked: usize,
+    pub failing: usize,
+    /// Tagged dead in this run
+    pub newly_dead: Vec<LinkChange>,
+    /// Working again, and untagged if the checker had tagged them
+    pub recovered: Vec<LinkChange>,
+    /// False when every check failed to connect, which more likely means
+    /// this device is offline; nothing was recorded then
+    pub recorded: bool,
+}
+
+/// Bookmarks with links worth checking: http(s) only
+pub fn targets(data: &BookmarksData) -> Vec<(String, String)> {
+    data.get_bookmarks()
+        .into_iter()
+        .filter_map(|resource| match resource {
+            Resource::Bookmark { id, attributes, .. } => Some((id.clone(), attributes.url.clone())),
+            _ => None,
+        })
+        .filter(|(_, url)| {
+            url::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        })
+        .collect()
+}
+
+/// Check one link. Servers that refuse `HEAD` are asked with `GET`.
+/// Answers meaning "not for you" (401, 403, 429) count as alive.
+pub async fn check_url(client: &Client, url: &str) -> Check {
+    let at = Utc::now();
+    let mut response = client.head(url).timeout(CHECK_TIMEOUT).send().await;
+    if let Ok(head) = &response {
+        if matches!(
+            head.status(),
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        ) {
+            response = client.get(url).timeout(CHECK_TIMEOUT).send().await;
+        }
+    }
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            let ok = !(status.is_client_error() || status.is_server_error())
+                || matches!(
+                    status,
+                    StatusCode::UNAUTHORIZED
+                        | StatusCode::FORBIDDEN
+                        | StatusCode::TOO_MANY_REQUESTS
+                );
+            Check {
+                at,
+                ok,
+                status: Some(status.as_u16()),
+                error: None,
+            }
+        }
+        Err(e) => Check {
+            at,
+            ok: false,
+            status: None,
+            error: Some(if e.is_timeout() {
+                "timed out".to_string()
+            } else if e.is_connect() {
+                "could not connect".to_string()
+            } else {
+                "request failed".to_string()
+            }),
+        },
+    }
+}
+
+/// Check every target, calling `on_progress(done, total)` as checks finish
+pub async fn check_all(
+    client: &Client,
+    targets: Vec<(String, String)>,
+    mut on_progress: impl FnMut(usize, usize),
+) -> Vec<(String, String, Check)> {
+    let total = targets.len();
+    let mut pending = targets.into_iter();
+    let mut checks = JoinSet::new();
+    let mut results = Vec::with_capacity(total);
+    loop {
+        while checks.len() < MAX_CONCURRENT_CHECKS {
+            let Some((id, url)) = pending.next() else {
+                break;
+            };
+            let client = client.clone();
+            checks.spawn(async move {
+                let check = check_url(&client, &url).await;
+                (id, url, check)
+            });
+        }
+        let Some(finished) = checks.join_next().await else {
+            break;
+        };
+        if let Ok(result) = finished {
+            results.push(result);
+        }
+        on_progress(results.len(), total);
+    }
+    results
+}
+
+/// Record `results` in `status` and tag bookmarks that have failed
+/// `threshold` times in a row, untagging those that recovered. Returns what
+/// changed; `data` is only modified if some tag did.
+pub fn apply(
+    status: &mut StatusFile,
+    data: &mut BookmarksData,
+    results: Vec<(String, String, Check)>,
+    threshold: u32,
+    now: DateTime<Utc>,
+) -> RunSummary {
+    let mut summary = RunSummary {
+        checked: results.len(),
+        ..RunSummary::default()
+    };
+    status.last_attempt = Some(now);
+    let answered = results.iter().any(|(_, _, check)| check.status.is_some());
+    if !results.is_empty() && !answered {
+        return summary;
+    }
+    summary.recorded = true;
+    status.last_run = Some(now);
+
+    let mut dead = Vec::new();
+    let mut recovered = Vec::new();
+    for (id, url, check) in results {
+        let ok = check.ok;
+        let link = status.record(&id, &url, check);
+        if !ok {
+            summary.failing += 1;
+        }
+        if !ok && link.consecutive_failures >= threshold && !link.tagged {
+            link.tagged = true;
+            dead.push((id, link.failing_since));
+        } else if ok && link.tagged {
+            link.tagged = false;
+            recovered.push(id);
+        }
+    }
+    // Forget bookmarks that were deleted
+    status.links.retain(|id, _| data.get(id).is_some());
+
+    if !dead.is_empty() {
+        let tag_id = bulk::top_level_tag(data, DEAD_LINK_TAG);
+        for (id, failing_since) in dead {
+            if let Some(change) = set_tag(data, &id, &tag_id, true, failing_since) {
+                summary.newly_dead.push(change);
+            }
+        }
+    }
+    if let Some(tag_id) = bulk::find_top_level_tag(data, DEAD_LINK_TAG) {
+        for id in recovered {
+            if let Some(change) = set_tag(data, &id, &tag_id, false, None) {
+                summary.recovered.push(change);
+            }
+        }
+    }
+    summary
+}
+
+fn set_tag(
+    data: &mut BookmarksData,
+    bookmark_id: &str,
+    tag_id: &str,
+    add: bool,
+    failing_since: Option<DateTime<Utc>>,
+) -> Option<LinkChange> {
+    data.data_mut()
+        .iter_mut()
+        .find_map(|resource| match resource {
+            Resource::Bookmark {
+                id,
+                attributes,
+                relationships,
+                ..
+            } if id == bookmark_id => {
+                if !bulk::set_tag(relationships, tag_id, add) {
+                    return None;
+                }
+                attributes.modified = Some(Utc::now());
+                Some(LinkChange {
+                    id: id.clone(),
+                    title: attributes.title.clone(),
+                    url: attributes.url.clone(),
+                    failing_since,
+                    archive_url: attributes.archive_url.clone(),
+                })
+            }
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn failed(at: DateTime<Utc>) -> Check {
+        Check {
+            at,
+            ok: false,
+            status: Some(404),
+            error: None,
+        }
+    }
+
+    fn passed(at: DateTime<Utc>) -> Check {
+        Check {
+            at,
+            ok: true,
+            status: Some(200),
+            error: None,
+        }
+    }
+
+    fn tags_of(data: &BookmarksData, id: &str) -> usize {
+        match data.get(id) {
+            Some(Resource::Bookmark { relationships, .. }) => relationships
+                .iter()
+                .flat_map(|r| r.tags.iter().flat_map(|tags| &tags.data))
+                .count(),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn test_dead_links_are_tagged_after_threshold_and_untagged_on_recovery() {
+        let mut data = BookmarksData::new();
+        let bookmark = create_bookmark(
+            "https://gone.example".to_string(),
+            "Gone".to_string(),
+            vec![],
+        );
+        let id = bookmark.id().to_string();
+        data.add_bookmark(bookmark).unwrap();
+        let url = "https://gone.example".to_string();
+        let mut status = StatusFile::default();
+        let first = Utc::now() - chrono::Duration::days(2);
+
+        for (day, expect_dead) in [(0, false), (1, true)] {
+            let at = first + chrono::Duration::days(day);
+            let summary = apply(
+                &mut status,
+                &mut data,
+                vec![(id.clone(), url.clone(), failed(at))],
+                2,
+                at,
+            );
+            assert!(summary.recorded);
+            assert_eq!(summary.failing, 1);
+            assert_eq!(summary.newly_dead.len(), usize::from(expect_dead));
+        }
+        let link = &status.links[&id];
+        assert_eq!(link.consecutive_failures, 2);
+        assert_eq!(link.failing_since, Some(first));
+        assert_eq!(tags_of(&data, &id), 1);
+        assert!(bulk::find_top_level_tag(&data, DEAD_LINK_TAG).is_some());
+
+        // Already tagged: a third failure doesn't report it again
+        let summary = apply(
+            &mut status,
+            &mut data,
+            vec![(id.clone(), url.clone(), failed(Utc::now()))],
+            2,
+            Utc::now(),
+        );
+        assert!(summary.newly_dead.is_empty());
+
+        let summary = apply(
+            &mut status,
+            &mut data,
+            vec![(id.clone(), url, passed(Utc::now()))],
+            2,
+            Utc::now(),
+        );
+        assert_eq!(summary.recovered.len(), 1);
+        assert_eq!(status.links[&id].failing_since, None);
+        assert_eq!(status.links[&id].history.len(), 4);
+        assert_eq!(tags_of(&data, &id), 0);
+    }
+
+    #[test]
+    fn test_offline_run_records_nothing() {
+        let mut data = BookmarksData::new();
+        let bookmark = create_bookmark("https://a.example".to_string(), "A".to_string(), vec![]);
+        let id = bookmark.id().to_string();
+        data.add_bookmark(bookmark).unwrap();
+        let mut status = StatusFile::default();
+        let unreachable = Check {
+            at: Utc::now(),
+            ok: false,
+            status: None,
+            error: Some("could not connect".to_string()),
+        };
+        let summary = apply(
+            &mut status,
+            &mut data,
+            vec![(id, "https://a.example".to_string(), unreachable)],
+            1,
+            Utc::now(),
+        );
+        assert!(!summary.recorded);
+        assert!(status.links.is_empty());
+        assert_eq!(status.last_run, None);
+        // Tried again an hour later rather than on every request
+        assert!(!status.is_due(Schedule::Daily, Utc::now()));
+        assert!(status.is_due(Schedule::Daily, Utc::now() + chrono::Duration::hours(1)));
+    }
+
+    #[test]
+    fn test_status_file_round_trip_and_schedule() {
+        let dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        let mut status = StatusFile::load(dir.path()).unwrap();
+        let now = Utc::now();
+        assert!(status.is_due(Schedule::Daily, now));
+        assert!(!status.is_due(Schedule::Off, now));
+        status.last_run = Some(now);
+        status.record("b1", "https://a.example", passed(now));
+        status.save(dir.path()).unwrap();
+        let loaded = StatusFile::load(dir.path()).unwrap();
+        assert_eq!(loaded, status);
+        assert!(!loaded.is_due(Schedule::Daily, now + chrono::Duration::hours(23)));
+        assert!(loaded.is_due(Schedule::Daily, now + chrono::Duration::hours(24)));
+
+        let settings = Settings::update(
+            &repo,
+            Update {
+                schedule: Some(Schedule::Daily),
+                failure_threshold: Some(5),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                schedule: Schedule::Daily,
+                failure_threshold: 5
+            }
+        );
+        assert!(Settings::update(
+            &repo,
+            Update {
+                failure_threshold: Some(0),
+                ..Update::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/gone"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/no-head"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/no-head"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/private"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+
+        let client = Client::new();
+        let check = |p: &str| format!("{}{p}", server.uri());
+        let gone = check_url(&client, &check("/gone")).await;
+        assert!(!gone.ok);
+        assert_eq!(gone.status, Some(404));
+        assert!(check_url(&client, &check("/no-head")).await.ok);
+        assert!(check_url(&client, &check("/private")).await.ok);
+
+        let mut data = BookmarksData::new();
+        for url in [check("/gone"), "magnet:?xt=urn:btih:abc".to_string()] {
+            data.add_bookmark(create_bookmark(url, "x".to_string(), vec![]))
+                .unwrap();
+        }
+        let results = check_all(&client, targets(&data), |_, _| {}).await;
+        assert_eq!(results.len(), 1);
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+/// Lock file inside `.git`, so it is per-clone and never committed
+pub const LOCK_FILE: &str = "webtags.lock";
+
+/// Another host process is using the repository
+#[derive(Debug, thiserror::Error)]
+#[error("Repository is locked by another WebTags host{}", .pid.map(|pid| format!(" (PID {pid})")).unwrap_or_default())]
+pub struct LockedError {
+    /// The holder's process ID, if it could be read
+    pub pid: Option<u32>,
+}
+
+/// Exclusive cross-process lock on a repository, held until dropped.
+///
+/// Two browser profiles can each launch a host for the same repository;
+/// taking this around every write keeps them from interleaving commits.
+#[derive(Debug)]
+pub struct RepoLock {
+    _file: File,
+}
+
+impl RepoLock {
+    /// Take the lock without waiting. Fails with [`LockedError`] if another
+    /// process holds it.
+    pub fn acquire<P: AsRef<Path>>(repo_path: P) -> Result<Self> {
+        let path = repo_path.as_ref().join(".git").join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .context("Failed to open repository lock")?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let pid = file
+                    .read_to_string(&mut holder)
+                    .ok()
+                    .and_then(|_| holder.trim().parse().ok());
+                return Err(LockedError { pid }.into());
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).context("Failed to lock repository");
+            }
+        }
+
+        // Record who holds it so a blocked host can say
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_second_acquire_reports_holder() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+
+        let held = RepoLock::acquire(dir.path()).unwrap();
+        // Each acquire opens its own handle, so this conflicts just as another
+        // process would
+        let err = RepoLock::acquire(dir.path()).unwrap_err();
+        let locked = err.downcast_ref::<LockedError>().unwrap();
+        assert_eq!(locked.pid, Some(std::process::id()));
+        assert!(err.to_string().contains("PID"));
+
+        drop(held);
+        assert!(RepoLock::acquire(dir.path()).is_ok());
+    }
+}
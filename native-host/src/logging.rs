@@ -0,0 +1,272 @@
+use anyhow::{Context, Result};
+use log::LevelFilter;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Current log file; rotated copies get `.1`, `.2`, ... appended
+pub const LOG_FILE: &str = "webtags-host.log";
+
+/// Rotate once the current file reaches this size
+pub const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+/// Rotated files kept besides the current one
+pub const MAX_ROTATED_FILES: usize = 3;
+
+/// Lines `GetLogs` returns when the client doesn't ask for a number
+pub const DEFAULT_LOG_LINES: usize = 200;
+
+/// Upper bound on lines `GetLogs` will return
+pub const MAX_LOG_LINES: usize = 5000;
+
+/// Correlation ID of the request being handled, stamped on every log line.
+/// Requests are handled one at a time, so one slot is enough.
+static REQUEST_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// Default log directory: `~/.local/share/webtags/logs`
+pub fn log_dir() -> Result<PathBuf> {
+    let home = dirs::home_dir().context("No home directory found")?;
+    Ok(home
+        .join(".local")
+        .join("share")
+        .join("webtags")
+        .join("logs"))
+}
+
+/// Log to stderr and, if `dir` is usable, to a rotating file in it.
+///
+/// Browsers discard the host's stderr, so the file is the only record users
+/// can retrieve (with `GetLogs`). `RUST_LOG` still works; otherwise the level
+/// starts at `info` and can be changed with [`set_level`].
+pub fn init(dir: Option<&Path>) {
+    let file = dir.and_then(|dir| match RotatingFile::open(dir) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("Failed to open log file: {e:#}");
+            None
+        }
+    });
+
+    let mut builder = env_logger::Builder::new();
+    // The logger passes everything; `log::max_level` does the filtering so it
+    // can change at runtime
+    builder.filter_level(LevelFilter::Trace);
+    let from_env = std::env::var("RUST_LOG").is_ok();
+    if from_env {
+        builder.parse_env(env_logger::Env::default());
+    }
+    builder
+        .format(|out, record| {
+            let request = request_id().map_or_else(String::new, |id| format!(" [{id}]"));
+            writeln!(
+                out,
+                "{} {:<5} {}{request} {}",
+                chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        })
+        .target(env_logger::Target::Pipe(Box::new(Tee { file })));
+    builder.init();
+
+    if !from_env {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
+
+/// Change the log level; accepts `off`, `error`, `warn`, `info`, `debug`, `trace`
+pub fn set_level(level: &str) -> Result<LevelFilter> {
+    let level: LevelFilter = level
+        .parse()
+        .with_context(|| format!("Unknown log level '{level}'"))?;
+    log::set_max_level(level);
+    Ok(level)
+}
+
+/// Start a new request: generate and record its correlation ID
+pub fn begin_request() -> String {
+    let id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+    *REQUEST_ID.lock().unwrap_or_else(PoisonError::into_inner) = Some(id.clone());
+    id
+}
+
+/// The request is done; later log lines aren't part of it
+pub fn end_request() {
+    *REQUEST_ID.lock().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+pub fn request_id() -> Option<String> {
+    REQUEST_ID
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// The last `lines` lines logged, oldest first, reading back into rotated files
+pub fn tail(dir: &Path, lines: usize) -> Result<Vec<String>> {
+    let mut collected: Vec<String> = Vec::new();
+    for path in log_files(dir) {
+        if collected.len() >= lines {
+            break;
+        }
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        // Newest file first, so older lines go in front
+        let wanted = lines - collected.len();
+        let mut older: Vec<String> = content
+            .lines()
+            .rev()
+            .take(wanted)
+            .map(str::to_string)
+            .collect();
+        older.reverse();
+        older.append(&mut collected);
+        collected = older;
+    }
+    Ok(collected)
+}
+
+/// Current file, then rotated files from newest to oldest
+fn log_files(dir: &Path) -> Vec<PathBuf> {
+    std::iter::once(dir.join(LOG_FILE))
+        .chain((1..=MAX_ROTATED_FILES).map(|n| rotated_path(dir, n)))
+        .collect()
+}
+
+fn rotated_path(dir: &Path, n: usize) -> PathBuf {
+    dir.join(format!("{LOG_FILE}.{n}"))
+}
+
+/// Log file that starts over, keeping a few old copies, once it gets too big
+struct RotatingFile {
+    dir: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).context("Failed to create log directory")?;
+        let path = dir.join(LOG_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let size = file.metadata().map_or(0, |metadata| metadata.len());
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            file,
+            size,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..MAX_ROTATED_FILES).rev() {
+            let from = rotated_path(&self.dir, n);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.dir, n + 1))?;
+            }
+        }
+        fs::rename(self.dir.join(LOG_FILE), rotated_path(&self.dir, 1))?;
+        *self = Self::open(&self.dir).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > MAX_LOG_SIZE {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes to stderr and the log file; a failing file never stops stderr logging
+struct Tee {
+    file: Option<RotatingFile>,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(file) = &mut self.file {
+            let _ = file.write_all(buf);
+        }
+        io::stderr().write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            let _ = file.flush();
+        }
+        io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rotation_keeps_limited_history() {
+        let dir = TempDir::new().unwrap();
+        let mut file = RotatingFile::open(dir.path()).unwrap();
+
+        let line = vec![b'x'; usize::try_from(MAX_LOG_SIZE / 2).unwrap()];
+        for _ in 0..12 {
+            file.write_all(&line).unwrap();
+        }
+
+        assert!(dir.path().join(LOG_FILE).exists());
+        assert!(rotated_path(dir.path(), MAX_ROTATED_FILES).exists());
+        assert!(!rotated_path(dir.path(), MAX_ROTATED_FILES + 1).exists());
+        for path in log_files(dir.path()) {
+            assert!(fs::metadata(path).unwrap().len() <= MAX_LOG_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_tail_reads_across_rotated_files() {
+        let dir = TempDir::new().unwrap();
+        fs::write(rotated_path(dir.path(), 2), "one\ntwo\n").unwrap();
+        fs::write(rotated_path(dir.path(), 1), "three\nfour\n").unwrap();
+        fs::write(dir.path().join(LOG_FILE), "five\n").unwrap();
+
+        assert_eq!(tail(dir.path(), 2).unwrap(), vec!["four", "five"]);
+        assert_eq!(
+            tail(dir.path(), 10).unwrap(),
+            vec!["one", "two", "three", "four", "five"]
+        );
+        assert!(tail(&dir.path().join("missing"), 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_request_ids() {
+        let id = begin_request();
+        assert_eq!(id.len(), 8);
+        assert_eq!(request_id(), Some(id));
+        end_request();
+        assert_eq!(request_id(), None);
+    }
+
+    #[test]
+    fn test_set_level_rejects_unknown() {
+        assert!(set_level("verbose").is_err());
+        assert_eq!(set_level("debug").unwrap(), LevelFilter::Debug);
+        log::set_max_level(LevelFilter::Info);
+    }
+}
@@ -1,16 +1,61 @@
 use anyhow::{Context, Result};
-use log::{error, info};
+use log::{error, info, warn};
 use messaging::{Message, Response};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::io::{stdin, stdout};
 use std::path::{Path, PathBuf};
-#[cfg(target_os = "macos")]
-use webtags_host::encryption;
-use webtags_host::{git, github, messaging, storage};
+use std::time::{Duration, Instant};
+use webtags_host::error::HostError;
+use webtags_host::sync::{RemoteFile as _, SyncBackend as _};
+use webtags_host::{
+    activity, backup, batch, bulk, commit_template, compression, device, diff, doctor, encryption,
+    enrich, expiry, gist, git, git_url, github, hooks, import, index, install, journal, lfs,
+    linkcheck, lock, logging, markdown, mcp, merge, messaging, metrics, migrations, origin,
+    pending, proxy, query, recovery, report, retry, rules, similar, site, snapshots, ssh, stats,
+    storage, suggest, sync, tag_ops, urlnorm, validation, wal, wayback, webhooks,
+};
 
 /// Configuration for the native host
 struct HostConfig {
     repo_path: Option<PathBuf>,
     encryption_enabled: bool,
+    encryption_mode: encryption::EncryptionMode,
+    cipher: encryption::Cipher,
+    payloads: messaging::PayloadAssembler,
+    /// Capabilities negotiated via `Hello` (empty for pre-handshake clients)
+    capabilities: Vec<messaging::Capability>,
+    /// Compression for large responses, if agreed via `Hello`
+    compression: Option<compression::Compression>,
+    /// Holds back commits for rapid writes (disabled until configured)
+    batcher: batch::CommitBatcher,
+    /// Commit message template set with `SetConfig` (default if `None`)
+    commit_template: Option<String>,
+    /// Length limits for writes, loaded from the repository
+    validation: validation::ValidationConfig,
+    /// Set by `Shutdown`; the host exits once the reply is sent
+    shutting_down: bool,
+    /// When the host last looked for expired bookmarks on its own
+    last_expiry_check: Option<Instant>,
+    /// Scheduled link check whose requests are still in flight
+    link_check: Option<RunningLinkCheck>,
+    /// Counts from the last `TagCounts` or `TagTree`, reused until
+    /// bookmarks.json changes and refreshed by this host's own writes
+    tag_counts: Option<CachedTagCounts>,
+}
+
+/// Bookmark counts per tag and the SHA-256 of the bookmarks file they were
+/// counted from
+struct CachedTagCounts {
+    digest: Vec<u8>,
+    counts: HashMap<String, storage::TagCounts>,
+}
+
+/// Link checks run in the background so the host keeps answering messages;
+/// the results are applied from the message loop once they're all in
+struct RunningLinkCheck {
+    threshold: u32,
+    task: tokio::task::JoinHandle<Vec<(String, String, linkcheck::Check)>>,
 }
 
 impl HostConfig {
@@ -18,7 +63,42 @@ impl HostConfig {
         Self {
             repo_path: None,
             encryption_enabled: false,
+            encryption_mode: encryption::EncryptionMode::default(),
+            cipher: encryption::Cipher::default(),
+            payloads: messaging::PayloadAssembler::new(),
+            capabilities: Vec::new(),
+            compression: None,
+            batcher: batch::CommitBatcher::default(),
+            commit_template: None,
+            validation: validation::ValidationConfig::default(),
+            shutting_down: false,
+            last_expiry_check: None,
+            link_check: None,
+            tag_counts: None,
+        }
+    }
+
+    /// Encryption mode to write bookmarks with, or `None` for plain text
+    fn active_encryption(&self) -> Option<encryption::EncryptionMode> {
+        self.encryption_enabled.then_some(self.encryption_mode)
+    }
+
+    /// Match the encryption settings to what bookmarks.json holds, so the next
+    /// write keeps the repo's state and mode. A no-op before the repo is set up.
+    fn adopt_repo_encryption(&mut self) -> Result<()> {
+        let Some(repo_path) = &self.repo_path else {
+            return Ok(());
+        };
+        let detected = storage::detect_encryption(repo_path.join("bookmarks.json"))?;
+        self.encryption_enabled = detected.is_some();
+        if let Some(mode) = detected {
+            self.encryption_mode = mode;
         }
+        Ok(())
+    }
+
+    fn has_capability(&self, capability: messaging::Capability) -> bool {
+        self.capabilities.contains(&capability)
     }
 
     fn get_repo_path(&self) -> Result<PathBuf> {
@@ -86,35 +166,328 @@ fn validate_repo_path(path: &Path) -> Result<PathBuf> {
     Ok(canonical_path)
 }
 
+const USAGE: &str = "\
+Usage:
+  webtags-host                  Run as a native messaging host (started by the browser)
+  webtags-host install [--chrome-id ID]... [--firefox-id ID]... [--binary PATH]
+                                Register the host with every installed browser
+  webtags-host uninstall        Remove the host registrations
+  webtags-host mcp [--repo PATH]
+                                Serve the bookmarks to LLM agents over MCP (stdio)";
+
+/// `install` / `uninstall` subcommands. Browsers launch the host with their
+/// own arguments (the calling extension's origin), so anything else runs the host.
+fn run_cli(args: &[String]) -> Option<i32> {
+    let result = match args.first().map(String::as_str) {
+        Some("install") => run_install(&args[1..]),
+        Some("uninstall") => run_uninstall(),
+        Some("mcp") => run_mcp(&args[1..]),
+        Some("--help" | "-h") => {
+            println!("{USAGE}");
+            Ok(true)
+        }
+        _ => return None,
+    };
+    Some(match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("Error: {e:#}\n\n{USAGE}");
+            2
+        }
+    })
+}
+
+/// Returns whether any browser was set up
+fn run_install(args: &[String]) -> Result<bool> {
+    let mut ids = install::ExtensionIds::default();
+    let mut binary = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{arg} needs a value"))
+                .cloned()
+        };
+        match arg.as_str() {
+            "--chrome-id" => {
+                let id = value()?;
+                install::validate_chromium_id(&id)?;
+                ids.chromium.push(id);
+            }
+            "--firefox-id" => ids.firefox.push(value()?),
+            "--binary" => binary = Some(PathBuf::from(value()?)),
+            other => anyhow::bail!("Unknown option {other}"),
+        }
+    }
+    if ids.firefox.is_empty() {
+        ids.firefox
+            .push(install::DEFAULT_FIREFOX_EXTENSION_ID.to_string());
+    }
+
+    let binary = match binary {
+        Some(path) => path,
+        None => std::env::current_exe().context("Failed to find the host binary")?,
+    };
+    let binary = binary
+        .canonicalize()
+        .with_context(|| format!("Host binary {} not found", binary.display()))?;
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+
+    println!("Installing {} for {}", install::HOST_NAME, binary.display());
+    let installed = report_outcomes(install::install(&home, &binary, &ids));
+    // The host only answers the extensions it was installed for
+    origin::save_allowlist(&origin::allowlist_path()?, &ids)?;
+    Ok(installed)
+}
+
+fn run_uninstall() -> Result<bool> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    println!("Removing {}", install::HOST_NAME);
+    let allowlist = origin::allowlist_path()?;
+    if allowlist.exists() {
+        std::fs::remove_file(&allowlist)
+            .with_context(|| format!("Failed to remove {}", allowlist.display()))?;
+    }
+    Ok(report_outcomes(install::uninstall(&home)))
+}
+
+/// Serve MCP on stdin/stdout until the client disconnects
+fn run_mcp(args: &[String]) -> Result<bool> {
+    let mut repo_path = PathBuf::from("default-repo");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--repo" => {
+                repo_path = args.next().context("--repo needs a value")?.into();
+            }
+            other => anyhow::bail!("Unknown option {other}"),
+        }
+    }
+
+    // stdout carries the protocol, so logs only go to the log file
+    logging::init(logging::log_dir().ok().as_deref());
+    let repo_path = validate_repo_path(&repo_path).context("Invalid repository path")?;
+    if !repo_path.join(".git").exists() {
+        anyhow::bail!(
+            "No repository at {}; set one up from the extension first",
+            repo_path.display()
+        );
+    }
+
+    let mut config = HostConfig::new();
+    let encryption = storage::detect_encryption(repo_path.join("bookmarks.json"))
+        .context("Failed to read bookmarks")?;
+    if let Some(mode) = encryption {
+        config.encryption_enabled = true;
+        config.encryption_mode = mode;
+    }
+    recover_interrupted_write(&repo_path);
+    let repo = git::GitRepo::init(&repo_path)?;
+    config.validation = validation::ValidationConfig::load(&repo);
+    urlnorm::set_rules(urlnorm::Rules::load(&repo));
+    config.repo_path = Some(repo_path);
+    info!("Serving MCP for {}", config.get_repo_path()?.display());
+
+    mcp::serve(McpStore(config), stdin().lock(), stdout().lock())?;
+    Ok(true)
+}
+
+/// The repo the MCP server reads and writes, through the same load and
+/// save paths as extension messages (so rules, note sanitizing, and delete
+/// journaling apply to agent writes too)
+struct McpStore(HostConfig);
+
+impl mcp::Store for McpStore {
+    fn load(&mut self) -> Result<storage::BookmarksData> {
+        load_bookmarks(&self.0).map_err(response_error)
+    }
+
+    fn modify(
+        &mut self,
+        change: &mut dyn FnMut(&mut storage::BookmarksData) -> Result<()>,
+    ) -> Result<()> {
+        let repo_path = self.0.get_repo_path()?;
+        let _lock = lock_repo(&repo_path).map_err(response_error)?;
+        let mut data = load_bookmarks(&self.0).map_err(response_error)?;
+        let before = data.clone();
+        change(&mut data)?;
+        match commit_written(&mut self.0, &repo_path, Some(&before), data) {
+            Response::Success { .. } => Ok(()),
+            response => Err(response_error(response)),
+        }
+    }
+}
+
+/// A failure response as an error, for callers outside the message loop
+fn response_error(response: Response) -> anyhow::Error {
+    match response {
+        Response::Error { message, .. } => anyhow::anyhow!(message),
+        Response::NeedsSecret { prompt, .. } => anyhow::anyhow!(prompt),
+        response => anyhow::anyhow!("Unexpected response: {response:?}"),
+    }
+}
+
+/// Print one line per browser; returns whether any succeeded
+fn report_outcomes(results: Vec<(install::Browser, Result<install::Outcome>)>) -> bool {
+    let mut any_done = false;
+    for (browser, outcome) in results {
+        match outcome {
+            Ok(install::Outcome::Done(path)) => {
+                any_done = true;
+                println!("  ✓ {}: {}", browser.name(), path.display());
+            }
+            Ok(install::Outcome::Skipped(reason)) => println!("  - {}: {reason}", browser.name()),
+            Err(e) => println!("  ✗ {}: {e:#}", browser.name()),
+        }
+    }
+    any_done
+}
+
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = run_cli(&args) {
+        std::process::exit(code);
+    }
+
     // Initialize logger
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    logging::init(logging::log_dir().ok().as_deref());
 
     info!("WebTags native messaging host started");
 
+    if let Ok(name) = std::env::var(messaging::BYTE_ORDER_ENV) {
+        match messaging::ByteOrder::from_name(&name) {
+            Some(order) => messaging::set_byte_order(order),
+            None => warn!(
+                "Ignoring {}={name}; expected native, little, or big",
+                messaging::BYTE_ORDER_ENV
+            ),
+        }
+    }
+
+    // Requests from anything but an allowed extension all get this answer
+    let caller = origin::Caller::from_args(&args);
+    let refusal = match origin::allowlist_path().and_then(|path| origin::load_allowlist(&path)) {
+        Ok(allowlist) if caller.is_allowed(allowlist.as_ref()) => None,
+        Ok(_) => Some(format!(
+            "{caller} isn't allowed to use this host; run `webtags-host install` with its ID"
+        )),
+        Err(e) => Some(format!("Can't check {caller} against the allowlist: {e:#}")),
+    };
+    match &refusal {
+        Some(reason) => warn!("{reason}"),
+        None => info!("Serving {caller}"),
+    }
+
     let mut config = HostConfig::new();
 
+    // stdin reads block, so they run on their own thread and the loop below
+    // can also wake up to commit batched writes when their window closes
+    let (sender, mut incoming) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || loop {
+        let message = messaging::read_message(stdin());
+        // A frame that doesn't parse leaves the stream in sync; anything else
+        // means stdin is unusable
+        let failed = message
+            .as_ref()
+            .is_err_and(|e| !e.is::<messaging::ParseError>());
+        // The main loop may be busy waiting on this very flow
+        if let Ok(Message::AuthCancel { device_code }) = &message {
+            github::cancel_device_flow(device_code);
+        }
+        if sender.send(message).is_err() || failed {
+            break;
+        }
+    });
+
     // Main message loop
     loop {
-        match messaging::read_message(stdin()) {
+        let next = tokio::select! {
+            next = incoming.recv() => next,
+            () = batch_deadline(config.batcher.deadline()) => {
+                let head = repo_head(&config);
+                if let Some(Response::Error { message, .. }) = flush_batch(&mut config) {
+                    error!("Failed to commit batched writes: {message}");
+                }
+                notify_webhook(&config, "write", head.as_deref());
+                upload_local_commits(&config).await;
+                continue;
+            }
+            (threshold, results) = link_check_results(&mut config.link_check) => {
+                config.link_check = None;
+                finish_scheduled_link_check(&mut config, threshold, results).await;
+                continue;
+            }
+        };
+        let Some(next) = next else {
+            break;
+        };
+
+        match next {
             Ok(message) => {
-                info!("Received message: {message:?}");
+                logging::begin_request();
+                let type_name = message.type_name();
+                info!("Received message: {type_name}");
+
+                if let Some(reason) = &refusal {
+                    let response = Response::error(HostError::UntrustedCaller, reason.clone());
+                    logging::end_request();
+                    if let Err(e) = send_response(&config, &response) {
+                        error!("Failed to write response: {e}");
+                        break;
+                    }
+                    continue;
+                }
 
-                let response = handle_message(message, &mut config).await;
+                let started = Instant::now();
+                let head = repo_head(&config);
+                let retry = message.clone();
+                let mut response = handle_message(message, &mut config).await;
+                if let Some(warning) = restore_damaged_bookmarks(&config, &response) {
+                    // The request failed on the damaged file and can be answered now
+                    response = with_warning(handle_message(retry, &mut config).await, &warning);
+                }
+                let error_code = match &response {
+                    Response::Error { code, .. } => Some(code.code()),
+                    _ => None,
+                };
+                metrics::record_message(type_name, started.elapsed(), error_code);
+                logging::end_request();
 
-                if let Err(e) = messaging::write_response(stdout(), &response) {
+                if let Err(e) = send_response(&config, &response) {
                     error!("Failed to write response: {e}");
                     break;
                 }
+                if config.shutting_down {
+                    break;
+                }
+                let event = if type_name == "sync" { "sync" } else { "write" };
+                notify_webhook(&config, event, head.as_deref());
+                upload_local_commits(&config).await;
+                run_scheduled_backup(&config);
+                run_scheduled_link_check(&mut config);
+                run_scheduled_expiry(&mut config);
+            }
+            Err(e) if e.is::<messaging::ParseError>() => {
+                warn!("{e}");
+                let response = Response::error(HostError::Parse, e.to_string());
+                if let Err(e) = messaging::write_response(stdout(), &response) {
+                    error!("Failed to write error response: {e}");
+                    break;
+                }
+            }
+            Err(e) if e.is::<messaging::Disconnected>() => {
+                info!("{e}");
+                break;
             }
             Err(e) => {
                 error!("Failed to read message: {e}");
 
-                let error_response = Response::Error {
-                    message: format!("Failed to read message: {e}"),
-                    code: Some("ERR_READ_MESSAGE".to_string()),
-                };
+                let error_response = Response::error(
+                    HostError::ReadMessage,
+                    format!("Failed to read message: {e}"),
+                );
 
                 if let Err(e) = messaging::write_response(stdout(), &error_response) {
                     error!("Failed to write error response: {e}");
@@ -124,23 +497,598 @@ async fn main() {
         }
     }
 
+    // The extension disconnected; don't leave batched writes uncommitted
+    if let Some(Response::Error { message, .. }) = flush_batch(&mut config) {
+        error!("Failed to commit batched writes: {message}");
+    }
+
     info!("WebTags native messaging host stopped");
 }
 
+/// Write a response compressed and chunked as the client negotiated
+fn send_response(config: &HostConfig, response: &Response) -> Result<()> {
+    match config.compression {
+        Some(compression) => write_framed(
+            config,
+            &compression::encode_response(response, compression)?,
+        ),
+        None => write_framed(config, response),
+    }
+}
+
+fn write_framed<T: serde::Serialize>(config: &HostConfig, body: &T) -> Result<()> {
+    // Only clients that negotiated chunking know how to reassemble responses
+    if config.has_capability(messaging::Capability::ChunkedPayloads) {
+        messaging::write_response_chunked(stdout(), body)
+    } else {
+        messaging::write_response(stdout(), body)
+    }
+}
+
+/// File backends (WebDAV, S3, Gist) only commit locally on write; send those
+/// commits to the server straight away, after the reply so the write isn't
+/// held up. Anything that fails goes out with the next `Sync` instead.
+async fn upload_local_commits(config: &HostConfig) {
+    let Some(repo_path) = config.repo_path.as_deref() else {
+        return;
+    };
+    let Ok(repo) = git::GitRepo::init(repo_path) else {
+        return;
+    };
+    if !sync::has_unsynced_commits(&repo) {
+        return;
+    }
+    let Ok(_lock) = lock_repo(repo_path) else {
+        return;
+    };
+
+    let result = match sync::remote_file(&repo) {
+        Ok(Some(remote)) => {
+            sync::FileBackend::new(&repo, remote, config.active_encryption(), config.cipher)
+                .push()
+                .await
+        }
+        Ok(None) => return,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("Upload left for the next sync: {e:#}");
+    }
+}
+
+/// Take a scheduled backup if one is due. The host only runs while the
+/// browser does, so this is checked after each message rather than on a timer.
+fn run_scheduled_backup(config: &HostConfig) {
+    let Some(repo_path) = config.repo_path.as_deref() else {
+        return;
+    };
+    let Ok(repo) = git::GitRepo::init(repo_path) else {
+        return;
+    };
+    let settings = snapshots::Settings::load(&repo);
+    if settings.schedule == snapshots::Schedule::Off {
+        return;
+    }
+    let result = snapshots::SnapshotDir::for_repo(repo_path)
+        .and_then(|dir| dir.run_if_due(repo_path, settings, chrono::Utc::now()));
+    match result {
+        Ok(Some(snapshot)) => info!("Took scheduled backup {}", snapshot.id),
+        Ok(None) => {}
+        Err(e) => warn!("Scheduled backup failed: {e:#}"),
+    }
+}
+
+/// Current commit of the repository, to tell afterwards whether a request
+/// committed anything
+fn repo_head(config: &HostConfig) -> Option<String> {
+    let repo = git::GitRepo::init(config.repo_path.as_deref()?).ok()?;
+    repo.head_id().map(|id| id.to_string())
+}
+
+/// GitHub client retrying as the repository is configured to, or with the
+/// default policy before one is set up
+fn github_client(config: &HostConfig) -> github::GitHubClient {
+    let policy = config
+        .repo_path
+        .as_deref()
+        .and_then(|path| git::GitRepo::init(path).ok())
+        .map_or_else(retry::RetryPolicy::default, |repo| repo.retry_policy());
+    github::GitHubClient::new().with_retry_policy(policy)
+}
+
+/// Post the changes committed since `previous_head` to the webhook, if one
+/// is set. Delivery runs in the background; a failure is only logged.
+fn notify_webhook(config: &HostConfig, event: &str, previous_head: Option<&str>) {
+    let Some(repo_path) = config.repo_path.as_deref() else {
+        return;
+    };
+    let Ok(repo) = git::GitRepo::init(repo_path) else {
+        return;
+    };
+    let Some(url) = webhooks::configured_url(&repo) else {
+        return;
+    };
+    let Some(head) = repo.head_id().map(|id| id.to_string()) else {
+        return;
+    };
+    if previous_head == Some(head.as_str()) {
+        return;
+    }
+
+    let previous = match previous_head {
+        Some(rev) => sync::bookmarks_at(&repo, rev, config.encryption_enabled),
+        None => Ok(storage::BookmarksData::new()),
+    };
+    let current = sync::bookmarks_at(&repo, &head, config.encryption_enabled);
+    let (previous, current) = match (previous, current) {
+        (Ok(previous), Ok(current)) => (previous, current),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("Skipping webhook: {e:#}");
+            return;
+        }
+    };
+    let Some(payload) =
+        webhooks::Payload::new(event, head, repo.device_name(), &previous, &current)
+    else {
+        return;
+    };
+    let secret = match webhooks::secret(&url) {
+        Ok(secret) => secret,
+        Err(e) => {
+            warn!("Skipping webhook: {e:#}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        match webhooks::deliver(&url, &secret, &payload).await {
+            Ok(()) => info!("Sent {} webhook", payload.event),
+            Err(e) => warn!("Webhook failed: {e:#}"),
+        }
+    });
+}
+
+/// Start the link check in the background if its schedule says it's due.
+/// [`finish_scheduled_link_check`] applies the results when they're in.
+fn run_scheduled_link_check(config: &mut HostConfig) {
+    if config.link_check.is_some() {
+        return;
+    }
+    let Some(repo_path) = config.repo_path.clone() else {
+        return;
+    };
+    let Ok(repo) = git::GitRepo::init(&repo_path) else {
+        return;
+    };
+    let settings = linkcheck::Settings::load(&repo);
+    let due = linkcheck::StatusFile::load(&repo_path)
+        .is_ok_and(|status| status.is_due(settings.schedule, chrono::Utc::now()));
+    if !due {
+        return;
+    }
+    let targets = match load_bookmarks(config) {
+        Ok(data) => linkcheck::targets(&data),
+        Err(Response::Error { message, .. }) => {
+            warn!("Scheduled link check failed: {message}");
+            return;
+        }
+        Err(_) => return,
+    };
+
+    info!("Running scheduled link check");
+    let client = link_check_client();
+    config.link_check = Some(RunningLinkCheck {
+        threshold: settings.failure_threshold,
+        task: tokio::spawn(async move { linkcheck::check_all(&client, targets, |_, _| {}).await }),
+    });
+}
+
+/// Resolves with the results of the running scheduled link check, or never
+/// if there is none
+async fn link_check_results(
+    running: &mut Option<RunningLinkCheck>,
+) -> (
+    u32,
+    Result<Vec<(String, String, linkcheck::Check)>, tokio::task::JoinError>,
+) {
+    match running {
+        Some(running) => (running.threshold, (&mut running.task).await),
+        None => std::future::pending().await,
+    }
+}
+
+/// Record the results of a scheduled link check, telling the client about
+/// links that died or came back
+async fn finish_scheduled_link_check(
+    config: &mut HostConfig,
+    threshold: u32,
+    results: Result<Vec<(String, String, linkcheck::Check)>, tokio::task::JoinError>,
+) {
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            warn!("Scheduled link check failed: {e}");
+            return;
+        }
+    };
+    let summary = match apply_link_results(config, threshold, results).await {
+        Ok(summary) => summary,
+        Err(Response::Error { message, .. }) => {
+            warn!("Scheduled link check failed: {message}");
+            return;
+        }
+        Err(_) => return,
+    };
+    let changed = !summary.newly_dead.is_empty() || !summary.recovered.is_empty();
+    if changed && config.has_capability(messaging::Capability::LinkMonitoring) {
+        let event = Response::Event {
+            event: "links".to_string(),
+            data: serde_json::json!(summary),
+        };
+        if let Err(e) = send_response(config, &event) {
+            warn!("Failed to send link check event: {e}");
+        }
+    }
+}
+
+/// Deal with expired bookmarks, at most once per [`expiry::CHECK_INTERVAL`],
+/// telling the client which ones expired
+fn run_scheduled_expiry(config: &mut HostConfig) {
+    if config.repo_path.is_none()
+        || config
+            .last_expiry_check
+            .is_some_and(|last| last.elapsed() < expiry::CHECK_INTERVAL)
+    {
+        return;
+    }
+    config.last_expiry_check = Some(Instant::now());
+
+    let expired = match expire_bookmarks(config) {
+        Ok(expired) => expired,
+        Err(Response::Error { message, .. }) => {
+            warn!("Expiring bookmarks failed: {message}");
+            return;
+        }
+        Err(_) => return,
+    };
+    if !expired.is_empty() && config.has_capability(messaging::Capability::Expiration) {
+        let event = Response::Event {
+            event: "expired".to_string(),
+            data: serde_json::json!({ "expired": expired }),
+        };
+        if let Err(e) = send_response(config, &event) {
+            warn!("Failed to send expiry event: {e}");
+        }
+    }
+}
+
+/// Tag or delete bookmarks whose `expires_at` has passed, as the repository's
+/// expiry settings say, and commit the change
+fn expire_bookmarks(config: &mut HostConfig) -> Result<Vec<expiry::Expired>, Response> {
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return Err(response);
+    }
+    let repo_path = config
+        .get_repo_path()
+        .map_err(|e| Response::error(HostError::NotInitialized, e.to_string()))?;
+    let _lock = lock_repo(&repo_path)?;
+    let repo = git::GitRepo::init(&repo_path).map_err(|e| {
+        Response::error(
+            HostError::OpenRepo,
+            format!("Failed to open repository: {e}"),
+        )
+    })?;
+    let action = expiry::Settings::load(&repo).action;
+
+    let mut bookmarks_data = load_bookmarks(config)?;
+    let before = bookmarks_data.clone();
+    let expired = expiry::expire(&mut bookmarks_data, action, chrono::Utc::now());
+    if !expired.is_empty() {
+        info!("{} bookmarks expired ({action:?})", expired.len());
+        let response = save_bookmarks(config, &repo_path, &bookmarks_data);
+        if let response @ Response::Error { .. } =
+            journal_saved(&repo_path, "expire", &before, &bookmarks_data, response)
+        {
+            return Err(response);
+        }
+    }
+    Ok(expired)
+}
+
+/// Resolves when the open commit batch is due, or never if there is none
+async fn batch_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
 async fn handle_message(message: Message, config: &mut HostConfig) -> Response {
+    if let Some(capability) = message.required_capability() {
+        if !config.has_capability(capability) {
+            return Response::error_with_details(
+                HostError::Capability,
+                format!("Capability not negotiated: {capability:?}"),
+                serde_json::json!({ "capability": capability }),
+            );
+        }
+    }
+
     match message {
+        Message::Hello {
+            protocol_version,
+            compression,
+        } => handle_hello(config, protocol_version, &compression),
         Message::Init {
             repo_path,
             repo_url,
-        } => handle_init(config, repo_path, repo_url).await,
-        Message::Write { data } => handle_write(config, data).await,
-        Message::Read => handle_read(config).await,
+            depth,
+            device_name,
+            backend,
+        } => handle_init(config, repo_path, repo_url, depth, device_name, backend).await,
+        Message::Write { data, strict } => handle_write(config, data, strict).await,
+        Message::WriteDelta {
+            added,
+            updated,
+            removed,
+        } => handle_write_delta(
+            config,
+            storage::Delta {
+                added,
+                updated,
+                removed,
+            },
+        ),
+        Message::Read { options } => handle_read(config, &options).await,
         Message::Sync => handle_sync(config).await,
-        Message::Auth { method, token } => handle_auth(method, token).await,
+        Message::Auth { method, token } => handle_auth(config, method, token).await,
+        Message::Reauth { device_code } => handle_reauth(config, device_code).await,
+        Message::AuthCancel { device_code } => handle_auth_cancel(&device_code),
+        Message::ListRepos { query } => handle_list_repos(config, query.as_deref()).await,
+        Message::SignOut { remove_remote } => handle_sign_out(config, remove_remote).await,
+        Message::ResolveConflicts { decisions } => handle_resolve_conflicts(config, &decisions),
         Message::Status => handle_status(config).await,
-        Message::EnableEncryption => handle_enable_encryption(config).await,
+        Message::EnableEncryption { mode, cipher } => {
+            handle_enable_encryption(config, mode, cipher).await
+        }
         Message::DisableEncryption => handle_disable_encryption(config).await,
         Message::EncryptionStatus => handle_encryption_status(config).await,
+        Message::BeginPayload {
+            payload_id,
+            total_size,
+            total_chunks,
+        } => handle_begin_payload(config, &payload_id, total_size, total_chunks),
+        Message::PayloadChunk {
+            payload_id,
+            index,
+            data,
+        } => handle_payload_chunk(config, &payload_id, index, data),
+        Message::EndPayload { payload_id } => match config.payloads.finish(&payload_id) {
+            Ok(inner) => {
+                info!("Reassembled chunked payload {payload_id}");
+                Box::pin(handle_message(inner, config)).await
+            }
+            Err(e) => Response::error(
+                HostError::Payload,
+                format!("Failed to reassemble payload: {e}"),
+            ),
+        },
+        Message::RebuildIndexes => handle_rebuild_indexes(config).await,
+        Message::RecordVisit { bookmark_id } => handle_record_visit(config, &bookmark_id).await,
+        Message::ClearActivity => handle_clear_activity(config).await,
+        Message::ExportEncryptionKey { passphrase } => handle_export_key(&passphrase).await,
+        Message::ImportEncryptionKey { passphrase, blob } => {
+            handle_import_key(config, &passphrase, &blob).await
+        }
+        Message::VerifyRecoveryCode { code, restore } => {
+            handle_verify_recovery_code(config, &code, restore).await
+        }
+        Message::LockNow => handle_lock_now(),
+        Message::SetKeyCacheTtl { ttl_secs } => handle_set_key_cache_ttl(ttl_secs),
+        Message::Diff { from, to } => handle_diff(config, &from, to.as_deref()).await,
+        Message::SetConfig {
+            proxy,
+            commit_window_secs,
+            commit_template,
+            ssh_key_path,
+            backup_schedule,
+            backup_keep,
+            device_branches,
+            hooks,
+            webhook,
+            link_check,
+            expiry,
+            retry,
+            max_url_length,
+            max_title_length,
+            max_tag_length,
+            max_tag_depth,
+            allowed_schemes,
+            url_normalization,
+        } => handle_set_config(
+            config,
+            proxy.as_deref(),
+            commit_window_secs,
+            commit_template,
+            ssh_key_path.as_deref(),
+            RepoSettings {
+                backup_schedule,
+                backup_keep,
+                device_branches,
+                hooks,
+                webhook,
+                link_check,
+                expiry,
+                retry,
+            },
+            (
+                validation::Update {
+                    max_url_length,
+                    max_title_length,
+                    max_tag_length,
+                    max_tag_depth,
+                    allowed_schemes,
+                },
+                url_normalization,
+            ),
+        ),
+        Message::Flush => handle_flush(config),
+        Message::Deepen { depth } => handle_deepen(config, depth).await,
+        Message::Maintain {
+            squash_older_than_days,
+            confirm_force_push,
+        } => handle_maintain(config, squash_older_than_days, confirm_force_push).await,
+        Message::ResetToRemote { keep_local_changes } => {
+            handle_reset_to_remote(config, keep_local_changes)
+        }
+        Message::History { limit, device } => handle_history(config, limit, device.as_deref()),
+        Message::AddRemote { name, url } => handle_add_remote(config, &name, &url),
+        Message::ProvideSecret { secret, value } => handle_provide_secret(secret, &value),
+        Message::Doctor => handle_doctor(config),
+        Message::SetLogLevel { level } => handle_set_log_level(&level),
+        Message::GetLogs { lines } => handle_get_logs(lines),
+        Message::Metrics { reset } => handle_metrics(reset),
+        Message::Shutdown => handle_shutdown(config),
+        Message::Repair => handle_repair(config),
+        Message::MarkRead { id } => handle_mark_read(config, &id),
+        Message::UpdateBookmark { id, title, notes } => {
+            handle_update_bookmark(config, &id, title, notes)
+        }
+        Message::UpdateTags { ops } => handle_update_tags(config, &ops),
+        Message::Report {
+            kind,
+            months,
+            check_domains,
+            days,
+        } => handle_report(config, kind, months, check_domains, days).await,
+        Message::CheckLinks => handle_check_links(config).await,
+        Message::Enrich {
+            refresh,
+            suggest_tags,
+        } => handle_enrich(config, refresh, suggest_tags).await,
+        Message::ReviewSuggestedTags {
+            id,
+            accept,
+            dismiss,
+        } => handle_review_suggested_tags(config, &id, &accept, &dismiss),
+        Message::Stats => handle_stats(config),
+        Message::BulkOp { filter, op } => handle_bulk_op(config, &filter, &op),
+        Message::Undo { count } => handle_undo(config, count),
+        Message::Backup { path, passphrase } => {
+            handle_backup(config, Path::new(&path), &passphrase)
+        }
+        Message::Restore {
+            path,
+            passphrase,
+            repo_path,
+        } => handle_restore(config, Path::new(&path), &passphrase, repo_path),
+        Message::ListBackups => handle_list_backups(config),
+        Message::RestoreBackup { id } => handle_restore_backup(config, &id),
+        Message::ExportBundle { path, since } => {
+            handle_export_bundle(config, Path::new(&path), since.as_deref())
+        }
+        Message::ImportBundle { path } => handle_import_bundle(config, Path::new(&path)),
+        Message::FindDuplicates => handle_find_duplicates(config),
+        Message::ListUnread => handle_list_unread(config),
+        Message::TagTree => handle_tag_tree(config),
+        Message::TagCounts => handle_tag_counts(config),
+        Message::ListPinned => handle_list_pinned(config),
+        Message::ReorderPinned { ids } => handle_reorder_pinned(config, &ids),
+        Message::CreateSavedSearch { name, query, sort } => {
+            handle_create_saved_search(config, name, query, sort)
+        }
+        Message::ListSavedSearches => handle_list_saved_searches(config),
+        Message::RunSavedSearch { id } => handle_run_saved_search(config, &id),
+        Message::ApplyRules => handle_apply_rules(config),
+        Message::SuggestTags { url, title, limit } => {
+            handle_suggest_tags(config, &url, &title, limit)
+        }
+        Message::Import {
+            format,
+            content,
+            dry_run,
+            options,
+        } => handle_import(config, format, &content, dry_run, &options),
+        Message::ExportSite => handle_export_site(config),
+        Message::ExportMarkdown {
+            path,
+            include_private,
+        } => handle_export_markdown(config, Path::new(&path), include_private),
+        Message::PublishPublic { path } => handle_publish_public(config, path.as_deref()),
+        Message::StoreArchive { id, content } => handle_store_archive(config, &id, &content).await,
+        Message::GetArchive { id } => handle_get_archive(config, &id).await,
+        Message::ImportBrowserTree {
+            tree,
+            dry_run,
+            options,
+        } => {
+            info!("Importing browser bookmarks (dry run: {dry_run})");
+            import_items(config, import::browser_tree_items(&tree), dry_run, &options)
+        }
+    }
+}
+
+fn handle_hello(
+    config: &mut HostConfig,
+    protocol_version: u32,
+    compression: &[String],
+) -> Response {
+    info!("Negotiating protocol (client version {protocol_version})");
+
+    let version = match messaging::negotiate_version(protocol_version) {
+        Ok(version) => version,
+        Err(e) => {
+            return Response::error_with_details(
+                HostError::ProtocolVersion,
+                e.to_string(),
+                serde_json::json!({ "supported_versions": messaging::SUPPORTED_PROTOCOL_VERSIONS }),
+            )
+        }
+    };
+
+    config.capabilities = messaging::capabilities_for(version);
+    config.compression = compression::Compression::negotiate(compression)
+        .filter(|_| config.has_capability(messaging::Capability::Compression));
+
+    Response::Hello {
+        protocol_version: version,
+        supported_versions: messaging::SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+        capabilities: config.capabilities.clone(),
+        compression: config.compression,
+    }
+}
+
+fn handle_begin_payload(
+    config: &mut HostConfig,
+    payload_id: &str,
+    total_size: usize,
+    total_chunks: usize,
+) -> Response {
+    info!("Starting chunked payload {payload_id} ({total_size} bytes, {total_chunks} chunks)");
+
+    match config.payloads.begin(payload_id, total_size, total_chunks) {
+        Ok(()) => Response::Success {
+            message: "Payload started".to_string(),
+            data: None,
+        },
+        Err(e) => Response::error(HostError::Payload, format!("Failed to start payload: {e}")),
+    }
+}
+
+fn handle_payload_chunk(
+    config: &mut HostConfig,
+    payload_id: &str,
+    index: usize,
+    data: String,
+) -> Response {
+    match config.payloads.push_chunk(payload_id, index, data) {
+        Ok(()) => Response::Success {
+            message: format!("Chunk {index} received"),
+            data: None,
+        },
+        Err(e) => Response::error(
+            HostError::Payload,
+            format!("Failed to store payload chunk: {e}"),
+        ),
     }
 }
 
@@ -148,9 +1096,25 @@ async fn handle_init(
     config: &mut HostConfig,
     repo_path: Option<String>,
     repo_url: Option<String>,
+    depth: Option<u32>,
+    device_name: Option<String>,
+    backend: Option<sync::BackendConfig>,
 ) -> Response {
     info!("Initializing repository");
 
+    if repo_url.is_some() && !matches!(backend, None | Some(sync::BackendConfig::Git)) {
+        return Response::error(
+            HostError::Validate,
+            "repo_url can only be used with the git backend",
+        );
+    }
+
+    if let Some(name) = &device_name {
+        if let Err(e) = device::validate_device_name(name) {
+            return Response::error(HostError::Validate, format!("Invalid device name: {e}"));
+        }
+    }
+
     // Determine repo path (use provided or default)
     let requested_path = repo_path.map_or_else(|| PathBuf::from("default-repo"), PathBuf::from);
 
@@ -158,23 +1122,33 @@ async fn handle_init(
     let path = match validate_repo_path(&requested_path) {
         Ok(p) => p,
         Err(e) => {
-            return Response::Error {
-                message: format!("Invalid repository path: {e}"),
-                code: Some("ERR_INVALID_PATH".to_string()),
-            }
+            return Response::error(
+                HostError::InvalidPath,
+                format!("Invalid repository path: {e}"),
+            )
         }
     };
 
     // Clone or init repository
+    if let Some(prompt) = repo_url
+        .as_ref()
+        .and_then(|url| ssh_passphrase_prompt(std::slice::from_ref(url)))
+    {
+        return prompt;
+    }
+
     let repo = if let Some(url) = repo_url {
-        info!("Cloning repository from {url}");
-        match git::GitRepo::clone(&url, &path) {
+        match depth {
+            Some(depth) => info!("Cloning repository from {url} (depth {depth})"),
+            None => info!("Cloning repository from {url}"),
+        }
+        match git::GitRepo::clone(&url, &path, depth) {
             Ok(repo) => repo,
             Err(e) => {
-                return Response::Error {
-                    message: format!("Failed to clone repository: {e}"),
-                    code: Some("ERR_CLONE".to_string()),
-                }
+                return Response::error(
+                    HostError::Clone,
+                    format!("Failed to clone repository: {e}"),
+                )
             }
         }
     } else {
@@ -182,413 +1156,3904 @@ async fn handle_init(
         match git::GitRepo::init(&path) {
             Ok(repo) => repo,
             Err(e) => {
-                return Response::Error {
-                    message: format!("Failed to initialize repository: {e}"),
-                    code: Some("ERR_INIT".to_string()),
-                }
+                return Response::error(
+                    HostError::Init,
+                    format!("Failed to initialize repository: {e}"),
+                )
+            }
+        }
+    };
+
+    // A new gist starts out with whatever is here already
+    let backend = match backend {
+        Some(sync::BackendConfig::Gist { gist_id: None }) => {
+            if repo.has_remote(git::PRIMARY_REMOTE) {
+                return Response::error(
+                    HostError::Validate,
+                    "Repository already syncs with a git remote",
+                );
+            }
+            match create_gist(&repo).await {
+                Ok(gist_id) => Some(sync::BackendConfig::Gist {
+                    gist_id: Some(gist_id),
+                }),
+                Err(response) => return response,
             }
         }
+        backend => backend,
     };
+    if let Some(backend) = &backend {
+        if let Err(e) = sync::configure(&repo, backend) {
+            return Response::error(HostError::Init, format!("Failed to configure sync: {e:#}"));
+        }
+    }
 
     config.repo_path = Some(repo.path().to_path_buf());
+    config.validation = validation::ValidationConfig::load(&repo);
+    urlnorm::set_rules(urlnorm::Rules::load(&repo));
+
+    // Before anything reads or writes the file the last session may have left half done
+    let recovered = recover_interrupted_write(repo.path());
+
+    // Name this clone so its commits can be told apart from other machines'.
+    // An explicit name replaces any earlier one; otherwise keep what's set.
+    let device_name = device_name.or_else(|| {
+        repo.device_name()
+            .is_none()
+            .then(device::hostname)
+            .filter(|name| device::validate_device_name(name).is_ok())
+    });
+    if let Some(name) = device_name {
+        if let Err(e) = repo.set_device_name(&name) {
+            warn!("Failed to save device name: {e:#}");
+        }
+    }
+
+    // Like a clone, start from what the server already has
+    let remote = match sync::remote_file(&repo) {
+        Ok(remote) => remote,
+        Err(e) => return Response::error(HostError::Remote, format!("{e:#}")),
+    };
+    if let Some(remote) = remote {
+        let mut backend =
+            sync::FileBackend::new(&repo, remote, config.active_encryption(), config.cipher);
+        if let Err(e) = backend.pull().await {
+            return sync_error(HostError::GitPull, "Failed to pull", &e).await;
+        }
+    }
+
+    // Derived state is disposable, so a failure here shouldn't block init
+    match index::IndexDir::for_repo(repo.path()).and_then(|dir| dir.open()) {
+        Ok(true) => info!("Index format changed; run rebuildindexes to regenerate"),
+        Ok(false) => {}
+        Err(e) => log::warn!("Failed to open index directory: {e}"),
+    }
 
     Response::Success {
         message: format!("Repository initialized at {}", repo.path().display()),
-        data: None,
+        data: recovered.map(|recovery| serde_json::json!({ "recovered_write": recovery })),
     }
 }
 
-async fn handle_write(config: &mut HostConfig, data: serde_json::Value) -> Response {
-    info!("Writing bookmarks data");
-
-    let repo_path = match config.get_repo_path() {
+/// Finish or undo a write that a previous host was killed partway through
+/// (e.g. by the browser quitting), or put back the committed bookmarks if
+/// the file was left damaged. A failure leaves the repository as it was
+/// found, which is no worse than not trying.
+fn recover_interrupted_write(repo_path: &Path) -> Option<wal::Recovery> {
+    let _lock = lock_repo(repo_path).ok()?;
+    match git::GitRepo::init(repo_path).and_then(|repo| wal::recover(&repo)) {
+        Ok(Some(recovery)) => {
+            warn!("Recovered an interrupted write: {recovery:?}");
+            Some(recovery)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!("Failed to recover an interrupted write: {e:#}");
+            None
+        }
+    }
+}
+
+/// Create a secret gist holding the repository's bookmarks (or an empty
+/// collection), returning its ID
+async fn create_gist(repo: &git::GitRepo) -> Result<String, Response> {
+    let token = github::get_token().map_err(|e| {
+        Response::error(HostError::NoToken, format!("Not signed in to GitHub: {e}"))
+    })?;
+    let bookmarks_file = repo.path().join("bookmarks.json");
+    let content = if bookmarks_file.exists() {
+        std::fs::read_to_string(&bookmarks_file).map_err(|e| {
+            Response::error(
+                HostError::ReadFile,
+                format!("Failed to read bookmarks file: {e}"),
+            )
+        })?
+    } else {
+        serde_json::to_string_pretty(&storage::BookmarksData::new())
+            .map_err(|e| Response::error(HostError::Serialize, e.to_string()))?
+    };
+
+    info!("Creating gist for bookmarks");
+    gist::GistClient::new(token, String::new())
+        .with_retry_policy(repo.retry_policy())
+        .create(&content)
+        .await
+        .map_err(|e| Response::error(HostError::Remote, format!("{e:#}")))
+}
+
+async fn handle_write(
+    config: &mut HostConfig,
+    bookmarks_data: storage::BookmarksData,
+    strict: bool,
+) -> Response {
+    info!("Writing bookmarks data");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    if strict {
+        let unknown = bookmarks_data.unknown_fields();
+        if !unknown.is_empty() {
+            return Response::error_with_details(
+                HostError::Validate,
+                format!("{} fields this version doesn't know about", unknown.len()),
+                serde_json::json!({ "unknown_fields": unknown }),
+            );
+        }
+    }
+
+    let stored = read_stored_bookmarks(config, &repo_path);
+    commit_written(config, &repo_path, stored.as_ref(), bookmarks_data)
+}
+
+/// Apply the changes in `delta` to the stored bookmarks and commit them
+fn handle_write_delta(config: &mut HostConfig, delta: storage::Delta) -> Response {
+    info!(
+        "Writing bookmark changes ({} added, {} updated, {} removed)",
+        delta.added.len(),
+        delta.updated.len(),
+        delta.removed.len()
+    );
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let stored = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let mut bookmarks_data = stored.clone();
+    if let Err(e) = bookmarks_data.apply_delta(delta) {
+        return Response::error(HostError::Validate, format!("Invalid changes: {e}"));
+    }
+    commit_written(config, &repo_path, Some(&stored), bookmarks_data)
+}
+
+/// Validate bookmarks the extension wrote, tag new ones by rule, and
+/// commit them, journaling any deletes against `stored`
+fn commit_written(
+    config: &mut HostConfig,
+    repo_path: &Path,
+    stored: Option<&storage::BookmarksData>,
+    mut bookmarks_data: storage::BookmarksData,
+) -> Response {
+    // Fields Read added for this device only aren't stored
+    bookmarks_data.remove_host_meta();
+    bookmarks_data.sanitize_notes();
+    bookmarks_data.normalize_unicode();
+
+    if let Err(e) = bookmarks_data.validate_with(&config.validation) {
+        return broken_references(&e).unwrap_or_else(|| {
+            Response::error(HostError::Validate, format!("Invalid bookmarks data: {e}"))
+        });
+    }
+
+    let rule_tagged = apply_rules_to_new(stored, &mut bookmarks_data);
+    if let Some(author) = author_for(repo_path) {
+        bookmarks_data.stamp_author(&author, stored);
+    }
+
+    let mut response = save_bookmarks(config, repo_path, &bookmarks_data);
+    // Only deletes are journaled; the extension can redo any other edit itself
+    if let Some(stored) = stored.filter(|stored| removes_resources(stored, &bookmarks_data)) {
+        response = journal_saved(repo_path, "delete", stored, &bookmarks_data, response);
+    }
+    if rule_tagged.is_empty() {
+        return response;
+    }
+    with_data(response, "rule_tagged", serde_json::json!(rule_tagged))
+}
+
+/// Who bookmarks saved from this clone are attributed to, if anyone
+fn author_for(repo_path: &Path) -> Option<String> {
+    git::GitRepo::init(repo_path).ok()?.author_name()
+}
+
+/// The bookmarks file as it is before a write, empty if there isn't one
+/// yet, or `None` if it can't be read
+fn read_stored_bookmarks(config: &HostConfig, repo_path: &Path) -> Option<storage::BookmarksData> {
+    let bookmarks_file = repo_path.join("bookmarks.json");
+    if !bookmarks_file.exists() {
+        return Some(storage::BookmarksData::new());
+    }
+    match storage::read_from_file_with_encryption(&bookmarks_file, config.encryption_enabled) {
+        Ok(stored) => Some(stored),
+        Err(e) => {
+            warn!("Failed to read current bookmarks: {e}");
+            None
+        }
+    }
+}
+
+/// Whether anything in `before` is gone from `after`
+fn removes_resources(before: &storage::BookmarksData, after: &storage::BookmarksData) -> bool {
+    let resources = |data: &storage::BookmarksData| -> HashSet<String> {
+        data.data()
+            .iter()
+            .chain(data.included().into_iter().flatten())
+            .map(|resource| resource.id().to_string())
+            .collect()
+    };
+    !resources(before).is_subset(&resources(after))
+}
+
+/// Journal `operation` for `Undo` if `response` shows it was saved
+fn journal_saved(
+    repo_path: &Path,
+    operation: &str,
+    before: &storage::BookmarksData,
+    after: &storage::BookmarksData,
+    response: Response,
+) -> Response {
+    if matches!(response, Response::Success { .. }) {
+        if let Err(e) = journal::record(repo_path, operation, before, after) {
+            warn!("Failed to journal {operation}: {e:#}");
+        }
+    }
+    response
+}
+
+/// Run rules over bookmarks that aren't in the stored file yet, returning
+/// the IDs of those that gained a tag. Rules never block a write; if the
+/// stored file can't be read, they're skipped until `ApplyRules`.
+fn apply_rules_to_new(
+    stored: Option<&storage::BookmarksData>,
+    bookmarks_data: &mut storage::BookmarksData,
+) -> Vec<String> {
+    if bookmarks_data.get_rules().is_empty() {
+        return Vec::new();
+    }
+    let Some(stored) = stored else {
+        warn!("Not applying rules; the current bookmarks couldn't be read");
+        return Vec::new();
+    };
+    let stored: HashSet<&str> = stored
+        .get_bookmarks()
+        .iter()
+        .map(|bookmark| bookmark.id())
+        .collect();
+
+    match rules::apply(bookmarks_data, |id| !stored.contains(id)) {
+        Ok(tagged) => tagged,
+        Err(e) => {
+            warn!("Not applying rules: {e:#}");
+            Vec::new()
+        }
+    }
+}
+
+/// If `response` failed because bookmarks.json is damaged, put back the
+/// newest version in history that reads, returning the warning to send
+/// with the retried request
+fn restore_damaged_bookmarks(
+    config: &HostConfig,
+    response: &Response,
+) -> Option<messaging::Warning> {
+    if !matches!(
+        response,
+        Response::Error {
+            code: HostError::ReadFile,
+            ..
+        }
+    ) {
+        return None;
+    }
+    let repo_path = config.repo_path.as_deref()?;
+    if !wal::is_damaged(repo_path) {
+        return None;
+    }
+    let _lock = lock_repo(repo_path).ok()?;
+
+    let encryption_enabled = config.encryption_enabled;
+    let readable = |content: &[u8]| {
+        std::str::from_utf8(content).is_ok_and(|content| {
+            storage::parse_with_encryption(content, "bookmarks.json", encryption_enabled).is_ok()
+        })
+    };
+    match git::GitRepo::init(repo_path).and_then(|repo| wal::restore_from_history(&repo, readable))
+    {
+        Ok(restored) => {
+            warn!(
+                "Restored damaged bookmarks from commit {}; kept the damaged file at {}",
+                restored.commit,
+                restored.corrupt_file.display()
+            );
+            Some(messaging::Warning {
+                code: messaging::WarningCode::RestoredFromHistory,
+                message: format!(
+                    "bookmarks.json was damaged; restored the version from {}",
+                    restored.committed_at.format("%Y-%m-%d %H:%M UTC")
+                ),
+                details: serde_json::to_value(&restored).ok(),
+            })
+        }
+        Err(e) => {
+            error!("Failed to restore damaged bookmarks: {e:#}");
+            None
+        }
+    }
+}
+
+/// Add `warning` to the `warnings` in the data of a success response
+fn with_warning(response: Response, warning: &messaging::Warning) -> Response {
+    match response {
+        Response::Success { message, data } => {
+            let mut data = data.unwrap_or_else(|| serde_json::json!({}));
+            match data["warnings"].as_array_mut() {
+                Some(warnings) => warnings.push(serde_json::json!(warning)),
+                None => data["warnings"] = serde_json::json!([warning]),
+            }
+            Response::Success {
+                message,
+                data: Some(data),
+            }
+        }
+        response => response,
+    }
+}
+
+/// Add `key` to the data of a success response
+fn with_data(response: Response, key: &str, value: serde_json::Value) -> Response {
+    match response {
+        Response::Success { message, data } => {
+            let mut data = data.unwrap_or_else(|| serde_json::json!({}));
+            data[key] = value;
+            Response::Success {
+                message,
+                data: Some(data),
+            }
+        }
+        response => response,
+    }
+}
+
+/// Write bookmarks to the repository and commit them, or leave them staged
+/// for the batcher. The caller holds the repository lock.
+fn save_bookmarks(
+    config: &mut HostConfig,
+    repo_path: &Path,
+    bookmarks_data: &storage::BookmarksData,
+) -> Response {
+    let repo = match git::GitRepo::init(repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    // Ask before touching the file so the write can simply be resent
+    if !config.batcher.is_enabled() {
+        if let Some(prompt) = ssh_passphrase_prompt(&remote_urls(&repo)) {
+            return prompt;
+        }
+    }
+
+    if let Err(e) = bookmarks_data.validate_with(&config.validation) {
+        return Response::error(HostError::Validate, format!("Invalid bookmarks: {e}"));
+    }
+
+    // Recorded first so a crash between here and the commit can be recovered
+    if let Err(e) = wal::begin(&repo) {
+        return Response::error(
+            HostError::WriteFile,
+            format!("Failed to record write intent: {e:#}"),
+        );
+    }
+
+    // Write to file (with encryption support)
+    let bookmarks_file = repo_path.join("bookmarks.json");
+    if let Err(e) = storage::write_to_file_with_mode(
+        &bookmarks_file,
+        bookmarks_data,
+        config.active_encryption(),
+        config.cipher,
+    ) {
+        return Response::error(
+            HostError::WriteFile,
+            format!("Failed to write bookmarks file: {e}"),
+        );
+    }
+
+    // Once a client has asked for counts, keep them current from the data
+    // just written rather than reading the file back on the next request
+    if config.tag_counts.is_some() {
+        remember_tag_counts(config, bookmarks_digest(repo_path), bookmarks_data);
+    }
+
+    // Add and commit
+    if let Err(e) = repo.add_file("bookmarks.json") {
+        return Response::error(HostError::GitAdd, format!("Failed to stage file: {e}"));
+    }
+
+    // Sync ignore rules (for local-only sidecars) and the recovery file along with
+    // bookmarks, including its removal once encryption is disabled
+    for tracked in [".gitignore", recovery::RECOVERY_FILE] {
+        let staged = if repo_path.join(tracked).exists() {
+            repo.add_file(tracked)
+        } else {
+            repo.remove_file(tracked)
+        };
+        if let Err(e) = staged {
+            return Response::error(HostError::GitAdd, format!("Failed to stage file: {e}"));
+        }
+    }
+
+    if config.batcher.is_enabled() {
+        // Staged; committed together with later writes once the window closes
+        let writes = config.batcher.record_write(Instant::now()).writes;
+        let commit_in = config
+            .batcher
+            .deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        return Response::Success {
+            message: "Bookmarks saved; commit batched".to_string(),
+            data: Some(serde_json::json!({
+                "batched": true,
+                "pending_writes": writes,
+                "commit_in_ms": commit_in.map(|d| d.as_millis()),
+            })),
+        };
+    }
+
+    commit_and_push(config, &repo, repo_path)
+}
+
+/// Write bookmarks upgraded to the current schema back to disk. The old file
+/// is committed first (if it isn't already) so the migration can be undone
+/// from history. Both commits stay local until the next sync.
+fn save_migration(
+    config: &HostConfig,
+    repo_path: &Path,
+    loaded: &storage::Loaded,
+) -> anyhow::Result<()> {
+    let _lock = lock::RepoLock::acquire(repo_path)?;
+    let repo = git::GitRepo::init(repo_path)?;
+    let to = migrations::CURRENT_SCHEMA_VERSION;
+
+    repo.add_file("bookmarks.json")?;
+    if repo.has_staged_changes()? {
+        repo.commit(&format!(
+            "Back up bookmarks before migrating to schema v{to}"
+        ))?;
+    }
+
+    wal::begin(&repo)?;
+    storage::write_to_file_with_mode(
+        repo_path.join("bookmarks.json"),
+        &loaded.data,
+        config.active_encryption(),
+        config.cipher,
+    )?;
+    repo.add_file("bookmarks.json")?;
+    repo.commit(&format!(
+        "Migrate bookmarks from schema v{} to v{to}",
+        loaded.stored_version
+    ))?;
+    wal::complete(repo_path)?;
+
+    info!(
+        "Migrated bookmarks from schema v{} to v{to}",
+        loaded.stored_version
+    );
+    Ok(())
+}
+
+/// The `ERR_INTEGRITY` response, listing the problems, if `e` is a
+/// [`storage::ReferenceError`]
+fn broken_references(e: &anyhow::Error) -> Option<Response> {
+    let broken = e.downcast_ref::<storage::ReferenceError>()?;
+    Some(Response::error_with_details(
+        HostError::Integrity,
+        format!("{broken}; send Repair to fix them"),
+        serde_json::json!({ "problems": broken.problems }),
+    ))
+}
+
+/// Take the cross-process repository lock, or the `ERR_LOCKED` response if
+/// another host holds it
+fn lock_repo(repo_path: &Path) -> Result<lock::RepoLock, Response> {
+    lock::RepoLock::acquire(repo_path).map_err(|e| match e.downcast_ref::<lock::LockedError>() {
+        Some(locked) => Response::error_with_details(
+            HostError::Locked,
+            e.to_string(),
+            serde_json::json!({ "pid": locked.pid }),
+        ),
+        None => Response::error(HostError::Locked, format!("{e:#}")),
+    })
+}
+
+/// Commit any writes held back by the batcher. `None` if nothing was pending.
+fn flush_batch(config: &mut HostConfig) -> Option<Response> {
+    config.batcher.pending()?;
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Some(Response::error(HostError::NotInitialized, e.to_string())),
+    };
+    // Leave the batch open if another host has the repo, to retry later
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return Some(response),
+    };
+
+    let batch = config.batcher.take()?;
+    info!("Committing {} batched writes", batch.writes);
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Some(Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            ))
+        }
+    };
+
+    Some(commit_and_push(config, &repo, &repo_path))
+}
+
+/// Commit batched writes, push queued operations, and drop the cached key,
+/// then tell the main loop to exit
+fn handle_shutdown(config: &mut HostConfig) -> Response {
+    info!("Shutting down");
+
+    let committed_writes = config.batcher.pending().map_or(0, |batch| batch.writes);
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        // Stay up so the client can retry rather than lose the batch
+        return response;
+    }
+
+    // Anything still queued is already on disk and goes out on the next start
+    let pending_ops = match config.get_repo_path() {
+        Ok(repo_path) => match git::GitRepo::init(&repo_path) {
+            Ok(repo) if repo.has_remote(git::PRIMARY_REMOTE) => {
+                flush_pending_ops(&repo, &repo_path)
+            }
+            Ok(_) => 0,
+            Err(e) => {
+                warn!("Failed to open repository: {e}");
+                0
+            }
+        },
+        Err(_) => 0,
+    };
+
+    encryption::EncryptionManager::lock();
+    config.shutting_down = true;
+
+    Response::Success {
+        message: "Shutting down".to_string(),
+        data: Some(serde_json::json!({
+            "committed_writes": committed_writes,
+            "pending_ops": pending_ops,
+        })),
+    }
+}
+
+fn handle_flush(config: &mut HostConfig) -> Response {
+    flush_batch(config).unwrap_or_else(|| Response::Success {
+        message: "Nothing to commit".to_string(),
+        data: None,
+    })
+}
+
+/// Commit message for the staged bookmarks.json, from the configured template
+fn commit_message(config: &HostConfig, repo: &git::GitRepo, repo_path: &Path) -> String {
+    let template = config
+        .commit_template
+        .as_deref()
+        .unwrap_or(commit_template::DEFAULT_COMMIT_TEMPLATE);
+
+    // Counts come from diffing against the last committed version
+    let previous = if repo.has_commits() {
+        sync::bookmarks_at(repo, "HEAD", config.encryption_enabled)
+    } else {
+        Ok(storage::BookmarksData::new())
+    };
+    let current = storage::read_from_file_with_encryption(
+        repo_path.join("bookmarks.json"),
+        config.encryption_enabled,
+    );
+
+    match (previous, current) {
+        (Ok(previous), Ok(current)) => {
+            let summary = commit_template::CommitSummary::new(
+                &previous,
+                &current,
+                repo.device_name().unwrap_or_else(device::hostname),
+                chrono::Utc::now(),
+            );
+            commit_template::render(template, &summary)
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("Failed to summarize changes for commit message: {e:#}");
+            "Update bookmarks".to_string()
+        }
+    }
+}
+
+/// Commit staged changes and push them (queueing the push if offline)
+fn commit_and_push(config: &HostConfig, repo: &git::GitRepo, repo_path: &Path) -> Response {
+    let message = commit_message(config, repo, repo_path);
+    commit_and_push_with(repo, repo_path, &message)
+}
+
+/// Let the pre-commit hook, if there is one, stop a commit
+fn run_pre_commit_hook(repo: &git::GitRepo, commit_message: &str) -> Result<(), Response> {
+    let summary = serde_json::json!({
+        "message": commit_message,
+        "device": repo.device_name(),
+    });
+    match hooks::run(repo, hooks::Hook::PreCommit, &summary) {
+        Ok(Some(outcome)) if !outcome.succeeded() => Err(Response::error_with_details(
+            HostError::Hook,
+            format!("{}; the change was discarded", outcome.describe()),
+            serde_json::json!({ "hook": outcome }),
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => Err(Response::error(HostError::Hook, format!("{e:#}"))),
+    }
+}
+
+/// Run the post-sync hook, if there is one, with the sync's summary. The sync
+/// has already happened, so a failure only becomes a warning.
+fn run_post_sync_hook(
+    repo: &git::GitRepo,
+    summary: &serde_json::Value,
+) -> Option<messaging::Warning> {
+    let (message, details) = match hooks::run(repo, hooks::Hook::PostSync, summary) {
+        Ok(Some(outcome)) if !outcome.succeeded() => (
+            outcome.describe(),
+            Some(serde_json::json!({ "hook": outcome })),
+        ),
+        Ok(_) => return None,
+        Err(e) => (format!("{e:#}"), None),
+    };
+    warn!("{message}");
+    Some(messaging::Warning {
+        code: messaging::WarningCode::HookFailed,
+        message,
+        details,
+    })
+}
+
+/// Commit what's staged with `commit_message` and push it to every remote
+fn commit_and_push_with(repo: &git::GitRepo, repo_path: &Path, commit_message: &str) -> Response {
+    if let Err(response) = run_pre_commit_hook(repo, commit_message) {
+        // A rejected change is undone rather than left staged, where a later
+        // write or crash recovery would commit it without asking the hook
+        if let Err(e) = repo.discard_staged() {
+            warn!("Failed to discard rejected changes: {e:#}");
+        }
+        if let Err(e) = wal::complete(repo_path) {
+            warn!("Failed to clear write intent: {e:#}");
+        }
+        return response;
+    }
+    if let Err(e) = repo.commit(commit_message) {
+        return Response::error(HostError::GitCommit, format!("Failed to commit: {e}"));
+    }
+    if let Err(e) = wal::complete(repo_path) {
+        warn!("Failed to clear write intent: {e:#}");
+    }
+
+    let remotes = match repo.remotes() {
+        Ok(remotes) => remotes,
+        Err(e) => return Response::error(HostError::GitPush, format!("{e:#}")),
+    };
+    if remotes.is_empty() {
+        return Response::Success {
+            message: "Bookmarks saved".to_string(),
+            data: None,
+        };
+    }
+
+    let (pushes, pending_ops) = match push_to_remotes(repo, repo_path, &remotes) {
+        Ok(result) => result,
+        Err(response) => return response,
+    };
+    let data = serde_json::json!({
+        "queued": pushes.iter().any(|push| matches!(push.outcome, git::PushOutcome::Queued { .. })),
+        "pending_ops": pending_ops,
+        "remotes": pushes,
+    });
+
+    // Only the primary remote failing outright fails the write; mirrors are best-effort
+    let primary_failed = pushes.iter().find_map(|push| match &push.outcome {
+        git::PushOutcome::Failed { error } if push.remote == git::PRIMARY_REMOTE => Some(error),
+        _ => None,
+    });
+    if let Some(error) = primary_failed {
+        return Response::error_with_details(
+            HostError::GitPush,
+            format!("Failed to push: {error}"),
+            data,
+        );
+    }
+
+    let message = if pushes
+        .iter()
+        .all(|push| push.outcome == git::PushOutcome::Pushed)
+    {
+        "Bookmarks saved and synced"
+    } else {
+        "Bookmarks saved; some remotes were not updated"
+    };
+    Response::Success {
+        message: message.to_string(),
+        data: Some(data),
+    }
+}
+
+/// Push `main` to each remote. Pushes that fail because a remote is
+/// unreachable are queued for later. Returns each remote's outcome and the
+/// number of queued operations.
+fn push_to_remotes(
+    repo: &git::GitRepo,
+    repo_path: &Path,
+    remotes: &[String],
+) -> Result<(Vec<git::RemotePush>, usize), Response> {
+    let mut pending = match pending::PendingOps::load(repo_path) {
+        Ok(pending) => pending,
+        Err(e) => return Err(Response::error(HostError::GitPush, e.to_string())),
+    };
+
+    let pushes: Vec<git::RemotePush> = remotes
+        .iter()
+        .map(|remote| {
+            let outcome = match repo.push(remote, "main") {
+                Ok(()) => git::PushOutcome::Pushed,
+                Err(e) if git::is_transient_error(&e) => {
+                    // Offline: keep the commit and push it on the next Sync or connectivity check
+                    warn!("Push to {remote} failed, queueing for later: {e:#}");
+                    pending.enqueue_push(remote, "main");
+                    git::PushOutcome::Queued {
+                        error: format!("{e:#}"),
+                    }
+                }
+                Err(e) => {
+                    warn!("Push to {remote} failed: {e:#}");
+                    git::PushOutcome::Failed {
+                        error: format!("{e:#}"),
+                    }
+                }
+            };
+            git::RemotePush {
+                remote: remote.clone(),
+                outcome,
+            }
+        })
+        .collect();
+
+    if let Err(e) = pending.save(repo_path) {
+        return Err(Response::error(
+            HostError::GitPush,
+            format!("Failed to queue push: {e}"),
+        ));
+    }
+    Ok((pushes, pending.len()))
+}
+
+/// URLs of every configured remote
+fn remote_urls(repo: &git::GitRepo) -> Vec<String> {
+    repo.remotes()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|remote| repo.remote_url(remote))
+        .collect()
+}
+
+/// `NeedsSecret` if any of `urls` is an SSH remote and the configured key's
+/// passphrase hasn't been given yet. Checked before a request changes anything.
+fn ssh_passphrase_prompt(urls: &[String]) -> Option<Response> {
+    let uses_ssh = urls
+        .iter()
+        .any(|url| matches!(git_url::parse_git_url(url), Ok(git_url::GitUrlType::Ssh)));
+    if !uses_ssh || !ssh::needs_passphrase() {
+        return None;
+    }
+
+    let key = ssh::key_path().unwrap_or_default();
+    Some(Response::NeedsSecret {
+        secret: messaging::SecretKind::SshPassphrase,
+        prompt: format!("Enter the passphrase for SSH key {}", key.display()),
+    })
+}
+
+/// Run queued operations. Failures are logged and leave the queue in place.
+fn flush_pending_ops(repo: &git::GitRepo, repo_path: &Path) -> usize {
+    let mut pending = match pending::PendingOps::load(repo_path) {
+        Ok(pending) => pending,
+        Err(e) => {
+            warn!("Failed to load pending operations: {e}");
+            return 0;
+        }
+    };
+    if pending.is_empty() {
+        return 0;
+    }
+
+    let flushed = pending.flush(repo).unwrap_or_else(|e| {
+        warn!("Failed to flush pending operations: {e:#}");
+        0
+    });
+    if let Err(e) = pending.save(repo_path) {
+        warn!("Failed to save pending operations: {e}");
+    }
+    info!(
+        "Flushed {flushed} pending operations, {} remaining",
+        pending.len()
+    );
+    pending.len()
+}
+
+async fn handle_read(config: &mut HostConfig, options: &query::ReadOptions) -> Response {
+    info!("Reading bookmarks data");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+
+    let bookmarks_file = repo_path.join("bookmarks.json");
+
+    // Check if file exists
+    if !bookmarks_file.exists() {
+        // Return empty bookmarks data
+        let empty_data = storage::BookmarksData::new();
+        let data_value = match serde_json::to_value(empty_data) {
+            Ok(v) => v,
+            Err(e) => {
+                return Response::error(
+                    HostError::Serialize,
+                    format!("Failed to serialize empty data: {e}"),
+                )
+            }
+        };
+        return Response::Success {
+            message: "No bookmarks file found, returning empty data".to_string(),
+            data: Some(data_value),
+        };
+    }
+
+    // Read from file (with encryption support)
+    let loaded = match storage::load(&bookmarks_file, config.encryption_enabled) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            return broken_references(&e).unwrap_or_else(|| {
+                Response::error(
+                    HostError::ReadFile,
+                    format!("Failed to read bookmarks file: {e}"),
+                )
+            })
+        }
+    };
+    // The upgraded data is returned either way; writing it back can wait
+    // for a later read if this fails
+    if loaded.was_migrated() {
+        if let Err(e) = save_migration(config, &repo_path, &loaded) {
+            warn!("Failed to save migrated bookmarks: {e:#}");
+        }
+    }
+    let bookmarks_data = loaded.data;
+
+    let serialized = if options.is_paged() {
+        match options.document(&bookmarks_data) {
+            Ok(document) => Ok(document),
+            Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+        }
+    } else {
+        serde_json::to_value(bookmarks_data)
+    };
+    let mut data_value = match serialized {
+        Ok(v) => v,
+        Err(e) => {
+            return Response::error(
+                HostError::Serialize,
+                format!("Failed to serialize bookmarks data: {e}"),
+            )
+        }
+    };
+
+    // Local-only activity is merged in here and never written to bookmarks.json
+    match activity::ActivityLog::load(&repo_path) {
+        Ok(log) => log.merge_into(&mut data_value),
+        Err(e) => log::warn!("Failed to load local activity: {e}"),
+    }
+
+    Response::Success {
+        message: "Bookmarks loaded".to_string(),
+        data: Some(data_value),
+    }
+}
+
+/// Load the current bookmarks, returning empty data if no file exists yet
+fn load_bookmarks(config: &HostConfig) -> Result<storage::BookmarksData, Response> {
+    let repo_path = config
+        .get_repo_path()
+        .map_err(|e| Response::error(HostError::NotInitialized, e.to_string()))?;
+
+    let bookmarks_file = repo_path.join("bookmarks.json");
+    if !bookmarks_file.exists() {
+        return Ok(storage::BookmarksData::new());
+    }
+
+    storage::read_from_file_with_encryption(&bookmarks_file, config.encryption_enabled).map_err(
+        |e| {
+            Response::error(
+                HostError::ReadFile,
+                format!("Failed to read bookmarks file: {e}"),
+            )
+        },
+    )
+}
+
+async fn handle_rebuild_indexes(config: &mut HostConfig) -> Response {
+    info!("Rebuilding indexes");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let index_dir = match config.get_repo_path().and_then(index::IndexDir::for_repo) {
+        Ok(dir) => dir,
+        Err(e) => {
+            return Response::error(
+                HostError::Index,
+                format!("Failed to locate index directory: {e}"),
+            )
+        }
+    };
+
+    match index_dir.rebuild(&bookmarks_data) {
+        Ok(stats) => Response::Success {
+            message: "Indexes rebuilt".to_string(),
+            data: Some(serde_json::json!({
+                "index_path": index_dir.path(),
+                "format_version": index::INDEX_FORMAT_VERSION,
+                "stats": stats,
+            })),
+        },
+        Err(e) => Response::error(HostError::Index, format!("Failed to rebuild indexes: {e}")),
+    }
+}
+
+async fn handle_record_visit(config: &mut HostConfig, bookmark_id: &str) -> Response {
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+
+    let mut log = match activity::ActivityLog::load(&repo_path) {
+        Ok(log) => log,
+        Err(e) => {
+            return Response::error(HostError::Activity, format!("Failed to load activity: {e}"))
+        }
+    };
+
+    let visit = log.record_visit(bookmark_id, chrono::Utc::now()).clone();
+
+    if let Err(e) = log.save(&repo_path) {
+        return Response::error(HostError::Activity, format!("Failed to save activity: {e}"));
+    }
+
+    Response::Success {
+        message: "Visit recorded".to_string(),
+        data: serde_json::to_value(visit).ok(),
+    }
+}
+
+async fn handle_clear_activity(config: &mut HostConfig) -> Response {
+    info!("Clearing local activity");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+
+    if let Err(e) = activity::ActivityLog::default().save(&repo_path) {
+        return Response::error(
+            HostError::Activity,
+            format!("Failed to clear activity: {e}"),
+        );
+    }
+
+    Response::Success {
+        message: "Local activity cleared".to_string(),
+        data: None,
+    }
+}
+
+async fn handle_sync(config: &mut HostConfig) -> Response {
+    info!("Syncing with remote");
+
+    // Batched writes go out with this sync
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    let remote = match sync::remote_file(&repo) {
+        Ok(remote) => remote,
+        Err(e) => return Response::error(HostError::Remote, format!("{e:#}")),
+    };
+    if let Some(remote) = remote {
+        let message = format!("Synced with {}", remote.name());
+        let mut backend =
+            sync::FileBackend::new(&repo, remote, config.active_encryption(), config.cipher);
+        let (pulled, pushed) = match run_sync(&mut backend).await {
+            Ok(result) => result,
+            Err(response) => return response,
+        };
+        let data = serde_json::json!({
+            "backend": sync::backend_name(&repo),
+            "pulled": pulled.pulled,
+            "pushed": pushed,
+            "conflicts_resolved": pulled.conflicts_resolved,
+            "version": sync::synced_version(&repo),
+        });
+        let hook_warning = run_post_sync_hook(&repo, &data);
+        let response = sync_response(config, &repo_path, message, data);
+        return match hook_warning {
+            Some(warning) => with_warning(response, &warning),
+            None => response,
+        };
+    }
+
+    if !repo.has_remote("origin") {
+        return Response::error(HostError::NoRemote, "No remote configured");
+    }
+
+    if let Some(prompt) = ssh_passphrase_prompt(&remote_urls(&repo)) {
+        return prompt;
+    }
+
+    // Before the push, so the pointers it carries can be resolved
+    let archives_uploaded = match upload_archives(&repo, &repo_path).await {
+        Ok(count) => count,
+        Err(e) => {
+            return Response::error(
+                HostError::GitPush,
+                format!("Failed to upload archives: {e:#}"),
+            )
+        }
+    };
+
+    let mut backend = sync::GitBackend::new(&repo, config.active_encryption(), config.cipher);
+    let (pulled, unpushed) = match run_sync(&mut backend).await {
+        Ok(result) => result,
+        Err(response) => return response,
+    };
+
+    // Bring mirrors up to date with what origin now has
+    let mirrors: Vec<String> = match repo.remotes() {
+        Ok(remotes) => remotes
+            .into_iter()
+            .filter(|remote| remote != git::PRIMARY_REMOTE)
+            .collect(),
+        Err(e) => return Response::error(HostError::GitPush, format!("{e:#}")),
+    };
+    let mut remotes = vec![git::RemotePush {
+        remote: git::PRIMARY_REMOTE.to_string(),
+        outcome: git::PushOutcome::Pushed,
+    }];
+    if !mirrors.is_empty() {
+        match push_to_remotes(&repo, &repo_path, &mirrors) {
+            Ok((pushes, _)) => remotes.extend(pushes),
+            Err(response) => return response,
+        }
+    }
+
+    let pending_ops = flush_pending_ops(&repo, &repo_path);
+
+    let data = serde_json::json!({
+        "backend": "git",
+        "pulled": pulled.pulled,
+        "pushed": unpushed,
+        "conflicts_resolved": pulled.conflicts_resolved,
+        "pending_ops": pending_ops,
+        "remotes": remotes,
+        "archives_uploaded": archives_uploaded,
+    });
+    let hook_warning = run_post_sync_hook(&repo, &data);
+    let response = sync_response(config, &repo_path, "Synced with remote".to_string(), data);
+    match hook_warning {
+        Some(warning) => with_warning(response, &warning),
+        None => response,
+    }
+}
+
+/// `Success`, or `Conflicts` if the merge left fields for the user to settle
+/// and the client can show them
+fn sync_response(
+    config: &HostConfig,
+    repo_path: &Path,
+    message: String,
+    data: serde_json::Value,
+) -> Response {
+    let conflicts = if config.has_capability(messaging::Capability::ConflictResolution) {
+        merge::load_conflicts(repo_path).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable merge conflicts: {e:#}");
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+    if conflicts.is_empty() {
+        return Response::Success {
+            message,
+            data: Some(data),
+        };
+    }
+    Response::Conflicts {
+        message: format!("{message}; {} fields need a decision", conflicts.len()),
+        conflicts,
+        data: Some(data),
+    }
+}
+
+fn handle_resolve_conflicts(
+    config: &mut HostConfig,
+    decisions: &[merge::ConflictDecision],
+) -> Response {
+    info!("Resolving {} merge conflicts", decisions.len());
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let mut conflicts = match merge::load_conflicts(&repo_path) {
+        Ok(conflicts) => conflicts,
+        Err(e) => return Response::error(HostError::ReadFile, format!("{e:#}")),
+    };
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    for decision in decisions {
+        let Some(at) = conflicts
+            .iter()
+            .position(|c| c.id == decision.id && c.field == decision.field)
+        else {
+            return Response::error(
+                HostError::Validate,
+                format!(
+                    "No conflict on {} of {}; sync again for the current list",
+                    if decision.field.is_empty() {
+                        "the whole resource"
+                    } else {
+                        &decision.field
+                    },
+                    decision.id
+                ),
+            );
+        };
+        if let Err(e) = merge::resolve(&mut bookmarks_data, &conflicts[at], decision.choice) {
+            return Response::error(HostError::Validate, format!("{e:#}"));
+        }
+        conflicts.remove(at);
+    }
+
+    let response = save_bookmarks(config, &repo_path, &bookmarks_data);
+    if matches!(response, Response::Error { .. }) {
+        return response;
+    }
+    if let Err(e) = merge::save_conflicts(&repo_path, &conflicts) {
+        return Response::error(HostError::WriteFile, format!("{e:#}"));
+    }
+    if conflicts.is_empty() {
+        return response;
+    }
+    Response::Conflicts {
+        message: format!("{} fields still need a decision", conflicts.len()),
+        conflicts,
+        data: None,
+    }
+}
+
+/// Pull then push, so local commits go out on top of the other side's changes
+async fn run_sync(
+    backend: &mut impl sync::SyncBackend,
+) -> Result<(git::PullSummary, usize), Response> {
+    let pulled = match backend.pull().await {
+        Ok(pulled) => pulled,
+        Err(e) => return Err(sync_error(HostError::GitPull, "Failed to pull", &e).await),
+    };
+    let pushed = match backend.push().await {
+        Ok(pushed) => pushed,
+        Err(e) => return Err(sync_error(HostError::GitPush, "Failed to push", &e).await),
+    };
+    Ok((pulled, pushed))
+}
+
+/// An error with `code`, unless syncing again or signing in again would
+/// resolve it
+async fn sync_error(code: HostError, action: &str, err: &anyhow::Error) -> Response {
+    if err.is::<sync::Conflict>() {
+        Response::error(HostError::SyncConflict, err.to_string())
+    } else if let Some(rewritten) = err.downcast_ref::<git::HistoryRewritten>() {
+        Response::error(HostError::HistoryRewritten, rewritten.to_string())
+    } else if token_was_rejected(err).await {
+        Response::error(
+            HostError::InvalidToken,
+            "GitHub rejected the stored token; send reauth to sign in again",
+        )
+    } else {
+        Response::error(code, format!("{action}: {err}"))
+    }
+}
+
+/// Whether `err` came from GitHub refusing the stored token, which is then
+/// remembered for `Status`. A git authentication failure may be down to SSH
+/// instead, so the token is only blamed once GitHub confirms it.
+async fn token_was_rejected(err: &anyhow::Error) -> bool {
+    if err.is::<github::TokenRejected>() {
+        github::mark_token_rejected();
+        return true;
+    }
+    if !git::is_auth_error(err) {
+        return false;
+    }
+    let Ok(token) = github::get_token() else {
+        return false;
+    };
+    match github::GitHubClient::new().check_token(&token).await {
+        Ok(checked) if checked.rejected => {
+            github::mark_token_rejected();
+            true
+        }
+        _ => false,
+    }
+}
+
+async fn handle_auth(
+    config: &HostConfig,
+    method: messaging::AuthMethod,
+    token: Option<String>,
+) -> Response {
+    info!("Handling authentication: {method:?}");
+
+    match method {
+        messaging::AuthMethod::OAuth => {
+            // Start OAuth device flow
+            let client = github_client(config);
+
+            let device_code_response = match client.start_device_flow().await {
+                Ok(response) => response,
+                Err(e) => {
+                    return Response::error(
+                        HostError::OAuthStart,
+                        format!("Failed to start OAuth flow: {e}"),
+                    )
+                }
+            };
+
+            // Return device code info to extension (which will show to user)
+            github::track_device_flow(&device_code_response);
+            Response::AuthFlow {
+                user_code: device_code_response.user_code,
+                verification_uri: device_code_response.verification_uri,
+                device_code: device_code_response.device_code,
+            }
+        }
+        messaging::AuthMethod::PAT => {
+            // Store provided PAT
+            let Some(token) = token else {
+                return Response::error(HostError::NoToken, "No token provided");
+            };
+
+            // Validate token
+            let client = github_client(config);
+            match client.check_token(&token).await {
+                Ok(metadata) if !metadata.rejected => {
+                    // Store in keychain, with its expiry if GitHub reported one
+                    if let Err(e) = github::store_token(&token)
+                        .and_then(|()| github::store_token_metadata(&metadata))
+                    {
+                        return Response::error(
+                            HostError::StoreToken,
+                            format!("Failed to store token: {e}"),
+                        );
+                    }
+
+                    let report = check_token_scopes(config, &client, &token, metadata.scopes).await;
+                    let message = if report.is_insufficient() {
+                        "Token stored, but it lacks the access needed to sync"
+                    } else if !report.excess_scopes.is_empty() {
+                        "Token stored; it has more access than WebTags needs"
+                    } else {
+                        "Token validated and stored"
+                    };
+                    Response::Success {
+                        message: message.to_string(),
+                        data: Some(serde_json::json!(report)),
+                    }
+                }
+                Ok(_) => Response::error(HostError::InvalidToken, "Invalid token"),
+                Err(e) => Response::error(
+                    HostError::ValidateToken,
+                    format!("Failed to validate token: {e}"),
+                ),
+            }
+        }
+    }
+}
+
+/// Compare a token's access with what the configured repository needs: the
+/// `gist` scope for the Gist backend, otherwise `repo` and push access to the
+/// GitHub remote, if there is one
+async fn check_token_scopes(
+    config: &HostConfig,
+    client: &github::GitHubClient,
+    token: &str,
+    scopes: Option<Vec<String>>,
+) -> github::ScopeReport {
+    let repo = config
+        .repo_path
+        .as_ref()
+        .and_then(|path| git::GitRepo::init(path).ok());
+    let backend = repo.as_ref().map_or("git", sync::backend_name);
+    let required: &[&str] = if backend == "gist" {
+        &["gist"]
+    } else {
+        &["repo"]
+    };
+    let mut report = github::ScopeReport::new(scopes, required);
+
+    let github_repo = repo
+        .filter(|_| backend == "git")
+        .and_then(|repo| repo.remote_url("origin"))
+        .and_then(|url| git_url::github_repo(&url));
+    if let Some((owner, name)) = github_repo {
+        match client.can_push(token, &owner, &name).await {
+            Ok(can_push) => report.can_push = Some(can_push),
+            Err(e) => warn!("Failed to check push access to {owner}/{name}: {e:#}"),
+        }
+    }
+    report
+}
+
+async fn handle_reauth(config: &HostConfig, device_code: Option<String>) -> Response {
+    let client = github_client(config);
+
+    let Some(device_code) = device_code else {
+        info!("Starting GitHub re-authentication");
+        return match client.start_device_flow().await {
+            Ok(flow) => {
+                github::track_device_flow(&flow);
+                Response::AuthFlow {
+                    user_code: flow.user_code,
+                    verification_uri: flow.verification_uri,
+                    device_code: flow.device_code,
+                }
+            }
+            Err(e) => Response::error(
+                HostError::OAuthStart,
+                format!("Failed to start OAuth flow: {e}"),
+            ),
+        };
+    };
+
+    info!("Waiting for GitHub authorization");
+    // A flow started before a host restart polls with GitHub's defaults
+    let (interval, timeout) = github::device_flow(&device_code).map_or(
+        (
+            github::DEFAULT_POLL_INTERVAL,
+            github::DEFAULT_DEVICE_CODE_LIFETIME,
+        ),
+        |flow| {
+            (
+                flow.interval,
+                flow.expires_at.saturating_duration_since(Instant::now()),
+            )
+        },
+    );
+    let send_progress = config.has_capability(messaging::Capability::Progress);
+    let total = usize::try_from(timeout.as_secs()).unwrap_or(usize::MAX);
+    let polled = client
+        .poll_for_token_until(&device_code, interval, timeout, |remaining| {
+            if send_progress {
+                let left = usize::try_from(remaining.as_secs()).unwrap_or(usize::MAX);
+                let progress = Response::Progress {
+                    operation: "auth".to_string(),
+                    done: total.saturating_sub(left),
+                    total,
+                };
+                if let Err(e) = messaging::write_response(stdout(), &progress) {
+                    warn!("Failed to send sign-in progress: {e}");
+                }
+            }
+        })
+        .await;
+    github::forget_device_flow(&device_code);
+    let issued = match polled {
+        Ok(issued) => issued,
+        Err(e) if e.is::<github::AuthCancelled>() => {
+            return Response::error(HostError::AuthCancelled, e.to_string())
+        }
+        Err(e) => {
+            return Response::error(
+                HostError::InvalidToken,
+                format!("GitHub authorization failed: {e}"),
+            )
+        }
+    };
+    if let Err(e) = github::store_issued_token(&issued) {
+        return Response::error(HostError::StoreToken, format!("Failed to store token: {e}"));
+    }
+
+    let metadata = github::get_token_metadata();
+    Response::Success {
+        message: "Signed in to GitHub".to_string(),
+        data: Some(serde_json::json!({
+            "github_token": metadata.state(chrono::Utc::now()),
+            "github_token_expires_at": metadata.expires_at,
+        })),
+    }
+}
+
+/// Cancelling takes effect when the message arrives (see `main`); by the time
+/// it is handled here the poll, if any, has already stopped
+fn handle_auth_cancel(device_code: &str) -> Response {
+    info!("Cancelling GitHub sign-in");
+    github::forget_device_flow(device_code);
+    Response::Success {
+        message: "Sign-in cancelled".to_string(),
+        data: None,
+    }
+}
+
+/// Repositories checked for a bookmarks file; the picker shows this many
+const MAX_PICKER_REPOS: usize = 30;
+
+async fn handle_list_repos(config: &HostConfig, query: Option<&str>) -> Response {
+    info!("Listing GitHub repositories");
+
+    let token = match github::get_token() {
+        Ok(token) => token,
+        Err(e) => {
+            return Response::error(HostError::NoToken, format!("Not signed in to GitHub: {e}"))
+        }
+    };
+    let client = github_client(config);
+    let repos = match client.list_repositories(&token).await {
+        Ok(repos) => repos,
+        Err(e) if e.is::<github::TokenRejected>() => {
+            github::mark_token_rejected();
+            return Response::error(
+                HostError::InvalidToken,
+                "GitHub rejected the stored token; send reauth to sign in again",
+            );
+        }
+        Err(e) => return Response::error(HostError::GitHubApi, format!("{e:#}")),
+    };
+
+    let query = query.map(str::to_lowercase);
+    let matching: Vec<_> = repos
+        .into_iter()
+        .filter(|repo| {
+            query
+                .as_deref()
+                .is_none_or(|query| repo.full_name.to_lowercase().contains(query))
+        })
+        .collect();
+    let total = matching.len();
+
+    let mut choices = Vec::new();
+    for repo in matching.into_iter().take(MAX_PICKER_REPOS) {
+        let has_bookmarks = client
+            .has_file(&token, &repo.full_name, "bookmarks.json")
+            .await
+            .inspect_err(|e| warn!("Failed to look inside {}: {e:#}", repo.full_name))
+            .ok();
+        choices.push(github::RepoChoice {
+            name: repo.name,
+            full_name: repo.full_name,
+            clone_url: repo.clone_url,
+            ssh_url: repo.ssh_url,
+            private: repo.private,
+            updated_at: repo.updated_at,
+            has_bookmarks,
+        });
+    }
+
+    Response::Success {
+        message: format!("Found {total} repositories"),
+        data: Some(serde_json::json!({
+            "repos": choices,
+            "total": total,
+        })),
+    }
+}
+
+async fn handle_sign_out(config: &HostConfig, remove_remote: bool) -> Response {
+    info!("Signing out of GitHub");
+
+    let removed_remote = if remove_remote {
+        match disconnect_github(config) {
+            Ok(removed) => removed,
+            Err(response) => return response,
+        }
+    } else {
+        false
+    };
+
+    let token = github::get_token().ok();
+    let revoked = match &token {
+        Some(token) => match github_client(config).revoke_token(token).await {
+            Ok(()) => true,
+            // Forgetting the token matters more than revoking it
+            Err(e) => {
+                warn!("Failed to revoke GitHub token: {e:#}");
+                false
+            }
+        },
+        None => false,
+    };
+    if token.is_some() {
+        if let Err(e) = github::delete_token() {
+            return Response::error(
+                HostError::StoreToken,
+                format!("Failed to delete token: {e}"),
+            );
+        }
+    }
+
+    Response::Success {
+        message: if token.is_some() {
+            "Signed out of GitHub".to_string()
+        } else {
+            "Not signed in to GitHub".to_string()
+        },
+        data: Some(serde_json::json!({
+            "revoked": revoked,
+            "removed_remote": removed_remote,
+        })),
+    }
+}
+
+/// Stop syncing with GitHub: remove `origin`, or switch a Gist backend back
+/// to local-only git. Returns whether anything was removed.
+fn disconnect_github(config: &HostConfig) -> Result<bool, Response> {
+    let Some(repo_path) = config.repo_path.as_ref() else {
+        return Ok(false);
+    };
+    let _lock = lock_repo(repo_path)?;
+    let mut repo = git::GitRepo::init(repo_path).map_err(|e| {
+        Response::error(
+            HostError::OpenRepo,
+            format!("Failed to open repository: {e}"),
+        )
+    })?;
+
+    if sync::backend_name(&repo) == "gist" {
+        sync::configure(&repo, &sync::BackendConfig::Git)
+            .map_err(|e| Response::error(HostError::Remote, format!("{e:#}")))?;
+        return Ok(true);
+    }
+    if !repo.has_remote(git::PRIMARY_REMOTE) {
+        return Ok(false);
+    }
+    repo.remove_remote(git::PRIMARY_REMOTE)
+        .map_err(|e| Response::error(HostError::Remote, format!("{e:#}")))?;
+
+    let mut pending = pending::PendingOps::load(repo_path).unwrap_or_default();
+    if pending.drop_remote(git::PRIMARY_REMOTE) > 0 {
+        if let Err(e) = pending.save(repo_path) {
+            warn!("Failed to save pending operations: {e:#}");
+        }
+    }
+    Ok(true)
+}
+
+async fn handle_status(config: &HostConfig) -> Response {
+    info!("Getting status");
+
+    let Some(repo_path) = config.repo_path.as_ref() else {
+        return Response::Success {
+            message: "Not initialized".to_string(),
+            data: Some(serde_json::json!({
+                "initialized": false,
+            })),
+        };
+    };
+
+    let repo = match git::GitRepo::init(repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    let is_clean = repo.is_clean().unwrap_or(false);
+    let has_remote = repo.has_remote("origin");
+
+    let last_commit = repo.get_last_commit_message().ok();
+
+    // Queued pushes double as a connectivity check: flush them if the remote
+    // is back. Pushing needs the repository lock; if another process holds
+    // it, leave the queue for that process or the next status.
+    let mut pending_ops = pending::PendingOps::load(repo_path).map_or(0, |p| p.len());
+    if pending_ops > 0 && has_remote && repo.check_connectivity("origin").is_ok() {
+        match lock_repo(repo_path) {
+            Ok(_lock) => pending_ops = flush_pending_ops(&repo, repo_path),
+            Err(_) => info!("Repository is locked; not flushing queued pushes"),
+        }
+    }
+
+    // Relative to the last fetch; null until the remote branch is known
+    let ahead_behind = if has_remote {
+        repo.ahead_behind("origin").unwrap_or_else(|e| {
+            log::warn!("Failed to compare with remote: {e}");
+            None
+        })
+    } else {
+        None
+    };
+
+    Response::Success {
+        message: "Status retrieved".to_string(),
+        data: Some(serde_json::json!({
+            "initialized": true,
+            "repo_path": repo_path,
+            "is_clean": is_clean,
+            "has_remote": has_remote,
+            "backend": sync::backend_name(&repo),
+            "branch": repo.current_branch(),
+            "shallow": repo.is_shallow(),
+            "ahead": ahead_behind.map(|ab| ab.ahead),
+            "behind": ahead_behind.map(|ab| ab.behind),
+            "last_commit": last_commit,
+            "pending_ops": pending_ops,
+            "pending_writes": config.batcher.pending().map_or(0, |batch| batch.writes),
+            "encryption_enabled": config.encryption_enabled,
+            "encryption_mode": config.active_encryption(),
+            "github_token": github_client(config).token_state().await,
+            "github_token_expires_at": github::get_token_metadata().expires_at,
+        })),
+    }
+}
+
+async fn handle_deepen(config: &HostConfig, depth: Option<u32>) -> Response {
+    match depth {
+        Some(depth) => info!("Deepening history by {depth} commits"),
+        None => info!("Fetching full history"),
+    }
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    if !repo.has_remote("origin") {
+        return Response::error(HostError::NoRemote, "No remote configured");
+    }
+
+    if let Some(prompt) = ssh_passphrase_prompt(&remote_urls(&repo)) {
+        return prompt;
+    }
+
+    if let Err(e) = repo.deepen("origin", "main", depth) {
+        return Response::error(HostError::GitPull, format!("Failed to fetch history: {e}"));
+    }
+
+    Response::Success {
+        message: "History fetched".to_string(),
+        data: Some(serde_json::json!({ "shallow": repo.is_shallow() })),
+    }
+}
+
+async fn handle_maintain(
+    config: &mut HostConfig,
+    squash_older_than_days: Option<u32>,
+    confirm_force_push: bool,
+) -> Response {
+    info!("Running repository maintenance");
+
+    let expired = match expire_bookmarks(config) {
+        Ok(expired) => expired,
+        Err(response) => return response,
+    };
+
+    // Squashing replays HEAD, so batched writes need to be committed first
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    let size_before = repo.git_dir_size().ok();
+    let mut squash = serde_json::Value::Null;
+
+    if let Some(days) = squash_older_than_days {
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(i64::from(days));
+        let plan = match repo.plan_squash(cutoff) {
+            Ok(plan) => plan,
+            Err(e) => {
+                return Response::error(
+                    HostError::Maintenance,
+                    format!("Failed to plan history squash: {e}"),
+                )
+            }
+        };
+
+        if let Some(plan) = plan {
+            let has_remote = repo.has_remote("origin");
+            if has_remote && !confirm_force_push {
+                // Rewriting shared history needs explicit consent; report what would happen
+                return Response::Success {
+                    message:
+                        "Squashing history requires a force push; resend with confirm_force_push"
+                            .to_string(),
+                    data: Some(serde_json::json!({
+                        "requires_confirmation": true,
+                        "squash": { "squashed": plan.squashed, "kept": plan.kept },
+                    })),
+                };
+            }
+
+            if let Err(e) = repo.squash_history(&plan) {
+                return Response::error(
+                    HostError::Maintenance,
+                    format!("Failed to squash history: {e}"),
+                );
+            }
+            if has_remote {
+                if let Err(e) = repo.force_push("origin", "main") {
+                    return Response::error(
+                        HostError::GitPush,
+                        format!("History squashed locally but force push failed: {e}"),
+                    );
+                }
+            }
+            squash = serde_json::json!({
+                "squashed": plan.squashed,
+                "kept": plan.kept,
+                "force_pushed": has_remote,
+            });
+        }
+    }
+
+    let gc_ran = match repo.gc() {
+        Ok(ran) => ran,
+        Err(e) => {
+            return Response::error(
+                HostError::Maintenance,
+                format!("Garbage collection failed: {e}"),
+            )
+        }
+    };
+    if !gc_ran {
+        warn!("git CLI not found; skipping garbage collection");
+    }
+
+    Response::Success {
+        message: "Repository maintenance complete".to_string(),
+        data: Some(serde_json::json!({
+            "gc": gc_ran,
+            "squash": squash,
+            "expired": expired,
+            "size_before": size_before,
+            "size_after": repo.git_dir_size().ok(),
+        })),
+    }
+}
+
+fn handle_reset_to_remote(config: &mut HostConfig, keep_local_changes: bool) -> Response {
+    info!("Resetting to the remote history (keeping local changes: {keep_local_changes})");
+
+    // Batched writes are local changes too
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+    if !repo.has_remote(git::PRIMARY_REMOTE) {
+        return Response::error(HostError::NoRemote, "No remote configured");
+    }
+    if let Some(prompt) = ssh_passphrase_prompt(&remote_urls(&repo)) {
+        return prompt;
+    }
+
+    let local = if keep_local_changes {
+        match load_bookmarks(config) {
+            Ok(data) => Some(data),
+            Err(response) => return response,
+        }
+    } else {
+        None
+    };
+    // Whatever happens next, the bookmarks as they were can be restored
+    let backup = if repo_path.join("bookmarks.json").exists() {
+        match snapshots::SnapshotDir::for_repo(&repo_path)
+            .and_then(|dir| dir.take(&repo_path, chrono::Utc::now()))
+        {
+            Ok(snapshot) => Some(snapshot.id),
+            Err(e) => {
+                return Response::error(
+                    HostError::Backup,
+                    format!("Failed to back up bookmarks before resetting: {e:#}"),
+                )
+            }
+        }
+    } else {
+        None
+    };
+
+    let previous = match repo.reset_to_remote(git::PRIMARY_REMOTE, "main") {
+        Ok(previous) => previous,
+        Err(e) => {
+            return Response::error(
+                HostError::GitPull,
+                format!("Failed to reset to the remote history: {e:#}"),
+            )
+        }
+    };
+    let data = serde_json::json!({ "backup": backup, "kept_local_changes": false });
+    let Some(local) = local else {
+        return Response::Success {
+            message: "Reset to the remote history".to_string(),
+            data: Some(data),
+        };
+    };
+
+    // What this device last synced is the base: its changes since then are
+    // the local ones, and a squash alone changes nothing in the file
+    let remote = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let base = match previous {
+        Some(previous) => {
+            match sync::bookmarks_at(&repo, &previous.to_string(), config.encryption_enabled) {
+                Ok(base) => base,
+                Err(e) => {
+                    return Response::error(
+                        HostError::ReadFile,
+                        format!("Failed to read the last synced bookmarks: {e:#}"),
+                    )
+                }
+            }
+        }
+        None => storage::BookmarksData::new(),
+    };
+    let (merged, conflicts) = merge::merge(&base, &local, &remote);
+    if let Err(e) = merge::save_conflicts(&repo_path, &conflicts) {
+        warn!("Failed to save merge conflicts: {e:#}");
+    }
+    if merged.data() == remote.data() && merged.included() == remote.included() {
+        return Response::Success {
+            message: "Reset to the remote history; there were no local changes to keep".to_string(),
+            data: Some(data),
+        };
+    }
+
+    let response = save_bookmarks(config, &repo_path, &merged);
+    let response = with_data(response, "backup", serde_json::json!(backup));
+    with_data(response, "kept_local_changes", serde_json::json!(true))
+}
+
+async fn handle_diff(config: &HostConfig, from: &str, to: Option<&str>) -> Response {
+    let to = to.unwrap_or("HEAD");
+    info!("Diffing bookmarks {from}..{to}");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    let read_at = |rev| sync::bookmarks_at(&repo, rev, config.encryption_enabled);
+    let (old, new) = match (read_at(from), read_at(to)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            return Response::error(
+                HostError::Diff,
+                format!("Failed to read bookmarks for diff: {e:#}"),
+            )
+        }
+    };
+
+    match serde_json::to_value(diff::diff(&old, &new)) {
+        Ok(changes) => Response::Success {
+            message: format!("Diff {from}..{to}"),
+            data: Some(changes),
+        },
+        Err(e) => Response::error(
+            HostError::Serialize,
+            format!("Failed to serialize diff: {e}"),
+        ),
+    }
+}
+
+fn handle_set_log_level(level: &str) -> Response {
+    match logging::set_level(level) {
+        Ok(level) => {
+            let level = level.as_str().to_lowercase();
+            info!("Log level set to {level}");
+            Response::Success {
+                message: format!("Log level set to {level}"),
+                data: None,
+            }
+        }
+        Err(e) => Response::error(HostError::Validate, e.to_string()),
+    }
+}
+
+fn handle_get_logs(lines: Option<usize>) -> Response {
+    let lines = lines
+        .unwrap_or(logging::DEFAULT_LOG_LINES)
+        .min(logging::MAX_LOG_LINES);
+
+    let logs = match logging::log_dir().and_then(|dir| logging::tail(&dir, lines)) {
+        Ok(logs) => logs,
+        Err(e) => {
+            return Response::error(HostError::ReadFile, format!("Failed to read logs: {e:#}"))
+        }
+    };
+
+    Response::Success {
+        message: format!("{} log lines", logs.len()),
+        data: Some(serde_json::json!({
+            "level": log::max_level().as_str().to_lowercase(),
+            "lines": logs,
+        })),
+    }
+}
+
+fn handle_metrics(reset: bool) -> Response {
+    let snapshot = metrics::snapshot();
+    if reset {
+        metrics::reset();
+    }
+
+    match serde_json::to_value(&snapshot) {
+        Ok(data) => Response::Success {
+            message: format!("{} messages handled", snapshot.messages_handled),
+            data: Some(data),
+        },
+        Err(e) => Response::error(
+            HostError::Serialize,
+            format!("Failed to serialize metrics: {e}"),
+        ),
+    }
+}
+
+fn handle_doctor(config: &HostConfig) -> Response {
+    info!("Checking repository health");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    let report = doctor::run(&repo, config.encryption_enabled);
+    let message = match (report.problems(), report.fixed()) {
+        (0, 0) => "Repository is healthy".to_string(),
+        (0, fixed) => format!("Repaired {fixed} problems"),
+        (problems, fixed) => format!("Repaired {fixed} problems; {problems} need attention"),
+    };
+
+    match serde_json::to_value(&report) {
+        Ok(data) => Response::Success {
+            message,
+            data: Some(data),
+        },
+        Err(e) => Response::error(
+            HostError::Serialize,
+            format!("Failed to serialize report: {e}"),
+        ),
+    }
+}
+
+fn handle_repair(config: &mut HostConfig) -> Response {
+    info!("Repairing tags");
+
+    // Pending writes are committed first so the repair lands on top of them
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let bookmarks_file = repo_path.join("bookmarks.json");
+    let mut bookmarks_data = if bookmarks_file.exists() {
+        match storage::read_from_file_unchecked(&bookmarks_file, config.encryption_enabled) {
+            Ok(data) => data,
+            Err(e) => {
+                return Response::error(
+                    HostError::ReadFile,
+                    format!("Failed to read bookmarks file: {e}"),
+                )
+            }
+        }
+    } else {
+        storage::BookmarksData::new()
+    };
+
+    let fixed = bookmarks_data.repair();
+    let normalized = bookmarks_data.normalize_unicode();
+    let merged_tags = bookmarks_data.merge_duplicate_tags();
+    if fixed.is_empty() && normalized == 0 && merged_tags.is_empty() {
+        return Response::Success {
+            message: "No broken references found".to_string(),
+            data: Some(serde_json::json!({
+                "fixed": fixed,
+                "normalized": 0,
+                "merged_tags": merged_tags,
+            })),
+        };
+    }
+
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+    if let Some(prompt) = ssh_passphrase_prompt(&remote_urls(&repo)) {
+        return prompt;
+    }
+
+    if let Err(e) = wal::begin(&repo) {
+        return Response::error(
+            HostError::WriteFile,
+            format!("Failed to record write intent: {e:#}"),
+        );
+    }
+    // Anything else wrong with the data (duplicate IDs, bad URLs) fails here
+    if let Err(e) = storage::write_to_file_with_mode(
+        &bookmarks_file,
+        &bookmarks_data,
+        config.active_encryption(),
+        config.cipher,
+    ) {
+        return Response::error(
+            HostError::WriteFile,
+            format!("Failed to write bookmarks file: {e:#}"),
+        );
+    }
+    if let Err(e) = repo.add_file("bookmarks.json") {
+        return Response::error(HostError::GitAdd, format!("Failed to stage file: {e}"));
+    }
+
+    match commit_and_push(config, &repo, &repo_path) {
+        Response::Success { data, .. } => {
+            let mut data = data.unwrap_or_else(|| serde_json::json!({}));
+            data["fixed"] = serde_json::json!(fixed);
+            data["normalized"] = serde_json::json!(normalized);
+            data["merged_tags"] = serde_json::json!(merged_tags);
+            Response::Success {
+                message: format!(
+                    "Repaired {} broken references and merged {} duplicate tags",
+                    fixed.len(),
+                    merged_tags.iter().map(|m| m.merged.len()).sum::<usize>()
+                ),
+                data: Some(data),
+            }
+        }
+        response => response,
+    }
+}
+
+fn handle_mark_read(config: &mut HostConfig, id: &str) -> Response {
+    info!("Marking bookmark {id} read");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match bookmarks_data.mark_read(id) {
+        Ok(true) => save_bookmarks(config, &repo_path, &bookmarks_data),
+        Ok(false) => Response::Success {
+            message: "Bookmark was already read".to_string(),
+            data: None,
+        },
+        Err(e) => Response::error(HostError::Validate, e.to_string()),
+    }
+}
+
+fn handle_update_bookmark(
+    config: &mut HostConfig,
+    id: &str,
+    title: Option<String>,
+    notes: Option<String>,
+) -> Response {
+    info!("Updating bookmark {id}");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match bookmarks_data.update_bookmark(id, title, notes) {
+        Ok(true) => {}
+        Ok(false) => {
+            return Response::Success {
+                message: "Bookmark was already up to date".to_string(),
+                data: None,
+            }
+        }
+        Err(e) => return Response::error(HostError::Validate, e.to_string()),
+    }
+    if let Err(e) = bookmarks_data.validate_with(&config.validation) {
+        return Response::error(HostError::Validate, format!("Invalid bookmark: {e}"));
+    }
+    // Sanitizing may have changed the notes, so send back what was stored
+    let notes = match bookmarks_data.get(id) {
+        Some(storage::Resource::Bookmark { attributes, .. }) => serde_json::json!(attributes.notes),
+        _ => serde_json::Value::Null,
+    };
+    with_data(
+        save_bookmarks(config, &repo_path, &bookmarks_data),
+        "notes",
+        notes,
+    )
+}
+
+fn handle_update_tags(config: &mut HostConfig, ops: &[tag_ops::TagOp]) -> Response {
+    info!("Applying {} tag operations", ops.len());
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let mut results = Vec::with_capacity(ops.len());
+    for op in ops {
+        match op.apply(&mut bookmarks_data) {
+            Ok(result) => results.push(result),
+            Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+        }
+    }
+    if let Err(e) = bookmarks_data.validate_with(&config.validation) {
+        return Response::error(HostError::Validate, format!("Invalid tags: {e}"));
+    }
+    with_data(
+        save_bookmarks(config, &repo_path, &bookmarks_data),
+        "results",
+        serde_json::json!(results),
+    )
+}
+
+async fn handle_check_links(config: &mut HostConfig) -> Response {
+    info!("Checking links");
+
+    let threshold = match config.get_repo_path().map(git::GitRepo::init) {
+        Ok(Ok(repo)) => linkcheck::Settings::load(&repo).failure_threshold,
+        Ok(Err(e)) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let send_progress = config.has_capability(messaging::Capability::Progress);
+    let summary = match check_links(config, threshold, send_progress).await {
+        Ok(summary) => summary,
+        Err(response) => return response,
+    };
+    let message = if summary.recorded {
+        format!(
+            "{} of {} links failing; {} newly dead, {} recovered",
+            summary.failing,
+            summary.checked,
+            summary.newly_dead.len(),
+            summary.recovered.len()
+        )
+    } else {
+        "No link could be reached; this device may be offline".to_string()
+    };
+    Response::Success {
+        message,
+        data: Some(serde_json::json!({ "links": summary })),
+    }
+}
+
+/// Longest the archive lookups for newly dead links may hold up a link check
+const ARCHIVE_LOOKUP_BUDGET: Duration = Duration::from_secs(30);
+
+/// Check every link, record the results, and commit any dead-link tags that
+/// changed. The checks run without the repository lock, which is only taken
+/// to apply the results.
+async fn check_links(
+    config: &mut HostConfig,
+    threshold: u32,
+    send_progress: bool,
+) -> Result<linkcheck::RunSummary, Response> {
+    let targets = linkcheck::targets(&load_bookmarks(config)?);
+    let client = link_check_client();
+    let results = linkcheck::check_all(&client, targets, |done, total| {
+        if send_progress {
+            let progress = Response::Progress {
+                operation: "checklinks".to_string(),
+                done,
+                total,
+            };
+            if let Err(e) = messaging::write_response(stdout(), &progress) {
+                warn!("Failed to send link check progress: {e}");
+            }
+        }
+    })
+    .await;
+    apply_link_results(config, threshold, results).await
+}
+
+/// HTTP client for link checks and archive lookups, through the configured proxy
+fn link_check_client() -> reqwest::Client {
+    proxy::http_client().unwrap_or_else(|e| {
+        warn!("Ignoring proxy configuration: {e:#}");
+        reqwest::Client::new()
+    })
+}
+
+/// Record link check results and commit any dead-link tags that changed
+async fn apply_link_results(
+    config: &mut HostConfig,
+    threshold: u32,
+    results: Vec<(String, String, linkcheck::Check)>,
+) -> Result<linkcheck::RunSummary, Response> {
+    let repo_path = config
+        .get_repo_path()
+        .map_err(|e| Response::error(HostError::NotInitialized, e.to_string()))?;
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return Err(response);
+    }
+    let _lock = lock_repo(&repo_path)?;
+    let mut bookmarks_data = load_bookmarks(config)?;
+    let mut status = linkcheck::StatusFile::load(&repo_path)
+        .map_err(|e| Response::error(HostError::ReadFile, format!("{e:#}")))?;
+    let mut summary = linkcheck::apply(
+        &mut status,
+        &mut bookmarks_data,
+        results,
+        threshold,
+        chrono::Utc::now(),
+    );
+    status
+        .save(&repo_path)
+        .map_err(|e| Response::error(HostError::WriteFile, format!("{e:#}")))?;
+
+    // Point newly dead bookmarks at an archived copy, if the Wayback Machine has one
+    let unarchived = wayback::lookup_targets(&summary.newly_dead, &bookmarks_data);
+    if !unarchived.is_empty() {
+        // Bounded, since the lock is held and the message loop may be waiting
+        let wayback = wayback::WaybackClient::new(link_check_client());
+        let found = tokio::time::timeout(ARCHIVE_LOOKUP_BUDGET, wayback.closest_all(unarchived))
+            .await
+            .unwrap_or_else(|_| {
+                warn!("Archive lookups timed out; dead links are left without archived copies");
+                Vec::new()
+            });
+        for (id, snapshot) in found {
+            wayback::set_archive_url(&mut bookmarks_data, &id, &snapshot);
+            if let Some(change) = summary.newly_dead.iter_mut().find(|c| c.id == id) {
+                change.archive_url = Some(snapshot);
+            }
+        }
+    }
+
+    if !summary.newly_dead.is_empty() || !summary.recovered.is_empty() {
+        if let response @ Response::Error { .. } =
+            save_bookmarks(config, &repo_path, &bookmarks_data)
+        {
+            return Err(response);
+        }
+    }
+    Ok(summary)
+}
+
+/// Fetch bookmarked pages and record what they are. The fetches run
+/// without the repository lock, which is only taken to store the results.
+async fn handle_enrich(config: &mut HostConfig, refresh: bool, suggest_tags: bool) -> Response {
+    info!("Enriching bookmarks");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let targets = match load_bookmarks(config) {
+        Ok(data) => enrich::targets(&data, refresh),
+        Err(response) => return response,
+    };
+    let total = targets.len();
+    let client = proxy::http_client().unwrap_or_else(|e| {
+        warn!("Ignoring proxy configuration: {e:#}");
+        reqwest::Client::new()
+    });
+    let send_progress = config.has_capability(messaging::Capability::Progress);
+    let results = enrich::enrich_all(&client, targets, |done, total| {
+        if send_progress {
+            let progress = Response::Progress {
+                operation: "enrich".to_string(),
+                done,
+                total,
+            };
+            if let Err(e) = messaging::write_response(stdout(), &progress) {
+                warn!("Failed to send enrichment progress: {e}");
+            }
+        }
+    })
+    .await;
+    let failed = total - results.len();
+
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let applied = enrich::apply(&mut bookmarks_data, results, suggest_tags);
+    if applied != enrich::Applied::default() {
+        if let response @ Response::Error { .. } =
+            save_bookmarks(config, &repo_path, &bookmarks_data)
+        {
+            return response;
+        }
+    }
+
+    Response::Success {
+        message: format!(
+            "Enriched {} bookmarks; {failed} of {total} pages could not be fetched",
+            applied.enriched
+        ),
+        data: Some(serde_json::json!({
+            "total": total,
+            "enriched": applied.enriched,
+            "suggested": applied.suggested,
+            "failed": failed,
+        })),
+    }
+}
+
+async fn handle_report(
+    config: &HostConfig,
+    kind: report::ReportKind,
+    months: Option<u32>,
+    check_domains: bool,
+    days: Option<u32>,
+) -> Response {
+    info!("Generating {kind:?} report");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    if kind == report::ReportKind::Expiring {
+        let days = days.unwrap_or(report::DEFAULT_EXPIRING_DAYS);
+        let expiring = report::expiring(&bookmarks_data, chrono::Utc::now(), days);
+        return Response::Success {
+            message: format!(
+                "{} expired, {} expiring in the next {days} days",
+                expiring.expired.len(),
+                expiring.expiring.len()
+            ),
+            data: Some(serde_json::json!({ "report": expiring })),
+        };
+    }
+    let log = match activity::ActivityLog::load(&repo_path) {
+        Ok(log) => log,
+        Err(e) => {
+            return Response::error(HostError::Activity, format!("Failed to load activity: {e}"))
+        }
+    };
+
+    let months = months.unwrap_or(report::DEFAULT_STALE_MONTHS);
+    let mut stale = report::stale(&bookmarks_data, &log, chrono::Utc::now(), months);
+    if check_domains {
+        let send_progress = config.has_capability(messaging::Capability::Progress);
+        report::check_domains(&mut stale, &bookmarks_data, &log, |done, total| {
+            if send_progress {
+                let progress = Response::Progress {
+                    operation: "report".to_string(),
+                    done,
+                    total,
+                };
+                if let Err(e) = messaging::write_response(stdout(), &progress) {
+                    warn!("Failed to send report progress: {e}");
+                }
+            }
+        })
+        .await;
+    }
+
+    Response::Success {
+        message: format!(
+            "{} never visited, {} not visited in {months} months, {} on unresolved domains",
+            stale.never_visited.len(),
+            stale.not_visited_recently.len(),
+            stale.unresolved_domains.len()
+        ),
+        data: Some(serde_json::json!({ "report": stale })),
+    }
+}
+
+fn handle_bulk_op(
+    config: &mut HostConfig,
+    filter: &bulk::BulkFilter,
+    op: &bulk::BulkOp,
+) -> Response {
+    info!("Applying bulk operation {op:?}");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let before = bookmarks_data.clone();
+    let changed = match op.apply(&mut bookmarks_data, filter) {
+        Ok(changed) => changed,
+        Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+    };
+    if changed.is_empty() {
+        return Response::Success {
+            message: "Nothing matched".to_string(),
+            data: Some(serde_json::json!({ "changed": changed })),
+        };
+    }
+    if let Err(e) = bookmarks_data.validate_with(&config.validation) {
+        return Response::error(HostError::Validate, format!("Invalid result: {e}"));
+    }
+    let response = save_bookmarks(config, &repo_path, &bookmarks_data);
+    let response = journal_saved(&repo_path, "bulkop", &before, &bookmarks_data, response);
+    with_data(response, "changed", serde_json::json!(changed))
+}
+
+fn handle_undo(config: &mut HostConfig, count: Option<usize>) -> Response {
+    let count = count.unwrap_or(1);
+    info!("Undoing {count} operations");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let mut journal = match journal::Journal::load(&repo_path) {
+        Ok(journal) => journal,
+        Err(e) => return Response::error(HostError::ReadFile, format!("{e:#}")),
+    };
+    if journal.entries.is_empty() {
+        return Response::Success {
+            message: "Nothing to undo".to_string(),
+            data: Some(serde_json::json!({ "undone": [], "skipped": [] })),
+        };
+    }
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let summary = journal.undo(&mut bookmarks_data, count);
+    if let Err(e) = bookmarks_data.validate_with(&config.validation) {
+        return Response::error(
+            HostError::Validate,
+            format!("Can't undo {}: {e}", summary.undone.join(", ")),
+        );
+    }
+    let response = save_bookmarks(config, &repo_path, &bookmarks_data);
+    if matches!(response, Response::Success { .. }) {
+        if let Err(e) = journal.save(&repo_path) {
+            warn!("Failed to update the operation journal: {e:#}");
+        }
+    }
+    with_data(response, "undo", serde_json::json!(summary))
+}
+
+fn handle_backup(config: &HostConfig, path: &Path, passphrase: &str) -> Response {
+    info!("Backing up to {}", path.display());
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+    let favicon_dir = match index::IndexDir::for_repo(&repo_path) {
+        Ok(index) => index.favicon_dir(),
+        Err(e) => return Response::error(HostError::Index, format!("{e:#}")),
+    };
+
+    let contents = match backup::Contents::collect(&repo, &bookmarks_data, &favicon_dir) {
+        Ok(contents) => contents,
+        Err(e) => return Response::error(HostError::Backup, format!("{e:#}")),
+    };
+    if let Err(e) = contents.write(path, passphrase) {
+        return Response::error(HostError::Backup, format!("Failed to write backup: {e:#}"));
+    }
+    Response::Success {
+        message: format!("Backed up {} files", contents.files.len()),
+        data: Some(serde_json::json!({ "path": path, "files": contents.files.len() })),
+    }
+}
+
+fn handle_restore(
+    config: &mut HostConfig,
+    path: &Path,
+    passphrase: &str,
+    repo_path: Option<String>,
+) -> Response {
+    info!("Restoring from {}", path.display());
+
+    let requested_path = repo_path.map_or_else(|| PathBuf::from("default-repo"), PathBuf::from);
+    let repo_path = match validate_repo_path(&requested_path) {
+        Ok(p) => p,
+        Err(e) => {
+            return Response::error(
+                HostError::InvalidPath,
+                format!("Invalid repository path: {e}"),
+            )
+        }
+    };
+
+    let contents = match backup::Contents::read(path, passphrase) {
+        Ok(contents) => contents,
+        Err(e) => return Response::error(HostError::Backup, format!("{e:#}")),
+    };
+    let index = match index::IndexDir::for_repo(&repo_path) {
+        Ok(index) => index,
+        Err(e) => return Response::error(HostError::Index, format!("{e:#}")),
+    };
+    if let Err(e) = index.open() {
+        return Response::error(HostError::Index, format!("{e:#}"));
+    }
+    let summary = match backup::restore(&contents, &repo_path, &index.favicon_dir()) {
+        Ok(summary) => summary,
+        Err(e) => return Response::error(HostError::Backup, format!("{e:#}")),
+    };
+
+    // Restored bookmarks are plain text until encryption is turned on again
+    config.repo_path = Some(repo_path.clone());
+    config.encryption_enabled = false;
+
+    Response::Success {
+        message: format!("Restored {} bookmarks", summary.bookmarks),
+        data: Some(serde_json::json!({
+            "repo_path": repo_path,
+            "restored": summary,
+            "backed_up_at": contents.created,
+        })),
+    }
+}
+
+fn handle_list_backups(config: &HostConfig) -> Response {
+    info!("Listing scheduled backups");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+    let backups = match snapshots::SnapshotDir::for_repo(&repo_path).and_then(|dir| dir.list()) {
+        Ok(backups) => backups,
+        Err(e) => return Response::error(HostError::Backup, format!("{e:#}")),
+    };
+    let settings = snapshots::Settings::load(&repo);
+    Response::Success {
+        message: format!("{} backups", backups.len()),
+        data: Some(serde_json::json!({
+            "backups": backups,
+            "schedule": settings.schedule,
+            "keep": settings.keep,
+        })),
+    }
+}
+
+fn handle_restore_backup(config: &mut HostConfig, id: &str) -> Response {
+    info!("Restoring scheduled backup {id}");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let snapshot =
+        match snapshots::SnapshotDir::for_repo(&repo_path).and_then(|dir| dir.path_of(id)) {
+            Ok(path) => path,
+            Err(e) => return Response::error(HostError::Backup, format!("{e:#}")),
+        };
+    let restored =
+        match storage::read_from_file_with_encryption(&snapshot, config.encryption_enabled) {
+            Ok(data) => data,
+            Err(e) => {
+                return Response::error(
+                    HostError::Backup,
+                    format!("Backup {id} can't be read: {e:#}"),
+                )
+            }
+        };
+    let current = read_stored_bookmarks(config, &repo_path);
+
+    let response = save_bookmarks(config, &repo_path, &restored);
+    match current {
+        Some(current) => journal_saved(&repo_path, "restorebackup", &current, &restored, response),
+        None => response,
+    }
+}
+
+fn handle_export_bundle(config: &mut HostConfig, path: &Path, since: Option<&str>) -> Response {
+    info!("Exporting bundle to {}", path.display());
+
+    // Batched writes belong in the bundle too
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    let commits = match repo.create_bundle(path, "main", since) {
+        Ok(commits) => commits,
+        Err(e) => return Response::error(HostError::Bundle, format!("{e:#}")),
+    };
+    Response::Success {
+        message: format!("Exported {commits} commits"),
+        data: Some(serde_json::json!({
+            "path": path,
+            "commits": commits,
+            // What to pass as `since` next time
+            "head": repo.head_id().map(|id| id.to_string()),
+        })),
+    }
+}
+
+fn handle_import_bundle(config: &mut HostConfig, path: &Path) -> Response {
+    info!("Importing bundle from {}", path.display());
+
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    let backend = sync::GitBackend::new(&repo, config.active_encryption(), config.cipher);
+    let pulled = match backend.pull_bundle(path) {
+        Ok(pulled) => pulled,
+        Err(e) => return Response::error(HostError::Bundle, format!("{e:#}")),
+    };
+    let data = serde_json::json!({
+        "pulled": pulled.pulled,
+        "conflicts_resolved": pulled.conflicts_resolved,
+    });
+    sync_response(
+        config,
+        &repo_path,
+        format!("Imported {} commits", pulled.pulled),
+        data,
+    )
+}
+
+fn handle_stats(config: &HostConfig) -> Response {
+    info!("Computing collection stats");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let stats = stats::stats(&bookmarks_data);
+    Response::Success {
+        message: format!(
+            "{} bookmarks, {} tags, {} domains",
+            stats.bookmarks, stats.tags, stats.domains
+        ),
+        data: Some(serde_json::json!({ "stats": stats })),
+    }
+}
+
+fn handle_find_duplicates(config: &HostConfig) -> Response {
+    info!("Finding duplicate bookmarks");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let duplicates = urlnorm::duplicates(&bookmarks_data);
+    let similar = similar::near_duplicates(&bookmarks_data, similar::SIMILARITY_THRESHOLD);
+    Response::Success {
+        message: format!(
+            "{} pages saved more than once, {} more sets that look alike",
+            duplicates.len(),
+            similar.len()
+        ),
+        data: Some(serde_json::json!({ "duplicates": duplicates, "similar": similar })),
+    }
+}
+
+fn handle_list_unread(config: &HostConfig) -> Response {
+    info!("Listing unread bookmarks");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let unread = bookmarks_data.get_unread_bookmarks();
+    Response::Success {
+        message: format!("{} unread bookmarks", unread.len()),
+        data: Some(serde_json::json!({ "bookmarks": unread })),
+    }
+}
+
+fn handle_tag_tree(config: &mut HostConfig) -> Response {
+    info!("Building tag tree");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let digest = bookmarks_digest(&repo_path);
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let counts = match cached_tag_counts(config, digest.as_ref()) {
+        Some(counts) => counts,
+        None => remember_tag_counts(config, digest, &bookmarks_data),
+    };
+    let tree = bookmarks_data.tag_tree_with_counts(&counts);
+    Response::Success {
+        message: format!("{} top-level tags", tree.len()),
+        data: Some(serde_json::json!({ "tags": tree })),
+    }
+}
+
+/// Counts are kept across messages: while bookmarks.json is unchanged, a
+/// repeat request only hashes the file instead of loading and counting it
+fn handle_tag_counts(config: &mut HostConfig) -> Response {
+    info!("Counting bookmarks per tag");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let digest = bookmarks_digest(&repo_path);
+    let counts = match cached_tag_counts(config, digest.as_ref()) {
+        Some(counts) => counts,
+        None => match load_bookmarks(config) {
+            Ok(data) => remember_tag_counts(config, digest, &data),
+            Err(response) => return response,
+        },
+    };
+
+    Response::Success {
+        message: format!("Counted bookmarks for {} tags", counts.len()),
+        data: Some(serde_json::json!({ "counts": counts })),
+    }
+}
+
+/// SHA-256 of bookmarks.json as it is on disk, or `None` if it can't be read
+fn bookmarks_digest(repo_path: &Path) -> Option<Vec<u8>> {
+    std::fs::read(repo_path.join("bookmarks.json"))
+        .ok()
+        .map(|content| Sha256::digest(content).to_vec())
+}
+
+/// Counts kept from an earlier request, if bookmarks.json hasn't changed since
+fn cached_tag_counts(
+    config: &HostConfig,
+    digest: Option<&Vec<u8>>,
+) -> Option<HashMap<String, storage::TagCounts>> {
+    config
+        .tag_counts
+        .as_ref()
+        .filter(|cached| Some(&cached.digest) == digest)
+        .map(|cached| cached.counts.clone())
+}
+
+/// Count `bookmarks_data`, which is the file with `digest`, and keep the
+/// counts for later requests
+fn remember_tag_counts(
+    config: &mut HostConfig,
+    digest: Option<Vec<u8>>,
+    bookmarks_data: &storage::BookmarksData,
+) -> HashMap<String, storage::TagCounts> {
+    let counts = bookmarks_data.tag_counts().clone();
+    config.tag_counts = digest.map(|digest| CachedTagCounts {
+        digest,
+        counts: counts.clone(),
+    });
+    counts
+}
+
+fn handle_list_pinned(config: &HostConfig) -> Response {
+    info!("Listing pinned bookmarks");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let pinned = bookmarks_data.get_pinned_bookmarks();
+    Response::Success {
+        message: format!("{} pinned bookmarks", pinned.len()),
+        data: Some(serde_json::json!({ "bookmarks": pinned })),
+    }
+}
+
+fn handle_reorder_pinned(config: &mut HostConfig, ids: &[String]) -> Response {
+    info!("Reordering {} pinned bookmarks", ids.len());
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match bookmarks_data.reorder_pinned(ids) {
+        Ok(true) => save_bookmarks(config, &repo_path, &bookmarks_data),
+        Ok(false) => Response::Success {
+            message: "Pinned bookmarks were already in that order".to_string(),
+            data: None,
+        },
+        Err(e) => Response::error(HostError::Validate, e.to_string()),
+    }
+}
+
+fn handle_create_saved_search(
+    config: &mut HostConfig,
+    name: String,
+    query: String,
+    sort: storage::SortOrder,
+) -> Response {
+    info!("Creating saved search");
+
+    if let Err(e) = query::Query::parse(&query) {
+        return Response::error(HostError::Validate, format!("Invalid query: {e}"));
+    }
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let search = storage::create_saved_search(name, query, sort);
+    let id = search.id().to_string();
+    if let Err(e) = bookmarks_data
+        .add_saved_search(search)
+        .and_then(|()| bookmarks_data.validate_with(&config.validation))
+    {
+        return Response::error(HostError::Validate, format!("Invalid saved search: {e}"));
+    }
+
+    with_data(
+        save_bookmarks(config, &repo_path, &bookmarks_data),
+        "id",
+        serde_json::json!(id),
+    )
+}
+
+fn handle_list_saved_searches(config: &HostConfig) -> Response {
+    info!("Listing saved searches");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let searches = bookmarks_data.get_saved_searches();
+    Response::Success {
+        message: format!("{} saved searches", searches.len()),
+        data: Some(serde_json::json!({ "searches": searches })),
+    }
+}
+
+fn handle_run_saved_search(config: &HostConfig, id: &str) -> Response {
+    info!("Running saved search {id}");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let Some(storage::Resource::SavedSearch { attributes, .. }) = bookmarks_data.get(id) else {
+        return Response::error(HostError::Validate, format!("No saved search with ID {id}"));
+    };
+    let query = match query::Query::parse(&attributes.query) {
+        Ok(query) => query,
+        Err(e) => return Response::error(HostError::Validate, format!("Invalid query: {e}")),
+    };
+
+    let bookmarks = query.run(&bookmarks_data, attributes.sort);
+    Response::Success {
+        message: format!("{} bookmarks match '{}'", bookmarks.len(), attributes.name),
+        data: Some(serde_json::json!({ "bookmarks": bookmarks })),
+    }
+}
+
+fn handle_apply_rules(config: &mut HostConfig) -> Response {
+    info!("Applying rules to all bookmarks");
+
+    let repo_path = match config.get_repo_path() {
         Ok(path) => path,
-        Err(e) => {
-            return Response::Error {
-                message: e.to_string(),
-                code: Some("ERR_NOT_INITIALIZED".to_string()),
-            }
-        }
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
     };
 
-    // Parse bookmarks data
-    let bookmarks_data: storage::BookmarksData = match serde_json::from_value(data) {
+    let mut bookmarks_data = match load_bookmarks(config) {
         Ok(data) => data,
-        Err(e) => {
-            return Response::Error {
-                message: format!("Failed to parse bookmarks data: {e}"),
-                code: Some("ERR_PARSE".to_string()),
-            }
-        }
+        Err(response) => return response,
     };
 
-    // Validate data
-    if let Err(e) = bookmarks_data.validate() {
-        return Response::Error {
-            message: format!("Invalid bookmarks data: {e}"),
-            code: Some("ERR_VALIDATE".to_string()),
+    let tagged = match rules::apply(&mut bookmarks_data, |_| true) {
+        Ok(tagged) => tagged,
+        Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+    };
+    if tagged.is_empty() {
+        return Response::Success {
+            message: "No bookmarks needed tagging".to_string(),
+            data: Some(serde_json::json!({ "tagged": tagged })),
         };
     }
 
-    // Write to file (with encryption support)
-    let bookmarks_file = repo_path.join("bookmarks.json");
-    if let Err(e) = storage::write_to_file_with_encryption(
-        &bookmarks_file,
-        &bookmarks_data,
-        config.encryption_enabled,
-    ) {
-        return Response::Error {
-            message: format!("Failed to write bookmarks file: {e}"),
-            code: Some("ERR_WRITE_FILE".to_string()),
-        };
-    }
+    with_data(
+        save_bookmarks(config, &repo_path, &bookmarks_data),
+        "tagged",
+        serde_json::json!(tagged),
+    )
+}
 
-    // Git operations
-    let repo = match git::GitRepo::init(&repo_path) {
-        Ok(repo) => repo,
+fn handle_import(
+    config: &mut HostConfig,
+    format: import::ImportFormat,
+    content: &str,
+    dry_run: bool,
+    options: &import::ImportOptions,
+) -> Response {
+    info!("Importing bookmarks ({format:?}, dry run: {dry_run})");
+
+    let items = match import::parse(format, content) {
+        Ok(items) => items,
         Err(e) => {
-            return Response::Error {
-                message: format!("Failed to open repository: {e}"),
-                code: Some("ERR_OPEN_REPO".to_string()),
-            }
+            return Response::error(
+                HostError::Parse,
+                format!("Failed to read import file: {e:#}"),
+            )
         }
     };
+    import_items(config, items, dry_run, options)
+}
 
-    // Add and commit
-    if let Err(e) = repo.add_file("bookmarks.json") {
-        return Response::Error {
-            message: format!("Failed to stage file: {e}"),
-            code: Some("ERR_GIT_ADD".to_string()),
-        };
-    }
-
-    let commit_message = format!(
-        "Update bookmarks: {} bookmarks, {} tags",
-        bookmarks_data.get_bookmarks().len(),
-        bookmarks_data.get_tags().len()
-    );
+/// Merge imported items into the bookmarks and commit them, or with
+/// `dry_run` only report what would change
+fn import_items(
+    config: &mut HostConfig,
+    items: Vec<import::ImportedItem>,
+    dry_run: bool,
+    options: &import::ImportOptions,
+) -> Response {
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
 
-    if let Err(e) = repo.commit(&commit_message) {
-        return Response::Error {
-            message: format!("Failed to commit: {e}"),
-            code: Some("ERR_GIT_COMMIT".to_string()),
-        };
-    }
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
 
-    // Push to remote (if configured)
-    if repo.has_remote("origin") {
-        if let Err(e) = repo.push("origin", "main") {
-            return Response::Error {
-                message: format!("Failed to push: {e}"),
-                code: Some("ERR_GIT_PUSH".to_string()),
+    let options = import::ImportOptions {
+        limits: config.validation.clone(),
+        ..options.clone()
+    };
+    let before = bookmarks_data.clone();
+    let send_progress = config.has_capability(messaging::Capability::Progress);
+    let report = import::import(&mut bookmarks_data, items, &options, |done, total| {
+        if send_progress {
+            let progress = Response::Progress {
+                operation: "import".to_string(),
+                done,
+                total,
             };
+            if let Err(e) = messaging::write_response(stdout(), &progress) {
+                warn!("Failed to send import progress: {e}");
+            }
         }
+    });
+    let report_value = serde_json::json!(report);
+    if dry_run || report.created + report.merged == 0 {
+        return Response::Success {
+            message: if dry_run {
+                format!(
+                    "Would create {} and merge {} bookmarks",
+                    report.created, report.merged
+                )
+            } else {
+                "Nothing new to import".to_string()
+            },
+            data: Some(serde_json::json!({ "report": report_value })),
+        };
     }
 
-    Response::Success {
-        message: "Bookmarks saved and synced".to_string(),
-        data: None,
+    let rule_tagged = apply_rules_to_new(Some(&before), &mut bookmarks_data);
+    if let Some(author) = author_for(&repo_path) {
+        bookmarks_data.stamp_author(&author, Some(&before));
+    }
+    let response = with_data(
+        save_bookmarks(config, &repo_path, &bookmarks_data),
+        "report",
+        report_value,
+    );
+    if rule_tagged.is_empty() {
+        return response;
     }
+    with_data(response, "rule_tagged", serde_json::json!(rule_tagged))
 }
 
-async fn handle_read(config: &mut HostConfig) -> Response {
-    info!("Reading bookmarks data");
+fn handle_export_site(config: &mut HostConfig) -> Response {
+    info!("Exporting static site");
+
+    // The site is public; with whole-file encryption nothing in the repo is
+    if config.active_encryption() == Some(encryption::EncryptionMode::Full) {
+        return Response::error(
+            HostError::Validate,
+            "The bookmarks are encrypted; a static site would publish them in plaintext",
+        );
+    }
 
     let repo_path = match config.get_repo_path() {
         Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    // Batched writes are committed first so the site matches what's pushed
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let pages = match site::write(&repo_path, &site::render(&bookmarks_data)) {
+        Ok(pages) => pages,
+        Err(e) => return Response::error(HostError::WriteFile, format!("{e:#}")),
+    };
+
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
         Err(e) => {
-            return Response::Error {
-                message: e.to_string(),
-                code: Some("ERR_NOT_INITIALIZED".to_string()),
-            }
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
         }
     };
+    if let Some(prompt) = ssh_passphrase_prompt(&remote_urls(&repo)) {
+        return prompt;
+    }
+    if let Err(e) = repo.add_dir(site::SITE_DIR) {
+        return Response::error(HostError::GitAdd, format!("Failed to stage site: {e}"));
+    }
+    match repo.has_staged_changes() {
+        Ok(false) => {
+            return Response::Success {
+                message: "Site is up to date".to_string(),
+                data: Some(serde_json::json!({ "pages": pages, "path": site::SITE_DIR })),
+            }
+        }
+        Ok(true) => {}
+        Err(e) => return Response::error(HostError::GitAdd, format!("{e:#}")),
+    }
 
-    let bookmarks_file = repo_path.join("bookmarks.json");
+    let response = commit_and_push_with(&repo, &repo_path, "Update static site");
+    let response = with_data(response, "pages", serde_json::json!(pages));
+    with_data(response, "path", serde_json::json!(site::SITE_DIR))
+}
 
-    // Check if file exists
-    if !bookmarks_file.exists() {
-        // Return empty bookmarks data
-        let empty_data = storage::BookmarksData::new();
-        let data_value = match serde_json::to_value(empty_data) {
-            Ok(v) => v,
-            Err(e) => {
-                return Response::Error {
-                    message: format!("Failed to serialize empty data: {e}"),
-                    code: Some("ERR_SERIALIZE".to_string()),
-                }
-            }
-        };
-        return Response::Success {
-            message: "No bookmarks file found, returning empty data".to_string(),
-            data: Some(data_value),
+fn handle_publish_public(config: &mut HostConfig, path: Option<&str>) -> Response {
+    info!("Publishing public bookmarks");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let public = bookmarks_data.public_subset();
+    let count = public.get_bookmarks().len();
+    let write = |path: &Path| {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        storage::write_to_file(path, &public)
+    };
+
+    if let Some(path) = path {
+        let path = Path::new(path);
+        if !path.is_absolute() {
+            return Response::error(HostError::Validate, "Publish path must be absolute");
+        }
+        return match write(path) {
+            Ok(()) => Response::Success {
+                message: format!("Published {count} bookmarks"),
+                data: Some(serde_json::json!({ "bookmarks": count, "path": path })),
+            },
+            Err(e) => Response::error(HostError::WriteFile, format!("{e:#}")),
         };
     }
 
-    // Read from file (with encryption support)
-    let bookmarks_data =
-        match storage::read_from_file_with_encryption(&bookmarks_file, config.encryption_enabled) {
-            Ok(data) => data,
-            Err(e) => {
-                return Response::Error {
-                    message: format!("Failed to read bookmarks file: {e}"),
-                    code: Some("ERR_READ_FILE".to_string()),
-                }
-            }
-        };
+    // A plaintext file in an encrypted repo would be pushed alongside it
+    if config.active_encryption() == Some(encryption::EncryptionMode::Full) {
+        return Response::error(
+            HostError::Validate,
+            "The bookmarks are encrypted; publish to a path outside the repository",
+        );
+    }
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+    if let Err(e) = write(&repo_path.join(site::PUBLIC_FILE)) {
+        return Response::error(HostError::WriteFile, format!("{e:#}"));
+    }
 
-    let data_value = match serde_json::to_value(bookmarks_data) {
-        Ok(v) => v,
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
         Err(e) => {
-            return Response::Error {
-                message: format!("Failed to serialize bookmarks data: {e}"),
-                code: Some("ERR_SERIALIZE".to_string()),
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+    if let Some(prompt) = ssh_passphrase_prompt(&remote_urls(&repo)) {
+        return prompt;
+    }
+    if let Err(e) = repo.add_file(site::PUBLIC_FILE) {
+        return Response::error(
+            HostError::GitAdd,
+            format!("Failed to stage public bookmarks: {e}"),
+        );
+    }
+    match repo.has_staged_changes() {
+        Ok(false) => {
+            return Response::Success {
+                message: "Public bookmarks are up to date".to_string(),
+                data: Some(serde_json::json!({ "bookmarks": count, "path": site::PUBLIC_FILE })),
             }
         }
+        Ok(true) => {}
+        Err(e) => return Response::error(HostError::GitAdd, format!("{e:#}")),
+    }
+
+    let response = commit_and_push_with(&repo, &repo_path, "Publish public bookmarks");
+    let response = with_data(response, "bookmarks", serde_json::json!(count));
+    with_data(response, "path", serde_json::json!(site::PUBLIC_FILE))
+}
+
+/// Client for the LFS server of `repo`: its `lfs.url` if set, else the one
+/// derived from the origin URL
+fn lfs_client(repo: &git::GitRepo) -> Result<lfs::LfsClient> {
+    let endpoint = if let Some(url) = repo.local_config("lfs.url") {
+        url
+    } else {
+        let url = repo
+            .remote_url(git::PRIMARY_REMOTE)
+            .context("No remote configured")?;
+        git_url::lfs_endpoint(&url)?
     };
+    Ok(lfs::LfsClient::new(&endpoint, github::get_token().ok()))
+}
 
-    Response::Success {
-        message: "Bookmarks loaded".to_string(),
-        data: Some(data_value),
+/// Send the LFS objects of this device's archives that the server lacks, so
+/// pushed pointers never name content other devices can't fetch
+async fn upload_archives(repo: &git::GitRepo, repo_path: &Path) -> Result<usize> {
+    let pointers = lfs::pointers(repo_path)?;
+    let store = lfs::ObjectStore::for_git_dir(repo.git_dir());
+    if !pointers.iter().any(|pointer| store.contains(pointer)) {
+        return Ok(0);
     }
+    lfs_client(repo)?.upload(&store, &pointers).await
 }
 
-async fn handle_sync(config: &mut HostConfig) -> Response {
-    info!("Syncing with remote");
+async fn handle_store_archive(config: &mut HostConfig, id: &str, content: &str) -> Response {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    info!("Archiving page of bookmark {id}");
 
+    // LFS objects sit outside the encrypted bookmarks file
+    if config.active_encryption().is_some() {
+        return Response::error(
+            HostError::Validate,
+            "Pages can't be archived while the bookmarks are encrypted",
+        );
+    }
+    let content = match BASE64.decode(content) {
+        Ok(content) => content,
+        Err(e) => return Response::error(HostError::Validate, format!("Invalid archive: {e}")),
+    };
+    if let Some(response @ Response::Error { .. }) = flush_batch(config) {
+        return response;
+    }
     let repo_path = match config.get_repo_path() {
         Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
+    let repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
         Err(e) => {
-            return Response::Error {
-                message: e.to_string(),
-                code: Some("ERR_NOT_INITIALIZED".to_string()),
-            }
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
         }
     };
+    if let Some(prompt) = ssh_passphrase_prompt(&remote_urls(&repo)) {
+        return prompt;
+    }
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    if !bookmarks_data.get_bookmarks().iter().any(|b| b.id() == id) {
+        return Response::error(HostError::Validate, format!("No bookmark with ID {id}"));
+    }
+
+    let store = lfs::ObjectStore::for_git_dir(repo.git_dir());
+    let paths = match lfs::store_archive(&repo_path, &store, id, &content) {
+        Ok(paths) => paths,
+        Err(e) => return Response::error(HostError::WriteFile, format!("{e:#}")),
+    };
+    for path in &paths {
+        if let Err(e) = repo.add_file(path) {
+            return Response::error(HostError::GitAdd, format!("Failed to stage {path}: {e}"));
+        }
+    }
+    match repo.has_staged_changes() {
+        Ok(false) => {
+            return Response::Success {
+                message: "Page is already archived".to_string(),
+                data: Some(serde_json::json!({ "path": paths[0] })),
+            }
+        }
+        Ok(true) => {}
+        Err(e) => return Response::error(HostError::GitAdd, format!("{e:#}")),
+    }
+
+    // Offline is fine: the next sync uploads it before pushing
+    if repo.has_remote(git::PRIMARY_REMOTE) {
+        if let Err(e) = upload_archives(&repo, &repo_path).await {
+            warn!("Failed to upload archive, will retry on sync: {e:#}");
+        }
+    }
+    let response = commit_and_push_with(&repo, &repo_path, &format!("Archive page of {id}"));
+    with_data(response, "path", serde_json::json!(paths[0]))
+}
+
+async fn handle_get_archive(config: &HostConfig, id: &str) -> Response {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let pointer = match lfs::read_pointer(&repo_path, id) {
+        Ok(Some(pointer)) => pointer,
+        Ok(None) => {
+            return Response::error(HostError::Validate, format!("No archive for bookmark {id}"))
+        }
+        Err(e) => return Response::error(HostError::ReadFile, format!("{e:#}")),
+    };
     let repo = match git::GitRepo::init(&repo_path) {
         Ok(repo) => repo,
         Err(e) => {
-            return Response::Error {
-                message: format!("Failed to open repository: {e}"),
-                code: Some("ERR_OPEN_REPO".to_string()),
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+    };
+
+    let store = lfs::ObjectStore::for_git_dir(repo.git_dir());
+    let content = match store.get(&pointer) {
+        Ok(Some(content)) => content,
+        Ok(None) => {
+            let downloaded = match lfs_client(&repo) {
+                Ok(client) => client.download(&store, &pointer).await,
+                Err(e) => Err(e),
+            };
+            match downloaded {
+                Ok(content) => content,
+                Err(e) => return Response::error(HostError::Remote, format!("{e:#}")),
             }
         }
+        Err(e) => return Response::error(HostError::ReadFile, format!("{e:#}")),
     };
+    Response::Success {
+        message: format!("Archive of {id}"),
+        data: Some(serde_json::json!({
+            "id": id,
+            "size": pointer.size,
+            "content": BASE64.encode(content),
+        })),
+    }
+}
 
-    if !repo.has_remote("origin") {
-        return Response::Error {
-            message: "No remote configured".to_string(),
-            code: Some("ERR_NO_REMOTE".to_string()),
-        };
+fn handle_export_markdown(config: &HostConfig, path: &Path, include_private: bool) -> Response {
+    info!("Exporting Markdown notes");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let notes = markdown::render(&bookmarks_data, include_private);
+    match markdown::write(path, &notes) {
+        Ok(written) => Response::Success {
+            message: format!("Exported {written} notes"),
+            data: Some(serde_json::json!({ "notes": written, "path": path })),
+        },
+        Err(e) => Response::error(HostError::WriteFile, format!("{e:#}")),
     }
+}
+
+fn handle_review_suggested_tags(
+    config: &mut HostConfig,
+    id: &str,
+    accept: &[String],
+    dismiss: &[String],
+) -> Response {
+    info!("Reviewing suggested tags of bookmark {id}");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+    let _lock = match lock_repo(&repo_path) {
+        Ok(lock) => lock,
+        Err(response) => return response,
+    };
 
-    // Pull from remote
-    if let Err(e) = repo.pull("origin", "main") {
-        return Response::Error {
-            message: format!("Failed to pull: {e}"),
-            code: Some("ERR_GIT_PULL".to_string()),
+    let mut bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let review = match suggest::review(&mut bookmarks_data, id, accept, dismiss, &config.validation)
+    {
+        Ok(review) => review,
+        Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+    };
+    if review == suggest::Review::default() {
+        return Response::Success {
+            message: "No suggested tags to review".to_string(),
+            data: None,
         };
     }
+    with_data(
+        save_bookmarks(config, &repo_path, &bookmarks_data),
+        "review",
+        serde_json::json!(review),
+    )
+}
+
+fn handle_suggest_tags(
+    config: &HostConfig,
+    url: &str,
+    title: &str,
+    limit: Option<usize>,
+) -> Response {
+    info!("Suggesting tags");
+
+    let bookmarks_data = match load_bookmarks(config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let limit = limit
+        .unwrap_or(suggest::DEFAULT_SUGGESTIONS)
+        .min(suggest::MAX_SUGGESTIONS);
+    let suggestions = suggest::suggest_tags(&bookmarks_data, url, title, limit);
+    Response::Success {
+        message: format!("{} tag suggestions", suggestions.len()),
+        data: Some(serde_json::json!({ "suggestions": suggestions })),
+    }
+}
+
+fn handle_provide_secret(secret: messaging::SecretKind, value: &str) -> Response {
+    info!("Received secret {secret:?}");
+
+    match secret {
+        messaging::SecretKind::SshPassphrase => {
+            if let Err(e) = ssh::set_passphrase(value) {
+                return Response::error(HostError::Validate, e.to_string());
+            }
+        }
+    }
 
     Response::Success {
-        message: "Synced with remote".to_string(),
+        message: "Secret stored for this session".to_string(),
         data: None,
     }
 }
 
-async fn handle_auth(method: messaging::AuthMethod, token: Option<String>) -> Response {
-    info!("Handling authentication: {method:?}");
+fn handle_add_remote(config: &HostConfig, name: &str, url: &str) -> Response {
+    info!("Adding remote {name}");
 
-    match method {
-        messaging::AuthMethod::OAuth => {
-            // Start OAuth device flow
-            let client = github::GitHubClient::new();
+    if !git2::Remote::is_valid_name(name) {
+        return Response::error(HostError::Validate, format!("Invalid remote name: {name}"));
+    }
+    if let Err(e) = git_url::parse_git_url(url) {
+        return Response::error(HostError::Validate, e.to_string());
+    }
 
-            let device_code_response = match client.start_device_flow().await {
-                Ok(response) => response,
-                Err(e) => {
-                    return Response::Error {
-                        message: format!("Failed to start OAuth flow: {e}"),
-                        code: Some("ERR_OAUTH_START".to_string()),
-                    }
-                }
-            };
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
 
-            // Return device code info to extension (which will show to user)
-            Response::AuthFlow {
-                user_code: device_code_response.user_code,
-                verification_uri: device_code_response.verification_uri,
-                device_code: device_code_response.device_code,
-            }
+    let mut repo = match git::GitRepo::init(&repo_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
         }
-        messaging::AuthMethod::PAT => {
-            // Store provided PAT
-            let Some(token) = token else {
-                return Response::Error {
-                    message: "No token provided".to_string(),
-                    code: Some("ERR_NO_TOKEN".to_string()),
-                };
-            };
+    };
 
-            // Validate token
-            let client = github::GitHubClient::new();
-            match client.validate_token(&token).await {
-                Ok(true) => {
-                    // Store in keychain
-                    if let Err(e) = github::store_token(&token) {
-                        return Response::Error {
-                            message: format!("Failed to store token: {e}"),
-                            code: Some("ERR_STORE_TOKEN".to_string()),
-                        };
-                    }
+    if repo.has_remote(name) {
+        return Response::error(HostError::Remote, format!("Remote {name} already exists"));
+    }
+    if let Err(e) = repo.add_remote(name, url) {
+        return Response::error(HostError::Remote, format!("{e:#}"));
+    }
 
-                    Response::Success {
-                        message: "Token validated and stored".to_string(),
-                        data: None,
-                    }
-                }
-                Ok(false) => Response::Error {
-                    message: "Invalid token".to_string(),
-                    code: Some("ERR_INVALID_TOKEN".to_string()),
-                },
-                Err(e) => Response::Error {
-                    message: format!("Failed to validate token: {e}"),
-                    code: Some("ERR_VALIDATE_TOKEN".to_string()),
-                },
-            }
-        }
+    let remotes = repo.remotes().unwrap_or_default();
+    Response::Success {
+        message: format!("Added remote {name}"),
+        data: Some(serde_json::json!({ "remotes": remotes })),
     }
 }
 
-async fn handle_status(config: &HostConfig) -> Response {
-    info!("Getting status");
+fn handle_history(config: &HostConfig, limit: Option<usize>, device: Option<&str>) -> Response {
+    let limit = limit.unwrap_or(git::DEFAULT_HISTORY_LIMIT);
+    info!("Listing up to {limit} commits");
 
-    let Some(repo_path) = config.repo_path.as_ref() else {
-        return Response::Success {
-            message: "Not initialized".to_string(),
-            data: Some(serde_json::json!({
-                "initialized": false,
-            })),
-        };
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
     };
 
-    let repo = match git::GitRepo::init(repo_path) {
+    let repo = match git::GitRepo::init(&repo_path) {
         Ok(repo) => repo,
         Err(e) => {
-            return Response::Error {
-                message: format!("Failed to open repository: {e}"),
-                code: Some("ERR_OPEN_REPO".to_string()),
-            }
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
         }
     };
 
-    let is_clean = repo.is_clean().unwrap_or(false);
-    let has_remote = repo.has_remote("origin");
-
-    let last_commit = repo.get_last_commit_message().ok();
+    // Filtering by device has to look past the first `limit` commits
+    let history = match device {
+        Some(device) => repo.history(usize::MAX).map(|commits| {
+            commits
+                .into_iter()
+                .filter(|commit| commit.device.as_deref() == Some(device))
+                .take(limit)
+                .collect()
+        }),
+        None => repo.history(limit),
+    };
+    let commits = match history {
+        Ok(commits) => commits,
+        Err(e) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to read history: {e:#}"),
+            )
+        }
+    };
 
     Response::Success {
-        message: "Status retrieved".to_string(),
+        message: format!("{} commits", commits.len()),
         data: Some(serde_json::json!({
-            "initialized": true,
-            "repo_path": repo_path,
-            "is_clean": is_clean,
-            "has_remote": has_remote,
-            "last_commit": last_commit,
-            "encryption_enabled": config.encryption_enabled,
+            "device": repo.device_name(),
+            "commits": commits,
         })),
     }
 }
 
 #[cfg_attr(not(target_os = "macos"), allow(unused_variables))]
-async fn handle_enable_encryption(config: &mut HostConfig) -> Response {
-    info!("Enabling encryption");
+async fn handle_enable_encryption(
+    config: &mut HostConfig,
+    mode: encryption::EncryptionMode,
+    cipher: encryption::Cipher,
+) -> Response {
+    info!("Enabling encryption ({mode:?}, {})", cipher.name());
 
     #[cfg(not(target_os = "macos"))]
     {
-        Response::Error {
-            message: "Encryption with biometric authentication is only supported on macOS"
-                .to_string(),
-            code: Some("ERR_PLATFORM_NOT_SUPPORTED".to_string()),
-        }
+        Response::error(
+            HostError::PlatformNotSupported,
+            "Encryption with biometric authentication is only supported on macOS",
+        )
     }
 
     #[cfg(target_os = "macos")]
     {
         use encryption::EncryptionManager;
 
-        // Generate and store encryption key
-        if let Err(e) = EncryptionManager::generate_and_store_key() {
-            return Response::Error {
-                message: format!("Failed to generate encryption key: {e}"),
-                code: Some("ERR_KEYGEN".to_string()),
-            };
-        }
-
         // Get repo path
         let repo_path = match config.get_repo_path() {
             Ok(path) => path,
-            Err(e) => {
-                return Response::Error {
-                    message: e.to_string(),
-                    code: Some("ERR_NOT_INITIALIZED".to_string()),
-                }
-            }
+            Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+        };
+        let _lock = match lock_repo(&repo_path) {
+            Ok(lock) => lock,
+            Err(response) => return response,
         };
 
         let bookmarks_file = repo_path.join("bookmarks.json");
 
-        // If bookmarks file exists and is not encrypted, encrypt it
-        if bookmarks_file.exists() {
-            match encryption::is_encrypted(&bookmarks_file) {
-                Ok(true) => {
-                    // Already encrypted
-                    info!("Bookmarks file is already encrypted");
-                }
-                Ok(false) => {
-                    // Read plain bookmarks
-                    let bookmarks_data = match storage::read_from_file(&bookmarks_file) {
-                        Ok(data) => data,
-                        Err(e) => {
-                            return Response::Error {
-                                message: format!("Failed to read bookmarks for encryption: {e}"),
-                                code: Some("ERR_READ_FOR_ENCRYPT".to_string()),
-                            };
-                        }
-                    };
-
-                    // Write encrypted version
-                    if let Err(e) = storage::write_to_file_with_encryption(
-                        &bookmarks_file,
-                        &bookmarks_data,
-                        true,
-                    ) {
-                        return Response::Error {
-                            message: format!("Failed to encrypt bookmarks: {e}"),
-                            code: Some("ERR_ENCRYPT".to_string()),
-                        };
-                    }
+        let current = match storage::detect_encryption(&bookmarks_file) {
+            Ok(current) => current,
+            Err(e) => {
+                return Response::error(
+                    HostError::CheckEncryption,
+                    format!("Failed to check encryption status: {e}"),
+                );
+            }
+        };
 
-                    info!("Bookmarks file encrypted successfully");
-                }
+        // Read existing bookmarks before touching the key; an already
+        // encrypted file needs the current key to decrypt. It is rewritten
+        // even if already encrypted, in case the mode or cipher changed.
+        let existing = if bookmarks_file.exists() {
+            match storage::read_from_file_with_encryption(&bookmarks_file, current.is_some()) {
+                Ok(data) => Some(data),
                 Err(e) => {
-                    return Response::Error {
-                        message: format!("Failed to check encryption status: {e}"),
-                        code: Some("ERR_CHECK_ENCRYPTION".to_string()),
-                    };
+                    return Response::error(
+                        HostError::ReadForEncrypt,
+                        format!("Failed to read bookmarks for encryption: {e}"),
+                    );
                 }
             }
+        } else {
+            None
+        };
+
+        // Keep the stored key if it already encrypts this repository
+        if current.is_none() {
+            if let Err(e) = EncryptionManager::generate_and_store_key() {
+                return Response::error(
+                    HostError::Keygen,
+                    format!("Failed to generate encryption key: {e}"),
+                );
+            }
+        }
+
+        // Recovery code lets the user decrypt if the Keychain item is ever lost
+        let recovery_code = match recovery::create_for_stored_key(&repo_path) {
+            Ok(code) => code,
+            Err(e) => {
+                return Response::error(
+                    HostError::Recovery,
+                    format!("Failed to create recovery code: {e}"),
+                );
+            }
+        };
+
+        if let Some(bookmarks_data) = existing {
+            if let Err(e) = storage::write_to_file_with_mode(
+                &bookmarks_file,
+                &bookmarks_data,
+                Some(mode),
+                cipher,
+            ) {
+                return Response::error(
+                    HostError::Encrypt,
+                    format!("Failed to encrypt bookmarks: {e}"),
+                );
+            }
+
+            info!("Bookmarks file encrypted successfully");
         }
 
         // Enable encryption in config
         config.encryption_enabled = true;
+        config.encryption_mode = mode;
+        config.cipher = cipher;
 
         Response::Success {
             message: "Encryption enabled. Your bookmarks are now encrypted with Touch ID."
                 .to_string(),
+            // Only time the recovery code is ever returned
             data: Some(serde_json::json!({
                 "encryption_enabled": true,
+                "encryption_mode": mode,
+                "cipher": cipher,
+                "recovery_code": recovery_code,
             })),
         }
     }
@@ -613,51 +5078,50 @@ async fn handle_disable_encryption(config: &mut HostConfig) -> Response {
         // Get repo path
         let repo_path = match config.get_repo_path() {
             Ok(path) => path,
-            Err(e) => {
-                return Response::Error {
-                    message: e.to_string(),
-                    code: Some("ERR_NOT_INITIALIZED".to_string()),
-                }
-            }
+            Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+        };
+        let _lock = match lock_repo(&repo_path) {
+            Ok(lock) => lock,
+            Err(response) => return response,
         };
 
         let bookmarks_file = repo_path.join("bookmarks.json");
 
         // If bookmarks file exists and is encrypted, decrypt it
         if bookmarks_file.exists() {
-            match encryption::is_encrypted(&bookmarks_file) {
-                Ok(true) => {
+            match storage::detect_encryption(&bookmarks_file) {
+                Ok(Some(_)) => {
                     // Read encrypted bookmarks
                     let bookmarks_data =
                         match storage::read_from_file_with_encryption(&bookmarks_file, true) {
                             Ok(data) => data,
                             Err(e) => {
-                                return Response::Error {
-                                    message: format!("Failed to decrypt bookmarks: {e}"),
-                                    code: Some("ERR_DECRYPT".to_string()),
-                                };
+                                return Response::error(
+                                    HostError::Decrypt,
+                                    format!("Failed to decrypt bookmarks: {e}"),
+                                );
                             }
                         };
 
                     // Write plain text version
                     if let Err(e) = storage::write_to_file(&bookmarks_file, &bookmarks_data) {
-                        return Response::Error {
-                            message: format!("Failed to write decrypted bookmarks: {e}"),
-                            code: Some("ERR_WRITE_DECRYPT".to_string()),
-                        };
+                        return Response::error(
+                            HostError::WriteDecrypt,
+                            format!("Failed to write decrypted bookmarks: {e}"),
+                        );
                     }
 
                     info!("Bookmarks file decrypted successfully");
                 }
-                Ok(false) => {
+                Ok(None) => {
                     // Already plain text
                     info!("Bookmarks file is already in plain text");
                 }
                 Err(e) => {
-                    return Response::Error {
-                        message: format!("Failed to check encryption status: {e}"),
-                        code: Some("ERR_CHECK_ENCRYPTION".to_string()),
-                    };
+                    return Response::error(
+                        HostError::CheckEncryption,
+                        format!("Failed to check encryption status: {e}"),
+                    );
                 }
             }
         }
@@ -668,6 +5132,12 @@ async fn handle_disable_encryption(config: &mut HostConfig) -> Response {
             // Don't fail the operation, just log
         }
 
+        // The recovery file wraps the deleted key; its removal is committed with the
+        // plain text bookmarks on the next write
+        if let Err(e) = recovery::remove_recovery_file(&repo_path) {
+            log::warn!("Failed to remove recovery file: {e}");
+        }
+
         // Disable encryption in config
         config.encryption_enabled = false;
 
@@ -680,6 +5150,422 @@ async fn handle_disable_encryption(config: &mut HostConfig) -> Response {
     }
 }
 
+async fn handle_export_key(passphrase: &str) -> Response {
+    info!("Exporting encryption key");
+
+    match encryption::EncryptionManager::export_key(passphrase) {
+        Ok(blob) => Response::Success {
+            message: "Encryption key exported".to_string(),
+            data: Some(serde_json::json!({ "blob": blob })),
+        },
+        Err(e) => Response::error(
+            HostError::KeyExport,
+            format!("Failed to export encryption key: {e}"),
+        ),
+    }
+}
+
+async fn handle_import_key(config: &mut HostConfig, passphrase: &str, blob: &str) -> Response {
+    info!("Importing encryption key");
+
+    if let Err(e) = encryption::EncryptionManager::import_key(passphrase, blob) {
+        return Response::error(
+            HostError::KeyImport,
+            format!("Failed to import encryption key: {e}"),
+        );
+    }
+
+    // The imported key is the one that encrypted this repo on the other device;
+    // keep whatever state and mode the repo is actually in
+    if let Err(e) = config.adopt_repo_encryption() {
+        return Response::error(
+            HostError::CheckEncryption,
+            format!("Failed to check encryption status: {e}"),
+        );
+    }
+
+    Response::Success {
+        message: "Encryption key imported".to_string(),
+        data: Some(serde_json::json!({
+            "encryption_enabled": config.encryption_enabled,
+            "encryption_mode": config.active_encryption(),
+        })),
+    }
+}
+
+async fn handle_verify_recovery_code(
+    config: &mut HostConfig,
+    code: &str,
+    restore: bool,
+) -> Response {
+    info!("Verifying recovery code");
+
+    let repo_path = match config.get_repo_path() {
+        Ok(path) => path,
+        Err(e) => return Response::error(HostError::NotInitialized, e.to_string()),
+    };
+
+    let valid = match recovery::verify_recovery_code(&repo_path, code) {
+        Ok(valid) => valid,
+        Err(e) => {
+            return Response::error(
+                HostError::Recovery,
+                format!("Failed to verify recovery code: {e}"),
+            )
+        }
+    };
+
+    if valid && restore {
+        if let Err(e) = recovery::restore_key(&repo_path, code) {
+            return Response::error(
+                HostError::Recovery,
+                format!("Failed to restore encryption key: {e}"),
+            );
+        }
+        if let Err(e) = config.adopt_repo_encryption() {
+            return Response::error(
+                HostError::CheckEncryption,
+                format!("Failed to check encryption status: {e}"),
+            );
+        }
+    }
+
+    Response::Success {
+        message: if valid {
+            "Recovery code is valid".to_string()
+        } else {
+            "Recovery code is incorrect".to_string()
+        },
+        data: Some(serde_json::json!({
+            "valid": valid,
+            "restored": valid && restore,
+        })),
+    }
+}
+
+fn handle_lock_now() -> Response {
+    info!("Locking encryption key cache");
+
+    encryption::EncryptionManager::lock();
+
+    Response::Success {
+        message: "Encryption key locked".to_string(),
+        data: Some(serde_json::json!({ "unlocked": false })),
+    }
+}
+
+fn handle_set_key_cache_ttl(ttl_secs: u64) -> Response {
+    info!("Setting encryption key cache TTL to {ttl_secs}s");
+
+    if ttl_secs > encryption::MAX_KEY_CACHE_TTL.as_secs() {
+        return Response::error(
+            HostError::Validate,
+            format!(
+                "Key cache TTL must be at most {}s",
+                encryption::MAX_KEY_CACHE_TTL.as_secs()
+            ),
+        );
+    }
+    encryption::EncryptionManager::set_key_cache_ttl(Duration::from_secs(ttl_secs));
+
+    Response::Success {
+        message: "Key cache TTL updated".to_string(),
+        data: Some(serde_json::json!({ "key_cache_ttl_secs": ttl_secs })),
+    }
+}
+
+/// `SetConfig` settings kept in the repository's local git config
+struct RepoSettings {
+    backup_schedule: Option<snapshots::Schedule>,
+    backup_keep: Option<usize>,
+    device_branches: Option<bool>,
+    hooks: Option<hooks::Update>,
+    webhook: Option<webhooks::Update>,
+    link_check: Option<linkcheck::Update>,
+    expiry: Option<expiry::Update>,
+    retry: Option<retry::Update>,
+}
+
+fn handle_set_config(
+    config: &mut HostConfig,
+    proxy: Option<&str>,
+    commit_window_secs: Option<u64>,
+    commit_template: Option<String>,
+    ssh_key_path: Option<&str>,
+    RepoSettings {
+        backup_schedule,
+        backup_keep,
+        device_branches,
+        hooks,
+        webhook,
+        link_check,
+        expiry,
+        retry,
+    }: RepoSettings,
+    (validation, url_normalization): (validation::Update, Option<urlnorm::Update>),
+) -> Response {
+    info!("Updating host configuration");
+
+    // Scheduled backups, device branches, hooks, and the webhook are per
+    // repository, so their settings live with it
+    let repo = match config.repo_path.as_deref().map(git::GitRepo::init) {
+        Some(Ok(repo)) => Some(repo),
+        Some(Err(e)) => {
+            return Response::error(
+                HostError::OpenRepo,
+                format!("Failed to open repository: {e}"),
+            )
+        }
+        None => None,
+    };
+    let backups = match &repo {
+        Some(repo) => match snapshots::Settings::update(repo, backup_schedule, backup_keep) {
+            Ok(settings) => Some(settings),
+            Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+        },
+        None if backup_schedule.is_some() || backup_keep.is_some() => {
+            return Response::error(
+                HostError::NotInitialized,
+                "Scheduled backups need a repository",
+            )
+        }
+        None => None,
+    };
+    match (&repo, device_branches) {
+        (Some(repo), Some(enabled)) => {
+            if enabled && repo.device_name().is_none() {
+                return Response::error(
+                    HostError::Validate,
+                    "Name this device before turning on device branches",
+                );
+            }
+            if let Err(e) = repo.set_device_branches(enabled) {
+                return Response::error(HostError::WriteFile, format!("{e:#}"));
+            }
+        }
+        (None, Some(_)) => {
+            return Response::error(
+                HostError::NotInitialized,
+                "Device branches need a repository",
+            )
+        }
+        (_, None) => {}
+    }
+    let hooks = match (&repo, hooks) {
+        (Some(repo), Some(update)) => match hooks::Settings::update(repo, update) {
+            Ok(settings) => Some(settings),
+            Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+        },
+        (None, Some(_)) => {
+            return Response::error(HostError::NotInitialized, "Hooks need a repository")
+        }
+        (repo, None) => repo.as_ref().map(hooks::Settings::load),
+    };
+    let link_check = match (&repo, link_check) {
+        (Some(repo), Some(update)) => match linkcheck::Settings::update(repo, update) {
+            Ok(settings) => Some(settings),
+            Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+        },
+        (None, Some(_)) => {
+            return Response::error(HostError::NotInitialized, "Link checks need a repository")
+        }
+        (repo, None) => repo.as_ref().map(linkcheck::Settings::load),
+    };
+    let expiry = match (&repo, expiry) {
+        (Some(repo), Some(update)) => match expiry::Settings::update(repo, update) {
+            Ok(settings) => Some(settings),
+            Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+        },
+        (None, Some(_)) => {
+            return Response::error(HostError::NotInitialized, "Expiry needs a repository")
+        }
+        (repo, None) => repo.as_ref().map(expiry::Settings::load),
+    };
+    let retry = match (&repo, retry) {
+        (Some(repo), Some(update)) => match retry::RetryPolicy::update(repo, update) {
+            Ok(policy) => Some(policy),
+            Err(e) => return Response::error(HostError::Validate, format!("{e:#}")),
+        },
+        (None, Some(_)) => {
+            return Response::error(
+                HostError::NotInitialized,
+                "Retry settings need a repository",
+            )
+        }
+        (repo, None) => repo.as_ref().map(git::GitRepo::retry_policy),
+    };
+    match (&repo, webhook) {
+        (Some(repo), Some(update)) => {
+            if let Err(e) = webhooks::configure(repo, &update) {
+                return Response::error(HostError::Validate, format!("{e:#}"));
+            }
+        }
+        (None, Some(_)) => {
+            return Response::error(HostError::NotInitialized, "Webhooks need a repository")
+        }
+        (_, None) => {}
+    }
+
+    if !validation.is_empty() {
+        if let Err(response) = update_validation(config, validation) {
+            return response;
+        }
+    }
+
+    if let Some(update) = url_normalization {
+        if let Err(response) = update_url_rules(config, update) {
+            return response;
+        }
+    }
+
+    if let Some(path) = ssh_key_path {
+        let path = Some(Path::new(path)).filter(|path| !path.as_os_str().is_empty());
+        if let Err(e) = ssh::set_key_path(path) {
+            return Response::error(HostError::Validate, format!("Invalid SSH key: {e:#}"));
+        }
+    }
+
+    if let Some(template) = commit_template {
+        if template.is_empty() {
+            config.commit_template = None;
+        } else {
+            if let Err(e) = commit_template::validate_template(&template) {
+                return Response::error(
+                    HostError::Validate,
+                    format!("Invalid commit template: {e}"),
+                );
+            }
+            config.commit_template = Some(template);
+        }
+    }
+
+    if let Some(secs) = commit_window_secs {
+        info!("Commit batching window set to {secs}s");
+        config.batcher.set_window(Duration::from_secs(secs));
+        // Turning batching off shouldn't strand writes already held back
+        if secs == 0 {
+            if let Some(Response::Error { message, .. }) = flush_batch(config) {
+                return Response::error(
+                    HostError::GitCommit,
+                    format!("Failed to commit batched writes: {message}"),
+                );
+            }
+        }
+    }
+
+    if let Some(url) = proxy {
+        let url = Some(url).filter(|url| !url.is_empty());
+        if let Err(e) = proxy::set_proxy(url) {
+            return Response::error(HostError::Validate, format!("Invalid proxy: {e}"));
+        }
+        info!(
+            "Proxy set to {}",
+            url.map_or("(environment)".to_string(), proxy::redact)
+        );
+    }
+
+    let proxy_source = if proxy::explicit_proxy().is_some() {
+        Some("explicit")
+    } else if proxy::effective_proxy().is_some() {
+        Some("environment")
+    } else {
+        None
+    };
+
+    Response::Success {
+        message: "Configuration updated".to_string(),
+        data: Some(serde_json::json!({
+            "proxy": proxy::effective_proxy().as_deref().map(proxy::redact),
+            "proxy_source": proxy_source,
+            "commit_window_secs": config.batcher.window().as_secs(),
+            "commit_template": config
+                .commit_template
+                .as_deref()
+                .unwrap_or(commit_template::DEFAULT_COMMIT_TEMPLATE),
+            "ssh_key_path": ssh::key_path(),
+            "ssh_key_needs_passphrase": ssh::needs_passphrase(),
+            "backup_schedule": backups.map(|b| b.schedule),
+            "backup_keep": backups.map(|b| b.keep),
+            "device_branch": repo.as_ref().and_then(git::GitRepo::device_branch),
+            "hooks": hooks,
+            "webhook_url": repo.as_ref().and_then(webhooks::configured_url),
+            "link_check": link_check,
+            "expiry": expiry,
+            "retry": retry.map(|policy| serde_json::json!({
+                "attempts": policy.max_attempts,
+                "base_ms": policy.base_delay.as_millis(),
+            })),
+            "validation": config.validation,
+            "url_normalization": urlnorm::rules(),
+        })),
+    }
+}
+
+/// Change the repository's validation limits, as long as the bookmarks
+/// already stored still pass them
+fn update_validation(config: &mut HostConfig, update: validation::Update) -> Result<(), Response> {
+    let repo_path = config.get_repo_path().map_err(|_| {
+        Response::error(
+            HostError::NotInitialized,
+            "Validation limits need a repository",
+        )
+    })?;
+    let limits = config
+        .validation
+        .with(update)
+        .map_err(|e| Response::error(HostError::Validate, format!("{e:#}")))?;
+    let _lock = lock_repo(&repo_path)?;
+    if let Err(e) = load_bookmarks(config)?.validate_with(&limits) {
+        return Err(Response::error(
+            HostError::Validate,
+            format!("Stored bookmarks exceed the new limits: {e}"),
+        ));
+    }
+    let repo = git::GitRepo::init(&repo_path).map_err(|e| {
+        Response::error(
+            HostError::OpenRepo,
+            format!("Failed to open repository: {e}"),
+        )
+    })?;
+    limits.save(&repo).map_err(|e| {
+        Response::error(
+            HostError::WriteFile,
+            format!("Failed to save validation limits: {e:#}"),
+        )
+    })?;
+    info!("Validation limits set to {limits:?}");
+    config.validation = limits;
+    Ok(())
+}
+
+/// Change how the repository's URLs are normalized and compared
+fn update_url_rules(config: &HostConfig, update: urlnorm::Update) -> Result<(), Response> {
+    let repo_path = config.get_repo_path().map_err(|_| {
+        Response::error(
+            HostError::NotInitialized,
+            "URL normalization settings need a repository",
+        )
+    })?;
+    let rules = urlnorm::rules()
+        .with(update)
+        .map_err(|e| Response::error(HostError::Validate, format!("{e:#}")))?;
+    let repo = git::GitRepo::init(&repo_path).map_err(|e| {
+        Response::error(
+            HostError::OpenRepo,
+            format!("Failed to open repository: {e}"),
+        )
+    })?;
+    rules.save(&repo).map_err(|e| {
+        Response::error(
+            HostError::WriteFile,
+            format!("Failed to save URL normalization settings: {e:#}"),
+        )
+    })?;
+    info!("URL normalization set to {rules:?}");
+    urlnorm::set_rules(rules);
+    Ok(())
+}
+
 async fn handle_encryption_status(config: &HostConfig) -> Response {
     info!("Getting encryption status");
 
@@ -693,8 +5579,428 @@ async fn handle_encryption_status(config: &HostConfig) -> Response {
         message: "Encryption status retrieved".to_string(),
         data: Some(serde_json::json!({
             "encryption_enabled": config.encryption_enabled,
+            "encryption_mode": config.active_encryption(),
+            "cipher": config.cipher,
             "platform_supported": platform_supported,
             "biometric_available": platform_supported, // Simplified for now
+            "unlocked": encryption::EncryptionManager::is_unlocked(),
+            "key_cache_ttl_secs": encryption::EncryptionManager::key_cache_ttl().as_secs(),
         })),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config_for(repo_path: &Path) -> HostConfig {
+        let mut config = HostConfig::new();
+        config.repo_path = Some(repo_path.to_path_buf());
+        config
+    }
+
+    fn write_plain_bookmarks(repo_path: &Path) {
+        storage::write_to_file(
+            repo_path.join("bookmarks.json"),
+            &storage::BookmarksData::new(),
+        )
+        .unwrap();
+    }
+
+    fn error_code(response: &Response) -> Option<HostError> {
+        match response {
+            Response::Error { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_adopt_repo_encryption_plain() {
+        let temp_dir = TempDir::new().unwrap();
+        write_plain_bookmarks(temp_dir.path());
+        let mut config = config_for(temp_dir.path());
+        config.encryption_enabled = true;
+
+        config.adopt_repo_encryption().unwrap();
+        assert!(!config.encryption_enabled);
+        assert_eq!(config.active_encryption(), None);
+    }
+
+    #[test]
+    fn test_adopt_repo_encryption_full() {
+        let temp_dir = TempDir::new().unwrap();
+        let encrypted = serde_json::json!({
+            "version": "1",
+            "encrypted": true,
+            "algorithm": "AES-256-GCM",
+            "nonce": "AAAAAAAAAAAAAAAA",
+            "ciphertext": "AAAA",
+        });
+        std::fs::write(
+            temp_dir.path().join("bookmarks.json"),
+            encrypted.to_string(),
+        )
+        .unwrap();
+        let mut config = config_for(temp_dir.path());
+        config.encryption_mode = encryption::EncryptionMode::Fields;
+
+        config.adopt_repo_encryption().unwrap();
+        assert_eq!(
+            config.active_encryption(),
+            Some(encryption::EncryptionMode::Full)
+        );
+    }
+
+    #[test]
+    fn test_adopt_repo_encryption_fields() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("bookmarks.json");
+        write_plain_bookmarks(temp_dir.path());
+        let mut value: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        value["meta"]["encryption"] = serde_json::json!("fields");
+        std::fs::write(&file, value.to_string()).unwrap();
+        let mut config = config_for(temp_dir.path());
+
+        config.adopt_repo_encryption().unwrap();
+        assert_eq!(
+            config.active_encryption(),
+            Some(encryption::EncryptionMode::Fields)
+        );
+    }
+
+    #[test]
+    fn test_adopt_repo_encryption_without_repo() {
+        let mut config = HostConfig::new();
+        config.encryption_enabled = true;
+
+        config.adopt_repo_encryption().unwrap();
+        assert!(config.encryption_enabled);
+    }
+
+    #[tokio::test]
+    async fn test_import_key_invalid_blob_keeps_config() {
+        let temp_dir = TempDir::new().unwrap();
+        write_plain_bookmarks(temp_dir.path());
+        let mut config = config_for(temp_dir.path());
+
+        let response = handle_import_key(&mut config, "correct horse battery staple", "%%%").await;
+        assert_eq!(error_code(&response), Some(HostError::KeyImport));
+        assert!(!config.encryption_enabled);
+    }
+
+    #[tokio::test]
+    async fn test_verify_recovery_code_without_repo() {
+        let mut config = HostConfig::new();
+        let code = recovery::generate_recovery_code();
+
+        let response = handle_verify_recovery_code(&mut config, &code, true).await;
+        assert_eq!(error_code(&response), Some(HostError::NotInitialized));
+    }
+
+    #[tokio::test]
+    async fn test_restore_with_wrong_code_keeps_config() {
+        let temp_dir = TempDir::new().unwrap();
+        write_plain_bookmarks(temp_dir.path());
+        recovery::create_recovery_file(temp_dir.path(), &[9u8; 32]).unwrap();
+        let mut config = config_for(temp_dir.path());
+
+        let code = recovery::generate_recovery_code();
+        let response = handle_verify_recovery_code(&mut config, &code, true).await;
+        let Response::Success {
+            data: Some(data), ..
+        } = response
+        else {
+            panic!("expected success, got {response:?}");
+        };
+        assert_eq!(data["valid"], false);
+        assert_eq!(data["restored"], false);
+        assert!(!config.encryption_enabled);
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[tokio::test]
+    async fn test_restore_without_keychain_keeps_config() {
+        let temp_dir = TempDir::new().unwrap();
+        write_plain_bookmarks(temp_dir.path());
+        let code = recovery::create_recovery_file(temp_dir.path(), &[9u8; 32]).unwrap();
+        let mut config = config_for(temp_dir.path());
+
+        let response = handle_verify_recovery_code(&mut config, &code, true).await;
+        assert_eq!(error_code(&response), Some(HostError::Recovery));
+        assert!(!config.encryption_enabled);
+    }
+
+    #[test]
+    fn test_key_cache_ttl_is_bounded() {
+        let too_long = encryption::MAX_KEY_CACHE_TTL.as_secs() + 1;
+        let response = handle_set_key_cache_ttl(too_long);
+        assert_eq!(error_code(&response), Some(HostError::Validate));
+
+        let response = handle_set_key_cache_ttl(u64::MAX);
+        assert_eq!(error_code(&response), Some(HostError::Validate));
+        assert!(encryption::EncryptionManager::key_cache_ttl() <= encryption::MAX_KEY_CACHE_TTL);
+    }
+
+    #[test]
+    fn test_scheduled_backup_without_repo() {
+        let config = HostConfig::new();
+        run_scheduled_backup(&config);
+        assert!(config.repo_path.is_none());
+    }
+
+    #[test]
+    fn test_scheduled_link_check_without_repo() {
+        let mut config = HostConfig::new();
+        run_scheduled_link_check(&mut config);
+        assert!(config.link_check.is_none());
+    }
+
+    #[test]
+    fn test_scheduled_expiry_without_repo() {
+        let mut config = HostConfig::new();
+        run_scheduled_expiry(&mut config);
+        assert!(config.last_expiry_check.is_none());
+    }
+
+    #[test]
+    fn test_tag_counts_recounted_after_change() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("bookmarks.json");
+        let mut data = storage::BookmarksData::new();
+        let tag = storage::create_tag("rust".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+        data.add_bookmark(storage::create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec![tag_id.clone()],
+        ))
+        .unwrap();
+        storage::write_to_file(&file, &data).unwrap();
+        let mut config = config_for(temp_dir.path());
+
+        let count = |response: Response| match response {
+            Response::Success {
+                data: Some(data), ..
+            } => data["counts"][&tag_id]["direct"].clone(),
+            other => panic!("expected success, got {other:?}"),
+        };
+        assert_eq!(count(handle_tag_counts(&mut config)), 1);
+        assert!(config.tag_counts.is_some());
+        assert_eq!(count(handle_tag_counts(&mut config)), 1);
+
+        data.add_bookmark(storage::create_bookmark(
+            "https://example.org".to_string(),
+            "Another".to_string(),
+            vec![tag_id.clone()],
+        ))
+        .unwrap();
+        storage::write_to_file(&file, &data).unwrap();
+        assert_eq!(count(handle_tag_counts(&mut config)), 2);
+
+        // The tree fills and reuses the same cache
+        config.tag_counts = None;
+        let Response::Success {
+            data: Some(tree), ..
+        } = handle_tag_tree(&mut config)
+        else {
+            panic!("expected the tag tree");
+        };
+        assert_eq!(tree["tags"][0]["counts"]["direct"], 2);
+        assert!(config.tag_counts.is_some());
+        assert_eq!(count(handle_tag_counts(&mut config)), 2);
+    }
+
+    #[test]
+    fn test_import_applies_rules_to_new_bookmarks() {
+        let temp_dir = TempDir::new().unwrap();
+        git::GitRepo::init(temp_dir.path()).unwrap();
+        let mut data = storage::BookmarksData::new();
+        let tag = storage::create_tag("code".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+        data.add_rule(storage::create_rule(
+            "GitHub".to_string(),
+            storage::RuleField::Domain,
+            storage::MatchKind::Equals,
+            "github.com".to_string(),
+            vec![tag_id.clone()],
+        ))
+        .unwrap();
+        storage::write_to_file(temp_dir.path().join("bookmarks.json"), &data).unwrap();
+        let mut config = config_for(temp_dir.path());
+
+        let item = |url: &str| import::ImportedItem {
+            url: url.to_string(),
+            title: "Imported".to_string(),
+            tags: Vec::new(),
+            folder: Vec::new(),
+            notes: None,
+            created: None,
+            unread: false,
+            favorite: false,
+            private: false,
+        };
+        let response = import_items(
+            &mut config,
+            vec![
+                item("https://github.com/rust-lang/rust"),
+                item("https://example.com"),
+            ],
+            false,
+            &import::ImportOptions::default(),
+        );
+        let Response::Success {
+            data: Some(result), ..
+        } = response
+        else {
+            panic!("expected success, got {response:?}");
+        };
+        assert_eq!(result["rule_tagged"].as_array().map(Vec::len), Some(1));
+
+        let saved = storage::read_from_file(temp_dir.path().join("bookmarks.json")).unwrap();
+        for bookmark in saved.get_bookmarks() {
+            let storage::Resource::Bookmark {
+                attributes,
+                relationships,
+                ..
+            } = bookmark
+            else {
+                continue;
+            };
+            let tagged = relationships
+                .iter()
+                .flat_map(|rels| rels.tags.iter().flat_map(|tags| &tags.data))
+                .any(|tag| tag.id == tag_id);
+            assert_eq!(
+                tagged,
+                attributes.url.contains("github.com"),
+                "{}",
+                attributes.url
+            );
+        }
+    }
+
+    #[test]
+    fn test_mcp_writes_go_through_the_host_save_path() {
+        let temp_dir = TempDir::new().unwrap();
+        git::GitRepo::init(temp_dir.path()).unwrap();
+        let mut data = storage::BookmarksData::new();
+        let tag = storage::create_tag("lang".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+        data.add_rule(storage::create_rule(
+            "Rust".to_string(),
+            storage::RuleField::Domain,
+            storage::MatchKind::Equals,
+            "rust-lang.org".to_string(),
+            vec![tag_id],
+        ))
+        .unwrap();
+        let mut private = storage::create_bookmark(
+            "https://secret.example.com".to_string(),
+            "Secret rust notes".to_string(),
+            vec![],
+        );
+        if let storage::Resource::Bookmark { attributes, .. } = &mut private {
+            attributes.private = true;
+        }
+        data.add_bookmark(private).unwrap();
+        storage::write_to_file(temp_dir.path().join("bookmarks.json"), &data).unwrap();
+
+        let requests = [
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": {
+                    "name": "add_bookmark",
+                    "arguments": { "url": "https://www.rust-lang.org", "title": "Rust" },
+                },
+            }),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "tools/call",
+                "params": { "name": "search_bookmarks", "arguments": { "query": "rust" } },
+            }),
+        ];
+        let input = requests.map(|r| r.to_string()).join("\n");
+        let mut output = Vec::new();
+        mcp::serve(
+            McpStore(config_for(temp_dir.path())),
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+
+        let replies: Vec<serde_json::Value> = output
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(replies[0]["result"]["isError"], false);
+        let text = replies[1]["result"]["content"][0]["text"].as_str().unwrap();
+        let results: Vec<serde_json::Value> = serde_json::from_str(text).unwrap();
+        // The private bookmark matches too, but agents never see it
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["url"], "https://www.rust-lang.org/");
+        // The new bookmark was tagged by the rule and committed
+        assert_eq!(results[0]["tags"], serde_json::json!(["lang"]));
+        let repo = git::GitRepo::init(temp_dir.path()).unwrap();
+        assert!(repo.head_id().is_some());
+        assert!(repo.is_clean().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rejected_commit_is_not_recovered() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let repo = git::GitRepo::init(temp_dir.path()).unwrap();
+        write_plain_bookmarks(temp_dir.path());
+        repo.add_file("bookmarks.json").unwrap();
+        repo.commit("Initial").unwrap();
+        let head = repo.head_id();
+
+        let hook_dir = TempDir::new().unwrap();
+        let hook = hook_dir.path().join("reject");
+        std::fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        hooks::Settings::update(
+            &repo,
+            hooks::Update {
+                pre_commit: Some(hook.display().to_string()),
+                ..hooks::Update::default()
+            },
+        )
+        .unwrap();
+
+        let mut data = storage::BookmarksData::new();
+        data.add_bookmark(storage::create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec![],
+        ))
+        .unwrap();
+        let mut config = config_for(temp_dir.path());
+        let response = save_bookmarks(&mut config, temp_dir.path(), &data);
+        assert_eq!(error_code(&response), Some(HostError::Hook));
+
+        let stored = |repo_path: &Path| {
+            storage::read_from_file(repo_path.join("bookmarks.json"))
+                .unwrap()
+                .get_bookmarks()
+                .len()
+        };
+        assert_eq!(repo.head_id(), head);
+        assert_eq!(stored(temp_dir.path()), 0);
+        assert!(!repo.has_staged_changes().unwrap());
+
+        assert_eq!(recover_interrupted_write(temp_dir.path()), None);
+        assert_eq!(repo.head_id(), head);
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::storage::{BookmarkAttributes, BookmarksData, Resource};
+
+/// Folder (inside the export directory) for bookmark notes
+pub const BOOKMARKS_DIR: &str = "Bookmarks";
+
+/// Folder (inside the export directory) for tag pages
+pub const TAGS_DIR: &str = "Tags";
+
+/// Written into the export directory so later exports know they may replace it
+const MARKER_FILE: &str = ".webtags-export";
+
+/// Longest note name, before any suffix that keeps names unique
+const MAX_NAME_LENGTH: usize = 100;
+
+/// A Markdown file: path inside the export directory and contents
+pub type Note = (String, String);
+
+/// One note per bookmark (front matter with its URL, tags, and dates, and its
+/// notes as the body) and one page per tag linking to them, in the shape
+/// Obsidian and similar tools read.
+///
+/// Private bookmarks are left out unless `include_private` is set.
+pub fn render(data: &BookmarksData, include_private: bool) -> Vec<Note> {
+    let breadcrumbs = data.get_tag_breadcrumbs();
+    let mut tag_names = UniqueNames::default();
+    let tag_pages: HashMap<&str, String> = data
+        .get_tags()
+        .into_iter()
+        .map(|tag| {
+            let path = breadcrumbs.get(tag.id()).map_or(&[][..], Vec::as_slice);
+            (tag.id(), tag_names.claim(&path.join(" - ")))
+        })
+        .collect();
+
+    let mut bookmark_names = UniqueNames::default();
+    let mut notes = Vec::new();
+    let mut tagged: HashMap<&str, Vec<(String, &str)>> = HashMap::new();
+    for resource in data.get_bookmarks() {
+        let Resource::Bookmark {
+            attributes,
+            relationships,
+            ..
+        } = resource
+        else {
+            continue;
+        };
+        if attributes.private && !include_private {
+            continue;
+        }
+        let tag_ids: Vec<&str> = relationships
+            .iter()
+            .flat_map(|r| r.tags.iter().flat_map(|tags| &tags.data))
+            .map(|tag| tag.id.as_str())
+            .filter(|id| tag_pages.contains_key(id))
+            .collect();
+
+        let name = bookmark_names.claim(&attributes.title);
+        for &tag_id in &tag_ids {
+            tagged
+                .entry(tag_id)
+                .or_default()
+                .push((name.clone(), attributes.title.as_str()));
+        }
+        let tags: Vec<(String, &str)> = tag_ids
+            .iter()
+            .map(|id| {
+                let path = breadcrumbs.get(*id).map_or(&[][..], Vec::as_slice);
+                (tag_path(path), tag_pages[id].as_str())
+            })
+            .collect();
+        notes.push((
+            format!("{BOOKMARKS_DIR}/{name}.md"),
+            bookmark_note(attributes, &tags),
+        ));
+    }
+
+    for (id, page) in &tag_pages {
+        let path = breadcrumbs.get(*id).map_or(&[][..], Vec::as_slice);
+        let mut body = String::new();
+        let _ = writeln!(
+            body,
+            "# {}\n",
+            path.last().map_or("Untitled", String::as_str)
+        );
+        if let Some(parent) = data.tag_parent_id(id).and_then(|p| tag_pages.get(p)) {
+            let _ = writeln!(body, "Parent: [[{TAGS_DIR}/{parent}]]\n");
+        }
+        for (name, title) in tagged.get(id).into_iter().flatten() {
+            let _ = writeln!(body, "- [[{BOOKMARKS_DIR}/{name}|{}]]", link_text(title));
+        }
+        notes.push((format!("{TAGS_DIR}/{page}.md"), body));
+    }
+
+    notes
+}
+
+fn bookmark_note(bookmark: &BookmarkAttributes, tags: &[(String, &str)]) -> String {
+    // JSON strings are valid YAML scalars, so values need no further escaping
+    let quote = |value: &str| serde_json::Value::from(value).to_string();
+    let mut note = String::from("---\n");
+    let _ = writeln!(note, "title: {}", quote(&bookmark.title));
+    let _ = writeln!(note, "url: {}", quote(&bookmark.url));
+    if !tags.is_empty() {
+        note.push_str("tags:\n");
+        for (tag, _) in tags {
+            let _ = writeln!(note, "  - {}", quote(tag));
+        }
+    }
+    let _ = writeln!(note, "created: {}", bookmark.created.to_rfc3339());
+    if let Some(modified) = bookmark.modified {
+        let _ = writeln!(note, "modified: {}", modified.to_rfc3339());
+    }
+    if bookmark.unread {
+        note.push_str("unread: true\n");
+    }
+    if bookmark.private {
+        note.push_str("private: true\n");
+    }
+    note.push_str("---\n\n");
+
+    // Angle brackets keep URLs with spaces or parentheses in one piece
+    let _ = writeln!(note, "[{}](<{}>)", link_text(&bookmark.title), bookmark.url);
+    if let Some(notes) = bookmark.notes.as_deref().filter(|n| !n.trim().is_empty()) {
+        let _ = writeln!(note, "\n{}", notes.trim_end());
+    }
+    if !tags.is_empty() {
+        let links: Vec<String> = tags
+            .iter()
+            .map(|(_, page)| format!("[[{TAGS_DIR}/{page}]]"))
+            .collect();
+        let _ = writeln!(note, "\nTags: {}", links.join(" "));
+    }
+    note
+}
+
+/// Replace the export in `dir` with `notes`. A directory that isn't empty is
+/// only replaced if an earlier export wrote it, so a vault's own notes are
+/// never deleted.
+pub fn write(dir: &Path, notes: &[Note]) -> Result<usize> {
+    if !dir.is_absolute() {
+        anyhow::bail!("Export directory must be an absolute path");
+    }
+    if dir.exists() {
+        let empty = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .next()
+            .is_none();
+        if !empty && !dir.join(MARKER_FILE).exists() {
+            anyhow::bail!(
+                "{} isn't empty and wasn't written by an earlier export; choose an empty \
+                 directory",
+                dir.display()
+            );
+        }
+        for subdir in [BOOKMARKS_DIR, TAGS_DIR] {
+            let subdir = dir.join(subdir);
+            if subdir.exists() {
+                fs::remove_dir_all(&subdir)
+                    .with_context(|| format!("Failed to remove {}", subdir.display()))?;
+            }
+        }
+    }
+
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::write(dir.join(MARKER_FILE), "").context("Failed to mark the export directory")?;
+    for (path, contents) in notes {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(notes.len())
+}
+
+/// File names that are safe on every platform and unique (ignoring case)
+#[derive(Default)]
+struct UniqueNames(HashSet<String>);
+
+impl UniqueNames {
+    fn claim(&mut self, wanted: &str) -> String {
+        let base = file_name(wanted);
+        let mut name = base.clone();
+        let mut n = 2;
+        while !self.0.insert(name.to_lowercase()) {
+            name = format!("{base} ({n})");
+            n += 1;
+        }
+        name
+    }
+}
+
+/// Characters file systems or wiki links don't allow become spaces
+fn file_name(title: &str) -> String {
+    let cleaned: String = title
+        .chars()
+        .map(|c| {
+            if c.is_control() || r#"/\:*?"<>|#^[]"#.contains(c) {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect();
+    let mut name = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    // A leading dot would hide the file
+    name = name.trim_start_matches('.').trim().to_string();
+    if name.chars().count() > MAX_NAME_LENGTH {
+        name = name.chars().take(MAX_NAME_LENGTH).collect::<String>();
+        name = name.trim_end().to_string();
+    }
+    if name.is_empty() {
+        "Untitled".to_string()
+    } else {
+        name
+    }
+}
+
+/// Nested tag in front matter: path segments joined by `/`, with characters
+/// tags can't contain replaced by `-`
+fn tag_path(path: &[String]) -> String {
+    path.iter()
+        .map(|segment| {
+            segment
+                .trim()
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || c == '_' || c == '-' {
+                        c
+                    } else {
+                        '-'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Text safe inside a link's label
+fn link_text(text: &str) -> String {
+    text.replace(['[', ']', '|'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+
+    fn sample_data() -> BookmarksData {
+        let mut data = BookmarksData::new();
+        let dev = create_tag("Dev".to_string(), None, None);
+        let dev_id = dev.id().to_string();
+        let rust = create_tag("Rust lang".to_string(), None, Some(dev_id.clone()));
+        let rust_id = rust.id().to_string();
+        data.add_tag(dev).unwrap();
+        data.add_tag(rust).unwrap();
+
+        for (title, tags, private) in [
+            ("The Book: \"Rust\"", vec![rust_id.clone()], false),
+            ("The Book: \"Rust\"", vec![], false),
+            ("Secret", vec![dev_id], true),
+        ] {
+            let mut bookmark =
+                create_bookmark("https://example.com".to_string(), title.to_string(), tags);
+            if let Resource::Bookmark { attributes, .. } = &mut bookmark {
+                attributes.private = private;
+                attributes.notes = Some("Read chapter 4".to_string());
+            }
+            data.add_bookmark(bookmark).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn test_render_notes_and_tag_pages() {
+        let notes: HashMap<String, String> = render(&sample_data(), false).into_iter().collect();
+        let mut paths: Vec<&str> = notes.keys().map(String::as_str).collect();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            vec![
+                "Bookmarks/The Book Rust (2).md",
+                "Bookmarks/The Book Rust.md",
+                "Tags/Dev - Rust lang.md",
+                "Tags/Dev.md",
+            ]
+        );
+
+        let note = &notes["Bookmarks/The Book Rust.md"];
+        assert!(note.starts_with("---\ntitle: \"The Book: \\\"Rust\\\"\"\n"));
+        assert!(note.contains("tags:\n  - \"Dev/Rust-lang\"\n"));
+        assert!(note.contains("Read chapter 4"));
+        assert!(note.contains("[[Tags/Dev - Rust lang]]"));
+
+        let tag = &notes["Tags/Dev - Rust lang.md"];
+        assert!(tag.contains("Parent: [[Tags/Dev]]"));
+        assert!(tag.contains("- [[Bookmarks/The Book Rust|The Book: \"Rust\"]]"));
+
+        // The private bookmark only appears when asked for
+        assert!(!notes["Tags/Dev.md"].contains("Secret"));
+        let with_private = render(&sample_data(), true);
+        assert!(with_private
+            .iter()
+            .any(|(path, note)| path == "Bookmarks/Secret.md" && note.contains("private: true")));
+    }
+
+    #[test]
+    fn test_write_never_replaces_other_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let export = dir.path().join("export");
+        let notes = render(&sample_data(), false);
+
+        assert_eq!(write(&export, &notes).unwrap(), notes.len());
+        // Notes from the previous export are removed
+        fs::write(export.join(BOOKMARKS_DIR).join("Stale.md"), "").unwrap();
+        write(&export, &notes).unwrap();
+        assert!(!export.join(BOOKMARKS_DIR).join("Stale.md").exists());
+
+        fs::write(dir.path().join("mine.md"), "").unwrap();
+        assert!(write(dir.path(), &notes).is_err());
+        assert!(write(Path::new("relative"), &notes).is_err());
+    }
+}
@@ -0,0 +1,550 @@
+//! Model Context Protocol server, so LLM agents can search, read, and add
+//! bookmarks through the same storage the extension uses.
+//!
+//! Speaks JSON-RPC 2.0 over stdio, one message per line. Private bookmarks
+//! are never shown to agents.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::import::{self, ImportOptions, ImportedItem};
+use crate::query::Query;
+use crate::storage::{BookmarksData, Resource, SortOrder};
+
+/// MCP revision this server implements
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Results `search_bookmarks` returns when the agent doesn't ask for a number
+const DEFAULT_RESULTS: usize = 20;
+
+/// Upper bound on results `search_bookmarks` will return
+const MAX_RESULTS: usize = 200;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Where the server's bookmarks live
+pub trait Store {
+    fn load(&mut self) -> Result<BookmarksData>;
+
+    /// Load the bookmarks, apply `change`, and save (and commit) the result,
+    /// holding whatever lock keeps other writers out throughout
+    fn modify(&mut self, change: &mut dyn FnMut(&mut BookmarksData) -> Result<()>) -> Result<()>;
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    jsonrpc: String,
+    /// Absent for notifications, which get no reply
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// An error to report in a JSON-RPC error object
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+pub struct Server<S> {
+    store: S,
+}
+
+impl<S: Store> Server<S> {
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    /// Handle one line of input, returning the reply to send, if any
+    pub fn handle_line(&mut self, line: &str) -> Option<Value> {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => {
+                let code = if serde_json::from_str::<Value>(line).is_ok() {
+                    INVALID_REQUEST
+                } else {
+                    PARSE_ERROR
+                };
+                return Some(error_reply(
+                    &Value::Null,
+                    &RpcError::new(code, e.to_string()),
+                ));
+            }
+        };
+        let id = request.id.clone()?;
+        if request.jsonrpc != "2.0" {
+            return Some(error_reply(
+                &id,
+                &RpcError::new(INVALID_REQUEST, "Only JSON-RPC 2.0 is supported"),
+            ));
+        }
+
+        Some(match self.handle(&request) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => error_reply(&id, &error),
+        })
+    }
+
+    fn handle(&mut self, request: &Request) -> Result<Value, RpcError> {
+        match request.method.as_str() {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "webtags", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tools() })),
+            "tools/call" => {
+                let name = request.params["name"]
+                    .as_str()
+                    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing tool name"))?;
+                let arguments = match &request.params["arguments"] {
+                    Value::Null => json!({}),
+                    arguments => arguments.clone(),
+                };
+                // Tool failures are results the agent can read, not protocol errors
+                Ok(match self.call(name, arguments)? {
+                    Ok(output) => json!({
+                        "content": [{ "type": "text", "text": output }],
+                        "isError": false,
+                    }),
+                    Err(e) => json!({
+                        "content": [{ "type": "text", "text": format!("{e:#}") }],
+                        "isError": true,
+                    }),
+                })
+            }
+            method => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method '{method}'"),
+            )),
+        }
+    }
+
+    /// Run a tool. The outer error is for a call that can't be made at all.
+    fn call(&mut self, name: &str, arguments: Value) -> Result<Result<String>, RpcError> {
+        fn parse<T: for<'de> Deserialize<'de>>(arguments: Value) -> Result<T, RpcError> {
+            serde_json::from_value(arguments)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid arguments: {e}")))
+        }
+
+        Ok(match name {
+            "search_bookmarks" => {
+                let args: SearchArgs = parse(arguments)?;
+                self.store.load().and_then(|data| search(&data, &args))
+            }
+            "get_bookmark" => {
+                let args: GetArgs = parse(arguments)?;
+                self.store.load().and_then(|data| get(&data, &args.id))
+            }
+            "list_tags" => self.store.load().map(|data| list_tags(&data)),
+            "add_bookmark" => {
+                let args: AddArgs = parse(arguments)?;
+                self.add(args)
+            }
+            name => {
+                return Err(RpcError::new(
+                    INVALID_PARAMS,
+                    format!("Unknown tool '{name}'"),
+                ))
+            }
+        })
+    }
+
+    fn add(&mut self, args: AddArgs) -> Result<String> {
+        let item = ImportedItem {
+            url: args.url.trim().to_string(),
+            title: args.title.unwrap_or_default().trim().to_string(),
+            tags: args.tags,
+            folder: Vec::new(),
+            notes: args.notes.filter(|notes| !notes.trim().is_empty()),
+            created: None,
+            unread: args.unread,
+            favorite: false,
+            private: false,
+        };
+        let url = item.url.clone();
+        let mut report = None;
+        self.store.modify(&mut |data| {
+            let result = import::import(
+                data,
+                vec![item.clone()],
+                &ImportOptions::default(),
+                |_, _| {},
+            );
+            if result.skipped > 0 {
+                anyhow::bail!("{}", result.warnings.join("; "));
+            }
+            report = Some(result);
+            Ok(())
+        })?;
+
+        let report = report.context("Bookmark was not added")?;
+        Ok(if report.created > 0 {
+            format!("Added {url}")
+        } else if report.merged > 0 {
+            format!("{url} was already saved; added the new tags")
+        } else {
+            format!("{url} was already saved with those tags")
+        })
+    }
+}
+
+fn error_reply(id: &Value, error: &RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchArgs {
+    #[serde(default)]
+    query: String,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetArgs {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AddArgs {
+    url: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    unread: bool,
+}
+
+fn tools() -> Value {
+    json!([
+        {
+            "name": "search_bookmarks",
+            "description": "Search saved bookmarks, newest first. Words match titles and URLs; \
+                `tag:NAME` filters by tag, `is:unread` or `is:read` by read-later state, and \
+                `type:video`, `lang:en`, or `minutes:>20` by what the page is.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Search terms; empty lists every bookmark" },
+                    "limit": { "type": "integer", "minimum": 1, "maximum": MAX_RESULTS },
+                },
+            },
+        },
+        {
+            "name": "get_bookmark",
+            "description": "A bookmark's URL, title, tags, dates, and notes",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "id": { "type": "string" } },
+                "required": ["id"],
+            },
+        },
+        {
+            "name": "list_tags",
+            "description": "Every tag with its full path and how many bookmarks it has",
+            "inputSchema": { "type": "object", "properties": {} },
+        },
+        {
+            "name": "add_bookmark",
+            "description": "Save a bookmark and sync it. Tags are matched by name or created; \
+                saving a URL that is already saved adds any new tags to it.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "url": { "type": "string" },
+                    "title": { "type": "string" },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                    "notes": { "type": "string" },
+                    "unread": { "type": "boolean", "description": "Save to read later" },
+                },
+                "required": ["url"],
+            },
+        },
+    ])
+}
+
+/// A bookmark as agents see it, with tag names in place of IDs
+fn describe(data: &BookmarksData, resource: &Resource, with_notes: bool) -> Option<Value> {
+    let Resource::Bookmark {
+        id,
+        attributes,
+        relationships,
+        ..
+    } = resource
+    else {
+        return None;
+    };
+    if attributes.private {
+        return None;
+    }
+    let tags: Vec<String> = relationships
+        .iter()
+        .flat_map(|r| r.tags.iter().flat_map(|tags| &tags.data))
+        .map(|tag| data.get_tag_breadcrumb(&tag.id).join("/"))
+        .filter(|path| !path.is_empty())
+        .collect();
+
+    let mut bookmark = json!({
+        "id": id,
+        "url": attributes.url,
+        "title": attributes.title,
+        "tags": tags,
+        "created": attributes.created,
+    });
+    if attributes.unread {
+        bookmark["unread"] = json!(true);
+    }
+    if let Some(archive_url) = &attributes.archive_url {
+        bookmark["archive_url"] = json!(archive_url);
+    }
+    if let Some(enrichment) = &attributes.enrichment {
+        bookmark["content_type"] = json!(enrichment.content_type);
+        if let Some(minutes) = enrichment.reading_minutes {
+            bookmark["reading_minutes"] = json!(minutes);
+        }
+        if let Some(language) = &enrichment.language {
+            bookmark["language"] = json!(language);
+        }
+    }
+    if with_notes {
+        bookmark["modified"] = json!(attributes.modified);
+        bookmark["notes"] = json!(attributes.notes);
+    }
+    Some(bookmark)
+}
+
+fn search(data: &BookmarksData, args: &SearchArgs) -> Result<String> {
+    let query = Query::parse(&args.query)?;
+    let limit = args.limit.unwrap_or(DEFAULT_RESULTS).clamp(1, MAX_RESULTS);
+    let results: Vec<Value> = query
+        .run(data, SortOrder::Newest)
+        .into_iter()
+        .filter_map(|resource| describe(data, resource, false))
+        .take(limit)
+        .collect();
+    Ok(serde_json::to_string_pretty(&results)?)
+}
+
+fn get(data: &BookmarksData, id: &str) -> Result<String> {
+    let bookmark = data
+        .get(id)
+        .and_then(|resource| describe(data, resource, true))
+        .with_context(|| format!("No bookmark with ID {id}"))?;
+    Ok(serde_json::to_string_pretty(&bookmark)?)
+}
+
+fn list_tags(data: &BookmarksData) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for bookmark in data.get_bookmarks() {
+        if let Resource::Bookmark {
+            attributes,
+            relationships: Some(relationships),
+            ..
+        } = bookmark
+        {
+            if attributes.private {
+                continue;
+            }
+            for tag in relationships.tags.iter().flat_map(|tags| &tags.data) {
+                *counts.entry(&tag.id).or_default() += 1;
+            }
+        }
+    }
+
+    let breadcrumbs = data.get_tag_breadcrumbs();
+    let mut tags: Vec<Value> = data
+        .get_tags()
+        .into_iter()
+        .map(|tag| {
+            json!({
+                "path": breadcrumbs.get(tag.id()).map(|path| path.join("/")),
+                "bookmarks": counts.get(tag.id()).copied().unwrap_or(0),
+            })
+        })
+        .collect();
+    tags.sort_by(|a, b| a["path"].as_str().cmp(&b["path"].as_str()));
+    serde_json::to_string_pretty(&tags).unwrap_or_default()
+}
+
+/// Serve requests from `input` until it closes
+pub fn serve<S: Store>(store: S, input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut server = Server::new(store);
+    for line in input.lines() {
+        let line = line.context("Failed to read request")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(reply) = server.handle_line(&line) {
+            serde_json::to_writer(&mut output, &reply).context("Failed to write reply")?;
+            output.write_all(b"\n").context("Failed to write reply")?;
+            output.flush().context("Failed to write reply")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        data: BookmarksData,
+        saves: usize,
+    }
+
+    impl Store for &mut MemoryStore {
+        fn load(&mut self) -> Result<BookmarksData> {
+            Ok(self.data.clone())
+        }
+
+        fn modify(
+            &mut self,
+            change: &mut dyn FnMut(&mut BookmarksData) -> Result<()>,
+        ) -> Result<()> {
+            let mut data = self.data.clone();
+            change(&mut data)?;
+            data.validate()?;
+            self.data = data;
+            self.saves += 1;
+            Ok(())
+        }
+    }
+
+    fn call(server: &mut Server<&mut MemoryStore>, id: u64, method: &str, params: Value) -> Value {
+        let mut request = json!({ "jsonrpc": "2.0", "id": id, "method": method });
+        request["params"] = params;
+        server.handle_line(&request.to_string()).unwrap()
+    }
+
+    fn tool_text(reply: &Value) -> &str {
+        reply["result"]["content"][0]["text"].as_str().unwrap()
+    }
+
+    #[test]
+    fn test_handshake_and_errors() {
+        let mut store = MemoryStore::default();
+        let mut server = Server::new(&mut store);
+
+        let reply = call(&mut server, 1, "initialize", json!({}));
+        assert_eq!(reply["result"]["protocolVersion"], PROTOCOL_VERSION);
+        assert_eq!(reply["id"], 1);
+
+        // Notifications get no reply
+        let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        assert!(server.handle_line(&notification.to_string()).is_none());
+
+        let tools = call(&mut server, 2, "tools/list", json!({}));
+        assert_eq!(tools["result"]["tools"].as_array().unwrap().len(), 4);
+
+        assert_eq!(
+            call(&mut server, 3, "resources/list", json!({}))["error"]["code"],
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            server.handle_line("{not json").unwrap()["error"]["code"],
+            PARSE_ERROR
+        );
+        let bad_args = call(
+            &mut server,
+            4,
+            "tools/call",
+            json!({ "name": "get_bookmark", "arguments": {} }),
+        );
+        assert_eq!(bad_args["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_add_then_search() {
+        let mut store = MemoryStore::default();
+        let mut private = create_bookmark(
+            "https://secret.example.com".to_string(),
+            "Secret rust notes".to_string(),
+            vec![],
+        );
+        if let Resource::Bookmark { attributes, .. } = &mut private {
+            attributes.private = true;
+        }
+        store.data.add_bookmark(private).unwrap();
+        let mut server = Server::new(&mut store);
+
+        let add = |server: &mut Server<&mut MemoryStore>, tags: Value| {
+            call(
+                server,
+                1,
+                "tools/call",
+                json!({
+                    "name": "add_bookmark",
+                    "arguments": {
+                        "url": "https://www.rust-lang.org",
+                        "title": "Rust",
+                        "tags": tags,
+                    },
+                }),
+            )
+        };
+        let added = add(&mut server, json!(["lang"]));
+        assert_eq!(added["result"]["isError"], false);
+        assert_eq!(tool_text(&added), "Added https://www.rust-lang.org");
+        let again = add(&mut server, json!(["lang", "favorite"]));
+        assert!(tool_text(&again).contains("added the new tags"));
+
+        let found = call(
+            &mut server,
+            2,
+            "tools/call",
+            json!({ "name": "search_bookmarks", "arguments": { "query": "rust" } }),
+        );
+        let results: Vec<Value> = serde_json::from_str(tool_text(&found)).unwrap();
+        // The private bookmark matches too, but agents never see it
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["tags"], json!(["lang", "favorite"]));
+
+        let id = results[0]["id"].as_str().unwrap();
+        let bookmark = call(
+            &mut server,
+            3,
+            "tools/call",
+            json!({ "name": "get_bookmark", "arguments": { "id": id } }),
+        );
+        assert!(tool_text(&bookmark).contains("https://www.rust-lang.org"));
+
+        let invalid = call(
+            &mut server,
+            4,
+            "tools/call",
+            json!({ "name": "add_bookmark", "arguments": { "url": "javascript:alert(1)" } }),
+        );
+        assert_eq!(invalid["result"]["isError"], true);
+        assert_eq!(store.saves, 2);
+    }
+}
@@ -0,0 +1,915 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::storage::{
+    self, BookmarkRelationships, BookmarksData, RelationshipData, Resource, ResourceIdentifier,
+};
+use crate::urlnorm;
+use chrono::{DateTime, Utc};
+
+/// Conflicts from the last merge still waiting for the user. Kept inside
+/// `.git` so it is per-clone and never committed.
+pub const CONFLICTS_FILE: &str = "conflicts.json";
+
+/// A field both sides changed to different values, and the one kept
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldConflict {
+    pub id: String,
+    /// Dotted path such as `attributes.title`; empty when one side deleted
+    /// the whole resource and the other edited it
+    pub field: String,
+    /// `null` for the side that deleted the resource
+    pub local: Value,
+    pub remote: Value,
+    pub merged: Value,
+}
+
+/// Three-way merge of two versions of the bookmarks that both started from
+/// `base`. The result doesn't depend on which side is called local, so two
+/// devices merging the same pair of versions converge.
+///
+/// Resources are merged field by field as a small CRDT:
+/// - objects merge key by key
+/// - arrays (tag lists) are observed-remove sets: an element stays if both
+///   sides have it or one side added it, so a concurrent add beats a remove
+/// - anything else is a last-writer-wins register: a value changed on one
+///   side wins; changed on both, the bookmark with the later `modified`
+///   time wins, and without one the greater value does
+/// - a resource deleted on one side and edited on the other keeps the edit
+///
+/// Bookmarks both sides added for the same page, as when two people sharing
+/// a repository save the same link, become one (see
+/// [`fold_concurrent_additions`]).
+pub fn merge(
+    base: &BookmarksData,
+    local: &BookmarksData,
+    remote: &BookmarksData,
+) -> (BookmarksData, Vec<FieldConflict>) {
+    let mut conflicts = Vec::new();
+    let mut merged = BookmarksData::new();
+    merged.meta = if local.meta.schema_version >= remote.meta.schema_version {
+        local.meta.clone()
+    } else {
+        remote.meta.clone()
+    };
+    let mut data = merge_resources(base.data(), local.data(), remote.data(), &mut conflicts);
+    fold_concurrent_additions(base.data(), local.data(), remote.data(), &mut data);
+    *merged.data_mut() = data;
+
+    let empty = &[][..];
+    let included = merge_resources(
+        base.included().unwrap_or(empty),
+        local.included().unwrap_or(empty),
+        remote.included().unwrap_or(empty),
+        &mut conflicts,
+    );
+    if !included.is_empty() {
+        *merged.included_mut() = Some(included);
+    }
+    (merged, conflicts)
+}
+
+fn merge_resources(
+    base: &[Resource],
+    local: &[Resource],
+    remote: &[Resource],
+    conflicts: &mut Vec<FieldConflict>,
+) -> Vec<Resource> {
+    // One side unchanged: keep the other exactly, order included
+    if local == remote || remote == base {
+        return local.to_vec();
+    }
+    if local == base {
+        return remote.to_vec();
+    }
+
+    let base_ids = by_id(base);
+    let local_ids = by_id(local);
+    let remote_ids = by_id(remote);
+
+    // Base order, then additions sorted so neither side's order wins
+    let mut ids: Vec<&str> = base.iter().map(Resource::id).collect();
+    let added: BTreeSet<&str> = local
+        .iter()
+        .chain(remote)
+        .map(Resource::id)
+        .filter(|id| !base_ids.contains_key(id))
+        .collect();
+    ids.extend(added);
+
+    let mut merged = Vec::new();
+    for id in ids {
+        let original = base_ids.get(id).map(|&i| &base[i]);
+        let ours = local_ids.get(id).map(|&i| &local[i]);
+        let theirs = remote_ids.get(id).map(|&i| &remote[i]);
+        if let Some(resource) = merge_resource(id, original, ours, theirs, conflicts) {
+            merged.push(resource);
+        }
+    }
+    merged
+}
+
+/// Fold bookmarks added on both sides under the same URL (by
+/// [`urlnorm::key`]) into the one with the smallest ID, which takes every
+/// tag the others had and the earliest `created` time. Only the IDs decide
+/// which is kept, so both sides of a merge fold the same way.
+fn fold_concurrent_additions(
+    base: &[Resource],
+    local: &[Resource],
+    remote: &[Resource],
+    merged: &mut Vec<Resource>,
+) {
+    let base_ids = by_id(base);
+    // Added on `side` only; the same addition on both is already one bookmark
+    let added = |side: &[Resource], other: &[Resource]| {
+        let other_ids = by_id(other);
+        let mut by_key: HashMap<String, Vec<String>> = HashMap::new();
+        for resource in side {
+            if let Resource::Bookmark { id, attributes, .. } = resource {
+                if !base_ids.contains_key(id.as_str()) && !other_ids.contains_key(id.as_str()) {
+                    by_key
+                        .entry(urlnorm::key(&attributes.url))
+                        .or_default()
+                        .push(id.clone());
+                }
+            }
+        }
+        by_key
+    };
+    let local_added = added(local, remote);
+    let remote_added = added(remote, local);
+
+    // Folded ID → the ID it's folded into
+    let mut folded: HashMap<String, String> = HashMap::new();
+    for (key, local_ids) in local_added {
+        let Some(remote_ids) = remote_added.get(&key) else {
+            continue;
+        };
+        let ids: BTreeSet<String> = local_ids.into_iter().chain(remote_ids.clone()).collect();
+        let mut ids = ids.into_iter();
+        let Some(kept) = ids.next() else {
+            continue;
+        };
+        for id in ids {
+            folded.insert(id, kept.clone());
+        }
+    }
+    if folded.is_empty() {
+        return;
+    }
+
+    let mut taken: HashMap<String, (Vec<ResourceIdentifier>, DateTime<Utc>)> = HashMap::new();
+    merged.retain(|resource| {
+        let Resource::Bookmark {
+            id,
+            attributes,
+            relationships,
+            ..
+        } = resource
+        else {
+            return true;
+        };
+        let Some(kept) = folded.get(id) else {
+            return true;
+        };
+        let entry = taken
+            .entry(kept.clone())
+            .or_insert_with(|| (Vec::new(), attributes.created));
+        entry.0.extend(
+            relationships
+                .iter()
+                .filter_map(|r| r.tags.as_ref())
+                .flat_map(|tags| tags.data.iter().cloned()),
+        );
+        entry.1 = entry.1.min(attributes.created);
+        false
+    });
+
+    for resource in merged.iter_mut() {
+        let Resource::Bookmark {
+            id,
+            attributes,
+            relationships,
+            ..
+        } = resource
+        else {
+            continue;
+        };
+        let Some((mut tags, created)) = taken.remove(id) else {
+            continue;
+        };
+        attributes.created = attributes.created.min(created);
+        let own = &mut relationships
+            .get_or_insert_with(|| BookmarkRelationships { tags: None })
+            .tags
+            .get_or_insert_with(|| RelationshipData { data: Vec::new() })
+            .data;
+        tags.sort_by(|a, b| a.id.cmp(&b.id));
+        for tag in tags {
+            if !own.iter().any(|existing| existing.id == tag.id) {
+                own.push(tag);
+            }
+        }
+    }
+}
+
+fn by_id(resources: &[Resource]) -> HashMap<&str, usize> {
+    resources
+        .iter()
+        .enumerate()
+        .map(|(i, resource)| (resource.id(), i))
+        .collect()
+}
+
+fn merge_resource(
+    id: &str,
+    base: Option<&Resource>,
+    local: Option<&Resource>,
+    remote: Option<&Resource>,
+    conflicts: &mut Vec<FieldConflict>,
+) -> Option<Resource> {
+    match (local, remote) {
+        (Some(ours), Some(theirs)) if ours == theirs => Some(ours.clone()),
+        (Some(ours), Some(theirs)) if base == Some(ours) => Some(theirs.clone()),
+        (Some(ours), Some(theirs)) if base == Some(theirs) => Some(ours.clone()),
+        (Some(ours), Some(theirs)) => Some(merge_fields(id, base, ours, theirs, conflicts)),
+        // Deleted on one side: gone, unless the other side changed it
+        (Some(kept), None) | (None, Some(kept)) if base == Some(kept) => None,
+        (Some(kept), None) | (None, Some(kept)) => {
+            if base.is_some() {
+                let kept_value = to_value(kept);
+                let (local, remote) = if local.is_some() {
+                    (kept_value.clone(), Value::Null)
+                } else {
+                    (Value::Null, kept_value.clone())
+                };
+                conflicts.push(FieldConflict {
+                    id: id.to_string(),
+                    field: String::new(),
+                    local,
+                    remote,
+                    merged: kept_value,
+                });
+            }
+            Some(kept.clone())
+        }
+        (None, None) => None,
+    }
+}
+
+fn merge_fields(
+    id: &str,
+    base: Option<&Resource>,
+    local: &Resource,
+    remote: &Resource,
+    conflicts: &mut Vec<FieldConflict>,
+) -> Resource {
+    let newer = modified(local).cmp(&modified(remote));
+    let mut context = FieldMerge {
+        id,
+        newer,
+        conflicts,
+    };
+    let merged = merge_value(
+        &base.map_or(Value::Null, to_value),
+        &to_value(local),
+        &to_value(remote),
+        "",
+        &mut context,
+    );
+    // A merge of two valid resources of different types can be invalid
+    serde_json::from_value(merged).unwrap_or_else(|_| {
+        if pick_local(newer, &to_value(local), &to_value(remote)) {
+            local.clone()
+        } else {
+            remote.clone()
+        }
+    })
+}
+
+struct FieldMerge<'a> {
+    id: &'a str,
+    /// How the local resource's `modified` time compares with the remote's
+    newer: Ordering,
+    conflicts: &'a mut Vec<FieldConflict>,
+}
+
+fn merge_value(
+    base: &Value,
+    local: &Value,
+    remote: &Value,
+    path: &str,
+    context: &mut FieldMerge,
+) -> Value {
+    if local == remote || remote == base {
+        return local.clone();
+    }
+    if local == base {
+        return remote.clone();
+    }
+    match (local, remote) {
+        (Value::Object(ours), Value::Object(theirs)) => {
+            let empty = Map::new();
+            let original = base.as_object().unwrap_or(&empty);
+            let keys: BTreeSet<&String> = ours.keys().chain(theirs.keys()).collect();
+            let mut merged = Map::new();
+            for key in keys {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                let value = merge_value(
+                    original.get(key).unwrap_or(&Value::Null),
+                    ours.get(key).unwrap_or(&Value::Null),
+                    theirs.get(key).unwrap_or(&Value::Null),
+                    &field,
+                    context,
+                );
+                // Absent and null mean the same to every optional field
+                if !value.is_null() || (ours.contains_key(key) && theirs.contains_key(key)) {
+                    merged.insert(key.clone(), value);
+                }
+            }
+            Value::Object(merged)
+        }
+        (Value::Array(ours), Value::Array(theirs)) if base.is_array() || base.is_null() => {
+            let empty = Vec::new();
+            let original = base.as_array().unwrap_or(&empty);
+            Value::Array(merge_set(original, ours, theirs))
+        }
+        _ => {
+            let merged = if pick_local(context.newer, local, remote) {
+                local.clone()
+            } else {
+                remote.clone()
+            };
+            context.conflicts.push(FieldConflict {
+                id: context.id.to_string(),
+                field: path.to_string(),
+                local: local.clone(),
+                remote: remote.clone(),
+                merged: merged.clone(),
+            });
+            merged
+        }
+    }
+}
+
+/// Observed-remove set merge: base elements both sides kept, in base order,
+/// then elements either side added, sorted
+fn merge_set(base: &[Value], local: &[Value], remote: &[Value]) -> Vec<Value> {
+    let mut merged: Vec<Value> = base
+        .iter()
+        .filter(|element| local.contains(element) && remote.contains(element))
+        .cloned()
+        .collect();
+    let added: BTreeSet<String> = local
+        .iter()
+        .chain(remote)
+        .filter(|element| !base.contains(element))
+        .map(Value::to_string)
+        .collect();
+    for element in added {
+        if let Ok(value) = serde_json::from_str(&element) {
+            if !merged.contains(&value) {
+                merged.push(value);
+            }
+        }
+    }
+    merged
+}
+
+/// Last writer wins, with the greater value breaking ties so both sides agree
+fn pick_local(newer: Ordering, local: &Value, remote: &Value) -> bool {
+    match newer {
+        Ordering::Greater => true,
+        Ordering::Less => false,
+        Ordering::Equal => {
+            let (local, remote) = (local.to_string(), remote.to_string());
+            local >= remote
+        }
+    }
+}
+
+fn modified(resource: &Resource) -> Option<chrono::DateTime<chrono::Utc>> {
+    match resource {
+        Resource::Bookmark { attributes, .. } => attributes.modified,
+        _ => None,
+    }
+}
+
+fn to_value(resource: &Resource) -> Value {
+    serde_json::to_value(resource).unwrap_or(Value::Null)
+}
+
+/// Which version of a conflicting field to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Local,
+    Remote,
+}
+
+/// The user's choice for one [`FieldConflict`], matched by `id` and `field`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConflictDecision {
+    pub id: String,
+    #[serde(default)]
+    pub field: String,
+    pub choice: Side,
+}
+
+fn conflicts_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(".git").join(CONFLICTS_FILE)
+}
+
+/// Conflicts waiting for a decision, or none if the file doesn't exist
+pub fn load_conflicts<P: AsRef<Path>>(repo_path: P) -> Result<Vec<FieldConflict>> {
+    let path = conflicts_path(repo_path.as_ref());
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(&path).context("Failed to read merge conflicts")?;
+    serde_json::from_str(&content).context("Failed to parse merge conflicts")
+}
+
+/// Save the conflicts waiting for a decision, removing the file once there
+/// are none
+pub fn save_conflicts<P: AsRef<Path>>(repo_path: P, conflicts: &[FieldConflict]) -> Result<()> {
+    let path = conflicts_path(repo_path.as_ref());
+    if conflicts.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).context("Failed to remove merge conflicts")?;
+        }
+        return Ok(());
+    }
+
+    let json = serde_json::to_string(conflicts).context("Failed to serialize merge conflicts")?;
+    storage::write_atomic(&path, json.as_bytes())?;
+
+    Ok(())
+}
+
+/// Replace the merged value of a conflicting field with the `choice` side's.
+/// A bookmark's `modified` time moves to now, so the decision wins the next
+/// merge against a device that still has the other value.
+pub fn resolve(data: &mut BookmarksData, conflict: &FieldConflict, choice: Side) -> Result<()> {
+    let chosen = match choice {
+        Side::Local => &conflict.local,
+        Side::Remote => &conflict.remote,
+    };
+    let current = data.get(&conflict.id).map(to_value);
+
+    let value = if conflict.field.is_empty() {
+        chosen.clone()
+    } else {
+        let mut value =
+            current.with_context(|| format!("Resource {} no longer exists", conflict.id))?;
+        set_path(&mut value, &conflict.field, chosen.clone());
+        if conflict.field != "attributes.modified" && value["type"] == "bookmark" {
+            value["attributes"]["modified"] = serde_json::to_value(chrono::Utc::now())?;
+        }
+        value
+    };
+
+    data.remove_resource(&conflict.id);
+    if value.is_null() {
+        return Ok(());
+    }
+    let resource: Resource =
+        serde_json::from_value(value).context("The chosen value is not valid here")?;
+    data.add_resource(resource);
+    Ok(())
+}
+
+/// Set the value at a dotted path, removing the key for `null`
+fn set_path(value: &mut Value, path: &str, new: Value) {
+    let (parents, key) = path.rsplit_once('.').unwrap_or(("", path));
+    let mut target = value;
+    for part in parents.split('.').filter(|part| !part.is_empty()) {
+        if !target[part].is_object() {
+            target[part] = Value::Object(Map::new());
+        }
+        target = &mut target[part];
+    }
+    let Some(object) = target.as_object_mut() else {
+        return;
+    };
+    if new.is_null() {
+        object.remove(key);
+    } else {
+        object.insert(key.to_string(), new);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage;
+    use proptest::prelude::*;
+
+    fn bookmark(url: &str, title: &str) -> Resource {
+        storage::create_bookmark(url.to_string(), title.to_string(), Vec::new())
+    }
+
+    fn with(resources: &[&Resource]) -> BookmarksData {
+        let mut data = BookmarksData::new();
+        *data.data_mut() = resources.iter().map(|&r| r.clone()).collect();
+        data
+    }
+
+    fn edit(
+        resource: &Resource,
+        change: impl FnOnce(&mut storage::BookmarkAttributes),
+    ) -> Resource {
+        let mut resource = resource.clone();
+        if let Resource::Bookmark { attributes, .. } = &mut resource {
+            change(attributes);
+        }
+        resource
+    }
+
+    fn set_tags(resource: &Resource, tags: &[&str]) -> Resource {
+        let mut resource = resource.clone();
+        if let Resource::Bookmark { relationships, .. } = &mut resource {
+            *relationships = Some(storage::BookmarkRelationships {
+                tags: Some(storage::RelationshipData {
+                    data: tags
+                        .iter()
+                        .map(|id| storage::ResourceIdentifier {
+                            resource_type: "tag".to_string(),
+                            id: (*id).to_string(),
+                        })
+                        .collect(),
+                }),
+            });
+        }
+        resource
+    }
+
+    fn title_of(data: &BookmarksData, id: &str) -> Option<String> {
+        match data.get(id)? {
+            Resource::Bookmark { attributes, .. } => Some(attributes.title.clone()),
+            _ => None,
+        }
+    }
+
+    fn tags_of(data: &BookmarksData, id: &str) -> Vec<String> {
+        match data.get(id) {
+            Some(Resource::Bookmark {
+                relationships: Some(relationships),
+                ..
+            }) => relationships
+                .tags
+                .iter()
+                .flat_map(|tags| tags.data.iter().map(|tag| tag.id.clone()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_merge_resources() {
+        let kept = bookmark("https://a.example.com", "A");
+        let edited_here = bookmark("https://b.example.com", "B");
+        let deleted_there = bookmark("https://c.example.com", "C");
+        let both_edited = bookmark("https://d.example.com", "D");
+        let base = with(&[&kept, &edited_here, &deleted_there, &both_edited]);
+
+        let added_here = bookmark("https://e.example.com", "E");
+        let local = with(&[
+            &kept,
+            &edit(&edited_here, |a| a.title = "B (local)".to_string()),
+            &deleted_there,
+            &edit(&both_edited, |a| a.title = "D (local)".to_string()),
+            &added_here,
+        ]);
+        let added_there = bookmark("https://f.example.com", "F");
+        let remote = with(&[
+            &kept,
+            &edited_here,
+            &edit(&both_edited, |a| a.title = "D (remote)".to_string()),
+            &added_there,
+        ]);
+
+        let (merged, conflicts) = merge(&base, &local, &remote);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].field, "attributes.title");
+        assert_eq!(merged.data().len(), 5);
+        assert_eq!(title_of(&merged, kept.id()).unwrap(), "A");
+        assert_eq!(title_of(&merged, edited_here.id()).unwrap(), "B (local)");
+        assert!(merged.get(deleted_there.id()).is_none());
+        // Neither edit is newer, so the greater value wins on both devices
+        assert_eq!(title_of(&merged, both_edited.id()).unwrap(), "D (remote)");
+        assert!(merged.get(added_here.id()).is_some());
+        assert!(merged.get(added_there.id()).is_some());
+    }
+
+    #[test]
+    fn test_concurrent_additions_of_one_page_fold() {
+        let existing = bookmark("https://a.example.com", "A");
+        let base = with(&[&existing]);
+        let mine = edit(
+            &set_tags(
+                &bookmark("https://www.shared.example/post", "Mine"),
+                &["t1"],
+            ),
+            |a| a.author = Some("Sam".to_string()),
+        );
+        let theirs = edit(
+            &set_tags(
+                &bookmark("https://shared.example/post/", "Theirs"),
+                &["t2", "t1"],
+            ),
+            |a| a.author = Some("Alex".to_string()),
+        );
+        let other = bookmark("https://other.example", "Other");
+        let local = with(&[&existing, &mine, &other]);
+        let remote = with(&[&existing, &theirs]);
+
+        let (merged, conflicts) = merge(&base, &local, &remote);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.data().len(), 3);
+        let (kept, gone) = if mine.id() < theirs.id() {
+            (&mine, &theirs)
+        } else {
+            (&theirs, &mine)
+        };
+        assert!(merged.get(gone.id()).is_none());
+        let mut tags = tags_of(&merged, kept.id());
+        tags.sort();
+        assert_eq!(tags, vec!["t1", "t2"]);
+        assert!(merged.get(other.id()).is_some());
+
+        // Either device merging gets the same result
+        let (swapped, _) = merge(&base, &remote, &local);
+        let ids = |data: &BookmarksData| {
+            let mut ids: Vec<String> = data.data().iter().map(|r| r.id().to_string()).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&swapped), ids(&merged));
+        assert_eq!(tags_of(&swapped, kept.id()), tags_of(&merged, kept.id()));
+    }
+
+    #[test]
+    fn test_different_fields_both_survive() {
+        let original = bookmark("https://a.example.com", "A");
+        let base = with(&[&original]);
+        let local = with(&[&edit(&original, |a| a.title = "Retitled".to_string())]);
+        let remote = with(&[&edit(&original, |a| a.notes = Some("Noted".to_string()))]);
+
+        let (merged, conflicts) = merge(&base, &local, &remote);
+        assert!(conflicts.is_empty());
+        let Some(Resource::Bookmark { attributes, .. }) = merged.get(original.id()) else {
+            panic!("bookmark missing");
+        };
+        assert_eq!(attributes.title, "Retitled");
+        assert_eq!(attributes.notes.as_deref(), Some("Noted"));
+    }
+
+    #[test]
+    fn test_tags_are_an_observed_remove_set() {
+        let original = set_tags(&bookmark("https://a.example.com", "A"), &["rust", "web"]);
+        let base = with(&[&original]);
+        // One side drops "web" and adds "async"; the other adds "tokio"
+        let local = with(&[&set_tags(&original, &["rust", "async"])]);
+        let remote = with(&[&set_tags(&original, &["rust", "web", "tokio"])]);
+
+        let (merged, conflicts) = merge(&base, &local, &remote);
+        assert!(conflicts.is_empty());
+        assert_eq!(tags_of(&merged, original.id()), ["rust", "async", "tokio"]);
+    }
+
+    #[test]
+    fn test_later_modification_wins() {
+        let original = bookmark("https://a.example.com", "A");
+        let base = with(&[&original]);
+        let earlier = chrono::Utc::now();
+        let later = earlier + chrono::Duration::seconds(1);
+        let local = with(&[&edit(&original, |a| {
+            a.title = "Z (earlier)".to_string();
+            a.modified = Some(earlier);
+        })]);
+        let remote = with(&[&edit(&original, |a| {
+            a.title = "A (later)".to_string();
+            a.modified = Some(later);
+        })]);
+
+        for (ours, theirs) in [(&local, &remote), (&remote, &local)] {
+            let (merged, _) = merge(&base, ours, theirs);
+            assert_eq!(title_of(&merged, original.id()).unwrap(), "A (later)");
+        }
+    }
+
+    #[test]
+    fn test_edit_beats_delete() {
+        let original = bookmark("https://a.example.com", "A");
+        let base = with(&[&original]);
+        let edited = with(&[&edit(&original, |a| a.unread = true)]);
+        let deleted = with(&[]);
+
+        for (ours, theirs) in [(&edited, &deleted), (&deleted, &edited)] {
+            let (merged, conflicts) = merge(&base, ours, theirs);
+            assert!(merged.get(original.id()).is_some());
+            assert_eq!(conflicts.len(), 1);
+            assert!(conflicts[0].field.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_resolve_keeps_chosen_side() {
+        let original = bookmark("https://a.example.com", "A");
+        let base = with(&[&original]);
+        let local = with(&[&edit(&original, |a| a.title = "Local".to_string())]);
+        let remote = with(&[&edit(&original, |a| a.title = "Remote".to_string())]);
+
+        let (mut merged, conflicts) = merge(&base, &local, &remote);
+        assert_eq!(title_of(&merged, original.id()).unwrap(), "Remote");
+        resolve(&mut merged, &conflicts[0], Side::Local).unwrap();
+        let Some(Resource::Bookmark { attributes, .. }) = merged.get(original.id()) else {
+            panic!("bookmark missing");
+        };
+        assert_eq!(attributes.title, "Local");
+        // Newer than either side, so the decision sticks on the next merge
+        assert!(attributes.modified.is_some());
+    }
+
+    #[test]
+    fn test_resolve_delete_conflict() {
+        let original = bookmark("https://a.example.com", "A");
+        let base = with(&[&original]);
+        let edited = with(&[&edit(&original, |a| a.unread = true)]);
+
+        let (mut merged, conflicts) = merge(&base, &with(&[]), &edited);
+        assert!(merged.get(original.id()).is_some());
+        resolve(&mut merged, &conflicts[0], Side::Local).unwrap();
+        assert!(merged.get(original.id()).is_none());
+        resolve(&mut merged, &conflicts[0], Side::Remote).unwrap();
+        assert!(merged.get(original.id()).is_some());
+    }
+
+    #[test]
+    fn test_save_and_load_conflicts() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join(".git")).unwrap();
+        assert!(load_conflicts(temp_dir.path()).unwrap().is_empty());
+
+        let conflict = FieldConflict {
+            id: "b1".to_string(),
+            field: "attributes.title".to_string(),
+            local: Value::from("Local"),
+            remote: Value::from("Remote"),
+            merged: Value::from("Remote"),
+        };
+        save_conflicts(temp_dir.path(), std::slice::from_ref(&conflict)).unwrap();
+        assert_eq!(load_conflicts(temp_dir.path()).unwrap(), [conflict]);
+
+        save_conflicts(temp_dir.path(), &[]).unwrap();
+        assert!(!temp_dir.path().join(".git").join(CONFLICTS_FILE).exists());
+    }
+
+    /// One edit a device might make
+    #[derive(Debug, Clone)]
+    enum Edit {
+        Title(usize, u8),
+        Notes(usize, Option<u8>),
+        Unread(usize, bool),
+        AddTag(usize, u8),
+        RemoveTag(usize, u8),
+        Touch(usize, i64),
+        Delete(usize),
+        Add(u8),
+    }
+
+    fn edit_strategy() -> impl Strategy<Value = Edit> {
+        prop_oneof![
+            (0..4usize, 0..3u8).prop_map(|(i, v)| Edit::Title(i, v)),
+            (0..4usize, proptest::option::of(0..3u8)).prop_map(|(i, v)| Edit::Notes(i, v)),
+            (0..4usize, any::<bool>()).prop_map(|(i, v)| Edit::Unread(i, v)),
+            (0..4usize, 0..4u8).prop_map(|(i, t)| Edit::AddTag(i, t)),
+            (0..4usize, 0..4u8).prop_map(|(i, t)| Edit::RemoveTag(i, t)),
+            (0..4usize, 0..3i64).prop_map(|(i, s)| Edit::Touch(i, s)),
+            (0..4usize).prop_map(Edit::Delete),
+            (0..3u8).prop_map(Edit::Add),
+        ]
+    }
+
+    fn base_collection() -> BookmarksData {
+        let resources: Vec<Resource> = (0..4)
+            .map(|i| {
+                set_tags(
+                    &bookmark(&format!("https://{i}.example.com"), &format!("B{i}")),
+                    &["t0", "t1"],
+                )
+            })
+            .collect();
+        with(&resources.iter().collect::<Vec<_>>())
+    }
+
+    fn apply(base: &BookmarksData, edits: &[Edit]) -> BookmarksData {
+        let epoch = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let mut resources = base.data().to_vec();
+        let ids: Vec<String> = base.data().iter().map(|r| r.id().to_string()).collect();
+        for change in edits {
+            let position = |i: &usize| resources.iter().position(|r| r.id() == ids[*i]);
+            match change {
+                Edit::Delete(i) => {
+                    if let Some(at) = position(i) {
+                        resources.remove(at);
+                    }
+                }
+                Edit::Add(v) => {
+                    resources.push(bookmark(&format!("https://new{v}.example.com"), "New"));
+                }
+                Edit::AddTag(i, t) | Edit::RemoveTag(i, t) => {
+                    if let Some(at) = position(i) {
+                        let tag = format!("t{t}");
+                        let mut tags = {
+                            let data = with(&[&resources[at]]);
+                            tags_of(&data, &ids[*i])
+                        };
+                        tags.retain(|existing| *existing != tag);
+                        if matches!(change, Edit::AddTag(..)) {
+                            tags.push(tag);
+                        }
+                        let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+                        resources[at] = set_tags(&resources[at], &tags);
+                    }
+                }
+                Edit::Title(i, _) | Edit::Notes(i, _) | Edit::Unread(i, _) | Edit::Touch(i, _) => {
+                    if let Some(at) = position(i) {
+                        resources[at] = edit(&resources[at], |a| match change {
+                            Edit::Title(_, v) => a.title = format!("T{v}"),
+                            Edit::Notes(_, v) => a.notes = v.map(|v| format!("N{v}")),
+                            Edit::Unread(_, v) => a.unread = *v,
+                            Edit::Touch(_, s) => {
+                                a.modified = Some(epoch + chrono::Duration::seconds(*s));
+                            }
+                            _ => {}
+                        });
+                    }
+                }
+            }
+        }
+        with(&resources.iter().collect::<Vec<_>>())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_merge_converges(
+            local_edits in proptest::collection::vec(edit_strategy(), 0..8),
+            remote_edits in proptest::collection::vec(edit_strategy(), 0..8),
+        ) {
+            let base = base_collection();
+            let local = apply(&base, &local_edits);
+            let remote = apply(&base, &remote_edits);
+
+            let (ours, _) = merge(&base, &local, &remote);
+            let (theirs, _) = merge(&base, &remote, &local);
+            prop_assert_eq!(ours.data(), theirs.data());
+        }
+
+        #[test]
+        fn prop_merge_keeps_one_sided_changes(
+            edits in proptest::collection::vec(edit_strategy(), 0..8),
+        ) {
+            let base = base_collection();
+            let changed = apply(&base, &edits);
+
+            let (merged, conflicts) = merge(&base, &base, &changed);
+            prop_assert_eq!(merged.data(), changed.data());
+            prop_assert!(conflicts.is_empty());
+            let (merged, _) = merge(&base, &changed, &changed);
+            prop_assert_eq!(merged.data(), changed.data());
+        }
+
+        #[test]
+        fn prop_merged_values_come_from_a_side(
+            local_edits in proptest::collection::vec(edit_strategy(), 0..8),
+            remote_edits in proptest::collection::vec(edit_strategy(), 0..8),
+        ) {
+            let base = base_collection();
+            let local = apply(&base, &local_edits);
+            let remote = apply(&base, &remote_edits);
+
+            let (merged, _) = merge(&base, &local, &remote);
+            for resource in merged.data() {
+                let title = title_of(&merged, resource.id());
+                prop_assert!(
+                    title == title_of(&local, resource.id())
+                        || title == title_of(&remote, resource.id())
+                );
+            }
+        }
+    }
+}
@@ -1,29 +1,939 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+
+use crate::bulk::{BulkFilter, BulkOp};
+use crate::compression::{self, Compression};
+use crate::encryption::{Cipher, EncryptionMode};
+use crate::error::{ErrorCategory, HostError};
+use crate::expiry;
+use crate::hooks;
+use crate::import::{BrowserNode, ImportFormat, ImportOptions};
+use crate::linkcheck;
+use crate::merge::{ConflictDecision, FieldConflict};
+use crate::query::ReadOptions;
+use crate::report::ReportKind;
+use crate::retry;
+use crate::snapshots::Schedule;
+use crate::storage::{BookmarksData, Resource, SortOrder};
+use crate::sync::BackendConfig;
+use crate::tag_ops::TagOp;
+use crate::urlnorm;
+use crate::webhooks;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::{Read, Write};
+use std::sync::{Mutex, PoisonError};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+/// Current native messaging protocol version
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Protocol versions this host can speak. Version 1 is the original,
+/// pre-handshake protocol used by extensions that never send `Hello`.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u32] = &[1, 2];
+
+/// Optional features negotiated during the `Hello` handshake
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    ChunkedPayloads,
+    Indexes,
+    LocalActivity,
+    KeyTransfer,
+    RecoveryCodes,
+    KeyCache,
+    FieldEncryption,
+    #[serde(rename = "xchacha20")]
+    XChaCha20,
+    Diff,
+    Config,
+    ShallowClone,
+    Maintenance,
+    CommitBatching,
+    DeviceIdentity,
+    MultipleRemotes,
+    SshKey,
+    Doctor,
+    Logs,
+    Metrics,
+    Shutdown,
+    Repair,
+    ReadLater,
+    SavedSearches,
+    Rules,
+    TagSuggestions,
+    Pagination,
+    Import,
+    Progress,
+    StaticSite,
+    MarkdownExport,
+    SyncBackends,
+    Reauth,
+    RepoPicker,
+    SignOut,
+    AuthCancel,
+    ConflictResolution,
+    Notes,
+    TagOps,
+    Reports,
+    Stats,
+    BulkOps,
+    Undo,
+    Backup,
+    ScheduledBackups,
+    Bundles,
+    Duplicates,
+    WriteDelta,
+    Compression,
+    Visibility,
+    HistoryReset,
+    Archives,
+    LinkMonitoring,
+    Enrichment,
+    ContentSuggestions,
+    Expiration,
+    Pinned,
+    TagTree,
+    TagCounts,
+}
+
+impl Capability {
+    pub const ALL: &'static [Capability] = &[
+        Capability::ChunkedPayloads,
+        Capability::Indexes,
+        Capability::LocalActivity,
+        Capability::KeyTransfer,
+        Capability::RecoveryCodes,
+        Capability::KeyCache,
+        Capability::FieldEncryption,
+        Capability::XChaCha20,
+        Capability::Diff,
+        Capability::Config,
+        Capability::ShallowClone,
+        Capability::Maintenance,
+        Capability::CommitBatching,
+        Capability::DeviceIdentity,
+        Capability::MultipleRemotes,
+        Capability::SshKey,
+        Capability::Doctor,
+        Capability::Logs,
+        Capability::Metrics,
+        Capability::Shutdown,
+        Capability::Repair,
+        Capability::ReadLater,
+        Capability::SavedSearches,
+        Capability::Rules,
+        Capability::TagSuggestions,
+        Capability::Pagination,
+        Capability::Import,
+        Capability::Progress,
+        Capability::StaticSite,
+        Capability::MarkdownExport,
+        Capability::SyncBackends,
+        Capability::Reauth,
+        Capability::RepoPicker,
+        Capability::SignOut,
+        Capability::AuthCancel,
+        Capability::ConflictResolution,
+        Capability::Notes,
+        Capability::TagOps,
+        Capability::Reports,
+        Capability::Stats,
+        Capability::BulkOps,
+        Capability::Undo,
+        Capability::Backup,
+        Capability::ScheduledBackups,
+        Capability::Bundles,
+        Capability::Duplicates,
+        Capability::WriteDelta,
+        Capability::Compression,
+        Capability::Visibility,
+        Capability::HistoryReset,
+        Capability::Archives,
+        Capability::LinkMonitoring,
+        Capability::Enrichment,
+        Capability::ContentSuggestions,
+        Capability::Expiration,
+        Capability::Pinned,
+        Capability::TagTree,
+        Capability::TagCounts,
+    ];
+
+    /// First protocol version that offers this capability
+    pub fn since_version(self) -> u32 {
+        match self {
+            Capability::ChunkedPayloads
+            | Capability::Indexes
+            | Capability::LocalActivity
+            | Capability::KeyTransfer
+            | Capability::RecoveryCodes
+            | Capability::KeyCache
+            | Capability::FieldEncryption
+            | Capability::XChaCha20
+            | Capability::Diff
+            | Capability::Config
+            | Capability::ShallowClone
+            | Capability::Maintenance
+            | Capability::CommitBatching
+            | Capability::DeviceIdentity
+            | Capability::MultipleRemotes
+            | Capability::SshKey
+            | Capability::Doctor
+            | Capability::Logs
+            | Capability::Metrics
+            | Capability::Shutdown
+            | Capability::Repair
+            | Capability::ReadLater
+            | Capability::SavedSearches
+            | Capability::Rules
+            | Capability::TagSuggestions
+            | Capability::Pagination
+            | Capability::Import
+            | Capability::Progress
+            | Capability::StaticSite
+            | Capability::MarkdownExport
+            | Capability::SyncBackends
+            | Capability::Reauth
+            | Capability::RepoPicker
+            | Capability::SignOut
+            | Capability::AuthCancel
+            | Capability::ConflictResolution
+            | Capability::Notes
+            | Capability::TagOps
+            | Capability::Reports
+            | Capability::Stats
+            | Capability::BulkOps
+            | Capability::Undo
+            | Capability::Backup
+            | Capability::ScheduledBackups
+            | Capability::Bundles
+            | Capability::Duplicates
+            | Capability::WriteDelta
+            | Capability::Compression
+            | Capability::Visibility
+            | Capability::HistoryReset
+            | Capability::Archives
+            | Capability::LinkMonitoring
+            | Capability::Enrichment
+            | Capability::ContentSuggestions
+            | Capability::Expiration
+            | Capability::Pinned
+            | Capability::TagTree
+            | Capability::TagCounts => 2,
+        }
+    }
+}
+
+/// Pick the protocol version to use for a client requesting `requested`
+pub fn negotiate_version(requested: u32) -> Result<u32> {
+    let newest = requested.min(PROTOCOL_VERSION);
+    if SUPPORTED_PROTOCOL_VERSIONS.contains(&newest) {
+        Ok(newest)
+    } else {
+        anyhow::bail!("Unsupported protocol version: {requested}")
+    }
+}
+
+/// Capabilities available at a given protocol version
+pub fn capabilities_for(version: u32) -> Vec<Capability> {
+    Capability::ALL
+        .iter()
+        .copied()
+        .filter(|c| c.since_version() <= version)
+        .collect()
+}
+
 /// Message types supported by the native messaging protocol
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Message {
+    /// Protocol handshake; should be the first message a client sends
+    Hello {
+        protocol_version: u32,
+        /// Compression the client can decode, most preferred first
+        /// (`gzip`, `zstd`); the host picks the first it supports
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        compression: Vec<String>,
+    },
     Init {
         repo_path: Option<String>,
         repo_url: Option<String>,
+        /// Shallow-clone `repo_url` with only this many commits of history
+        #[serde(default)]
+        depth: Option<u32>,
+        /// Name shown in commit authors and `Device:` trailers; defaults to
+        /// the hostname
+        #[serde(default)]
+        device_name: Option<String>,
+        /// Where to sync; git (with `repo_url` as the remote) if unset
+        #[serde(default)]
+        backend: Option<BackendConfig>,
     },
     Write {
-        data: serde_json::Value,
+        data: BookmarksData,
+        /// Reject fields this version doesn't know about instead of storing
+        /// them as they are
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        strict: bool,
+    },
+    /// Only what changed since the extension's last read or write, applied
+    /// to the stored bookmarks and committed like `Write`
+    WriteDelta {
+        #[serde(default)]
+        added: Vec<Resource>,
+        #[serde(default)]
+        updated: Vec<Resource>,
+        /// IDs of deleted resources
+        #[serde(default)]
+        removed: Vec<String>,
+    },
+    /// Everything, or with any options set a page of bookmarks (with all
+    /// tags in `included` and the match count in `meta.page`). A page is
+    /// not a whole collection and must not be written back.
+    Read {
+        #[serde(flatten)]
+        options: ReadOptions,
     },
-    Read,
     Sync,
     Auth {
         method: AuthMethod,
         token: Option<String>,
     },
     Status,
-    EnableEncryption,
+    EnableEncryption {
+        /// Omitted by older clients, which always get whole-file encryption
+        #[serde(default)]
+        mode: EncryptionMode,
+        /// Cipher for new encryptions (defaults to AES-256-GCM)
+        #[serde(default)]
+        cipher: Cipher,
+    },
     DisableEncryption,
     EncryptionStatus,
+    /// Start a chunked transfer of a message too large for a single frame
+    BeginPayload {
+        payload_id: String,
+        total_size: usize,
+        total_chunks: usize,
+    },
+    /// One slice of the serialized inner message
+    PayloadChunk {
+        payload_id: String,
+        index: usize,
+        data: String,
+    },
+    /// Finish a chunked transfer; the reassembled message is then handled
+    EndPayload {
+        payload_id: String,
+    },
+    /// Discard and regenerate all derived index artifacts from bookmarks.json
+    RebuildIndexes,
+    /// Record a visit to a bookmark in the local-only activity log
+    RecordVisit {
+        bookmark_id: String,
+    },
+    /// Erase all local activity data
+    ClearActivity,
+    /// Export the encryption key wrapped with a passphrase
+    ExportEncryptionKey {
+        passphrase: String,
+    },
+    /// Import an encryption key exported from another device
+    ImportEncryptionKey {
+        passphrase: String,
+        blob: String,
+    },
+    /// Check a recovery code, optionally restoring the key to the Keychain
+    VerifyRecoveryCode {
+        code: String,
+        #[serde(default)]
+        restore: bool,
+    },
+    /// Forget the cached encryption key so the next operation prompts for Touch ID
+    LockNow,
+    /// Set how long the encryption key stays cached (0 disables caching, at most 24 hours)
+    SetKeyCacheTtl {
+        ttl_secs: u64,
+    },
+    /// Summarize bookmark and tag changes between two commits
+    Diff {
+        from: String,
+        /// Defaults to `HEAD`
+        #[serde(default)]
+        to: Option<String>,
+    },
+    /// Fetch more history for a shallow clone (`depth` more commits from the
+    /// remote tip, or everything when omitted)
+    Deepen {
+        #[serde(default)]
+        depth: Option<u32>,
+    },
+    /// Garbage-collect the repository, optionally squashing history older
+    /// than `squash_older_than_days` into a single baseline commit. Expired
+    /// bookmarks are dealt with first.
+    Maintain {
+        #[serde(default)]
+        squash_older_than_days: Option<u32>,
+        /// Squashing rewrites history, so with a remote it only happens once
+        /// the user has agreed to a force push
+        #[serde(default)]
+        confirm_force_push: bool,
+    },
+    /// Start again from the remote's history after it was rewritten
+    /// (`ERR_HISTORY_REWRITTEN`), backing up the bookmarks file first.
+    /// Local commits are dropped; with `keep_local_changes` the changes they
+    /// made since the last sync are merged back in and committed.
+    ResetToRemote {
+        #[serde(default)]
+        keep_local_changes: bool,
+    },
+    /// Change host settings. Omitted fields are left as they are.
+    SetConfig {
+        /// HTTP(S) proxy URL for GitHub and git traffic; an empty string
+        /// clears it and falls back to the environment
+        #[serde(default)]
+        proxy: Option<String>,
+        /// Batch writes made within this many seconds into one commit (0 disables)
+        #[serde(default)]
+        commit_window_secs: Option<u64>,
+        /// Commit message template with `{added}`, `{removed}`, `{modified}`,
+        /// `{bookmarks}`, `{tags}`, `{device}`, and `{timestamp}` placeholders;
+        /// an empty string restores the default
+        #[serde(default)]
+        commit_template: Option<String>,
+        /// Private key file for SSH remotes, tried before the SSH agent; an
+        /// empty string clears it
+        #[serde(default)]
+        ssh_key_path: Option<String>,
+        /// Take local backups of the bookmarks file on this schedule
+        #[serde(default)]
+        backup_schedule: Option<Schedule>,
+        /// Scheduled backups to keep; older ones are deleted
+        #[serde(default)]
+        backup_keep: Option<usize>,
+        /// Have `Sync` push this device's commits to its own `device/<name>`
+        /// branch and merge every device's branch into `main`, so devices
+        /// online at the same time don't race to push `main`. Needs a device
+        /// name.
+        #[serde(default)]
+        device_branches: Option<bool>,
+        /// Commands run before WebTags commits and after it syncs
+        #[serde(default)]
+        hooks: Option<hooks::Update>,
+        /// URL to post a signed summary to after writes and syncs that
+        /// change bookmarks, with the secret to sign it
+        #[serde(default)]
+        webhook: Option<webhooks::Update>,
+        /// How often links are checked on their own, and how many failures in
+        /// a row make a bookmark a dead link
+        #[serde(default)]
+        link_check: Option<linkcheck::Update>,
+        /// Whether expired bookmarks are tagged or deleted
+        #[serde(default)]
+        expiry: Option<expiry::Update>,
+        /// Attempts (1 to 10) and first delay in milliseconds (up to 8000)
+        /// for network operations that fail with a transient error
+        #[serde(default)]
+        retry: Option<retry::Update>,
+        /// Longest bookmark URL accepted, up to 32768
+        #[serde(default)]
+        max_url_length: Option<usize>,
+        /// Longest bookmark title accepted, up to 4096
+        #[serde(default)]
+        max_title_length: Option<usize>,
+        /// Longest tag name accepted, up to 255
+        #[serde(default)]
+        max_tag_length: Option<usize>,
+        /// Most tags in one breadcrumb, from 1 (top-level tags only) to 32
+        #[serde(default)]
+        max_tag_depth: Option<usize>,
+        /// URL schemes allowed besides http and https, e.g. `["ftp",
+        /// "magnet"]`; an empty list allows only those two again.
+        /// `javascript` and `data` are never allowed.
+        #[serde(default)]
+        allowed_schemes: Option<Vec<String>>,
+        /// How URLs are cleaned up on import and compared when looking for
+        /// duplicates or searching with `url:`
+        #[serde(default)]
+        url_normalization: Option<urlnorm::Update>,
+    },
+    /// Commit batched writes now instead of waiting for the window to close
+    Flush,
+    /// Recent commits, newest first, optionally only those from one device
+    History {
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        device: Option<String>,
+    },
+    /// Add a remote that every `Write` and `Sync` also pushes to
+    AddRemote {
+        name: String,
+        url: String,
+    },
+    /// Answer a `NeedsSecret` response, then resend the original request
+    ProvideSecret {
+        secret: SecretKind,
+        value: String,
+    },
+    /// Check the repository for interrupted merges, stale locks, a broken
+    /// HEAD, or an unreadable bookmarks file, repairing what is safe to
+    Doctor,
+    /// Change how much the host logs: `off`, `error`, `warn`, `info`,
+    /// `debug`, or `trace`
+    SetLogLevel {
+        level: String,
+    },
+    /// The most recent lines of the host's log file
+    GetLogs {
+        #[serde(default)]
+        lines: Option<usize>,
+    },
+    /// Local-only counters and latencies for a diagnostics page; `reset`
+    /// zeroes them after reporting
+    Metrics {
+        #[serde(default)]
+        reset: bool,
+    },
+    /// Commit batched writes, push or persist queued syncs, and exit after
+    /// replying. Safer than closing stdin, which gives no chance to report
+    /// a failed final commit.
+    Shutdown,
+    /// Drop bookmark tags, rule tags, and tag parents that point at missing
+    /// tags, break cycles in the tag hierarchy, normalize titles and tag
+    /// names to NFC, merge sibling tags whose names differ only in case or
+    /// normalization form, and commit the result
+    Repair,
+    /// Clear a bookmark's `unread` flag and commit the change
+    MarkRead {
+        id: String,
+    },
+    /// Bookmarks still marked `unread`, oldest first
+    ListUnread,
+    /// Every tag nested under its parent, for rendering the sidebar in one
+    /// request (see [`crate::storage::TagNode`])
+    TagTree,
+    /// Bookmarks per tag, both tagged with it directly and through the tags
+    /// below it, keyed by tag ID (see [`crate::storage::TagCounts`])
+    TagCounts,
+    /// Pinned bookmarks, in their arranged order
+    ListPinned,
+    /// Arrange the pinned bookmarks in the order of `ids`, which must list
+    /// every pinned bookmark exactly once, and commit the change
+    ReorderPinned {
+        ids: Vec<String>,
+    },
+    /// Store a named query (see [`crate::query::Query`]) and commit it
+    CreateSavedSearch {
+        name: String,
+        query: String,
+        #[serde(default)]
+        sort: SortOrder,
+    },
+    ListSavedSearches,
+    /// Bookmarks matching a saved search, in its sort order
+    RunSavedSearch {
+        id: String,
+    },
+    /// Run every rule over all bookmarks, not just newly added ones, and
+    /// commit the tags they add
+    ApplyRules,
+    /// Existing tags that fit a bookmark about to be saved, best first
+    SuggestTags {
+        url: String,
+        #[serde(default)]
+        title: String,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Add the items in another service's export file. With `dry_run`,
+    /// only report what would be created and merged.
+    Import {
+        format: ImportFormat,
+        content: String,
+        #[serde(default)]
+        dry_run: bool,
+        #[serde(flatten)]
+        options: ImportOptions,
+    },
+    /// Render the bookmarks as a static site in `site/` and commit it, for
+    /// GitHub Pages to serve
+    ExportSite,
+    /// Write a Markdown note per bookmark and a page per tag into `path`, an
+    /// absolute directory that is empty or holds an earlier export
+    ExportMarkdown {
+        path: String,
+        #[serde(default)]
+        include_private: bool,
+    },
+    /// Write only the public bookmarks and tags to `path`, an absolute file
+    /// path, or without one to `public/bookmarks.json` in the repository and
+    /// commit it
+    PublishPublic {
+        #[serde(default)]
+        path: Option<String>,
+    },
+    /// Keep a copy of a bookmarked page (base64 `content`) in
+    /// `archive/<id>`, as a Git LFS object so clones stay small
+    StoreArchive {
+        id: String,
+        content: String,
+    },
+    /// The archived copy of a bookmark's page, downloaded from the LFS
+    /// server if this device doesn't have it
+    GetArchive {
+        id: String,
+    },
+    /// Add the browser's own bookmarks, as `chrome.bookmarks.getTree()`
+    /// returns them; folders become tags
+    ImportBrowserTree {
+        tree: Vec<BrowserNode>,
+        #[serde(default)]
+        dry_run: bool,
+        #[serde(flatten)]
+        options: ImportOptions,
+    },
+    /// Sign in to GitHub again after the stored token expired or was
+    /// rejected. Without a `device_code` this starts the device flow and
+    /// answers `authflow`; sent again with that code once the user has
+    /// entered it, it waits for GitHub to issue the new token and stores it.
+    /// While waiting, clients with the `progress` capability get `auth`
+    /// progress events counting seconds (`total - done` is the time left).
+    Reauth {
+        #[serde(default)]
+        device_code: Option<String>,
+    },
+    /// The signed-in user's GitHub repositories whose names contain `query`,
+    /// most recently updated first, for picking one during setup
+    ListRepos {
+        #[serde(default)]
+        query: Option<String>,
+    },
+    /// Revoke and forget the GitHub token. With `remove_remote`, also stop
+    /// syncing with GitHub: the `origin` remote, or the Gist backend, is
+    /// removed and its queued pushes dropped.
+    SignOut {
+        #[serde(default)]
+        remove_remote: bool,
+    },
+    /// Stop waiting for the user to approve a device flow. Takes effect as
+    /// soon as it arrives, even while a `reauth` for the code is running.
+    AuthCancel {
+        device_code: String,
+    },
+    /// Settle conflicts reported by `sync`, keeping the chosen side of each
+    /// field, and commit the result. Conflicts without a decision are left
+    /// as merged and can be settled later.
+    ResolveConflicts {
+        decisions: Vec<ConflictDecision>,
+    },
+    /// Change one bookmark's title and/or notes and commit it. Notes are
+    /// Markdown; raw HTML is stripped, and empty notes remove them.
+    UpdateBookmark {
+        id: String,
+        #[serde(default)]
+        title: Option<String>,
+        #[serde(default)]
+        notes: Option<String>,
+    },
+    /// Apply tag operations in order and commit the result once; if any
+    /// fails, nothing is saved
+    UpdateTags {
+        ops: Vec<TagOp>,
+    },
+    /// Generate a maintenance report over the whole collection. For
+    /// `stale`: bookmarks never visited or not visited for `months`
+    /// (default 6), and with `check_domains`, those on domains that no
+    /// longer resolve. For `expiring`: bookmarks past their `expires_at` or
+    /// reaching it within `days` (default 7).
+    Report {
+        kind: ReportKind,
+        #[serde(default)]
+        months: Option<u32>,
+        #[serde(default)]
+        check_domains: bool,
+        #[serde(default)]
+        days: Option<u32>,
+    },
+    /// Check every http(s) bookmark's link now, record the results in
+    /// `linkstatus.json`, and tag bookmarks that keep failing `dead-link`
+    CheckLinks,
+    /// Fetch bookmarked pages to record their content type, word count and
+    /// reading time, and language. Only bookmarks not enriched yet are
+    /// fetched, unless `refresh` is set. With `suggest_tags`, tags picked
+    /// from each page's text are stored as its `suggested_tags`.
+    Enrich {
+        #[serde(default)]
+        refresh: bool,
+        #[serde(default)]
+        suggest_tags: bool,
+    },
+    /// Add some of a bookmark's suggested tags and drop others; either way
+    /// they leave `suggested_tags`
+    ReviewSuggestedTags {
+        id: String,
+        #[serde(default)]
+        accept: Vec<String>,
+        #[serde(default)]
+        dismiss: Vec<String>,
+    },
+    /// Aggregate numbers about the collection (bookmarks per tag, per
+    /// domain, and per month, untagged count, note coverage) for dashboards
+    Stats,
+    /// Apply one operation to every bookmark `filter` matches (or, for
+    /// `movetag`, to a tag) and commit the result once
+    BulkOp {
+        #[serde(default)]
+        filter: BulkFilter,
+        op: BulkOp,
+    },
+    /// Revert the last `count` (default 1) deletes, bulk operations, or
+    /// sync merges and commit the result
+    Undo {
+        #[serde(default)]
+        count: Option<usize>,
+    },
+    /// Write a passphrase-encrypted backup of the bookmarks, favicons, and
+    /// settings (no secrets) to `path`
+    Backup {
+        path: String,
+        passphrase: String,
+    },
+    /// Unpack a backup into a new repository (`repo_path`, like `Init`) and
+    /// use it from then on
+    Restore {
+        path: String,
+        passphrase: String,
+        #[serde(default)]
+        repo_path: Option<String>,
+    },
+    /// Scheduled local backups, newest first
+    ListBackups,
+    /// Put a scheduled backup back as the current bookmarks and commit it
+    RestoreBackup {
+        id: String,
+    },
+    /// Write the bookmark history to a git bundle file at `path`, to carry
+    /// to a machine with no remote in common: all of it, or only the commits
+    /// after `since` (a commit the other machine already has)
+    ExportBundle {
+        path: String,
+        #[serde(default)]
+        since: Option<String>,
+    },
+    /// Merge in the history from a bundle written by `ExportBundle`
+    ImportBundle {
+        path: String,
+    },
+    /// Bookmarks saved more than once under URLs that are the same page, and
+    /// ones on the same site with nearly the same title
+    FindDuplicates,
+}
+
+/// A secret the host must ask the user for mid-request
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretKind {
+    /// Passphrase for the key set with `SetConfig { ssh_key_path }`
+    SshPassphrase,
+}
+
+impl Message {
+    /// Wire name of the message type, safe to log (unlike `Debug`, which
+    /// would include tokens and passphrases)
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Message::Hello { .. } => "hello",
+            Message::Init { .. } => "init",
+            Message::Write { .. } => "write",
+            Message::WriteDelta { .. } => "writedelta",
+            Message::Read { .. } => "read",
+            Message::Sync => "sync",
+            Message::Auth { .. } => "auth",
+            Message::Status => "status",
+            Message::EnableEncryption { .. } => "enableencryption",
+            Message::DisableEncryption => "disableencryption",
+            Message::EncryptionStatus => "encryptionstatus",
+            Message::BeginPayload { .. } => "beginpayload",
+            Message::PayloadChunk { .. } => "payloadchunk",
+            Message::EndPayload { .. } => "endpayload",
+            Message::RebuildIndexes => "rebuildindexes",
+            Message::RecordVisit { .. } => "recordvisit",
+            Message::ClearActivity => "clearactivity",
+            Message::ExportEncryptionKey { .. } => "exportencryptionkey",
+            Message::ImportEncryptionKey { .. } => "importencryptionkey",
+            Message::VerifyRecoveryCode { .. } => "verifyrecoverycode",
+            Message::LockNow => "locknow",
+            Message::SetKeyCacheTtl { .. } => "setkeycachettl",
+            Message::Diff { .. } => "diff",
+            Message::SetConfig { .. } => "setconfig",
+            Message::Deepen { .. } => "deepen",
+            Message::Maintain { .. } => "maintain",
+            Message::ResetToRemote { .. } => "resettoremote",
+            Message::Flush => "flush",
+            Message::History { .. } => "history",
+            Message::AddRemote { .. } => "addremote",
+            Message::ProvideSecret { .. } => "providesecret",
+            Message::Doctor => "doctor",
+            Message::SetLogLevel { .. } => "setloglevel",
+            Message::GetLogs { .. } => "getlogs",
+            Message::Metrics { .. } => "metrics",
+            Message::Shutdown => "shutdown",
+            Message::Repair => "repair",
+            Message::MarkRead { .. } => "markread",
+            Message::ListUnread => "listunread",
+            Message::TagTree => "tagtree",
+            Message::TagCounts => "tagcounts",
+            Message::ListPinned => "listpinned",
+            Message::ReorderPinned { .. } => "reorderpinned",
+            Message::CreateSavedSearch { .. } => "createsavedsearch",
+            Message::ListSavedSearches => "listsavedsearches",
+            Message::RunSavedSearch { .. } => "runsavedsearch",
+            Message::ApplyRules => "applyrules",
+            Message::SuggestTags { .. } => "suggesttags",
+            Message::Import { .. } => "import",
+            Message::ImportBrowserTree { .. } => "importbrowsertree",
+            Message::ExportSite => "exportsite",
+            Message::ExportMarkdown { .. } => "exportmarkdown",
+            Message::PublishPublic { .. } => "publishpublic",
+            Message::StoreArchive { .. } => "storearchive",
+            Message::GetArchive { .. } => "getarchive",
+            Message::Reauth { .. } => "reauth",
+            Message::ListRepos { .. } => "listrepos",
+            Message::SignOut { .. } => "signout",
+            Message::AuthCancel { .. } => "authcancel",
+            Message::ResolveConflicts { .. } => "resolveconflicts",
+            Message::UpdateBookmark { .. } => "updatebookmark",
+            Message::UpdateTags { .. } => "updatetags",
+            Message::Report { .. } => "report",
+            Message::CheckLinks => "checklinks",
+            Message::Enrich { .. } => "enrich",
+            Message::ReviewSuggestedTags { .. } => "reviewsuggestedtags",
+            Message::Stats => "stats",
+            Message::BulkOp { .. } => "bulkop",
+            Message::Undo { .. } => "undo",
+            Message::Backup { .. } => "backup",
+            Message::Restore { .. } => "restore",
+            Message::ListBackups => "listbackups",
+            Message::RestoreBackup { .. } => "restorebackup",
+            Message::ExportBundle { .. } => "exportbundle",
+            Message::ImportBundle { .. } => "importbundle",
+            Message::FindDuplicates => "findduplicates",
+        }
+    }
+
+    /// Capability a client must have negotiated before sending this message.
+    /// Messages from the original protocol need none, so old extensions keep working.
+    pub fn required_capability(&self) -> Option<Capability> {
+        match self {
+            Message::Hello { .. }
+            | Message::Init {
+                depth: None,
+                device_name: None,
+                backend: None,
+                ..
+            }
+            | Message::Write { .. }
+            | Message::Sync
+            | Message::Auth { .. }
+            | Message::Status
+            | Message::EnableEncryption {
+                mode: EncryptionMode::Full,
+                cipher: Cipher::Aes256Gcm,
+            }
+            | Message::DisableEncryption
+            | Message::EncryptionStatus => None,
+            Message::Read { options } => options.is_paged().then_some(Capability::Pagination),
+            Message::BeginPayload { .. }
+            | Message::PayloadChunk { .. }
+            | Message::EndPayload { .. } => Some(Capability::ChunkedPayloads),
+            Message::EnableEncryption {
+                mode: EncryptionMode::Fields,
+                ..
+            } => Some(Capability::FieldEncryption),
+            Message::EnableEncryption {
+                cipher: Cipher::XChaCha20Poly1305,
+                ..
+            } => Some(Capability::XChaCha20),
+            Message::RebuildIndexes => Some(Capability::Indexes),
+            Message::RecordVisit { .. } | Message::ClearActivity => Some(Capability::LocalActivity),
+            Message::ExportEncryptionKey { .. } | Message::ImportEncryptionKey { .. } => {
+                Some(Capability::KeyTransfer)
+            }
+            Message::VerifyRecoveryCode { .. } => Some(Capability::RecoveryCodes),
+            Message::LockNow | Message::SetKeyCacheTtl { .. } => Some(Capability::KeyCache),
+            Message::Diff { .. } => Some(Capability::Diff),
+            Message::SetConfig { .. } => Some(Capability::Config),
+            Message::Maintain { .. } => Some(Capability::Maintenance),
+            Message::ResetToRemote { .. } => Some(Capability::HistoryReset),
+            Message::Flush => Some(Capability::CommitBatching),
+            Message::Init { depth: Some(_), .. } | Message::Deepen { .. } => {
+                Some(Capability::ShallowClone)
+            }
+            Message::Init {
+                device_name: Some(_),
+                ..
+            }
+            | Message::History { .. } => Some(Capability::DeviceIdentity),
+            Message::Init {
+                backend: Some(_), ..
+            } => Some(Capability::SyncBackends),
+            Message::AddRemote { .. } => Some(Capability::MultipleRemotes),
+            Message::ProvideSecret { .. } => Some(Capability::SshKey),
+            Message::Doctor => Some(Capability::Doctor),
+            Message::SetLogLevel { .. } | Message::GetLogs { .. } => Some(Capability::Logs),
+            Message::Metrics { .. } => Some(Capability::Metrics),
+            Message::Shutdown => Some(Capability::Shutdown),
+            Message::Repair => Some(Capability::Repair),
+            Message::MarkRead { .. } | Message::ListUnread => Some(Capability::ReadLater),
+            Message::ListPinned | Message::ReorderPinned { .. } => Some(Capability::Pinned),
+            Message::TagTree => Some(Capability::TagTree),
+            Message::TagCounts => Some(Capability::TagCounts),
+            Message::CreateSavedSearch { .. }
+            | Message::ListSavedSearches
+            | Message::RunSavedSearch { .. } => Some(Capability::SavedSearches),
+            Message::ApplyRules => Some(Capability::Rules),
+            Message::SuggestTags { .. } => Some(Capability::TagSuggestions),
+            Message::Import { .. } | Message::ImportBrowserTree { .. } => Some(Capability::Import),
+            Message::ExportSite => Some(Capability::StaticSite),
+            Message::ExportMarkdown { .. } => Some(Capability::MarkdownExport),
+            Message::PublishPublic { .. } => Some(Capability::Visibility),
+            Message::StoreArchive { .. } | Message::GetArchive { .. } => Some(Capability::Archives),
+            Message::Reauth { .. } => Some(Capability::Reauth),
+            Message::ListRepos { .. } => Some(Capability::RepoPicker),
+            Message::SignOut { .. } => Some(Capability::SignOut),
+            Message::AuthCancel { .. } => Some(Capability::AuthCancel),
+            Message::ResolveConflicts { .. } => Some(Capability::ConflictResolution),
+            Message::UpdateBookmark { .. } => Some(Capability::Notes),
+            Message::UpdateTags { .. } => Some(Capability::TagOps),
+            Message::Report {
+                kind: ReportKind::Expiring,
+                ..
+            } => Some(Capability::Expiration),
+            Message::Report { .. } => Some(Capability::Reports),
+            Message::CheckLinks => Some(Capability::LinkMonitoring),
+            Message::Enrich {
+                suggest_tags: false,
+                ..
+            } => Some(Capability::Enrichment),
+            Message::Enrich {
+                suggest_tags: true, ..
+            }
+            | Message::ReviewSuggestedTags { .. } => Some(Capability::ContentSuggestions),
+            Message::Stats => Some(Capability::Stats),
+            Message::BulkOp { .. } => Some(Capability::BulkOps),
+            Message::Undo { .. } => Some(Capability::Undo),
+            Message::Backup { .. } | Message::Restore { .. } => Some(Capability::Backup),
+            Message::ListBackups | Message::RestoreBackup { .. } => {
+                Some(Capability::ScheduledBackups)
+            }
+            Message::ExportBundle { .. } | Message::ImportBundle { .. } => {
+                Some(Capability::Bundles)
+            }
+            Message::FindDuplicates => Some(Capability::Duplicates),
+            Message::WriteDelta { .. } => Some(Capability::WriteDelta),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
@@ -37,6 +947,15 @@ pub enum AuthMethod {
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Response {
+    /// Handshake reply with the negotiated version and enabled capabilities
+    Hello {
+        protocol_version: u32,
+        supported_versions: Vec<u32>,
+        capabilities: Vec<Capability>,
+        /// Applied to large `Success` data from now on, if any was agreed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<Compression>,
+    },
     Success {
         message: String,
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -44,54 +963,582 @@ pub enum Response {
     },
     Error {
         message: String,
+        code: HostError,
+        category: ErrorCategory,
+        retryable: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
-        code: Option<String>,
+        details: Option<serde_json::Value>,
     },
     AuthFlow {
         user_code: String,
         verification_uri: String,
         device_code: String,
     },
+    /// `sync` finished, but some fields were changed on both sides. Each
+    /// is already merged as `merged`; reply with `ResolveConflicts` to keep
+    /// the other version instead. Only sent to clients that negotiated the
+    /// `conflict_resolution` capability; others get `Success`.
+    Conflicts {
+        message: String,
+        conflicts: Vec<FieldConflict>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<serde_json::Value>,
+    },
+    /// The request needs a secret from the user. Reply with `ProvideSecret`
+    /// and resend the request; nothing was changed.
+    NeedsSecret { secret: SecretKind, prompt: String },
+    /// How far a long request has got; the final response follows. Only
+    /// sent to clients that negotiated the `progress` capability.
+    Progress {
+        operation: String,
+        done: usize,
+        total: usize,
+    },
+    /// Something that happened without a request, such as a scheduled link
+    /// check finding dead links. Only sent to clients that negotiated the
+    /// capability it belongs to.
+    Event {
+        event: String,
+        data: serde_json::Value,
+    },
+    /// One slice of a response too large for a single frame
+    PayloadChunk {
+        payload_id: String,
+        index: usize,
+        total_chunks: usize,
+        data: String,
+    },
+}
+
+/// Something the host worked around that the user should hear about, sent
+/// in a `Success` response's data as `warnings`
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningCode {
+    /// bookmarks.json couldn't be read, so the newest readable version in
+    /// history was put back
+    RestoredFromHistory,
+    /// The post-sync hook failed or timed out; the sync itself succeeded
+    HookFailed,
+}
+
+impl Response {
+    /// Build an error response; category and retryability come from the code
+    pub fn error(code: HostError, message: impl Into<String>) -> Self {
+        Response::Error {
+            message: message.into(),
+            code,
+            category: code.category(),
+            retryable: code.retryable(),
+            details: None,
+        }
+    }
+
+    /// Build an error response carrying a structured details object
+    pub fn error_with_details(
+        code: HostError,
+        message: impl Into<String>,
+        details: serde_json::Value,
+    ) -> Self {
+        Response::Error {
+            message: message.into(),
+            code,
+            category: code.category(),
+            retryable: code.retryable(),
+            details: Some(details),
+        }
+    }
+}
+
+/// Maximum size of a single native messaging frame (Chrome's host-to-extension limit)
+pub const MAX_MESSAGE_SIZE: usize = 1_000_000;
+
+/// Maximum size of a chunk's data; leaves room for JSON escaping within a frame
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Maximum size of a reassembled chunked payload
+pub const MAX_PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+/// Deepest nesting of arrays and objects a message may have. Bookmark data
+/// needs fewer than ten levels; the rest is room for unknown fields.
+pub const MAX_JSON_DEPTH: usize = 64;
+
+/// Most elements any one array in a message may have
+pub const MAX_ARRAY_LENGTH: usize = 200_000;
+
+/// Maximum number of chunks in a single payload
+const MAX_PAYLOAD_CHUNKS: usize = 4096;
+
+/// Maximum number of chunked transfers in flight at once
+const MAX_PENDING_PAYLOADS: usize = 4;
+
+struct PendingPayload {
+    total_size: usize,
+    total_chunks: usize,
+    chunks: Vec<Option<String>>,
+    received_size: usize,
+}
+
+/// Reassembles chunked payloads sent with `BeginPayload`/`PayloadChunk`/`EndPayload`
+#[derive(Default)]
+pub struct PayloadAssembler {
+    pending: HashMap<String, PendingPayload>,
+}
+
+impl PayloadAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a new chunked payload
+    pub fn begin(
+        &mut self,
+        payload_id: &str,
+        total_size: usize,
+        total_chunks: usize,
+    ) -> Result<()> {
+        if total_size > MAX_PAYLOAD_SIZE {
+            anyhow::bail!("Payload too large: {total_size} bytes (max {MAX_PAYLOAD_SIZE})");
+        }
+        if total_chunks == 0 || total_chunks > total_size || total_chunks > MAX_PAYLOAD_CHUNKS {
+            anyhow::bail!("Invalid chunk count: {total_chunks}");
+        }
+        if self.pending.contains_key(payload_id) {
+            anyhow::bail!("Payload already in progress: {payload_id}");
+        }
+        if self.pending.len() >= MAX_PENDING_PAYLOADS {
+            anyhow::bail!("Too many payloads in progress");
+        }
+
+        self.pending.insert(
+            payload_id.to_string(),
+            PendingPayload {
+                total_size,
+                total_chunks,
+                chunks: vec![None; total_chunks],
+                received_size: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Store one chunk of an in-progress payload
+    pub fn push_chunk(&mut self, payload_id: &str, index: usize, data: String) -> Result<()> {
+        let result = self.store_chunk(payload_id, index, data);
+        if result.is_err() {
+            // A malformed transfer can't be recovered, so drop it entirely
+            self.pending.remove(payload_id);
+        }
+        result
+    }
+
+    fn store_chunk(&mut self, payload_id: &str, index: usize, data: String) -> Result<()> {
+        let payload = self
+            .pending
+            .get_mut(payload_id)
+            .with_context(|| format!("Unknown payload: {payload_id}"))?;
+
+        if data.len() > MAX_CHUNK_SIZE {
+            anyhow::bail!("Chunk too large: {} bytes", data.len());
+        }
+        let slot = payload
+            .chunks
+            .get_mut(index)
+            .with_context(|| format!("Chunk index {index} out of range"))?;
+        if slot.is_some() {
+            anyhow::bail!("Duplicate chunk index {index}");
+        }
+
+        payload.received_size += data.len();
+        if payload.received_size > payload.total_size {
+            anyhow::bail!(
+                "Payload exceeds declared size of {} bytes",
+                payload.total_size
+            );
+        }
+        *slot = Some(data);
+        Ok(())
+    }
+
+    /// Finish a payload and parse the reassembled message
+    pub fn finish(&mut self, payload_id: &str) -> Result<Message> {
+        let payload = self
+            .pending
+            .remove(payload_id)
+            .with_context(|| format!("Unknown payload: {payload_id}"))?;
+
+        if payload.received_size != payload.total_size {
+            anyhow::bail!(
+                "Incomplete payload: received {} of {} bytes",
+                payload.received_size,
+                payload.total_size
+            );
+        }
+
+        let mut buffer = String::with_capacity(payload.total_size);
+        for (index, chunk) in payload.chunks.into_iter().enumerate() {
+            let chunk = chunk
+                .with_context(|| format!("Missing chunk {index} of {}", payload.total_chunks))?;
+            buffer.push_str(&chunk);
+        }
+
+        let message =
+            parse_message(buffer.as_bytes()).context("Failed to parse reassembled message")?;
+
+        // Nested transfers would let a payload recurse indefinitely
+        if matches!(
+            message,
+            Message::BeginPayload { .. }
+                | Message::PayloadChunk { .. }
+                | Message::EndPayload { .. }
+        ) {
+            anyhow::bail!("Chunked payloads cannot contain chunk control messages");
+        }
+
+        Ok(message)
+    }
+}
+
+/// Split a serialized string into chunks of at most `max_size` bytes on char boundaries
+fn split_into_chunks(json: &str, max_size: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < json.len() {
+        let mut end = (start + max_size).min(json.len());
+        while !json.is_char_boundary(end) {
+            end -= 1;
+        }
+        chunks.push(json[start..end].to_string());
+        start = end;
+    }
+
+    chunks
+}
+
+/// Write a response, splitting it into `PayloadChunk` frames if it exceeds the frame limit
+pub fn write_response_chunked<W: Write, T: Serialize + ?Sized>(
+    mut writer: W,
+    response: &T,
+) -> Result<()> {
+    let json = serde_json::to_string(response).context("Failed to serialize response")?;
+
+    if json.len() <= MAX_MESSAGE_SIZE {
+        return write_response(writer, response);
+    }
+
+    let chunks = split_into_chunks(&json, MAX_CHUNK_SIZE);
+    let payload_id = uuid::Uuid::new_v4().to_string();
+    let total_chunks = chunks.len();
+
+    for (index, data) in chunks.into_iter().enumerate() {
+        let chunk = Response::PayloadChunk {
+            payload_id: payload_id.clone(),
+            index,
+            total_chunks,
+            data,
+        };
+        write_response(&mut writer, &chunk)?;
+    }
+
+    Ok(())
+}
+
+/// A complete frame arrived but its JSON isn't a valid message. The stream is
+/// still in sync, so the host can report this and keep reading.
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to parse JSON message: {0}")]
+pub struct ParseError(#[from] serde_json::Error);
+
+/// Just the tag of a message, for choosing how to parse the rest
+#[derive(Deserialize)]
+struct MessageTag<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+    #[serde(default)]
+    encoding: Option<serde::de::IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+struct WriteBody {
+    data: BookmarksData,
+    #[serde(default)]
+    strict: bool,
+}
+
+/// A write whose `data` is compressed JSON in base64
+#[derive(Deserialize)]
+struct CompressedWriteBody {
+    data: String,
+    encoding: Compression,
+    #[serde(default)]
+    strict: bool,
+}
+
+/// Refuse JSON nested deeper than [`MAX_JSON_DEPTH`] or with an array longer
+/// than [`MAX_ARRAY_LENGTH`]. This is a scan over the bytes, so hostile input
+/// is turned away before anything is allocated for it; malformed JSON passes
+/// and is left for the parser to reject.
+fn check_json_limits(json: &[u8]) -> Result<(), ParseError> {
+    let limit = |message: String| ParseError(serde::de::Error::custom(message));
+    // Element count of each open array, or `None` for an object
+    let mut open: Vec<Option<usize>> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in json {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == b'"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                if open.len() == MAX_JSON_DEPTH {
+                    return Err(limit(format!(
+                        "JSON is nested more than {MAX_JSON_DEPTH} levels deep"
+                    )));
+                }
+                open.push((byte == b'[').then_some(1));
+            }
+            b']' | b'}' => {
+                open.pop();
+            }
+            b',' => {
+                if let Some(Some(count)) = open.last_mut() {
+                    *count += 1;
+                    if *count > MAX_ARRAY_LENGTH {
+                        return Err(limit(format!(
+                            "JSON array has more than {MAX_ARRAY_LENGTH} elements"
+                        )));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parse a message body.
+///
+/// The derived `Message` deserializer buffers the whole message as an
+/// untyped tree before building the variant, which doubles peak memory for
+/// large bookmark sets. Writes are parsed straight into [`BookmarksData`]
+/// instead, in one pass over the bytes, after decompressing them if the
+/// write has an `encoding`. Anything past [`check_json_limits`] is refused.
+pub fn parse_message(json: &[u8]) -> Result<Message, ParseError> {
+    check_json_limits(json)?;
+    if let Ok(tag) = serde_json::from_slice::<MessageTag>(json) {
+        if tag.kind == "write" && tag.encoding.is_some() {
+            let body: CompressedWriteBody = serde_json::from_slice(json)?;
+            let data = compression::decode(&body.data, body.encoding)
+                .map_err(|e| <serde_json::Error as serde::de::Error>::custom(format!("{e:#}")))?;
+            check_json_limits(&data)?;
+            return Ok(Message::Write {
+                data: serde_json::from_slice(&data)?,
+                strict: body.strict,
+            });
+        }
+        if tag.kind == "write" {
+            let body: WriteBody = serde_json::from_slice(json)?;
+            return Ok(Message::Write {
+                data: body.data,
+                strict: body.strict,
+            });
+        }
+    }
+    Ok(serde_json::from_slice(json)?)
+}
+
+/// Environment variable that sets the length prefix byte order:
+/// `native` (the default), `little`, or `big`
+pub const BYTE_ORDER_ENV: &str = "WEBTAGS_BYTE_ORDER";
+
+/// Byte order of the 4-byte length prefix on each frame.
+///
+/// Browsers write the prefix in native byte order, which is little-endian
+/// everywhere Chrome and Firefox ship today. `Native` also follows a browser
+/// that turns out to use the other order; an explicit order never changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    #[default]
+    Native,
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "native" => Some(Self::Native),
+            "little" | "le" => Some(Self::Little),
+            "big" | "be" => Some(Self::Big),
+            _ => None,
+        }
+    }
+
+    fn decode(self, prefix: [u8; 4]) -> u32 {
+        match self {
+            Self::Native => u32::from_ne_bytes(prefix),
+            Self::Little => u32::from_le_bytes(prefix),
+            Self::Big => u32::from_be_bytes(prefix),
+        }
+    }
+
+    fn encode(self, length: u32) -> [u8; 4] {
+        match self {
+            Self::Native => length.to_ne_bytes(),
+            Self::Little => length.to_le_bytes(),
+            Self::Big => length.to_be_bytes(),
+        }
+    }
+
+    /// The order a browser disagreeing with native order would be using
+    fn opposite_of_native() -> Self {
+        if cfg!(target_endian = "little") {
+            Self::Big
+        } else {
+            Self::Little
+        }
+    }
+}
+
+/// Byte order for every frame the process reads and writes
+static BYTE_ORDER: Mutex<ByteOrder> = Mutex::new(ByteOrder::Native);
+
+pub fn set_byte_order(order: ByteOrder) {
+    *BYTE_ORDER.lock().unwrap_or_else(PoisonError::into_inner) = order;
+}
+
+pub fn byte_order() -> ByteOrder {
+    *BYTE_ORDER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The body length from a frame's prefix read in `order`, and the order to
+/// use from now on. A prefix that's only a sensible length the other way
+/// round means the browser isn't using native order.
+fn frame_length(prefix: [u8; 4], order: ByteOrder) -> Result<(usize, ByteOrder)> {
+    let length = order.decode(prefix) as usize;
+    if length <= MAX_MESSAGE_SIZE {
+        return Ok((length, order));
+    }
+    if order == ByteOrder::Native {
+        let other = ByteOrder::opposite_of_native();
+        let swapped = other.decode(prefix) as usize;
+        if (1..=MAX_MESSAGE_SIZE).contains(&swapped) {
+            log::warn!("Length prefix is in {other:?} byte order; switching to it");
+            return Ok((swapped, other));
+        }
+    }
+    anyhow::bail!("Message too large: {length} bytes")
+}
+
+/// The stream ended cleanly between frames: the browser closed the connection
+#[derive(Debug, thiserror::Error)]
+#[error("The extension closed the connection")]
+pub struct Disconnected;
+
+/// Fill `buffer` from `reader`, retrying interrupted and short reads, and
+/// return how many bytes arrived before end of stream
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Async version of [`read_full`]
+async fn read_full_async<R: AsyncReadExt + Unpin>(
+    reader: &mut R,
+    buffer: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]).await {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Check a prefix read with [`read_full`], telling a clean close from one
+/// mid-frame
+fn check_prefix(received: usize) -> Result<()> {
+    match received {
+        4 => Ok(()),
+        0 => Err(Disconnected.into()),
+        n => anyhow::bail!("Connection closed after {n} of 4 length prefix bytes"),
+    }
+}
+
+/// Check a body read with [`read_full`]
+fn check_body(received: usize, length: usize) -> Result<()> {
+    if received < length {
+        anyhow::bail!("Connection closed after {received} of {length} message bytes");
+    }
+    Ok(())
 }
 
 /// Read a message from stdin using the native messaging protocol
-/// Format: 4-byte length prefix (little-endian) + JSON message
+/// Format: 4-byte length prefix (see [`ByteOrder`]) + JSON message
 pub fn read_message<R: Read>(mut reader: R) -> Result<Message> {
     // Read 4-byte length prefix
-    let mut length_bytes = [0u8; 4];
-    reader
-        .read_exact(&mut length_bytes)
-        .context("Failed to read message length")?;
-    let length = u32::from_le_bytes(length_bytes) as usize;
-
-    // Validate length (max 1MB for safety)
-    if length > 1_000_000 {
-        anyhow::bail!("Message too large: {length} bytes");
-    }
+    let mut prefix = [0u8; 4];
+    let received = read_full(&mut reader, &mut prefix).context("Failed to read message length")?;
+    check_prefix(received)?;
+    let length = read_length(prefix)?;
 
     // Read JSON message
     let mut buffer = vec![0u8; length];
-    reader
-        .read_exact(&mut buffer)
-        .context("Failed to read message body")?;
+    let received = read_full(&mut reader, &mut buffer).context("Failed to read message body")?;
+    check_body(received, length)?;
 
-    // Parse JSON
-    let message: Message =
-        serde_json::from_slice(&buffer).context("Failed to parse JSON message")?;
+    Ok(parse_message(&buffer)?)
+}
 
-    Ok(message)
+/// Decode a length prefix, keeping any change of byte order for later frames
+fn read_length(prefix: [u8; 4]) -> Result<usize> {
+    let order = byte_order();
+    let (length, now) = frame_length(prefix, order)?;
+    if now != order {
+        set_byte_order(now);
+    }
+    Ok(length)
 }
 
 /// Write a response to stdout using the native messaging protocol
-/// Format: 4-byte length prefix (little-endian) + JSON message
-pub fn write_response<W: Write>(mut writer: W, response: &Response) -> Result<()> {
+/// Format: 4-byte length prefix (see [`ByteOrder`]) + JSON message
+pub fn write_response<W: Write, T: Serialize + ?Sized>(mut writer: W, response: &T) -> Result<()> {
     // Serialize response to JSON
     let json = serde_json::to_vec(response).context("Failed to serialize response")?;
     let length = u32::try_from(json.len()).context("Response too large")?;
 
     // Write length prefix
     writer
-        .write_all(&length.to_le_bytes())
+        .write_all(&byte_order().encode(length))
         .context("Failed to write response length")?;
 
     // Write JSON
@@ -107,30 +1554,21 @@ pub fn write_response<W: Write>(mut writer: W, response: &Response) -> Result<()
 /// Async version of `read_message` for use in async contexts
 pub async fn read_message_async<R: AsyncReadExt + Unpin>(mut reader: R) -> Result<Message> {
     // Read 4-byte length prefix
-    let mut length_bytes = [0u8; 4];
-    reader
-        .read_exact(&mut length_bytes)
+    let mut prefix = [0u8; 4];
+    let received = read_full_async(&mut reader, &mut prefix)
         .await
         .context("Failed to read message length")?;
-    let length = u32::from_le_bytes(length_bytes) as usize;
-
-    // Validate length
-    if length > 1_000_000 {
-        anyhow::bail!("Message too large: {length} bytes");
-    }
+    check_prefix(received)?;
+    let length = read_length(prefix)?;
 
     // Read JSON message
     let mut buffer = vec![0u8; length];
-    reader
-        .read_exact(&mut buffer)
+    let received = read_full_async(&mut reader, &mut buffer)
         .await
         .context("Failed to read message body")?;
+    check_body(received, length)?;
 
-    // Parse JSON
-    let message: Message =
-        serde_json::from_slice(&buffer).context("Failed to parse JSON message")?;
-
-    Ok(message)
+    Ok(parse_message(&buffer)?)
 }
 
 /// Async version of `write_response` for use in async contexts
@@ -144,7 +1582,7 @@ pub async fn write_response_async<W: AsyncWriteExt + Unpin>(
 
     // Write length prefix
     writer
-        .write_all(&length.to_le_bytes())
+        .write_all(&byte_order().encode(length))
         .await
         .context("Failed to write response length")?;
 
@@ -169,6 +1607,9 @@ mod tests {
         let message = Message::Init {
             repo_path: Some("/tmp/test".to_string()),
             repo_url: None,
+            depth: None,
+            device_name: None,
+            backend: None,
         };
         let json = serde_json::to_vec(&message).unwrap();
         let length = u32::try_from(json.len()).unwrap().to_le_bytes();
@@ -183,10 +1624,65 @@ mod tests {
         assert_eq!(result, message);
     }
 
+    #[test]
+    fn test_parse_message_write_with_tag_last() {
+        // The tag doesn't have to come first for the fast path
+        let json = br#"{"data": {"jsonapi": {"version": "1.1"}, "data": []}, "type": "write"}"#;
+        assert_eq!(
+            parse_message(json).unwrap(),
+            Message::Write {
+                data: BookmarksData::new(),
+                strict: false,
+            }
+        );
+        let json = br#"{"type": "write", "strict": true, "data": {"jsonapi": {"version": "1.1"}, "data": []}}"#;
+        assert!(matches!(
+            parse_message(json).unwrap(),
+            Message::Write { strict: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_message_compressed_write() {
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+        let data = serde_json::to_vec(&BookmarksData::new()).unwrap();
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let encoded = BASE64.encode(compression.compress(&data).unwrap());
+            let json = serde_json::json!({
+                "type": "write",
+                "encoding": compression,
+                "strict": true,
+                "data": encoded,
+            });
+            assert_eq!(
+                parse_message(json.to_string().as_bytes()).unwrap(),
+                Message::Write {
+                    data: BookmarksData::new(),
+                    strict: true,
+                }
+            );
+        }
+
+        let unknown = br#"{"type": "write", "encoding": "br", "data": "AAAA"}"#;
+        assert!(parse_message(unknown).is_err());
+        let corrupt = br#"{"type": "write", "encoding": "gzip", "data": "AAAA"}"#;
+        assert!(parse_message(corrupt).is_err());
+    }
+
+    #[test]
+    fn test_parse_message_rejects_invalid_write_data() {
+        let json = br#"{"type": "write", "data": {"bookmarks": []}}"#;
+        assert!(parse_message(json).is_err());
+        assert!(parse_message(b"{not json").is_err());
+    }
+
     #[test]
     fn test_read_message_write() {
-        let data = serde_json::json!({"bookmarks": []});
-        let message = Message::Write { data: data.clone() };
+        let message = Message::Write {
+            data: BookmarksData::new(),
+            strict: false,
+        };
         let json = serde_json::to_vec(&message).unwrap();
         let length = u32::try_from(json.len()).unwrap().to_le_bytes();
 
@@ -249,6 +1745,108 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("too large"));
     }
 
+    #[test]
+    fn test_json_limits() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(check_json_limits(nested(MAX_JSON_DEPTH).as_bytes()).is_ok());
+        let deep = nested(MAX_JSON_DEPTH + 1);
+        assert!(check_json_limits(deep.as_bytes())
+            .unwrap_err()
+            .to_string()
+            .contains("nested"));
+
+        let array = |length: usize| format!("[{}]", vec!["0"; length].join(","));
+        assert!(check_json_limits(array(MAX_ARRAY_LENGTH).as_bytes()).is_ok());
+        let long = format!(
+            r#"{{"type": "write", "data": {{"data": {}}}}}"#,
+            array(MAX_ARRAY_LENGTH + 1)
+        );
+        assert!(parse_message(long.as_bytes())
+            .unwrap_err()
+            .to_string()
+            .contains("more than"));
+
+        // Brackets and commas inside strings don't count
+        let quoted = format!(
+            r#"{{"type": "sync", "note": "{}\"{}"}}"#,
+            "[".repeat(100),
+            ",".repeat(MAX_ARRAY_LENGTH + 1)
+        );
+        assert!(check_json_limits(quoted.as_bytes()).is_ok());
+        assert_eq!(parse_message(quoted.as_bytes()).unwrap(), Message::Sync);
+    }
+
+    /// Hands out at most one byte per read, like a pipe under load
+    struct Trickle<R>(R);
+
+    impl<R: Read> Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let end = buf.len().min(1);
+            self.0.read(&mut buf[..end])
+        }
+    }
+
+    fn frame(json: &[u8]) -> Vec<u8> {
+        let mut input = Vec::from(u32::try_from(json.len()).unwrap().to_ne_bytes());
+        input.extend_from_slice(json);
+        input
+    }
+
+    #[test]
+    fn test_read_message_short_reads_and_eof() {
+        let input = frame(br#"{"type": "sync"}"#);
+        assert_eq!(
+            read_message(Trickle(Cursor::new(input.clone()))).unwrap(),
+            Message::Sync
+        );
+
+        // Nothing at all is a clean disconnect; anything partial is not
+        let closed = read_message(Cursor::new(Vec::new())).unwrap_err();
+        assert!(closed.is::<Disconnected>());
+        let cut = read_message(Cursor::new(input[..2].to_vec())).unwrap_err();
+        assert!(!cut.is::<Disconnected>());
+        assert!(cut.to_string().contains("2 of 4"));
+        let cut = read_message(Cursor::new(input[..10].to_vec())).unwrap_err();
+        assert!(cut.to_string().contains("6 of 16"));
+    }
+
+    #[test]
+    fn test_frame_length_byte_order() {
+        let little = 300u32.to_le_bytes();
+        let big = 300u32.to_be_bytes();
+        assert_eq!(
+            frame_length(little, ByteOrder::Little).unwrap(),
+            (300, ByteOrder::Little)
+        );
+        assert_eq!(
+            frame_length(big, ByteOrder::Big).unwrap(),
+            (300, ByteOrder::Big)
+        );
+        assert_eq!(ByteOrder::Big.encode(300), big);
+
+        // Native order follows a browser using the other one...
+        let (native, other) = if cfg!(target_endian = "little") {
+            (little, big)
+        } else {
+            (big, little)
+        };
+        assert_eq!(
+            frame_length(native, ByteOrder::Native).unwrap(),
+            (300, ByteOrder::Native)
+        );
+        assert_eq!(
+            frame_length(other, ByteOrder::Native).unwrap(),
+            (300, ByteOrder::opposite_of_native())
+        );
+        // ...but an explicit order doesn't guess
+        assert!(frame_length(big, ByteOrder::Little).is_err());
+        assert!(frame_length(2_000_000u32.to_le_bytes(), ByteOrder::Native).is_err());
+
+        assert_eq!(ByteOrder::from_name(" BIG "), Some(ByteOrder::Big));
+        assert_eq!(ByteOrder::from_name("le"), Some(ByteOrder::Little));
+        assert_eq!(ByteOrder::from_name("middle"), None);
+    }
+
     #[test]
     fn test_read_message_invalid_json() {
         let invalid_json = b"not valid json";
@@ -286,10 +1884,7 @@ mod tests {
 
     #[test]
     fn test_write_response_error() {
-        let response = Response::Error {
-            message: "Something went wrong".to_string(),
-            code: Some("ERR_GIT_PUSH".to_string()),
-        };
+        let response = Response::error(HostError::GitPush, "Something went wrong");
 
         let mut output = Vec::new();
         write_response(&mut output, &response).unwrap();
@@ -298,6 +1893,13 @@ mod tests {
         let json_bytes = &output[4..];
         let parsed: Response = serde_json::from_slice(json_bytes).unwrap();
         assert_eq!(parsed, response);
+
+        // Verify the structured fields are on the wire
+        let value: serde_json::Value = serde_json::from_slice(json_bytes).unwrap();
+        assert_eq!(value["code"], "ERR_GIT_PUSH");
+        assert_eq!(value["category"], "network");
+        assert_eq!(value["retryable"], true);
+        assert!(value.get("details").is_none());
     }
 
     #[test]
@@ -316,6 +1918,66 @@ mod tests {
         assert_eq!(parsed, response);
     }
 
+    #[test]
+    fn test_needs_secret_response() {
+        let response = Response::NeedsSecret {
+            secret: SecretKind::SshPassphrase,
+            prompt: "Passphrase for ~/.ssh/id_ed25519".to_string(),
+        };
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(value["type"], "needssecret");
+        assert_eq!(value["secret"], "ssh_passphrase");
+
+        let reply: Message = serde_json::from_str(
+            r#"{"type":"providesecret","secret":"ssh_passphrase","value":"hunter2"}"#,
+        )
+        .unwrap();
+        assert_eq!(reply.required_capability(), Some(Capability::SshKey));
+    }
+
+    #[test]
+    fn test_conflicts_response() {
+        let response = Response::Conflicts {
+            message: "1 fields need a decision".to_string(),
+            conflicts: vec![FieldConflict {
+                id: "b1".to_string(),
+                field: "attributes.title".to_string(),
+                local: serde_json::json!("Local"),
+                remote: serde_json::json!("Remote"),
+                merged: serde_json::json!("Remote"),
+            }],
+            data: None,
+        };
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(value["type"], "conflicts");
+        assert_eq!(value["conflicts"][0]["field"], "attributes.title");
+
+        let reply: Message = serde_json::from_str(
+            r#"{"type":"resolveconflicts","decisions":[{"id":"b1","field":"attributes.title","choice":"local"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            reply.required_capability(),
+            Some(Capability::ConflictResolution)
+        );
+    }
+
+    #[test]
+    fn test_warning_wire_format() {
+        let warning = Warning {
+            code: WarningCode::RestoredFromHistory,
+            message: "bookmarks.json was damaged".to_string(),
+            details: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&warning).unwrap(),
+            serde_json::json!({
+                "code": "restored_from_history",
+                "message": "bookmarks.json was damaged",
+            })
+        );
+    }
+
     #[test]
     fn test_round_trip() {
         // Test that we can write a response and read it back as a message
@@ -332,4 +1994,487 @@ mod tests {
 
         assert_eq!(parsed, original);
     }
+
+    #[test]
+    fn test_payload_reassembly() {
+        let mut data = BookmarksData::new();
+        data.add_bookmark(crate::storage::create_bookmark(
+            "https://example.com".to_string(),
+            "a".repeat(100),
+            vec![],
+        ))
+        .unwrap();
+        let message = Message::Write {
+            data,
+            strict: false,
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        let chunks = split_into_chunks(&json, 16);
+
+        let mut assembler = PayloadAssembler::new();
+        assembler.begin("p1", json.len(), chunks.len()).unwrap();
+        // Chunks may arrive out of order
+        for (index, chunk) in chunks.into_iter().enumerate().rev() {
+            assembler.push_chunk("p1", index, chunk).unwrap();
+        }
+
+        assert_eq!(assembler.finish("p1").unwrap(), message);
+    }
+
+    #[test]
+    fn test_payload_size_caps() {
+        let mut assembler = PayloadAssembler::new();
+        assert!(assembler.begin("big", MAX_PAYLOAD_SIZE + 1, 1).is_err());
+
+        assembler.begin("p1", 4, 1).unwrap();
+        assert!(assembler.push_chunk("p1", 0, "12345".to_string()).is_err());
+        // Failed transfers are discarded
+        assert!(assembler.finish("p1").is_err());
+    }
+
+    #[test]
+    fn test_payload_missing_chunk() {
+        let mut assembler = PayloadAssembler::new();
+        assembler.begin("p1", 4, 2).unwrap();
+        assembler.push_chunk("p1", 0, "ab".to_string()).unwrap();
+
+        let result = assembler.finish("p1");
+        assert!(result.unwrap_err().to_string().contains("Incomplete"));
+    }
+
+    #[test]
+    fn test_payload_rejects_nested_chunks() {
+        let nested = serde_json::to_string(&Message::EndPayload {
+            payload_id: "p0".to_string(),
+        })
+        .unwrap();
+
+        let mut assembler = PayloadAssembler::new();
+        assembler.begin("p1", nested.len(), 1).unwrap();
+        assembler.push_chunk("p1", 0, nested).unwrap();
+        assert!(assembler.finish("p1").is_err());
+    }
+
+    #[test]
+    fn test_split_into_chunks_respects_char_boundaries() {
+        let text = "héllo wörld ✓";
+        let chunks = split_into_chunks(text, 3);
+        assert!(chunks.iter().all(|c| c.len() <= 3));
+        assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn test_write_response_chunked_large() {
+        let response = Response::Success {
+            message: "Bookmarks loaded".to_string(),
+            data: Some(serde_json::json!({"blob": "x".repeat(MAX_MESSAGE_SIZE)})),
+        };
+
+        let mut output = Vec::new();
+        write_response_chunked(&mut output, &response).unwrap();
+
+        // Read each frame back and reassemble
+        let mut cursor = Cursor::new(output);
+        let mut reassembled = String::new();
+        loop {
+            let mut length_bytes = [0u8; 4];
+            if Read::read_exact(&mut cursor, &mut length_bytes).is_err() {
+                break;
+            }
+            let mut frame = vec![0u8; u32::from_le_bytes(length_bytes) as usize];
+            Read::read_exact(&mut cursor, &mut frame).unwrap();
+            assert!(frame.len() <= MAX_MESSAGE_SIZE);
+
+            match serde_json::from_slice::<Response>(&frame).unwrap() {
+                Response::PayloadChunk { data, .. } => reassembled.push_str(&data),
+                other => panic!("Expected chunk, got {other:?}"),
+            }
+        }
+
+        let parsed: Response = serde_json::from_str(&reassembled).unwrap();
+        assert_eq!(parsed, response);
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate_version(1).unwrap(), 1);
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION).unwrap(),
+            PROTOCOL_VERSION
+        );
+        // Newer clients fall back to the newest version we speak
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION + 5).unwrap(),
+            PROTOCOL_VERSION
+        );
+        assert!(negotiate_version(0).is_err());
+    }
+
+    #[test]
+    fn test_capabilities_for_version() {
+        assert!(capabilities_for(1).is_empty());
+        assert!(capabilities_for(2).contains(&Capability::ChunkedPayloads));
+    }
+
+    #[test]
+    fn test_original_messages_need_no_capability() {
+        assert_eq!(
+            Message::Read {
+                options: ReadOptions::default()
+            }
+            .required_capability(),
+            None
+        );
+        assert_eq!(Message::Status.required_capability(), None);
+        assert_eq!(
+            Message::RebuildIndexes.required_capability(),
+            Some(Capability::Indexes)
+        );
+    }
+
+    #[test]
+    fn test_enable_encryption_mode_defaults_to_full() {
+        let message: Message = serde_json::from_str(r#"{"type": "enableencryption"}"#).unwrap();
+        assert_eq!(
+            message,
+            Message::EnableEncryption {
+                mode: EncryptionMode::Full,
+                cipher: Cipher::Aes256Gcm,
+            }
+        );
+        assert_eq!(message.required_capability(), None);
+
+        let message: Message =
+            serde_json::from_str(r#"{"type": "enableencryption", "mode": "fields"}"#).unwrap();
+        assert_eq!(
+            message.required_capability(),
+            Some(Capability::FieldEncryption)
+        );
+    }
+
+    #[test]
+    fn test_read_options_are_optional() {
+        let message: Message = serde_json::from_str(r#"{"type": "read"}"#).unwrap();
+        assert_eq!(message.required_capability(), None);
+
+        let message: Message = serde_json::from_str(
+            r#"{"type": "read", "sort": "title", "order": "desc", "limit": 20, "offset": 40}"#,
+        )
+        .unwrap();
+        let Message::Read { options } = &message else {
+            panic!("expected Read, got {message:?}");
+        };
+        assert_eq!(options.sort, Some(crate::query::SortField::Title));
+        assert_eq!(options.offset, 40);
+        assert_eq!(message.required_capability(), Some(Capability::Pagination));
+    }
+
+    #[test]
+    fn test_enable_encryption_cipher() {
+        let message: Message =
+            serde_json::from_str(r#"{"type": "enableencryption", "cipher": "xchacha20-poly1305"}"#)
+                .unwrap();
+        assert_eq!(
+            message,
+            Message::EnableEncryption {
+                mode: EncryptionMode::Full,
+                cipher: Cipher::XChaCha20Poly1305,
+            }
+        );
+        assert_eq!(message.required_capability(), Some(Capability::XChaCha20));
+    }
+
+    #[test]
+    fn test_hello_serialization() {
+        let json = r#"{"type": "hello", "protocol_version": 2}"#;
+        let message: Message = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            message,
+            Message::Hello {
+                protocol_version: 2,
+                compression: Vec::new(),
+            }
+        );
+
+        let response = Response::Hello {
+            protocol_version: 2,
+            supported_versions: SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+            capabilities: vec![Capability::ChunkedPayloads],
+            compression: Some(Compression::Zstd),
+        };
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(value["type"], "hello");
+        assert_eq!(value["capabilities"][0], "chunked_payloads");
+        assert_eq!(value["compression"], "zstd");
+    }
+
+    #[test]
+    fn test_shallow_init_requires_capability() {
+        let full: Message =
+            serde_json::from_str(r#"{"type":"init","repo_url":"https://example.com/r.git"}"#)
+                .unwrap();
+        assert_eq!(full.required_capability(), None);
+
+        let shallow: Message = serde_json::from_str(
+            r#"{"type":"init","repo_url":"https://example.com/r.git","depth":1}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            shallow.required_capability(),
+            Some(Capability::ShallowClone)
+        );
+    }
+
+    #[test]
+    fn test_device_identity_requires_capability() {
+        let named: Message =
+            serde_json::from_str(r#"{"type":"init","device_name":"work-laptop"}"#).unwrap();
+        assert_eq!(
+            named.required_capability(),
+            Some(Capability::DeviceIdentity)
+        );
+
+        let history: Message = serde_json::from_str(r#"{"type":"history"}"#).unwrap();
+        assert_eq!(
+            history,
+            Message::History {
+                limit: None,
+                device: None,
+            }
+        );
+        assert_eq!(
+            history.required_capability(),
+            Some(Capability::DeviceIdentity)
+        );
+    }
+
+    #[test]
+    fn test_webdav_init_requires_capability() {
+        let message: Message = serde_json::from_str(
+            r#"{"type":"init","backend":{"kind":"webdav","url":"https://dav.example.com/bookmarks.json","username":"me"}}"#,
+        )
+        .unwrap();
+        let Message::Init {
+            backend:
+                Some(BackendConfig::Webdav {
+                    url,
+                    username,
+                    password,
+                }),
+            ..
+        } = &message
+        else {
+            panic!("expected a WebDAV init, got {message:?}");
+        };
+        assert_eq!(url, "https://dav.example.com/bookmarks.json");
+        assert_eq!(username.as_deref(), Some("me"));
+        assert_eq!(*password, None);
+        assert_eq!(
+            message.required_capability(),
+            Some(Capability::SyncBackends)
+        );
+    }
+
+    #[test]
+    fn test_diff_message_defaults_to_head() {
+        let json = r#"{"type":"diff","from":"abc123"}"#;
+        let message: Message = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            message,
+            Message::Diff {
+                from: "abc123".to_string(),
+                to: None,
+            }
+        );
+        assert_eq!(message.required_capability(), Some(Capability::Diff));
+    }
+
+    #[test]
+    fn test_type_name_matches_wire_tag() {
+        let messages = vec![
+            Message::Read {
+                options: ReadOptions::default(),
+            },
+            Message::Read {
+                options: ReadOptions {
+                    limit: Some(50),
+                    ..ReadOptions::default()
+                },
+            },
+            Message::Auth {
+                method: AuthMethod::PAT,
+                token: Some("secret".to_string()),
+            },
+            Message::ExportEncryptionKey {
+                passphrase: "secret".to_string(),
+            },
+            Message::SetKeyCacheTtl { ttl_secs: 60 },
+            Message::Diff {
+                from: "HEAD~1".to_string(),
+                to: None,
+            },
+            Message::ResetToRemote {
+                keep_local_changes: true,
+            },
+            Message::SetConfig {
+                proxy: None,
+                commit_window_secs: Some(30),
+                commit_template: None,
+                ssh_key_path: None,
+                backup_schedule: None,
+                backup_keep: None,
+                device_branches: None,
+                hooks: None,
+                webhook: None,
+                link_check: None,
+                expiry: None,
+                retry: None,
+                max_url_length: None,
+                max_title_length: None,
+                max_tag_length: None,
+                max_tag_depth: None,
+                allowed_schemes: None,
+                url_normalization: None,
+            },
+            Message::Flush,
+            Message::History {
+                limit: Some(10),
+                device: None,
+            },
+            Message::AddRemote {
+                name: "backup".to_string(),
+                url: "https://git.example.com/me/bookmarks.git".to_string(),
+            },
+            Message::ProvideSecret {
+                secret: SecretKind::SshPassphrase,
+                value: "secret".to_string(),
+            },
+            Message::Doctor,
+            Message::SetLogLevel {
+                level: "debug".to_string(),
+            },
+            Message::GetLogs { lines: Some(50) },
+            Message::Metrics { reset: false },
+            Message::Shutdown,
+            Message::Repair,
+            Message::MarkRead {
+                id: "bookmark".to_string(),
+            },
+            Message::ListUnread,
+            Message::TagTree,
+            Message::TagCounts,
+            Message::ListPinned,
+            Message::ReorderPinned {
+                ids: vec!["bookmark-1".to_string(), "bookmark-2".to_string()],
+            },
+            Message::CreateSavedSearch {
+                name: "Unread Rust".to_string(),
+                query: "tag:rust is:unread".to_string(),
+                sort: SortOrder::Newest,
+            },
+            Message::ListSavedSearches,
+            Message::RunSavedSearch {
+                id: "search".to_string(),
+            },
+            Message::ApplyRules,
+            Message::SuggestTags {
+                url: "https://example.com".to_string(),
+                title: "Example".to_string(),
+                limit: None,
+            },
+            Message::Import {
+                format: ImportFormat::PocketHtml,
+                content: String::new(),
+                dry_run: true,
+                options: ImportOptions::default(),
+            },
+            Message::Reauth { device_code: None },
+            Message::ListRepos { query: None },
+            Message::SignOut {
+                remove_remote: false,
+            },
+            Message::AuthCancel {
+                device_code: "code".to_string(),
+            },
+            Message::ResolveConflicts {
+                decisions: Vec::new(),
+            },
+            Message::UpdateBookmark {
+                id: "id".to_string(),
+                title: None,
+                notes: None,
+            },
+            Message::UpdateTags { ops: Vec::new() },
+            Message::Report {
+                kind: ReportKind::Stale,
+                months: None,
+                check_domains: false,
+                days: None,
+            },
+            Message::CheckLinks,
+            Message::Enrich {
+                refresh: false,
+                suggest_tags: false,
+            },
+            Message::ReviewSuggestedTags {
+                id: "id".to_string(),
+                accept: vec!["rust".to_string()],
+                dismiss: Vec::new(),
+            },
+            Message::Stats,
+            Message::BulkOp {
+                filter: BulkFilter::default(),
+                op: BulkOp::Delete,
+            },
+            Message::Undo { count: None },
+            Message::Backup {
+                path: "/tmp/webtags.backup".to_string(),
+                passphrase: "secret".to_string(),
+            },
+            Message::Restore {
+                path: "/tmp/webtags.backup".to_string(),
+                passphrase: "secret".to_string(),
+                repo_path: None,
+            },
+            Message::ListBackups,
+            Message::RestoreBackup {
+                id: "bookmarks-20260101T000000Z".to_string(),
+            },
+            Message::ExportBundle {
+                path: "/media/usb/webtags.bundle".to_string(),
+                since: None,
+            },
+            Message::ImportBundle {
+                path: "/media/usb/webtags.bundle".to_string(),
+            },
+            Message::FindDuplicates,
+            Message::WriteDelta {
+                added: Vec::new(),
+                updated: Vec::new(),
+                removed: vec!["bookmark-1".to_string()],
+            },
+            Message::ImportBrowserTree {
+                tree: Vec::new(),
+                dry_run: true,
+                options: ImportOptions::default(),
+            },
+            Message::ExportSite,
+            Message::ExportMarkdown {
+                path: "/tmp/vault".to_string(),
+                include_private: false,
+            },
+            Message::PublishPublic { path: None },
+            Message::StoreArchive {
+                id: "bookmark-1".to_string(),
+                content: "PGh0bWw+".to_string(),
+            },
+            Message::GetArchive {
+                id: "bookmark-1".to_string(),
+            },
+        ];
+        for message in messages {
+            let value = serde_json::to_value(&message).unwrap();
+            assert_eq!(value["type"], message.type_name());
+        }
+    }
 }
@@ -0,0 +1,188 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// In-process counters and timings, shared by every module. Nothing leaves
+/// the machine; the extension reads them with `Metrics`.
+static METRICS: LazyLock<Mutex<Metrics>> = LazyLock::new(|| Mutex::new(Metrics::new()));
+
+/// Timed operations outside message handling
+pub const GIT_PUSH: &str = "git.push";
+pub const GIT_FETCH: &str = "git.fetch";
+pub const GIT_CLONE: &str = "git.clone";
+pub const ENCRYPT: &str = "encryption.encrypt";
+pub const DECRYPT: &str = "encryption.decrypt";
+
+struct Metrics {
+    started_at: Instant,
+    messages: BTreeMap<String, Timing>,
+    errors: BTreeMap<String, u64>,
+    operations: BTreeMap<&'static str, Timing>,
+}
+
+/// Count and duration stats for one kind of operation
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Timing {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Timing {
+    fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+}
+
+/// Serializable copy of the metrics at one moment
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub uptime_secs: u64,
+    pub messages_handled: u64,
+    /// Per message type
+    pub messages: BTreeMap<String, TimingStats>,
+    /// Error responses per `ERR_*` code
+    pub errors: BTreeMap<String, u64>,
+    /// Git and encryption operations
+    pub operations: BTreeMap<String, TimingStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimingStats {
+    pub count: u64,
+    pub total_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+}
+
+impl From<&Timing> for TimingStats {
+    #[allow(clippy::cast_precision_loss)] // counts never get near 2^52
+    fn from(timing: &Timing) -> Self {
+        let total_ms = timing.total.as_secs_f64() * 1000.0;
+        Self {
+            count: timing.count,
+            total_ms,
+            avg_ms: if timing.count == 0 {
+                0.0
+            } else {
+                total_ms / timing.count as f64
+            },
+            max_ms: timing.max.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            messages: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            operations: BTreeMap::new(),
+        }
+    }
+}
+
+fn metrics() -> MutexGuard<'static, Metrics> {
+    METRICS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Record a handled message, and its error code if it failed
+pub fn record_message(type_name: &str, duration: Duration, error_code: Option<&str>) {
+    let mut metrics = metrics();
+    metrics
+        .messages
+        .entry(type_name.to_string())
+        .or_default()
+        .record(duration);
+    if let Some(code) = error_code {
+        *metrics.errors.entry(code.to_string()).or_default() += 1;
+    }
+}
+
+/// Record how long an operation took
+pub fn record_operation(name: &'static str, duration: Duration) {
+    metrics()
+        .operations
+        .entry(name)
+        .or_default()
+        .record(duration);
+}
+
+/// Run `op`, recording its duration under `name`
+pub fn time<T>(name: &'static str, op: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = op();
+    record_operation(name, start.elapsed());
+    result
+}
+
+pub fn snapshot() -> Snapshot {
+    let metrics = metrics();
+    Snapshot {
+        uptime_secs: metrics.started_at.elapsed().as_secs(),
+        messages_handled: metrics.messages.values().map(|timing| timing.count).sum(),
+        messages: metrics
+            .messages
+            .iter()
+            .map(|(name, timing)| (name.clone(), timing.into()))
+            .collect(),
+        errors: metrics.errors.clone(),
+        operations: metrics
+            .operations
+            .iter()
+            .map(|(name, timing)| ((*name).to_string(), timing.into()))
+            .collect(),
+    }
+}
+
+/// Start counting from zero (uptime is unaffected)
+pub fn reset() {
+    let mut metrics = metrics();
+    metrics.messages.clear();
+    metrics.errors.clear();
+    metrics.operations.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing_stats() {
+        let mut timing = Timing::default();
+        timing.record(Duration::from_millis(10));
+        timing.record(Duration::from_millis(30));
+
+        let stats = TimingStats::from(&timing);
+        assert_eq!(stats.count, 2);
+        assert!((stats.total_ms - 40.0).abs() < 1e-9);
+        assert!((stats.avg_ms - 20.0).abs() < 1e-9);
+        assert!((stats.max_ms - 30.0).abs() < 1e-9);
+        assert!((TimingStats::from(&Timing::default()).avg_ms).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_records_show_up_in_snapshot() {
+        // Other tests may record into the shared metrics, so only check
+        // what this test adds
+        let before = snapshot();
+        record_message("metrics-test", Duration::from_millis(5), None);
+        record_message(
+            "metrics-test",
+            Duration::from_millis(5),
+            Some("ERR_METRICS_TEST"),
+        );
+        let value = time("metrics.test", || 42);
+        assert_eq!(value, 42);
+
+        let after = snapshot();
+        assert!(after.messages_handled >= before.messages_handled + 2);
+        assert_eq!(after.messages["metrics-test"].count, 2);
+        assert_eq!(after.errors["ERR_METRICS_TEST"], 1);
+        assert_eq!(after.operations["metrics.test"].count, 1);
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Schema version of the bookmarks this host writes
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+/// Files written before `meta.schema_version` existed
+const UNVERSIONED: u32 = 1;
+
+/// One upgrade step, from schema `from` to `from + 1`
+struct Migration {
+    from: u32,
+    description: &'static str,
+    apply: fn(&mut Value) -> Result<()>,
+}
+
+/// Every step from the oldest version, in order. When the on-disk format
+/// changes, bump [`CURRENT_SCHEMA_VERSION`] and add a step here that rewrites
+/// the previous format.
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "record the schema version in meta",
+    // Nothing else changed; the version is stamped after every step
+    apply: |_| Ok(()),
+}];
+
+/// Schema version of serialized bookmarks
+pub fn schema_version(value: &Value) -> Result<u32> {
+    match value.pointer("/meta/schema_version") {
+        None => Ok(UNVERSIONED),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .with_context(|| format!("Invalid meta.schema_version: {version}")),
+    }
+}
+
+/// Upgrade serialized (and decrypted) bookmarks to [`CURRENT_SCHEMA_VERSION`]
+/// in place, returning the version they were stored in
+pub fn migrate(value: &mut Value) -> Result<u32> {
+    let stored = schema_version(value)?;
+    if stored > CURRENT_SCHEMA_VERSION {
+        anyhow::bail!(
+            "Bookmarks use schema version {stored}, but this host only understands up to \
+             {CURRENT_SCHEMA_VERSION}. Update WebTags on this device."
+        );
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= stored) {
+        let to = migration.from + 1;
+        log::info!(
+            "Migrating bookmarks from schema v{} to v{to}: {}",
+            migration.from,
+            migration.description
+        );
+        (migration.apply)(value)
+            .with_context(|| format!("Failed to migrate bookmarks to schema v{to}"))?;
+        set_schema_version(value, to)?;
+    }
+
+    Ok(stored)
+}
+
+fn set_schema_version(value: &mut Value, version: u32) -> Result<()> {
+    let meta = value
+        .as_object_mut()
+        .context("Bookmarks are not a JSON object")?
+        .entry("meta")
+        .or_insert_with(|| Value::Object(serde_json::Map::new()));
+    meta.as_object_mut()
+        .context("meta is not a JSON object")?
+        .insert("schema_version".to_string(), version.into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrations_cover_every_version() {
+        let steps: Vec<u32> = MIGRATIONS.iter().map(|m| m.from).collect();
+        let expected: Vec<u32> = (UNVERSIONED..CURRENT_SCHEMA_VERSION).collect();
+        assert_eq!(steps, expected);
+    }
+
+    #[test]
+    fn test_unversioned_file_is_upgraded() {
+        let mut value = json!({"jsonapi": {"version": "1.1"}, "data": []});
+        assert_eq!(migrate(&mut value).unwrap(), UNVERSIONED);
+        assert_eq!(schema_version(&value).unwrap(), CURRENT_SCHEMA_VERSION);
+
+        // Other meta entries survive
+        let mut value = json!({"meta": {"encryption": "fields"}, "data": []});
+        migrate(&mut value).unwrap();
+        assert_eq!(value["meta"]["encryption"], "fields");
+    }
+
+    #[test]
+    fn test_current_file_is_untouched() {
+        let original = json!({"meta": {"schema_version": CURRENT_SCHEMA_VERSION}, "data": []});
+        let mut value = original.clone();
+        assert_eq!(migrate(&mut value).unwrap(), CURRENT_SCHEMA_VERSION);
+        assert_eq!(value, original);
+    }
+
+    #[test]
+    fn test_newer_or_invalid_versions_are_rejected() {
+        let mut newer = json!({"meta": {"schema_version": CURRENT_SCHEMA_VERSION + 1}});
+        assert!(migrate(&mut newer)
+            .unwrap_err()
+            .to_string()
+            .contains("Update WebTags"));
+
+        let mut invalid = json!({"meta": {"schema_version": "two"}});
+        assert!(migrate(&mut invalid).is_err());
+    }
+}
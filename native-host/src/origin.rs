@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::install::ExtensionIds;
+
+/// Extensions allowed to use the host, written by `webtags-host install`:
+/// `~/.local/share/webtags/allowed-extensions.json`
+pub fn allowlist_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().context("No home directory found")?;
+    Ok(home
+        .join(".local")
+        .join("share")
+        .join("webtags")
+        .join("allowed-extensions.json"))
+}
+
+/// The allowlist at `path`, or `None` if there isn't one
+pub fn load_allowlist(path: &Path) -> Result<Option<ExtensionIds>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let ids = serde_json::from_str(&json)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(ids))
+}
+
+pub fn save_allowlist(path: &Path, ids: &ExtensionIds) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let json = serde_json::to_string_pretty(ids)?;
+    fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Who launched the host, from the arguments browsers pass.
+///
+/// Chromium browsers pass the calling extension's origin
+/// (`chrome-extension://<id>/`, followed by `--parent-window=<handle>` on
+/// Windows); Firefox passes the path to the host manifest and then the
+/// add-on ID. Any local process can pass the same arguments, so this keeps
+/// out extensions the host wasn't installed for, not a determined local
+/// attacker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    Chromium(String),
+    Firefox(String),
+    /// Launched without an origin, e.g. from a terminal
+    Unknown,
+}
+
+impl Caller {
+    pub fn from_args(args: &[String]) -> Self {
+        if let Some(id) = args.iter().find_map(|arg| {
+            arg.strip_prefix("chrome-extension://")
+                .map(|rest| rest.trim_end_matches('/'))
+        }) {
+            return Self::Chromium(id.to_string());
+        }
+        match args {
+            [manifest, id, ..]
+                if Path::new(manifest)
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("json")) =>
+            {
+                Self::Firefox(id.clone())
+            }
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Whether the caller may use the host. Without an allowlist (installs
+    /// from before there was one, or manifests written by hand) the
+    /// browser's own check of the manifest is all there is.
+    pub fn is_allowed(&self, allowlist: Option<&ExtensionIds>) -> bool {
+        let Some(allowed) = allowlist else {
+            return true;
+        };
+        match self {
+            Self::Chromium(id) => allowed.chromium.contains(id),
+            Self::Firefox(id) => allowed.firefox.contains(id),
+            Self::Unknown => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Caller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Chromium(id) => write!(f, "chrome-extension://{id}/"),
+            Self::Firefox(id) => write!(f, "Firefox add-on {id}"),
+            Self::Unknown => f.write_str("a caller with no extension origin"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const CHROME_ID: &str = "abcdefghijklmnopabcdefghijklmnop";
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_caller_from_args() {
+        let origin = format!("chrome-extension://{CHROME_ID}/");
+        assert_eq!(
+            Caller::from_args(&args(&[&origin])),
+            Caller::Chromium(CHROME_ID.to_string())
+        );
+        assert_eq!(
+            Caller::from_args(&args(&[&origin, "--parent-window=1234"])),
+            Caller::Chromium(CHROME_ID.to_string())
+        );
+        assert_eq!(
+            Caller::from_args(&args(&[
+                "/home/me/.mozilla/native-messaging-hosts/com.webtags.host.json",
+                "webtags@example.com"
+            ])),
+            Caller::Firefox("webtags@example.com".to_string())
+        );
+        assert_eq!(Caller::from_args(&[]), Caller::Unknown);
+        assert_eq!(
+            Caller::from_args(&args(&["--verbose", "x"])),
+            Caller::Unknown
+        );
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("webtags").join("allowed-extensions.json");
+        assert!(load_allowlist(&path).unwrap().is_none());
+        assert!(Caller::Unknown.is_allowed(None));
+
+        let ids = ExtensionIds {
+            chromium: vec![CHROME_ID.to_string()],
+            firefox: vec!["webtags@example.com".to_string()],
+        };
+        save_allowlist(&path, &ids).unwrap();
+        let allowlist = load_allowlist(&path).unwrap();
+        let allowlist = allowlist.as_ref();
+
+        assert!(Caller::Chromium(CHROME_ID.to_string()).is_allowed(allowlist));
+        assert!(Caller::Firefox("webtags@example.com".to_string()).is_allowed(allowlist));
+        assert!(!Caller::Chromium("p".repeat(32)).is_allowed(allowlist));
+        assert!(!Caller::Firefox(CHROME_ID.to_string()).is_allowed(allowlist));
+        assert!(!Caller::Unknown.is_allowed(allowlist));
+    }
+}
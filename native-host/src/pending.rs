@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::git::GitRepo;
+use crate::storage;
+
+/// Queue of sync operations that couldn't reach the remote. Kept inside
+/// `.git` so it is per-clone and never committed.
+pub const PENDING_OPS_FILE: &str = "pending_ops.json";
+
+/// A network operation to retry once the remote is reachable again
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PendingOp {
+    Push {
+        remote: String,
+        branch: String,
+        queued_at: DateTime<Utc>,
+    },
+}
+
+/// Persisted queue of [`PendingOp`]s, oldest first
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct PendingOps {
+    pub ops: Vec<PendingOp>,
+}
+
+fn queue_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(".git").join(PENDING_OPS_FILE)
+}
+
+impl PendingOps {
+    /// Load the queue for a repository, or an empty queue if none exists
+    pub fn load<P: AsRef<Path>>(repo_path: P) -> Result<Self> {
+        let path = queue_path(repo_path.as_ref());
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path).context("Failed to read pending operations")?;
+        serde_json::from_str(&content).context("Failed to parse pending operations")
+    }
+
+    /// Save the queue, removing the file once it is empty
+    pub fn save<P: AsRef<Path>>(&self, repo_path: P) -> Result<()> {
+        let path = queue_path(repo_path.as_ref());
+        if self.ops.is_empty() {
+            if path.exists() {
+                fs::remove_file(&path).context("Failed to remove pending operations")?;
+            }
+            return Ok(());
+        }
+
+        let json = serde_json::to_string(self).context("Failed to serialize pending operations")?;
+        storage::write_atomic(&path, json.as_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Queue a push. A push sends every commit on the branch, so an
+    /// already-queued push to the same remote branch covers this one too.
+    pub fn enqueue_push(&mut self, remote: &str, branch: &str) {
+        let already_queued = self.ops.iter().any(|op| match op {
+            PendingOp::Push {
+                remote: r,
+                branch: b,
+                ..
+            } => r == remote && b == branch,
+        });
+        if !already_queued {
+            self.ops.push(PendingOp::Push {
+                remote: remote.to_string(),
+                branch: branch.to_string(),
+                queued_at: Utc::now(),
+            });
+        }
+    }
+
+    /// Drop everything queued for `remote`, e.g. once it is removed.
+    /// Returns how many operations were dropped.
+    pub fn drop_remote(&mut self, remote: &str) -> usize {
+        let before = self.ops.len();
+        self.ops.retain(|op| match op {
+            PendingOp::Push { remote: r, .. } => r != remote,
+        });
+        before - self.ops.len()
+    }
+
+    /// Run queued operations in order, dropping each one that succeeds.
+    /// Stops at the first failure, leaving it and everything after queued.
+    /// Returns how many operations completed.
+    pub fn flush(&mut self, repo: &GitRepo) -> Result<usize> {
+        let mut completed = 0;
+        while let Some(op) = self.ops.first() {
+            match op {
+                PendingOp::Push { remote, branch, .. } => repo.push(remote, branch)?,
+            }
+            self.ops.remove(0);
+            completed += 1;
+        }
+        Ok(completed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Repository;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_enqueue_deduplicates_and_roundtrips() {
+        let temp_dir = TempDir::new().unwrap();
+        GitRepo::init(temp_dir.path()).unwrap();
+
+        let mut queue = PendingOps::load(temp_dir.path()).unwrap();
+        assert!(queue.is_empty());
+
+        queue.enqueue_push("origin", "main");
+        queue.enqueue_push("origin", "main");
+        queue.enqueue_push("backup", "main");
+        assert_eq!(queue.len(), 2);
+
+        queue.save(temp_dir.path()).unwrap();
+        assert_eq!(PendingOps::load(temp_dir.path()).unwrap(), queue);
+
+        PendingOps::default().save(temp_dir.path()).unwrap();
+        assert!(!queue_path(temp_dir.path()).exists());
+    }
+
+    #[test]
+    fn test_drop_remote() {
+        let mut queue = PendingOps::default();
+        queue.enqueue_push("origin", "main");
+        queue.enqueue_push("backup", "main");
+
+        assert_eq!(queue.drop_remote("origin"), 1);
+        assert_eq!(queue.drop_remote("origin"), 0);
+        assert!(matches!(
+            &queue.ops[..],
+            [PendingOp::Push { remote, .. }] if remote == "backup"
+        ));
+    }
+
+    #[test]
+    fn test_flush_pushes_and_stops_at_failure() {
+        let temp_dir = TempDir::new().unwrap();
+        let remote_path = temp_dir.path().join("remote.git");
+        Repository::init_bare(&remote_path).unwrap();
+
+        let local_path = temp_dir.path().join("local");
+        let mut repo = GitRepo::init(&local_path).unwrap();
+        repo.add_remote("origin", remote_path.to_str().unwrap())
+            .unwrap();
+        fs::write(local_path.join("file.txt"), "content").unwrap();
+        repo.add_file("file.txt").unwrap();
+        repo.commit("Commit").unwrap();
+        let branch = repo.current_branch().unwrap();
+
+        let mut queue = PendingOps::default();
+        queue.enqueue_push("origin", &branch);
+        queue.enqueue_push("missing", &branch);
+
+        assert!(queue.flush(&repo).is_err());
+        assert_eq!(queue.len(), 1);
+        assert!(matches!(
+            &queue.ops[0],
+            PendingOp::Push { remote, .. } if remote == "missing"
+        ));
+        assert!(Repository::open_bare(&remote_path)
+            .unwrap()
+            .find_reference(&format!("refs/heads/{branch}"))
+            .is_ok());
+    }
+}
@@ -0,0 +1,742 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::index::tokenize;
+use crate::storage::{BookmarkAttributes, BookmarksData, ContentKind, Resource, SortOrder};
+use crate::unicode;
+use crate::urlnorm;
+
+/// A parsed search query.
+///
+/// Terms are separated by whitespace and must all match:
+/// - `tag:<name>`: tagged with a tag of that name (case-insensitive)
+/// - `url:<url>`: saved under that URL, compared the way
+///   [`crate::urlnorm::key`] does (so tracking parameters, `www.`, and a
+///   trailing slash don't matter by default)
+/// - `author:<name>`: saved by someone with that name, or with that word in
+///   their name (case-insensitive)
+/// - `is:unread`, `is:read`, `is:private`, `is:pinned`: by bookmark flags
+/// - `type:<kind>` (`article`, `video`, `pdf`, ...), `lang:<code>`, and
+///   `minutes:>N` / `minutes:<N`: by what `Enrich` found, so bookmarks not
+///   enriched yet never match
+/// - anything else: words that must appear in the title, URL, notes, or a
+///   tag name
+///
+/// A lone `+` is ignored, so `rust + is:unread` reads the way it's meant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Word(String),
+    Tag(String),
+    TagId(String),
+    /// Canonical URL key
+    Url(String),
+    /// Folded name, or one word of it
+    Author(String),
+    /// Folded full name
+    AuthorName(String),
+    Unread(bool),
+    Private,
+    Pinned,
+    Kind(ContentKind),
+    /// Primary language subtag
+    Language(String),
+    /// Reading time in minutes, exclusive bounds
+    MinutesOver(u32),
+    MinutesUnder(u32),
+}
+
+fn parse_kind(name: &str) -> Result<ContentKind> {
+    ContentKind::ALL
+        .into_iter()
+        .find(|kind| kind.name().eq_ignore_ascii_case(name))
+        .with_context(|| {
+            let names: Vec<_> = ContentKind::ALL.iter().map(|k| k.name()).collect();
+            format!("Unknown type '{name}' (expected {})", names.join(", "))
+        })
+}
+
+fn parse_minutes(bound: &str) -> Result<Term> {
+    let invalid = || format!("'minutes:' needs '>N' or '<N', not '{bound}'");
+    if let Some(n) = bound.strip_prefix('>') {
+        Ok(Term::MinutesOver(n.parse().with_context(invalid)?))
+    } else if let Some(n) = bound.strip_prefix('<') {
+        Ok(Term::MinutesUnder(n.parse().with_context(invalid)?))
+    } else {
+        anyhow::bail!(invalid())
+    }
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self> {
+        let mut terms = Vec::new();
+        for term in query.split_whitespace() {
+            if let Some(name) = term.strip_prefix("tag:") {
+                if name.is_empty() {
+                    anyhow::bail!("'tag:' needs a tag name");
+                }
+                terms.push(Term::Tag(unicode::fold(name)));
+            } else if let Some(url) = term.strip_prefix("url:") {
+                if url.is_empty() {
+                    anyhow::bail!("'url:' needs a URL");
+                }
+                terms.push(Term::Url(urlnorm::key(url)));
+            } else if let Some(name) = term.strip_prefix("author:") {
+                if name.is_empty() {
+                    anyhow::bail!("'author:' needs a name");
+                }
+                terms.push(Term::Author(unicode::fold(name)));
+            } else if let Some(kind) = term.strip_prefix("type:") {
+                terms.push(Term::Kind(parse_kind(kind)?));
+            } else if let Some(language) = term.strip_prefix("lang:") {
+                if language.is_empty() {
+                    anyhow::bail!("'lang:' needs a language code");
+                }
+                terms.push(Term::Language(language.to_ascii_lowercase()));
+            } else if let Some(bound) = term.strip_prefix("minutes:") {
+                terms.push(parse_minutes(bound)?);
+            } else if let Some(flag) = term.strip_prefix("is:") {
+                terms.push(match flag {
+                    "unread" => Term::Unread(true),
+                    "read" => Term::Unread(false),
+                    "private" => Term::Private,
+                    "pinned" => Term::Pinned,
+                    _ => anyhow::bail!(
+                        "Unknown filter 'is:{flag}' (expected unread, read, private, or pinned)"
+                    ),
+                });
+            } else {
+                terms.extend(tokenize(term).map(Term::Word));
+            }
+        }
+        Ok(Self { terms })
+    }
+
+    /// Whether `resource` is a bookmark matching every term
+    pub fn matches(&self, data: &BookmarksData, resource: &Resource) -> bool {
+        let Resource::Bookmark {
+            attributes,
+            relationships,
+            ..
+        } = resource
+        else {
+            return false;
+        };
+
+        let tag_names: Vec<&str> = relationships
+            .as_ref()
+            .and_then(|r| r.tags.as_ref())
+            .into_iter()
+            .flat_map(|tags| &tags.data)
+            .filter_map(|tag| match data.get(&tag.id) {
+                Some(Resource::Tag { attributes, .. }) => Some(attributes.name.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        let enrichment = attributes.enrichment.as_ref();
+        let minutes = enrichment.and_then(|e| e.reading_minutes);
+        let mut words: Option<HashSet<String>> = None;
+        self.terms.iter().all(|term| match term {
+            Term::Tag(name) => tag_names.iter().any(|tag| unicode::fold(tag) == *name),
+            Term::TagId(id) => relationships
+                .as_ref()
+                .and_then(|r| r.tags.as_ref())
+                .is_some_and(|tags| tags.data.iter().any(|tag| tag.id == *id)),
+            Term::Url(key) => urlnorm::key(&attributes.url) == *key,
+            Term::Author(name) => attributes.author.as_deref().is_some_and(|author| {
+                let author = unicode::fold(author);
+                author == *name || author.split_whitespace().any(|word| word == name)
+            }),
+            Term::AuthorName(name) => attributes
+                .author
+                .as_deref()
+                .is_some_and(|author| unicode::fold(author) == *name),
+            Term::Unread(unread) => attributes.unread == *unread,
+            Term::Private => attributes.private,
+            Term::Pinned => attributes.pinned,
+            Term::Kind(kind) => enrichment.is_some_and(|e| e.content_type == *kind),
+            Term::Language(language) => {
+                enrichment.is_some_and(|e| e.language.as_deref() == Some(language.as_str()))
+            }
+            Term::MinutesOver(n) => minutes.is_some_and(|m| m > *n),
+            Term::MinutesUnder(n) => minutes.is_some_and(|m| m < *n),
+            Term::Word(word) => words
+                .get_or_insert_with(|| {
+                    let text = format!(
+                        "{} {} {} {}",
+                        attributes.title,
+                        attributes.url,
+                        attributes.notes.as_deref().unwrap_or_default(),
+                        tag_names.join(" ")
+                    );
+                    tokenize(&text).collect()
+                })
+                .contains(word),
+        })
+    }
+
+    /// Bookmarks in `data` matching the query, in `sort` order
+    pub fn run<'a>(&self, data: &'a BookmarksData, sort: SortOrder) -> Vec<&'a Resource> {
+        let mut results: Vec<(&Resource, &BookmarkAttributes)> = data
+            .get_bookmarks()
+            .into_iter()
+            .filter(|bookmark| self.matches(data, bookmark))
+            .filter_map(|resource| match resource {
+                Resource::Bookmark { attributes, .. } => Some((resource, attributes)),
+                _ => None,
+            })
+            .collect();
+
+        match sort {
+            SortOrder::Newest => results.sort_by_key(|(_, a)| std::cmp::Reverse(a.created)),
+            SortOrder::Oldest => results.sort_by_key(|(_, a)| a.created),
+            SortOrder::Title => results.sort_by_cached_key(|(_, a)| a.title.to_lowercase()),
+        }
+        results.into_iter().map(|(resource, _)| resource).collect()
+    }
+}
+
+/// Bookmark field `Read` can sort by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Created,
+    /// Falls back to `created` for bookmarks never modified
+    Modified,
+    /// Case-insensitive
+    Title,
+    Url,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Resource types, for sparse fieldsets
+const RESOURCE_TYPES: &[&str] = &["bookmark", "tag", "savedsearch", "rule"];
+
+/// Relationship paths `include` accepts
+const INCLUDE_PATHS: &[&str] = &["tags", "tags.parent"];
+
+/// Which bookmarks `Read` returns and how, following JSON:API `sort`, `page`,
+/// `filter`, `fields`, and `include`. The defaults return everything in file
+/// order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadOptions {
+    #[serde(default)]
+    pub sort: Option<SortField>,
+    #[serde(default)]
+    pub order: Direction,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub offset: usize,
+    /// Only bookmarks tagged with this tag ID
+    #[serde(default)]
+    pub tag_filter: Option<String>,
+    /// `filter[...]`: `tag` (a tag ID), `unread` (`true` or `false`), `url`,
+    /// `author` (a full name, case-insensitive), `type` (a content kind),
+    /// `lang`, and `q` (a [`Query`])
+    #[serde(default)]
+    pub filter: BTreeMap<String, String>,
+    /// `fields[type]`: resource type → comma-separated attributes and
+    /// relationships to keep
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// Comma-separated relationship paths (`tags`, `tags.parent`) whose
+    /// resources go in `included`. Without it, every tag is included.
+    #[serde(default)]
+    pub include: Option<String>,
+}
+
+impl ReadOptions {
+    /// Whether these options ask for anything but the whole file
+    pub fn is_paged(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// `filter[...]` as a query
+    fn filter_query(&self) -> Result<Query> {
+        let mut terms = Vec::new();
+        for (key, value) in &self.filter {
+            match key.as_str() {
+                "tag" => terms.push(Term::TagId(value.clone())),
+                "unread" => terms.push(Term::Unread(value.parse().with_context(|| {
+                    format!("filter[unread] must be true or false, not '{value}'")
+                })?)),
+                "url" => terms.push(Term::Url(urlnorm::key(value))),
+                "author" => terms.push(Term::AuthorName(unicode::fold(value))),
+                "type" => terms.push(Term::Kind(parse_kind(value)?)),
+                "lang" => terms.push(Term::Language(value.to_ascii_lowercase())),
+                "q" => terms.extend(Query::parse(value).context("Invalid filter[q]")?.terms),
+                _ => anyhow::bail!(
+                    "Unknown filter '{key}' (expected tag, unread, url, author, type, lang, or q)"
+                ),
+            }
+        }
+        Ok(Query { terms })
+    }
+
+    /// The page of bookmarks these options select, and how many bookmarks
+    /// matched before paging
+    pub fn page<'a>(&self, data: &'a BookmarksData) -> Result<(Vec<&'a Resource>, usize)> {
+        let query = self.filter_query()?;
+        let mut bookmarks: Vec<(&Resource, &BookmarkAttributes)> = match &self.tag_filter {
+            Some(tag_id) => data.bookmarks_with_tag(tag_id),
+            None => data.get_bookmarks(),
+        }
+        .into_iter()
+        .filter(|resource| query.matches(data, resource))
+        .filter_map(|resource| match resource {
+            Resource::Bookmark { attributes, .. } => Some((resource, attributes)),
+            _ => None,
+        })
+        .collect();
+
+        if let Some(field) = self.sort {
+            let compare = |a: &BookmarkAttributes, b: &BookmarkAttributes| -> Ordering {
+                match field {
+                    SortField::Created => a.created.cmp(&b.created),
+                    SortField::Modified => a
+                        .modified
+                        .unwrap_or(a.created)
+                        .cmp(&b.modified.unwrap_or(b.created)),
+                    SortField::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+                    SortField::Url => a.url.cmp(&b.url),
+                }
+            };
+            // Stable, so ties keep file order either way
+            match self.order {
+                Direction::Asc => bookmarks.sort_by(|(_, a), (_, b)| compare(a, b)),
+                Direction::Desc => bookmarks.sort_by(|(_, a), (_, b)| compare(b, a)),
+            }
+        } else if self.order == Direction::Desc {
+            bookmarks.reverse();
+        }
+
+        let total = bookmarks.len();
+        let page = bookmarks
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|(resource, _)| resource)
+            .collect();
+        Ok((page, total))
+    }
+
+    /// The JSON:API document for these options: the page in `data`, the
+    /// resources `include` asks for in `included`, sparse fieldsets applied,
+    /// and the match count in `meta.page`
+    pub fn document(&self, data: &BookmarksData) -> Result<Value> {
+        let fields = self.sparse_fields()?;
+        let (page, total) = self.page(data)?;
+        let included = match &self.include {
+            Some(paths) => included(data, &page, paths)?,
+            None => data.get_tags(),
+        };
+
+        let mut document = serde_json::json!({
+            "jsonapi": data.jsonapi,
+            "meta": {
+                "schema_version": data.meta.schema_version,
+                "page": {
+                    "offset": self.offset,
+                    "limit": self.limit,
+                    "total": total,
+                },
+            },
+            "data": page,
+            "included": included,
+        });
+
+        if !fields.is_empty() {
+            for section in ["data", "included"] {
+                for resource in document[section].as_array_mut().into_iter().flatten() {
+                    apply_sparse_fields(resource, &fields);
+                }
+            }
+        }
+        Ok(document)
+    }
+
+    /// `fields[...]` split into sets, checking the resource types
+    fn sparse_fields(&self) -> Result<BTreeMap<&str, HashSet<&str>>> {
+        self.fields
+            .iter()
+            .map(|(resource_type, names)| {
+                if !RESOURCE_TYPES.contains(&resource_type.as_str()) {
+                    anyhow::bail!(
+                        "Unknown resource type '{resource_type}' in fields (expected one of {})",
+                        RESOURCE_TYPES.join(", ")
+                    );
+                }
+                let names = names.split(',').map(str::trim).filter(|n| !n.is_empty());
+                Ok((resource_type.as_str(), names.collect()))
+            })
+            .collect()
+    }
+}
+
+/// Tags the page links to, and with `tags.parent` their ancestors, in file order
+fn included<'a>(
+    data: &'a BookmarksData,
+    page: &[&Resource],
+    paths: &str,
+) -> Result<Vec<&'a Resource>> {
+    let mut with_tags = false;
+    let mut with_parents = false;
+    for path in paths.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match path {
+            "tags" => with_tags = true,
+            "tags.parent" => with_parents = true,
+            _ => anyhow::bail!(
+                "Can't include '{path}' (expected {})",
+                INCLUDE_PATHS.join(" or ")
+            ),
+        }
+    }
+
+    let mut tag_ids: HashSet<&str> = HashSet::new();
+    for resource in page {
+        let Resource::Bookmark {
+            relationships: Some(relationships),
+            ..
+        } = resource
+        else {
+            continue;
+        };
+        for tag in relationships.tags.iter().flat_map(|tags| &tags.data) {
+            if with_tags || with_parents {
+                tag_ids.insert(&tag.id);
+            }
+            if with_parents {
+                let mut current = tag.id.as_str();
+                while let Some(parent) = data.tag_parent_id(current) {
+                    if !tag_ids.insert(parent) {
+                        break;
+                    }
+                    current = parent;
+                }
+            }
+        }
+    }
+
+    Ok(data
+        .get_tags()
+        .into_iter()
+        .filter(|tag| tag_ids.contains(tag.id()))
+        .collect())
+}
+
+/// Keep only the listed attributes and relationships of a serialized resource
+fn apply_sparse_fields(resource: &mut Value, fields: &BTreeMap<&str, HashSet<&str>>) {
+    let Some(wanted) = resource
+        .get("type")
+        .and_then(Value::as_str)
+        .and_then(|resource_type| fields.get(resource_type))
+    else {
+        return;
+    };
+    let Some(resource) = resource.as_object_mut() else {
+        return;
+    };
+    for member in ["attributes", "relationships"] {
+        if let Some(Value::Object(values)) = resource.get_mut(member) {
+            values.retain(|name, _| wanted.contains(name.as_str()));
+            if values.is_empty() {
+                resource.remove(member);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+    use chrono::{Duration, Utc};
+
+    fn sample_data() -> BookmarksData {
+        let mut data = BookmarksData::new();
+        let tag = create_tag("Rust".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+
+        for (url, title, tags, days_ago, unread) in [
+            (
+                "https://doc.rust-lang.org/book",
+                "The Book",
+                vec![tag_id.clone()],
+                3,
+                false,
+            ),
+            ("https://blog.rust-lang.org", "Rust Blog", vec![], 2, true),
+            (
+                "https://example.com/async",
+                "Async in depth",
+                vec![tag_id],
+                1,
+                true,
+            ),
+        ] {
+            let mut bookmark = create_bookmark(url.to_string(), title.to_string(), tags);
+            if let Resource::Bookmark { attributes, .. } = &mut bookmark {
+                attributes.created = Utc::now() - Duration::days(days_ago);
+                attributes.unread = unread;
+                if title == "The Book" {
+                    attributes.notes = Some("Ownership chapter".to_string());
+                }
+                if title != "Rust Blog" {
+                    attributes.author = Some("Sam Jones".to_string());
+                }
+            }
+            data.add_bookmark(bookmark).unwrap();
+        }
+        data
+    }
+
+    fn titles(results: &[&Resource]) -> Vec<String> {
+        results
+            .iter()
+            .map(|r| match r {
+                Resource::Bookmark { attributes, .. } => attributes.title.clone(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn run(query: &str, sort: SortOrder) -> Vec<String> {
+        let data = sample_data();
+        titles(&Query::parse(query).unwrap().run(&data, sort))
+    }
+
+    #[test]
+    fn test_words_match_title_url_notes_and_tags() {
+        assert_eq!(
+            run("rust", SortOrder::Oldest),
+            vec!["The Book", "Rust Blog", "Async in depth"]
+        );
+        assert_eq!(run("rust blog", SortOrder::Newest), vec!["Rust Blog"]);
+        assert_eq!(
+            run("RUST-lang", SortOrder::Oldest),
+            vec!["The Book", "Rust Blog"]
+        );
+        assert_eq!(run("ownership", SortOrder::Oldest), vec!["The Book"]);
+    }
+
+    #[test]
+    fn test_filters_and_sorting() {
+        assert_eq!(
+            run("rust + is:unread", SortOrder::Newest),
+            vec!["Async in depth", "Rust Blog"]
+        );
+        assert_eq!(run("tag:rust is:read", SortOrder::Newest), vec!["The Book"]);
+        assert_eq!(
+            run("tag:RUST", SortOrder::Title),
+            vec!["Async in depth", "The Book"]
+        );
+        assert!(run("is:private", SortOrder::Newest).is_empty());
+        assert!(run("is:pinned", SortOrder::Newest).is_empty());
+        assert_eq!(
+            run(
+                "url:https://www.doc.rust-lang.org/book/?utm_source=x",
+                SortOrder::Newest
+            ),
+            vec!["The Book"]
+        );
+        assert!(Query::parse("url:").is_err());
+        assert_eq!(
+            run("author:SAM", SortOrder::Oldest),
+            vec!["The Book", "Async in depth"]
+        );
+        assert!(run("author:sa", SortOrder::Oldest).is_empty());
+        assert!(Query::parse("author:").is_err());
+
+        let by_author = ReadOptions {
+            filter: BTreeMap::from([("author".to_string(), "sam jones".to_string())]),
+            ..ReadOptions::default()
+        };
+        let data = sample_data();
+        let (page, total) = by_author.page(&data).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(page.len(), 2);
+        // An empty query is every bookmark
+        assert_eq!(run("", SortOrder::Newest).len(), 3);
+    }
+
+    #[test]
+    fn test_read_options_page() {
+        let data = sample_data();
+        let page = |options: ReadOptions| {
+            let (page, total) = options.page(&data).unwrap();
+            (titles(&page), total)
+        };
+
+        assert!(!ReadOptions::default().is_paged());
+        assert_eq!(
+            page(ReadOptions::default()).0,
+            vec!["The Book", "Rust Blog", "Async in depth"]
+        );
+
+        let by_title = ReadOptions {
+            sort: Some(SortField::Title),
+            order: Direction::Desc,
+            limit: Some(2),
+            ..ReadOptions::default()
+        };
+        assert!(by_title.is_paged());
+        assert_eq!(
+            page(by_title.clone()),
+            (vec!["The Book".to_string(), "Rust Blog".to_string()], 3)
+        );
+        assert_eq!(
+            page(ReadOptions {
+                offset: 2,
+                ..by_title
+            }),
+            (vec!["Async in depth".to_string()], 3)
+        );
+
+        let tag_id = data.get_tags()[0].id().to_string();
+        let tagged = ReadOptions {
+            sort: Some(SortField::Created),
+            order: Direction::Desc,
+            tag_filter: Some(tag_id),
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            page(tagged),
+            (
+                vec!["Async in depth".to_string(), "The Book".to_string()],
+                2
+            )
+        );
+
+        let past_the_end = ReadOptions {
+            offset: 10,
+            ..ReadOptions::default()
+        };
+        assert_eq!(page(past_the_end), (vec![], 3));
+    }
+
+    #[test]
+    fn test_read_options_filter_fields_include() {
+        let data = sample_data();
+        let tag_id = data.get_tags()[0].id().to_string();
+        let options = ReadOptions {
+            filter: [
+                ("tag".to_string(), tag_id.clone()),
+                ("unread".to_string(), "true".to_string()),
+            ]
+            .into(),
+            fields: [("bookmark".to_string(), "title,tags".to_string())].into(),
+            include: Some("tags".to_string()),
+            ..ReadOptions::default()
+        };
+        assert!(options.is_paged());
+
+        let document = options.document(&data).unwrap();
+        let bookmarks = document["data"].as_array().unwrap();
+        assert_eq!(bookmarks.len(), 1);
+        assert_eq!(document["meta"]["page"]["total"], 1);
+        let attributes = bookmarks[0]["attributes"].as_object().unwrap();
+        assert_eq!(attributes.keys().collect::<Vec<_>>(), vec!["title"]);
+        assert_eq!(attributes["title"], "Async in depth");
+        assert!(bookmarks[0]["relationships"]["tags"].is_object());
+        let included = document["included"].as_array().unwrap();
+        assert_eq!(included.len(), 1);
+        assert_eq!(included[0]["id"], tag_id.as_str());
+
+        // Fields that drop every relationship drop the member
+        let options = ReadOptions {
+            fields: [("bookmark".to_string(), "url".to_string())].into(),
+            include: Some(String::new()),
+            ..ReadOptions::default()
+        };
+        let document = options.document(&data).unwrap();
+        assert!(document["data"][0].get("relationships").is_none());
+        assert_eq!(document["included"].as_array().unwrap().len(), 0);
+
+        for invalid in [
+            ReadOptions {
+                filter: [("color".to_string(), "red".to_string())].into(),
+                ..ReadOptions::default()
+            },
+            ReadOptions {
+                filter: [("unread".to_string(), "maybe".to_string())].into(),
+                ..ReadOptions::default()
+            },
+            ReadOptions {
+                fields: [("widget".to_string(), "name".to_string())].into(),
+                ..ReadOptions::default()
+            },
+            ReadOptions {
+                include: Some("bookmarks".to_string()),
+                ..ReadOptions::default()
+            },
+        ] {
+            assert!(invalid.document(&data).is_err());
+        }
+    }
+
+    #[test]
+    fn test_enrichment_filters() {
+        let mut data = sample_data();
+        for resource in data.data_mut() {
+            if let Resource::Bookmark { attributes, .. } = resource {
+                if attributes.title == "Rust Blog" {
+                    continue;
+                }
+                let long = attributes.title == "The Book";
+                attributes.enrichment = Some(crate::storage::Enrichment {
+                    content_type: if long {
+                        ContentKind::Article
+                    } else {
+                        ContentKind::Video
+                    },
+                    mime_type: Some("text/html".to_string()),
+                    word_count: Some(if long { 9000 } else { 100 }),
+                    reading_minutes: Some(if long { 40 } else { 1 }),
+                    language: Some(if long { "en" } else { "de" }.to_string()),
+                    fetched: Utc::now(),
+                });
+            }
+        }
+        let run = |query: &str| titles(&Query::parse(query).unwrap().run(&data, SortOrder::Title));
+
+        assert_eq!(run("type:article minutes:>20"), vec!["The Book"]);
+        assert_eq!(run("type:VIDEO"), vec!["Async in depth"]);
+        assert_eq!(run("minutes:<5"), vec!["Async in depth"]);
+        assert_eq!(run("lang:EN"), vec!["The Book"]);
+        // Not enriched, so no filter matches
+        assert!(run("rust type:page").is_empty());
+
+        let videos = ReadOptions {
+            filter: BTreeMap::from([("type".to_string(), "video".to_string())]),
+            ..ReadOptions::default()
+        };
+        assert_eq!(videos.page(&data).unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_invalid_queries() {
+        assert!(Query::parse("is:starred").is_err());
+        assert!(Query::parse("tag:").is_err());
+        assert!(Query::parse("type:podcast").is_err());
+        assert!(Query::parse("minutes:10").is_err());
+        assert!(Query::parse("minutes:>long").is_err());
+        assert!(Query::parse("lang:").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use rand::RngCore;
+use std::fs;
+use std::path::Path;
+
+use crate::encryption::{unwrap_key, wrap_key, EncryptionManager, WrappedKey};
+use crate::storage;
+
+/// Master key wrapped with the recovery code. Committed alongside
+/// bookmarks.json so the code works on any clone of the repository.
+pub const RECOVERY_FILE: &str = "recovery.json";
+
+/// 16 words × 8 bits = 128 bits of entropy
+const RECOVERY_CODE_WORDS: usize = 16;
+
+/// One word per byte value, so a code is just random bytes spelled out
+static WORDLIST: [&str; 256] = [
+    "acid", "acorn", "actor", "adult", "agent", "alarm", "album", "alley", "amber", "angle",
+    "ankle", "apple", "april", "apron", "arena", "armor", "arrow", "atlas", "attic", "audio",
+    "autumn", "bacon", "badge", "bagel", "baker", "bamboo", "banana", "banjo", "barrel", "basil",
+    "basket", "beach", "beacon", "beaver", "bench", "berry", "bicycle", "bishop", "blanket",
+    "blossom", "border", "bottle", "bracket", "branch", "breeze", "brick", "bridge", "bronze",
+    "bucket", "buffalo", "butter", "button", "cabin", "cable", "cactus", "camera", "canal",
+    "candle", "canoe", "canyon", "carbon", "carpet", "carrot", "castle", "cattle", "celery",
+    "cement", "cereal", "chalk", "cherry", "chess", "circle", "clock", "cloud", "clover", "cobalt",
+    "coconut", "coffee", "comet", "copper", "coral", "cotton", "cousin", "crater", "crayon",
+    "cricket", "crystal", "curtain", "cushion", "daisy", "dancer", "desert", "diamond", "dinner",
+    "dolphin", "donkey", "dragon", "drum", "eagle", "elbow", "ember", "engine", "falcon",
+    "feather", "fence", "ferry", "fiddle", "finger", "flame", "flute", "forest", "fossil",
+    "fountain", "galaxy", "garden", "garlic", "gentle", "ginger", "giraffe", "glacier", "globe",
+    "goose", "grape", "gravel", "guitar", "hammer", "harbor", "harvest", "hazel", "helmet",
+    "hermit", "hollow", "honey", "horizon", "hotel", "island", "ivory", "jacket", "jaguar",
+    "jasmine", "jelly", "jungle", "kayak", "kernel", "kettle", "kitten", "ladder", "lagoon",
+    "lantern", "laptop", "lemon", "lentil", "lizard", "lobster", "locket", "lumber", "magnet",
+    "mango", "maple", "marble", "meadow", "melon", "mirror", "monkey", "mosaic", "motor",
+    "mountain", "muffin", "museum", "mustard", "napkin", "nectar", "needle", "noodle", "nutmeg",
+    "oasis", "ocean", "olive", "onion", "orange", "orbit", "orchid", "otter", "oyster", "paddle",
+    "palace", "panda", "paper", "parrot", "pasta", "peach", "peanut", "pebble", "pencil", "pepper",
+    "piano", "pickle", "pilot", "pine", "planet", "plaster", "pocket", "potato", "pumpkin",
+    "puzzle", "quartz", "quilt", "rabbit", "radar", "radio", "raven", "ribbon", "river", "rocket",
+    "saddle", "salmon", "sandal", "satin", "saucer", "scarf", "sheep", "shell", "silver", "sketch",
+    "sleeve", "socket", "spider", "spoon", "squirrel", "stable", "statue", "sugar", "summit",
+    "sunset", "table", "tiger", "timber", "tomato", "tractor", "trumpet", "tulip", "tunnel",
+    "turtle", "umbrella", "valley", "velvet", "violin", "volcano", "wagon", "walnut", "whale",
+    "willow", "window", "winter", "yogurt", "zebra",
+];
+
+/// Generate a new random recovery code
+pub fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; RECOVERY_CODE_WORDS];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    bytes
+        .iter()
+        .map(|b| WORDLIST[usize::from(*b)])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Normalize user input (case, spacing, separators) and check every word is valid
+pub fn normalize_recovery_code(code: &str) -> Result<String> {
+    let words: Vec<String> = code
+        .split(|c: char| c.is_whitespace() || c == '-' || c == ',')
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    if words.len() != RECOVERY_CODE_WORDS {
+        anyhow::bail!(
+            "Recovery code must be {RECOVERY_CODE_WORDS} words, got {}",
+            words.len()
+        );
+    }
+    if let Some(unknown) = words.iter().find(|w| !WORDLIST.contains(&w.as_str())) {
+        anyhow::bail!("Unknown word in recovery code: {unknown}");
+    }
+
+    Ok(words.join(" "))
+}
+
+/// Wrap `key` with a fresh recovery code, write the recovery file, and return the code.
+/// The code is never stored; the caller must show it to the user exactly once.
+pub fn create_recovery_file<P: AsRef<Path>>(repo_path: P, key: &[u8]) -> Result<String> {
+    let code = generate_recovery_code();
+    let wrapped = wrap_key(key, &code)?;
+
+    let json =
+        serde_json::to_string_pretty(&wrapped).context("Failed to serialize recovery file")?;
+    storage::write_atomic(&repo_path.as_ref().join(RECOVERY_FILE), json.as_bytes())?;
+
+    Ok(code)
+}
+
+/// Delete the recovery file once encryption is off; the key it wraps no longer
+/// protects anything. Returns whether there was a file to delete.
+pub fn remove_recovery_file<P: AsRef<Path>>(repo_path: P) -> Result<bool> {
+    let path = repo_path.as_ref().join(RECOVERY_FILE);
+    if !path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(&path).context("Failed to remove recovery file")?;
+    Ok(true)
+}
+
+/// Create a recovery file for the key currently stored in the Keychain
+pub fn create_for_stored_key<P: AsRef<Path>>(repo_path: P) -> Result<String> {
+    let key = EncryptionManager::get_key_from_keychain()?;
+    create_recovery_file(repo_path, &key)
+}
+
+/// Recover the master key from the recovery file using a recovery code
+pub fn recover_key<P: AsRef<Path>>(repo_path: P, code: &str) -> Result<Vec<u8>> {
+    let code = normalize_recovery_code(code)?;
+
+    let path = repo_path.as_ref().join(RECOVERY_FILE);
+    let content = fs::read_to_string(&path).context("No recovery file found in repository")?;
+    let wrapped: WrappedKey =
+        serde_json::from_str(&content).context("Failed to parse recovery file")?;
+
+    unwrap_key(&wrapped, &code)
+}
+
+/// Check whether a recovery code unlocks this repository's recovery file
+pub fn verify_recovery_code<P: AsRef<Path>>(repo_path: P, code: &str) -> Result<bool> {
+    if !repo_path.as_ref().join(RECOVERY_FILE).exists() {
+        anyhow::bail!("No recovery file found in repository");
+    }
+    Ok(recover_key(repo_path, code).is_ok())
+}
+
+/// Recover the master key and put it back in the Keychain
+pub fn restore_key<P: AsRef<Path>>(repo_path: P, code: &str) -> Result<()> {
+    let key = recover_key(repo_path, code)?;
+    EncryptionManager::store_key_in_keychain(&key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_wordlist_is_unique() {
+        let unique: HashSet<_> = WORDLIST.iter().collect();
+        assert_eq!(unique.len(), WORDLIST.len());
+    }
+
+    #[test]
+    fn test_generated_code_normalizes() {
+        let code = generate_recovery_code();
+        assert_eq!(code.split(' ').count(), RECOVERY_CODE_WORDS);
+        assert_eq!(normalize_recovery_code(&code).unwrap(), code);
+
+        let messy = format!("  {}  ", code.to_uppercase().replace(' ', "-"));
+        assert_eq!(normalize_recovery_code(&messy).unwrap(), code);
+    }
+
+    #[test]
+    fn test_normalize_rejects_bad_codes() {
+        assert!(normalize_recovery_code("apple banana").is_err());
+        let unknown = ["notaword"; RECOVERY_CODE_WORDS].join(" ");
+        assert!(normalize_recovery_code(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_recovery_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let key = [9u8; 32];
+
+        let code = create_recovery_file(temp_dir.path(), &key).unwrap();
+        assert_eq!(recover_key(temp_dir.path(), &code).unwrap(), key);
+        assert!(verify_recovery_code(temp_dir.path(), &code).unwrap());
+
+        let wrong = generate_recovery_code();
+        assert!(!verify_recovery_code(temp_dir.path(), &wrong).unwrap());
+    }
+
+    #[test]
+    fn test_remove_recovery_file() {
+        let temp_dir = TempDir::new().unwrap();
+        create_recovery_file(temp_dir.path(), &[9u8; 32]).unwrap();
+
+        assert!(remove_recovery_file(temp_dir.path()).unwrap());
+        assert!(!temp_dir.path().join(RECOVERY_FILE).exists());
+        assert!(!remove_recovery_file(temp_dir.path()).unwrap());
+    }
+
+    #[test]
+    fn test_verify_without_recovery_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let code = generate_recovery_code();
+        assert!(verify_recovery_code(temp_dir.path(), &code).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use chrono::{DateTime, Months, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::activity::ActivityLog;
+use crate::expiry::{self, Expired};
+use crate::storage::{BookmarksData, Resource};
+
+/// Months without a visit before a bookmark counts as stale, by default
+pub const DEFAULT_STALE_MONTHS: u32 = 6;
+
+/// How far ahead the expiring report looks, by default
+pub const DEFAULT_EXPIRING_DAYS: u32 = 7;
+
+/// How long one domain lookup may take before it counts as failed
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Domain lookups in flight at once
+const MAX_CONCURRENT_LOOKUPS: usize = 16;
+
+/// Reports `Report` can generate
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportKind {
+    /// Bookmarks that look abandoned; see [`stale`]
+    Stale,
+    /// Bookmarks past or coming up to their `expires_at`; see [`expiring`]
+    Expiring,
+}
+
+/// A bookmark as listed in a report
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ReportEntry {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub created: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_visited: Option<DateTime<Utc>>,
+}
+
+/// Bookmarks worth reviewing in a large collection. Visits are recorded per
+/// device, so "never visited" means never visited from this one.
+#[derive(Debug, Serialize, Clone, PartialEq, Default)]
+pub struct StaleReport {
+    /// No recorded visit, oldest first
+    pub never_visited: Vec<ReportEntry>,
+    /// Last visited before `visited_before`, least recently visited first
+    pub not_visited_recently: Vec<ReportEntry>,
+    pub visited_before: Option<DateTime<Utc>>,
+    /// On a domain that no longer resolves; empty unless domains were checked
+    pub unresolved_domains: Vec<ReportEntry>,
+    /// Whether domains were looked up. False when not asked for, or when no
+    /// lookup worked at all, which more likely means this device is offline.
+    pub domains_checked: bool,
+}
+
+/// Bookmarks with an `expires_at` that has passed or is coming up
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ExpiringReport {
+    /// Past their expiry but still saved (tagged, or not dealt with yet),
+    /// soonest first
+    pub expired: Vec<Expired>,
+    /// Expiring before `until`, soonest first
+    pub expiring: Vec<Expired>,
+    pub until: DateTime<Utc>,
+}
+
+/// Bookmarks that have expired by `now` or will within `days`
+pub fn expiring(data: &BookmarksData, now: DateTime<Utc>, days: u32) -> ExpiringReport {
+    let until = now + chrono::Duration::days(i64::from(days));
+    let (expired, expiring) = expiry::expiring_before(data, until)
+        .into_iter()
+        .partition(|bookmark| bookmark.expires_at <= now);
+    ExpiringReport {
+        expired,
+        expiring,
+        until,
+    }
+}
+
+/// Sort bookmarks into the visit-based sections of a [`StaleReport`]: never
+/// visited, or not visited for `months` before `now`
+pub fn stale(
+    data: &BookmarksData,
+    activity: &ActivityLog,
+    now: DateTime<Utc>,
+    months: u32,
+) -> StaleReport {
+    let visited_before = now.checked_sub_months(Months::new(months));
+    let mut report = StaleReport {
+        visited_before,
+        ..StaleReport::default()
+    };
+    for (entry, _) in entries(data, activity) {
+        match entry.last_visited {
+            None => report.never_visited.push(entry),
+            Some(at) if visited_before.is_some_and(|before| at < before) => {
+                report.not_visited_recently.push(entry);
+            }
+            Some(_) => {}
+        }
+    }
+    report.never_visited.sort_by_key(|entry| entry.created);
+    report
+        .not_visited_recently
+        .sort_by_key(|entry| entry.last_visited);
+    report
+}
+
+/// Fill in `unresolved_domains` by looking up every bookmark's host,
+/// calling `on_progress(done, total)` as lookups finish
+pub async fn check_domains(
+    report: &mut StaleReport,
+    data: &BookmarksData,
+    activity: &ActivityLog,
+    mut on_progress: impl FnMut(usize, usize),
+) {
+    let bookmarks = entries(data, activity);
+    let hosts: BTreeSet<String> = bookmarks
+        .iter()
+        .filter_map(|(_, host)| host.clone())
+        .collect();
+
+    let total = hosts.len();
+    let mut unresolved = HashSet::new();
+    let mut pending = hosts.into_iter();
+    let mut lookups = JoinSet::new();
+    let mut done = 0;
+    loop {
+        while lookups.len() < MAX_CONCURRENT_LOOKUPS {
+            let Some(host) = pending.next() else {
+                break;
+            };
+            lookups.spawn(async move {
+                let resolves = resolves(&host).await;
+                (host, resolves)
+            });
+        }
+        let Some(finished) = lookups.join_next().await else {
+            break;
+        };
+        if let Ok((host, false)) = finished {
+            unresolved.insert(host);
+        }
+        done += 1;
+        on_progress(done, total);
+    }
+
+    report.domains_checked = total == 0 || unresolved.len() < total;
+    if !report.domains_checked {
+        return;
+    }
+    report.unresolved_domains = bookmarks
+        .into_iter()
+        .filter(|(_, host)| host.as_ref().is_some_and(|h| unresolved.contains(h)))
+        .map(|(entry, _)| entry)
+        .collect();
+}
+
+async fn resolves(host: &str) -> bool {
+    let lookup = tokio::net::lookup_host((host, 443));
+    match tokio::time::timeout(LOOKUP_TIMEOUT, lookup).await {
+        Ok(Ok(mut addresses)) => addresses.next().is_some(),
+        _ => false,
+    }
+}
+
+/// Every bookmark with its last visit and host
+fn entries(data: &BookmarksData, activity: &ActivityLog) -> Vec<(ReportEntry, Option<String>)> {
+    data.get_bookmarks()
+        .into_iter()
+        .filter_map(|resource| match resource {
+            Resource::Bookmark { id, attributes, .. } => Some((id, attributes)),
+            _ => None,
+        })
+        .map(|(id, attributes)| {
+            let last_visited = activity
+                .bookmarks
+                .get(id)
+                .filter(|visits| visits.visit_count > 0)
+                .and_then(|visits| visits.last_visited);
+            let host = url::Url::parse(&attributes.url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_lowercase));
+            let entry = ReportEntry {
+                id: id.clone(),
+                title: attributes.title.clone(),
+                url: attributes.url.clone(),
+                created: attributes.created,
+                last_visited,
+            };
+            (entry, host)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+
+    fn sample() -> (BookmarksData, ActivityLog, Vec<String>) {
+        let mut data = BookmarksData::new();
+        let mut ids = Vec::new();
+        for url in [
+            "https://never.example.com",
+            "https://old.example.com",
+            "https://recent.example.com",
+        ] {
+            let bookmark = create_bookmark(url.to_string(), url.to_string(), vec![]);
+            ids.push(bookmark.id().to_string());
+            data.add_bookmark(bookmark).unwrap();
+        }
+        let mut activity = ActivityLog::default();
+        activity.record_visit(&ids[1], Utc::now() - chrono::Duration::days(400));
+        activity.record_visit(&ids[2], Utc::now() - chrono::Duration::days(3));
+        (data, activity, ids)
+    }
+
+    #[test]
+    fn test_stale_sections() {
+        let (data, activity, ids) = sample();
+        let report = stale(&data, &activity, Utc::now(), DEFAULT_STALE_MONTHS);
+        let section = |entries: &[ReportEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.id.clone()).collect()
+        };
+        assert_eq!(section(&report.never_visited), [ids[0].clone()]);
+        assert_eq!(section(&report.not_visited_recently), [ids[1].clone()]);
+        assert!(!report.domains_checked);
+
+        let report = stale(&data, &activity, Utc::now(), 24);
+        assert!(report.not_visited_recently.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_domains() {
+        let mut data = BookmarksData::new();
+        for url in ["http://localhost:8080/", "https://gone.invalid/page"] {
+            data.add_bookmark(create_bookmark(url.to_string(), url.to_string(), vec![]))
+                .unwrap();
+        }
+        let activity = ActivityLog::default();
+        let mut report = stale(&data, &activity, Utc::now(), DEFAULT_STALE_MONTHS);
+        let mut progress = Vec::new();
+        check_domains(&mut report, &data, &activity, |done, total| {
+            progress.push((done, total));
+        })
+        .await;
+
+        assert!(report.domains_checked);
+        assert_eq!(report.unresolved_domains.len(), 1);
+        assert_eq!(
+            report.unresolved_domains[0].url,
+            "https://gone.invalid/page"
+        );
+        assert_eq!(progress.last(), Some(&(2, 2)));
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::Result;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+use crate::git::GitRepo;
+
+/// Attempts per network operation, kept in the repository's local git config
+const ATTEMPTS_KEY: &str = "webtags.retryAttempts";
+/// Delay before the first retry, in milliseconds
+const BASE_DELAY_KEY: &str = "webtags.retryBaseMs";
+
+/// Most attempts a repository may ask for
+pub const MAX_ATTEMPTS: u32 = 10;
+
+/// How often and how patiently to retry an operation that failed with a
+/// transient (e.g. network) error.
+///
+/// Delays grow exponentially from `base_delay`, capped at `max_delay`, with
+/// "equal jitter": half the delay is fixed and half is random, so devices
+/// that lost connectivity together don't all retry in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total attempts including the first; 1 disables retrying
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+        }
+    }
+}
+
+/// Retry settings to change with [`RetryPolicy::update`]; `None` leaves one
+/// as it is
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Update {
+    #[serde(default)]
+    pub attempts: Option<u32>,
+    #[serde(default)]
+    pub base_ms: Option<u64>,
+}
+
+impl RetryPolicy {
+    /// The policy configured for a repository, or the default
+    pub fn load(repo: &GitRepo) -> Self {
+        let default = Self::default();
+        Self {
+            max_attempts: repo
+                .local_config(ATTEMPTS_KEY)
+                .and_then(|n| n.parse().ok())
+                .filter(|n| (1..=MAX_ATTEMPTS).contains(n))
+                .unwrap_or(default.max_attempts),
+            base_delay: repo
+                .local_config(BASE_DELAY_KEY)
+                .and_then(|ms| ms.parse().ok())
+                .map(Duration::from_millis)
+                .filter(|delay| *delay <= default.max_delay)
+                .unwrap_or(default.base_delay),
+            ..default
+        }
+    }
+
+    /// Change the settings given, leaving the others as they are
+    pub fn update(repo: &GitRepo, update: Update) -> Result<Self> {
+        let default = Self::default();
+        if update
+            .attempts
+            .is_some_and(|n| !(1..=MAX_ATTEMPTS).contains(&n))
+        {
+            anyhow::bail!("Retry attempts must be between 1 and {MAX_ATTEMPTS}");
+        }
+        if update
+            .base_ms
+            .is_some_and(|ms| Duration::from_millis(ms) > default.max_delay)
+        {
+            anyhow::bail!(
+                "Retry delay must be at most {} ms",
+                default.max_delay.as_millis()
+            );
+        }
+        if let Some(attempts) = update.attempts {
+            let attempts = (attempts != default.max_attempts).then(|| attempts.to_string());
+            repo.set_local_config(ATTEMPTS_KEY, attempts.as_deref())?;
+        }
+        if let Some(ms) = update.base_ms {
+            let ms = (Duration::from_millis(ms) != default.base_delay).then(|| ms.to_string());
+            repo.set_local_config(BASE_DELAY_KEY, ms.as_deref())?;
+        }
+        Ok(Self::load(repo))
+    }
+
+    /// Try once and never retry
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retry number `retry` (0 for the first retry)
+    pub fn delay_for(&self, retry: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let half = cap / 2;
+        let jitter_ms = u64::try_from(cap.saturating_sub(half).as_millis()).unwrap_or(u64::MAX);
+        half + Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms))
+    }
+
+    /// Run a blocking operation, sleeping the thread between attempts.
+    /// Errors for which `is_retryable` returns false are returned immediately.
+    pub fn run<T>(
+        &self,
+        mut operation: impl FnMut() -> Result<T>,
+        is_retryable: impl Fn(&anyhow::Error) -> bool,
+    ) -> Result<T> {
+        let mut retry = 0;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(e) if retry + 1 < self.max_attempts && is_retryable(&e) => {
+                    let delay = self.delay_for(retry);
+                    log::warn!("Transient failure, retrying in {delay:?}: {e:#}");
+                    std::thread::sleep(delay);
+                    retry += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Async counterpart of [`RetryPolicy::run`]
+    pub async fn run_async<T, F, Fut>(
+        &self,
+        mut operation: F,
+        is_retryable: impl Fn(&anyhow::Error) -> bool,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if retry + 1 < self.max_attempts && is_retryable(&e) => {
+                    let delay = self.delay_for(retry);
+                    log::warn!("Transient failure, retrying in {delay:?}: {e:#}");
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn instant_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_retries_until_success() {
+        let calls = Cell::new(0);
+        let result = instant_policy(4).run(
+            || {
+                calls.set(calls.get() + 1);
+                if calls.get() < 3 {
+                    anyhow::bail!("transient")
+                }
+                Ok(calls.get())
+            },
+            |_| true,
+        );
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let calls = Cell::new(0);
+        let result: Result<()> = instant_policy(3).run(
+            || {
+                calls.set(calls.get() + 1);
+                anyhow::bail!("still down")
+            },
+            |_| true,
+        );
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn test_fatal_errors_are_not_retried() {
+        let calls = Cell::new(0);
+        let result: Result<()> = instant_policy(5).run(
+            || {
+                calls.set(calls.get() + 1);
+                anyhow::bail!("bad credentials")
+            },
+            |_| false,
+        );
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn test_delay_is_bounded() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        for retry in 0..10 {
+            let cap = (Duration::from_millis(100) * 2u32.pow(retry)).min(Duration::from_secs(1));
+            let delay = policy.delay_for(retry);
+            assert!(delay >= cap / 2 && delay <= cap, "{delay:?} vs {cap:?}");
+        }
+        // Huge retry counts must not overflow
+        assert!(policy.delay_for(u32::MAX) <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_policy_kept_in_local_config() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = GitRepo::init(temp_dir.path()).unwrap();
+        assert_eq!(RetryPolicy::load(&repo), RetryPolicy::default());
+
+        let policy = RetryPolicy::update(
+            &repo,
+            Update {
+                attempts: Some(2),
+                base_ms: Some(100),
+            },
+        )
+        .unwrap();
+        assert_eq!(policy.max_attempts, 2);
+        assert_eq!(policy.base_delay, Duration::from_millis(100));
+        assert_eq!(
+            GitRepo::init(temp_dir.path()).unwrap().retry_policy(),
+            policy
+        );
+
+        assert!(RetryPolicy::update(
+            &repo,
+            Update {
+                attempts: Some(0),
+                base_ms: None,
+            }
+        )
+        .is_err());
+        assert!(RetryPolicy::update(
+            &repo,
+            Update {
+                attempts: None,
+                base_ms: Some(60_000),
+            }
+        )
+        .is_err());
+
+        // Back to the defaults removes the settings
+        RetryPolicy::update(
+            &repo,
+            Update {
+                attempts: Some(4),
+                base_ms: Some(500),
+            },
+        )
+        .unwrap();
+        assert_eq!(repo.local_config(ATTEMPTS_KEY), None);
+        assert_eq!(repo.local_config(BASE_DELAY_KEY), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_async_retries() {
+        let calls = Cell::new(0);
+        let result = instant_policy(2)
+            .run_async(
+                || {
+                    calls.set(calls.get() + 1);
+                    async {
+                        if calls.get() < 2 {
+                            anyhow::bail!("transient")
+                        }
+                        Ok(())
+                    }
+                },
+                |_| true,
+            )
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(calls.get(), 2);
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use url::Url;
+
+use crate::storage::{
+    BookmarkAttributes, BookmarkRelationships, BookmarksData, MatchKind, RelationshipData,
+    Resource, ResourceIdentifier, RuleAttributes, RuleField,
+};
+
+/// Longest pattern a rule may have
+pub const MAX_PATTERN_LENGTH: usize = 500;
+
+/// A rule compiled and ready to run against bookmarks
+#[derive(Debug)]
+pub struct Rule {
+    field: RuleField,
+    matcher: Matcher,
+    tag_ids: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    /// Lowercased pattern
+    Contains(String),
+    /// Lowercased pattern
+    Equals(String),
+    Regex(Regex),
+}
+
+impl Rule {
+    /// Check a rule's pattern and build its matcher
+    pub fn compile(attributes: &RuleAttributes, tag_ids: Vec<String>) -> Result<Self> {
+        let pattern = &attributes.pattern;
+        if pattern.is_empty() || pattern.len() > MAX_PATTERN_LENGTH {
+            anyhow::bail!("Pattern must be between 1-{MAX_PATTERN_LENGTH} characters");
+        }
+        let matcher = match attributes.match_kind {
+            MatchKind::Contains => Matcher::Contains(pattern.to_lowercase()),
+            MatchKind::Equals => Matcher::Equals(pattern.to_lowercase()),
+            MatchKind::Regex => Matcher::Regex(
+                RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .with_context(|| format!("Invalid regex '{pattern}'"))?,
+            ),
+        };
+        Ok(Self {
+            field: attributes.field,
+            matcher,
+            tag_ids,
+        })
+    }
+
+    pub fn matches(&self, bookmark: &BookmarkAttributes) -> bool {
+        let value = match self.field {
+            RuleField::Url => bookmark.url.to_lowercase(),
+            RuleField::Title => bookmark.title.to_lowercase(),
+            RuleField::Domain => match domain(&bookmark.url) {
+                Some(domain) => domain,
+                None => return false,
+            },
+        };
+
+        match &self.matcher {
+            Matcher::Contains(pattern) => value.contains(pattern.as_str()),
+            Matcher::Equals(pattern) if self.field == RuleField::Domain => {
+                value == *pattern
+                    || value
+                        .strip_suffix(pattern.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            }
+            Matcher::Equals(pattern) => value == *pattern,
+            Matcher::Regex(regex) => regex.is_match(&value),
+        }
+    }
+}
+
+/// Lowercase host of `url`, if it has one
+pub(crate) fn domain(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(str::to_lowercase)
+}
+
+/// Compile every rule in `data`
+pub fn rules(data: &BookmarksData) -> Result<Vec<Rule>> {
+    data.get_rules()
+        .into_iter()
+        .filter_map(|resource| match resource {
+            Resource::Rule {
+                id,
+                attributes,
+                relationships,
+            } => Some((id, attributes, relationships)),
+            _ => None,
+        })
+        .map(|(id, attributes, relationships)| {
+            let tag_ids = relationships
+                .tags
+                .data
+                .iter()
+                .map(|tag| tag.id.clone())
+                .collect();
+            Rule::compile(attributes, tag_ids).with_context(|| format!("Rule {id} is invalid"))
+        })
+        .collect()
+}
+
+/// Tag the bookmarks the rules in `data` match, among those `include`
+/// accepts by ID. Returns the IDs of bookmarks that gained a tag.
+pub fn apply(data: &mut BookmarksData, include: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+    let rules = rules(data)?;
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut tagged = Vec::new();
+    for resource in data.data_mut() {
+        let Resource::Bookmark {
+            id,
+            attributes,
+            relationships,
+            ..
+        } = resource
+        else {
+            continue;
+        };
+        if !include(id) {
+            continue;
+        }
+
+        let mut changed = false;
+        for rule in rules.iter().filter(|rule| rule.matches(attributes)) {
+            let tags = &mut relationships
+                .get_or_insert_with(|| BookmarkRelationships { tags: None })
+                .tags
+                .get_or_insert_with(|| RelationshipData { data: Vec::new() })
+                .data;
+            for tag_id in &rule.tag_ids {
+                if !tags.iter().any(|tag| tag.id == *tag_id) {
+                    tags.push(ResourceIdentifier {
+                        resource_type: "tag".to_string(),
+                        id: tag_id.clone(),
+                    });
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            tagged.push(id.clone());
+        }
+    }
+
+    Ok(tagged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_rule, create_tag, UnknownFields};
+
+    fn rule(field: RuleField, match_kind: MatchKind, pattern: &str) -> Rule {
+        let attributes = RuleAttributes {
+            name: "test".to_string(),
+            field,
+            match_kind,
+            pattern: pattern.to_string(),
+            unknown: UnknownFields::new(),
+        };
+        Rule::compile(&attributes, Vec::new()).unwrap()
+    }
+
+    fn bookmark(url: &str, title: &str) -> BookmarkAttributes {
+        match create_bookmark(url.to_string(), title.to_string(), vec![]) {
+            Resource::Bookmark { attributes, .. } => attributes,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_matching() {
+        let github = bookmark("https://Gist.GitHub.com/user/1", "A Rust snippet");
+
+        assert!(rule(RuleField::Domain, MatchKind::Equals, "github.com").matches(&github));
+        assert!(!rule(RuleField::Domain, MatchKind::Equals, "hub.com").matches(&github));
+        assert!(rule(RuleField::Url, MatchKind::Contains, "GITHUB.com/user").matches(&github));
+        assert!(rule(RuleField::Title, MatchKind::Regex, r"\brust\b").matches(&github));
+        assert!(!rule(RuleField::Title, MatchKind::Equals, "rust").matches(&github));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        let attributes = |match_kind, pattern: &str| RuleAttributes {
+            name: "test".to_string(),
+            field: RuleField::Url,
+            match_kind,
+            pattern: pattern.to_string(),
+            unknown: UnknownFields::new(),
+        };
+        assert!(Rule::compile(&attributes(MatchKind::Regex, "(unclosed"), Vec::new()).is_err());
+        assert!(Rule::compile(&attributes(MatchKind::Contains, ""), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_apply_tags_matching_bookmarks_once() {
+        let mut data = BookmarksData::new();
+        let tag = create_tag("code".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+        data.add_rule(create_rule(
+            "GitHub".to_string(),
+            RuleField::Domain,
+            MatchKind::Equals,
+            "github.com".to_string(),
+            vec![tag_id.clone()],
+        ))
+        .unwrap();
+
+        let repo = create_bookmark(
+            "https://github.com/a/b".to_string(),
+            "b".to_string(),
+            vec![],
+        );
+        let other = create_bookmark("https://example.com".to_string(), "x".to_string(), vec![]);
+        let later = create_bookmark(
+            "https://github.com/c/d".to_string(),
+            "d".to_string(),
+            vec![],
+        );
+        let (repo_id, later_id) = (repo.id().to_string(), later.id().to_string());
+        for bookmark in [repo, other, later] {
+            data.add_bookmark(bookmark).unwrap();
+        }
+
+        assert_eq!(
+            apply(&mut data, |id| id == repo_id).unwrap(),
+            vec![repo_id.clone()]
+        );
+        assert_eq!(apply(&mut data, |_| true).unwrap(), vec![later_id]);
+        assert!(apply(&mut data, |_| true).unwrap().is_empty());
+
+        assert_eq!(data.bookmarks_with_tag(&tag_id).len(), 2);
+        assert!(data.validate().is_ok());
+    }
+}
@@ -0,0 +1,250 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::index::tokenize;
+use crate::storage::{BookmarksData, Resource};
+use crate::unicode;
+use crate::urlnorm;
+
+/// Titles at least this similar, on the same site, are likely the same page
+pub const SIMILARITY_THRESHOLD: f64 = 0.85;
+
+/// Shorter normalized titles ("Home", "Login") say too little to compare
+const MIN_TITLE_CHARS: usize = 12;
+
+/// What sites put between a page's own title and their name
+const TITLE_SEPARATORS: &[&str] = &[" | ", " - ", " – ", " — ", " · ", " :: ", " » "];
+
+/// Bookmarks on one site whose titles are nearly the same, though their URLs
+/// differ
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct NearDuplicates {
+    /// The registrable domain they share
+    pub domain: String,
+    /// Title of the oldest
+    pub title: String,
+    /// Oldest first
+    pub ids: Vec<String>,
+    /// Title similarity of the least similar pair that joined the group,
+    /// from 0 to 1
+    pub similarity: f64,
+}
+
+/// A title as compared: case-folded, without the site name sites add before
+/// or after it, and with punctuation dropped
+pub fn normalize_title(title: &str) -> String {
+    let folded = unicode::fold(title);
+    let mut parts = vec![folded.as_str()];
+    for separator in TITLE_SEPARATORS {
+        parts = parts.into_iter().flat_map(|p| p.split(separator)).collect();
+    }
+    // The page's own title is nearly always the longest part
+    let longest = parts
+        .into_iter()
+        .max_by_key(|part| part.chars().count())
+        .unwrap_or_default();
+    tokenize(longest).collect::<Vec<_>>().join(" ")
+}
+
+/// Character pairs of a normalized title, with how often each appears
+fn bigrams(title: &str) -> HashMap<(char, char), usize> {
+    let chars: Vec<char> = title.chars().collect();
+    let mut counts = HashMap::new();
+    for pair in chars.windows(2) {
+        *counts.entry((pair[0], pair[1])).or_default() += 1;
+    }
+    counts
+}
+
+/// Sørensen–Dice coefficient of two bigram sets: 1 for the same text
+#[allow(clippy::cast_precision_loss)] // titles are far shorter than 2^52 chars
+fn dice(a: &HashMap<(char, char), usize>, b: &HashMap<(char, char), usize>) -> f64 {
+    let total: usize = a.values().sum::<usize>() + b.values().sum::<usize>();
+    if total == 0 {
+        return 0.0;
+    }
+    let shared: usize = a
+        .iter()
+        .map(|(pair, count)| (*count).min(b.get(pair).copied().unwrap_or(0)))
+        .sum();
+    2.0 * shared as f64 / total as f64
+}
+
+/// How alike two titles are once normalized, from 0 to 1
+pub fn title_similarity(a: &str, b: &str) -> f64 {
+    dice(&bigrams(&normalize_title(a)), &bigrams(&normalize_title(b)))
+}
+
+/// Find the group `i` belongs to, flattening the path on the way
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Bookmarks in `data` that are probably the same page saved from different
+/// URLs: same registrable domain, and titles at least `threshold` similar.
+/// Bookmarks whose URLs are the same page anyway (see
+/// [`urlnorm::duplicates`]) aren't paired up again here.
+pub fn near_duplicates(data: &BookmarksData, threshold: f64) -> Vec<NearDuplicates> {
+    let rules = urlnorm::rules();
+    let mut bookmarks: Vec<_> = data
+        .get_bookmarks()
+        .into_iter()
+        .filter_map(|resource| match resource {
+            Resource::Bookmark { id, attributes, .. } => {
+                let title = normalize_title(&attributes.title);
+                if title.chars().count() < MIN_TITLE_CHARS {
+                    return None;
+                }
+                let domain = urlnorm::registrable_domain(&attributes.url)?;
+                Some((
+                    attributes.created,
+                    id,
+                    &attributes.title,
+                    domain,
+                    urlnorm::key_with(&attributes.url, &rules),
+                    bigrams(&title),
+                ))
+            }
+            _ => None,
+        })
+        .collect();
+    bookmarks.sort_by_key(|(created, ..)| *created);
+
+    let mut by_domain: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (_, _, _, domain, _, _)) in bookmarks.iter().enumerate() {
+        by_domain.entry(domain).or_default().push(i);
+    }
+
+    let mut parents: Vec<usize> = (0..bookmarks.len()).collect();
+    let mut weakest: HashMap<usize, f64> = HashMap::new();
+    for indexes in by_domain.values() {
+        for (n, &i) in indexes.iter().enumerate() {
+            for &j in &indexes[n + 1..] {
+                if bookmarks[i].4 == bookmarks[j].4 {
+                    continue;
+                }
+                let similarity = dice(&bookmarks[i].5, &bookmarks[j].5);
+                if similarity < threshold {
+                    continue;
+                }
+                let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+                let joined = [a, b]
+                    .iter()
+                    .filter_map(|root| weakest.get(root))
+                    .fold(similarity, |low, s| low.min(*s));
+                // Keep the older bookmark as the root, so groups list oldest first
+                let (root, child) = if a <= b { (a, b) } else { (b, a) };
+                parents[child] = root;
+                weakest.remove(&child);
+                weakest.insert(root, joined);
+            }
+        }
+    }
+
+    let mut groups: Vec<NearDuplicates> = Vec::new();
+    let mut by_root: HashMap<usize, usize> = HashMap::new();
+    for (i, (_, id, title, domain, _, _)) in bookmarks.iter().enumerate() {
+        let root = find(&mut parents, i);
+        let Some(&similarity) = weakest.get(&root) else {
+            continue;
+        };
+        if let Some(&group) = by_root.get(&root) {
+            groups[group].ids.push((*id).clone());
+        } else {
+            by_root.insert(root, groups.len());
+            groups.push(NearDuplicates {
+                domain: domain.clone(),
+                title: (*title).clone(),
+                ids: vec![(*id).clone()],
+                similarity: (similarity * 100.0).round() / 100.0,
+            });
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+
+    #[test]
+    fn test_normalize_title() {
+        assert_eq!(
+            normalize_title("Why Rust's Borrow Checker Is Great | Example Blog"),
+            "why rust s borrow checker is great"
+        );
+        assert_eq!(
+            normalize_title("Tech News — Why Rust's Borrow Checker Is Great"),
+            "why rust s borrow checker is great"
+        );
+        assert!(title_similarity("The Rust Book", "The Rust Book - Rust") > 0.99);
+        assert!(title_similarity("Async Rust in depth", "Cooking with cast iron") < 0.5);
+    }
+
+    #[test]
+    fn test_near_duplicates() {
+        let mut data = BookmarksData::new();
+        let mut ids = Vec::new();
+        for (url, title) in [
+            (
+                "https://blog.example.com/posts/borrow-checker",
+                "Why the borrow checker is great | Example",
+            ),
+            (
+                "https://example.com/p/1234?ref=aggregator",
+                "Why the Borrow Checker is Great",
+            ),
+            (
+                "https://m.example.com/borrow-checker-amp",
+                "Why the borrow checker is great!",
+            ),
+            // Same title, another site
+            (
+                "https://other.org/borrow-checker",
+                "Why the borrow checker is great",
+            ),
+            // Same site, another article
+            ("https://example.com/p/5678", "Understanding async runtimes"),
+            // Same page by URL, left to exact duplicate detection
+            (
+                "https://www.example.com/p/1234",
+                "Why the Borrow Checker is Great",
+            ),
+            ("https://example.com/home", "Home"),
+            ("https://example.com/about", "Home"),
+        ] {
+            let bookmark = create_bookmark(url.to_string(), title.to_string(), vec![]);
+            ids.push(bookmark.id().to_string());
+            data.add_bookmark(bookmark).unwrap();
+        }
+        // Created in order, so the oldest comes first
+        for (i, resource) in data.data_mut().iter_mut().enumerate() {
+            if let Resource::Bookmark { attributes, .. } = resource {
+                attributes.created = chrono::DateTime::UNIX_EPOCH
+                    + chrono::Duration::minutes(i64::try_from(i).unwrap());
+            }
+        }
+
+        let groups = near_duplicates(&data, SIMILARITY_THRESHOLD);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].domain, "example.com");
+        assert_eq!(groups[0].title, "Why the borrow checker is great | Example");
+        assert_eq!(
+            groups[0].ids,
+            vec![
+                ids[0].clone(),
+                ids[1].clone(),
+                ids[2].clone(),
+                ids[5].clone()
+            ]
+        );
+        assert!(groups[0].similarity >= SIMILARITY_THRESHOLD);
+
+        assert!(near_duplicates(&data, 1.01).is_empty());
+    }
+}
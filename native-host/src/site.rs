@@ -0,0 +1,366 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::storage::{BookmarkAttributes, BookmarksData, Resource, Visibility};
+
+/// Directory in the repo the site is written to
+pub const SITE_DIR: &str = "site";
+
+/// Where `PublishPublic` writes the public bookmarks in the repo
+pub const PUBLIC_FILE: &str = "public/bookmarks.json";
+
+/// Marks pages as ours, so a `site/` directory the user made is never replaced
+const GENERATOR: &str = r#"<meta name="generator" content="WebTags">"#;
+
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; color: #222; }
+a { color: #0645ad; text-decoration: none; }
+a:hover { text-decoration: underline; }
+nav { margin-bottom: 1.5rem; color: #666; }
+ul { padding-left: 1.25rem; }
+li { margin: 0.25rem 0; }
+.meta { color: #888; font-size: 0.85em; }
+input[type=search] { width: 100%; font-size: 1rem; padding: 0.5rem; box-sizing: border-box; }
+";
+
+const SEARCH_SCRIPT: &str = r"
+const bookmarks = JSON.parse(document.getElementById('bookmarks').textContent);
+const input = document.getElementById('q');
+const results = document.getElementById('results');
+function render() {
+  const words = input.value.toLowerCase().split(/\s+/).filter(Boolean);
+  results.replaceChildren(...bookmarks
+    .filter(b => words.every(w => (b.title + ' ' + b.url + ' ' + b.tags.join(' ')).toLowerCase().includes(w)))
+    .slice(0, 200)
+    .map(b => {
+      const li = document.createElement('li');
+      const a = document.createElement('a');
+      a.href = b.url;
+      a.textContent = b.title;
+      const meta = document.createElement('span');
+      meta.className = 'meta';
+      meta.textContent = b.tags.length ? ' ' + b.tags.join(', ') : '';
+      li.append(a, meta);
+      return li;
+    }));
+}
+input.addEventListener('input', render);
+render();
+";
+
+/// A page of the site: path under [`SITE_DIR`] and contents
+pub type Page = (String, String);
+
+/// Render the collection as a static site: an index of the tag hierarchy, a
+/// page per tag, and a search page with the bookmarks embedded as JSON.
+///
+/// Private bookmarks and tags and notes are left out; the site is meant to
+/// be public.
+pub fn render(data: &BookmarksData) -> Vec<Page> {
+    let shown = |resource: &Resource| data.visibility(resource) != Visibility::Private;
+    let bookmarks: Vec<(&BookmarkAttributes, Vec<&str>)> = data
+        .get_bookmarks()
+        .into_iter()
+        .filter(|resource| shown(resource))
+        .filter_map(|resource| match resource {
+            Resource::Bookmark {
+                attributes,
+                relationships,
+                ..
+            } => {
+                let tags = relationships
+                    .iter()
+                    .flat_map(|r| r.tags.iter().flat_map(|tags| &tags.data))
+                    .map(|tag| tag.id.as_str())
+                    .collect();
+                Some((attributes, tags))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let names: HashMap<&str, &str> = data
+        .get_tags()
+        .into_iter()
+        .filter(|tag| shown(tag))
+        .filter_map(|tag| match tag {
+            Resource::Tag { id, attributes, .. } => Some((id.as_str(), attributes.name.as_str())),
+            _ => None,
+        })
+        .collect();
+    // Tags in file order under each parent (None for top-level tags)
+    let mut children: BTreeMap<Option<&str>, Vec<&str>> = BTreeMap::new();
+    for tag in data.get_tags().into_iter().filter(|tag| shown(tag)) {
+        let parent = data
+            .tag_parent_id(tag.id())
+            .filter(|parent| names.contains_key(parent));
+        children.entry(parent).or_default().push(tag.id());
+    }
+    let mut tagged: HashMap<&str, Vec<&BookmarkAttributes>> = HashMap::new();
+    for (attributes, tags) in &bookmarks {
+        for tag in tags {
+            tagged.entry(tag).or_default().push(attributes);
+        }
+    }
+
+    let mut pages = vec![
+        ("style.css".to_string(), STYLE.to_string()),
+        // Serve the files as they are rather than through Jekyll
+        (".nojekyll".to_string(), String::new()),
+    ];
+
+    let mut body = String::from("<h1>Bookmarks</h1>\n");
+    let _ = writeln!(
+        body,
+        "<p>{} bookmarks · <a href=\"search.html\">Search</a></p>",
+        bookmarks.len()
+    );
+    tag_tree(&mut body, None, &children, &names, &tagged, "tags/");
+    let untagged: Vec<&BookmarkAttributes> = bookmarks
+        .iter()
+        .filter(|(_, tags)| tags.is_empty())
+        .map(|(attributes, _)| *attributes)
+        .collect();
+    if !untagged.is_empty() {
+        body.push_str("<h2>Untagged</h2>\n");
+        bookmark_list(&mut body, &untagged);
+    }
+    pages.push(("index.html".to_string(), page("Bookmarks", "", &body)));
+
+    for (&id, &name) in &names {
+        let mut body = String::new();
+        let _ = writeln!(body, "<h1>{}</h1>", escape(name));
+        if children.contains_key(&Some(id)) {
+            tag_tree(&mut body, Some(id), &children, &names, &tagged, "");
+        }
+        bookmark_list(&mut body, tagged.get(id).map_or(&[], Vec::as_slice));
+        pages.push((
+            format!("tags/{}.html", file_name(id)),
+            page(name, "../", &body),
+        ));
+    }
+
+    let index: Vec<serde_json::Value> = bookmarks
+        .iter()
+        .map(|(attributes, tags)| {
+            serde_json::json!({
+                "url": attributes.url,
+                "title": attributes.title,
+                "tags": tags.iter().filter_map(|tag| names.get(tag)).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    let body = format!(
+        "<h1>Search</h1>\n\
+         <input type=\"search\" id=\"q\" placeholder=\"Search titles, URLs, and tags\" autofocus>\n\
+         <ul id=\"results\"></ul>\n\
+         <script type=\"application/json\" id=\"bookmarks\">{}</script>\n\
+         <script>{SEARCH_SCRIPT}</script>\n",
+        // `<` escaped so a title can't close the script element
+        serde_json::Value::Array(index)
+            .to_string()
+            .replace('<', "\\u003c")
+    );
+    pages.push(("search.html".to_string(), page("Search", "", &body)));
+
+    pages
+}
+
+/// Replace the site in `repo_path` with `pages`, returning how many were
+/// written. Refuses to replace a `site/` directory that wasn't generated here.
+pub fn write(repo_path: &Path, pages: &[Page]) -> Result<usize> {
+    let dir = repo_path.join(SITE_DIR);
+    if dir.exists() {
+        let index = fs::read_to_string(dir.join("index.html")).unwrap_or_default();
+        if !index.contains(GENERATOR) {
+            anyhow::bail!(
+                "{} exists and wasn't generated by WebTags; move it aside first",
+                dir.display()
+            );
+        }
+        fs::remove_dir_all(&dir).context("Failed to remove the old site")?;
+    }
+
+    for (path, contents) in pages {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(pages.len())
+}
+
+fn page(title: &str, root: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         {GENERATOR}\n<title>{}</title>\n<link rel=\"stylesheet\" href=\"{root}style.css\">\n\
+         </head>\n<body>\n<nav><a href=\"{root}index.html\">Bookmarks</a> · \
+         <a href=\"{root}search.html\">Search</a></nav>\n{body}</body>\n</html>\n",
+        escape(title)
+    )
+}
+
+/// Nested list of the tags under `parent`, with bookmark counts
+fn tag_tree(
+    out: &mut String,
+    parent: Option<&str>,
+    children: &BTreeMap<Option<&str>, Vec<&str>>,
+    names: &HashMap<&str, &str>,
+    tagged: &HashMap<&str, Vec<&BookmarkAttributes>>,
+    prefix: &str,
+) {
+    let Some(tags) = children.get(&parent) else {
+        return;
+    };
+    out.push_str("<ul>\n");
+    for &id in tags {
+        let _ = write!(
+            out,
+            "<li><a href=\"{prefix}{}.html\">{}</a> <span class=\"meta\">{}</span>",
+            file_name(id),
+            escape(names[id]),
+            tagged.get(id).map_or(0, Vec::len)
+        );
+        tag_tree(out, Some(id), children, names, tagged, prefix);
+        out.push_str("</li>\n");
+    }
+    out.push_str("</ul>\n");
+}
+
+fn bookmark_list(out: &mut String, bookmarks: &[&BookmarkAttributes]) {
+    out.push_str("<ul>\n");
+    for bookmark in bookmarks {
+        let _ = writeln!(
+            out,
+            "<li><a href=\"{}\">{}</a> <span class=\"meta\">{}</span></li>",
+            escape(&bookmark.url),
+            escape(&bookmark.title),
+            bookmark.created.format("%Y-%m-%d")
+        );
+    }
+    out.push_str("</ul>\n");
+}
+
+/// Tag IDs are UUIDs, but nothing stops a hand-edited file using anything
+fn file_name(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+
+    fn sample_data() -> BookmarksData {
+        let mut data = BookmarksData::new();
+        let dev = create_tag("Dev".to_string(), None, None);
+        let dev_id = dev.id().to_string();
+        let rust = create_tag("Rust & <friends>".to_string(), None, Some(dev_id.clone()));
+        let rust_id = rust.id().to_string();
+        data.add_tag(dev).unwrap();
+        data.add_tag(rust).unwrap();
+
+        data.add_bookmark(create_bookmark(
+            "https://doc.rust-lang.org/book".to_string(),
+            "The </script> Book".to_string(),
+            vec![rust_id],
+        ))
+        .unwrap();
+        data.add_bookmark(create_bookmark(
+            "https://example.com".to_string(),
+            "Loose".to_string(),
+            vec![],
+        ))
+        .unwrap();
+        let mut private = create_bookmark(
+            "https://secret.example.com".to_string(),
+            "Secret".to_string(),
+            vec![dev_id],
+        );
+        if let Resource::Bookmark { attributes, .. } = &mut private {
+            attributes.private = true;
+        }
+        data.add_bookmark(private).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_render_pages() {
+        let pages: HashMap<String, String> = render(&sample_data()).into_iter().collect();
+        // style, .nojekyll, index, two tags, search
+        assert_eq!(pages.len(), 6);
+
+        let index = &pages["index.html"];
+        assert!(index.contains("2 bookmarks"));
+        assert!(index.contains("Rust &amp; &lt;friends&gt;"));
+        assert!(index.contains("Untagged"));
+        assert!(!pages.values().any(|page| page.contains("Secret")));
+
+        // A private tag hides itself and everything carrying it
+        let mut data = sample_data();
+        let dev_id = data.get_tags()[0].id().to_string();
+        if let Some(Resource::Tag { attributes, .. }) = data
+            .included_mut()
+            .iter_mut()
+            .flatten()
+            .find(|tag| tag.id() == dev_id)
+        {
+            attributes.visibility = Some(Visibility::Private);
+        }
+        let pages: HashMap<String, String> = render(&data).into_iter().collect();
+        assert!(pages["index.html"].contains("2 bookmarks"));
+        assert!(!pages["index.html"].contains(">Dev<"));
+        assert!(!pages.contains_key(&format!("tags/{}.html", file_name(&dev_id))));
+
+        let search = &pages["search.html"];
+        assert!(search.contains(r"The \u003c/script> Book"));
+        assert!(!search.contains("The </script>"));
+    }
+
+    #[test]
+    fn test_write_replaces_only_generated_sites() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = render(&sample_data());
+
+        assert_eq!(write(dir.path(), &pages).unwrap(), pages.len());
+        // Pages from the previous run are removed
+        fs::write(dir.path().join(SITE_DIR).join("stale.html"), "").unwrap();
+        write(dir.path(), &pages).unwrap();
+        assert!(!dir.path().join(SITE_DIR).join("stale.html").exists());
+
+        let other = tempfile::tempdir().unwrap();
+        fs::create_dir(other.path().join(SITE_DIR)).unwrap();
+        fs::write(other.path().join(SITE_DIR).join("index.html"), "mine").unwrap();
+        assert!(write(other.path(), &pages).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::git::GitRepo;
+use crate::storage;
+
+/// How often scheduled backups are taken, kept in the repository's local
+/// git config
+const SCHEDULE_KEY: &str = "webtags.backupSchedule";
+/// How many scheduled backups to keep
+const KEEP_KEY: &str = "webtags.backupKeep";
+
+pub const DEFAULT_KEEP: usize = 7;
+
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+const SNAPSHOT_PREFIX: &str = "bookmarks-";
+const SNAPSHOT_EXTENSION: &str = "json";
+/// Timestamp in snapshot IDs, e.g. `20260102T030405Z`
+const ID_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+    #[default]
+    Off,
+    Daily,
+    Weekly,
+}
+
+impl Schedule {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Schedule::Off => "off",
+            Schedule::Daily => "daily",
+            Schedule::Weekly => "weekly",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        [Schedule::Off, Schedule::Daily, Schedule::Weekly]
+            .into_iter()
+            .find(|schedule| schedule.name() == name)
+    }
+
+    /// Time between backups, or `None` when off
+    pub fn interval(self) -> Option<chrono::Duration> {
+        match self {
+            Schedule::Off => None,
+            Schedule::Daily => Some(chrono::Duration::days(1)),
+            Schedule::Weekly => Some(chrono::Duration::weeks(1)),
+        }
+    }
+}
+
+/// Scheduled backup settings for one repository
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub schedule: Schedule,
+    pub keep: usize,
+}
+
+impl Settings {
+    pub fn load(repo: &GitRepo) -> Self {
+        Self {
+            schedule: repo
+                .local_config(SCHEDULE_KEY)
+                .and_then(|name| Schedule::from_name(&name))
+                .unwrap_or_default(),
+            keep: repo
+                .local_config(KEEP_KEY)
+                .and_then(|keep| keep.parse().ok())
+                .unwrap_or(DEFAULT_KEEP),
+        }
+    }
+
+    /// Change the settings given, leaving the others as they are
+    pub fn update(repo: &GitRepo, schedule: Option<Schedule>, keep: Option<usize>) -> Result<Self> {
+        if keep == Some(0) {
+            anyhow::bail!("Keep at least one backup (or set the schedule to \"off\")");
+        }
+        if let Some(schedule) = schedule {
+            let name = (schedule != Schedule::Off).then_some(schedule.name());
+            repo.set_local_config(SCHEDULE_KEY, name)?;
+        }
+        if let Some(keep) = keep {
+            let keep = (keep != DEFAULT_KEEP).then(|| keep.to_string());
+            repo.set_local_config(KEEP_KEY, keep.as_deref())?;
+        }
+        Ok(Self::load(repo))
+    }
+}
+
+/// A copy of bookmarks.json taken by the scheduler, exactly as it was on
+/// disk (so still encrypted if the repository is)
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Snapshot {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// Directory of scheduled backups for one repository
+pub struct SnapshotDir {
+    root: PathBuf,
+}
+
+impl SnapshotDir {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Default location for a repository: `~/.local/share/webtags/backups/<repo name>`
+    pub fn for_repo<P: AsRef<Path>>(repo_path: P) -> Result<Self> {
+        let home = dirs::home_dir().context("No home directory found")?;
+        let repo_name = repo_path
+            .as_ref()
+            .file_name()
+            .context("Repository path has no name")?;
+
+        Ok(Self::new(
+            home.join(".local")
+                .join("share")
+                .join("webtags")
+                .join("backups")
+                .join(repo_name),
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Every backup, newest first
+    pub fn list(&self) -> Result<Vec<Snapshot>> {
+        if !self.root.exists() {
+            return Ok(Vec::new());
+        }
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.root).context("Failed to read backups directory")? {
+            let entry = entry.context("Failed to read backups directory")?;
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SNAPSHOT_EXTENSION) {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let Some(created) = created_at(id) else {
+                continue;
+            };
+            snapshots.push(Snapshot {
+                id: id.to_string(),
+                created,
+                size: entry.metadata().map_or(0, |m| m.len()),
+            });
+        }
+        snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.created));
+        Ok(snapshots)
+    }
+
+    /// File holding the backup `id`
+    pub fn path_of(&self, id: &str) -> Result<PathBuf> {
+        let path = self.root.join(format!("{id}.{SNAPSHOT_EXTENSION}"));
+        if created_at(id).is_none() || !path.is_file() {
+            anyhow::bail!("No backup with ID {id}");
+        }
+        Ok(path)
+    }
+
+    /// Copy the repository's bookmarks file into a new backup
+    pub fn take(&self, repo_path: &Path, now: DateTime<Utc>) -> Result<Snapshot> {
+        fs::create_dir_all(&self.root).context("Failed to create backups directory")?;
+        let id = format!("{SNAPSHOT_PREFIX}{}", now.format(ID_TIME_FORMAT));
+        let path = self.root.join(format!("{id}.{SNAPSHOT_EXTENSION}"));
+        let content =
+            fs::read(repo_path.join(BOOKMARKS_FILE)).context("Failed to read bookmarks file")?;
+        storage::write_atomic(&path, &content)?;
+        let size = content.len() as u64;
+        Ok(Snapshot {
+            id,
+            created: created_at_second(now),
+            size,
+        })
+    }
+
+    /// Delete all but the newest `keep` backups, returning how many went
+    pub fn prune(&self, keep: usize) -> Result<usize> {
+        let stale = self.list()?.into_iter().skip(keep);
+        let mut removed = 0;
+        for snapshot in stale {
+            fs::remove_file(self.path_of(&snapshot.id)?)
+                .with_context(|| format!("Failed to remove backup {}", snapshot.id))?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Take a backup if the schedule says one is due, then apply retention
+    pub fn run_if_due(
+        &self,
+        repo_path: &Path,
+        settings: Settings,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Snapshot>> {
+        let Some(interval) = settings.schedule.interval() else {
+            return Ok(None);
+        };
+        if !repo_path.join(BOOKMARKS_FILE).exists() {
+            return Ok(None);
+        }
+        let due = self
+            .list()?
+            .first()
+            .is_none_or(|latest| now - latest.created >= interval);
+        if !due {
+            return Ok(None);
+        }
+        let snapshot = self.take(repo_path, now)?;
+        self.prune(settings.keep)?;
+        Ok(Some(snapshot))
+    }
+}
+
+fn created_at(id: &str) -> Option<DateTime<Utc>> {
+    let time = id.strip_prefix(SNAPSHOT_PREFIX)?;
+    NaiveDateTime::parse_from_str(time, ID_TIME_FORMAT)
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+/// `time` as it reads back from an ID, without sub-second precision
+fn created_at_second(time: DateTime<Utc>) -> DateTime<Utc> {
+    created_at(&format!("{SNAPSHOT_PREFIX}{}", time.format(ID_TIME_FORMAT))).unwrap_or(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn repo_with_bookmarks() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(BOOKMARKS_FILE), "{}").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        assert_eq!(
+            Settings::load(&repo),
+            Settings {
+                schedule: Schedule::Off,
+                keep: DEFAULT_KEEP,
+            }
+        );
+        let settings = Settings::update(&repo, Some(Schedule::Weekly), Some(3)).unwrap();
+        assert_eq!(settings.schedule, Schedule::Weekly);
+        assert_eq!(settings.keep, 3);
+        // Omitted settings stay as they are
+        let settings = Settings::update(&repo, None, None).unwrap();
+        assert_eq!(settings.schedule, Schedule::Weekly);
+        assert!(Settings::update(&repo, None, Some(0)).is_err());
+    }
+
+    #[test]
+    fn test_scheduled_backups_and_retention() {
+        let repo = repo_with_bookmarks();
+        let backups = TempDir::new().unwrap();
+        let dir = SnapshotDir::new(backups.path());
+        let settings = Settings {
+            schedule: Schedule::Daily,
+            keep: 2,
+        };
+        let start = Utc::now();
+
+        let first = dir.run_if_due(repo.path(), settings, start).unwrap();
+        assert!(first.is_some());
+        // Not due again within the day
+        let hours = chrono::Duration::hours(5);
+        assert!(dir
+            .run_if_due(repo.path(), settings, start + hours)
+            .unwrap()
+            .is_none());
+
+        for day in 1..=3 {
+            let now = start + chrono::Duration::days(day);
+            assert!(dir
+                .run_if_due(repo.path(), settings, now)
+                .unwrap()
+                .is_some());
+        }
+        let listed = dir.list().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].created > listed[1].created);
+        assert_eq!(listed[0].size, 2);
+        assert!(dir.path_of(&listed[0].id).is_ok());
+        assert!(dir.path_of("../bookmarks").is_err());
+
+        let off = Settings {
+            schedule: Schedule::Off,
+            ..settings
+        };
+        let later = start + chrono::Duration::days(30);
+        assert!(dir.run_if_due(repo.path(), off, later).unwrap().is_none());
+    }
+}
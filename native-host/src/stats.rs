@@ -0,0 +1,176 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::rules::domain;
+use crate::storage::{BookmarksData, Resource};
+
+/// Domains listed in [`Stats::per_domain`]; the rest only count towards
+/// [`Stats::domains`]
+pub const MAX_DOMAINS: usize = 100;
+
+/// Aggregate numbers about a collection, small enough to send whole
+#[derive(Debug, Serialize, Clone, PartialEq, Default)]
+pub struct Stats {
+    pub bookmarks: usize,
+    pub tags: usize,
+    /// Bookmarks with no tags
+    pub untagged: usize,
+    /// Bookmarks with notes
+    pub with_notes: usize,
+    /// Share of bookmarks with notes, from 0 to 1
+    pub note_coverage: f64,
+    pub unread: usize,
+    pub private: usize,
+    /// Every tag, most used first
+    pub per_tag: Vec<TagCount>,
+    /// Distinct domains across all bookmarks
+    pub domains: usize,
+    /// The most common domains, most bookmarks first
+    pub per_domain: Vec<DomainCount>,
+    /// Bookmarks added per month, keyed `YYYY-MM`
+    pub per_month: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TagCount {
+    pub id: String,
+    pub name: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DomainCount {
+    pub domain: String,
+    pub count: usize,
+}
+
+/// Count up `data`
+pub fn stats(data: &BookmarksData) -> Stats {
+    let mut stats = Stats::default();
+    let mut per_tag: HashMap<&str, usize> = HashMap::new();
+    let mut per_domain: HashMap<String, usize> = HashMap::new();
+
+    for resource in data.get_bookmarks() {
+        let Resource::Bookmark {
+            attributes,
+            relationships,
+            ..
+        } = resource
+        else {
+            continue;
+        };
+        stats.bookmarks += 1;
+        let tag_ids = relationships
+            .as_ref()
+            .and_then(|r| r.tags.as_ref())
+            .map(|tags| tags.data.as_slice())
+            .unwrap_or_default();
+        if tag_ids.is_empty() {
+            stats.untagged += 1;
+        }
+        for tag in tag_ids {
+            *per_tag.entry(tag.id.as_str()).or_default() += 1;
+        }
+        if attributes.notes.as_ref().is_some_and(|n| !n.is_empty()) {
+            stats.with_notes += 1;
+        }
+        stats.unread += usize::from(attributes.unread);
+        stats.private += usize::from(attributes.private);
+        if let Some(domain) = domain(&attributes.url) {
+            *per_domain.entry(domain).or_default() += 1;
+        }
+        let month = attributes.created.format("%Y-%m").to_string();
+        *stats.per_month.entry(month).or_default() += 1;
+    }
+
+    if stats.bookmarks > 0 {
+        #[allow(clippy::cast_precision_loss)] // counts stay far below 2^52
+        let coverage = stats.with_notes as f64 / stats.bookmarks as f64;
+        stats.note_coverage = coverage;
+    }
+
+    for resource in data.get_tags() {
+        if let Resource::Tag { id, attributes, .. } = resource {
+            stats.tags += 1;
+            stats.per_tag.push(TagCount {
+                id: id.clone(),
+                name: attributes.name.clone(),
+                count: per_tag.get(id.as_str()).copied().unwrap_or(0),
+            });
+        }
+    }
+    stats
+        .per_tag
+        .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+    stats.domains = per_domain.len();
+    stats.per_domain = per_domain
+        .into_iter()
+        .map(|(domain, count)| DomainCount { domain, count })
+        .collect();
+    stats
+        .per_domain
+        .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.domain.cmp(&b.domain)));
+    stats.per_domain.truncate(MAX_DOMAINS);
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+
+    #[test]
+    fn test_stats() {
+        let mut data = BookmarksData::new();
+        let rust = create_tag("rust".to_string(), None, None);
+        let unused = create_tag("unused".to_string(), None, None);
+        let rust_id = rust.id().to_string();
+        data.add_tag(rust).unwrap();
+        data.add_tag(unused).unwrap();
+
+        for (url, tagged) in [
+            ("https://doc.rust-lang.org/book", true),
+            ("https://doc.rust-lang.org/std", true),
+            ("https://example.com", false),
+        ] {
+            let tags = if tagged {
+                vec![rust_id.clone()]
+            } else {
+                vec![]
+            };
+            data.add_bookmark(create_bookmark(url.to_string(), url.to_string(), tags))
+                .unwrap();
+        }
+        let id = data.get_bookmarks()[0].id().to_string();
+        data.update_bookmark(&id, None, Some("Read chapter 4".to_string()))
+            .unwrap();
+
+        let stats = stats(&data);
+        assert_eq!(stats.bookmarks, 3);
+        assert_eq!(stats.tags, 2);
+        assert_eq!(stats.untagged, 1);
+        assert_eq!(stats.with_notes, 1);
+        assert!((stats.note_coverage - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            stats
+                .per_tag
+                .iter()
+                .map(|t| (t.name.as_str(), t.count))
+                .collect::<Vec<_>>(),
+            [("rust", 2), ("unused", 0)]
+        );
+        assert_eq!(stats.domains, 2);
+        assert_eq!(stats.per_domain[0].domain, "doc.rust-lang.org");
+        assert_eq!(stats.per_domain[0].count, 2);
+        assert_eq!(stats.per_month.values().sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn test_stats_of_empty_collection() {
+        let stats = stats(&BookmarksData::new());
+        assert_eq!(stats.bookmarks, 0);
+        assert!(stats.note_coverage.abs() < f64::EPSILON);
+        assert!(stats.per_month.is_empty());
+    }
+}
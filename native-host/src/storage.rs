@@ -1,29 +1,146 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::Write;
 use std::path::Path;
+use std::sync::OnceLock;
 use url::Url;
 use uuid::Uuid;
 
+use crate::color;
+use crate::encryption::{
+    is_encrypted, repo_relative_path, Cipher, EncryptedData, EncryptionManager, EncryptionMode,
+};
+use crate::migrations::{self, CURRENT_SCHEMA_VERSION};
+use crate::query::Query;
+use crate::rules;
+use crate::unicode;
+use crate::validation::ValidationConfig;
+
+/// Top-level `meta.encryption` value marking a field-level encrypted file
+const FIELD_ENCRYPTION_MARKER: &str = "fields";
+
+/// Resource sections of bookmarks.json that may contain bookmarks
+const RESOURCE_SECTIONS: [&str; 2] = ["data", "included"];
+
+/// Most custom `meta` fields one bookmark may carry
+pub const MAX_EXTRA_FIELDS: usize = 32;
+
+/// Longest custom field name
+pub const MAX_EXTRA_KEY_LENGTH: usize = 64;
+
+/// Largest a bookmark's custom fields may be, serialized
+pub const MAX_EXTRA_SIZE: usize = 4096;
+
+/// Longest a bookmark's notes may be, in bytes
+pub const MAX_NOTES_LENGTH: usize = 10_000;
+
+/// Bookmark `meta` keys the host fills in on `Read`; they are never stored
+const HOST_META_KEYS: [&str; 1] = [crate::activity::ACTIVITY_META_KEY];
+
+/// Validate a bookmark's custom fields
+fn validate_extra(extra: &serde_json::Map<String, serde_json::Value>) -> Result<()> {
+    if extra.len() > MAX_EXTRA_FIELDS {
+        anyhow::bail!("Too many custom fields (max {MAX_EXTRA_FIELDS})");
+    }
+    for key in extra.keys() {
+        if key.is_empty() || key.len() > MAX_EXTRA_KEY_LENGTH {
+            anyhow::bail!("Custom field names must be 1-{MAX_EXTRA_KEY_LENGTH} characters");
+        }
+        if !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            anyhow::bail!("Invalid custom field name '{key}' (use letters, digits, _ - .)");
+        }
+        if HOST_META_KEYS.contains(&key.as_str()) {
+            anyhow::bail!("Custom field name '{key}' is reserved");
+        }
+    }
+    let size = serde_json::to_vec(extra).map_or(usize::MAX, |json| json.len());
+    if size > MAX_EXTRA_SIZE {
+        anyhow::bail!("Custom fields too large ({size} bytes, max {MAX_EXTRA_SIZE})");
+    }
+    Ok(())
+}
+
+/// Notes are Markdown, so raw HTML isn't needed: elements that run code or
+/// embed content are dropped with their contents, other tags are dropped
+/// leaving their text, and links to `javascript:` (and similar) URLs go
+/// nowhere.
+pub fn sanitize_notes(notes: &str) -> Cow<'_, str> {
+    static ELEMENTS: OnceLock<Regex> = OnceLock::new();
+    static TAGS: OnceLock<Regex> = OnceLock::new();
+    static LINKS: OnceLock<Regex> = OnceLock::new();
+    let elements = ELEMENTS.get_or_init(|| {
+        Regex::new(r"(?is)<(script|style|iframe|object|embed)\b.*?(?:</\s*(?:script|style|iframe|object|embed)\s*>|\z)|<!--.*?(?:-->|\z)")
+            .expect("valid regex")
+    });
+    let tags = TAGS.get_or_init(|| Regex::new(r"</?[A-Za-z][^>]*>").expect("valid regex"));
+    let links = LINKS.get_or_init(|| {
+        Regex::new(r"(?i)\]\(\s*(?:javascript|vbscript|data):[^)]*\)").expect("valid regex")
+    });
+
+    if !notes.contains('<') && !notes.contains("](") {
+        return Cow::Borrowed(notes);
+    }
+    let stripped = elements.replace_all(notes, "");
+    let stripped = tags.replace_all(&stripped, "");
+    let sanitized = links.replace_all(&stripped, "](#)");
+    if sanitized == notes {
+        Cow::Borrowed(notes)
+    } else {
+        Cow::Owned(sanitized.into_owned())
+    }
+}
+
 /// Validate bookmark URL for security
-fn validate_bookmark_url(url_str: &str) -> Result<()> {
+pub(crate) fn validate_bookmark_url(url_str: &str, limits: &ValidationConfig) -> Result<()> {
     // Check length
     if url_str.is_empty() {
         anyhow::bail!("URL cannot be empty");
     }
-    if url_str.len() > 2048 {
-        anyhow::bail!("URL too long (max 2048 characters)");
+    if url_str.len() > limits.max_url_length {
+        anyhow::bail!("URL too long (max {} characters)", limits.max_url_length);
     }
 
     // Parse URL
     let parsed = Url::parse(url_str).context("Invalid URL format")?;
 
     // Only allow safe schemes
-    match parsed.scheme() {
-        "http" | "https" => Ok(()),
-        scheme => anyhow::bail!("Unsafe URL scheme '{scheme}'. Only http and https are allowed."),
+    let scheme = parsed.scheme();
+    if !limits.allows_scheme(scheme) {
+        anyhow::bail!(
+            "Unsafe URL scheme '{scheme}'. Only {} are allowed.",
+            limits.scheme_list()
+        );
+    }
+    Ok(())
+}
+
+/// Validate a tag name
+pub(crate) fn validate_tag_name(name: &str, limits: &ValidationConfig) -> Result<()> {
+    if name.is_empty() || name.len() > limits.max_tag_length {
+        anyhow::bail!(
+            "Tag name must be between 1-{} characters",
+            limits.max_tag_length
+        );
+    }
+    // Validate tag name doesn't contain HTML
+    if name.contains('<') || name.contains('>') {
+        anyhow::bail!("Tag name cannot contain HTML characters");
+    }
+    Ok(())
+}
+
+fn validate_tag_color(id: &str, attributes: &TagAttributes) -> Result<()> {
+    match &attributes.color {
+        Some(color) => color::validate_color(color).with_context(|| format!("Tag {id}")),
+        None => Ok(()),
     }
 }
 
@@ -31,9 +148,88 @@ fn validate_bookmark_url(url_str: &str) -> Result<()> {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct BookmarksData {
     pub jsonapi: JsonApiVersion,
-    pub data: Vec<Resource>,
+    #[serde(default)]
+    pub meta: Meta,
+    data: Vec<Resource>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub included: Option<Vec<Resource>>,
+    included: Option<Vec<Resource>>,
+    #[serde(skip)]
+    lookup: LookupCache,
+}
+
+/// Where a resource sits in [`BookmarksData`]
+#[derive(Debug, Clone, Copy)]
+enum Position {
+    Data(usize),
+    Included(usize),
+}
+
+/// ID maps over a [`BookmarksData`], so lookups don't scan every resource
+#[derive(Debug, Default)]
+struct Lookup {
+    by_id: HashMap<String, Position>,
+    /// Tag ID → positions in `data` of the bookmarks carrying it
+    bookmarks_by_tag: HashMap<String, Vec<usize>>,
+    /// Tag ID → positions of the tags from its top-level ancestor down to
+    /// it, built on first use. Positions rather than names, so renaming a
+    /// tag doesn't make it stale.
+    tag_paths: OnceLock<HashMap<String, Vec<Position>>>,
+    /// Tag ID → bookmark counts, built on first use
+    tag_counts: OnceLock<HashMap<String, TagCounts>>,
+}
+
+impl Lookup {
+    fn build(data: &[Resource], included: Option<&[Resource]>) -> Self {
+        let mut lookup = Self::default();
+        let data_ids = data.iter().enumerate().map(|(i, r)| (r, Position::Data(i)));
+        let included_ids = included
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (r, Position::Included(i)));
+        for (resource, position) in data_ids.chain(included_ids) {
+            // Duplicates are rejected by `validate`; until then the first wins
+            lookup
+                .by_id
+                .entry(resource.id().to_string())
+                .or_insert(position);
+        }
+
+        for (i, resource) in data.iter().enumerate() {
+            let Resource::Bookmark {
+                relationships: Some(rels),
+                ..
+            } = resource
+            else {
+                continue;
+            };
+            for tag in rels.tags.iter().flat_map(|tags| &tags.data) {
+                lookup
+                    .bookmarks_by_tag
+                    .entry(tag.id.clone())
+                    .or_default()
+                    .push(i);
+            }
+        }
+        lookup
+    }
+}
+
+/// Lazily built [`Lookup`]. Every mutable path into `BookmarksData` resets it,
+/// and it takes no part in cloning or comparison.
+#[derive(Debug, Default)]
+struct LookupCache(OnceLock<Lookup>);
+
+impl Clone for LookupCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PartialEq for LookupCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -41,6 +237,31 @@ pub struct JsonApiVersion {
     pub version: String,
 }
 
+/// Fields written by a newer version or another tool. They are kept as
+/// they are so writing the file back doesn't lose them; a strict write
+/// rejects them instead (see [`BookmarksData::unknown_fields`]).
+pub type UnknownFields = serde_json::Map<String, serde_json::Value>;
+
+/// Top-level `meta`: about the file rather than the bookmarks in it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Meta {
+    /// On-disk format version; older files are upgraded by
+    /// [`migrations::migrate`] when read
+    pub schema_version: u32,
+    /// Fields this version doesn't know about
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
+}
+
+impl Default for Meta {
+    fn default() -> Self {
+        Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            unknown: UnknownFields::new(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Resource {
@@ -49,6 +270,15 @@ pub enum Resource {
         attributes: BookmarkAttributes,
         #[serde(skip_serializing_if = "Option::is_none")]
         relationships: Option<BookmarkRelationships>,
+        /// Fields integrations attach (ratings, read status, source, ...),
+        /// stored as the resource's JSON:API `meta` and kept as-is. Never
+        /// encrypted in field-level mode, even on private bookmarks.
+        #[serde(
+            rename = "meta",
+            default,
+            skip_serializing_if = "serde_json::Map::is_empty"
+        )]
+        extra: serde_json::Map<String, serde_json::Value>,
     },
     Tag {
         id: String,
@@ -56,6 +286,28 @@ pub enum Resource {
         #[serde(skip_serializing_if = "Option::is_none")]
         relationships: Option<TagRelationships>,
     },
+    /// A named query the extension can run in one click
+    SavedSearch {
+        id: String,
+        attributes: SavedSearchAttributes,
+    },
+    /// Tags applied automatically to bookmarks that match a pattern
+    Rule {
+        id: String,
+        attributes: RuleAttributes,
+        relationships: RuleRelationships,
+    },
+}
+
+impl Resource {
+    pub fn id(&self) -> &str {
+        match self {
+            Resource::Bookmark { id, .. }
+            | Resource::Tag { id, .. }
+            | Resource::SavedSearch { id, .. }
+            | Resource::Rule { id, .. } => id,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -67,6 +319,323 @@ pub struct BookmarkAttributes {
     pub modified: Option<DateTime<Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notes: Option<String>,
+    /// User-marked private; sealed entirely in field-level encryption mode
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    /// Saved to read later and not read yet
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unread: bool,
+    /// Shown among the favorites on the new-tab page
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Position among pinned bookmarks, set by `ReorderPinned`. Pinned
+    /// bookmarks without one come after those with one; it means nothing
+    /// on a bookmark that isn't pinned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_order: Option<u32>,
+    /// Who saved it, in a repository shared by several people
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Who may see it; unset follows its tags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    /// Nearest Wayback Machine snapshot, looked up once the link went dead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive_url: Option<String>,
+    /// What the page is, found by `Enrich`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enrichment: Option<Enrichment>,
+    /// Tag names picked from the page's text, waiting to be accepted or
+    /// dismissed with `ReviewSuggestedTags`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggested_tags: Vec<String>,
+    /// When it stops being useful, like an event page; see `crate::expiry`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Fields this version doesn't know about
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
+}
+
+/// Who may see a bookmark or tag
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Never published, nor shown on the static site
+    Private,
+    /// Everyone with the repository, but not published
+    #[default]
+    Shared,
+    /// Published by `PublishPublic`
+    Public,
+}
+
+/// What kind of thing a bookmark links to
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentKind {
+    /// A page with enough text to be read
+    Article,
+    Video,
+    Audio,
+    Image,
+    Pdf,
+    /// Any other web page
+    Page,
+    Other,
+}
+
+impl ContentKind {
+    pub const ALL: [Self; 7] = [
+        Self::Article,
+        Self::Video,
+        Self::Audio,
+        Self::Image,
+        Self::Pdf,
+        Self::Page,
+        Self::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Article => "article",
+            Self::Video => "video",
+            Self::Audio => "audio",
+            Self::Image => "image",
+            Self::Pdf => "pdf",
+            Self::Page => "page",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Facts about a bookmark's page, from a lightweight fetch
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Enrichment {
+    pub content_type: ContentKind,
+    /// MIME type the server sent, without parameters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Approximate, for pages with text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub word_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reading_minutes: Option<u32>,
+    /// Primary language subtag, like `en`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    pub fetched: DateTime<Utc>,
+}
+
+/// Bookmark attributes as stored on disk in field-level encryption mode.
+///
+/// URLs and titles stay plaintext so git diffs remain meaningful. `notes` is
+/// encrypted on its own, and a private bookmark has its URL, title, and notes
+/// sealed together in `sealed`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SealedBookmarkAttributes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub created: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<EncryptedData>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unread: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_order: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enrichment: Option<Enrichment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggested_tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<EncryptedData>,
+    /// Kept in plaintext, even on private bookmarks: what they hold isn't known
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
+}
+
+/// Plaintext of a private bookmark's `sealed` envelope
+#[derive(Debug, Serialize, Deserialize)]
+struct PrivateFields {
+    url: String,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    /// An archived copy gives away the URL, so it's sealed alongside it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archive_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    enrichment: Option<Enrichment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    suggested_tags: Vec<String>,
+}
+
+impl SealedBookmarkAttributes {
+    /// Encrypt the sensitive fields of `attributes`, binding each envelope to
+    /// `location` (`<file>#<bookmark id>`) so it can't be moved to another
+    /// bookmark. Envelopes from `previous` are reused when their plaintext is
+    /// unchanged, so rewriting the file doesn't churn every ciphertext in the
+    /// git history.
+    pub fn seal(
+        attributes: &BookmarkAttributes,
+        manager: &EncryptionManager,
+        location: &str,
+        previous: Option<&SealedBookmarkAttributes>,
+    ) -> Result<Self> {
+        let mut sealed = Self {
+            url: None,
+            title: None,
+            created: attributes.created,
+            modified: attributes.modified,
+            notes: None,
+            private: attributes.private,
+            unread: attributes.unread,
+            pinned: attributes.pinned,
+            pin_order: attributes.pin_order,
+            author: attributes.author.clone(),
+            visibility: attributes.visibility,
+            archive_url: None,
+            enrichment: None,
+            suggested_tags: Vec::new(),
+            expires_at: attributes.expires_at,
+            sealed: None,
+            unknown: attributes.unknown.clone(),
+        };
+
+        if attributes.private {
+            let fields = PrivateFields {
+                url: attributes.url.clone(),
+                title: attributes.title.clone(),
+                notes: attributes.notes.clone(),
+                archive_url: attributes.archive_url.clone(),
+                enrichment: attributes.enrichment.clone(),
+                suggested_tags: attributes.suggested_tags.clone(),
+            };
+            let plaintext =
+                serde_json::to_vec(&fields).context("Failed to serialize private fields")?;
+            sealed.sealed = Some(encrypt_reusing(
+                manager,
+                &plaintext,
+                &format!("{location}/sealed"),
+                previous.and_then(|p| p.sealed.as_ref()),
+            )?);
+        } else {
+            sealed.url = Some(attributes.url.clone());
+            sealed.title = Some(attributes.title.clone());
+            sealed.archive_url.clone_from(&attributes.archive_url);
+            sealed.enrichment.clone_from(&attributes.enrichment);
+            sealed.suggested_tags.clone_from(&attributes.suggested_tags);
+            if let Some(notes) = &attributes.notes {
+                sealed.notes = Some(encrypt_reusing(
+                    manager,
+                    notes.as_bytes(),
+                    &format!("{location}/notes"),
+                    previous.and_then(|p| p.notes.as_ref()),
+                )?);
+            }
+        }
+
+        Ok(sealed)
+    }
+
+    /// Decrypt back into plain bookmark attributes, checking each envelope
+    /// was sealed for `location`
+    pub fn open(&self, manager: &EncryptionManager, location: &str) -> Result<BookmarkAttributes> {
+        let (url, title, notes, archive_url, enrichment, suggested_tags) = if self.private {
+            let envelope = self
+                .sealed
+                .as_ref()
+                .context("Private bookmark is missing its sealed fields")?;
+            let fields: PrivateFields = serde_json::from_slice(
+                &manager.decrypt_bound(envelope, &format!("{location}/sealed"))?,
+            )
+            .context("Failed to parse private fields")?;
+            (
+                fields.url,
+                fields.title,
+                fields.notes,
+                fields.archive_url,
+                fields.enrichment,
+                fields.suggested_tags,
+            )
+        } else {
+            let notes = match &self.notes {
+                Some(envelope) => Some(
+                    String::from_utf8(
+                        manager.decrypt_bound(envelope, &format!("{location}/notes"))?,
+                    )
+                    .context("Decrypted notes are not valid UTF-8")?,
+                ),
+                None => None,
+            };
+            (
+                self.url.clone().context("Bookmark is missing its URL")?,
+                self.title
+                    .clone()
+                    .context("Bookmark is missing its title")?,
+                notes,
+                self.archive_url.clone(),
+                self.enrichment.clone(),
+                self.suggested_tags.clone(),
+            )
+        };
+
+        Ok(BookmarkAttributes {
+            url,
+            title,
+            created: self.created,
+            modified: self.modified,
+            notes,
+            private: self.private,
+            unread: self.unread,
+            pinned: self.pinned,
+            pin_order: self.pin_order,
+            author: self.author.clone(),
+            visibility: self.visibility,
+            archive_url,
+            enrichment,
+            suggested_tags,
+            expires_at: self.expires_at,
+            unknown: self.unknown.clone(),
+        })
+    }
+}
+
+/// Encrypt `plaintext`, returning `previous` unchanged if it already holds it
+fn encrypt_reusing(
+    manager: &EncryptionManager,
+    plaintext: &[u8],
+    location: &str,
+    previous: Option<&EncryptedData>,
+) -> Result<EncryptedData> {
+    // Unbound envelopes or ones written with a different cipher are
+    // re-encrypted, migrating them
+    let reusable = previous.filter(|p| p.is_bound() && p.algorithm() == manager.cipher().name());
+    if let Some(previous) = reusable {
+        if manager
+            .decrypt_bound(previous, location)
+            .is_ok_and(|p| p == plaintext)
+        {
+            return Ok(previous.clone());
+        }
+    }
+    manager.encrypt_bound(plaintext, location)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -94,6 +663,74 @@ pub struct TagAttributes {
     pub color: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// Who may see it and the bookmarks carrying it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    /// Fields this version doesn't know about
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SavedSearchAttributes {
+    pub name: String,
+    /// Query in the syntax [`crate::query::Query::parse`] accepts
+    pub query: String,
+    #[serde(default)]
+    pub sort: SortOrder,
+    pub created: DateTime<Utc>,
+    /// Fields this version doesn't know about
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
+}
+
+/// Order of saved search results
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Newest,
+    Oldest,
+    Title,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RuleAttributes {
+    pub name: String,
+    pub field: RuleField,
+    #[serde(rename = "match", default)]
+    pub match_kind: MatchKind,
+    pub pattern: String,
+    /// Fields this version doesn't know about
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
+}
+
+/// What part of a bookmark a rule looks at
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleField {
+    Url,
+    Title,
+    /// Host of the URL
+    Domain,
+}
+
+/// How a rule's pattern is compared; all are case-insensitive
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    #[default]
+    Contains,
+    /// The whole field; for domains, subdomains match too
+    Equals,
+    Regex,
+}
+
+/// Tags a rule applies
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RuleRelationships {
+    pub tags: RelationshipData,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -114,19 +751,155 @@ impl BookmarksData {
             jsonapi: JsonApiVersion {
                 version: "1.1".to_string(),
             },
+            meta: Meta::default(),
             data: Vec::new(),
             included: None,
+            lookup: LookupCache::default(),
+        }
+    }
+
+    /// Resources in the `data` section
+    pub fn data(&self) -> &[Resource] {
+        &self.data
+    }
+
+    /// Resources in the `included` section, if there is one
+    pub fn included(&self) -> Option<&[Resource]> {
+        self.included.as_deref()
+    }
+
+    /// Mutable access to the `data` section
+    pub fn data_mut(&mut self) -> &mut Vec<Resource> {
+        self.invalidate();
+        &mut self.data
+    }
+
+    /// Mutable access to the `included` section
+    pub fn included_mut(&mut self) -> &mut Option<Vec<Resource>> {
+        self.invalidate();
+        &mut self.included
+    }
+
+    /// Mutable access to both sections at once
+    pub fn resources_mut(&mut self) -> (&mut Vec<Resource>, &mut Option<Vec<Resource>>) {
+        self.invalidate();
+        (&mut self.data, &mut self.included)
+    }
+
+    fn invalidate(&mut self) {
+        self.lookup = LookupCache::default();
+    }
+
+    fn lookup(&self) -> &Lookup {
+        self.lookup
+            .0
+            .get_or_init(|| Lookup::build(&self.data, self.included.as_deref()))
+    }
+
+    /// Find a bookmark or tag by ID
+    pub fn get(&self, id: &str) -> Option<&Resource> {
+        self.at(*self.lookup().by_id.get(id)?)
+    }
+
+    fn at(&self, position: Position) -> Option<&Resource> {
+        match position {
+            Position::Data(i) => self.data.get(i),
+            Position::Included(i) => self.included.as_ref()?.get(i),
         }
     }
 
+    /// Every tag's materialized path; see [`Lookup::tag_paths`]
+    fn tag_paths(&self) -> &HashMap<String, Vec<Position>> {
+        let lookup = self.lookup();
+        lookup.tag_paths.get_or_init(|| {
+            let mut paths = HashMap::new();
+            for tag in self.tags() {
+                let mut path = Vec::new();
+                let mut current_id = tag.id();
+                let mut visited = HashSet::new();
+                // Traverse up the hierarchy; a revisited ID means a circular reference
+                while visited.insert(current_id) {
+                    let Some(&position) = lookup.by_id.get(current_id) else {
+                        break;
+                    };
+                    let Some(Resource::Tag { relationships, .. }) = self.at(position) else {
+                        break;
+                    };
+                    path.push(position);
+                    match tag_parent(relationships.as_ref()) {
+                        Some(parent_id) => current_id = parent_id,
+                        None => break,
+                    }
+                }
+                path.reverse();
+                paths.insert(tag.id().to_string(), path);
+            }
+            paths
+        })
+    }
+
+    /// How many bookmarks each tag has, directly and through the tags below
+    /// it, keyed by tag ID. A full pass over the bookmarks on first use,
+    /// kept like the other lookups until the collection is changed.
+    pub fn tag_counts(&self) -> &HashMap<String, TagCounts> {
+        let lookup = self.lookup();
+        lookup.tag_counts.get_or_init(|| {
+            let paths = self.tag_paths();
+            let mut counts: HashMap<String, TagCounts> = paths
+                .keys()
+                .map(|id| (id.clone(), TagCounts::default()))
+                .collect();
+            for resource in &self.data {
+                let Resource::Bookmark {
+                    relationships: Some(rels),
+                    ..
+                } = resource
+                else {
+                    continue;
+                };
+                // A bookmark tagged both `rust` and its parent `programming`
+                // counts once towards `programming`
+                let mut direct = HashSet::new();
+                let mut under = HashSet::new();
+                for tag in rels.tags.iter().flat_map(|tags| &tags.data) {
+                    let Some(path) = paths.get(tag.id.as_str()) else {
+                        continue;
+                    };
+                    direct.insert(tag.id.as_str());
+                    under.extend(path.iter().filter_map(|&p| self.at(p)).map(Resource::id));
+                }
+                for id in direct {
+                    if let Some(count) = counts.get_mut(id) {
+                        count.direct += 1;
+                    }
+                }
+                for id in under {
+                    if let Some(count) = counts.get_mut(id) {
+                        count.total += 1;
+                    }
+                }
+            }
+            counts
+        })
+    }
+
+    /// Bookmarks tagged with `tag_id`, in file order
+    pub fn bookmarks_with_tag(&self, tag_id: &str) -> Vec<&Resource> {
+        self.lookup()
+            .bookmarks_by_tag
+            .get(tag_id)
+            .map(|positions| positions.iter().map(|&i| &self.data[i]).collect())
+            .unwrap_or_default()
+    }
+
     /// Add a bookmark to the data
     pub fn add_bookmark(&mut self, bookmark: Resource) -> Result<()> {
         match bookmark {
             Resource::Bookmark { .. } => {
-                self.data.push(bookmark);
+                self.data_mut().push(bookmark);
                 Ok(())
             }
-            Resource::Tag { .. } => anyhow::bail!("Expected bookmark resource"),
+            _ => anyhow::bail!("Expected bookmark resource"),
         }
     }
 
@@ -134,174 +907,1050 @@ impl BookmarksData {
     pub fn add_tag(&mut self, tag: Resource) -> Result<()> {
         match tag {
             Resource::Tag { .. } => {
-                if self.included.is_none() {
-                    self.included = Some(Vec::new());
-                }
-                if let Some(included) = &mut self.included {
-                    included.push(tag);
-                }
+                self.included_mut().get_or_insert_with(Vec::new).push(tag);
                 Ok(())
             }
-            Resource::Bookmark { .. } => anyhow::bail!("Expected tag resource"),
+            _ => anyhow::bail!("Expected tag resource"),
         }
     }
 
-    /// Get all bookmarks
-    pub fn get_bookmarks(&self) -> Vec<&Resource> {
-        self.data
-            .iter()
-            .filter(|r| matches!(r, Resource::Bookmark { .. }))
-            .collect()
+    /// Add any resource to the section it belongs in
+    pub fn add_resource(&mut self, resource: Resource) {
+        match resource {
+            Resource::Tag { .. } => self
+                .included_mut()
+                .get_or_insert_with(Vec::new)
+                .push(resource),
+            _ => self.data_mut().push(resource),
+        }
     }
 
-    /// Get all tags (from both data and included)
-    pub fn get_tags(&self) -> Vec<&Resource> {
-        let mut tags = Vec::new();
-
-        // Tags from data section
-        tags.extend(
-            self.data
-                .iter()
-                .filter(|r| matches!(r, Resource::Tag { .. })),
-        );
-
-        // Tags from included section
-        if let Some(included) = &self.included {
-            tags.extend(
-                included
-                    .iter()
-                    .filter(|r| matches!(r, Resource::Tag { .. })),
-            );
+    /// Remove the resource with `id` from either section
+    pub fn remove_resource(&mut self, id: &str) {
+        let (data, included) = self.resources_mut();
+        data.retain(|resource| resource.id() != id);
+        if let Some(included) = included {
+            included.retain(|resource| resource.id() != id);
         }
-
-        tags
     }
 
-    /// Get tag hierarchy (parent-child relationships)
-    pub fn get_tag_hierarchy(&self) -> HashMap<String, Vec<String>> {
-        let mut hierarchy: HashMap<String, Vec<String>> = HashMap::new();
-
-        for tag in self.get_tags() {
-            if let Resource::Tag {
-                id,
-                relationships: Some(rels),
-                ..
-            } = tag
-            {
-                if let Some(parent_rel) = &rels.parent {
-                    if let Some(parent_id) = &parent_rel.data {
-                        hierarchy
-                            .entry(parent_id.id.clone())
-                            .or_default()
-                            .push(id.clone());
-                    }
-                }
+    /// Apply changes the extension sent instead of the whole file. Every
+    /// change is checked before any is made, so a bad delta leaves the data
+    /// as it was.
+    pub fn apply_delta(&mut self, delta: Delta) -> Result<()> {
+        let mut seen = HashSet::new();
+        for resource in &delta.added {
+            let id = resource.id();
+            if self.get(id).is_some() || !seen.insert(id) {
+                anyhow::bail!("Can't add {id}: the ID is already in use");
             }
         }
-
-        hierarchy
-    }
-
-    /// Get breadcrumb path for a tag (e.g., `["tech", "programming", "rust"]`)
-    pub fn get_tag_breadcrumb(&self, tag_id: &str) -> Vec<String> {
-        let mut breadcrumb = Vec::new();
-        let tags_by_id: HashMap<String, &Resource> = self
-            .get_tags()
-            .into_iter()
-            .filter_map(|t| {
-                if let Resource::Tag { id, .. } = t {
-                    Some((id.clone(), t))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        let mut current_id = tag_id.to_string();
-        let mut visited = std::collections::HashSet::new();
-
-        // Traverse up the hierarchy
-        loop {
-            if visited.contains(&current_id) {
-                // Circular reference detected
-                break;
+        for resource in &delta.updated {
+            let id = resource.id();
+            match self.get(id) {
+                Some(existing)
+                    if std::mem::discriminant(existing) == std::mem::discriminant(resource) => {}
+                Some(_) => anyhow::bail!("Can't update {id} to a different type"),
+                None => anyhow::bail!("Can't update {id}: no resource has that ID"),
+            }
+            if !seen.insert(id) {
+                anyhow::bail!("{id} is changed more than once");
+            }
+        }
+        for id in &delta.removed {
+            if self.get(id).is_none() {
+                anyhow::bail!("Can't remove {id}: no resource has that ID");
             }
-            visited.insert(current_id.clone());
+            if !seen.insert(id) {
+                anyhow::bail!("{id} is changed more than once");
+            }
+        }
 
-            if let Some(Resource::Tag {
-                attributes,
-                relationships,
-                ..
-            }) = tags_by_id.get(&current_id)
-            {
-                breadcrumb.insert(0, attributes.name.clone());
-
-                // Check for parent
-                if let Some(rels) = relationships {
-                    if let Some(parent_rel) = &rels.parent {
-                        if let Some(parent_id) = &parent_rel.data {
-                            current_id = parent_id.id.clone();
-                            continue;
-                        }
+        // Updates go by position, so they come before removals shift anything
+        for resource in delta.updated {
+            let position = self.lookup().by_id[resource.id()];
+            let (data, included) = self.resources_mut();
+            match position {
+                Position::Data(i) => data[i] = resource,
+                Position::Included(i) => {
+                    if let Some(included) = included {
+                        included[i] = resource;
                     }
                 }
             }
-            break;
         }
-
-        breadcrumb
+        if !delta.removed.is_empty() {
+            let removed: HashSet<&str> = delta.removed.iter().map(String::as_str).collect();
+            let (data, included) = self.resources_mut();
+            data.retain(|resource| !removed.contains(resource.id()));
+            if let Some(included) = included {
+                included.retain(|resource| !removed.contains(resource.id()));
+            }
+        }
+        for resource in delta.added {
+            self.add_resource(resource);
+        }
+        Ok(())
     }
 
-    /// Validate the data structure against JSON API v1.1 spec
+    /// Add a saved search to the data
+    pub fn add_saved_search(&mut self, search: Resource) -> Result<()> {
+        match search {
+            Resource::SavedSearch { .. } => {
+                self.data_mut().push(search);
+                Ok(())
+            }
+            _ => anyhow::bail!("Expected saved search resource"),
+        }
+    }
+
+    /// Get all bookmarks
+    pub fn get_bookmarks(&self) -> Vec<&Resource> {
+        self.data
+            .iter()
+            .filter(|r| matches!(r, Resource::Bookmark { .. }))
+            .collect()
+    }
+
+    /// Add a rule to the data
+    pub fn add_rule(&mut self, rule: Resource) -> Result<()> {
+        match rule {
+            Resource::Rule { .. } => {
+                self.data_mut().push(rule);
+                Ok(())
+            }
+            _ => anyhow::bail!("Expected rule resource"),
+        }
+    }
+
+    /// Get all rules
+    pub fn get_rules(&self) -> Vec<&Resource> {
+        self.data
+            .iter()
+            .filter(|r| matches!(r, Resource::Rule { .. }))
+            .collect()
+    }
+
+    /// Get all saved searches
+    pub fn get_saved_searches(&self) -> Vec<&Resource> {
+        self.data
+            .iter()
+            .filter(|r| matches!(r, Resource::SavedSearch { .. }))
+            .collect()
+    }
+
+    /// Bookmarks marked `unread`, oldest first
+    pub fn get_unread_bookmarks(&self) -> Vec<&Resource> {
+        let mut unread: Vec<&Resource> = self
+            .data
+            .iter()
+            .filter(|r| matches!(r, Resource::Bookmark { attributes, .. } if attributes.unread))
+            .collect();
+        unread.sort_by_key(|r| match r {
+            Resource::Bookmark { attributes, .. } => attributes.created,
+            _ => unreachable!("only bookmarks are collected"),
+        });
+        unread
+    }
+
+    /// Pinned bookmarks in the order `ReorderPinned` gave them, then those
+    /// not placed yet. Ties, which merging two devices' orders can leave,
+    /// go oldest first.
+    pub fn get_pinned_bookmarks(&self) -> Vec<&Resource> {
+        let mut pinned: Vec<&Resource> = self
+            .data
+            .iter()
+            .filter(|r| matches!(r, Resource::Bookmark { attributes, .. } if attributes.pinned))
+            .collect();
+        pinned.sort_by_key(|r| match r {
+            Resource::Bookmark { attributes, .. } => (
+                attributes.pin_order.is_none(),
+                attributes.pin_order,
+                attributes.created,
+            ),
+            _ => unreachable!("only bookmarks are collected"),
+        });
+        pinned
+    }
+
+    /// Put the pinned bookmarks in the order of `ids`, which must name each
+    /// of them exactly once, so a client working from an out-of-date list
+    /// can't unpin or drop anything. Returns whether any position changed.
+    pub fn reorder_pinned(&mut self, ids: &[String]) -> Result<bool> {
+        let mut positions = HashMap::with_capacity(ids.len());
+        for (position, id) in ids.iter().enumerate() {
+            let position = u32::try_from(position).context("Too many pinned bookmarks")?;
+            if positions.insert(id.as_str(), position).is_some() {
+                anyhow::bail!("Bookmark {id} is listed more than once");
+            }
+        }
+        let pinned: Vec<&str> = self
+            .get_pinned_bookmarks()
+            .into_iter()
+            .map(Resource::id)
+            .collect();
+        if let Some(missing) = pinned.iter().find(|id| !positions.contains_key(*id)) {
+            anyhow::bail!("Pinned bookmark {missing} is missing from the new order");
+        }
+        if let Some(extra) = ids.iter().find(|id| !pinned.contains(&id.as_str())) {
+            anyhow::bail!("{extra} is not a pinned bookmark");
+        }
+
+        let mut changed = false;
+        for resource in &mut self.data {
+            if let Resource::Bookmark { id, attributes, .. } = resource {
+                // Also clears positions left on bookmarks since unpinned
+                let position = positions.get(id.as_str()).copied();
+                if attributes.pin_order != position {
+                    attributes.pin_order = position;
+                    changed = true;
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Clear the `unread` flag on bookmark `id`, returning whether it was set
+    pub fn mark_read(&mut self, id: &str) -> Result<bool> {
+        let Some(&Position::Data(i)) = self.lookup().by_id.get(id) else {
+            anyhow::bail!("No bookmark with ID {id}");
+        };
+        // The flag isn't part of the lookup, so the cache stays valid
+        match &mut self.data[i] {
+            Resource::Bookmark { attributes, .. } => {
+                Ok(std::mem::replace(&mut attributes.unread, false))
+            }
+            _ => anyhow::bail!("{id} is not a bookmark"),
+        }
+    }
+
+    /// Change bookmark `id`'s title and/or notes, returning whether anything
+    /// changed. Empty notes are removed; notes are sanitized before they're
+    /// stored.
+    pub fn update_bookmark(
+        &mut self,
+        id: &str,
+        title: Option<String>,
+        notes: Option<String>,
+    ) -> Result<bool> {
+        let Some(&Position::Data(i)) = self.lookup().by_id.get(id) else {
+            anyhow::bail!("No bookmark with ID {id}");
+        };
+        // Neither field is part of the lookup, so the cache stays valid
+        let Resource::Bookmark { attributes, .. } = &mut self.data[i] else {
+            anyhow::bail!("{id} is not a bookmark");
+        };
+        let mut changed = false;
+        if let Some(title) = title {
+            let title = unicode::into_nfc(title);
+            changed |= attributes.title != title;
+            attributes.title = title;
+        }
+        if let Some(notes) = notes {
+            let notes = Some(sanitize_notes(&notes).into_owned()).filter(|n| !n.trim().is_empty());
+            changed |= attributes.notes != notes;
+            attributes.notes = notes;
+        }
+        if changed {
+            attributes.modified = Some(Utc::now());
+        }
+        Ok(changed)
+    }
+
+    /// Get all tags (from both data and included)
+    pub fn get_tags(&self) -> Vec<&Resource> {
+        self.tags().collect()
+    }
+
+    /// Iterate over all tags without collecting them
+    fn tags(&self) -> impl Iterator<Item = &Resource> {
+        self.data
+            .iter()
+            .chain(self.included.iter().flatten())
+            .filter(|r| matches!(r, Resource::Tag { .. }))
+    }
+
+    /// Get tag hierarchy (parent-child relationships)
+    pub fn get_tag_hierarchy(&self) -> HashMap<String, Vec<String>> {
+        let mut hierarchy: HashMap<String, Vec<String>> = HashMap::new();
+
+        for tag in self.tags() {
+            if let Resource::Tag {
+                id,
+                relationships: Some(rels),
+                ..
+            } = tag
+            {
+                if let Some(parent_rel) = &rels.parent {
+                    if let Some(parent_id) = &parent_rel.data {
+                        hierarchy
+                            .entry(parent_id.id.clone())
+                            .or_default()
+                            .push(id.clone());
+                    }
+                }
+            }
+        }
+
+        hierarchy
+    }
+
+    /// Get breadcrumb path for a tag (e.g., `["tech", "programming", "rust"]`)
+    pub fn get_tag_breadcrumb(&self, tag_id: &str) -> Vec<String> {
+        self.tag_paths()
+            .get(tag_id)
+            .map(|path| self.path_names(path))
+            .unwrap_or_default()
+    }
+
+    fn path_names(&self, path: &[Position]) -> Vec<String> {
+        path.iter()
+            .filter_map(|&position| match self.at(position) {
+                Some(Resource::Tag { attributes, .. }) => Some(attributes.name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every tag, nested under its parent, with tags on each level in name
+    /// order. Tags whose parent is missing are top-level; tags caught in a
+    /// cycle, which validation refuses and `Repair` fixes, are left out.
+    pub fn tag_tree(&self) -> Vec<TagNode> {
+        self.tag_tree_with_counts(self.tag_counts())
+    }
+
+    /// [`BookmarksData::tag_tree`] with counts already worked out, e.g. kept
+    /// from an earlier request. Tags missing from `counts` count zero.
+    pub fn tag_tree_with_counts(&self, counts: &HashMap<String, TagCounts>) -> Vec<TagNode> {
+        let mut roots = Vec::new();
+        let mut children: HashMap<&str, Vec<TagNode>> = HashMap::new();
+        for tag in self.tags() {
+            let Resource::Tag { id, attributes, .. } = tag else {
+                continue;
+            };
+            let path = &self.tag_paths()[id.as_str()];
+            let node = TagNode {
+                id: id.clone(),
+                name: attributes.name.clone(),
+                color: attributes.color.clone(),
+                path: self.path_names(path),
+                counts: counts.get(id.as_str()).copied().unwrap_or_default(),
+                children: Vec::new(),
+            };
+            match path.len().checked_sub(2).and_then(|i| self.at(path[i])) {
+                Some(parent) => children.entry(parent.id()).or_default().push(node),
+                None => roots.push(node),
+            }
+        }
+        adopt_tag_nodes(&mut roots, &mut children);
+        roots
+    }
+
+    /// ID of a tag's parent, if it has one
+    pub fn tag_parent_id(&self, tag_id: &str) -> Option<&str> {
+        match self.get(tag_id)? {
+            Resource::Tag { relationships, .. } => tag_parent(relationships.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// Breadcrumb paths for every tag, keyed by tag ID
+    pub fn get_tag_breadcrumbs(&self) -> HashMap<String, Vec<String>> {
+        self.tags()
+            .filter_map(|tag| match tag {
+                Resource::Tag { id, .. } => Some((id.clone(), self.get_tag_breadcrumb(id))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Drop the `meta` entries `Read` adds (like local activity), which come
+    /// back when the extension writes what it read
+    pub fn remove_host_meta(&mut self) {
+        for resource in self.data_mut() {
+            if let Resource::Bookmark { extra, .. } = resource {
+                for key in HOST_META_KEYS {
+                    extra.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Strip what [`sanitize_notes`] doesn't allow from every bookmark's notes
+    pub fn sanitize_notes(&mut self) {
+        for resource in self.data_mut() {
+            if let Resource::Bookmark { attributes, .. } = resource {
+                if let Some(notes) = &mut attributes.notes {
+                    if let Cow::Owned(sanitized) = sanitize_notes(notes) {
+                        *notes = sanitized;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Where every field this version doesn't know about sits, e.g.
+    /// `meta.origin` or `bookmark b1: attributes.rating`
+    pub fn unknown_fields(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .meta
+            .unknown
+            .keys()
+            .map(|key| format!("meta.{key}"))
+            .collect();
+        for resource in self.data.iter().chain(self.included.iter().flatten()) {
+            let (kind, unknown) = match resource {
+                Resource::Bookmark { attributes, .. } => ("bookmark", &attributes.unknown),
+                Resource::Tag { attributes, .. } => ("tag", &attributes.unknown),
+                Resource::SavedSearch { attributes, .. } => ("savedsearch", &attributes.unknown),
+                Resource::Rule { attributes, .. } => ("rule", &attributes.unknown),
+            };
+            paths.extend(
+                unknown
+                    .keys()
+                    .map(|key| format!("{kind} {}: attributes.{key}", resource.id())),
+            );
+        }
+        paths
+    }
+
+    /// Validate the data structure against JSON API v1.1 spec, with the
+    /// default length limits
     pub fn validate(&self) -> Result<()> {
+        self.validate_with(&ValidationConfig::default())
+    }
+
+    /// Validate the data structure with the given length limits
+    pub fn validate_with(&self, limits: &ValidationConfig) -> Result<()> {
         // Check version
         if self.jsonapi.version != "1.1" {
             anyhow::bail!("Invalid JSON API version: {}", self.jsonapi.version);
         }
+        if self.meta.schema_version != CURRENT_SCHEMA_VERSION {
+            anyhow::bail!(
+                "Unsupported schema version {} (expected {CURRENT_SCHEMA_VERSION})",
+                self.meta.schema_version
+            );
+        }
 
         // Validate all resources have unique IDs and valid data
-        let mut ids = std::collections::HashSet::new();
+        let mut ids = std::collections::HashSet::with_capacity(
+            self.data.len() + self.included.as_ref().map_or(0, Vec::len),
+        );
         for resource in &self.data {
             let id = match resource {
-                Resource::Bookmark { id, attributes, .. } => {
+                Resource::Bookmark {
+                    id,
+                    attributes,
+                    extra,
+                    ..
+                } => {
                     // Validate bookmark URL
-                    validate_bookmark_url(&attributes.url)?;
+                    validate_bookmark_url(&attributes.url, limits)?;
                     // Validate title length
-                    if attributes.title.len() > 500 {
-                        anyhow::bail!("Bookmark title too long (max 500 characters)");
+                    if attributes.title.len() > limits.max_title_length {
+                        anyhow::bail!(
+                            "Bookmark title too long (max {} characters)",
+                            limits.max_title_length
+                        );
                     }
+                    if let Some(notes) = &attributes.notes {
+                        if notes.len() > MAX_NOTES_LENGTH {
+                            anyhow::bail!(
+                                "Bookmark {id} notes too long (max {MAX_NOTES_LENGTH} characters)"
+                            );
+                        }
+                        if sanitize_notes(notes) != notes.as_str() {
+                            anyhow::bail!("Bookmark {id} notes contain HTML that isn't allowed");
+                        }
+                    }
+                    validate_extra(extra).with_context(|| format!("Bookmark {id}"))?;
                     id
                 }
                 Resource::Tag { id, attributes, .. } => {
-                    // Validate tag name
+                    validate_tag_name(&attributes.name, limits)?;
+                    validate_tag_color(id, attributes)?;
+                    id
+                }
+                Resource::SavedSearch { id, attributes } => {
                     if attributes.name.is_empty() || attributes.name.len() > 100 {
-                        anyhow::bail!("Tag name must be between 1-100 characters");
+                        anyhow::bail!("Saved search name must be between 1-100 characters");
                     }
-                    // Validate tag name doesn't contain HTML
-                    if attributes.name.contains('<') || attributes.name.contains('>') {
-                        anyhow::bail!("Tag name cannot contain HTML characters");
+                    Query::parse(&attributes.query)
+                        .with_context(|| format!("Saved search {id} has an invalid query"))?;
+                    id
+                }
+                Resource::Rule { id, attributes, .. } => {
+                    if attributes.name.is_empty() || attributes.name.len() > 100 {
+                        anyhow::bail!("Rule name must be between 1-100 characters");
                     }
+                    rules::Rule::compile(attributes, Vec::new())
+                        .with_context(|| format!("Rule {id} is invalid"))?;
                     id
                 }
             };
-            if !ids.insert(id) {
+            if !ids.insert(id.as_str()) {
                 anyhow::bail!("Duplicate resource ID: {id}");
             }
         }
 
         if let Some(included) = &self.included {
             for resource in included {
-                let id = match resource {
-                    Resource::Bookmark { id, .. } | Resource::Tag { id, .. } => id,
-                };
+                let id = resource.id();
+                if let Resource::Tag { attributes, .. } = resource {
+                    validate_tag_name(&attributes.name, limits)?;
+                    validate_tag_color(id, attributes)?;
+                }
                 if !ids.insert(id) {
                     anyhow::bail!("Duplicate resource ID: {id}");
                 }
             }
         }
 
+        let problems = self.reference_problems();
+        if !problems.is_empty() {
+            return Err(ReferenceError { problems }.into());
+        }
+
+        // Without cycles, every breadcrumb ends at a top-level tag
+        for tag in self.tags() {
+            let depth = self.get_tag_breadcrumb(tag.id()).len();
+            if depth > limits.max_tag_depth {
+                anyhow::bail!(
+                    "Tag {} is nested {depth} levels deep (max {})",
+                    tag.id(),
+                    limits.max_tag_depth
+                );
+            }
+        }
+
         Ok(())
     }
+
+    /// Relationships that point at missing tags or loop back on themselves
+    pub fn reference_problems(&self) -> Vec<ReferenceProblem> {
+        let is_tag = |id: &str| matches!(self.get(id), Some(Resource::Tag { .. }));
+        let mut problems = Vec::new();
+
+        for resource in &self.data {
+            match resource {
+                Resource::Bookmark {
+                    id,
+                    relationships: Some(rels),
+                    ..
+                } => {
+                    for tag in rels.tags.iter().flat_map(|tags| &tags.data) {
+                        if !is_tag(&tag.id) {
+                            problems.push(ReferenceProblem::MissingTag {
+                                bookmark_id: id.clone(),
+                                tag_id: tag.id.clone(),
+                            });
+                        }
+                    }
+                }
+                Resource::Rule {
+                    id, relationships, ..
+                } => {
+                    for tag in &relationships.tags.data {
+                        if !is_tag(&tag.id) {
+                            problems.push(ReferenceProblem::RuleMissingTag {
+                                rule_id: id.clone(),
+                                tag_id: tag.id.clone(),
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Only parents that exist are followed when looking for cycles
+        let mut parents: HashMap<&str, &str> = HashMap::new();
+        for tag in self.tags() {
+            let Resource::Tag {
+                id, relationships, ..
+            } = tag
+            else {
+                continue;
+            };
+            let Some(parent_id) = tag_parent(relationships.as_ref()) else {
+                continue;
+            };
+            if is_tag(parent_id) {
+                parents.insert(id, parent_id);
+            } else {
+                problems.push(ReferenceProblem::MissingParent {
+                    tag_id: id.clone(),
+                    parent_id: parent_id.to_string(),
+                });
+            }
+        }
+
+        let mut checked: HashSet<&str> = HashSet::new();
+        for tag in self.tags() {
+            let mut path = HashSet::new();
+            let mut current = tag.id();
+            while !checked.contains(current) && path.insert(current) {
+                match parents.get(current) {
+                    Some(&parent) if path.contains(parent) => {
+                        problems.push(ReferenceProblem::Cycle {
+                            tag_id: current.to_string(),
+                            parent_id: parent.to_string(),
+                        });
+                        break;
+                    }
+                    Some(&parent) => current = parent,
+                    None => break,
+                }
+            }
+            checked.extend(path);
+        }
+
+        problems
+    }
+
+    /// Fix everything [`reference_problems`](Self::reference_problems)
+    /// finds: dangling bookmark and rule tags are dropped, and tags with a missing
+    /// parent or one that closes a cycle become top-level tags. Returns what
+    /// was fixed.
+    pub fn repair(&mut self) -> Vec<ReferenceProblem> {
+        let problems = self.reference_problems();
+        if problems.is_empty() {
+            return problems;
+        }
+
+        let mut dangling_tags: HashSet<(&str, &str)> = HashSet::new();
+        let mut detached: HashSet<&str> = HashSet::new();
+        for problem in &problems {
+            match problem {
+                ReferenceProblem::MissingTag {
+                    bookmark_id: id,
+                    tag_id,
+                }
+                | ReferenceProblem::RuleMissingTag {
+                    rule_id: id,
+                    tag_id,
+                } => {
+                    dangling_tags.insert((id, tag_id));
+                }
+                ReferenceProblem::MissingParent { tag_id, .. }
+                | ReferenceProblem::Cycle { tag_id, .. } => {
+                    detached.insert(tag_id);
+                }
+            }
+        }
+
+        self.invalidate();
+        for resource in self
+            .data
+            .iter_mut()
+            .chain(self.included.iter_mut().flatten())
+        {
+            match resource {
+                Resource::Bookmark {
+                    id,
+                    relationships: Some(rels),
+                    ..
+                } => {
+                    if let Some(tags) = &mut rels.tags {
+                        tags.data
+                            .retain(|tag| !dangling_tags.contains(&(id.as_str(), tag.id.as_str())));
+                    }
+                }
+                Resource::Rule {
+                    id, relationships, ..
+                } => relationships
+                    .tags
+                    .data
+                    .retain(|tag| !dangling_tags.contains(&(id.as_str(), tag.id.as_str()))),
+                Resource::Tag {
+                    id,
+                    relationships: Some(rels),
+                    ..
+                } if detached.contains(id.as_str()) => rels.parent = None,
+                _ => {}
+            }
+        }
+
+        problems
+    }
+
+    /// Who may see `resource`. A bookmark is private if it's marked private
+    /// or carries a private tag; otherwise its own visibility applies, or
+    /// when it has none, public if any of its tags is public.
+    pub fn visibility(&self, resource: &Resource) -> Visibility {
+        let tag_visibility = |id: &str| match self.get(id) {
+            Some(Resource::Tag { attributes, .. }) => attributes.visibility.unwrap_or_default(),
+            _ => Visibility::Shared,
+        };
+        match resource {
+            Resource::Bookmark {
+                attributes,
+                relationships,
+                ..
+            } => {
+                let tags: Vec<Visibility> = relationships
+                    .iter()
+                    .flat_map(|r| r.tags.iter().flat_map(|tags| &tags.data))
+                    .map(|tag| tag_visibility(&tag.id))
+                    .collect();
+                if attributes.private
+                    || attributes.visibility == Some(Visibility::Private)
+                    || tags.contains(&Visibility::Private)
+                {
+                    Visibility::Private
+                } else if let Some(visibility) = attributes.visibility {
+                    visibility
+                } else if tags.contains(&Visibility::Public) {
+                    Visibility::Public
+                } else {
+                    Visibility::Shared
+                }
+            }
+            Resource::Tag { attributes, .. } => attributes.visibility.unwrap_or_default(),
+            _ => Visibility::Shared,
+        }
+    }
+
+    /// Only the public bookmarks and tags, for publishing. Notes, authors,
+    /// and fields this version doesn't know about are left out, as are
+    /// references to tags that aren't public.
+    #[must_use]
+    pub fn public_subset(&self) -> BookmarksData {
+        let public_tags: HashSet<&str> = self
+            .get_tags()
+            .into_iter()
+            .filter(|tag| self.visibility(tag) == Visibility::Public)
+            .map(Resource::id)
+            .collect();
+        let mut public = BookmarksData::new();
+        for resource in self.data.iter().chain(self.included.iter().flatten()) {
+            if self.visibility(resource) != Visibility::Public {
+                continue;
+            }
+            match resource {
+                Resource::Bookmark {
+                    id,
+                    attributes,
+                    relationships,
+                    ..
+                } => {
+                    let tags: Vec<ResourceIdentifier> = relationships
+                        .iter()
+                        .flat_map(|r| r.tags.iter().flat_map(|tags| &tags.data))
+                        .filter(|tag| public_tags.contains(tag.id.as_str()))
+                        .cloned()
+                        .collect();
+                    public.add_resource(Resource::Bookmark {
+                        id: id.clone(),
+                        attributes: BookmarkAttributes {
+                            notes: None,
+                            author: None,
+                            unknown: UnknownFields::new(),
+                            ..attributes.clone()
+                        },
+                        relationships: (!tags.is_empty()).then_some(BookmarkRelationships {
+                            tags: Some(RelationshipData { data: tags }),
+                        }),
+                        extra: serde_json::Map::new(),
+                    });
+                }
+                Resource::Tag { id, attributes, .. } => {
+                    let parent = self
+                        .tag_parent_id(id)
+                        .filter(|parent| public_tags.contains(parent));
+                    public.add_resource(Resource::Tag {
+                        id: id.clone(),
+                        attributes: TagAttributes {
+                            unknown: UnknownFields::new(),
+                            ..attributes.clone()
+                        },
+                        relationships: parent.map(|parent| TagRelationships {
+                            parent: Some(ParentRelationship {
+                                data: Some(ResourceIdentifier {
+                                    resource_type: "tag".to_string(),
+                                    id: parent.to_string(),
+                                }),
+                            }),
+                        }),
+                    });
+                }
+                _ => {}
+            }
+        }
+        public
+    }
+
+    /// Attribute bookmarks that aren't in `previous` and have no author to
+    /// `author`. Returns how many were stamped.
+    pub fn stamp_author(&mut self, author: &str, previous: Option<&BookmarksData>) -> usize {
+        let mut stamped = 0;
+        // Authors aren't part of the lookup, so the cache stays valid
+        for resource in &mut self.data {
+            if let Resource::Bookmark { id, attributes, .. } = resource {
+                let new = previous.is_none_or(|previous| previous.get(id).is_none());
+                if new && attributes.author.is_none() {
+                    attributes.author = Some(author.to_string());
+                    stamped += 1;
+                }
+            }
+        }
+        stamped
+    }
+
+    /// Store bookmark titles and tag names in Unicode NFC. Returns how many
+    /// changed.
+    pub fn normalize_unicode(&mut self) -> usize {
+        let mut changed = 0;
+        let mut normalize = |text: &mut String| {
+            if let Cow::Owned(normalized) = unicode::nfc(text) {
+                *text = normalized;
+                changed += 1;
+            }
+        };
+        // Names and titles aren't part of the lookup, so the cache stays valid
+        for resource in self
+            .data
+            .iter_mut()
+            .chain(self.included.iter_mut().flatten())
+        {
+            match resource {
+                Resource::Bookmark { attributes, .. } => normalize(&mut attributes.title),
+                Resource::Tag { attributes, .. } => normalize(&mut attributes.name),
+                _ => {}
+            }
+        }
+        changed
+    }
+
+    /// Merge tags under the same parent whose names differ only in case or
+    /// Unicode normalization form into the first of them. Bookmarks and
+    /// rules move to the tag kept, as do child tags (which may then merge
+    /// in turn), and it takes a color if it had none.
+    pub fn merge_duplicate_tags(&mut self) -> Vec<MergedTags> {
+        let mut merged: Vec<MergedTags> = Vec::new();
+        loop {
+            let mut kept: HashMap<(Option<&str>, String), &str> = HashMap::new();
+            let mut replaced: HashMap<String, String> = HashMap::new();
+            for tag in self.tags() {
+                if let Resource::Tag {
+                    id,
+                    attributes,
+                    relationships,
+                } = tag
+                {
+                    let key = (
+                        tag_parent(relationships.as_ref()),
+                        unicode::fold(&attributes.name),
+                    );
+                    match kept.get(&key) {
+                        Some(&first) => {
+                            replaced.insert(id.clone(), first.to_string());
+                        }
+                        None => {
+                            kept.insert(key, id);
+                        }
+                    }
+                }
+            }
+            if replaced.is_empty() {
+                return merged;
+            }
+
+            let mut colors: HashMap<String, String> = HashMap::new();
+            for tag in self.tags() {
+                let Resource::Tag { id, attributes, .. } = tag else {
+                    continue;
+                };
+                let Some(into) = replaced.get(id) else {
+                    continue;
+                };
+                if let Some(color) = &attributes.color {
+                    colors.entry(into.clone()).or_insert_with(|| color.clone());
+                }
+                // A tag kept in an earlier pass may itself merge now
+                let mut ids = vec![id.clone()];
+                if let Some(earlier) = merged.iter().position(|m| m.kept == *id) {
+                    ids.extend(merged.remove(earlier).merged);
+                }
+                match merged.iter_mut().find(|m| m.kept == *into) {
+                    Some(entry) => entry.merged.extend(ids),
+                    None => merged.push(MergedTags {
+                        kept: into.clone(),
+                        name: String::new(),
+                        merged: ids,
+                    }),
+                }
+            }
+
+            self.invalidate();
+            let retag = |tags: &mut RelationshipData| {
+                let mut seen = HashSet::new();
+                for tag in &mut tags.data {
+                    if let Some(into) = replaced.get(&tag.id) {
+                        tag.id.clone_from(into);
+                    }
+                }
+                tags.data.retain(|tag| seen.insert(tag.id.clone()));
+            };
+            for resource in self
+                .data
+                .iter_mut()
+                .chain(self.included.iter_mut().flatten())
+            {
+                match resource {
+                    Resource::Bookmark {
+                        relationships: Some(BookmarkRelationships { tags: Some(tags) }),
+                        ..
+                    } => retag(tags),
+                    Resource::Rule { relationships, .. } => retag(&mut relationships.tags),
+                    Resource::Tag {
+                        id,
+                        attributes,
+                        relationships,
+                    } => {
+                        if attributes.color.is_none() {
+                            attributes.color = colors.get(id.as_str()).cloned();
+                        }
+                        let parent = relationships
+                            .as_mut()
+                            .and_then(|rels| rels.parent.as_mut())
+                            .and_then(|parent| parent.data.as_mut());
+                        if let Some(parent) = parent {
+                            if let Some(into) = replaced.get(&parent.id) {
+                                parent.id.clone_from(into);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            self.data.retain(|r| !replaced.contains_key(r.id()));
+            if let Some(included) = &mut self.included {
+                included.retain(|r| !replaced.contains_key(r.id()));
+            }
+
+            for entry in &mut merged {
+                if let Some(Resource::Tag { attributes, .. }) = self.get(&entry.kept) {
+                    entry.name.clone_from(&attributes.name);
+                }
+            }
+        }
+    }
+}
+
+/// Changes to apply with [`BookmarksData::apply_delta`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Delta {
+    /// New resources, with IDs not already in use
+    #[serde(default)]
+    pub added: Vec<Resource>,
+    /// Replacements for existing resources of the same type
+    #[serde(default)]
+    pub updated: Vec<Resource>,
+    /// IDs of resources to delete
+    #[serde(default)]
+    pub removed: Vec<String>,
+}
+
+impl Delta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Tags [`BookmarksData::merge_duplicate_tags`] folded into one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MergedTags {
+    /// ID of the tag kept
+    pub kept: String,
+    pub name: String,
+    /// IDs of the tags merged into it, now deleted
+    pub merged: Vec<String>,
+}
+
+/// A tag and the tags below it, from [`BookmarksData::tag_tree`]
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TagNode {
+    pub id: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Names from the top-level tag down to this one
+    pub path: Vec<String>,
+    pub counts: TagCounts,
+    pub children: Vec<TagNode>,
+}
+
+/// How many bookmarks carry a tag, from [`BookmarksData::tag_counts`]
+#[derive(Debug, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TagCounts {
+    /// Tagged with it
+    pub direct: usize,
+    /// Tagged with it or any tag below it, each bookmark counted once
+    pub total: usize,
+}
+
+/// Sort `nodes` by name and move each one's children, keyed by parent ID,
+/// under it, all the way down
+fn adopt_tag_nodes(nodes: &mut [TagNode], children: &mut HashMap<&str, Vec<TagNode>>) {
+    nodes.sort_by_cached_key(|node| (unicode::fold(&node.name), node.id.clone()));
+    for node in nodes {
+        if let Some(mut own) = children.remove(node.id.as_str()) {
+            adopt_tag_nodes(&mut own, children);
+            node.children = own;
+        }
+    }
+}
+
+fn tag_parent(relationships: Option<&TagRelationships>) -> Option<&str> {
+    relationships?
+        .parent
+        .as_ref()?
+        .data
+        .as_ref()
+        .map(|parent| parent.id.as_str())
+}
+
+/// A relationship that doesn't point where it should
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReferenceProblem {
+    /// A bookmark is tagged with a tag that doesn't exist
+    MissingTag { bookmark_id: String, tag_id: String },
+    /// A tag's parent doesn't exist
+    MissingParent { tag_id: String, parent_id: String },
+    /// Following parents up from `parent_id` leads back to `tag_id`
+    Cycle { tag_id: String, parent_id: String },
+    /// A rule applies a tag that doesn't exist
+    RuleMissingTag { rule_id: String, tag_id: String },
+}
+
+impl std::fmt::Display for ReferenceProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReferenceProblem::MissingTag {
+                bookmark_id,
+                tag_id,
+            } => write!(f, "bookmark {bookmark_id} has missing tag {tag_id}"),
+            ReferenceProblem::MissingParent { tag_id, parent_id } => {
+                write!(f, "tag {tag_id} has missing parent {parent_id}")
+            }
+            ReferenceProblem::Cycle { tag_id, parent_id } => {
+                write!(f, "tag {tag_id} and parent {parent_id} form a cycle")
+            }
+            ReferenceProblem::RuleMissingTag { rule_id, tag_id } => {
+                write!(f, "rule {rule_id} applies missing tag {tag_id}")
+            }
+        }
+    }
+}
+
+/// Validation found broken relationships; `Repair` can fix them
+#[derive(Debug, thiserror::Error)]
+#[error("{} broken tag reference(s), starting with: {}", .problems.len(), .problems[0])]
+pub struct ReferenceError {
+    pub problems: Vec<ReferenceProblem>,
 }
 
 impl Default for BookmarksData {
@@ -320,14 +1969,63 @@ pub fn read_from_file_with_encryption<P: AsRef<Path>>(
     path: P,
     encryption_enabled: bool,
 ) -> Result<BookmarksData> {
-    use crate::encryption::{is_encrypted, EncryptionManager};
-
+    load(path, encryption_enabled).map(|loaded| loaded.data)
+}
+
+/// Bookmarks read from disk, upgraded to the current schema
+#[derive(Debug)]
+pub struct Loaded {
+    pub data: BookmarksData,
+    /// Schema version of the file itself; older than
+    /// [`CURRENT_SCHEMA_VERSION`] until the upgraded data is written back
+    pub stored_version: u32,
+}
+
+impl Loaded {
+    pub fn was_migrated(&self) -> bool {
+        self.stored_version < CURRENT_SCHEMA_VERSION
+    }
+}
+
+/// Read and validate bookmarks data, reporting the schema version it was
+/// stored in
+pub fn load<P: AsRef<Path>>(path: P, encryption_enabled: bool) -> Result<Loaded> {
+    let path_ref = path.as_ref();
+    let content = fs::read_to_string(path_ref).context("Failed to read bookmarks file")?;
+    let loaded = parse_unchecked(&content, &repo_relative_path(path_ref), encryption_enabled)?;
+    loaded.data.validate_with(&ValidationConfig::MAXIMUM)?;
+    Ok(loaded)
+}
+
+/// Read bookmarks data without validating it, so broken data can be repaired
+pub fn read_from_file_unchecked<P: AsRef<Path>>(
+    path: P,
+    encryption_enabled: bool,
+) -> Result<BookmarksData> {
     let path_ref = path.as_ref();
+    let content = fs::read_to_string(path_ref).context("Failed to read bookmarks file")?;
+    parse_unchecked(&content, &repo_relative_path(path_ref), encryption_enabled)
+        .map(|loaded| loaded.data)
+}
+
+/// Parse bookmarks content stored at repo-relative `location`, whether it is
+/// plain text, fully encrypted, or field-level encrypted
+pub fn parse_with_encryption(
+    content: &str,
+    location: &str,
+    encryption_enabled: bool,
+) -> Result<BookmarksData> {
+    let data = parse_unchecked(content, location, encryption_enabled)?.data;
+    data.validate_with(&ValidationConfig::MAXIMUM)?;
+    Ok(data)
+}
 
-    // Check if file is encrypted
-    let file_encrypted = is_encrypted(path_ref).unwrap_or(false);
+fn parse_unchecked(content: &str, location: &str, encryption_enabled: bool) -> Result<Loaded> {
+    let encrypted = serde_json::from_str::<EncryptedData>(content)
+        .ok()
+        .filter(EncryptedData::is_encrypted);
 
-    let content = if file_encrypted {
+    let value: serde_json::Value = if let Some(encrypted) = encrypted {
         // File is encrypted, decrypt it
         if !encryption_enabled {
             anyhow::bail!(
@@ -336,21 +2034,162 @@ pub fn read_from_file_with_encryption<P: AsRef<Path>>(
             );
         }
 
-        let manager = EncryptionManager::new(true);
-        let decrypted_bytes = manager.read_encrypted_file(path_ref).context(
-            "Failed to decrypt bookmarks file. Touch ID authentication may be required.",
-        )?;
+        let decrypted_bytes = EncryptionManager::new(true)
+            .decrypt_bound(&encrypted, location)
+            .context(
+                "Failed to decrypt bookmarks file. Touch ID authentication may be required.",
+            )?;
+        serde_json::from_slice(&decrypted_bytes).context("Failed to parse bookmarks JSON")?
+    } else {
+        serde_json::from_str(content).context("Failed to parse bookmarks JSON")?
+    };
+
+    let mut value = if is_field_encrypted(&value) {
+        if !encryption_enabled {
+            anyhow::bail!(
+                "Bookmarks file has encrypted fields but encryption is not enabled. \
+                 Enable encryption to access your bookmarks."
+            );
+        }
 
-        String::from_utf8(decrypted_bytes).context("Decrypted data is not valid UTF-8")?
+        open_field_values(value, &EncryptionManager::new(true), location).context(
+            "Failed to decrypt bookmark fields. Touch ID authentication may be required.",
+        )?
     } else {
-        // File is plain text
-        fs::read_to_string(path_ref).context("Failed to read bookmarks file")?
+        value
     };
 
-    let data: BookmarksData =
-        serde_json::from_str(&content).context("Failed to parse bookmarks JSON")?;
-    data.validate()?;
-    Ok(data)
+    // Migrations see the plaintext form whatever the encryption mode
+    let stored_version = migrations::migrate(&mut value)?;
+    let mut data: BookmarksData =
+        serde_json::from_value(value).context("Failed to parse bookmarks JSON")?;
+    // Files written before notes were sanitized still load
+    data.sanitize_notes();
+    Ok(Loaded {
+        data,
+        stored_version,
+    })
+}
+
+/// Which encryption mode a bookmarks file was written with, if any
+pub fn detect_encryption<P: AsRef<Path>>(path: P) -> Result<Option<EncryptionMode>> {
+    let path_ref = path.as_ref();
+    if is_encrypted(path_ref)? {
+        return Ok(Some(EncryptionMode::Full));
+    }
+    if !path_ref.exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(path_ref).context("Failed to read bookmarks file")?;
+    Ok(serde_json::from_str::<serde_json::Value>(&content)
+        .is_ok_and(|value| is_field_encrypted(&value))
+        .then_some(EncryptionMode::Fields))
+}
+
+/// Whether serialized bookmarks carry the field-level encryption marker
+pub fn is_field_encrypted(value: &serde_json::Value) -> bool {
+    value
+        .pointer("/meta/encryption")
+        .and_then(|v| v.as_str())
+        .is_some_and(|mode| mode == FIELD_ENCRYPTION_MARKER)
+}
+
+/// Serialize bookmarks with their sensitive fields encrypted individually.
+///
+/// `file` is the repo-relative path the result will be written to; envelopes
+/// are bound to it. `previous` is the current on-disk form, if any; unchanged
+/// envelopes are kept.
+pub fn seal_fields(
+    data: &BookmarksData,
+    manager: &EncryptionManager,
+    file: &str,
+    previous: Option<&serde_json::Value>,
+) -> Result<serde_json::Value> {
+    let mut previous_by_id: HashMap<&str, SealedBookmarkAttributes> = HashMap::new();
+    if let Some(previous) = previous {
+        for resource in bookmark_resources(previous) {
+            let id = resource.get("id").and_then(|id| id.as_str());
+            let attributes = resource
+                .get("attributes")
+                .and_then(|a| serde_json::from_value(a.clone()).ok());
+            if let (Some(id), Some(attributes)) = (id, attributes) {
+                previous_by_id.insert(id, attributes);
+            }
+        }
+    }
+
+    let mut value = serde_json::to_value(data).context("Failed to serialize bookmarks data")?;
+    for section in RESOURCE_SECTIONS {
+        let Some(resources) = value.get_mut(section).and_then(|s| s.as_array_mut()) else {
+            continue;
+        };
+        for resource in resources.iter_mut().filter(|r| is_bookmark_value(r)) {
+            let id = resource["id"].as_str().unwrap_or_default().to_string();
+            let attributes: BookmarkAttributes =
+                serde_json::from_value(resource["attributes"].take())
+                    .context("Failed to read bookmark attributes")?;
+            let sealed = SealedBookmarkAttributes::seal(
+                &attributes,
+                manager,
+                &format!("{file}#{id}"),
+                previous_by_id.get(&*id),
+            )?;
+            resource["attributes"] =
+                serde_json::to_value(sealed).context("Failed to serialize sealed attributes")?;
+        }
+    }
+
+    value["meta"]["encryption"] = serde_json::json!(FIELD_ENCRYPTION_MARKER);
+    Ok(value)
+}
+
+/// Decrypt a value produced by [`seal_fields`] back into bookmarks data
+pub fn open_fields(
+    value: serde_json::Value,
+    manager: &EncryptionManager,
+    file: &str,
+) -> Result<BookmarksData> {
+    let value = open_field_values(value, manager, file)?;
+    serde_json::from_value(value).context("Failed to parse bookmarks JSON")
+}
+
+/// Decrypt a value produced by [`seal_fields`], leaving it serialized
+fn open_field_values(
+    mut value: serde_json::Value,
+    manager: &EncryptionManager,
+    file: &str,
+) -> Result<serde_json::Value> {
+    for section in RESOURCE_SECTIONS {
+        let Some(resources) = value.get_mut(section).and_then(|s| s.as_array_mut()) else {
+            continue;
+        };
+        for resource in resources.iter_mut().filter(|r| is_bookmark_value(r)) {
+            let location = format!("{file}#{}", resource["id"].as_str().unwrap_or_default());
+            let sealed: SealedBookmarkAttributes =
+                serde_json::from_value(resource["attributes"].take())
+                    .context("Failed to parse sealed bookmark attributes")?;
+            resource["attributes"] = serde_json::to_value(sealed.open(manager, &location)?)
+                .context("Failed to serialize bookmark attributes")?;
+        }
+    }
+
+    if let Some(meta) = value.get_mut("meta").and_then(|meta| meta.as_object_mut()) {
+        meta.remove("encryption");
+    }
+    Ok(value)
+}
+
+fn is_bookmark_value(resource: &serde_json::Value) -> bool {
+    resource.get("type").and_then(|t| t.as_str()) == Some("bookmark")
+}
+
+fn bookmark_resources(value: &serde_json::Value) -> impl Iterator<Item = &serde_json::Value> {
+    RESOURCE_SECTIONS
+        .iter()
+        .filter_map(|section| value.get(*section).and_then(|s| s.as_array()))
+        .flatten()
+        .filter(|r| is_bookmark_value(r))
 }
 
 /// Write bookmarks data to a file atomically (plain text)
@@ -358,47 +2197,121 @@ pub fn write_to_file<P: AsRef<Path>>(path: P, data: &BookmarksData) -> Result<()
     write_to_file_with_encryption(path, data, false)
 }
 
+/// Write bookmarks data to a file in the given encryption mode (`None` for plain text),
+/// encrypting with `cipher`
+pub fn write_to_file_with_mode<P: AsRef<Path>>(
+    path: P,
+    data: &BookmarksData,
+    mode: Option<EncryptionMode>,
+    cipher: Cipher,
+) -> Result<()> {
+    let manager = EncryptionManager::new(true).with_cipher(cipher);
+    match mode {
+        Some(EncryptionMode::Fields) => write_field_encrypted(path.as_ref(), data, &manager),
+        Some(EncryptionMode::Full) => write_fully_encrypted(path.as_ref(), data, &manager),
+        None => write_to_file(path, data),
+    }
+}
+
+/// Write bookmarks data with notes and private bookmarks encrypted per field
+fn write_field_encrypted(
+    path: &Path,
+    data: &BookmarksData,
+    manager: &EncryptionManager,
+) -> Result<()> {
+    data.validate_with(&ValidationConfig::MAXIMUM)?;
+
+    // Reuse envelopes from the current file so unchanged fields don't show up in diffs
+    let previous = fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .filter(is_field_encrypted);
+
+    let value = seal_fields(data, manager, &repo_relative_path(path), previous.as_ref())
+        .context("Failed to encrypt bookmark fields. Touch ID authentication may be required.")?;
+    let json =
+        serde_json::to_string_pretty(&value).context("Failed to serialize bookmarks data")?;
+
+    write_atomic(path, json.as_bytes())?;
+
+    log::info!("Bookmarks written (field-level encrypted)");
+    Ok(())
+}
+
+/// Write bookmarks data as a single encrypted blob
+fn write_fully_encrypted(
+    path: &Path,
+    data: &BookmarksData,
+    manager: &EncryptionManager,
+) -> Result<()> {
+    data.validate_with(&ValidationConfig::MAXIMUM)?;
+
+    // Serialize to JSON first
+    let json = serde_json::to_string_pretty(data).context("Failed to serialize bookmarks data")?;
+
+    // Encrypt and write
+    manager
+        .write_encrypted_file(path, json.as_bytes())
+        .context("Failed to write encrypted bookmarks. Touch ID authentication may be required.")?;
+
+    log::info!("Bookmarks written (encrypted)");
+    Ok(())
+}
+
 /// Write bookmarks data to a file with optional encryption
 pub fn write_to_file_with_encryption<P: AsRef<Path>>(
     path: P,
     data: &BookmarksData,
     encryption_enabled: bool,
 ) -> Result<()> {
-    use crate::encryption::EncryptionManager;
-
-    data.validate()?;
-
     let path_ref = path.as_ref();
 
     if encryption_enabled {
-        // Encrypt the data
-        let manager = EncryptionManager::new(true);
+        return write_fully_encrypted(path_ref, data, &EncryptionManager::new(true));
+    }
 
-        // Serialize to JSON first
-        let json =
-            serde_json::to_string_pretty(data).context("Failed to serialize bookmarks data")?;
+    data.validate_with(&ValidationConfig::MAXIMUM)?;
 
-        // Encrypt and write
-        manager
-            .write_encrypted_file(path_ref, json.as_bytes())
-            .context(
-                "Failed to write encrypted bookmarks. Touch ID authentication may be required.",
-            )?;
+    // Write as plain text
+    let json = serde_json::to_string_pretty(data).context("Failed to serialize bookmarks data")?;
 
-        log::info!("Bookmarks written (encrypted)");
-    } else {
-        // Write as plain text
-        let json =
-            serde_json::to_string_pretty(data).context("Failed to serialize bookmarks data")?;
+    write_atomic(path_ref, json.as_bytes())?;
 
-        // Atomic write: write to temp file, then rename
-        let temp_path = path_ref.with_extension("tmp");
-        fs::write(&temp_path, json).context("Failed to write temp file")?;
-        fs::rename(&temp_path, path_ref).context("Failed to rename temp file to target")?;
+    log::info!("Bookmarks written (plain text)");
+    Ok(())
+}
 
-        log::info!("Bookmarks written (plain text)");
-    }
+/// Replace `path` with `content` so that after a crash or power loss it
+/// holds either the old content or the new, never a mix or nothing: the
+/// content goes to a temp file that is flushed to disk before being renamed
+/// over `path`, and the rename itself is flushed by syncing the directory.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let temp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp_path).context("Failed to write temp file")?;
+    file.write_all(content)
+        .context("Failed to write temp file")?;
+    file.sync_all().context("Failed to flush temp file")?;
+    drop(file);
+    fs::rename(&temp_path, path).context("Failed to rename temp file to target")?;
+    sync_parent_dir(path)
+}
+
+/// Flush a directory entry change (a create or rename) in `path`'s directory
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .context("Failed to flush directory")
+}
 
+/// Directories can't be opened for syncing on Windows; NTFS journals renames
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
     Ok(())
 }
 
@@ -409,10 +2322,21 @@ pub fn create_bookmark(url: String, title: String, tag_ids: Vec<String>) -> Reso
         id: Uuid::new_v4().to_string(),
         attributes: BookmarkAttributes {
             url,
-            title,
+            title: unicode::into_nfc(title),
             created: now,
             modified: None,
             notes: None,
+            private: false,
+            unread: false,
+            pinned: false,
+            pin_order: None,
+            author: None,
+            visibility: None,
+            archive_url: None,
+            enrichment: None,
+            suggested_tags: Vec::new(),
+            expires_at: None,
+            unknown: UnknownFields::new(),
         },
         relationships: if tag_ids.is_empty() {
             None
@@ -429,6 +2353,7 @@ pub fn create_bookmark(url: String, title: String, tag_ids: Vec<String>) -> Reso
                 }),
             })
         },
+        extra: serde_json::Map::new(),
     }
 }
 
@@ -437,9 +2362,11 @@ pub fn create_tag(name: String, color: Option<String>, parent_id: Option<String>
     Resource::Tag {
         id: Uuid::new_v4().to_string(),
         attributes: TagAttributes {
-            name,
+            name: unicode::into_nfc(name),
             color,
             description: None,
+            visibility: None,
+            unknown: UnknownFields::new(),
         },
         relationships: parent_id.map(|pid| TagRelationships {
             parent: Some(ParentRelationship {
@@ -452,93 +2379,666 @@ pub fn create_tag(name: String, color: Option<String>, parent_id: Option<String>
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::NamedTempFile;
+/// Helper to create a new rule resource
+pub fn create_rule(
+    name: String,
+    field: RuleField,
+    match_kind: MatchKind,
+    pattern: String,
+    tag_ids: Vec<String>,
+) -> Resource {
+    Resource::Rule {
+        id: Uuid::new_v4().to_string(),
+        attributes: RuleAttributes {
+            name,
+            field,
+            match_kind,
+            pattern,
+            unknown: UnknownFields::new(),
+        },
+        relationships: RuleRelationships {
+            tags: RelationshipData {
+                data: tag_ids
+                    .into_iter()
+                    .map(|id| ResourceIdentifier {
+                        resource_type: "tag".to_string(),
+                        id,
+                    })
+                    .collect(),
+            },
+        },
+    }
+}
+
+/// Helper to create a new saved search resource
+pub fn create_saved_search(name: String, query: String, sort: SortOrder) -> Resource {
+    Resource::SavedSearch {
+        id: Uuid::new_v4().to_string(),
+        attributes: SavedSearchAttributes {
+            name,
+            query,
+            sort,
+            created: Utc::now(),
+            unknown: UnknownFields::new(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::Update;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_new_bookmarks_data() {
+        let data = BookmarksData::new();
+        assert_eq!(data.jsonapi.version, "1.1");
+        assert!(data.data.is_empty());
+        assert!(data.included.is_none());
+    }
+
+    #[test]
+    fn test_add_bookmark() {
+        let mut data = BookmarksData::new();
+        let bookmark = create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec![],
+        );
+        data.add_bookmark(bookmark).unwrap();
+        assert_eq!(data.data.len(), 1);
+    }
+
+    #[test]
+    fn test_add_tag() {
+        let mut data = BookmarksData::new();
+        let tag = create_tag("rust".to_string(), Some("#3b82f6".to_string()), None);
+        data.add_tag(tag).unwrap();
+        assert!(data.included.is_some());
+        assert_eq!(data.included.as_ref().unwrap().len(), 1);
+        assert!(data.validate().is_ok());
+
+        let badly_colored = create_tag("web".to_string(), Some("blue-ish".to_string()), None);
+        data.add_tag(badly_colored).unwrap();
+        assert!(data.validate().is_err());
+    }
+
+    #[test]
+    fn test_hierarchical_tags() {
+        let mut data = BookmarksData::new();
+
+        // Create parent tag
+        let parent_tag = create_tag("programming".to_string(), None, None);
+        let parent_id = if let Resource::Tag { id, .. } = &parent_tag {
+            id.clone()
+        } else {
+            panic!("Expected tag");
+        };
+        data.add_tag(parent_tag).unwrap();
+
+        // Create child tag
+        let child_tag = create_tag("rust".to_string(), None, Some(parent_id.clone()));
+        data.add_tag(child_tag).unwrap();
+
+        let hierarchy = data.get_tag_hierarchy();
+        assert!(hierarchy.contains_key(&parent_id));
+        assert_eq!(hierarchy.get(&parent_id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_tag_breadcrumb() {
+        let mut data = BookmarksData::new();
+
+        // Create hierarchy: tech -> programming -> rust
+        let tech_tag = create_tag("tech".to_string(), None, None);
+        let tech_id = if let Resource::Tag { id, .. } = &tech_tag {
+            id.clone()
+        } else {
+            panic!("Expected tag");
+        };
+        data.add_tag(tech_tag).unwrap();
+
+        let prog_tag = create_tag("programming".to_string(), None, Some(tech_id.clone()));
+        let prog_id = if let Resource::Tag { id, .. } = &prog_tag {
+            id.clone()
+        } else {
+            panic!("Expected tag");
+        };
+        data.add_tag(prog_tag).unwrap();
+
+        let rust_tag = create_tag("rust".to_string(), None, Some(prog_id.clone()));
+        let rust_id = if let Resource::Tag { id, .. } = &rust_tag {
+            id.clone()
+        } else {
+            panic!("Expected tag");
+        };
+        data.add_tag(rust_tag).unwrap();
+
+        let breadcrumb = data.get_tag_breadcrumb(&rust_id);
+        assert_eq!(breadcrumb, vec!["tech", "programming", "rust"]);
+
+        let all = data.get_tag_breadcrumbs();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[&tech_id], vec!["tech"]);
+        assert_eq!(all[&prog_id], vec!["tech", "programming"]);
+        assert_eq!(all[&rust_id], breadcrumb);
+
+        // Moving a tag goes through a mutable path, which drops cached paths
+        for resource in data.included_mut().iter_mut().flatten() {
+            if let Resource::Tag {
+                id, relationships, ..
+            } = resource
+            {
+                if *id == prog_id {
+                    *relationships = None;
+                }
+            }
+        }
+        assert_eq!(
+            data.get_tag_breadcrumb(&rust_id),
+            vec!["programming", "rust"]
+        );
+        assert_eq!(data.get_tag_breadcrumb("missing"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_tag_counts() {
+        let mut data = BookmarksData::new();
+        let programming = create_tag("programming".to_string(), None, None);
+        let programming_id = programming.id().to_string();
+        let rust = create_tag("rust".to_string(), None, Some(programming_id.clone()));
+        let rust_id = rust.id().to_string();
+        let cooking = create_tag("cooking".to_string(), None, None);
+        let cooking_id = cooking.id().to_string();
+        for tag in [programming, rust, cooking] {
+            data.add_tag(tag).unwrap();
+        }
+        for tags in [
+            vec![rust_id.clone()],
+            vec![rust_id.clone(), programming_id.clone()],
+            vec![programming_id.clone()],
+            vec![],
+        ] {
+            data.add_bookmark(create_bookmark(
+                "https://example.com".to_string(),
+                "Example".to_string(),
+                tags,
+            ))
+            .unwrap();
+        }
+
+        let counts = data.tag_counts();
+        assert_eq!(
+            counts[&rust_id],
+            TagCounts {
+                direct: 2,
+                total: 2
+            }
+        );
+        assert_eq!(
+            counts[&programming_id],
+            TagCounts {
+                direct: 2,
+                total: 3
+            }
+        );
+        assert_eq!(counts[&cooking_id], TagCounts::default());
+
+        // Retagging goes through a mutable path, so the counts follow
+        if let Some(Resource::Bookmark { relationships, .. }) = data.data_mut().get_mut(3) {
+            *relationships = Some(BookmarkRelationships {
+                tags: Some(RelationshipData {
+                    data: vec![ResourceIdentifier {
+                        resource_type: "tag".to_string(),
+                        id: cooking_id.clone(),
+                    }],
+                }),
+            });
+        }
+        assert_eq!(data.tag_counts()[&cooking_id].direct, 1);
+        assert_eq!(data.tag_tree()[0].counts.total, 1);
+    }
+
+    #[test]
+    fn test_tag_tree() {
+        let mut data = BookmarksData::new();
+        let tech = create_tag("tech".to_string(), Some("#336699".to_string()), None);
+        let tech_id = tech.id().to_string();
+        let web = create_tag("Web".to_string(), None, Some(tech_id.clone()));
+        let web_id = web.id().to_string();
+        let ai = create_tag("ai".to_string(), None, Some(tech_id.clone()));
+        let rust = create_tag("rust".to_string(), None, Some(web_id.clone()));
+        let orphan = create_tag("orphan".to_string(), None, Some("gone".to_string()));
+        let cooking = create_tag("cooking".to_string(), None, None);
+        for tag in [tech, web, ai, rust, orphan, cooking] {
+            data.add_tag(tag).unwrap();
+        }
+
+        let tree = data.tag_tree();
+        let names = |nodes: &[TagNode]| -> Vec<String> {
+            nodes.iter().map(|node| node.name.clone()).collect()
+        };
+        // Missing parents make top-level tags; each level is in name order
+        assert_eq!(names(&tree), vec!["cooking", "orphan", "tech"]);
+        let tech = &tree[2];
+        assert_eq!(tech.color.as_deref(), Some("#336699"));
+        assert_eq!(names(&tech.children), vec!["ai", "Web"]);
+        let web = &tech.children[1];
+        assert_eq!(web.id, web_id);
+        assert_eq!(web.children[0].path, vec!["tech", "Web", "rust"]);
+        assert!(web.children[0].children.is_empty());
+        assert_eq!(web.counts, TagCounts::default());
+
+        let value = serde_json::to_value(&tree).unwrap();
+        assert!(value[0].get("color").is_none());
+    }
+
+    #[test]
+    fn test_lookups_follow_mutations() {
+        let mut data = BookmarksData::new();
+        let tag = create_tag("rust".to_string(), None, None);
+        let Resource::Tag { id: tag_id, .. } = &tag else {
+            panic!("Expected tag");
+        };
+        let tag_id = tag_id.clone();
+        data.add_tag(tag).unwrap();
+        let bookmark = create_bookmark(
+            "https://www.rust-lang.org".to_string(),
+            "Rust".to_string(),
+            vec![tag_id.clone()],
+        );
+        let Resource::Bookmark {
+            id: bookmark_id, ..
+        } = &bookmark
+        else {
+            panic!("Expected bookmark");
+        };
+        let bookmark_id = bookmark_id.clone();
+
+        // Build the maps before the bookmark exists
+        assert!(data.get(&bookmark_id).is_none());
+        assert!(data.bookmarks_with_tag(&tag_id).is_empty());
+
+        data.add_bookmark(bookmark).unwrap();
+        assert!(matches!(data.get(&tag_id), Some(Resource::Tag { .. })));
+        assert!(matches!(
+            data.get(&bookmark_id),
+            Some(Resource::Bookmark { .. })
+        ));
+        assert_eq!(data.bookmarks_with_tag(&tag_id).len(), 1);
+
+        data.data_mut().clear();
+        assert!(data.get(&bookmark_id).is_none());
+        assert!(data.bookmarks_with_tag(&tag_id).is_empty());
+
+        // The cache doesn't affect equality or serialization
+        let fresh = data.clone();
+        fresh.get(&tag_id);
+        assert_eq!(fresh, data);
+        assert!(!serde_json::to_string(&data).unwrap().contains("lookup"));
+    }
+
+    #[test]
+    fn test_custom_fields_round_trip() {
+        let json = r#"{
+            "jsonapi": {"version": "1.1"},
+            "data": [{
+                "type": "bookmark",
+                "id": "b1",
+                "attributes": {"url": "https://example.com", "title": "Example", "created": "2024-01-01T00:00:00Z"},
+                "meta": {"rating": 4, "read": true, "source": {"app": "reader", "ids": [1, 2]}}
+            }]
+        }"#;
+        let data: BookmarksData = serde_json::from_str(json).unwrap();
+        assert!(data.validate().is_ok());
+
+        let value = serde_json::to_value(&data).unwrap();
+        assert_eq!(value["data"][0]["meta"]["rating"], 4);
+        assert_eq!(value["data"][0]["meta"]["source"]["ids"][1], 2);
+        let reread: BookmarksData = serde_json::from_value(value).unwrap();
+        assert_eq!(reread, data);
+
+        // No meta is written for bookmarks without custom fields
+        let plain = create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec![],
+        );
+        assert!(serde_json::to_value(&plain).unwrap().get("meta").is_none());
+    }
+
+    #[test]
+    fn test_unread_queue() {
+        let mut data = BookmarksData::new();
+        let mut ids = Vec::new();
+        for (title, days_ago, unread) in
+            [("Newer", 1, true), ("Read", 3, false), ("Older", 2, true)]
+        {
+            let mut bookmark =
+                create_bookmark("https://example.com".to_string(), title.to_string(), vec![]);
+            if let Resource::Bookmark { id, attributes, .. } = &mut bookmark {
+                attributes.created = Utc::now() - chrono::Duration::days(days_ago);
+                attributes.unread = unread;
+                ids.push(id.clone());
+            }
+            data.add_bookmark(bookmark).unwrap();
+        }
+
+        let titles = |data: &BookmarksData| -> Vec<String> {
+            data.get_unread_bookmarks()
+                .into_iter()
+                .map(|r| match r {
+                    Resource::Bookmark { attributes, .. } => attributes.title.clone(),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        assert_eq!(titles(&data), vec!["Older", "Newer"]);
+
+        assert!(data.mark_read(&ids[2]).unwrap());
+        assert!(!data.mark_read(&ids[2]).unwrap());
+        assert_eq!(titles(&data), vec!["Newer"]);
+        assert!(data.mark_read("missing").is_err());
+
+        // Read bookmarks don't write the flag at all
+        let value = serde_json::to_value(&data).unwrap();
+        assert_eq!(value["data"][0]["attributes"]["unread"], true);
+        assert!(value["data"][1]["attributes"].get("unread").is_none());
+    }
 
     #[test]
-    fn test_new_bookmarks_data() {
-        let data = BookmarksData::new();
-        assert_eq!(data.jsonapi.version, "1.1");
-        assert!(data.data.is_empty());
-        assert!(data.included.is_none());
+    fn test_reorder_pinned() {
+        let mut data = BookmarksData::new();
+        let mut ids = Vec::new();
+        for (title, days_ago, pinned) in [("A", 3, true), ("B", 2, true), ("C", 1, false)] {
+            let mut bookmark =
+                create_bookmark("https://example.com".to_string(), title.to_string(), vec![]);
+            if let Resource::Bookmark { id, attributes, .. } = &mut bookmark {
+                attributes.created = Utc::now() - chrono::Duration::days(days_ago);
+                attributes.pinned = pinned;
+                ids.push(id.clone());
+            }
+            data.add_bookmark(bookmark).unwrap();
+        }
+
+        let titles = |data: &BookmarksData| -> Vec<String> {
+            data.get_pinned_bookmarks()
+                .into_iter()
+                .map(|r| match r {
+                    Resource::Bookmark { attributes, .. } => attributes.title.clone(),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        // Not placed yet, so oldest first
+        assert_eq!(titles(&data), vec!["A", "B"]);
+
+        assert!(data
+            .reorder_pinned(&[ids[1].clone(), ids[0].clone()])
+            .unwrap());
+        assert!(!data
+            .reorder_pinned(&[ids[1].clone(), ids[0].clone()])
+            .unwrap());
+        assert_eq!(titles(&data), vec!["B", "A"]);
+
+        // Newly pinned bookmarks go after the placed ones
+        if let Some(Resource::Bookmark { attributes, .. }) = data.data_mut().get_mut(2) {
+            attributes.pinned = true;
+        }
+        assert_eq!(titles(&data), vec!["B", "A", "C"]);
+
+        // The new order has to name every pinned bookmark, once
+        assert!(data
+            .reorder_pinned(&[ids[0].clone(), ids[1].clone()])
+            .is_err());
+        assert!(data
+            .reorder_pinned(&[ids[0].clone(), ids[0].clone(), ids[2].clone()])
+            .is_err());
+        assert!(data
+            .reorder_pinned(&[
+                ids[0].clone(),
+                ids[1].clone(),
+                ids[2].clone(),
+                "missing".to_string()
+            ])
+            .is_err());
+        assert_eq!(titles(&data), vec!["B", "A", "C"]);
     }
 
     #[test]
-    fn test_add_bookmark() {
+    fn test_saved_searches() {
         let mut data = BookmarksData::new();
-        let bookmark = create_bookmark(
-            "https://example.com".to_string(),
-            "Example".to_string(),
-            vec![],
+        let search = create_saved_search(
+            "Unread Rust".to_string(),
+            "tag:rust is:unread".to_string(),
+            SortOrder::Oldest,
         );
-        data.add_bookmark(bookmark).unwrap();
-        assert_eq!(data.data.len(), 1);
+        let id = search.id().to_string();
+        data.add_saved_search(search).unwrap();
+        assert!(data.validate().is_ok());
+        assert_eq!(data.get_saved_searches().len(), 1);
+        assert!(data.get_bookmarks().is_empty());
+
+        let value = serde_json::to_value(&data).unwrap();
+        assert_eq!(value["data"][0]["type"], "savedsearch");
+        assert_eq!(value["data"][0]["attributes"]["sort"], "oldest");
+        let reread: BookmarksData = serde_json::from_value(value).unwrap();
+        assert_eq!(reread, data);
+        assert!(matches!(
+            reread.get(&id),
+            Some(Resource::SavedSearch { .. })
+        ));
+
+        data.add_saved_search(create_saved_search(
+            "Broken".to_string(),
+            "is:starred".to_string(),
+            SortOrder::Newest,
+        ))
+        .unwrap();
+        assert!(data.validate().is_err());
+        assert!(data
+            .add_bookmark(create_saved_search(
+                String::new(),
+                String::new(),
+                SortOrder::Newest
+            ))
+            .is_err());
     }
 
     #[test]
-    fn test_add_tag() {
-        let mut data = BookmarksData::new();
-        let tag = create_tag("rust".to_string(), Some("#3b82f6".to_string()), None);
-        data.add_tag(tag).unwrap();
-        assert!(data.included.is_some());
-        assert_eq!(data.included.as_ref().unwrap().len(), 1);
+    fn test_custom_field_limits() {
+        let with_extra = |extra: serde_json::Value| {
+            let mut bookmark = create_bookmark(
+                "https://example.com".to_string(),
+                "Example".to_string(),
+                vec![],
+            );
+            if let Resource::Bookmark { extra: fields, .. } = &mut bookmark {
+                *fields = extra.as_object().unwrap().clone();
+            }
+            let mut data = BookmarksData::new();
+            data.add_bookmark(bookmark).unwrap();
+            data
+        };
+
+        assert!(with_extra(serde_json::json!({"read-status": "done"}))
+            .validate()
+            .is_ok());
+        assert!(with_extra(serde_json::json!({"bad key": 1}))
+            .validate()
+            .is_err());
+        assert!(
+            with_extra(serde_json::json!({"x": "a".repeat(MAX_EXTRA_SIZE)}))
+                .validate()
+                .is_err()
+        );
+        let too_many: serde_json::Map<_, _> = (0..=MAX_EXTRA_FIELDS)
+            .map(|i| (format!("field{i}"), serde_json::json!(i)))
+            .collect();
+        assert!(with_extra(serde_json::Value::Object(too_many))
+            .validate()
+            .is_err());
+
+        // Activity merged in by Read is reserved, and stripped before writing
+        let mut data = with_extra(serde_json::json!({"activity": {"visit_count": 3}, "rating": 5}));
+        assert!(data.validate().is_err());
+        data.remove_host_meta();
+        assert!(data.validate().is_ok());
+        let Resource::Bookmark { extra, .. } = &data.data()[0] else {
+            panic!("Expected bookmark");
+        };
+        assert_eq!(extra.keys().collect::<Vec<_>>(), vec!["rating"]);
     }
 
     #[test]
-    fn test_hierarchical_tags() {
+    fn test_configurable_length_limits() {
+        let long_url = format!("https://example.com/{}", "a".repeat(3000));
         let mut data = BookmarksData::new();
+        data.add_bookmark(create_bookmark(long_url, "x".repeat(600), vec![]))
+            .unwrap();
+        data.add_tag(create_tag("t".repeat(150), None, None))
+            .unwrap();
+        assert!(data.validate().is_err());
 
-        // Create parent tag
-        let parent_tag = create_tag("programming".to_string(), None, None);
-        let parent_id = if let Resource::Tag { id, .. } = &parent_tag {
-            id.clone()
-        } else {
-            panic!("Expected tag");
-        };
-        data.add_tag(parent_tag).unwrap();
+        let raised = ValidationConfig::default()
+            .with(Update {
+                max_url_length: Some(4096),
+                max_title_length: Some(1000),
+                max_tag_length: Some(200),
+                ..Update::default()
+            })
+            .unwrap();
+        assert!(data.validate_with(&raised).is_ok());
+        let lowered = raised
+            .with(Update {
+                max_tag_length: Some(120),
+                ..Update::default()
+            })
+            .unwrap();
+        assert!(data.validate_with(&lowered).is_err());
 
-        // Create child tag
-        let child_tag = create_tag("rust".to_string(), None, Some(parent_id.clone()));
-        data.add_tag(child_tag).unwrap();
+        // Files written under raised limits still read everywhere
+        let temp_file = NamedTempFile::new().unwrap();
+        write_to_file(temp_file.path(), &data).unwrap();
+        assert_eq!(read_from_file(temp_file.path()).unwrap().data.len(), 1);
 
-        let hierarchy = data.get_tag_hierarchy();
-        assert!(hierarchy.contains_key(&parent_id));
-        assert_eq!(hierarchy.get(&parent_id).unwrap().len(), 1);
+        let too_long = format!("https://example.com/{}", "a".repeat(40_000));
+        assert!(validate_bookmark_url(&too_long, &ValidationConfig::MAXIMUM).is_err());
     }
 
     #[test]
-    fn test_tag_breadcrumb() {
+    fn test_tag_depth_limit() {
         let mut data = BookmarksData::new();
+        let mut parent = None;
+        for name in ["a", "b", "c"] {
+            let tag = create_tag(name.to_string(), None, parent.take());
+            parent = Some(tag.id().to_string());
+            data.add_tag(tag).unwrap();
+        }
+        assert!(data.validate().is_ok());
 
-        // Create hierarchy: tech -> programming -> rust
-        let tech_tag = create_tag("tech".to_string(), None, None);
-        let tech_id = if let Resource::Tag { id, .. } = &tech_tag {
-            id.clone()
-        } else {
-            panic!("Expected tag");
-        };
-        data.add_tag(tech_tag).unwrap();
+        let shallow = ValidationConfig::default()
+            .with(Update {
+                max_tag_depth: Some(2),
+                ..Update::default()
+            })
+            .unwrap();
+        let err = data.validate_with(&shallow).unwrap_err();
+        assert!(err.to_string().contains("nested 3 levels deep"));
+    }
 
-        let prog_tag = create_tag("programming".to_string(), None, Some(tech_id.clone()));
-        let prog_id = if let Resource::Tag { id, .. } = &prog_tag {
-            id.clone()
-        } else {
-            panic!("Expected tag");
-        };
-        data.add_tag(prog_tag).unwrap();
+    #[test]
+    fn test_url_scheme_allowlist() {
+        let magnet = "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+        let defaults = ValidationConfig::default();
+        assert!(validate_bookmark_url(magnet, &defaults).is_err());
+
+        let allowed = defaults
+            .with(Update {
+                allowed_schemes: Some(vec!["magnet".to_string(), "obsidian".to_string()]),
+                ..Update::default()
+            })
+            .unwrap();
+        assert!(validate_bookmark_url(magnet, &allowed).is_ok());
+        assert!(validate_bookmark_url("obsidian://open?vault=notes", &allowed).is_ok());
+        assert!(validate_bookmark_url("ftp://example.com/file", &allowed).is_err());
+
+        // Never, whatever is allowed or however the file was written
+        for url in ["javascript:alert(1)", "data:text/html,<p>hi</p>"] {
+            assert!(validate_bookmark_url(url, &allowed).is_err());
+            assert!(validate_bookmark_url(url, &ValidationConfig::MAXIMUM).is_err());
+        }
+    }
 
-        let rust_tag = create_tag("rust".to_string(), None, Some(prog_id.clone()));
-        let rust_id = if let Resource::Tag { id, .. } = &rust_tag {
-            id.clone()
-        } else {
-            panic!("Expected tag");
+    #[test]
+    fn test_sanitize_notes() {
+        for (notes, expected) in [
+            (
+                "Plain **markdown** with a [link](https://example.com)",
+                None,
+            ),
+            ("2 < 3 and 5 > 4", None),
+            ("Before<script>alert(1)</script>After", Some("BeforeAfter")),
+            (
+                "<b>Bold</b> <img src=x onerror=alert(1)>text",
+                Some("Bold text"),
+            ),
+            ("<STYLE>body{}</style>Kept<!-- hidden -->", Some("Kept")),
+            ("Unclosed <iframe src=evil>rest", Some("Unclosed ")),
+            (
+                "[click](javascript:alert(1)) [ok](https://a.b)",
+                Some("[click](#)) [ok](https://a.b)"),
+            ),
+        ] {
+            let sanitized = sanitize_notes(notes);
+            assert_eq!(sanitized, expected.unwrap_or(notes), "{notes}");
+            assert_eq!(matches!(sanitized, Cow::Borrowed(_)), expected.is_none());
+        }
+    }
+
+    #[test]
+    fn test_notes_validation_and_update() {
+        let mut data = BookmarksData::new();
+        let bookmark = create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec![],
+        );
+        let id = bookmark.id().to_string();
+        data.add_bookmark(bookmark).unwrap();
+        let set_notes = |data: &mut BookmarksData, notes: String| {
+            if let Resource::Bookmark { attributes, .. } = &mut data.data_mut()[0] {
+                attributes.notes = Some(notes);
+            }
         };
-        data.add_tag(rust_tag).unwrap();
 
-        let breadcrumb = data.get_tag_breadcrumb(&rust_id);
-        assert_eq!(breadcrumb, vec!["tech", "programming", "rust"]);
+        set_notes(&mut data, "x".repeat(MAX_NOTES_LENGTH + 1));
+        assert!(data.validate().is_err());
+        set_notes(&mut data, "<script>alert(1)</script>Fine".to_string());
+        assert!(data.validate().is_err());
+        data.sanitize_notes();
+        assert!(data.validate().is_ok());
+
+        assert!(data
+            .update_bookmark(&id, None, Some("<b>New</b> note".to_string()))
+            .unwrap());
+        let Some(Resource::Bookmark { attributes, .. }) = data.get(&id) else {
+            panic!("Expected bookmark");
+        };
+        assert_eq!(attributes.notes.as_deref(), Some("New note"));
+        assert!(attributes.modified.is_some());
+        assert!(!data
+            .update_bookmark(&id, Some("Example".to_string()), None)
+            .unwrap());
+        assert!(data
+            .update_bookmark(&id, None, Some("  ".to_string()))
+            .unwrap());
+        assert!(matches!(
+            data.get(&id),
+            Some(Resource::Bookmark { attributes, .. }) if attributes.notes.is_none()
+        ));
+        assert!(data.update_bookmark("missing", None, None).is_err());
     }
 
     #[test]
@@ -552,8 +3052,20 @@ mod tests {
                 created: Utc::now(),
                 modified: None,
                 notes: None,
+                private: false,
+                unread: false,
+                pinned: false,
+                pin_order: None,
+                author: None,
+                visibility: None,
+                archive_url: None,
+                enrichment: None,
+                suggested_tags: Vec::new(),
+                expires_at: None,
+                unknown: UnknownFields::new(),
             },
             relationships: None,
+            extra: serde_json::Map::new(),
         };
         let bookmark2 = Resource::Bookmark {
             id: "same-id".to_string(),
@@ -563,12 +3075,24 @@ mod tests {
                 created: Utc::now(),
                 modified: None,
                 notes: None,
+                private: false,
+                unread: false,
+                pinned: false,
+                pin_order: None,
+                author: None,
+                visibility: None,
+                archive_url: None,
+                enrichment: None,
+                suggested_tags: Vec::new(),
+                expires_at: None,
+                unknown: UnknownFields::new(),
             },
             relationships: None,
+            extra: serde_json::Map::new(),
         };
 
-        data.data.push(bookmark1);
-        data.data.push(bookmark2);
+        data.data_mut().push(bookmark1);
+        data.data_mut().push(bookmark2);
 
         assert!(data.validate().is_err());
     }
@@ -617,6 +3141,21 @@ mod tests {
         assert_eq!(read_data.data.len(), 1);
     }
 
+    #[test]
+    fn test_unversioned_file_is_migrated_on_load() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path();
+        fs::write(path, r#"{"jsonapi": {"version": "1.1"}, "data": []}"#).unwrap();
+
+        let loaded = load(path, false).unwrap();
+        assert!(loaded.was_migrated());
+        assert_eq!(loaded.stored_version, 1);
+        assert_eq!(loaded.data.meta.schema_version, CURRENT_SCHEMA_VERSION);
+
+        write_to_file(path, &loaded.data).unwrap();
+        assert!(!load(path, false).unwrap().was_migrated());
+    }
+
     #[test]
     fn test_atomic_write() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -663,6 +3202,8 @@ mod tests {
                 name: "Tag 1".to_string(),
                 color: None,
                 description: None,
+                visibility: None,
+                unknown: UnknownFields::new(),
             },
             relationships: Some(TagRelationships {
                 parent: Some(ParentRelationship {
@@ -680,6 +3221,8 @@ mod tests {
                 name: "Tag 2".to_string(),
                 color: None,
                 description: None,
+                visibility: None,
+                unknown: UnknownFields::new(),
             },
             relationships: Some(TagRelationships {
                 parent: Some(ParentRelationship {
@@ -698,4 +3241,554 @@ mod tests {
         let breadcrumb = data.get_tag_breadcrumb("tag1");
         assert!(!breadcrumb.is_empty());
     }
+
+    #[test]
+    fn test_merge_duplicate_tags() {
+        let named = |id: &str, name: &str, parent: Option<&str>, color: Option<&str>| {
+            let mut tag = match parent {
+                Some(parent) => tag_with_parent(id, parent),
+                None => create_tag(String::new(), None, None),
+            };
+            if let Resource::Tag {
+                id: tag_id,
+                attributes,
+                ..
+            } = &mut tag
+            {
+                *tag_id = id.to_string();
+                attributes.name = name.to_string();
+                attributes.color = color.map(str::to_string);
+            }
+            tag
+        };
+        let mut data = BookmarksData::new();
+        data.add_tag(named("t1", "Café", None, None)).unwrap();
+        data.add_tag(named("t2", "CAFE\u{301}", None, Some("#ff0000")))
+            .unwrap();
+        data.add_tag(named("t3", "Beans", Some("t1"), None))
+            .unwrap();
+        data.add_tag(named("t4", "beans", Some("t2"), None))
+            .unwrap();
+        data.add_tag(named("t5", "Cafe", None, None)).unwrap();
+        data.add_bookmark(create_bookmark(
+            "https://example.com".to_string(),
+            "Cafe\u{301} guide".to_string(),
+            vec!["t2".to_string(), "t1".to_string(), "t4".to_string()],
+        ))
+        .unwrap();
+        data.add_rule(create_rule(
+            "Beans".to_string(),
+            RuleField::Domain,
+            MatchKind::Equals,
+            "example.com".to_string(),
+            vec!["t4".to_string()],
+        ))
+        .unwrap();
+
+        // Titles are NFC from the start; tag names set directly aren't
+        assert_eq!(data.normalize_unicode(), 1);
+        let merged = data.merge_duplicate_tags();
+        assert_eq!(
+            merged,
+            vec![
+                MergedTags {
+                    kept: "t1".to_string(),
+                    name: "Café".to_string(),
+                    merged: vec!["t2".to_string()],
+                },
+                MergedTags {
+                    kept: "t3".to_string(),
+                    name: "Beans".to_string(),
+                    merged: vec!["t4".to_string()],
+                },
+            ]
+        );
+        assert!(data.validate().is_ok());
+        let names: Vec<String> = data
+            .get_tags()
+            .iter()
+            .map(|tag| tag.id().to_string())
+            .collect();
+        assert_eq!(names, vec!["t1", "t3", "t5"]);
+        match data.get("t1") {
+            Some(Resource::Tag { attributes, .. }) => {
+                assert_eq!(attributes.color.as_deref(), Some("#ff0000"));
+            }
+            _ => unreachable!(),
+        }
+        let bookmark = data.get_bookmarks()[0].id().to_string();
+        assert_eq!(data.bookmarks_with_tag("t1")[0].id(), bookmark);
+        assert_eq!(data.bookmarks_with_tag("t3")[0].id(), bookmark);
+        assert_eq!(data.get_tag_breadcrumb("t3"), vec!["Café", "Beans"]);
+        assert!(data.merge_duplicate_tags().is_empty());
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let mut data = BookmarksData::new();
+        let tag = create_tag("Rust".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        data.add_tag(tag).unwrap();
+        let first = create_bookmark(
+            "https://example.com/1".to_string(),
+            "First".to_string(),
+            vec![tag_id.clone()],
+        );
+        let second = create_bookmark(
+            "https://example.com/2".to_string(),
+            "Second".to_string(),
+            vec![],
+        );
+        let (first_id, second_id) = (first.id().to_string(), second.id().to_string());
+        data.add_bookmark(first.clone()).unwrap();
+        data.add_bookmark(second).unwrap();
+
+        let mut renamed = first.clone();
+        if let Resource::Bookmark { attributes, .. } = &mut renamed {
+            attributes.title = "First, renamed".to_string();
+        }
+        let third = create_bookmark(
+            "https://example.com/3".to_string(),
+            "Third".to_string(),
+            vec![tag_id.clone()],
+        );
+        let third_id = third.id().to_string();
+        let delta = |added: Vec<Resource>, updated: Vec<Resource>, removed: Vec<&str>| Delta {
+            added,
+            updated,
+            removed: removed.into_iter().map(str::to_string).collect(),
+        };
+
+        // Nothing changes unless every part of the delta applies
+        let before = data.clone();
+        let tag = data.get(&tag_id).unwrap().clone();
+        for bad in [
+            delta(vec![first.clone()], vec![], vec![]),
+            delta(vec![], vec![third.clone()], vec![]),
+            delta(vec![], vec![], vec!["missing"]),
+            delta(vec![], vec![renamed.clone()], vec![&first_id]),
+            delta(vec![third.clone(), third.clone()], vec![], vec![]),
+        ] {
+            assert!(data.apply_delta(bad).is_err());
+            assert_eq!(data, before);
+        }
+        let mut retyped = tag;
+        if let Resource::Tag { id, .. } = &mut retyped {
+            id.clone_from(&second_id);
+        }
+        assert!(data
+            .apply_delta(delta(vec![], vec![retyped], vec![]))
+            .is_err());
+
+        data.apply_delta(delta(vec![third], vec![renamed], vec![&second_id]))
+            .unwrap();
+        let ids: Vec<&str> = data.get_bookmarks().iter().map(|b| b.id()).collect();
+        assert_eq!(ids, vec![first_id.as_str(), third_id.as_str()]);
+        match data.get(&first_id) {
+            Some(Resource::Bookmark { attributes, .. }) => {
+                assert_eq!(attributes.title, "First, renamed");
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(data.bookmarks_with_tag(&tag_id).len(), 2);
+        assert!(data.validate().is_ok());
+    }
+
+    #[test]
+    fn test_stamp_author() {
+        let mut previous = BookmarksData::new();
+        previous
+            .add_bookmark(create_bookmark(
+                "https://old.example".to_string(),
+                "Old".to_string(),
+                vec![],
+            ))
+            .unwrap();
+        let mut data = previous.clone();
+        let mut credited = create_bookmark(
+            "https://credited.example".to_string(),
+            "Credited".to_string(),
+            vec![],
+        );
+        if let Resource::Bookmark { attributes, .. } = &mut credited {
+            attributes.author = Some("Sam".to_string());
+        }
+        data.add_bookmark(credited).unwrap();
+        data.add_bookmark(create_bookmark(
+            "https://new.example".to_string(),
+            "New".to_string(),
+            vec![],
+        ))
+        .unwrap();
+
+        assert_eq!(data.stamp_author("Alex", Some(&previous)), 1);
+        let authors: Vec<Option<&str>> = data
+            .get_bookmarks()
+            .iter()
+            .map(|bookmark| match bookmark {
+                Resource::Bookmark { attributes, .. } => attributes.author.as_deref(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(authors, vec![None, Some("Sam"), Some("Alex")]);
+        assert_eq!(data.stamp_author("Alex", None), 1);
+    }
+
+    #[test]
+    fn test_visibility_and_public_subset() {
+        let with_visibility = |resource: &mut Resource, visibility| match resource {
+            Resource::Bookmark { attributes, .. } => attributes.visibility = Some(visibility),
+            Resource::Tag { attributes, .. } => attributes.visibility = Some(visibility),
+            _ => unreachable!(),
+        };
+        let mut data = BookmarksData::new();
+        let mut public = create_tag("Public".to_string(), None, None);
+        with_visibility(&mut public, Visibility::Public);
+        let public_id = public.id().to_string();
+        let mut secret = create_tag("Secret".to_string(), None, None);
+        with_visibility(&mut secret, Visibility::Private);
+        let secret_id = secret.id().to_string();
+        let child = create_tag("Child".to_string(), None, Some(public_id.clone()));
+        let child_id = child.id().to_string();
+        data.add_tag(public).unwrap();
+        data.add_tag(secret).unwrap();
+        data.add_tag(child).unwrap();
+
+        let bookmark = |url: &str, tags: &[&str]| {
+            let tags = tags.iter().map(ToString::to_string).collect();
+            create_bookmark(url.to_string(), url.to_string(), tags)
+        };
+        let mut by_tag = bookmark("https://tagged.example", &[&public_id, &child_id]);
+        if let Resource::Bookmark { attributes, .. } = &mut by_tag {
+            attributes.notes = Some("Just for me".to_string());
+            attributes.author = Some("Sam".to_string());
+        }
+        let mut own = bookmark("https://own.example", &[]);
+        with_visibility(&mut own, Visibility::Public);
+        let mut shared = bookmark("https://shared.example", &[&public_id]);
+        with_visibility(&mut shared, Visibility::Shared);
+        let hidden = bookmark("https://hidden.example", &[&public_id, &secret_id]);
+        let mut marked = bookmark("https://marked.example", &[&public_id]);
+        if let Resource::Bookmark { attributes, .. } = &mut marked {
+            attributes.private = true;
+        }
+        for resource in [by_tag, own, shared, hidden, marked] {
+            data.add_bookmark(resource).unwrap();
+        }
+
+        let visibilities: Vec<Visibility> = data
+            .get_bookmarks()
+            .into_iter()
+            .map(|bookmark| data.visibility(bookmark))
+            .collect();
+        assert_eq!(
+            visibilities,
+            vec![
+                Visibility::Public,
+                Visibility::Public,
+                Visibility::Shared,
+                Visibility::Private,
+                Visibility::Private,
+            ]
+        );
+
+        let published = data.public_subset();
+        published.validate().unwrap();
+        assert_eq!(published.get_bookmarks().len(), 2);
+        assert_eq!(published.get_tags().len(), 1);
+        let Some(Resource::Bookmark {
+            attributes,
+            relationships,
+            ..
+        }) = published.data().first()
+        else {
+            panic!("expected the tagged bookmark first");
+        };
+        assert_eq!(attributes.notes, None);
+        assert_eq!(attributes.author, None);
+        // The child tag isn't public itself, so the reference to it is dropped
+        let tags = &relationships.as_ref().unwrap().tags.as_ref().unwrap().data;
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].id, public_id);
+    }
+
+    fn tag_with_parent(id: &str, parent: &str) -> Resource {
+        Resource::Tag {
+            id: id.to_string(),
+            attributes: TagAttributes {
+                name: id.to_string(),
+                color: None,
+                description: None,
+                visibility: None,
+                unknown: UnknownFields::new(),
+            },
+            relationships: Some(TagRelationships {
+                parent: Some(ParentRelationship {
+                    data: Some(ResourceIdentifier {
+                        resource_type: "tag".to_string(),
+                        id: parent.to_string(),
+                    }),
+                }),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_validate_rejects_broken_references() {
+        let mut data = BookmarksData::new();
+        data.add_tag(create_tag("root".to_string(), None, None))
+            .unwrap();
+        data.add_tag(tag_with_parent("orphan", "deleted")).unwrap();
+        data.add_tag(tag_with_parent("a", "b")).unwrap();
+        data.add_tag(tag_with_parent("b", "a")).unwrap();
+        let bookmark = create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec!["a".to_string(), "gone".to_string()],
+        );
+        let Resource::Bookmark {
+            id: bookmark_id, ..
+        } = &bookmark
+        else {
+            panic!("Expected bookmark");
+        };
+        let bookmark_id = bookmark_id.clone();
+        data.add_bookmark(bookmark).unwrap();
+
+        assert_eq!(
+            data.reference_problems(),
+            vec![
+                ReferenceProblem::MissingTag {
+                    bookmark_id,
+                    tag_id: "gone".to_string(),
+                },
+                ReferenceProblem::MissingParent {
+                    tag_id: "orphan".to_string(),
+                    parent_id: "deleted".to_string(),
+                },
+                ReferenceProblem::Cycle {
+                    tag_id: "b".to_string(),
+                    parent_id: "a".to_string(),
+                },
+            ]
+        );
+        let err = data.validate().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReferenceError>().unwrap().problems.len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_repair_fixes_broken_references() {
+        let mut data = BookmarksData::new();
+        data.add_tag(tag_with_parent("orphan", "deleted")).unwrap();
+        data.add_tag(tag_with_parent("a", "b")).unwrap();
+        data.add_tag(tag_with_parent("b", "c")).unwrap();
+        data.add_tag(tag_with_parent("c", "a")).unwrap();
+        data.add_bookmark(create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec!["a".to_string(), "gone".to_string()],
+        ))
+        .unwrap();
+        data.add_rule(create_rule(
+            "Example".to_string(),
+            RuleField::Domain,
+            MatchKind::Equals,
+            "example.com".to_string(),
+            vec!["a".to_string(), "gone".to_string()],
+        ))
+        .unwrap();
+
+        assert!(data
+            .reference_problems()
+            .contains(&ReferenceProblem::RuleMissingTag {
+                rule_id: data.get_rules()[0].id().to_string(),
+                tag_id: "gone".to_string(),
+            }));
+        assert_eq!(data.repair().len(), 4);
+        assert!(data.validate().is_ok());
+        assert!(data.repair().is_empty());
+
+        // Only the link closing the cycle was cut
+        assert_eq!(data.get_tag_breadcrumb("a"), vec!["c", "b", "a"]);
+        assert_eq!(data.get_tag_breadcrumb("orphan"), vec!["orphan"]);
+        assert_eq!(data.bookmarks_with_tag("a").len(), 1);
+        assert!(data.bookmarks_with_tag("gone").is_empty());
+    }
+
+    fn field_test_data() -> BookmarksData {
+        let mut data = BookmarksData::new();
+        let mut public = create_bookmark(
+            "https://example.com/public".to_string(),
+            "Public".to_string(),
+            vec![],
+        );
+        if let Resource::Bookmark { attributes, .. } = &mut public {
+            attributes.notes = Some("secret note".to_string());
+        }
+        let mut private = create_bookmark(
+            "https://example.com/private".to_string(),
+            "Private".to_string(),
+            vec![],
+        );
+        if let Resource::Bookmark { attributes, .. } = &mut private {
+            attributes.private = true;
+            attributes.unread = true;
+            attributes.archive_url =
+                Some("https://web.archive.org/web/2024/https://example.com/private".to_string());
+        }
+        data.add_bookmark(public).unwrap();
+        data.add_bookmark(private).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_seal_fields_roundtrip() {
+        let manager = EncryptionManager::with_key(&[3u8; 32]);
+        let data = field_test_data();
+
+        let value = seal_fields(&data, &manager, "bookmarks.json", None).unwrap();
+        assert!(is_field_encrypted(&value));
+
+        let json = value.to_string();
+        assert!(json.contains("https://example.com/public"));
+        assert!(!json.contains("secret note"));
+        assert!(!json.contains("https://example.com/private"));
+        assert!(value["data"][1]["attributes"].get("archive_url").is_none());
+        assert_eq!(value["data"][1]["attributes"]["private"], true);
+        assert_eq!(value["data"][1]["attributes"]["unread"], true);
+
+        assert_eq!(
+            open_fields(value, &manager, "bookmarks.json").unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields_survive_round_trip() {
+        let json = serde_json::json!({
+            "jsonapi": {"version": "1.1"},
+            "meta": {"schema_version": CURRENT_SCHEMA_VERSION, "origin": "other-tool"},
+            "data": [{
+                "type": "bookmark",
+                "id": "b1",
+                "attributes": {
+                    "url": "https://example.com",
+                    "title": "Example",
+                    "created": "2026-01-01T00:00:00Z",
+                    "favicon": {"href": "/favicon.ico"},
+                },
+            }],
+            "included": [{
+                "type": "tag",
+                "id": "t1",
+                "attributes": {"name": "rust", "icon": "crab"},
+            }],
+        });
+        let data: BookmarksData = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(
+            data.unknown_fields(),
+            vec![
+                "meta.origin",
+                "bookmark b1: attributes.favicon",
+                "tag t1: attributes.icon",
+            ]
+        );
+        assert_eq!(serde_json::to_value(&data).unwrap(), json);
+
+        // Sealed and opened again in field-level mode, too
+        let manager = EncryptionManager::with_key(&[3u8; 32]);
+        let sealed = seal_fields(&data, &manager, "bookmarks.json", None).unwrap();
+        assert_eq!(
+            sealed["data"][0]["attributes"]["favicon"]["href"],
+            "/favicon.ico"
+        );
+        assert_eq!(
+            open_fields(sealed, &manager, "bookmarks.json").unwrap(),
+            data
+        );
+
+        assert!(field_test_data().unknown_fields().is_empty());
+    }
+
+    #[test]
+    fn test_seal_fields_reuses_unchanged_envelopes() {
+        let manager = EncryptionManager::with_key(&[3u8; 32]);
+        let mut data = field_test_data();
+        let first = seal_fields(&data, &manager, "bookmarks.json", None).unwrap();
+
+        let second = seal_fields(&data, &manager, "bookmarks.json", Some(&first)).unwrap();
+        assert_eq!(first, second);
+
+        if let Resource::Bookmark { attributes, .. } = &mut data.data_mut()[0] {
+            attributes.notes = Some("changed".to_string());
+        }
+        let third = seal_fields(&data, &manager, "bookmarks.json", Some(&first)).unwrap();
+        assert_ne!(
+            first["data"][0]["attributes"]["notes"],
+            third["data"][0]["attributes"]["notes"]
+        );
+        assert_eq!(
+            first["data"][1]["attributes"]["sealed"],
+            third["data"][1]["attributes"]["sealed"]
+        );
+    }
+
+    #[test]
+    fn test_seal_fields_reencrypts_on_cipher_change() {
+        let data = field_test_data();
+        let aes = EncryptionManager::with_key(&[3u8; 32]);
+        let first = seal_fields(&data, &aes, "bookmarks.json", None).unwrap();
+
+        let xchacha =
+            EncryptionManager::with_key(&[3u8; 32]).with_cipher(Cipher::XChaCha20Poly1305);
+        let second = seal_fields(&data, &xchacha, "bookmarks.json", Some(&first)).unwrap();
+        assert_eq!(
+            second["data"][0]["attributes"]["notes"]["algorithm"],
+            "XChaCha20-Poly1305"
+        );
+        assert_eq!(open_fields(second, &aes, "bookmarks.json").unwrap(), data);
+    }
+
+    #[test]
+    fn test_swapped_envelopes_fail_to_open() {
+        let manager = EncryptionManager::with_key(&[3u8; 32]);
+        let mut data = field_test_data();
+        if let Resource::Bookmark { attributes, .. } = &mut data.data_mut()[1] {
+            attributes.private = false;
+            attributes.notes = Some("other note".to_string());
+        }
+        let mut value = seal_fields(&data, &manager, "bookmarks.json", None).unwrap();
+
+        let notes = value["data"][0]["attributes"]["notes"].take();
+        value["data"][0]["attributes"]["notes"] = value["data"][1]["attributes"]["notes"].take();
+        value["data"][1]["attributes"]["notes"] = notes;
+
+        assert!(open_fields(value, &manager, "bookmarks.json").is_err());
+    }
+
+    #[test]
+    fn test_field_encrypted_file_requires_encryption() {
+        let manager = EncryptionManager::with_key(&[3u8; 32]);
+        let value = seal_fields(&field_test_data(), &manager, "bookmarks.json", None).unwrap();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(temp_file.path(), value.to_string()).unwrap();
+
+        assert_eq!(
+            detect_encryption(temp_file.path()).unwrap(),
+            Some(EncryptionMode::Fields)
+        );
+        let err = read_from_file(temp_file.path()).unwrap_err();
+        assert!(err.to_string().contains("encryption is not enabled"));
+    }
+
+    #[test]
+    fn test_detect_encryption_plain() {
+        let temp_file = NamedTempFile::new().unwrap();
+        write_to_file(temp_file.path(), &field_test_data()).unwrap();
+        assert_eq!(detect_encryption(temp_file.path()).unwrap(), None);
+    }
 }
@@ -0,0 +1,517 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::bulk;
+use crate::index::tokenize;
+use crate::rules::domain;
+use crate::storage::{self, BookmarksData, Resource};
+use crate::unicode;
+use crate::validation::ValidationConfig;
+
+/// Suggestions `SuggestTags` returns when the client doesn't ask for a number
+pub const DEFAULT_SUGGESTIONS: usize = 5;
+
+/// Upper bound on suggestions `SuggestTags` will return
+pub const MAX_SUGGESTIONS: usize = 50;
+
+/// A shared domain says more about a bookmark's topic than any one word
+const DOMAIN_WEIGHT: f64 = 2.0;
+
+/// A tag whose own name appears in the title is a strong hint
+const TAG_NAME_WEIGHT: f64 = 3.0;
+
+/// Tags suggested from one page's text
+pub const CONTENT_SUGGESTIONS: usize = 5;
+
+/// Keywords kept per page
+const MAX_KEYWORDS: usize = 20;
+
+/// A word in the page title counts as often as this many in the body
+const TITLE_WEIGHT: usize = 3;
+
+/// Body words must appear this often to say anything about the page
+const MIN_KEYWORD_COUNT: usize = 3;
+
+/// Common English words that say nothing about what a page is about
+const STOPWORDS: &[&str] = &[
+    "about", "after", "again", "all", "also", "and", "any", "are", "because", "been", "before",
+    "being", "between", "both", "but", "can", "could", "did", "does", "doing", "down", "during",
+    "each", "even", "few", "for", "from", "get", "had", "has", "have", "having", "her", "here",
+    "hers", "him", "his", "how", "into", "its", "just", "like", "more", "most", "much", "must",
+    "new", "not", "now", "off", "once", "one", "only", "other", "our", "out", "over", "own",
+    "same", "see", "she", "should", "some", "such", "than", "that", "the", "their", "them", "then",
+    "there", "these", "they", "this", "those", "through", "too", "two", "under", "until", "use",
+    "used", "using", "very", "was", "way", "were", "what", "when", "where", "which", "while",
+    "who", "whom", "why", "will", "with", "would", "you", "your", "yours",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub tag_id: String,
+    pub name: String,
+    pub score: f64,
+}
+
+/// What a bookmark is compared by: its domain and the words in its title
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Feature {
+    Domain(String),
+    Word(String),
+}
+
+impl Feature {
+    fn weight(&self) -> f64 {
+        match self {
+            Feature::Domain(_) => DOMAIN_WEIGHT,
+            Feature::Word(_) => 1.0,
+        }
+    }
+}
+
+fn features(url: &str, title: &str) -> HashSet<Feature> {
+    let mut features: HashSet<Feature> = tokenize(title)
+        // Too short to say anything about the topic
+        .filter(|word| word.chars().count() > 2)
+        .map(Feature::Word)
+        .collect();
+    if let Some(domain) = domain(url) {
+        let domain = domain.strip_prefix("www.").unwrap_or(&domain).to_string();
+        features.insert(Feature::Domain(domain));
+    }
+    features
+}
+
+/// Tags for a new bookmark, best first.
+///
+/// Every tagged bookmark that shares features with the new one votes for its
+/// tags, weighted by how rare the shared features are across the collection
+/// (inverse document frequency), so a shared domain or an unusual word counts
+/// for more than a common one. Tags whose names appear in the title get a
+/// bonus on top.
+pub fn suggest_tags(data: &BookmarksData, url: &str, title: &str, limit: usize) -> Vec<Suggestion> {
+    let wanted = features(url, title);
+    // Short words are no use for comparing bookmarks, but tags like "go" are
+    let title_words: HashSet<String> = tokenize(title).collect();
+
+    let tagged: Vec<(HashSet<Feature>, Vec<&str>)> = data
+        .get_bookmarks()
+        .into_iter()
+        .filter_map(|bookmark| match bookmark {
+            Resource::Bookmark {
+                attributes,
+                relationships,
+                ..
+            } => {
+                let tags: Vec<&str> = relationships
+                    .as_ref()?
+                    .tags
+                    .as_ref()?
+                    .data
+                    .iter()
+                    .map(|tag| tag.id.as_str())
+                    .collect();
+                (!tags.is_empty()).then(|| (features(&attributes.url, &attributes.title), tags))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut document_frequency: HashMap<&Feature, usize> = HashMap::new();
+    for (features, _) in &tagged {
+        for feature in features.iter().filter(|f| wanted.contains(f)) {
+            *document_frequency.entry(feature).or_default() += 1;
+        }
+    }
+    #[allow(clippy::cast_precision_loss)] // collections never get near 2^52 bookmarks
+    let idf = |feature: &Feature| {
+        let df = document_frequency.get(feature).copied().unwrap_or(0);
+        (tagged.len() as f64 / (1 + df) as f64).ln() + 1.0
+    };
+
+    let mut scores: HashMap<&str, f64> = HashMap::new();
+    for (features, tags) in &tagged {
+        let similarity: f64 = features
+            .intersection(&wanted)
+            .map(|feature| feature.weight() * idf(feature))
+            .sum();
+        if similarity > 0.0 {
+            for tag in tags {
+                *scores.entry(tag).or_default() += similarity;
+            }
+        }
+    }
+
+    let mut suggestions: Vec<Suggestion> = data
+        .get_tags()
+        .into_iter()
+        .filter_map(|tag| match tag {
+            Resource::Tag { id, attributes, .. } => {
+                let mut score = scores.get(id.as_str()).copied().unwrap_or(0.0);
+                let name_words: Vec<String> = tokenize(&attributes.name).collect();
+                if !name_words.is_empty() && name_words.iter().all(|w| title_words.contains(w)) {
+                    score += TAG_NAME_WEIGHT
+                        * name_words
+                            .into_iter()
+                            .map(|word| idf(&Feature::Word(word)))
+                            .sum::<f64>();
+                }
+                (score > 0.0).then(|| Suggestion {
+                    tag_id: id.clone(),
+                    name: attributes.name.clone(),
+                    score,
+                })
+            }
+            _ => None,
+        })
+        .collect();
+
+    suggestions.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    suggestions.truncate(limit);
+    suggestions
+}
+
+/// The words that best describe a page, most telling first: frequent words
+/// in its title and text, leaving out numbers, short words, and stopwords
+pub fn keywords(title: &str, text: &str) -> Vec<String> {
+    let useful = |word: &String| {
+        word.chars().count() > 2
+            && !word.chars().all(|c| c.is_ascii_digit())
+            && !STOPWORDS.contains(&word.as_str())
+    };
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let title_words: HashSet<String> = tokenize(title).filter(useful).collect();
+    for word in &title_words {
+        *counts.entry(word.clone()).or_default() += TITLE_WEIGHT;
+    }
+    for word in tokenize(text).filter(useful) {
+        *counts.entry(word).or_default() += 1;
+    }
+
+    let mut keywords: Vec<(String, usize)> = counts
+        .into_iter()
+        .filter(|(word, count)| title_words.contains(word) || *count >= MIN_KEYWORD_COUNT)
+        .collect();
+    keywords.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    keywords.truncate(MAX_KEYWORDS);
+    keywords.into_iter().map(|(word, _)| word).collect()
+}
+
+/// Tag names to suggest for bookmark `bookmark_id` from its page's
+/// `keywords`: existing tags whose names are among the keywords first, then
+/// the top keywords as new tags. Tags the bookmark already has are left out.
+pub fn content_suggestions(
+    data: &BookmarksData,
+    bookmark_id: &str,
+    keywords: &[String],
+    limit: usize,
+) -> Vec<String> {
+    let has: HashSet<String> = match data.get(bookmark_id) {
+        Some(Resource::Bookmark {
+            relationships: Some(relationships),
+            ..
+        }) => relationships
+            .tags
+            .iter()
+            .flat_map(|tags| &tags.data)
+            .filter_map(|tag| match data.get(&tag.id) {
+                Some(Resource::Tag { attributes, .. }) => Some(unicode::fold(&attributes.name)),
+                _ => None,
+            })
+            .collect(),
+        _ => HashSet::new(),
+    };
+    let rank: HashMap<&str, usize> = keywords
+        .iter()
+        .enumerate()
+        .map(|(i, word)| (word.as_str(), i))
+        .collect();
+
+    // Existing tags whose every word is a keyword, ranked by their best word
+    let mut existing: Vec<(usize, String)> = data
+        .get_tags()
+        .into_iter()
+        .filter_map(|tag| match tag {
+            Resource::Tag { attributes, .. } if !has.contains(&unicode::fold(&attributes.name)) => {
+                let words: Vec<String> = tokenize(&attributes.name).collect();
+                let ranks: Option<Vec<usize>> = words
+                    .iter()
+                    .map(|w| rank.get(w.as_str()).copied())
+                    .collect();
+                let best = ranks.filter(|r| !r.is_empty())?.into_iter().min()?;
+                Some((best, attributes.name.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    existing.sort();
+
+    let mut suggestions: Vec<String> = Vec::new();
+    let mut seen: HashSet<String> = has;
+    let new_tags = keywords.iter().map(|word| (0, word.clone()));
+    for (_, name) in existing.into_iter().chain(new_tags) {
+        if suggestions.len() == limit {
+            break;
+        }
+        if seen.insert(unicode::fold(&name)) {
+            suggestions.push(name);
+        }
+    }
+    suggestions
+}
+
+/// What [`review`] did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Review {
+    /// Tag names now on the bookmark
+    pub accepted: Vec<String>,
+    /// Of those, tags that had to be created
+    pub created: Vec<String>,
+    pub dismissed: Vec<String>,
+}
+
+/// Accept or dismiss suggested tags on bookmark `id`. Accepted names get an
+/// existing top-level tag of that name (case-insensitive), created if there
+/// is none; both are removed from the suggestions. Every name must be one
+/// of the bookmark's suggestions.
+pub fn review(
+    data: &mut BookmarksData,
+    id: &str,
+    accept: &[String],
+    dismiss: &[String],
+    limits: &ValidationConfig,
+) -> Result<Review> {
+    let Some(Resource::Bookmark { attributes, .. }) = data.get(id) else {
+        anyhow::bail!("No bookmark with ID {id}");
+    };
+    let suggested: HashSet<String> = attributes
+        .suggested_tags
+        .iter()
+        .map(|name| unicode::fold(name))
+        .collect();
+    if let Some(name) = accept
+        .iter()
+        .chain(dismiss)
+        .find(|name| !suggested.contains(&unicode::fold(name)))
+    {
+        anyhow::bail!("'{name}' is not a suggested tag of bookmark {id}");
+    }
+
+    let mut review = Review::default();
+    let mut tag_ids = Vec::new();
+    for name in accept {
+        let tag_id = if let Some(tag_id) = bulk::find_top_level_tag(data, name) {
+            tag_id
+        } else {
+            storage::validate_tag_name(name, limits)?;
+            let tag = storage::create_tag(name.clone(), None, None);
+            let tag_id = tag.id().to_string();
+            data.add_tag(tag)?;
+            review.created.push(name.clone());
+            tag_id
+        };
+        tag_ids.push(tag_id);
+        review.accepted.push(name.clone());
+    }
+    review.dismissed = dismiss.to_vec();
+
+    let reviewed: HashSet<String> = accept
+        .iter()
+        .chain(dismiss)
+        .map(|n| unicode::fold(n))
+        .collect();
+    for resource in data.data_mut() {
+        if let Resource::Bookmark {
+            id: bookmark_id,
+            attributes,
+            relationships,
+            ..
+        } = resource
+        {
+            if bookmark_id == id {
+                for tag_id in &tag_ids {
+                    bulk::set_tag(relationships, tag_id, true);
+                }
+                attributes
+                    .suggested_tags
+                    .retain(|name| !reviewed.contains(&unicode::fold(name)));
+                if !tag_ids.is_empty() {
+                    attributes.modified = Some(chrono::Utc::now());
+                }
+                break;
+            }
+        }
+    }
+    Ok(review)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag};
+
+    fn sample_data() -> BookmarksData {
+        let mut data = BookmarksData::new();
+        let mut tag_ids = HashMap::new();
+        for name in ["code", "rust", "cooking", "go"] {
+            let tag = create_tag(name.to_string(), None, None);
+            tag_ids.insert(name, tag.id().to_string());
+            data.add_tag(tag).unwrap();
+        }
+
+        for (url, title, tags) in [
+            (
+                "https://github.com/tokio-rs/tokio",
+                "Tokio runtime",
+                vec!["code", "rust"],
+            ),
+            (
+                "https://github.com/serde-rs/serde",
+                "Serde serialization",
+                vec!["code", "rust"],
+            ),
+            (
+                "https://www.github.com/psf/requests",
+                "Requests for Python",
+                vec!["code"],
+            ),
+            (
+                "https://cooking.example.com/bread",
+                "Sourdough bread recipe",
+                vec!["cooking"],
+            ),
+            (
+                "https://cooking.example.com/pasta",
+                "Fresh pasta recipe",
+                vec!["cooking"],
+            ),
+        ] {
+            let tags = tags.iter().map(|name| tag_ids[name].clone()).collect();
+            data.add_bookmark(create_bookmark(url.to_string(), title.to_string(), tags))
+                .unwrap();
+        }
+        data
+    }
+
+    fn names(suggestions: &[Suggestion]) -> Vec<&str> {
+        suggestions.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_domain_and_words_drive_suggestions() {
+        let data = sample_data();
+
+        let github = suggest_tags(&data, "https://github.com/rust-lang/cargo", "Cargo", 5);
+        assert_eq!(names(&github), vec!["code", "rust"]);
+
+        let recipe = suggest_tags(
+            &data,
+            "https://blog.example.org/soup",
+            "Tomato soup recipe",
+            5,
+        );
+        assert_eq!(names(&recipe), vec!["cooking"]);
+    }
+
+    #[test]
+    fn test_tag_names_in_title_count() {
+        let data = sample_data();
+        let suggestions =
+            suggest_tags(&data, "https://blog.example.org/post", "Why I like Rust", 5);
+        assert_eq!(names(&suggestions), vec!["rust"]);
+
+        let suggestions = suggest_tags(&data, "https://go.dev/blog", "Go generics", 5);
+        assert_eq!(names(&suggestions), vec!["go"]);
+    }
+
+    #[test]
+    fn test_no_overlap_and_limits() {
+        let data = sample_data();
+        assert!(suggest_tags(&data, "https://unrelated.org", "Nothing shared", 5).is_empty());
+        assert_eq!(
+            suggest_tags(&data, "https://github.com/x/y", "", 1).len(),
+            1
+        );
+        assert!(suggest_tags(&BookmarksData::new(), "https://github.com", "Rust", 5).is_empty());
+    }
+
+    #[test]
+    fn test_keywords() {
+        let text = "Bread needs flour. The flour and the water and the salt make bread; \
+            good bread takes time. Flour, 2024, 2024, 2024.";
+        assert_eq!(
+            keywords("Sourdough basics", text),
+            vec!["basics", "bread", "flour", "sourdough"]
+        );
+        assert!(keywords("", "the and the and the").is_empty());
+    }
+
+    #[test]
+    fn test_content_suggestions_and_review() {
+        let mut data = sample_data();
+        let bookmark = create_bookmark(
+            "https://example.net/post".to_string(),
+            "Post".to_string(),
+            vec![],
+        );
+        let id = bookmark.id().to_string();
+        data.add_bookmark(bookmark).unwrap();
+
+        let words: Vec<String> = ["async", "rust", "tokio"].map(String::from).to_vec();
+        let suggested = content_suggestions(&data, &id, &words, 5);
+        // The existing tag comes first, under its own name
+        assert_eq!(suggested, vec!["rust", "async", "tokio"]);
+        assert_eq!(content_suggestions(&data, &id, &words, 1), vec!["rust"]);
+
+        for resource in data.data_mut() {
+            if let Resource::Bookmark {
+                id: b, attributes, ..
+            } = resource
+            {
+                if *b == id {
+                    attributes.suggested_tags = suggested.clone();
+                }
+            }
+        }
+        let limits = ValidationConfig::default();
+        assert!(review(&mut data, &id, &["cooking".to_string()], &[], &limits).is_err());
+
+        let result = review(
+            &mut data,
+            &id,
+            &["RUST".to_string(), "async".to_string()],
+            &["tokio".to_string()],
+            &limits,
+        )
+        .unwrap();
+        assert_eq!(result.accepted, vec!["RUST", "async"]);
+        assert_eq!(result.created, vec!["async"]);
+        assert_eq!(result.dismissed, vec!["tokio"]);
+        let Some(Resource::Bookmark {
+            attributes,
+            relationships,
+            ..
+        }) = data.get(&id)
+        else {
+            panic!("bookmark missing");
+        };
+        assert!(attributes.suggested_tags.is_empty());
+        assert_eq!(
+            relationships
+                .as_ref()
+                .unwrap()
+                .tags
+                .as_ref()
+                .unwrap()
+                .data
+                .len(),
+            2
+        );
+        // Now that it has them, they aren't suggested again
+        assert_eq!(content_suggestions(&data, &id, &words, 5), vec!["tokio"]);
+    }
+}
@@ -0,0 +1,1140 @@
+use anyhow::{Context, Result};
+use keyring::Entry;
+use reqwest::{header, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::future::Future;
+
+use crate::encryption::{Cipher, EncryptionMode};
+use crate::gist::GistClient;
+use crate::git::{self, GitRepo, MergeParents, PullSummary};
+use crate::github;
+use crate::journal;
+use crate::merge::{self, FieldConflict};
+use crate::proxy;
+use crate::retry::RetryPolicy;
+use crate::s3::{S3Client, S3Location};
+use crate::storage::{self, BookmarksData};
+
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+
+/// Local git config keys; none of them are ever pushed
+const BACKEND_CONFIG_KEY: &str = "webtags.backend";
+const WEBDAV_URL_KEY: &str = "webtags.webdavUrl";
+const WEBDAV_USERNAME_KEY: &str = "webtags.webdavUsername";
+const S3_ENDPOINT_KEY: &str = "webtags.s3Endpoint";
+const S3_BUCKET_KEY: &str = "webtags.s3Bucket";
+const S3_KEY_KEY: &str = "webtags.s3Key";
+const S3_REGION_KEY: &str = "webtags.s3Region";
+const S3_PATH_STYLE_KEY: &str = "webtags.s3PathStyle";
+const S3_ACCESS_KEY_ID_KEY: &str = "webtags.s3AccessKeyId";
+const GIST_ID_KEY: &str = "webtags.gistId";
+/// `ETag` of the server's copy as of the last sync
+const SYNC_ETAG_KEY: &str = "webtags.syncEtag";
+/// Local commit whose `bookmarks.json` matches the server's copy
+const SYNCED_COMMIT_KEY: &str = "webtags.syncedCommit";
+/// Server-side version of the last upload, where the server keeps versions
+const SYNC_VERSION_KEY: &str = "webtags.syncVersion";
+
+const WEBDAV_KEYRING_SERVICE: &str = "com.webtags.webdav";
+const S3_KEYRING_SERVICE: &str = "com.webtags.s3";
+
+/// Where bookmarks sync to, chosen with `Init`
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BackendConfig {
+    /// Push and pull the repository's git remotes
+    #[default]
+    Git,
+    /// PUT and GET `bookmarks.json` at `url`. The local repository still keeps
+    /// history, but has no remote.
+    Webdav {
+        url: String,
+        #[serde(default)]
+        username: Option<String>,
+        /// Kept in the OS keychain, never in the repository
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Like `Webdav`, but the file is an object in an S3-compatible bucket
+    S3 {
+        #[serde(flatten)]
+        location: S3Location,
+        access_key_id: String,
+        /// Kept in the OS keychain; may be left out if already stored there
+        #[serde(default)]
+        secret_access_key: Option<String>,
+    },
+    /// `bookmarks.json` in a secret GitHub gist, using the token from `Auth`
+    Gist {
+        /// An existing gist to sync with; `Init` creates one if unset
+        #[serde(default)]
+        gist_id: Option<String>,
+    },
+}
+
+/// Moves changes between the local repository and wherever bookmarks are
+/// synced to. Writes only ever commit locally; `Sync` runs `pull` then `push`.
+pub trait SyncBackend {
+    /// Bring in changes made elsewhere, merging them with local commits
+    fn pull(&mut self) -> impl Future<Output = Result<PullSummary>>;
+
+    /// Publish local commits, returning how many went out
+    fn push(&mut self) -> impl Future<Output = Result<usize>>;
+}
+
+/// A single file on a server, read and written conditionally on its `ETag`
+pub trait RemoteFile {
+    /// Name of the service, for messages
+    fn name(&self) -> &'static str;
+
+    /// GET the file, unless it still has `etag`
+    fn get(&self, etag: Option<&str>) -> impl Future<Output = Result<Fetched>>;
+
+    /// PUT the file if the server's copy still has `etag` (or, with `None`,
+    /// if there is no file yet). Fails with [`Conflict`] if the server's copy
+    /// changed.
+    fn put(&self, content: &str, etag: Option<&str>) -> impl Future<Output = Result<Stored>>;
+}
+
+/// The server's copy changed since the last pull; pulling again merges it
+#[derive(Debug, thiserror::Error)]
+#[error("bookmarks changed on the server since the last sync; sync again to merge them")]
+pub struct Conflict;
+
+/// What a conditional GET found
+#[derive(Debug, PartialEq)]
+pub enum Fetched {
+    /// The server's copy still has the `ETag` we sent
+    Unchanged,
+    /// There is no file at the URL yet
+    Missing,
+    Changed {
+        content: String,
+        etag: Option<String>,
+    },
+}
+
+/// What a PUT left on the server
+#[derive(Debug, PartialEq, Default)]
+pub struct Stored {
+    pub etag: Option<String>,
+    /// Version ID, if the server keeps old versions
+    pub version: Option<String>,
+}
+
+/// Save the backend for the repository, replacing any earlier choice
+pub fn configure(repo: &GitRepo, backend: &BackendConfig) -> Result<()> {
+    if !matches!(backend, BackendConfig::Git) && repo.has_remote(git::PRIMARY_REMOTE) {
+        anyhow::bail!("Repository already syncs with a git remote");
+    }
+
+    // Sync state from an earlier setup would be wrong for a new one
+    for key in [
+        WEBDAV_URL_KEY,
+        WEBDAV_USERNAME_KEY,
+        S3_ENDPOINT_KEY,
+        S3_BUCKET_KEY,
+        S3_KEY_KEY,
+        S3_REGION_KEY,
+        S3_PATH_STYLE_KEY,
+        S3_ACCESS_KEY_ID_KEY,
+        GIST_ID_KEY,
+        SYNC_ETAG_KEY,
+        SYNCED_COMMIT_KEY,
+        SYNC_VERSION_KEY,
+    ] {
+        repo.set_local_config(key, None)?;
+    }
+
+    match backend {
+        BackendConfig::Git => repo.set_local_config(BACKEND_CONFIG_KEY, None),
+        BackendConfig::Webdav {
+            url,
+            username,
+            password,
+        } => {
+            validate_webdav_url(url)?;
+            if let Some(password) = password {
+                store_secret(WEBDAV_KEYRING_SERVICE, url, password)?;
+            }
+            repo.set_local_config(WEBDAV_URL_KEY, Some(url))?;
+            repo.set_local_config(WEBDAV_USERNAME_KEY, username.as_deref())?;
+            repo.set_local_config(BACKEND_CONFIG_KEY, Some("webdav"))
+        }
+        BackendConfig::S3 {
+            location,
+            access_key_id,
+            secret_access_key,
+        } => {
+            location.validate()?;
+            if access_key_id.is_empty() {
+                anyhow::bail!("S3 access key ID must not be empty");
+            }
+            if let Some(secret) = secret_access_key {
+                store_secret(S3_KEYRING_SERVICE, access_key_id, secret)?;
+            }
+            repo.set_local_config(S3_ENDPOINT_KEY, Some(&location.endpoint))?;
+            repo.set_local_config(S3_BUCKET_KEY, Some(&location.bucket))?;
+            repo.set_local_config(S3_KEY_KEY, Some(&location.key))?;
+            repo.set_local_config(S3_REGION_KEY, Some(&location.region))?;
+            repo.set_local_config(S3_PATH_STYLE_KEY, location.path_style.then_some("true"))?;
+            repo.set_local_config(S3_ACCESS_KEY_ID_KEY, Some(access_key_id))?;
+            repo.set_local_config(BACKEND_CONFIG_KEY, Some("s3"))
+        }
+        BackendConfig::Gist { gist_id } => {
+            let gist_id = gist_id.as_deref().context("Gist ID is not set")?;
+            if gist_id.is_empty() || !gist_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+                anyhow::bail!("Invalid gist ID: {gist_id}");
+            }
+            repo.set_local_config(GIST_ID_KEY, Some(gist_id))?;
+            repo.set_local_config(BACKEND_CONFIG_KEY, Some("gist"))
+        }
+    }
+}
+
+/// Which backend the repository syncs with: `"git"`, `"webdav"`, `"s3"`, or
+/// `"gist"`
+pub fn backend_name(repo: &GitRepo) -> &'static str {
+    match repo.local_config(BACKEND_CONFIG_KEY).as_deref() {
+        Some("webdav") => "webdav",
+        Some("s3") => "s3",
+        Some("gist") => "gist",
+        _ => "git",
+    }
+}
+
+/// Whether the repository syncs with a file backend and has commits the
+/// server hasn't seen. Only reads local config, so it's cheap to ask often.
+pub fn has_unsynced_commits(repo: &GitRepo) -> bool {
+    backend_name(repo) != "git" && head_is_unsynced(repo)
+}
+
+fn head_is_unsynced(repo: &GitRepo) -> bool {
+    let synced = repo.local_config(SYNCED_COMMIT_KEY);
+    repo.head_id()
+        .is_some_and(|head| synced != Some(head.to_string()))
+}
+
+/// Server-side version of the last upload, if the server keeps versions
+pub fn synced_version(repo: &GitRepo) -> Option<String> {
+    repo.local_config(SYNC_VERSION_KEY)
+}
+
+/// The server holding bookmarks.json, or `None` if the repository syncs
+/// over git
+pub fn remote_file(repo: &GitRepo) -> Result<Option<RemoteClient>> {
+    match backend_name(repo) {
+        "webdav" => {
+            let url = repo
+                .local_config(WEBDAV_URL_KEY)
+                .context("WebDAV URL is not configured")?;
+            let password = get_secret(WEBDAV_KEYRING_SERVICE, &url)
+                .map_err(|e| log::debug!("No WebDAV password stored: {e:#}"))
+                .ok();
+            let username = repo.local_config(WEBDAV_USERNAME_KEY);
+            Ok(Some(RemoteClient::WebDav(
+                WebDavClient::new(url, username, password).with_retry_policy(repo.retry_policy()),
+            )))
+        }
+        "s3" => {
+            let setting = |key: &str| {
+                repo.local_config(key)
+                    .with_context(|| format!("{key} is not configured"))
+            };
+            let location = S3Location {
+                endpoint: setting(S3_ENDPOINT_KEY)?,
+                bucket: setting(S3_BUCKET_KEY)?,
+                key: setting(S3_KEY_KEY)?,
+                region: setting(S3_REGION_KEY)?,
+                path_style: repo.local_config(S3_PATH_STYLE_KEY).as_deref() == Some("true"),
+            };
+            let access_key_id = setting(S3_ACCESS_KEY_ID_KEY)?;
+            let secret = get_secret(S3_KEYRING_SERVICE, &access_key_id)?;
+            Ok(Some(RemoteClient::S3(
+                S3Client::new(&location, access_key_id, secret)?
+                    .with_retry_policy(repo.retry_policy()),
+            )))
+        }
+        "gist" => {
+            let gist_id = repo
+                .local_config(GIST_ID_KEY)
+                .context("Gist ID is not configured")?;
+            let token = github::get_token().context("Not signed in to GitHub")?;
+            Ok(Some(RemoteClient::Gist(
+                GistClient::new(token, gist_id).with_retry_policy(repo.retry_policy()),
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn validate_webdav_url(url: &str) -> Result<()> {
+    let parsed = url::Url::parse(url).context("Invalid WebDAV URL")?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        anyhow::bail!("WebDAV URL must use http or https");
+    }
+    if parsed.host_str().is_none() || parsed.path().ends_with('/') {
+        anyhow::bail!("WebDAV URL must name the bookmarks file, not a folder");
+    }
+    if !parsed.username().is_empty() || parsed.password().is_some() {
+        anyhow::bail!("Pass WebDAV credentials separately, not in the URL");
+    }
+    Ok(())
+}
+
+fn store_secret(service: &str, account: &str, secret: &str) -> Result<()> {
+    let entry = Entry::new(service, account).context("Failed to create keyring entry")?;
+    entry
+        .set_password(secret)
+        .context("Failed to store credentials in keychain")
+}
+
+fn get_secret(service: &str, account: &str) -> Result<String> {
+    let entry = Entry::new(service, account).context("Failed to create keyring entry")?;
+    entry
+        .get_password()
+        .context("Failed to retrieve credentials from keychain")
+}
+
+/// bookmarks.json as of a revision. A revision without the file (e.g. before
+/// the first write) counts as empty.
+pub fn bookmarks_at(repo: &GitRepo, rev: &str, encryption_enabled: bool) -> Result<BookmarksData> {
+    let Some(content) = repo.read_file_at(rev, BOOKMARKS_FILE)? else {
+        return Ok(BookmarksData::new());
+    };
+    let content = String::from_utf8(content).context("bookmarks.json is not valid UTF-8")?;
+    storage::parse_with_encryption(&content, BOOKMARKS_FILE, encryption_enabled)
+}
+
+/// Sync over git: pull from and push to `origin`
+pub struct GitBackend<'a> {
+    repo: &'a GitRepo,
+    encryption: Option<EncryptionMode>,
+    cipher: Cipher,
+}
+
+impl<'a> GitBackend<'a> {
+    /// `encryption` and `cipher` are what local writes use, so a merged file
+    /// is written the same way
+    pub fn new(repo: &'a GitRepo, encryption: Option<EncryptionMode>, cipher: Cipher) -> Self {
+        Self {
+            repo,
+            encryption,
+            cipher,
+        }
+    }
+
+    /// Merge in the history from a bundle file, as a pull from `origin`
+    /// would, for machines that never share a remote
+    pub fn pull_bundle(&self, path: &std::path::Path) -> Result<PullSummary> {
+        let mut summary = self.repo.pull_bundle(path, "main")?;
+        if let Some(parents) = &summary.merge {
+            let conflicts = self.merge_bookmarks(parents)?;
+            merge::save_conflicts(self.repo.path(), &conflicts)?;
+            summary.conflicts_resolved = conflicts.len();
+        }
+        Ok(summary)
+    }
+
+    /// Merge every `device/*` branch on the remote into `main`, adding what
+    /// each brought in to `summary`. Returns the fields left in conflict,
+    /// or `None` if every branch fast-forwarded or was already merged.
+    fn merge_device_branches(
+        &self,
+        summary: &mut PullSummary,
+    ) -> Result<Option<Vec<FieldConflict>>> {
+        let mut conflicts = None;
+        let branches = self
+            .repo
+            .fetch_branches(git::PRIMARY_REMOTE, git::DEVICE_BRANCH_PREFIX)?;
+        for branch in branches {
+            let merged = self
+                .repo
+                .merge_remote_branch(git::PRIMARY_REMOTE, &branch, "main")?;
+            summary.pulled += merged.pulled;
+            if let Some(parents) = &merged.merge {
+                conflicts
+                    .get_or_insert_with(Vec::new)
+                    .extend(self.merge_bookmarks(parents)?);
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Replace git's line-based merge of the bookmarks with a field-level
+    /// one, so concurrent edits to different fields both survive. Returns
+    /// the fields left for the user to settle.
+    fn merge_bookmarks(&self, parents: &MergeParents) -> Result<Vec<FieldConflict>> {
+        let has_file = |rev: git2::Oid| -> Result<bool> {
+            Ok(self
+                .repo
+                .read_file_at(&rev.to_string(), BOOKMARKS_FILE)?
+                .is_some())
+        };
+        if !has_file(parents.ours)? && !has_file(parents.theirs)? {
+            return Ok(Vec::new());
+        }
+        let encryption_enabled = self.encryption.is_some();
+        let base = match parents.base {
+            Some(base) => bookmarks_at(self.repo, &base.to_string(), encryption_enabled)?,
+            None => BookmarksData::new(),
+        };
+        let local = bookmarks_at(self.repo, &parents.ours.to_string(), encryption_enabled)?;
+        let remote = bookmarks_at(self.repo, &parents.theirs.to_string(), encryption_enabled)?;
+        let (merged, conflicts) = merge::merge(&base, &local, &remote);
+
+        let file = self.repo.path().join(BOOKMARKS_FILE);
+        storage::write_to_file_with_mode(&file, &merged, self.encryption, self.cipher)
+            .context("Failed to write merged bookmarks")?;
+        self.repo.add_file(BOOKMARKS_FILE)?;
+        if self.repo.has_staged_changes()? {
+            self.repo.commit("Merge bookmarks")?;
+        }
+        record_merge(self.repo.path(), &local, &merged);
+        Ok(conflicts)
+    }
+}
+
+/// Journal a merge so `Undo` can take back what it brought in. Not being
+/// able to undo isn't worth failing the sync over.
+fn record_merge(repo_path: &std::path::Path, local: &BookmarksData, merged: &BookmarksData) {
+    if let Err(e) = journal::record(repo_path, "merge", local, merged) {
+        log::warn!("Failed to journal merge: {e:#}");
+    }
+}
+
+impl SyncBackend for GitBackend<'_> {
+    async fn pull(&mut self) -> Result<PullSummary> {
+        let mut summary = self.repo.pull(git::PRIMARY_REMOTE, "main")?;
+        let mut conflicts = match &summary.merge {
+            Some(parents) => Some(self.merge_bookmarks(parents)?),
+            None => None,
+        };
+        if self.repo.device_branches_enabled() {
+            if let Some(from_devices) = self.merge_device_branches(&mut summary)? {
+                conflicts.get_or_insert_with(Vec::new).extend(from_devices);
+            }
+        }
+        // Saved once, so one merge's conflicts don't replace another's
+        if let Some(conflicts) = conflicts {
+            merge::save_conflicts(self.repo.path(), &conflicts)?;
+            summary.conflicts_resolved = conflicts.len();
+        }
+        Ok(summary)
+    }
+
+    async fn push(&mut self) -> Result<usize> {
+        // Nobody else pushes to this device's branch, so this never races
+        // another device; rewritten history (after squashing) is replaced
+        let device_branch = self.repo.device_branch();
+        if let Some(branch) = &device_branch {
+            self.repo.create_branch(branch)?;
+            self.repo.force_push(git::PRIMARY_REMOTE, branch)?;
+        }
+        // The pull just refreshed the remote-tracking branch, so this is current
+        let unpushed = self
+            .repo
+            .branch_ahead_behind(git::PRIMARY_REMOTE, "main")
+            .context("Failed to count local commits")?
+            .map_or(0, |ab| ab.ahead);
+        if unpushed > 0 {
+            match self.repo.push(git::PRIMARY_REMOTE, "main") {
+                // Another device got there first; the changes are on this
+                // device's branch and reach main with a later sync. Any
+                // other failure (offline, bad credentials) is still an error.
+                Err(e) if device_branch.is_some() && git::is_push_rejected(&e) => {
+                    log::warn!("Left main for a later sync: {e:#}");
+                    return Ok(0);
+                }
+                result => result?,
+            }
+        }
+        Ok(unpushed)
+    }
+}
+
+/// One of the servers a [`FileBackend`] can sync with
+pub enum RemoteClient {
+    WebDav(WebDavClient),
+    S3(S3Client),
+    Gist(GistClient),
+}
+
+impl RemoteFile for RemoteClient {
+    fn name(&self) -> &'static str {
+        match self {
+            RemoteClient::WebDav(client) => client.name(),
+            RemoteClient::S3(client) => client.name(),
+            RemoteClient::Gist(client) => client.name(),
+        }
+    }
+
+    async fn get(&self, etag: Option<&str>) -> Result<Fetched> {
+        match self {
+            RemoteClient::WebDav(client) => client.get(etag).await,
+            RemoteClient::S3(client) => client.get(etag).await,
+            RemoteClient::Gist(client) => client.get(etag).await,
+        }
+    }
+
+    async fn put(&self, content: &str, etag: Option<&str>) -> Result<Stored> {
+        match self {
+            RemoteClient::WebDav(client) => client.put(content, etag).await,
+            RemoteClient::S3(client) => client.put(content, etag).await,
+            RemoteClient::Gist(client) => client.put(content, etag).await,
+        }
+    }
+}
+
+/// Reads and writes one file on a WebDAV server
+pub struct WebDavClient {
+    client: Client,
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    retry: RetryPolicy,
+}
+
+impl WebDavClient {
+    pub fn new(url: String, username: Option<String>, password: Option<String>) -> Self {
+        let client = proxy::http_client().unwrap_or_else(|e| {
+            log::warn!("Ignoring proxy configuration: {e:#}");
+            Client::new()
+        });
+        Self {
+            client,
+            url,
+            username,
+            password,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Use a different retry policy for requests
+    #[must_use]
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    fn request(&self, method: reqwest::Method) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, &self.url);
+        match &self.username {
+            Some(username) => request.basic_auth(username, self.password.as_deref()),
+            None => request,
+        }
+    }
+
+    async fn send(&self, build: impl Fn() -> reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let response = self
+            .retry
+            .run_async(|| async { Ok(build().send().await?) }, is_transient_error)
+            .await
+            .context("Failed to reach the WebDAV server")?;
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            anyhow::bail!("WebDAV server refused the credentials ({status})");
+        }
+        Ok(response)
+    }
+}
+
+impl RemoteFile for WebDavClient {
+    fn name(&self) -> &'static str {
+        "WebDAV"
+    }
+
+    async fn get(&self, etag: Option<&str>) -> Result<Fetched> {
+        let response = self
+            .send(|| {
+                let request = self.request(reqwest::Method::GET);
+                match etag {
+                    Some(etag) => request.header(header::IF_NONE_MATCH, etag),
+                    None => request,
+                }
+            })
+            .await?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => Ok(Fetched::Unchanged),
+            StatusCode::NOT_FOUND => Ok(Fetched::Missing),
+            status if status.is_success() => {
+                let etag = etag_of(&response);
+                let content = response
+                    .text()
+                    .await
+                    .context("Failed to download bookmarks")?;
+                Ok(Fetched::Changed { content, etag })
+            }
+            status => anyhow::bail!("WebDAV GET failed ({status})"),
+        }
+    }
+
+    async fn put(&self, content: &str, etag: Option<&str>) -> Result<Stored> {
+        let response = self
+            .send(|| {
+                let request = self
+                    .request(reqwest::Method::PUT)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(content.to_string());
+                match etag {
+                    Some(etag) => request.header(header::IF_MATCH, etag),
+                    None => request.header(header::IF_NONE_MATCH, "*"),
+                }
+            })
+            .await?;
+        let etag = match response.status() {
+            StatusCode::PRECONDITION_FAILED => return Err(Conflict.into()),
+            status if status.is_success() => match etag_of(&response) {
+                Some(etag) => Some(etag),
+                // Not every server returns the ETag from a PUT
+                None => etag_of(&self.send(|| self.request(reqwest::Method::HEAD)).await?),
+            },
+            status => anyhow::bail!("WebDAV PUT failed ({status})"),
+        };
+        Ok(Stored {
+            etag,
+            version: None,
+        })
+    }
+}
+
+pub(crate) fn etag_of(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Timeouts and connection failures are worth retrying
+pub(crate) fn is_transient_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_timeout() || e.is_connect())
+    })
+}
+
+/// Sync `bookmarks.json` as a single file on a server. Local commits are
+/// still made for every write, so history and undo work as they do with git.
+pub struct FileBackend<'a, F> {
+    repo: &'a GitRepo,
+    remote: F,
+    encryption: Option<EncryptionMode>,
+    cipher: Cipher,
+}
+
+impl<'a, F: RemoteFile> FileBackend<'a, F> {
+    /// `encryption` and `cipher` are what local writes use, so a merged file
+    /// is written the same way
+    pub fn new(
+        repo: &'a GitRepo,
+        remote: F,
+        encryption: Option<EncryptionMode>,
+        cipher: Cipher,
+    ) -> Self {
+        Self {
+            repo,
+            remote,
+            encryption,
+            cipher,
+        }
+    }
+
+    /// Whether there are commits the server hasn't seen
+    fn has_local_changes(&self) -> bool {
+        head_is_unsynced(self.repo)
+    }
+
+    fn commit(&self, message: &str) -> Result<()> {
+        self.repo.add_file(BOOKMARKS_FILE)?;
+        if self.repo.has_staged_changes()? {
+            self.repo.commit(message)?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: RemoteFile> SyncBackend for FileBackend<'_, F> {
+    async fn pull(&mut self) -> Result<PullSummary> {
+        let etag = self.repo.local_config(SYNC_ETAG_KEY);
+        let (content, etag) = match self.remote.get(etag.as_deref()).await? {
+            Fetched::Unchanged => return Ok(PullSummary::default()),
+            Fetched::Missing => {
+                // Deleted on the server (or never uploaded): the next push creates it
+                self.repo.set_local_config(SYNC_ETAG_KEY, None)?;
+                self.repo.set_local_config(SYNCED_COMMIT_KEY, None)?;
+                return Ok(PullSummary::default());
+            }
+            Fetched::Changed { content, etag } => (content, etag),
+        };
+
+        let name = self.remote.name();
+        let encryption_enabled = self.encryption.is_some();
+        let remote = storage::parse_with_encryption(&content, BOOKMARKS_FILE, encryption_enabled)
+            .with_context(|| format!("Bookmarks on the {name} server are invalid"))?;
+        let local_head = self
+            .has_local_changes()
+            .then(|| self.repo.head_id())
+            .flatten();
+        let base = self.repo.local_config(SYNCED_COMMIT_KEY);
+
+        // Commit the server's copy as it is, so it can be the base of the next merge
+        let file = self.repo.path().join(BOOKMARKS_FILE);
+        storage::write_atomic(&file, content.as_bytes())
+            .context("Failed to write bookmarks file")?;
+        self.commit(&format!("Pull from {name}"))?;
+        let synced = self.repo.head_id().map(|id| id.to_string());
+        self.repo
+            .set_local_config(SYNCED_COMMIT_KEY, synced.as_deref())?;
+        self.repo.set_local_config(SYNC_ETAG_KEY, etag.as_deref())?;
+
+        let mut summary = PullSummary {
+            pulled: 1,
+            ..PullSummary::default()
+        };
+        if let Some(local_head) = local_head {
+            let base = match base {
+                Some(base) => bookmarks_at(self.repo, &base, encryption_enabled)?,
+                None => BookmarksData::new(),
+            };
+            let local = bookmarks_at(self.repo, &local_head.to_string(), encryption_enabled)?;
+            let (merged, conflicts) = merge::merge(&base, &local, &remote);
+            summary.conflicts_resolved = conflicts.len();
+            storage::write_to_file_with_mode(&file, &merged, self.encryption, self.cipher)
+                .context("Failed to write merged bookmarks")?;
+            self.commit(&format!("Merge changes from {name}"))?;
+            merge::save_conflicts(self.repo.path(), &conflicts)?;
+            record_merge(self.repo.path(), &local, &merged);
+        }
+        Ok(summary)
+    }
+
+    async fn push(&mut self) -> Result<usize> {
+        let file = self.repo.path().join(BOOKMARKS_FILE);
+        if !self.has_local_changes() || !file.exists() {
+            return Ok(0);
+        }
+        let content = fs::read_to_string(&file).context("Failed to read bookmarks file")?;
+        let etag = self.repo.local_config(SYNC_ETAG_KEY);
+        let stored = self.remote.put(&content, etag.as_deref()).await?;
+
+        let head = self.repo.head_id().map(|id| id.to_string());
+        self.repo
+            .set_local_config(SYNCED_COMMIT_KEY, head.as_deref())?;
+        self.repo
+            .set_local_config(SYNC_ETAG_KEY, stored.etag.as_deref())?;
+        self.repo
+            .set_local_config(SYNC_VERSION_KEY, stored.version.as_deref())?;
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, Resource};
+    use wiremock::matchers::{header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn bookmark(url: &str, title: &str) -> Resource {
+        create_bookmark(url.to_string(), title.to_string(), vec![])
+    }
+
+    fn with(resources: &[&Resource]) -> BookmarksData {
+        let mut data = BookmarksData::new();
+        for resource in resources {
+            data.add_bookmark((*resource).clone()).unwrap();
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn test_git_backend_merges_bookmark_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote_path = dir.path().join("remote.git");
+        git2::Repository::init_bare(&remote_path)
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        let remote_url = remote_path.to_str().unwrap();
+
+        let local_path = dir.path().join("local");
+        let mut local = GitRepo::init(&local_path).unwrap();
+        git2::Repository::open(&local_path)
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        local.add_remote(git::PRIMARY_REMOTE, remote_url).unwrap();
+        let original = bookmark("https://a.example.com", "A");
+        let save = |repo: &GitRepo, resource: &Resource, message: &str| {
+            storage::write_to_file(repo.path().join(BOOKMARKS_FILE), &with(&[resource])).unwrap();
+            repo.add_file(BOOKMARKS_FILE).unwrap();
+            repo.commit(message).unwrap();
+        };
+        save(&local, &original, "Add bookmark");
+        local.push(git::PRIMARY_REMOTE, "main").unwrap();
+
+        // Another device adds a note while this one renames the bookmark
+        let other_path = dir.path().join("other");
+        let other = GitRepo::clone(remote_url, &other_path, None).unwrap();
+        let mut noted = original.clone();
+        if let Resource::Bookmark { attributes, .. } = &mut noted {
+            attributes.notes = Some("Noted".to_string());
+        }
+        save(&other, &noted, "Add note");
+        other.push(git::PRIMARY_REMOTE, "main").unwrap();
+        let mut retitled = original.clone();
+        if let Resource::Bookmark { attributes, .. } = &mut retitled {
+            attributes.title = "Renamed".to_string();
+        }
+        save(&local, &retitled, "Rename");
+
+        let mut backend = GitBackend::new(&local, None, Cipher::default());
+        let pulled = backend.pull().await.unwrap();
+        assert_eq!(pulled.conflicts_resolved, 0);
+        let merged = bookmarks_at(&local, "HEAD", false).unwrap();
+        let Some(Resource::Bookmark { attributes, .. }) = merged.get(original.id()) else {
+            panic!("bookmark missing");
+        };
+        assert_eq!(attributes.title, "Renamed");
+        assert_eq!(attributes.notes.as_deref(), Some("Noted"));
+    }
+
+    #[tokio::test]
+    async fn test_device_branches_carry_commits_main_missed() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote_path = dir.path().join("remote.git");
+        git2::Repository::init_bare(&remote_path)
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        let remote_url = remote_path.to_str().unwrap();
+        let save = |repo: &GitRepo, data: &BookmarksData, message: &str| {
+            storage::write_to_file(repo.path().join(BOOKMARKS_FILE), data).unwrap();
+            repo.add_file(BOOKMARKS_FILE).unwrap();
+            repo.commit(message).unwrap();
+        };
+
+        let laptop_path = dir.path().join("laptop");
+        let mut laptop = GitRepo::init(&laptop_path).unwrap();
+        git2::Repository::open(&laptop_path)
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        laptop.add_remote(git::PRIMARY_REMOTE, remote_url).unwrap();
+        let first = bookmark("https://a.example.com", "A");
+        save(&laptop, &with(&[&first]), "Add bookmark");
+        laptop.push(git::PRIMARY_REMOTE, "main").unwrap();
+
+        let desktop_path = dir.path().join("desktop");
+        let desktop = GitRepo::clone(remote_url, &desktop_path, None).unwrap();
+        for (repo, name) in [(&laptop, "Work Laptop"), (&desktop, "Desktop")] {
+            repo.set_device_name(name).unwrap();
+            repo.set_device_branches(true).unwrap();
+        }
+        assert_eq!(
+            laptop.device_branch().as_deref(),
+            Some("device/work-laptop")
+        );
+
+        // The laptop's commit only reaches its own branch, as if another
+        // device had pushed main first
+        let second = bookmark("https://b.example.com", "B");
+        save(&laptop, &with(&[&first, &second]), "Add another");
+        laptop.create_branch("device/work-laptop").unwrap();
+        laptop
+            .force_push(git::PRIMARY_REMOTE, "device/work-laptop")
+            .unwrap();
+        let third = bookmark("https://c.example.com", "C");
+        save(&desktop, &with(&[&first, &third]), "Add on desktop");
+
+        let mut backend = GitBackend::new(&desktop, None, Cipher::default());
+        let pulled = backend.pull().await.unwrap();
+        assert_eq!(pulled.pulled, 1);
+        // Its own commit, the merge and its bookmark merge, and the laptop's
+        // commit all go to main
+        assert_eq!(backend.push().await.unwrap(), 4);
+        let merged = bookmarks_at(&desktop, "HEAD", false).unwrap();
+        for resource in [&first, &second, &third] {
+            assert!(merged.get(resource.id()).is_some());
+        }
+        assert_eq!(
+            laptop
+                .fetch_branches(git::PRIMARY_REMOTE, git::DEVICE_BRANCH_PREFIX)
+                .unwrap(),
+            vec!["device/desktop", "device/work-laptop"]
+        );
+        assert_eq!(
+            desktop
+                .branch_ahead_behind(git::PRIMARY_REMOTE, "main")
+                .unwrap(),
+            Some(git::AheadBehind {
+                ahead: 0,
+                behind: 0
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_device_branches_leave_rejected_main_for_later() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote_path = dir.path().join("remote.git");
+        git2::Repository::init_bare(&remote_path)
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        let remote_url = remote_path.to_str().unwrap();
+        let save = |repo: &GitRepo, data: &BookmarksData, message: &str| {
+            storage::write_to_file(repo.path().join(BOOKMARKS_FILE), data).unwrap();
+            repo.add_file(BOOKMARKS_FILE).unwrap();
+            repo.commit(message).unwrap();
+        };
+
+        let laptop_path = dir.path().join("laptop");
+        let mut laptop = GitRepo::init(&laptop_path).unwrap();
+        git2::Repository::open(&laptop_path)
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        laptop.add_remote(git::PRIMARY_REMOTE, remote_url).unwrap();
+        let first = bookmark("https://a.example.com", "A");
+        save(&laptop, &with(&[&first]), "Add bookmark");
+        laptop.push(git::PRIMARY_REMOTE, "main").unwrap();
+
+        let desktop = GitRepo::clone(remote_url, dir.path().join("desktop"), None).unwrap();
+        laptop.set_device_name("Laptop").unwrap();
+        laptop.set_device_branches(true).unwrap();
+        let second = bookmark("https://b.example.com", "B");
+        save(&desktop, &with(&[&first, &second]), "Add on desktop");
+        desktop.push(git::PRIMARY_REMOTE, "main").unwrap();
+
+        // main moved on since the laptop last pulled: left for a later sync
+        let third = bookmark("https://c.example.com", "C");
+        save(&laptop, &with(&[&first, &third]), "Add on laptop");
+        let mut backend = GitBackend::new(&laptop, None, Cipher::default());
+        assert_eq!(backend.push().await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_bundles_carry_history_between_unconnected_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let init = |name: &str| {
+            let path = dir.path().join(name);
+            let repo = GitRepo::init(&path).unwrap();
+            git2::Repository::open(&path)
+                .unwrap()
+                .set_head("refs/heads/main")
+                .unwrap();
+            repo
+        };
+        let save = |repo: &GitRepo, resource: &Resource, message: &str| {
+            storage::write_to_file(repo.path().join(BOOKMARKS_FILE), &with(&[resource])).unwrap();
+            repo.add_file(BOOKMARKS_FILE).unwrap();
+            repo.commit(message).unwrap();
+        };
+        let laptop = init("laptop");
+        let desktop = init("desktop");
+        let original = bookmark("https://a.example.com", "A");
+        save(&laptop, &original, "Add bookmark");
+        let first = laptop.head_id().unwrap().to_string();
+
+        // The whole history into an empty repository
+        let full = dir.path().join("full.bundle");
+        assert_eq!(laptop.create_bundle(&full, "main", None).unwrap(), 1);
+        let pulled = GitBackend::new(&desktop, None, Cipher::default())
+            .pull_bundle(&full)
+            .unwrap();
+        assert_eq!(pulled.pulled, 1);
+        assert!(bookmarks_at(&desktop, "HEAD", false)
+            .unwrap()
+            .get(original.id())
+            .is_some());
+        assert!(laptop.create_bundle(&full, "main", Some(&first)).is_err());
+
+        // Then only what's new, merged field by field with local edits
+        let mut retitled = original.clone();
+        if let Resource::Bookmark { attributes, .. } = &mut retitled {
+            attributes.title = "Renamed".to_string();
+        }
+        save(&laptop, &retitled, "Rename");
+        let mut noted = original.clone();
+        if let Resource::Bookmark { attributes, .. } = &mut noted {
+            attributes.notes = Some("Noted".to_string());
+        }
+        save(&desktop, &noted, "Add note");
+
+        let update = dir.path().join("update.bundle");
+        assert_eq!(
+            laptop.create_bundle(&update, "main", Some(&first)).unwrap(),
+            1
+        );
+        let pulled = GitBackend::new(&desktop, None, Cipher::default())
+            .pull_bundle(&update)
+            .unwrap();
+        assert!(pulled.merge.is_some());
+        let merged = bookmarks_at(&desktop, "HEAD", false).unwrap();
+        let Some(Resource::Bookmark { attributes, .. }) = merged.get(original.id()) else {
+            panic!("bookmark missing");
+        };
+        assert_eq!(attributes.title, "Renamed");
+        assert_eq!(attributes.notes.as_deref(), Some("Noted"));
+
+        // An incremental bundle needs its base commit to be present
+        let stranger = init("stranger");
+        assert!(GitBackend::new(&stranger, None, Cipher::default())
+            .pull_bundle(&update)
+            .is_err());
+    }
+
+    #[test]
+    fn test_s3_backend_config_defaults() {
+        let backend: BackendConfig = serde_json::from_str(
+            r#"{"kind":"s3","endpoint":"http://nas.local:9000","bucket":"backups","access_key_id":"minio","path_style":true}"#,
+        )
+        .unwrap();
+        let BackendConfig::S3 {
+            location,
+            secret_access_key,
+            ..
+        } = backend
+        else {
+            panic!("expected an S3 backend");
+        };
+        assert_eq!(location.key, "bookmarks.json");
+        assert_eq!(location.region, "us-east-1");
+        assert!(location.path_style);
+        assert_eq!(secret_access_key, None);
+    }
+
+    #[test]
+    fn test_validate_webdav_url() {
+        assert!(validate_webdav_url("https://dav.example.com/webtags/bookmarks.json").is_ok());
+        assert!(validate_webdav_url("https://dav.example.com/webtags/").is_err());
+        assert!(validate_webdav_url("ftp://dav.example.com/bookmarks.json").is_err());
+        assert!(validate_webdav_url("https://me:pw@dav.example.com/bookmarks.json").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_webdav_client_uses_etags() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("If-None-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("{}")
+                    .insert_header("ETag", "\"v1\""),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(header("If-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(204).insert_header("ETag", "\"v2\""))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(412))
+            .mount(&server)
+            .await;
+
+        let client = WebDavClient::new(format!("{}/bookmarks.json", server.uri()), None, None)
+            .with_retry_policy(RetryPolicy::none());
+        assert_eq!(
+            client.get(None).await.unwrap(),
+            Fetched::Changed {
+                content: "{}".to_string(),
+                etag: Some("\"v1\"".to_string())
+            }
+        );
+        assert_eq!(
+            client.get(Some("\"v1\"")).await.unwrap(),
+            Fetched::Unchanged
+        );
+        assert_eq!(
+            client
+                .put("{}", Some("\"v1\""))
+                .await
+                .unwrap()
+                .etag
+                .as_deref(),
+            Some("\"v2\"")
+        );
+        let err = client.put("{}", Some("\"v0\"")).await.unwrap_err();
+        assert!(err.is::<Conflict>());
+    }
+
+    #[tokio::test]
+    async fn test_webdav_backend_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        let server = MockServer::start().await;
+        let url = format!("{}/bookmarks.json", server.uri());
+        configure(
+            &repo,
+            &BackendConfig::Webdav {
+                url: url.clone(),
+                username: None,
+                password: None,
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            remote_file(&repo).unwrap(),
+            Some(RemoteClient::WebDav(_))
+        ));
+
+        // Nothing on the server yet: the first push creates the file
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(header("If-None-Match", "*"))
+            .respond_with(ResponseTemplate::new(201).insert_header("ETag", "\"v1\""))
+            .mount(&server)
+            .await;
+
+        let local = bookmark("https://local.example.com", "Local");
+        storage::write_to_file(dir.path().join(BOOKMARKS_FILE), &with(&[&local])).unwrap();
+        repo.add_file(BOOKMARKS_FILE).unwrap();
+        repo.commit("Add local").unwrap();
+
+        let client =
+            || WebDavClient::new(url.clone(), None, None).with_retry_policy(RetryPolicy::none());
+        let mut backend = FileBackend::new(&repo, client(), None, Cipher::default());
+        assert_eq!(backend.pull().await.unwrap(), PullSummary::default());
+        assert_eq!(backend.push().await.unwrap(), 1);
+        assert_eq!(backend.push().await.unwrap(), 0);
+
+        // Someone else added a bookmark meanwhile, and so did we
+        let theirs = bookmark("https://remote.example.com", "Remote");
+        let remote = serde_json::to_string(&with(&[&local, &theirs])).unwrap();
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(remote)
+                    .insert_header("ETag", "\"v2\""),
+            )
+            .mount(&server)
+            .await;
+        let ours = bookmark("https://new.example.com", "New");
+        storage::write_to_file(dir.path().join(BOOKMARKS_FILE), &with(&[&local, &ours])).unwrap();
+        repo.add_file(BOOKMARKS_FILE).unwrap();
+        repo.commit("Add new").unwrap();
+
+        let mut backend = FileBackend::new(&repo, client(), None, Cipher::default());
+        let pulled = backend.pull().await.unwrap();
+        assert_eq!(pulled.conflicts_resolved, 0);
+        let merged = storage::read_from_file(dir.path().join(BOOKMARKS_FILE)).unwrap();
+        assert_eq!(merged.data().len(), 3);
+        assert_eq!(repo.local_config(SYNC_ETAG_KEY).as_deref(), Some("\"v2\""));
+        // The merge still has to go out
+        assert!(backend.has_local_changes());
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::bulk;
+use crate::color;
+use crate::storage::{BookmarksData, Resource, TagAttributes};
+
+/// One change to the tag collection, sent in `UpdateTags`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum TagOp {
+    /// Give tags a generated color (see [`color::auto_color`]). Without
+    /// `tag_ids`, every tag that has no color yet; with `overwrite`, tags
+    /// that already have one are recolored too.
+    AutoColor {
+        #[serde(default)]
+        tag_ids: Vec<String>,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    /// Put a tag under `new_parent`, or at the top level without one. A tag
+    /// can't move under itself or its descendants, and the move has to keep
+    /// the hierarchy within the configured depth.
+    Move {
+        tag_id: String,
+        #[serde(default)]
+        new_parent: Option<String>,
+    },
+}
+
+impl TagOp {
+    /// Apply the operation, returning what it changed for the response
+    pub fn apply(&self, data: &mut BookmarksData) -> Result<serde_json::Value> {
+        match self {
+            TagOp::AutoColor { tag_ids, overwrite } => auto_color(data, tag_ids, *overwrite),
+            TagOp::Move { tag_id, new_parent } => move_tag(data, tag_id, new_parent.as_deref()),
+        }
+    }
+}
+
+fn tags_mut(data: &mut BookmarksData) -> Vec<(&str, &mut TagAttributes)> {
+    let (data, included) = data.resources_mut();
+    data.iter_mut()
+        .chain(included.iter_mut().flatten())
+        .filter_map(|resource| match resource {
+            Resource::Tag { id, attributes, .. } => Some((id.as_str(), attributes)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn auto_color(
+    data: &mut BookmarksData,
+    tag_ids: &[String],
+    overwrite: bool,
+) -> Result<serde_json::Value> {
+    let mut tags = tags_mut(data);
+    let known: HashSet<&str> = tags.iter().map(|(id, _)| *id).collect();
+    if let Some(missing) = tag_ids.iter().find(|id| !known.contains(id.as_str())) {
+        anyhow::bail!("No tag with ID {missing}");
+    }
+
+    let selected = |id: &str, attributes: &TagAttributes| {
+        (tag_ids.is_empty() || tag_ids.iter().any(|t| t == id))
+            && (overwrite || attributes.color.is_none())
+    };
+    let mut used: Vec<String> = tags
+        .iter()
+        .filter(|(id, attributes)| !selected(id, attributes))
+        .filter_map(|(_, attributes)| attributes.color.clone())
+        .collect();
+    tags.retain(|(id, attributes)| selected(id, attributes));
+    // Name order, so the same tags get the same colors on every device
+    tags.sort_by_cached_key(|(id, attributes)| (attributes.name.to_lowercase(), id.to_string()));
+
+    let mut colored = serde_json::Map::new();
+    for (id, attributes) in tags {
+        let color = color::auto_color(&attributes.name, used.iter().map(String::as_str));
+        colored.insert(id.to_string(), serde_json::json!(color));
+        used.push(color.clone());
+        attributes.color = Some(color);
+    }
+    Ok(serde_json::json!({ "colored": colored }))
+}
+
+/// Move the tag, returning the new breadcrumbs of it and every tag below it
+fn move_tag(
+    data: &mut BookmarksData,
+    tag_id: &str,
+    new_parent: Option<&str>,
+) -> Result<serde_json::Value> {
+    bulk::move_tag(data, tag_id, new_parent)?;
+
+    let mut breadcrumbs = serde_json::Map::new();
+    for tag in data.get_tags() {
+        let id = tag.id();
+        let mut ancestor = Some(id);
+        let mut seen = HashSet::new();
+        while let Some(current) = ancestor.filter(|current| seen.insert(*current)) {
+            if current == tag_id {
+                breadcrumbs.insert(
+                    id.to_string(),
+                    serde_json::json!(data.get_tag_breadcrumb(id)),
+                );
+                break;
+            }
+            ancestor = data.tag_parent_id(current);
+        }
+    }
+    Ok(serde_json::json!({ "moved": tag_id, "breadcrumbs": breadcrumbs }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_tag;
+
+    fn color_of(data: &BookmarksData, id: &str) -> Option<String> {
+        match data.get(id) {
+            Some(Resource::Tag { attributes, .. }) => attributes.color.clone(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_auto_color_fills_missing_colors() {
+        let mut data = BookmarksData::new();
+        let mut ids = Vec::new();
+        for (name, color) in [("rust", None), ("go", Some("#336699")), ("web", None)] {
+            let tag = create_tag(name.to_string(), color.map(str::to_string), None);
+            ids.push(tag.id().to_string());
+            data.add_tag(tag).unwrap();
+        }
+
+        let op = TagOp::AutoColor {
+            tag_ids: Vec::new(),
+            overwrite: false,
+        };
+        let result = op.apply(&mut data).unwrap();
+        assert_eq!(result["colored"].as_object().unwrap().len(), 2);
+        assert_eq!(color_of(&data, &ids[1]).as_deref(), Some("#336699"));
+        let rust = color_of(&data, &ids[0]).unwrap();
+        assert_ne!(Some(&rust), color_of(&data, &ids[2]).as_ref());
+        assert!(data.validate().is_ok());
+
+        // Nothing left without a color
+        let result = op.apply(&mut data).unwrap();
+        assert!(result["colored"].as_object().unwrap().is_empty());
+
+        let recolor = TagOp::AutoColor {
+            tag_ids: vec![ids[1].clone()],
+            overwrite: true,
+        };
+        recolor.apply(&mut data).unwrap();
+        assert_ne!(color_of(&data, &ids[1]).as_deref(), Some("#336699"));
+        assert_eq!(color_of(&data, &ids[0]), Some(rust));
+
+        let missing = TagOp::AutoColor {
+            tag_ids: vec!["missing".to_string()],
+            overwrite: false,
+        };
+        assert!(missing.apply(&mut data).is_err());
+    }
+
+    #[test]
+    fn test_move_reports_new_breadcrumbs() {
+        let mut data = BookmarksData::new();
+        let tech = create_tag("tech".to_string(), None, None);
+        let tech_id = tech.id().to_string();
+        let rust = create_tag("rust".to_string(), None, None);
+        let rust_id = rust.id().to_string();
+        let async_tag = create_tag("async".to_string(), None, Some(rust_id.clone()));
+        let async_id = async_tag.id().to_string();
+        for tag in [tech, rust, async_tag] {
+            data.add_tag(tag).unwrap();
+        }
+
+        let op = TagOp::Move {
+            tag_id: rust_id.clone(),
+            new_parent: Some(tech_id.clone()),
+        };
+        let result = op.apply(&mut data).unwrap();
+        assert_eq!(
+            result["breadcrumbs"],
+            serde_json::json!({
+                rust_id.clone(): ["tech", "rust"],
+                async_id.clone(): ["tech", "rust", "async"],
+            })
+        );
+        assert_eq!(data.tag_parent_id(&rust_id), Some(tech_id.as_str()));
+
+        // Not under itself or its own descendant
+        let cycle = TagOp::Move {
+            tag_id: rust_id.clone(),
+            new_parent: Some(async_id),
+        };
+        assert!(cycle.apply(&mut data).is_err());
+        assert_eq!(data.tag_parent_id(&rust_id), Some(tech_id.as_str()));
+
+        let to_top = TagOp::Move {
+            tag_id: rust_id.clone(),
+            new_parent: None,
+        };
+        to_top.apply(&mut data).unwrap();
+        assert_eq!(data.tag_parent_id(&rust_id), None);
+    }
+
+    #[test]
+    fn test_tag_op_wire_format() {
+        let op: TagOp = serde_json::from_str(r#"{"op":"autocolor"}"#).unwrap();
+        assert_eq!(
+            op,
+            TagOp::AutoColor {
+                tag_ids: Vec::new(),
+                overwrite: false
+            }
+        );
+        let op: TagOp =
+            serde_json::from_str(r#"{"op":"move","tag_id":"t1","new_parent":"t2"}"#).unwrap();
+        assert_eq!(
+            op,
+            TagOp::Move {
+                tag_id: "t1".to_string(),
+                new_parent: Some("t2".to_string())
+            }
+        );
+    }
+}
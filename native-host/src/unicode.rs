@@ -0,0 +1,44 @@
+use icu_normalizer::ComposingNormalizerBorrowed;
+use std::borrow::Cow;
+
+/// `text` in Unicode Normalization Form C, so that "é" typed as one code
+/// point and as "e" plus a combining accent are stored the same way
+pub fn nfc(text: &str) -> Cow<'_, str> {
+    ComposingNormalizerBorrowed::new_nfc().normalize(text)
+}
+
+/// [`nfc`] for an owned string, reusing it when it's already normalized
+pub fn into_nfc(text: String) -> String {
+    match nfc(&text) {
+        Cow::Owned(normalized) => normalized,
+        Cow::Borrowed(_) => text,
+    }
+}
+
+/// `text` for case-insensitive comparison: NFC, lowercased with Unicode
+/// case rules, and normalized again since lowercasing can undo NFC
+pub fn fold(text: &str) -> String {
+    nfc(&nfc(text).to_lowercase()).into_owned()
+}
+
+/// Whether `a` and `b` are the same name ignoring case and normalization form
+pub fn same_name(a: &str, b: &str) -> bool {
+    fold(a) == fold(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nfc_and_folding() {
+        let decomposed = "Cafe\u{301}";
+        assert_eq!(nfc(decomposed), "Caf\u{e9}");
+        assert!(matches!(nfc("plain"), Cow::Borrowed(_)));
+
+        assert_eq!(fold(decomposed), "caf\u{e9}");
+        assert!(same_name("Café", "CAFE\u{301}"));
+        assert!(same_name("ΣΟΦΙΑ", "σοφια"));
+        assert!(!same_name("cafe", "café"));
+    }
+}
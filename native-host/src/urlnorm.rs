@@ -0,0 +1,495 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+use url::Url;
+
+use crate::git::GitRepo;
+use crate::storage::{BookmarksData, Resource};
+
+/// Rules, kept in the repository's local git config
+const STRIP_TRACKING_KEY: &str = "webtags.urlStripTracking";
+/// Space-separated parameters to strip besides the built-in ones
+const TRACKING_PARAMS_KEY: &str = "webtags.urlTrackingParams";
+const IGNORE_WWW_KEY: &str = "webtags.urlIgnoreWww";
+const IGNORE_TRAILING_SLASH_KEY: &str = "webtags.urlIgnoreTrailingSlash";
+
+/// Query parameters starting with these only track where a visit came from
+const TRACKING_PREFIXES: &[&str] = &["utm_"];
+
+/// Query parameters that only track where a visit came from
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid",
+    "gclid",
+    "dclid",
+    "gbraid",
+    "wbraid",
+    "msclkid",
+    "yclid",
+    "twclid",
+    "ttclid",
+    "igshid",
+    "mc_cid",
+    "mc_eid",
+    "_hsenc",
+    "_hsmi",
+    "mkt_tok",
+    "vero_id",
+    "oly_anon_id",
+    "oly_enc_id",
+];
+
+/// Rules in effect, shared by every caller in the process. Set from the
+/// repository when it's opened; the defaults until then.
+static RULES: Mutex<Option<Rules>> = Mutex::new(None);
+
+/// How URLs are cleaned up and which ones count as the same page
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Rules {
+    /// Drop `utm_*`, `fbclid`, and other tracking parameters
+    pub strip_tracking: bool,
+    /// More parameters to drop, lowercase
+    pub tracking_params: Vec<String>,
+    /// `www.example.com` and `example.com` are the same site
+    pub ignore_www: bool,
+    /// `/path/` and `/path` are the same page
+    pub ignore_trailing_slash: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            strip_tracking: true,
+            tracking_params: Vec::new(),
+            ignore_www: true,
+            ignore_trailing_slash: true,
+        }
+    }
+}
+
+/// Rules to change with [`Rules::with`]; `None` leaves one as it is
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Update {
+    #[serde(default)]
+    pub strip_tracking: Option<bool>,
+    /// Replaces the extra parameters; empty leaves only the built-in ones
+    #[serde(default)]
+    pub tracking_params: Option<Vec<String>>,
+    #[serde(default)]
+    pub ignore_www: Option<bool>,
+    #[serde(default)]
+    pub ignore_trailing_slash: Option<bool>,
+}
+
+impl Rules {
+    pub fn load(repo: &GitRepo) -> Self {
+        let defaults = Self::default();
+        let flag = |key: &str, default: bool| {
+            repo.local_config(key)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            strip_tracking: flag(STRIP_TRACKING_KEY, defaults.strip_tracking),
+            tracking_params: repo
+                .local_config(TRACKING_PARAMS_KEY)
+                .map(|value| value.split_whitespace().map(str::to_lowercase).collect())
+                .unwrap_or_default(),
+            ignore_www: flag(IGNORE_WWW_KEY, defaults.ignore_www),
+            ignore_trailing_slash: flag(IGNORE_TRAILING_SLASH_KEY, defaults.ignore_trailing_slash),
+        }
+    }
+
+    /// The rules with `update` applied
+    pub fn with(&self, update: Update) -> Result<Self> {
+        let tracking_params = match update.tracking_params {
+            Some(params) => {
+                let mut cleaned: Vec<String> = Vec::new();
+                for param in params {
+                    let param = param.trim().to_lowercase();
+                    if param.is_empty() || param.contains(|c: char| c.is_whitespace() || c == '&') {
+                        anyhow::bail!("Invalid tracking parameter '{param}'");
+                    }
+                    if !cleaned.contains(&param) {
+                        cleaned.push(param);
+                    }
+                }
+                cleaned
+            }
+            None => self.tracking_params.clone(),
+        };
+        Ok(Self {
+            strip_tracking: update.strip_tracking.unwrap_or(self.strip_tracking),
+            tracking_params,
+            ignore_www: update.ignore_www.unwrap_or(self.ignore_www),
+            ignore_trailing_slash: update
+                .ignore_trailing_slash
+                .unwrap_or(self.ignore_trailing_slash),
+        })
+    }
+
+    /// Store the rules for `repo`, leaving defaults unset
+    pub fn save(&self, repo: &GitRepo) -> Result<()> {
+        let defaults = Self::default();
+        let flags = [
+            (
+                STRIP_TRACKING_KEY,
+                self.strip_tracking,
+                defaults.strip_tracking,
+            ),
+            (IGNORE_WWW_KEY, self.ignore_www, defaults.ignore_www),
+            (
+                IGNORE_TRAILING_SLASH_KEY,
+                self.ignore_trailing_slash,
+                defaults.ignore_trailing_slash,
+            ),
+        ];
+        for (key, value, default) in flags {
+            let value = (value != default).then(|| value.to_string());
+            repo.set_local_config(key, value.as_deref())?;
+        }
+        let params = (!self.tracking_params.is_empty()).then(|| self.tracking_params.join(" "));
+        repo.set_local_config(TRACKING_PARAMS_KEY, params.as_deref())?;
+        Ok(())
+    }
+
+    fn is_tracking(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.strip_tracking
+            && (TRACKING_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+                || TRACKING_PARAMS.contains(&name.as_str())
+                || self.tracking_params.contains(&name))
+    }
+}
+
+/// Make `rules` the ones every caller in the process uses
+pub fn set_rules(rules: Rules) {
+    *RULES.lock().unwrap_or_else(PoisonError::into_inner) = Some(rules);
+}
+
+/// The rules in effect
+pub fn rules() -> Rules {
+    RULES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .unwrap_or_default()
+}
+
+/// `url` normalized per RFC 3986 and without tracking parameters, in a form
+/// fit to store. URLs that don't parse come back trimmed.
+pub fn normalize(url: &str) -> String {
+    normalize_with(url, &rules())
+}
+
+/// [`normalize`] with the given rules
+pub fn normalize_with(url: &str, rules: &Rules) -> String {
+    match parse_normalized(url, rules) {
+        Some(parsed) => parsed.to_string(),
+        None => url.trim().to_string(),
+    }
+}
+
+/// What `url` is compared by: normalized, then with the `www.` and
+/// trailing-slash rules applied. Two URLs with the same key are the same page.
+pub fn key(url: &str) -> String {
+    key_with(url, &rules())
+}
+
+/// [`key`] with the given rules
+pub fn key_with(url: &str, rules: &Rules) -> String {
+    let Some(mut parsed) = parse_normalized(url, rules) else {
+        return url.trim().to_string();
+    };
+    if rules.ignore_www {
+        let bare = parsed
+            .host_str()
+            .and_then(|host| host.strip_prefix("www."))
+            .filter(|rest| rest.contains('.'))
+            .map(str::to_string);
+        if let Some(bare) = bare {
+            // Only fails for hosts that can't change, which have no `www.`
+            let _ = parsed.set_host(Some(&bare));
+        }
+    }
+    if rules.ignore_trailing_slash && !parsed.cannot_be_a_base() {
+        let path = parsed.path();
+        if path.len() > 1 && path.ends_with('/') {
+            let trimmed = path.trim_end_matches('/').to_string();
+            parsed.set_path(&trimmed);
+        }
+    }
+    parsed.to_string()
+}
+
+/// Second-level labels under which country domains register names, like
+/// `co.uk`. Not the whole public suffix list, but the common cases.
+const SECOND_LEVEL_SUFFIXES: &[&str] = &["ac", "co", "com", "edu", "gov", "net", "ne", "or", "org"];
+
+/// The part of a URL's host someone registered: `example.co.uk` for
+/// `https://news.example.co.uk/a`. IP addresses and single-label hosts are
+/// returned whole.
+pub fn registrable_domain(url: &str) -> Option<String> {
+    let parsed = Url::parse(url.trim()).ok()?;
+    let host = match parsed.host()? {
+        url::Host::Domain(host) => host.trim_end_matches('.').to_lowercase(),
+        ip => return Some(ip.to_string()),
+    };
+    let labels: Vec<&str> = host.split('.').collect();
+    let keep = match labels.as_slice() {
+        [.., second, top]
+            if top.len() == 2 && labels.len() > 2 && SECOND_LEVEL_SUFFIXES.contains(second) =>
+        {
+            3
+        }
+        _ => 2,
+    };
+    Some(labels[labels.len().saturating_sub(keep)..].join("."))
+}
+
+/// A set of bookmarks that are the same page
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Duplicates {
+    /// The URL they share, as compared
+    pub url: String,
+    /// Oldest first
+    pub ids: Vec<String>,
+}
+
+/// Bookmarks in `data` saved more than once under URLs with the same key
+pub fn duplicates(data: &BookmarksData) -> Vec<Duplicates> {
+    let rules = rules();
+    let mut bookmarks: Vec<_> = data
+        .get_bookmarks()
+        .into_iter()
+        .filter_map(|resource| match resource {
+            Resource::Bookmark { id, attributes, .. } => Some((attributes.created, id, attributes)),
+            _ => None,
+        })
+        .collect();
+    bookmarks.sort_by_key(|(created, _, _)| *created);
+
+    let mut groups: Vec<Duplicates> = Vec::new();
+    let mut by_key: HashMap<String, usize> = HashMap::new();
+    for (_, id, attributes) in bookmarks {
+        let key = key_with(&attributes.url, &rules);
+        if let Some(&index) = by_key.get(&key) {
+            groups[index].ids.push(id.clone());
+        } else {
+            by_key.insert(key.clone(), groups.len());
+            groups.push(Duplicates {
+                url: key,
+                ids: vec![id.clone()],
+            });
+        }
+    }
+    groups.retain(|group| group.ids.len() > 1);
+    groups
+}
+
+fn parse_normalized(url: &str, rules: &Rules) -> Option<Url> {
+    // Scheme and host case, default ports, and dot segments are handled by
+    // parsing
+    let mut parsed = Url::parse(url.trim()).ok()?;
+
+    if !parsed.cannot_be_a_base() {
+        let path = normalize_percent_encoding(parsed.path());
+        parsed.set_path(&path);
+    }
+
+    let query = parsed.query().map(|query| {
+        query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| {
+                url::form_urlencoded::parse(pair.as_bytes())
+                    .next()
+                    .is_none_or(|(name, _)| !rules.is_tracking(&name))
+            })
+            .map(normalize_percent_encoding)
+            .collect::<Vec<_>>()
+            .join("&")
+    });
+    parsed.set_query(query.as_deref().filter(|query| !query.is_empty()));
+
+    let fragment = parsed.fragment().map(normalize_percent_encoding);
+    parsed.set_fragment(fragment.as_deref().filter(|fragment| !fragment.is_empty()));
+    Some(parsed)
+}
+
+/// Decode escaped unreserved characters and uppercase the remaining escapes
+/// (RFC 3986 §6.2.2.2)
+fn normalize_percent_encoding(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte)
+                if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') =>
+            {
+                out.push(char::from(byte));
+                i += 3;
+            }
+            Some(byte) => {
+                let _ = write!(out, "%{byte:02X}");
+                i += 3;
+            }
+            None => {
+                let ch = text[i..].chars().next().unwrap_or_default();
+                out.push(ch);
+                i += ch.len_utf8();
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rfc3986_normalization() {
+        let rules = Rules::default();
+        for (url, expected) in [
+            (
+                "HTTPS://Example.COM:443/a/./b/../c",
+                "https://example.com/a/c",
+            ),
+            ("http://example.com:80", "http://example.com/"),
+            (
+                "https://example.com/%7euser/%2fx%2f",
+                "https://example.com/~user/%2Fx%2F",
+            ),
+            ("https://example.com/?", "https://example.com/"),
+            ("https://example.com/#", "https://example.com/"),
+            ("  https://example.com/a  ", "https://example.com/a"),
+            ("not a url", "not a url"),
+        ] {
+            assert_eq!(normalize_with(url, &rules), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn test_tracking_parameters() {
+        let url = "https://example.com/post?utm_source=x&id=7&FBCLID=abc&utm_medium=y#top";
+        let rules = Rules::default();
+        assert_eq!(
+            normalize_with(url, &rules),
+            "https://example.com/post?id=7#top"
+        );
+
+        let custom = rules
+            .with(Update {
+                tracking_params: Some(vec!["ID".to_string()]),
+                ..Update::default()
+            })
+            .unwrap();
+        assert_eq!(normalize_with(url, &custom), "https://example.com/post#top");
+
+        let kept = rules
+            .with(Update {
+                strip_tracking: Some(false),
+                ..Update::default()
+            })
+            .unwrap();
+        assert_eq!(normalize_with(url, &kept), url);
+    }
+
+    #[test]
+    fn test_keys() {
+        let rules = Rules::default();
+        let same = [
+            "https://www.example.com/docs/",
+            "https://example.com/docs",
+            "HTTPS://EXAMPLE.com/docs/?utm_campaign=launch",
+        ];
+        for url in same {
+            assert_eq!(key_with(url, &rules), "https://example.com/docs", "{url}");
+        }
+        // Different pages, schemes, and hosts that only start with www
+        assert_ne!(
+            key_with("http://example.com/docs", &rules),
+            key_with(same[0], &rules)
+        );
+        assert_eq!(key_with("https://www.com/", &rules), "https://www.com/");
+
+        let strict = rules
+            .with(Update {
+                ignore_www: Some(false),
+                ignore_trailing_slash: Some(false),
+                ..Update::default()
+            })
+            .unwrap();
+        assert_eq!(key_with(same[0], &strict), "https://www.example.com/docs/");
+    }
+
+    #[test]
+    fn test_rules_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        assert_eq!(Rules::load(&repo), Rules::default());
+
+        let changed = Rules::default()
+            .with(Update {
+                tracking_params: Some(vec!["Ref".to_string(), "ref".to_string()]),
+                ignore_www: Some(false),
+                ..Update::default()
+            })
+            .unwrap();
+        assert_eq!(changed.tracking_params, vec!["ref".to_string()]);
+        changed.save(&repo).unwrap();
+        assert_eq!(Rules::load(&repo), changed);
+        assert!(Rules::default()
+            .with(Update {
+                tracking_params: Some(vec!["a b".to_string()]),
+                ..Update::default()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_registrable_domain() {
+        for (url, domain) in [
+            ("https://news.example.com/a", "example.com"),
+            ("https://example.com", "example.com"),
+            ("https://www.bbc.co.uk/news", "bbc.co.uk"),
+            ("https://co.uk/", "co.uk"),
+            ("https://a.b.example.io", "example.io"),
+            ("http://localhost:8080/", "localhost"),
+            ("http://192.168.1.1/admin", "192.168.1.1"),
+        ] {
+            assert_eq!(registrable_domain(url).as_deref(), Some(domain), "{url}");
+        }
+        assert_eq!(registrable_domain("not a url"), None);
+        assert_eq!(registrable_domain("mailto:someone@example.com"), None);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut data = BookmarksData::new();
+        for url in [
+            "https://example.com/a",
+            "https://www.example.com/a/",
+            "https://example.com/b",
+            "https://example.com/a?utm_source=feed",
+        ] {
+            data.add_bookmark(create_bookmark(url.to_string(), url.to_string(), vec![]))
+                .unwrap();
+        }
+        let groups = duplicates(&data);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].url, "https://example.com/a");
+        assert_eq!(groups[0].ids.len(), 3);
+    }
+}
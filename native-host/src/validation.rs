@@ -0,0 +1,339 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::git::GitRepo;
+
+/// Limits, kept in the repository's local git config
+const MAX_URL_LENGTH_KEY: &str = "webtags.maxUrlLength";
+const MAX_TITLE_LENGTH_KEY: &str = "webtags.maxTitleLength";
+const MAX_TAG_LENGTH_KEY: &str = "webtags.maxTagLength";
+const MAX_TAG_DEPTH_KEY: &str = "webtags.maxTagDepth";
+/// Space-separated schemes allowed besides http and https
+const ALLOWED_SCHEMES_KEY: &str = "webtags.allowedSchemes";
+
+/// Schemes every bookmark may use
+pub const DEFAULT_SCHEMES: [&str; 2] = ["http", "https"];
+
+/// Schemes that run code or smuggle content, which no allowlist can enable
+pub const BLOCKED_SCHEMES: [&str; 3] = ["javascript", "data", "vbscript"];
+
+/// How long bookmark URLs, titles, and tag names may be, in bytes, how
+/// deeply tags may nest, and which URL schemes bookmarks may use
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ValidationConfig {
+    pub max_url_length: usize,
+    pub max_title_length: usize,
+    pub max_tag_length: usize,
+    /// Tags in the longest breadcrumb allowed, so 1 allows only top-level tags
+    pub max_tag_depth: usize,
+    /// Schemes allowed besides http and https; `None` allows any that
+    /// isn't blocked
+    pub allowed_schemes: Option<Vec<String>>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            max_url_length: 2048,
+            max_title_length: 500,
+            max_tag_length: 100,
+            max_tag_depth: 10,
+            allowed_schemes: Some(Vec::new()),
+        }
+    }
+}
+
+/// Limits to change with [`ValidationConfig::with`]; `None` leaves one as it is
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Update {
+    pub max_url_length: Option<usize>,
+    pub max_title_length: Option<usize>,
+    pub max_tag_length: Option<usize>,
+    pub max_tag_depth: Option<usize>,
+    /// Replaces the allowlist; empty allows only http and https again
+    pub allowed_schemes: Option<Vec<String>>,
+}
+
+impl Update {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl ValidationConfig {
+    /// The most each limit can be relaxed to. Files are read against
+    /// these, so a device with relaxed limits can't write what another
+    /// device refuses to open.
+    pub const MAXIMUM: Self = Self {
+        max_url_length: 32_768,
+        max_title_length: 4096,
+        max_tag_length: 255,
+        max_tag_depth: 32,
+        allowed_schemes: None,
+    };
+
+    pub fn load(repo: &GitRepo) -> Self {
+        let defaults = Self::default();
+        let limit = |key: &str, default: usize, maximum: usize| {
+            repo.local_config(key)
+                .and_then(|value| value.parse().ok())
+                .filter(|&value| (1..=maximum).contains(&value))
+                .unwrap_or(default)
+        };
+        let allowed_schemes = repo
+            .local_config(ALLOWED_SCHEMES_KEY)
+            .map(|value| {
+                value
+                    .split_whitespace()
+                    .filter_map(|scheme| normalize_scheme(scheme).ok().flatten())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            max_url_length: limit(
+                MAX_URL_LENGTH_KEY,
+                defaults.max_url_length,
+                Self::MAXIMUM.max_url_length,
+            ),
+            max_title_length: limit(
+                MAX_TITLE_LENGTH_KEY,
+                defaults.max_title_length,
+                Self::MAXIMUM.max_title_length,
+            ),
+            max_tag_length: limit(
+                MAX_TAG_LENGTH_KEY,
+                defaults.max_tag_length,
+                Self::MAXIMUM.max_tag_length,
+            ),
+            max_tag_depth: limit(
+                MAX_TAG_DEPTH_KEY,
+                defaults.max_tag_depth,
+                Self::MAXIMUM.max_tag_depth,
+            ),
+            allowed_schemes: Some(allowed_schemes),
+        }
+    }
+
+    /// The limits with `update` applied, after checking it's in range
+    pub fn with(&self, update: Update) -> Result<Self> {
+        let check = |name: &str, value: Option<usize>, current: usize, maximum: usize| match value {
+            Some(value) if !(1..=maximum).contains(&value) => {
+                anyhow::bail!("{name} must be between 1 and {maximum}")
+            }
+            Some(value) => Ok(value),
+            None => Ok(current),
+        };
+        let allowed_schemes = match update.allowed_schemes {
+            Some(schemes) => {
+                let mut allowed = Vec::new();
+                for scheme in schemes {
+                    if let Some(scheme) = normalize_scheme(&scheme)? {
+                        if !allowed.contains(&scheme) {
+                            allowed.push(scheme);
+                        }
+                    }
+                }
+                Some(allowed)
+            }
+            None => self.allowed_schemes.clone(),
+        };
+        Ok(Self {
+            max_url_length: check(
+                "max_url_length",
+                update.max_url_length,
+                self.max_url_length,
+                Self::MAXIMUM.max_url_length,
+            )?,
+            max_title_length: check(
+                "max_title_length",
+                update.max_title_length,
+                self.max_title_length,
+                Self::MAXIMUM.max_title_length,
+            )?,
+            max_tag_length: check(
+                "max_tag_length",
+                update.max_tag_length,
+                self.max_tag_length,
+                Self::MAXIMUM.max_tag_length,
+            )?,
+            max_tag_depth: check(
+                "max_tag_depth",
+                update.max_tag_depth,
+                self.max_tag_depth,
+                Self::MAXIMUM.max_tag_depth,
+            )?,
+            allowed_schemes,
+        })
+    }
+
+    /// Whether bookmarks may use `scheme` (lowercase, as `url` parses it)
+    pub fn allows_scheme(&self, scheme: &str) -> bool {
+        if BLOCKED_SCHEMES.contains(&scheme) {
+            return false;
+        }
+        DEFAULT_SCHEMES.contains(&scheme)
+            || self
+                .allowed_schemes
+                .as_ref()
+                .is_none_or(|allowed| allowed.iter().any(|s| s == scheme))
+    }
+
+    /// Every scheme bookmarks may use, for messages
+    pub fn scheme_list(&self) -> String {
+        match &self.allowed_schemes {
+            Some(allowed) => DEFAULT_SCHEMES
+                .iter()
+                .copied()
+                .chain(allowed.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => "any but javascript, data, and vbscript".to_string(),
+        }
+    }
+
+    /// Store the limits for `repo`, leaving defaults unset
+    pub fn save(&self, repo: &GitRepo) -> Result<()> {
+        let defaults = Self::default();
+        let entries = [
+            (
+                MAX_URL_LENGTH_KEY,
+                self.max_url_length,
+                defaults.max_url_length,
+            ),
+            (
+                MAX_TITLE_LENGTH_KEY,
+                self.max_title_length,
+                defaults.max_title_length,
+            ),
+            (
+                MAX_TAG_LENGTH_KEY,
+                self.max_tag_length,
+                defaults.max_tag_length,
+            ),
+            (
+                MAX_TAG_DEPTH_KEY,
+                self.max_tag_depth,
+                defaults.max_tag_depth,
+            ),
+        ];
+        for (key, value, default) in entries {
+            let value = (value != default).then(|| value.to_string());
+            repo.set_local_config(key, value.as_deref())?;
+        }
+        let schemes = self
+            .allowed_schemes
+            .as_ref()
+            .filter(|allowed| !allowed.is_empty())
+            .map(|allowed| allowed.join(" "));
+        repo.set_local_config(ALLOWED_SCHEMES_KEY, schemes.as_deref())?;
+        Ok(())
+    }
+}
+
+/// `scheme` in lowercase without any trailing colon, or `None` for one
+/// that's always allowed
+fn normalize_scheme(scheme: &str) -> Result<Option<String>> {
+    let scheme = scheme.trim().trim_end_matches(':').to_ascii_lowercase();
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    if !valid {
+        anyhow::bail!("Invalid URL scheme '{scheme}'");
+    }
+    if BLOCKED_SCHEMES.contains(&scheme.as_str()) {
+        anyhow::bail!("The {scheme}: scheme can't be allowed");
+    }
+    Ok((!DEFAULT_SCHEMES.contains(&scheme.as_str())).then_some(scheme))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_limits_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        assert_eq!(ValidationConfig::load(&repo), ValidationConfig::default());
+
+        let raised = ValidationConfig::default()
+            .with(Update {
+                max_url_length: Some(8192),
+                max_tag_length: Some(200),
+                max_tag_depth: Some(4),
+                ..Update::default()
+            })
+            .unwrap();
+        raised.save(&repo).unwrap();
+        let loaded = ValidationConfig::load(&repo);
+        assert_eq!(loaded, raised);
+        assert_eq!(loaded.max_title_length, 500);
+
+        // Nothing past the ceilings, and nothing that would reject every value
+        let update = |max_url_length, max_title_length, max_tag_length| Update {
+            max_url_length,
+            max_title_length,
+            max_tag_length,
+            max_tag_depth: None,
+            allowed_schemes: None,
+        };
+        assert!(loaded.with(update(Some(0), None, None)).is_err());
+        assert!(loaded.with(update(None, Some(4097), None)).is_err());
+        assert!(loaded.with(update(None, None, Some(256))).is_err());
+        let deeper = Update {
+            max_tag_depth: Some(33),
+            ..Update::default()
+        };
+        assert!(loaded.with(deeper).is_err());
+
+        // Hand-edited values out of range fall back to the default
+        repo.set_local_config(MAX_URL_LENGTH_KEY, Some("1000000"))
+            .unwrap();
+        assert_eq!(ValidationConfig::load(&repo).max_url_length, 2048);
+    }
+
+    #[test]
+    fn test_scheme_allowlist() {
+        let dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        let defaults = ValidationConfig::default();
+        assert!(defaults.allows_scheme("https"));
+        assert!(!defaults.allows_scheme("ftp"));
+
+        let schemes = |schemes: &[&str]| Update {
+            allowed_schemes: Some(schemes.iter().map(ToString::to_string).collect()),
+            ..Update::default()
+        };
+        let allowed = defaults
+            .with(schemes(&["FTP:", "magnet", "obsidian", "https", "ftp"]))
+            .unwrap();
+        assert_eq!(
+            allowed.allowed_schemes.as_deref(),
+            Some(
+                &[
+                    "ftp".to_string(),
+                    "magnet".to_string(),
+                    "obsidian".to_string()
+                ][..]
+            )
+        );
+        assert!(allowed.allows_scheme("magnet"));
+        assert!(!allowed.allows_scheme("ipfs"));
+        assert_eq!(allowed.scheme_list(), "http, https, ftp, magnet, obsidian");
+
+        allowed.save(&repo).unwrap();
+        assert_eq!(ValidationConfig::load(&repo), allowed);
+        let cleared = allowed.with(schemes(&[])).unwrap();
+        cleared.save(&repo).unwrap();
+        assert_eq!(ValidationConfig::load(&repo), defaults);
+
+        // Script and inline content schemes stay blocked
+        assert!(defaults.with(schemes(&["javascript"])).is_err());
+        assert!(defaults.with(schemes(&["Data:"])).is_err());
+        assert!(defaults.with(schemes(&["no spaces"])).is_err());
+        assert!(!ValidationConfig::MAXIMUM.allows_scheme("javascript"));
+        assert!(ValidationConfig::MAXIMUM.allows_scheme("ipfs"));
+    }
+}
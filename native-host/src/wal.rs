@@ -0,0 +1,339 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::git::GitRepo;
+use crate::storage;
+
+/// Intent record for a write to bookmarks.json that hasn't been committed
+/// yet. Kept inside `.git` so it is per-clone and never committed.
+pub const INTENT_FILE: &str = "write_intent.json";
+
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+
+/// Left behind by git if it is killed while writing the index
+const INDEX_LOCK: &str = "index.lock";
+
+/// Where a damaged bookmarks.json is moved to (inside `.git`) before a
+/// readable version is put back, in case it was a hand edit worth fixing
+pub const CORRUPT_FILE: &str = "bookmarks.json.corrupt";
+
+/// How many commits back [`restore_from_history`] looks for a readable version
+pub const HISTORY_SEARCH_DEPTH: usize = 50;
+
+/// A write that was started, recorded before bookmarks.json is touched and
+/// cleared once the result is committed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Intent {
+    pub started: DateTime<Utc>,
+    /// Commit checked out when the write began; `None` before the first
+    pub head: Option<String>,
+}
+
+/// What [`recover`] did about a write that never finished
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Recovery {
+    /// The commit went through; only the record was left
+    AlreadyCommitted,
+    /// The new file was complete, so it was committed
+    RolledForward,
+    /// The file was damaged, so the last readable version was put back and
+    /// the damaged copy moved to [`CORRUPT_FILE`]
+    RolledBack,
+}
+
+/// What [`restore_from_history`] put back
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Restored {
+    /// Commit the readable version came from
+    pub commit: String,
+    pub committed_at: DateTime<Utc>,
+    /// Newer commits whose version couldn't be read either
+    pub skipped: usize,
+    /// Where the damaged file was kept
+    pub corrupt_file: PathBuf,
+}
+
+fn intent_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(".git").join(INTENT_FILE)
+}
+
+impl Intent {
+    /// The unfinished write for a repository, if any
+    pub fn load<P: AsRef<Path>>(repo_path: P) -> Result<Option<Self>> {
+        let path = intent_path(repo_path.as_ref());
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path).context("Failed to read write intent")?;
+        match serde_json::from_str(&content) {
+            Ok(intent) => Ok(Some(intent)),
+            // Torn while being recorded, so bookmarks.json wasn't touched yet
+            Err(e) => {
+                log::warn!("Ignoring unreadable write intent: {e}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Record that bookmarks.json is about to change. A write already open
+/// (batched writes not committed yet) is kept, since its `head` is the one
+/// to recover from. Flushed to disk before returning.
+pub fn begin(repo: &GitRepo) -> Result<()> {
+    if Intent::load(repo.path())?.is_some() {
+        return Ok(());
+    }
+    let intent = Intent {
+        started: Utc::now(),
+        head: repo.head_id().map(|id| id.to_string()),
+    };
+    let json = serde_json::to_vec(&intent).context("Failed to serialize write intent")?;
+    storage::write_atomic(&intent_path(repo.path()), &json).context("Failed to write write intent")
+}
+
+/// Clear the record once the write is committed
+pub fn complete<P: AsRef<Path>>(repo_path: P) -> Result<()> {
+    let path = intent_path(repo_path.as_ref());
+    if path.exists() {
+        fs::remove_file(&path).context("Failed to remove write intent")?;
+    }
+    Ok(())
+}
+
+/// Finish or undo a write the host was killed in the middle of (e.g. by the
+/// browser shutting down), so the file, the index, and history agree again.
+/// Also puts back the committed version of a file that is damaged with no
+/// write in progress. The caller holds the repository lock, so no other git
+/// process is running.
+pub fn recover(repo: &GitRepo) -> Result<Option<Recovery>> {
+    let Some(intent) = Intent::load(repo.path())? else {
+        if is_damaged(repo.path()) && repo.has_commits() {
+            restore_from_history(repo, is_well_formed)?;
+            return Ok(Some(Recovery::RolledBack));
+        }
+        return Ok(None);
+    };
+
+    let index_lock = repo.path().join(".git").join(INDEX_LOCK);
+    if index_lock.exists() {
+        fs::remove_file(&index_lock).context("Failed to remove stale index lock")?;
+    }
+
+    let recovery = if repo.head_id().map(|id| id.to_string()) != intent.head {
+        Recovery::AlreadyCommitted
+    } else if !is_damaged(repo.path()) {
+        repo.add_file(BOOKMARKS_FILE)?;
+        if repo.has_staged_changes()? {
+            repo.commit(&format!(
+                "Recover write interrupted at {}",
+                intent.started.format("%Y-%m-%d %H:%M UTC")
+            ))?;
+            Recovery::RolledForward
+        } else {
+            Recovery::AlreadyCommitted
+        }
+    } else if repo.has_commits() {
+        restore_from_history(repo, is_well_formed)?;
+        Recovery::RolledBack
+    } else {
+        // Never committed, so there's nothing to go back to
+        fs::rename(
+            repo.path().join(BOOKMARKS_FILE),
+            repo.path().join(".git").join(CORRUPT_FILE),
+        )
+        .context("Failed to move damaged bookmarks file aside")?;
+        Recovery::RolledBack
+    };
+
+    complete(repo.path())?;
+    Ok(Some(recovery))
+}
+
+/// Whether a repository's bookmarks.json exists but isn't even complete
+/// JSON, as a crash or power loss can leave it. Encrypted files are JSON
+/// too, so this works without the key and never mistakes a locked file for
+/// a damaged one.
+pub fn is_damaged<P: AsRef<Path>>(repo_path: P) -> bool {
+    let path = repo_path.as_ref().join(BOOKMARKS_FILE);
+    path.exists() && !fs::read(&path).is_ok_and(|content| is_well_formed(&content))
+}
+
+fn is_well_formed(content: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(content).is_ok()
+}
+
+/// Replace a damaged bookmarks.json with the newest version in the last
+/// [`HISTORY_SEARCH_DEPTH`] commits that `readable` accepts, keeping the
+/// damaged file as [`CORRUPT_FILE`] inside `.git`. The result is committed
+/// if it differs from HEAD (e.g. the damage had been committed too).
+/// Nothing is touched if no readable version is found.
+pub fn restore_from_history(repo: &GitRepo, readable: impl Fn(&[u8]) -> bool) -> Result<Restored> {
+    let mut skipped = 0;
+    let mut found = None;
+    for commit in repo.history(HISTORY_SEARCH_DEPTH)? {
+        // Older commits predate the file
+        let Some(content) = repo.read_file_at(&commit.id, BOOKMARKS_FILE)? else {
+            break;
+        };
+        if readable(&content) {
+            found = Some((commit, content));
+            break;
+        }
+        skipped += 1;
+    }
+    let Some((commit, content)) = found else {
+        anyhow::bail!("No readable {BOOKMARKS_FILE} in the last {HISTORY_SEARCH_DEPTH} commits");
+    };
+
+    let path = repo.path().join(BOOKMARKS_FILE);
+    let corrupt_file = repo.path().join(".git").join(CORRUPT_FILE);
+    if path.exists() {
+        fs::rename(&path, &corrupt_file).context("Failed to move damaged bookmarks file aside")?;
+    }
+    storage::write_atomic(&path, &content).context("Failed to restore bookmarks file")?;
+    repo.add_file(BOOKMARKS_FILE)?;
+    if repo.has_staged_changes()? {
+        repo.commit(&format!(
+            "Restore bookmarks from {}",
+            &commit.id[..commit.id.len().min(7)]
+        ))?;
+    }
+
+    Ok(Restored {
+        commit: commit.id,
+        committed_at: commit.time,
+        skipped,
+        corrupt_file,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, BookmarksData};
+    use tempfile::TempDir;
+
+    fn committed_repo() -> (TempDir, GitRepo) {
+        let dir = TempDir::new().unwrap();
+        let repo = GitRepo::init(dir.path()).unwrap();
+        storage::write_to_file(dir.path().join(BOOKMARKS_FILE), &BookmarksData::new()).unwrap();
+        repo.add_file(BOOKMARKS_FILE).unwrap();
+        repo.commit("Initial").unwrap();
+        (dir, repo)
+    }
+
+    fn one_bookmark() -> BookmarksData {
+        let mut data = BookmarksData::new();
+        data.add_bookmark(create_bookmark(
+            "https://example.com".to_string(),
+            "Example".to_string(),
+            vec![],
+        ))
+        .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_nothing_to_recover() {
+        let (_dir, repo) = committed_repo();
+        assert_eq!(recover(&repo).unwrap(), None);
+    }
+
+    #[test]
+    fn test_completed_write_clears_intent() {
+        let (dir, repo) = committed_repo();
+        begin(&repo).unwrap();
+        let first = Intent::load(dir.path()).unwrap().unwrap();
+        // A second write in the same batch keeps the original record
+        begin(&repo).unwrap();
+        assert_eq!(Intent::load(dir.path()).unwrap(), Some(first));
+
+        storage::write_to_file(dir.path().join(BOOKMARKS_FILE), &one_bookmark()).unwrap();
+        repo.add_file(BOOKMARKS_FILE).unwrap();
+        repo.commit("Add").unwrap();
+        // Killed before the record was cleared
+        assert_eq!(recover(&repo).unwrap(), Some(Recovery::AlreadyCommitted));
+        assert!(Intent::load(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_written_but_uncommitted_rolls_forward() {
+        let (dir, repo) = committed_repo();
+        begin(&repo).unwrap();
+        storage::write_to_file(dir.path().join(BOOKMARKS_FILE), &one_bookmark()).unwrap();
+        fs::write(dir.path().join(".git").join(INDEX_LOCK), "").unwrap();
+
+        assert_eq!(recover(&repo).unwrap(), Some(Recovery::RolledForward));
+        assert!(repo.is_clean().unwrap());
+        let committed = crate::sync::bookmarks_at(&repo, "HEAD", false).unwrap();
+        assert_eq!(committed.get_bookmarks().len(), 1);
+        assert!(Intent::load(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_damaged_file_rolls_back() {
+        let (dir, repo) = committed_repo();
+        begin(&repo).unwrap();
+        fs::write(dir.path().join(BOOKMARKS_FILE), "{\"data\": [").unwrap();
+
+        assert_eq!(recover(&repo).unwrap(), Some(Recovery::RolledBack));
+        assert!(repo.is_clean().unwrap());
+        let restored = storage::read_from_file(dir.path().join(BOOKMARKS_FILE)).unwrap();
+        assert!(restored.get_bookmarks().is_empty());
+        let corrupt = dir.path().join(".git").join(CORRUPT_FILE);
+        assert_eq!(fs::read_to_string(corrupt).unwrap(), "{\"data\": [");
+    }
+
+    #[test]
+    fn test_restore_skips_damaged_commits() {
+        let (dir, repo) = committed_repo();
+        let good = repo.head_id().unwrap().to_string();
+        storage::write_to_file(dir.path().join(BOOKMARKS_FILE), &one_bookmark()).unwrap();
+        repo.add_file(BOOKMARKS_FILE).unwrap();
+        repo.commit("Add").unwrap();
+        // The damage made it into a commit before anyone noticed
+        fs::write(dir.path().join(BOOKMARKS_FILE), "not json").unwrap();
+        repo.add_file(BOOKMARKS_FILE).unwrap();
+        repo.commit("Damaged").unwrap();
+
+        // Only the first version is good enough for this caller
+        let restored = restore_from_history(&repo, |content| {
+            storage::parse_with_encryption(
+                std::str::from_utf8(content).unwrap(),
+                BOOKMARKS_FILE,
+                false,
+            )
+            .is_ok_and(|data| data.get_bookmarks().is_empty())
+        })
+        .unwrap();
+        assert_eq!(restored.commit, good);
+        assert_eq!(restored.skipped, 2);
+        assert!(repo.is_clean().unwrap());
+        assert!(repo
+            .get_last_commit_message()
+            .unwrap()
+            .starts_with("Restore bookmarks from"));
+        assert!(!is_damaged(dir.path()));
+
+        // Nothing readable: left alone
+        fs::write(dir.path().join(BOOKMARKS_FILE), "").unwrap();
+        assert!(restore_from_history(&repo, |_| false).is_err());
+        assert!(is_damaged(dir.path()));
+    }
+
+    #[test]
+    fn test_empty_file_falls_back_to_committed_version() {
+        // What a power loss before the data was flushed can leave behind
+        let (dir, repo) = committed_repo();
+        fs::write(dir.path().join(BOOKMARKS_FILE), "").unwrap();
+
+        assert_eq!(recover(&repo).unwrap(), Some(Recovery::RolledBack));
+        assert!(storage::read_from_file(dir.path().join(BOOKMARKS_FILE)).is_ok());
+        assert!(repo.is_clean().unwrap());
+    }
+}
@@ -0,0 +1,307 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::linkcheck::LinkChange;
+use crate::storage::{BookmarksData, Resource};
+
+/// Internet Archive's availability API
+/// (<https://archive.org/help/wayback_api.php>)
+const AVAILABILITY_API: &str = "https://archive.org/wayback/available";
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_CONCURRENT_LOOKUPS: usize = 4;
+
+#[derive(Debug, Deserialize)]
+struct Availability {
+    #[serde(default)]
+    archived_snapshots: ArchivedSnapshots,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct ArchivedSnapshots {
+    closest: Option<Snapshot>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Snapshot {
+    #[serde(default)]
+    available: bool,
+    url: String,
+    /// HTTP status the page answered with when it was archived
+    #[serde(default)]
+    status: String,
+}
+
+/// Finds archived copies of pages in the Wayback Machine
+#[derive(Clone)]
+pub struct WaybackClient {
+    client: Client,
+    api_url: String,
+}
+
+impl WaybackClient {
+    pub fn new(client: Client) -> Self {
+        Self::with_api_url(client, AVAILABILITY_API)
+    }
+
+    /// Ask another availability endpoint, for tests
+    pub fn with_api_url(client: Client, api_url: &str) -> Self {
+        Self {
+            client,
+            api_url: api_url.to_string(),
+        }
+    }
+
+    /// URL of the snapshot of `url` nearest to `around`, if one was archived
+    /// while the page still worked
+    pub async fn closest(
+        &self,
+        url: &str,
+        around: Option<DateTime<Utc>>,
+    ) -> Result<Option<String>> {
+        let mut query = vec![("url", url.to_string())];
+        if let Some(around) = around {
+            query.push(("timestamp", around.format("%Y%m%d").to_string()));
+        }
+        let response = self
+            .client
+            .get(&self.api_url)
+            .query(&query)
+            .header("User-Agent", "WebTags")
+            .timeout(LOOKUP_TIMEOUT)
+            .send()
+            .await
+            .context("Failed to reach the Wayback Machine")?
+            .error_for_status()
+            .context("Wayback Machine lookup failed")?;
+        let availability: Availability = response
+            .json()
+            .await
+            .context("Failed to parse Wayback Machine response")?;
+
+        Ok(availability
+            .archived_snapshots
+            .closest
+            .filter(|snapshot| snapshot.available && snapshot.status.starts_with('2'))
+            .map(|snapshot| match snapshot.url.strip_prefix("http://") {
+                Some(rest) => format!("https://{rest}"),
+                None => snapshot.url,
+            }))
+    }
+
+    /// Look up snapshots for `(bookmark id, url, failing since)` links,
+    /// returning the bookmark IDs that have one. Links that can't be looked
+    /// up are skipped, to be tried again when they're next found dead.
+    pub async fn closest_all(
+        &self,
+        links: Vec<(String, String, Option<DateTime<Utc>>)>,
+    ) -> Vec<(String, String)> {
+        let mut pending = links.into_iter();
+        let mut lookups = JoinSet::new();
+        let mut found = Vec::new();
+        loop {
+            while lookups.len() < MAX_CONCURRENT_LOOKUPS {
+                let Some((id, url, around)) = pending.next() else {
+                    break;
+                };
+                let wayback = self.clone();
+                lookups.spawn(async move { (id, wayback.closest(&url, around).await) });
+            }
+            let Some(finished) = lookups.join_next().await else {
+                break;
+            };
+            match finished {
+                Ok((id, Ok(Some(snapshot)))) => found.push((id, snapshot)),
+                Ok((id, Err(e))) => log::warn!("No archived copy for {id}: {e:#}"),
+                _ => {}
+            }
+        }
+        found
+    }
+}
+
+/// Newly dead links to look up: those without an archived copy yet, leaving
+/// out private bookmarks, whose URLs aren't sent to the Internet Archive
+pub fn lookup_targets(
+    newly_dead: &[LinkChange],
+    data: &BookmarksData,
+) -> Vec<(String, String, Option<DateTime<Utc>>)> {
+    newly_dead
+        .iter()
+        .filter(|change| change.archive_url.is_none())
+        .filter(|change| {
+            !matches!(
+                data.get(&change.id),
+                Some(Resource::Bookmark { attributes, .. }) if attributes.private
+            )
+        })
+        .map(|change| (change.id.clone(), change.url.clone(), change.failing_since))
+        .collect()
+}
+
+/// Set a bookmark's `archive_url`, returning whether it changed
+pub fn set_archive_url(data: &mut BookmarksData, bookmark_id: &str, archive_url: &str) -> bool {
+    data.data_mut().iter_mut().any(|resource| match resource {
+        Resource::Bookmark { id, attributes, .. }
+            if id == bookmark_id && attributes.archive_url.as_deref() != Some(archive_url) =>
+        {
+            attributes.archive_url = Some(archive_url.to_string());
+            attributes.modified = Some(Utc::now());
+            true
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_bookmark;
+    use wiremock::matchers::{method, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn availability(url: &str, status: &str) -> serde_json::Value {
+        serde_json::json!({
+            "url": "example.com/gone",
+            "archived_snapshots": {
+                "closest": {
+                    "available": true,
+                    "url": url,
+                    "timestamp": "20240101000000",
+                    "status": status
+                }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_closest_snapshot() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("url", "https://example.com/gone"))
+            .and(query_param("timestamp", "20240315"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(availability(
+                "http://web.archive.org/web/20240101000000/https://example.com/gone",
+                "200",
+            )))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("url", "https://example.com/broken"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(availability(
+                "http://web.archive.org/web/20240101000000/https://example.com/broken",
+                "404",
+            )))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("url", "https://example.com/never"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"archived_snapshots": {}})),
+            )
+            .mount(&server)
+            .await;
+
+        let wayback = WaybackClient::with_api_url(Client::new(), &server.uri());
+        let around = "2024-03-15T12:00:00Z".parse().ok();
+        assert_eq!(
+            wayback
+                .closest("https://example.com/gone", around)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("https://web.archive.org/web/20240101000000/https://example.com/gone")
+        );
+        assert_eq!(
+            wayback
+                .closest("https://example.com/broken", None)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            wayback
+                .closest("https://example.com/never", None)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let found = wayback
+            .closest_all(vec![
+                (
+                    "a".to_string(),
+                    "https://example.com/gone".to_string(),
+                    around,
+                ),
+                (
+                    "b".to_string(),
+                    "https://example.com/never".to_string(),
+                    None,
+                ),
+            ])
+            .await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "a");
+    }
+
+    #[test]
+    fn test_set_archive_url() {
+        let mut data = BookmarksData::new();
+        let bookmark = create_bookmark(
+            "https://example.com/gone".to_string(),
+            "Gone".to_string(),
+            vec![],
+        );
+        let id = bookmark.id().to_string();
+        data.add_bookmark(bookmark).unwrap();
+
+        let snapshot = "https://web.archive.org/web/2024/https://example.com/gone";
+        assert!(set_archive_url(&mut data, &id, snapshot));
+        assert!(!set_archive_url(&mut data, &id, snapshot));
+        assert!(!set_archive_url(&mut data, "missing", snapshot));
+        match data.get(&id) {
+            Some(Resource::Bookmark { attributes, .. }) => {
+                assert_eq!(attributes.archive_url.as_deref(), Some(snapshot));
+            }
+            _ => panic!("bookmark missing"),
+        }
+    }
+
+    #[test]
+    fn test_private_bookmarks_are_not_looked_up() {
+        let mut data = BookmarksData::new();
+        let mut changes = Vec::new();
+        for (url, private, archive_url) in [
+            ("https://example.com/public", false, None),
+            ("https://example.com/private", true, None),
+            (
+                "https://example.com/archived",
+                false,
+                Some("https://web.archive.org/x"),
+            ),
+        ] {
+            let mut bookmark = create_bookmark(url.to_string(), "Gone".to_string(), vec![]);
+            if let Resource::Bookmark { attributes, .. } = &mut bookmark {
+                attributes.private = private;
+            }
+            changes.push(LinkChange {
+                id: bookmark.id().to_string(),
+                title: "Gone".to_string(),
+                url: url.to_string(),
+                failing_since: None,
+                archive_url: archive_url.map(str::to_string),
+            });
+            data.add_bookmark(bookmark).unwrap();
+        }
+
+        let targets = lookup_targets(&changes, &data);
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].1, "https://example.com/public");
+    }
+}
@@ -0,0 +1,406 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use keyring::Entry;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use crate::diff::{self, ChangeSet, ResourceRef};
+use crate::git::GitRepo;
+use crate::proxy;
+use crate::storage::{BookmarksData, Resource, Visibility};
+
+/// Webhook URL, kept in the repository's local git config
+const URL_KEY: &str = "webtags.webhook.url";
+/// The signing secret is kept in the keychain under the webhook URL
+const KEYRING_SERVICE: &str = "com.webtags.webhook";
+
+/// `sha256=` and the hex HMAC-SHA256 of the body, keyed with the secret
+pub const SIGNATURE_HEADER: &str = "X-WebTags-Signature";
+pub const EVENT_HEADER: &str = "X-WebTags-Event";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Bookmarks listed per kind of change; the counts stay exact
+const MAX_LISTED: usize = 50;
+/// Discord refuses messages longer than 2000 characters
+const MAX_TEXT_CHARS: usize = 1900;
+/// Short enough to guess by brute force offline from a captured signature
+const MIN_SECRET_LENGTH: usize = 16;
+
+/// Webhook to set with `SetConfig`; an empty `url` removes it
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Update {
+    pub url: String,
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// The configured webhook URL
+pub fn configured_url(repo: &GitRepo) -> Option<String> {
+    repo.local_config(URL_KEY)
+}
+
+/// Set or remove the webhook. A new URL needs a secret, stored in the
+/// keychain.
+pub fn configure(repo: &GitRepo, update: &Update) -> Result<()> {
+    if update.url.is_empty() {
+        if let Some(old) = configured_url(repo) {
+            // Already gone is fine
+            let _ = Entry::new(KEYRING_SERVICE, &old).and_then(|entry| entry.delete_password());
+        }
+        return repo.set_local_config(URL_KEY, None);
+    }
+
+    let parsed = url::Url::parse(&update.url).context("Invalid webhook URL")?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        anyhow::bail!("Webhook URL must use http or https");
+    }
+    let secret = update
+        .secret
+        .as_deref()
+        .context("A webhook needs a secret to sign its payloads")?;
+    if secret.len() < MIN_SECRET_LENGTH {
+        anyhow::bail!("Webhook secret must be at least {MIN_SECRET_LENGTH} characters");
+    }
+    Entry::new(KEYRING_SERVICE, &update.url)
+        .and_then(|entry| entry.set_password(secret))
+        .context("Failed to store webhook secret in keychain")?;
+    repo.set_local_config(URL_KEY, Some(&update.url))
+}
+
+/// The signing secret for `url`
+pub fn secret(url: &str) -> Result<String> {
+    Entry::new(KEYRING_SERVICE, url)
+        .and_then(|entry| entry.get_password())
+        .context("Failed to retrieve webhook secret from keychain")
+}
+
+/// A bookmark as listed in a payload
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BookmarkSummary {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    /// Tag names
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct Counts {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    pub tags_changed: usize,
+}
+
+/// What's posted to the webhook after a write or sync changes the bookmarks.
+/// Private bookmarks are never included.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Payload {
+    /// `write` or `sync`
+    pub event: String,
+    pub commit: String,
+    pub device: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub counts: Counts,
+    /// At most 50 of each; see `counts` for the totals
+    pub added: Vec<BookmarkSummary>,
+    pub removed: Vec<ResourceRef>,
+    pub modified: Vec<diff::Modification>,
+    /// One-line summary, under the field names Slack and Discord webhooks
+    /// read, so either can take the payload as it is
+    pub text: String,
+    pub content: String,
+}
+
+impl Payload {
+    /// The payload for the change from `previous` to `current`, or `None` if
+    /// nothing visible changed
+    pub fn new(
+        event: &str,
+        commit: String,
+        device: Option<String>,
+        previous: &BookmarksData,
+        current: &BookmarksData,
+    ) -> Option<Self> {
+        let diff::BookmarksDiff {
+            bookmarks, tags, ..
+        } = diff::diff(previous, current);
+        let shown = |data: &BookmarksData, id: &str| {
+            data.get(id)
+                .is_some_and(|resource| data.visibility(resource) != Visibility::Private)
+        };
+        let ChangeSet {
+            added,
+            removed,
+            modified,
+        } = bookmarks;
+        let added: Vec<BookmarkSummary> = added
+            .iter()
+            .filter(|added| shown(current, &added.id))
+            .filter_map(|added| summarize(current, &added.id))
+            .collect();
+        let removed: Vec<ResourceRef> = removed
+            .into_iter()
+            .filter(|removed| shown(previous, &removed.id))
+            .collect();
+        let modified: Vec<diff::Modification> = modified
+            .into_iter()
+            .filter(|modified| shown(previous, &modified.id) || shown(current, &modified.id))
+            .collect();
+        let tags_changed = [&tags.added, &tags.removed]
+            .into_iter()
+            .flatten()
+            .filter(|tag| shown(current, &tag.id) || shown(previous, &tag.id))
+            .count()
+            + tags
+                .modified
+                .iter()
+                .filter(|tag| shown(current, &tag.id))
+                .count();
+
+        let counts = Counts {
+            added: added.len(),
+            removed: removed.len(),
+            modified: modified.len(),
+            tags_changed,
+        };
+        if counts == Counts::default() {
+            return None;
+        }
+        let text = describe(&counts, &added);
+        Some(Self {
+            event: event.to_string(),
+            commit,
+            device,
+            timestamp: Utc::now(),
+            counts,
+            added: added.into_iter().take(MAX_LISTED).collect(),
+            removed: removed.into_iter().take(MAX_LISTED).collect(),
+            modified: modified.into_iter().take(MAX_LISTED).collect(),
+            content: text.clone(),
+            text,
+        })
+    }
+}
+
+fn summarize(data: &BookmarksData, id: &str) -> Option<BookmarkSummary> {
+    let Resource::Bookmark {
+        attributes,
+        relationships,
+        ..
+    } = data.get(id)?
+    else {
+        return None;
+    };
+    let tags = relationships
+        .iter()
+        .flat_map(|r| r.tags.iter().flat_map(|tags| &tags.data))
+        .filter_map(|tag| match data.get(&tag.id)? {
+            tag @ Resource::Tag { attributes, .. }
+                if data.visibility(tag) != Visibility::Private =>
+            {
+                Some(attributes.name.clone())
+            }
+            _ => None,
+        })
+        .collect();
+    Some(BookmarkSummary {
+        id: id.to_string(),
+        url: attributes.url.clone(),
+        title: attributes.title.clone(),
+        tags,
+    })
+}
+
+/// e.g. "WebTags: 2 added, 1 removed" followed by the added bookmarks
+fn describe(counts: &Counts, added: &[BookmarkSummary]) -> String {
+    let parts: Vec<String> = [
+        (counts.added, "added"),
+        (counts.removed, "removed"),
+        (counts.modified, "modified"),
+        (counts.tags_changed, "tags changed"),
+    ]
+    .into_iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(count, what)| format!("{count} {what}"))
+    .collect();
+    let mut text = format!("WebTags: {}", parts.join(", "));
+    for bookmark in added {
+        let line = format!("\n• {} <{}>", bookmark.title, bookmark.url);
+        if text.chars().count() + line.chars().count() > MAX_TEXT_CHARS {
+            text.push_str("\n…");
+            break;
+        }
+        text.push_str(&line);
+    }
+    text
+}
+
+/// Value of the signature header for `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let mut signature = String::from("sha256=");
+    for byte in mac.finalize().into_bytes() {
+        let _ = write!(signature, "{byte:02x}");
+    }
+    signature
+}
+
+/// Post `payload` to `url`, signed with `secret`
+pub async fn deliver(url: &str, secret: &str, payload: &Payload) -> Result<()> {
+    let client = proxy::http_client().unwrap_or_else(|e| {
+        log::warn!("Ignoring proxy configuration: {e:#}");
+        Client::new()
+    });
+    let body = serde_json::to_vec(payload).context("Failed to serialize webhook payload")?;
+    let response = client
+        .post(url)
+        .timeout(TIMEOUT)
+        .header("Content-Type", "application/json")
+        .header("User-Agent", "WebTags")
+        .header(EVENT_HEADER, &payload.event)
+        .header(SIGNATURE_HEADER, sign(secret, &body))
+        .body(body)
+        .send()
+        .await
+        .context("Webhook request failed")?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Webhook responded with {status}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_bookmark, create_tag, BookmarkAttributes};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn set_private(resource: &mut Resource) {
+        if let Resource::Bookmark { attributes, .. } = resource {
+            *attributes = BookmarkAttributes {
+                visibility: Some(Visibility::Private),
+                ..attributes.clone()
+            };
+        }
+    }
+
+    #[test]
+    fn test_payload_leaves_out_private_bookmarks() {
+        let previous = BookmarksData::new();
+        let mut current = BookmarksData::new();
+        let tag = create_tag("rust".to_string(), None, None);
+        let tag_id = tag.id().to_string();
+        current.add_tag(tag).unwrap();
+        let bookmark = create_bookmark(
+            "https://www.rust-lang.org".to_string(),
+            "Rust".to_string(),
+            vec![tag_id],
+        );
+        let bookmark_id = bookmark.id().to_string();
+        current.add_bookmark(bookmark).unwrap();
+        let mut secret = create_bookmark(
+            "https://example.com/secret".to_string(),
+            "Secret".to_string(),
+            vec![],
+        );
+        set_private(&mut secret);
+        current.add_bookmark(secret).unwrap();
+
+        let payload = Payload::new("write", "abc".to_string(), None, &previous, &current).unwrap();
+        assert_eq!(
+            payload.counts,
+            Counts {
+                added: 1,
+                tags_changed: 1,
+                ..Counts::default()
+            }
+        );
+        assert_eq!(
+            payload.added,
+            vec![BookmarkSummary {
+                id: bookmark_id,
+                url: "https://www.rust-lang.org".to_string(),
+                title: "Rust".to_string(),
+                tags: vec!["rust".to_string()],
+            }]
+        );
+        assert_eq!(
+            payload.text,
+            "WebTags: 1 added, 1 tags changed\n• Rust <https://www.rust-lang.org>"
+        );
+        assert_eq!(payload.content, payload.text);
+
+        // A change nobody may see sends nothing
+        let mut only_private = BookmarksData::new();
+        let mut secret =
+            create_bookmark("https://example.com".to_string(), "S".to_string(), vec![]);
+        set_private(&mut secret);
+        only_private.add_bookmark(secret).unwrap();
+        assert_eq!(
+            Payload::new("sync", "abc".to_string(), None, &previous, &only_private),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sign() {
+        // HMAC-SHA256 test vector from RFC 4231, case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deliver_signs_payload() {
+        let mut current = BookmarksData::new();
+        current
+            .add_bookmark(create_bookmark(
+                "https://example.com".to_string(),
+                "Example".to_string(),
+                vec![],
+            ))
+            .unwrap();
+        let payload = Payload::new(
+            "sync",
+            "abc".to_string(),
+            Some("laptop".to_string()),
+            &BookmarksData::new(),
+            &current,
+        )
+        .unwrap();
+        let body = serde_json::to_vec(&payload).unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .and(header(EVENT_HEADER, "sync"))
+            .and(header(
+                SIGNATURE_HEADER,
+                sign("0123456789abcdef", &body).as_str(),
+            ))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let url = format!("{}/hook", server.uri());
+        deliver(&url, "0123456789abcdef", &payload).await.unwrap();
+
+        Mock::given(method("POST"))
+            .and(path("/gone"))
+            .respond_with(ResponseTemplate::new(410))
+            .mount(&server)
+            .await;
+        let url = format!("{}/gone", server.uri());
+        assert!(deliver(&url, "0123456789abcdef", &payload).await.is_err());
+    }
+}
@@ -0,0 +1,244 @@
+// GitHub API client tests against a local wiremock server
+use std::time::Duration;
+
+use webtags_host::github::GitHubClient;
+use webtags_host::retry::RetryPolicy;
+use wiremock::matchers::{body_partial_json, body_string_contains, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn client(server: &MockServer) -> GitHubClient {
+    GitHubClient::new()
+        .with_api_base(&server.uri())
+        .with_login_base(&server.uri())
+        .with_http_client(reqwest::Client::new())
+        .with_retry_policy(RetryPolicy::none())
+}
+
+/// Answer the next token poll with `body`, once
+async fn poll_answer(server: &MockServer, body: serde_json::Value) {
+    Mock::given(method("POST"))
+        .and(path("/login/oauth/access_token"))
+        .and(body_string_contains("device_code=dev-code"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .up_to_n_times(1)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_start_device_flow() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/login/device/code"))
+        .and(body_string_contains("scope=repo+gist"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "device_code": "dev-code",
+            "user_code": "ABCD-1234",
+            "verification_uri": "https://github.com/login/device",
+            "expires_in": 900,
+            "interval": 5,
+        })))
+        .mount(&server)
+        .await;
+
+    let started = client(&server).start_device_flow().await.unwrap();
+    assert_eq!(started.device_code, "dev-code");
+    assert_eq!(started.user_code, "ABCD-1234");
+    assert_eq!(started.interval, 5);
+}
+
+#[tokio::test]
+async fn test_start_device_flow_error_hides_body() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/login/device/code"))
+        .respond_with(ResponseTemplate::new(400).set_body_string("secret details"))
+        .mount(&server)
+        .await;
+
+    let err = client(&server).start_device_flow().await.unwrap_err();
+    let message = format!("{err:#}");
+    assert!(message.contains("400"));
+    assert!(!message.contains("secret details"));
+}
+
+#[tokio::test]
+async fn test_poll_waits_for_authorization() {
+    let server = MockServer::start().await;
+    poll_answer(
+        &server,
+        serde_json::json!({ "error": "authorization_pending" }),
+    )
+    .await;
+    poll_answer(
+        &server,
+        serde_json::json!({
+            "access_token": "ghu_token",
+            "token_type": "bearer",
+            "scope": "repo,gist",
+            "expires_in": 28800,
+            "refresh_token": "ghr_refresh",
+        }),
+    )
+    .await;
+
+    let mut waits = 0;
+    let issued = client(&server)
+        .poll_for_token_until("dev-code", 0, Duration::from_secs(10), |_| waits += 1)
+        .await
+        .unwrap();
+    assert_eq!(issued.access_token, "ghu_token");
+    assert_eq!(issued.expires_in, Some(28800));
+    assert_eq!(issued.refresh_token.as_deref(), Some("ghr_refresh"));
+    assert_eq!(waits, 2);
+}
+
+#[tokio::test]
+async fn test_poll_slows_down_when_asked() {
+    let server = MockServer::start().await;
+    poll_answer(&server, serde_json::json!({ "error": "slow_down" })).await;
+    poll_answer(
+        &server,
+        serde_json::json!({ "access_token": "ghu_token", "token_type": "bearer", "scope": "repo" }),
+    )
+    .await;
+
+    // The longer interval is capped by the time left, so this stays quick
+    let started = std::time::Instant::now();
+    let issued = client(&server)
+        .poll_for_token_until("dev-code", 0, Duration::from_secs(1), |_| {})
+        .await
+        .unwrap();
+    assert_eq!(issued.access_token, "ghu_token");
+    assert!(started.elapsed() >= Duration::from_millis(900));
+}
+
+#[tokio::test]
+async fn test_poll_error_branches() {
+    for (error, expected) in [
+        ("expired_token", "Device code expired"),
+        ("access_denied", "User denied access"),
+        ("incorrect_client_credentials", "OAuth error"),
+    ] {
+        let server = MockServer::start().await;
+        poll_answer(&server, serde_json::json!({ "error": error })).await;
+        let err = client(&server)
+            .poll_for_token_until("dev-code", 0, Duration::from_secs(10), |_| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(expected), "{error}: {err}");
+    }
+
+    let server = MockServer::start().await;
+    poll_answer(&server, serde_json::json!({})).await;
+    let err = client(&server)
+        .poll_for_token_until("dev-code", 0, Duration::from_secs(10), |_| {})
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Unexpected response"));
+}
+
+#[tokio::test]
+async fn test_poll_times_out() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/login/oauth/access_token"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "error": "authorization_pending" })),
+        )
+        .mount(&server)
+        .await;
+
+    let err = client(&server)
+        .poll_for_token_until("dev-code", 0, Duration::from_millis(300), |_| {})
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Timeout"));
+}
+
+#[tokio::test]
+async fn test_create_repository() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/user/repos"))
+        .and(header("Authorization", "Bearer token"))
+        .and(body_partial_json(serde_json::json!({
+            "name": "bookmarks",
+            "private": true,
+            "auto_init": true,
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+            "id": 1,
+            "name": "bookmarks",
+            "full_name": "me/bookmarks",
+            "clone_url": "https://github.com/me/bookmarks.git",
+            "ssh_url": "git@github.com:me/bookmarks.git",
+            "private": true,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let repo = client(&server)
+        .create_repository("token", "bookmarks", None)
+        .await
+        .unwrap();
+    assert_eq!(repo.full_name, "me/bookmarks");
+    assert!(repo.private);
+}
+
+#[tokio::test]
+async fn test_create_repository_is_not_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/user/repos"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = client(&server).with_retry_policy(RetryPolicy::default());
+    assert!(client
+        .create_repository("token", "bookmarks", None)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_check_token_reads_expiry_and_scopes() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/user"))
+        .and(header("Authorization", "Bearer good"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(
+                    "github-authentication-token-expiration",
+                    "2030-01-01 00:00:00 UTC",
+                )
+                .insert_header("x-oauth-scopes", "repo, gist")
+                .set_body_json(serde_json::json!({ "login": "me" })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/user"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let client = client(&server);
+    let checked = client.check_token("good").await.unwrap();
+    assert!(!checked.rejected);
+    assert_eq!(
+        checked.expires_at.map(|at| at.to_rfc3339()).as_deref(),
+        Some("2030-01-01T00:00:00+00:00")
+    );
+    assert_eq!(
+        checked.scopes,
+        Some(vec!["repo".to_string(), "gist".to_string()])
+    );
+
+    assert!(!client.validate_token("bad").await.unwrap());
+}
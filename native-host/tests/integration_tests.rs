@@ -70,8 +70,8 @@ fn test_end_to_end_bookmark_storage() {
 
     // Verify data matches
     assert_eq!(read_data.jsonapi.version, "1.1");
-    assert_eq!(read_data.data.len(), 1);
-    assert_eq!(read_data.included.as_ref().unwrap().len(), 1);
+    assert_eq!(read_data.data().len(), 1);
+    assert_eq!(read_data.included().unwrap().len(), 1);
 
     // Verify bookmarks
     let bookmarks = read_data.get_bookmarks();
@@ -236,11 +236,11 @@ fn test_bookmark_with_tags_integration() {
     let read_data: BookmarksData = serde_json::from_str(&content).unwrap();
 
     // Verify structure
-    assert_eq!(read_data.data.len(), 1);
-    assert_eq!(read_data.included.as_ref().unwrap().len(), 2);
+    assert_eq!(read_data.data().len(), 1);
+    assert_eq!(read_data.included().unwrap().len(), 2);
 
     // Verify bookmark has relationships
-    if let webtags_host::storage::Resource::Bookmark { relationships, .. } = &read_data.data[0] {
+    if let webtags_host::storage::Resource::Bookmark { relationships, .. } = &read_data.data()[0] {
         assert!(relationships.is_some());
         let tags = relationships.as_ref().unwrap().tags.as_ref().unwrap();
         assert_eq!(tags.data.len(), 2);
@@ -257,6 +257,9 @@ fn test_native_messaging_protocol_integration() {
     let init_msg = Message::Init {
         repo_path: Some("/tmp/test".to_string()),
         repo_url: None,
+        depth: None,
+        device_name: None,
+        backend: None,
     };
     let json = serde_json::to_vec(&init_msg).unwrap();
     let length = u32::try_from(json.len()).unwrap().to_le_bytes();